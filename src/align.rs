@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: MIT
+//! Aligning the versions of internal packages across the fleet
+//!
+//! A package is internal when one of the scanned modules declares it as its
+//! name. When modules depend on different versions of an internal package,
+//! `align` picks one version per package, interactively or by a `--choose`
+//! policy, and rewrites the manifests on other versions.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::registry::compare_versions;
+
+/// How to pick a version without asking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Policy {
+    /// The highest version in use
+    Highest,
+    /// The lowest version in use
+    Lowest,
+    /// The latest version in the registry index
+    LatestRegistry,
+}
+
+/// An internal package used at more than one version
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub package: String,
+    /// Version to the manifests declaring it, in ascending version order
+    pub versions: Vec<(String, Vec<PathBuf>)>,
+}
+
+impl Conflict {
+    /// Version picked by `policy`; `None` for `latest-registry` when the
+    /// registry does not know the package
+    pub fn choose(&self, policy: Policy, latest: Option<&str>) -> Option<String> {
+        match policy {
+            Policy::Highest => self.versions.last().map(|(v, _)| v.clone()),
+            Policy::Lowest => self.versions.first().map(|(v, _)| v.clone()),
+            Policy::LatestRegistry => latest.map(str::to_string),
+        }
+    }
+
+    /// Manifests that need rewriting to use `version`
+    pub fn stale<'a>(&'a self, version: &'a str) -> impl Iterator<Item = &'a PathBuf> + 'a {
+        self.versions
+            .iter()
+            .filter(move |(v, _)| v != version)
+            .flat_map(|(_, manifests)| manifests)
+    }
+}
+
+/// Conflicts among `usages` (package, manifest, declared version) of the
+/// `internal` packages, sorted by package name
+pub fn find_conflicts(
+    internal: &BTreeSet<String>,
+    usages: impl IntoIterator<Item = (String, PathBuf, String)>,
+) -> Vec<Conflict> {
+    let mut by_package: BTreeMap<String, BTreeMap<String, Vec<PathBuf>>> = BTreeMap::new();
+    for (package, manifest, version) in usages {
+        if internal.contains(&package) {
+            by_package
+                .entry(package)
+                .or_default()
+                .entry(version)
+                .or_default()
+                .push(manifest);
+        }
+    }
+    by_package
+        .into_iter()
+        .filter(|(_, versions)| versions.len() > 1)
+        .map(|(package, versions)| {
+            let mut versions: Vec<(String, Vec<PathBuf>)> = versions.into_iter().collect();
+            versions.sort_by(|(a, _), (b, _)| compare_versions(a, b).then(a.cmp(b)));
+            Conflict { package, versions }
+        })
+        .collect()
+}
+
+/// Parse an interactive answer: a 1-based index into the versions, `r` for
+/// the registry version, or `s`/empty to skip
+pub fn parse_answer(
+    answer: &str,
+    conflict: &Conflict,
+    latest: Option<&str>,
+) -> Result<Option<String>, String> {
+    let answer = answer.trim();
+    if answer.is_empty() || answer.eq_ignore_ascii_case("s") {
+        return Ok(None);
+    }
+    if answer.eq_ignore_ascii_case("r") {
+        return latest
+            .map(|v| Some(v.to_string()))
+            .ok_or_else(|| "the registry has no version of this package".to_string());
+    }
+    match answer.parse::<usize>() {
+        Ok(n) if (1..=conflict.versions.len()).contains(&n) => {
+            Ok(Some(conflict.versions[n - 1].0.clone()))
+        }
+        _ => Err(format!("expected 1-{}, r or s", conflict.versions.len())),
+    }
+}
+
+/// Whether `latest` is newer than every version in use
+pub fn registry_is_newer(conflict: &Conflict, latest: &str) -> bool {
+    conflict
+        .versions
+        .last()
+        .is_some_and(|(v, _)| compare_versions(latest, v) == Ordering::Greater)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// acme/core at 0.10.0 in one manifest and 0.9.0 in two
+    fn conflict() -> Conflict {
+        let internal: BTreeSet<String> = ["acme/core".to_string()].into();
+        let usage = |pkg: &str, path: &str, version: &str| {
+            (pkg.to_string(), PathBuf::from(path), version.to_string())
+        };
+        let mut conflicts = find_conflicts(
+            &internal,
+            [
+                usage("acme/core", "a/moon.mod.json", "0.10.0"),
+                usage("acme/core", "b/moon.mod.json", "0.9.0"),
+                usage("acme/core", "c/moon.mod.json", "0.9.0"),
+                usage("other/lib", "a/moon.mod.json", "1.0.0"),
+                usage("other/lib", "b/moon.mod.json", "2.0.0"),
+            ],
+        );
+        assert_eq!(conflicts.len(), 1);
+        conflicts.remove(0)
+    }
+
+    #[test]
+    fn test_find_conflicts_of_internal_packages() {
+        let conflict = conflict();
+        assert_eq!(conflict.package, "acme/core");
+        assert_eq!(conflict.versions[0].0, "0.9.0");
+    }
+
+    #[test]
+    fn test_choose_by_policy() {
+        let conflict = conflict();
+        assert_eq!(
+            conflict.choose(Policy::Highest, None).as_deref(),
+            Some("0.10.0")
+        );
+        assert_eq!(
+            conflict.choose(Policy::Lowest, None).as_deref(),
+            Some("0.9.0")
+        );
+        assert_eq!(conflict.choose(Policy::LatestRegistry, None), None);
+        assert!(registry_is_newer(&conflict, "0.11.0"));
+    }
+
+    #[test]
+    fn test_stale_manifests() {
+        assert_eq!(
+            conflict().stale("0.10.0").collect::<Vec<_>>(),
+            [
+                &PathBuf::from("b/moon.mod.json"),
+                &PathBuf::from("c/moon.mod.json")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_answer() {
+        let conflict = conflict();
+        assert_eq!(
+            parse_answer("2\n", &conflict, None),
+            Ok(Some("0.10.0".to_string()))
+        );
+        assert_eq!(
+            parse_answer("r", &conflict, Some("0.11.0")),
+            Ok(Some("0.11.0".to_string()))
+        );
+        assert_eq!(parse_answer("", &conflict, None), Ok(None));
+        assert!(parse_answer("3", &conflict, None).is_err());
+    }
+}
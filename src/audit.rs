@@ -0,0 +1,381 @@
+// SPDX-License-Identifier: MIT
+//! Audit log of the programs moon-dst runs
+//!
+//! Every program started (moon, git, curl, ssh, containers) appends one JSON
+//! line to `audit.log` in the data directory: the program and arguments,
+//! working directory, environment variables set or removed for it, exit
+//! code, duration, and SHA-256 of its stdout and stderr (the first 16 hex
+//! digits). Values of variables whose name mentions a token, secret,
+//! password or key are left out.
+//!
+//! Entries form a hash chain: each holds the hash of the one before and a
+//! hash of itself, so `audit-log verify` finds edited, removed or reordered
+//! entries. Cutting off the newest entries leaves a valid chain; compare
+//! the head hash `verify` prints with one kept elsewhere to catch that.
+//! Writers lock the file while appending, so concurrent runs extend the
+//! same chain.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::errors::{Code, Coded};
+
+/// Log file name inside the data directory
+pub const FILE: &str = "audit.log";
+
+/// `prev` of the first entry
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One program run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    /// Position in the log, from 1
+    pub seq: u64,
+    pub time: String,
+    pub run_id: String,
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: String,
+    /// Variables set (`Some`) or removed (`None`) for the program
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, Option<String>>,
+    /// `None` when the program was killed by a signal or did not start
+    pub exit_code: Option<i32>,
+    /// Why the program did not start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_sha256: Option<String>,
+    /// Hash of the previous entry
+    pub prev: String,
+    /// Hash of this entry with `hash` empty
+    pub hash: String,
+}
+
+impl Entry {
+    fn digest(&self) -> String {
+        let unsealed = Entry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_string(&unsealed).unwrap_or_default();
+        hex(&Sha256::digest(json.as_bytes()))
+    }
+}
+
+static LOG: OnceLock<PathBuf> = OnceLock::new();
+
+/// Log programs started by this process to `path`; later calls are ignored
+pub fn init(path: PathBuf) {
+    LOG.get_or_init(|| path);
+}
+
+/// Running a [`Command`] with an audit log entry
+pub trait CommandExt {
+    /// [`Command::output`], logged
+    fn logged_output(&mut self) -> io::Result<Output>;
+    /// [`Command::status`], logged
+    fn logged_status(&mut self) -> io::Result<ExitStatus>;
+    /// Run with `input` on stdin and collect the output, logged
+    fn logged_output_with_input(&mut self, input: &[u8]) -> io::Result<Output>;
+}
+
+impl CommandExt for Command {
+    fn logged_output(&mut self) -> io::Result<Output> {
+        let started = Instant::now();
+        let result = self.output();
+        record(
+            self,
+            started.elapsed(),
+            result.as_ref().map(Outcome::Output),
+        );
+        result
+    }
+
+    fn logged_status(&mut self) -> io::Result<ExitStatus> {
+        let started = Instant::now();
+        let result = self.status();
+        record(
+            self,
+            started.elapsed(),
+            result.as_ref().map(|s| Outcome::Status(*s)),
+        );
+        result
+    }
+
+    fn logged_output_with_input(&mut self, input: &[u8]) -> io::Result<Output> {
+        let started = Instant::now();
+        let result = (|| {
+            let mut child = self.stdin(Stdio::piped()).spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input)?;
+            }
+            child.wait_with_output()
+        })();
+        record(
+            self,
+            started.elapsed(),
+            result.as_ref().map(Outcome::Output),
+        );
+        result
+    }
+}
+
+enum Outcome<'a> {
+    Output(&'a Output),
+    Status(ExitStatus),
+}
+
+fn record(command: &Command, duration: Duration, outcome: Result<Outcome, &io::Error>) {
+    let Some(path) = LOG.get() else {
+        return;
+    };
+    let (exit_code, output) = match &outcome {
+        Ok(Outcome::Output(output)) => (output.status.code(), Some(output)),
+        Ok(Outcome::Status(status)) => (status.code(), None),
+        Err(_) => (None, None),
+    };
+    let short_hash = |bytes: &[u8]| hex(&Sha256::digest(bytes))[..16].to_string();
+    let entry = Entry {
+        seq: 0,
+        time: crate::timestamp::rfc3339(crate::timestamp::now()),
+        run_id: crate::run_id::current().to_string(),
+        program: lossy(command.get_program()),
+        args: command.get_args().map(lossy).collect(),
+        cwd: match command.get_current_dir() {
+            Some(dir) => crate::ospath::encode(dir),
+            None => std::env::current_dir()
+                .map(|dir| crate::ospath::encode(&dir))
+                .unwrap_or_default(),
+        },
+        env: command
+            .get_envs()
+            .map(|(key, value)| {
+                let key = lossy(key);
+                let value = value.map(|v| {
+                    if is_secret(&key) {
+                        "<redacted>".to_string()
+                    } else {
+                        lossy(v)
+                    }
+                });
+                (key, value)
+            })
+            .collect(),
+        exit_code,
+        error: outcome.as_ref().err().map(|e| e.to_string()),
+        duration_ms: crate::timestamp::reported(duration).as_millis() as u64,
+        stdout_sha256: output.map(|o| short_hash(&o.stdout)),
+        stderr_sha256: output.map(|o| short_hash(&o.stderr)),
+        prev: String::new(),
+        hash: String::new(),
+    };
+    if let Err(e) = append(path, entry) {
+        // Once per process; the command itself goes on
+        static REPORTED: AtomicBool = AtomicBool::new(false);
+        if !REPORTED.swap(true, Ordering::Relaxed) {
+            crate::diagnostics::report(
+                crate::diagnostics::Code::RunState,
+                format!("cannot write the audit log {}: {e}", path.display()),
+            );
+        }
+    }
+}
+
+/// Chain `entry` to the last one in the log and append it
+fn append(path: &Path, mut entry: Entry) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    file.lock()?;
+    let (seq, prev) = match last_line(&mut file)? {
+        Some(line) => {
+            let last: Entry = serde_json::from_str(&line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("its last entry is damaged ({e}); run `moon-dst audit-log verify`"),
+                )
+            })?;
+            (last.seq + 1, last.hash)
+        }
+        None => (1, GENESIS.to_string()),
+    };
+    entry.seq = seq;
+    entry.prev = prev;
+    entry.hash = entry.digest();
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.unlock()
+}
+
+/// Last non-empty line of `file`, read backwards in growing chunks
+fn last_line(file: &mut File) -> io::Result<Option<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut chunk = 4096;
+    loop {
+        let start = len.saturating_sub(chunk);
+        file.seek(SeekFrom::Start(start))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let text = String::from_utf8_lossy(&bytes);
+        let text = text.trim_end_matches('\n');
+        match text.rfind('\n') {
+            Some(at) => return Ok(Some(text[at + 1..].to_string())),
+            None if start == 0 => {
+                return Ok((!text.is_empty()).then(|| text.to_string()));
+            }
+            None => chunk *= 4,
+        }
+    }
+}
+
+/// Entries in the log at `path`; a missing log has none
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: invalid entry", path.display(), i + 1))
+        })
+        .collect()
+}
+
+/// Check the chain of `entries`; returns the hash of the last entry
+pub fn verify(entries: &[Entry]) -> Result<String> {
+    let mut prev = GENESIS.to_string();
+    for (i, entry) in entries.iter().enumerate() {
+        let broken = |what: &str| {
+            Coded::new(
+                Code::AuditLogBroken,
+                format!("Audit log entry {} (seq {}) {what}", i + 1, entry.seq),
+            )
+        };
+        if entry.seq != i as u64 + 1 {
+            bail!(broken(
+                "is out of sequence; entries were removed or reordered"
+            ));
+        }
+        if entry.prev != prev {
+            bail!(broken("does not follow the entry before it"));
+        }
+        if entry.digest() != entry.hash {
+            bail!(broken("was modified after it was written"));
+        }
+        prev = entry.hash.clone();
+    }
+    Ok(prev)
+}
+
+fn lossy(text: &std::ffi::OsStr) -> String {
+    text.to_string_lossy().into_owned()
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    ["TOKEN", "SECRET", "PASSWORD", "PASSWD", "KEY", "CREDENTIAL"]
+        .iter()
+        .any(|word| name.contains(word))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A log of three entries in a fresh directory
+    fn chain(name: &str) -> (PathBuf, Vec<Entry>) {
+        let dir =
+            std::env::temp_dir().join(format!("moon_dst_audit_{name}_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let path = dir.join(FILE);
+        let entry = |program: &str| Entry {
+            seq: 0,
+            time: "2026-01-01T00:00:00Z".to_string(),
+            run_id: "00000000000000000000000000".to_string(),
+            program: program.to_string(),
+            args: vec!["status".to_string()],
+            cwd: "/".to_string(),
+            env: BTreeMap::from([("GH_TOKEN".to_string(), Some("<redacted>".to_string()))]),
+            exit_code: Some(0),
+            error: None,
+            duration_ms: 1,
+            stdout_sha256: None,
+            stderr_sha256: None,
+            prev: String::new(),
+            hash: String::new(),
+        };
+        for program in ["git", "moon", "curl"] {
+            append(&path, entry(program)).unwrap();
+        }
+        let entries = read(&path).unwrap();
+        (dir, entries)
+    }
+
+    #[test]
+    fn test_append_numbers_and_chains_entries() {
+        let (dir, entries) = chain("append");
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].seq, 3);
+        assert_eq!(entries[1].prev, entries[0].hash);
+        assert_eq!(verify(&entries).unwrap(), entries[2].hash);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_verify_detects_edited_entry() {
+        let (dir, mut entries) = chain("edited");
+        entries[1].args = vec!["push".to_string()];
+        assert!(verify(&entries)
+            .unwrap_err()
+            .to_string()
+            .contains("entry 2 (seq 2) was modified"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_verify_detects_removed_entry() {
+        let (dir, entries) = chain("removed");
+        let removed = [entries[0].clone(), entries[2].clone()];
+        assert!(verify(&removed).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_is_secret() {
+        assert!(is_secret("GITHUB_TOKEN"));
+        assert!(!is_secret("PATH"));
+    }
+}
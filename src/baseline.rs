@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: MIT
+//! Outdated dependencies accepted for now
+//!
+//! A fleet that is already behind cannot start gating on `outdated` at
+//! once. `outdated --write-baseline` records every dependency outdated
+//! today in a TOML file, each entry with a `reason` to fill in; with
+//! `--baseline <file>`, `outdated` leaves the entries out and fails only
+//! for the rest, the dependencies that fell behind since. An entry is a
+//! dependency of a module in a repo (its path below `--root`), whatever the
+//! versions; rewriting the baseline keeps the reasons of entries still in
+//! it and drops those no longer outdated.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::errors::{Code, Coded};
+
+/// Baseline file written without `--baseline`
+pub const DEFAULT_FILE: &str = "moon-dst.baseline.toml";
+
+const HEADER: &str = "# Outdated dependencies accepted by `moon-dst outdated --baseline`.\n\
+# Give each a reason; `outdated --write-baseline` keeps them.\n\n";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Baseline {
+    #[serde(default)]
+    pub accepted: Vec<Entry>,
+}
+
+/// One accepted outdated dependency
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    /// Repo path below `--root`
+    pub repo: String,
+    pub module: String,
+    pub dep: String,
+    /// Version declared when the entry was written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declared: Option<String>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl Entry {
+    fn is(&self, repo: &str, module: &str, dep: &str) -> bool {
+        self.repo == repo && self.module == module && self.dep == dep
+    }
+}
+
+impl Baseline {
+    pub fn read(path: &Path) -> Result<Baseline> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => anyhow::bail!(Coded::new(
+                Code::FileNotFound,
+                format!(
+                    "Baseline {} not found; create it with --write-baseline",
+                    path.display()
+                )
+            )),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        toml::from_str(&content).map_err(|e| {
+            Coded::new(
+                Code::InvalidValue,
+                format!("Invalid baseline {}: {e}", path.display()),
+            )
+            .into()
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = format!("{HEADER}{}", toml::to_string(self)?);
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The entry accepting `dep` of `module` in `repo`
+    pub fn accepts(&self, repo: &str, module: &str, dep: &str) -> Option<&Entry> {
+        self.accepted.iter().find(|e| e.is(repo, module, dep))
+    }
+
+    /// A baseline of `entries`, with the reasons `self` gives for them
+    pub fn rewritten(&self, entries: Vec<Entry>) -> Baseline {
+        let accepted = entries
+            .into_iter()
+            .map(
+                |entry| match self.accepts(&entry.repo, &entry.module, &entry.dep) {
+                    Some(kept) => Entry {
+                        reason: kept.reason.clone(),
+                        ..entry
+                    },
+                    None => entry,
+                },
+            )
+            .collect();
+        Baseline { accepted }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewritten_keeps_reasons() {
+        let entry = |dep: &str, declared: &str, reason: &str| Entry {
+            repo: "app".to_string(),
+            module: "me/app".to_string(),
+            dep: dep.to_string(),
+            declared: Some(declared.to_string()),
+            reason: reason.to_string(),
+        };
+        let old = Baseline {
+            accepted: vec![
+                entry("acme/x", "0.1.0", "0.2 drops the sync API"),
+                entry("acme/gone", "1.0.0", "updated since"),
+            ],
+        };
+        let new = old.rewritten(vec![
+            entry("acme/x", "0.1.1", ""),
+            entry("acme/y", "2.0.0", ""),
+        ]);
+        assert_eq!(
+            new.accepted,
+            [
+                entry("acme/x", "0.1.1", "0.2 drops the sync API"),
+                entry("acme/y", "2.0.0", "")
+            ]
+        );
+        assert!(new.accepts("app", "me/app", "acme/y").is_some());
+        assert!(new.accepts("lib", "me/app", "acme/y").is_none());
+
+        let text = toml::to_string(&new).unwrap();
+        assert_eq!(toml::from_str::<Baseline>(&text).unwrap(), new);
+    }
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MIT
+//! Cooperative cancellation of a running operation
+//!
+//! A [`CancellationToken`] is cloned into everything taking part in one
+//! operation. Cancelling it keeps tasks that have not started from
+//! starting, and running ones stop at their next check (between `moon add`
+//! passes, for instance) and return what they finished. Nothing is rolled
+//! back: results of completed work are reported as usual, with the
+//! cancelled steps counted separately.
+//!
+//! Fail-fast cancels the token of its run on the first failure; whoever
+//! drives an operation can cancel it the same way from any thread.
+
+use std::sync::{Arc, OnceLock};
+
+/// Shared cancellation flag with the reason it was set
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    reason: Arc<OnceLock<String>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operation; only the first reason is kept
+    pub fn cancel(&self, reason: &str) {
+        let _ = self.reason.set(reason.to_string());
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.reason.get().is_some()
+    }
+
+    /// Why the operation was cancelled, e.g. `--fail-fast`
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.get().map(String::as_str)
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_first_reason() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        std::thread::spawn(move || clone.cancel("--fail-fast"))
+            .join()
+            .unwrap();
+        token.cancel("later");
+        assert!(token.is_cancelled());
+        assert_eq!(token.reason(), Some("--fail-fast"));
+    }
+}
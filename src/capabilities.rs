@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: MIT
+//! What a run is allowed to do
+//!
+//! `[capabilities] allow` in config lists the kinds of side effects a run
+//! may have; without the section everything is allowed. The checks sit
+//! where the effect happens (writing a file in a repo, starting curl or a
+//! networked git command, `git commit`, `git push`), not in the commands
+//! that lead there, so no code path gets around a read-only profile:
+//!
+//! ```toml
+//! [profile.ci.capabilities]
+//! allow = ["network"]    # read the registry, change nothing
+//! ```
+//!
+//! `allow = []` makes a run read-only. Files moon-dst keeps for itself
+//! (caches, history, checkpoints) and outputs named on the command line
+//! are not restricted.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::errors::{Code, Coded};
+
+/// A kind of side effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Change files in repos: manifests, `moon add`, generated files
+    WriteManifests,
+    /// Create commits
+    GitCommit,
+    /// Push branches
+    GitPush,
+    /// Talk to the registry, download servers, code hosts and mail servers
+    Network,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 4] = [
+        Capability::WriteManifests,
+        Capability::GitCommit,
+        Capability::GitPush,
+        Capability::Network,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Capability::WriteManifests => "write-manifests",
+            Capability::GitCommit => "git-commit",
+            Capability::GitPush => "git-push",
+            Capability::Network => "network",
+        }
+    }
+}
+
+static ALLOWED: OnceLock<Vec<Capability>> = OnceLock::new();
+
+/// Set what this process may do; later calls are ignored
+pub fn init(allowed: Vec<Capability>) {
+    ALLOWED.get_or_init(|| allowed);
+}
+
+fn is_allowed(capability: Capability) -> bool {
+    ALLOWED
+        .get()
+        .is_none_or(|allowed| allowed.contains(&capability))
+}
+
+/// Fail unless `capability` is allowed; `action` says what needed it
+pub fn require(capability: Capability, action: &str) -> Result<()> {
+    if !is_allowed(capability) {
+        bail!(Coded::new(
+            Code::CapabilityDenied,
+            format!(
+                "{action} needs the {} capability, which [capabilities] allow does not grant",
+                capability.as_str()
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// Write a file in a repo, creating missing parent directories, if repo
+/// files may be changed
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    require(
+        Capability::WriteManifests,
+        &format!("Writing {}", path.display()),
+    )?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Capability a moon subcommand needs besides reading, if any
+pub fn for_moon(subcommand: &str) -> &'static [Capability] {
+    match subcommand {
+        "add" | "remove" | "install" => &[Capability::WriteManifests, Capability::Network],
+        "update" => &[Capability::Network],
+        _ => &[],
+    }
+}
+
+/// Fail unless the git command line `args` is allowed; `-c` options
+/// before the subcommand are skipped
+pub fn require_git(args: &[&str]) -> Result<()> {
+    let mut rest = args;
+    while let ["-c", _, tail @ ..] = rest {
+        rest = tail;
+    }
+    match rest.first() {
+        Some(subcommand) => match for_git(subcommand) {
+            Some(capability) => require(capability, &format!("git {subcommand}")),
+            None => Ok(()),
+        },
+        None => Ok(()),
+    }
+}
+
+/// Capability a git subcommand needs, if any
+fn for_git(subcommand: &str) -> Option<Capability> {
+    match subcommand {
+        "commit" => Some(Capability::GitCommit),
+        "push" => Some(Capability::GitPush),
+        "clone" | "fetch" | "pull" | "ls-remote" => Some(Capability::Network),
+        _ => None,
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_of_git_commands() {
+        assert_eq!(for_git("commit"), Some(Capability::GitCommit));
+        assert_eq!(for_git("push"), Some(Capability::GitPush));
+        assert_eq!(for_git("ls-remote"), Some(Capability::Network));
+        assert_eq!(for_git("rev-parse"), None);
+        // Leading `-c` overrides are skipped to find the subcommand
+        assert!(require_git(&["-c", "user.name=x", "rev-parse", "HEAD"]).is_ok());
+        assert!(require_git(&[]).is_ok());
+    }
+
+    #[test]
+    fn test_capabilities_of_moon_commands() {
+        assert_eq!(
+            for_moon("add"),
+            [Capability::WriteManifests, Capability::Network]
+        );
+        assert_eq!(for_moon("update"), [Capability::Network]);
+        assert!(for_moon("check").is_empty());
+    }
+
+    #[test]
+    fn test_allow_list_names() {
+        let allow: Vec<Capability> =
+            toml::from_str::<toml::Value>("allow = [\"git-commit\", \"network\"]")
+                .unwrap()
+                .get("allow")
+                .unwrap()
+                .clone()
+                .try_into()
+                .unwrap();
+        assert_eq!(allow, [Capability::GitCommit, Capability::Network]);
+        for capability in Capability::ALL {
+            let value = toml::Value::try_from(capability).unwrap();
+            assert_eq!(value.as_str(), Some(capability.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_write_creates_parent_dirs() {
+        let dir = std::env::temp_dir().join("moon_dst_capabilities_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join(".github/workflows/ci.yml");
+        write(&path, "on: push\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "on: push\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
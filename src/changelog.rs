@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: MIT
+//! Changelog entries for dependency updates
+//!
+//! With `apply --changelog-file CHANGELOG.md`, each updated repo gets one
+//! line per update in the unreleased section of its changelog:
+//!
+//! - `keep-a-changelog`: under `## [Unreleased]` / `### Changed`
+//! - `conventional`: under `## Unreleased`, as `chore(deps): ...` lines
+//!
+//! Missing files and sections are created. Entries are rendered from a
+//! template (see [`crate::message`]) once per update.
+
+use serde::{Deserialize, Serialize};
+
+/// Changelog layout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    #[default]
+    KeepAChangelog,
+    Conventional,
+}
+
+impl Format {
+    /// Entry template for one update
+    pub fn default_entry(self) -> &'static str {
+        match self {
+            Format::KeepAChangelog => "- Bump `{{name}}` from {{from}} to {{to}}",
+            Format::Conventional => "- chore(deps): bump {{name}} from {{from}} to {{to}}",
+        }
+    }
+
+    /// Heading of a new unreleased section
+    fn unreleased_heading(self) -> &'static str {
+        match self {
+            Format::KeepAChangelog => "## [Unreleased]",
+            Format::Conventional => "## Unreleased",
+        }
+    }
+
+    /// Subsection the entries go under, if any
+    fn subsection(self) -> Option<&'static str> {
+        match self {
+            Format::KeepAChangelog => Some("### Changed"),
+            Format::Conventional => None,
+        }
+    }
+}
+
+fn is_unreleased(line: &str) -> bool {
+    let Some(title) = line.strip_prefix("## ") else {
+        return false;
+    };
+    title
+        .trim()
+        .trim_start_matches('[')
+        .to_ascii_lowercase()
+        .starts_with("unreleased")
+}
+
+/// End of the block starting after `start`: the next line for which
+/// `is_end` holds, or the end of the file
+fn block_end(lines: &[String], start: usize, is_end: impl Fn(&str) -> bool) -> usize {
+    (start + 1..lines.len())
+        .find(|&i| is_end(&lines[i]))
+        .unwrap_or(lines.len())
+}
+
+/// Insert `entries` after the last non-blank line of `lines[start..end]`
+fn append_to_block(lines: &mut Vec<String>, start: usize, end: usize, entries: &[String]) {
+    let mut at = end;
+    while at > start + 1 && lines[at - 1].trim().is_empty() {
+        at -= 1;
+    }
+    let mut insert = Vec::new();
+    if at == start + 1 {
+        // Heading only: separate entries from it
+        insert.push(String::new());
+    }
+    insert.extend(entries.iter().cloned());
+    if at == end || !lines[at].trim().is_empty() {
+        insert.push(String::new());
+    }
+    lines.splice(at..at, insert);
+}
+
+/// Changelog text with `entries` (one line each) added to the unreleased
+/// section; `content` is `None` for a new file
+pub fn insert(content: Option<&str>, format: Format, entries: &[String]) -> String {
+    let mut lines: Vec<String> = content
+        .unwrap_or("# Changelog\n")
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    let section = match lines.iter().position(|l| is_unreleased(l)) {
+        Some(section) => section,
+        None => {
+            // New section before the first release, or at the end
+            let at = lines
+                .iter()
+                .position(|l| l.starts_with("## "))
+                .unwrap_or(lines.len());
+            let mut insert = Vec::new();
+            if at > 0 && !lines[at - 1].trim().is_empty() {
+                insert.push(String::new());
+            }
+            insert.extend([format.unreleased_heading().to_string(), String::new()]);
+            lines.splice(at..at, insert.iter().cloned());
+            at + insert.len() - 2
+        }
+    };
+    let section_end = block_end(&lines, section, |l| l.starts_with("## "));
+
+    match format.subsection() {
+        None => append_to_block(&mut lines, section, section_end, entries),
+        Some(heading) => match (section + 1..section_end).find(|&i| lines[i].trim() == heading) {
+            Some(sub) => {
+                let sub_end = block_end(&lines, sub, |l| {
+                    l.starts_with("## ") || l.starts_with("### ")
+                });
+                append_to_block(&mut lines, sub, sub_end, entries);
+            }
+            None => {
+                let mut block = vec![heading.to_string(), String::new()];
+                block.extend(entries.iter().cloned());
+                append_to_block(&mut lines, section, section_end, &block);
+            }
+        },
+    }
+
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_entries() {
+        let entries = vec!["- Bump `a/x` from 0.1.0 to 0.2.0".to_string()];
+        assert_eq!(
+            insert(None, Format::KeepAChangelog, &entries),
+            "# Changelog\n\n## [Unreleased]\n\n### Changed\n\n- Bump `a/x` from 0.1.0 to 0.2.0\n"
+        );
+
+        let existing = "# Changelog\n\n## [Unreleased]\n\n### Added\n\n- Feature\n\n### Changed\n\n- Old change\n\n## [1.0.0] - 2026-01-01\n\n- Initial\n";
+        assert_eq!(
+            insert(Some(existing), Format::KeepAChangelog, &entries),
+            "# Changelog\n\n## [Unreleased]\n\n### Added\n\n- Feature\n\n### Changed\n\n- Old change\n- Bump `a/x` from 0.1.0 to 0.2.0\n\n## [1.0.0] - 2026-01-01\n\n- Initial\n"
+        );
+
+        let released = "# Changelog\n\n## 1.0.0\n\n- Initial\n";
+        let entries = vec!["- chore(deps): bump a/x from 0.1.0 to 0.2.0".to_string()];
+        assert_eq!(
+            insert(Some(released), Format::Conventional, &entries),
+            "# Changelog\n\n## Unreleased\n\n- chore(deps): bump a/x from 0.1.0 to 0.2.0\n\n## 1.0.0\n\n- Initial\n"
+        );
+    }
+}
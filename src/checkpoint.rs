@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: MIT
+//! Checkpoints of apply runs in progress
+//!
+//! While apply runs, each repo's result is appended to a checkpoint file in
+//! the data directory as soon as the repo is done, and synced to disk. A run
+//! that finishes removes its checkpoint. After a crash, `apply --resume`
+//! reads it back, skips the repos it lists and reports their recorded
+//! results with the rest. There is one checkpoint per root.
+//!
+//! The file is JSON lines: a [`Header`], then one record per repo.
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::plan::sha256;
+
+/// First line of a checkpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Header {
+    #[serde(with = "crate::ospath")]
+    pub root: PathBuf,
+    /// Start time of the interrupted run in seconds since the Unix epoch
+    pub started: u64,
+}
+
+/// Checkpoint file for runs over `root`
+pub fn path(data_dir: &Path, root: &Path) -> PathBuf {
+    let key = sha256(root.as_os_str().as_encoded_bytes());
+    data_dir
+        .join("checkpoints")
+        .join(format!("{}.jsonl", &key[..16]))
+}
+
+/// Header and records of the checkpoint at `path`, `None` if there is none
+///
+/// A partly written last line (the crash hit mid-write) is dropped.
+pub fn read<T: DeserializeOwned>(path: &Path) -> Result<Option<(Header, Vec<T>)>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read checkpoint {}", path.display()))
+        }
+    };
+    let mut lines = content.lines();
+    let Some(header) = lines.next() else {
+        return Ok(None);
+    };
+    let header: Header = serde_json::from_str(header)
+        .with_context(|| format!("Invalid checkpoint {}", path.display()))?;
+    let records = lines
+        .map_while(|line| serde_json::from_str(line).ok())
+        .collect();
+    Ok(Some((header, records)))
+}
+
+/// Appends records to a checkpoint, syncing each one
+pub struct Writer {
+    path: PathBuf,
+    file: File,
+}
+
+impl Writer {
+    /// Start a new checkpoint, replacing any previous one
+    pub fn create(path: &Path, header: &Header) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create checkpoint {}", path.display()))?;
+        let mut writer = Writer {
+            path: path.to_path_buf(),
+            file,
+        };
+        writer.append(header)?;
+        Ok(writer)
+    }
+
+    /// Continue an existing checkpoint, first dropping a partly written
+    /// last line
+    pub fn resume(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read checkpoint {}", path.display()))?;
+        let complete = content.rfind('\n').map_or(0, |end| end + 1);
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open checkpoint {}", path.display()))?;
+        file.set_len(complete as u64)
+            .with_context(|| format!("Failed to truncate checkpoint {}", path.display()))?;
+        Ok(Writer {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Append one record and sync it to disk
+    pub fn append<T: Serialize>(&mut self, record: &T) -> Result<()> {
+        let line = serde_json::to_string(record)?;
+        writeln!(self.file, "{line}")
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("Failed to write checkpoint {}", self.path.display()))
+    }
+
+    /// Delete the checkpoint of a run that finished
+    pub fn remove(self) -> Result<()> {
+        std::fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove checkpoint {}", self.path.display()))
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let dir = std::env::temp_dir().join("moon_dst_checkpoint_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = path(&dir, Path::new("/work"));
+        assert!(read::<String>(&path).unwrap().is_none());
+
+        let header = Header {
+            root: PathBuf::from("/work"),
+            started: 1_700_000_000,
+        };
+        let mut writer = Writer::create(&path, &header).unwrap();
+        writer.append(&"app").unwrap();
+        drop(writer);
+        // A crash in the middle of the next record
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "\"li").unwrap();
+        assert_eq!(
+            read::<String>(&path).unwrap(),
+            Some((header.clone(), vec!["app".to_string()]))
+        );
+
+        let mut writer = Writer::resume(&path).unwrap();
+        writer.append(&"lib").unwrap();
+        assert_eq!(
+            read::<String>(&path).unwrap(),
+            Some((header, vec!["app".to_string(), "lib".to_string()]))
+        );
+        writer.remove().unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
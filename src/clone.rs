@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT
+//! Local clones of fleet repos listed by URL
+//!
+//! Repos in `[clone] repos` are cloned into `~/.moon-dst/clones/` and then
+//! treated like local repos. By default the clones are shallow (`--depth 1`),
+//! blobless, and sparse: only manifests and the files moon-dst reads are
+//! checked out, which keeps scanning hundreds of repos cheap. Commands that
+//! need the whole tree (`apply --full-clone`, `test`) turn existing clones
+//! into full ones.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+
+/// Files checked out in sparse clones (non-cone patterns)
+const SPARSE_PATTERNS: &[&str] = &[
+    "moon.mod.json",
+    "moon.pkg.json",
+    "moon-version",
+    ".moon-dst-skip",
+];
+
+/// Directory holding all clones
+pub fn clones_dir() -> Option<PathBuf> {
+    Some(crate::data_dir()?.join("clones"))
+}
+
+/// Clone location for a URL: host and path, without scheme, user and `.git`
+pub fn dir_name(url: &str) -> PathBuf {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.rsplit_once('@').map_or(rest, |(_, rest)| rest);
+    let rest = rest.trim_end_matches('/').trim_end_matches(".git");
+    rest.replace(':', "/")
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+        .collect()
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<()> {
+    crate::capabilities::require_git(args)?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .logged_output()
+        .context("Failed to run git (is it installed?)")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::GitFailed,
+            format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    Ok(())
+}
+
+fn is_sparse(dir: &Path) -> bool {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["config", "--bool", "core.sparseCheckout"])
+        .logged_output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+fn is_shallow(dir: &Path) -> bool {
+    dir.join(".git").join("shallow").exists()
+}
+
+/// Clone `url` into `dir`, or bring an existing clone up to date
+///
+/// With `full`, sparse or shallow clones are widened to the whole history
+/// and tree; without it, an existing full clone is left full.
+pub fn sync(url: &str, dir: &Path, full: bool, verbose: bool) -> Result<()> {
+    if !dir.join(".git").exists() {
+        if verbose {
+            let kind = if full { "full" } else { "sparse" };
+            println!("Cloning {url} ({kind})");
+        }
+        let parent = dir.parent().unwrap_or(dir);
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+        let target = dir.to_string_lossy();
+        if full {
+            return git(parent, &["clone", "--quiet", url, &target]);
+        }
+        git(
+            parent,
+            &[
+                "clone",
+                "--quiet",
+                "--depth",
+                "1",
+                "--filter=blob:none",
+                "--no-checkout",
+                url,
+                &target,
+            ],
+        )?;
+        let mut args = vec!["sparse-checkout", "set", "--no-cone"];
+        args.extend(SPARSE_PATTERNS);
+        git(dir, &args)?;
+        return git(dir, &["checkout", "--quiet"]);
+    }
+
+    if verbose {
+        println!("Updating {}", dir.display());
+    }
+    if full && is_shallow(dir) {
+        git(dir, &["fetch", "--quiet", "--unshallow"])?;
+    }
+    if full && is_sparse(dir) {
+        git(dir, &["sparse-checkout", "disable"])?;
+    }
+    if is_shallow(dir) {
+        git(dir, &["pull", "--quiet", "--ff-only", "--depth", "1"])
+    } else {
+        git(dir, &["pull", "--quiet", "--ff-only"])
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_name_from_url() {
+        assert_eq!(
+            dir_name("git@github.com:org/app.git"),
+            PathBuf::from("github.com/org/app")
+        );
+        assert_eq!(
+            dir_name("https://gitlab.example.com/group/sub/lib/"),
+            PathBuf::from("gitlab.example.com/group/sub/lib")
+        );
+    }
+
+    #[test]
+    fn test_sparse_clone_then_widen() {
+        let base = std::env::temp_dir().join("moon_dst_clone_test");
+        std::fs::remove_dir_all(&base).ok();
+        let origin = base.join("origin");
+        std::fs::create_dir_all(origin.join("src")).unwrap();
+        std::fs::write(origin.join("moon.mod.json"), "{}").unwrap();
+        std::fs::write(origin.join("src/moon.pkg.json"), "{}").unwrap();
+        std::fs::write(origin.join("src/lib.mbt"), "").unwrap();
+        git(&origin, &["init", "--quiet"]).unwrap();
+        git(&origin, &["add", "."]).unwrap();
+        git(
+            &origin,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@example.com",
+                "commit",
+                "-qm",
+                "init",
+            ],
+        )
+        .unwrap();
+
+        let url = format!("file://{}", origin.display());
+        let clone = base.join("clone");
+        sync(&url, &clone, false, false).unwrap();
+        assert!(clone.join("moon.mod.json").exists());
+        assert!(clone.join("src/moon.pkg.json").exists());
+        assert!(!clone.join("src/lib.mbt").exists());
+        assert!(is_shallow(&clone) && is_sparse(&clone));
+
+        sync(&url, &clone, true, false).unwrap();
+        assert!(clone.join("src/lib.mbt").exists());
+        assert!(!is_shallow(&clone));
+
+        std::fs::remove_dir_all(&base).ok();
+    }
+}
@@ -0,0 +1,1251 @@
+// SPDX-License-Identifier: MIT
+//! Configuration file loading, merging and validation
+//!
+//! Settings are resolved in layers, later layers overriding earlier ones:
+//! built-in defaults, the user config (`~/.config/moon-dst/config.toml`),
+//! the project config (`<root>/moon-dst.toml`), the profile chosen with
+//! `--profile`, and finally command-line flags.
+//!
+//! A profile is a `[profile.<name>]` table in either file holding any of
+//! the settings a file can hold, e.g. `[profile.ci.apply]`. Its values
+//! override both files, the project's profile winning over the user's.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
+use crate::capabilities::{self, Capability};
+use crate::changelog;
+use crate::errors::{Code, Coded};
+use crate::fetch;
+use crate::findings::Level;
+use crate::forge::{self, ProviderKind, SigningFormat};
+use crate::gate::{self, Check};
+use crate::grouping::GroupOutput;
+use crate::jsonc;
+use crate::justfile;
+use crate::layout::NestedModules;
+use crate::message::{self, RepoTemplates};
+use crate::owners::RepoOwners;
+use crate::package_filter::{self, MatchMode};
+use crate::pins::Pin;
+use crate::runner::{RepoRunner, RunnerSpec};
+use crate::ssh::Remote;
+use crate::submodule::Submodules;
+use crate::taskfile::TaskRunner;
+use crate::{
+    diagnostics, email, limits, output, timestamp, CommonOptions, EmailOn, FailOn, LowDisk,
+    WriteMode,
+};
+
+/// Project config file name, looked up in the `--root` directory
+pub const PROJECT_CONFIG_FILE: &str = "moon-dst.toml";
+
+// =============================================================================
+// File Format
+// =============================================================================
+
+/// On-disk config file structure
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    ignore: Option<Vec<String>>,
+    no_default_ignore: Option<bool>,
+    no_global_ignore: Option<bool>,
+    lenient_parse: Option<bool>,
+    nested_modules: Option<NestedModules>,
+    group_output: Option<GroupOutput>,
+    root_markers: Option<Vec<String>>,
+    submodules: Option<Submodules>,
+    jobs: Option<NonZeroUsize>,
+    verbose: Option<bool>,
+    pins: Option<Vec<Pin>>,
+    renames: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    apply: ApplySection,
+    #[serde(default)]
+    just: JustSection,
+    #[serde(default)]
+    gitignore: GitignoreSection,
+    #[serde(default)]
+    renovate: RenovateSection,
+    #[serde(default)]
+    spdx: SpdxSection,
+    #[serde(default)]
+    email: EmailSection,
+    #[serde(default)]
+    runner: RunnerSection,
+    #[serde(default)]
+    ssh: SshSection,
+    #[serde(default)]
+    clone: CloneSection,
+    #[serde(default)]
+    pr: PrSection,
+    #[serde(default)]
+    commit: CommitSection,
+    #[serde(default)]
+    changelog: ChangelogSection,
+    #[serde(default)]
+    issues: IssuesSection,
+    #[serde(default)]
+    limits: LimitsSection,
+    #[serde(default)]
+    disk: DiskSection,
+    #[serde(default)]
+    registry: RegistrySection,
+    #[serde(default)]
+    owners: OwnersSection,
+    #[serde(default)]
+    diagnostics: DiagnosticsSection,
+    #[serde(default)]
+    gate: GateSection,
+    #[serde(default)]
+    capabilities: CapabilitiesSection,
+    /// Named overrides selected with `--profile`
+    #[serde(default)]
+    profile: BTreeMap<String, ConfigFile>,
+}
+
+/// `[apply]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ApplySection {
+    skip_update: Option<bool>,
+    repeat: Option<NonZeroU32>,
+    repeat_until_stable: Option<bool>,
+    packages: Option<Vec<String>>,
+    package_match: Option<MatchMode>,
+    fail_fast: Option<bool>,
+    justfile: Option<bool>,
+    justfile_mode: Option<WriteMode>,
+    verify: Option<bool>,
+    respect_toolchain: Option<bool>,
+    min_age: Option<u64>,
+    fix_yanked: Option<bool>,
+    update_git_deps: Option<bool>,
+    fail_on: Option<FailOn>,
+    network_jobs: Option<NonZeroUsize>,
+    verify_jobs: Option<NonZeroUsize>,
+    confirm_threshold: Option<usize>,
+    max_error_length: Option<usize>,
+    group_errors: Option<bool>,
+}
+
+/// `[just]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct JustSection {
+    mode: Option<WriteMode>,
+    task_runner: Option<TaskRunner>,
+    recipes: Option<Vec<String>>,
+    extra_recipes: Option<Vec<String>>,
+    set: Option<BTreeMap<String, String>>,
+    repos: Option<Vec<justfile::RepoVariables>>,
+    root_file: Option<bool>,
+}
+
+/// `[gitignore]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct GitignoreSection {
+    mode: Option<WriteMode>,
+}
+
+/// `[renovate]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RenovateSection {
+    mode: Option<WriteMode>,
+    datasource_url: Option<String>,
+}
+
+/// `[email]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct EmailSection {
+    smtp_url: Option<String>,
+    from: Option<String>,
+    username: Option<String>,
+    password_env: Option<String>,
+    to: Option<Vec<String>>,
+    on: Option<EmailOn>,
+}
+
+/// `[runner]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RunnerSection {
+    default: Option<RunnerSpec>,
+    repos: Option<Vec<RepoRunner>>,
+}
+
+/// `[ssh]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct SshSection {
+    repos: Option<Vec<Remote>>,
+    max_per_host: Option<NonZeroUsize>,
+}
+
+/// `[clone]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct CloneSection {
+    repos: Option<Vec<String>>,
+    full: Option<bool>,
+}
+
+/// `[pr]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PrSection {
+    open: Option<bool>,
+    provider: Option<ProviderKind>,
+    api_url: Option<String>,
+    token_env: Option<String>,
+    branch: Option<String>,
+    base: Option<String>,
+    title: Option<String>,
+    body: Option<String>,
+    repos: Option<Vec<RepoTemplates>>,
+    update_submodules: Option<bool>,
+}
+
+/// `[commit]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct CommitSection {
+    author_name: Option<String>,
+    author_email: Option<String>,
+    committer_name: Option<String>,
+    committer_email: Option<String>,
+    sign: Option<bool>,
+    signing_key: Option<String>,
+    signing_format: Option<SigningFormat>,
+}
+
+/// `[changelog]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ChangelogSection {
+    file: Option<PathBuf>,
+    format: Option<changelog::Format>,
+    entry: Option<String>,
+}
+
+/// `[limits]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct LimitsSection {
+    nice: Option<u8>,
+    cpu: Option<NonZeroU32>,
+    memory: Option<String>,
+}
+
+/// `[disk]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct DiskSection {
+    min_free: Option<String>,
+    on_low: Option<LowDisk>,
+}
+
+/// `[registry]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RegistrySection {
+    index_url: Option<String>,
+    max_parallel: Option<NonZeroUsize>,
+}
+
+/// `[owners]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct OwnersSection {
+    repos: Option<Vec<RepoOwners>>,
+    emails: Option<BTreeMap<String, Vec<String>>>,
+}
+
+/// `[diagnostics]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct DiagnosticsSection {
+    allow: Option<Vec<String>>,
+    warn: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+}
+
+/// `[gate]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct GateSection {
+    fail_on: Option<gate::FailOn>,
+    max_major_lag: Option<u64>,
+    checks: Option<Vec<Check>>,
+    yanked: Option<Level>,
+    deprecated: Option<Level>,
+}
+
+/// `[capabilities]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct CapabilitiesSection {
+    allow: Option<Vec<Capability>>,
+}
+
+/// `[issues]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct IssuesSection {
+    after: Option<NonZeroU32>,
+    labels: Option<Vec<String>>,
+}
+
+/// `[spdx]` section
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct SpdxSection {
+    license: Option<String>,
+    holder: Option<String>,
+    exclude: Option<Vec<String>>,
+}
+
+// =============================================================================
+// Effective Configuration
+// =============================================================================
+
+/// Where a setting value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    User(PathBuf),
+    Project(PathBuf),
+    /// A `[profile.<name>]` table in a config file
+    Profile(String, PathBuf),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::User(path) => write!(f, "user config ({})", path.display()),
+            Source::Project(path) => write!(f, "project config ({})", path.display()),
+            Source::Profile(name, path) => write!(f, "profile {name} ({})", path.display()),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// A resolved setting value together with its origin
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    /// Override the value if one is provided by a higher layer
+    fn set(&mut self, value: Option<T>, source: &Source) {
+        if let Some(value) = value {
+            self.value = value;
+            self.source = source.clone();
+        }
+    }
+
+    /// Override the value from a command-line flag
+    pub fn cli(&mut self, value: Option<T>) {
+        self.set(value, &Source::Cli);
+    }
+}
+
+/// Fully merged configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub user_file: Option<PathBuf>,
+    pub project_file: PathBuf,
+    /// `--profile`, if any
+    pub profile: Option<String>,
+
+    pub ignore: Setting<Vec<String>>,
+    pub no_default_ignore: Setting<bool>,
+    /// Leave git's global and per-repo excludes to discovery
+    pub no_global_ignore: Setting<bool>,
+    /// Read manifests with JSON5 syntax instead of skipping them
+    pub lenient_parse: Setting<bool>,
+    pub nested_modules: Setting<NestedModules>,
+    pub group_output: Setting<GroupOutput>,
+    pub root_markers: Setting<Vec<String>>,
+    pub submodules: Setting<Submodules>,
+    pub jobs: Setting<Option<usize>>,
+    pub verbose: Setting<bool>,
+    pub pins: Setting<Vec<Pin>>,
+    /// Old package names mapped to new ones, e.g. after an org rename
+    pub renames: Setting<BTreeMap<String, String>>,
+
+    pub skip_update: Setting<bool>,
+    pub repeat: Setting<u32>,
+    pub repeat_until_stable: Setting<bool>,
+    pub packages: Setting<Vec<String>>,
+    /// How `packages` match dependency names
+    pub package_match: Setting<MatchMode>,
+    pub fail_fast: Setting<bool>,
+    pub justfile: Setting<bool>,
+    pub justfile_mode: Setting<WriteMode>,
+    pub verify: Setting<bool>,
+    pub respect_toolchain: Setting<bool>,
+    pub min_age: Setting<Option<u64>>,
+    pub fix_yanked: Setting<bool>,
+    pub update_git_deps: Setting<bool>,
+    pub fail_on: Setting<FailOn>,
+    /// Parallel `moon update` runs and target lookups (default: `jobs`)
+    pub network_jobs: Setting<Option<usize>>,
+    /// Parallel `moon check` runs (default: `jobs`)
+    pub verify_jobs: Setting<Option<usize>>,
+    /// Repos an apply may change without asking first (0: never ask)
+    pub confirm_threshold: Setting<usize>,
+    /// Characters of an error shown in the results (0: all)
+    pub max_error_length: Setting<usize>,
+    /// List an error seen in several repos once, with the repos
+    pub group_errors: Setting<bool>,
+
+    pub just_mode: Setting<WriteMode>,
+    pub just_task_runner: Setting<TaskRunner>,
+    pub just_recipes: Setting<Vec<String>>,
+    pub just_extra_recipes: Setting<Vec<String>>,
+    pub just_set: Setting<BTreeMap<String, String>>,
+    pub just_repos: Setting<Vec<justfile::RepoVariables>>,
+    pub just_root_file: Setting<bool>,
+
+    pub gitignore_mode: Setting<WriteMode>,
+
+    pub renovate_mode: Setting<WriteMode>,
+    pub renovate_datasource_url: Setting<Option<String>>,
+
+    pub spdx_license: Setting<Option<String>>,
+    pub spdx_holder: Setting<Option<String>>,
+    pub spdx_exclude: Setting<Vec<String>>,
+
+    pub email_smtp_url: Setting<Option<String>>,
+    pub email_from: Setting<Option<String>>,
+    pub email_username: Setting<Option<String>>,
+    pub email_password_env: Setting<Option<String>>,
+    pub email_to: Setting<Vec<String>>,
+    pub email_on: Setting<EmailOn>,
+
+    pub runner: Setting<RunnerSpec>,
+    pub runner_repos: Setting<Vec<RepoRunner>>,
+
+    pub ssh_repos: Setting<Vec<Remote>>,
+    pub ssh_max_per_host: Setting<Option<usize>>,
+
+    pub clone_repos: Setting<Vec<String>>,
+    pub clone_full: Setting<bool>,
+
+    pub pr_open: Setting<bool>,
+    pub pr_provider: Setting<Option<ProviderKind>>,
+    pub pr_api_url: Setting<Option<String>>,
+    pub pr_token_env: Setting<Option<String>>,
+    pub pr_branch: Setting<String>,
+    pub pr_base: Setting<Option<String>>,
+    pub pr_title: Setting<String>,
+    pub pr_body: Setting<String>,
+    pub pr_repos: Setting<Vec<RepoTemplates>>,
+    pub pr_update_submodules: Setting<bool>,
+
+    pub commit_author_name: Setting<Option<String>>,
+    pub commit_author_email: Setting<Option<String>>,
+    pub commit_committer_name: Setting<Option<String>>,
+    pub commit_committer_email: Setting<Option<String>>,
+    pub commit_sign: Setting<bool>,
+    pub commit_signing_key: Setting<Option<String>>,
+    pub commit_signing_format: Setting<Option<SigningFormat>>,
+
+    pub changelog_file: Setting<Option<PathBuf>>,
+    pub changelog_format: Setting<changelog::Format>,
+    pub changelog_entry: Setting<Option<String>>,
+
+    pub issues_after: Setting<Option<u32>>,
+    pub issues_labels: Setting<Vec<String>>,
+    pub limits_nice: Setting<Option<u8>>,
+    pub limits_cpu: Setting<Option<u32>>,
+    pub limits_memory: Setting<Option<String>>,
+    pub disk_min_free: Setting<String>,
+    pub disk_on_low: Setting<LowDisk>,
+    /// Remote registry index to read instead of moon's local copy
+    pub registry_index_url: Setting<Option<String>>,
+    pub registry_max_parallel: Setting<usize>,
+    pub owners_repos: Setting<Vec<RepoOwners>>,
+    pub owners_emails: Setting<BTreeMap<String, Vec<String>>>,
+    pub diagnostics_allow: Setting<Vec<String>>,
+    pub diagnostics_warn: Setting<Vec<String>>,
+    pub diagnostics_deny: Setting<Vec<String>>,
+    /// Lowest finding level failing `gate`
+    pub gate_fail_on: Setting<gate::FailOn>,
+    /// Major versions a dependency may lag before `gate` counts it an error
+    pub gate_max_major_lag: Setting<u64>,
+    pub gate_checks: Setting<Vec<Check>>,
+    /// Level of the `audit` findings for yanked and deprecated versions
+    pub gate_yanked: Setting<Level>,
+    pub gate_deprecated: Setting<Level>,
+    /// Side effects a run may have (default: all)
+    pub capabilities_allow: Setting<Vec<Capability>>,
+    /// `--owner`: only repos owned by one of these
+    pub owner_filter: Vec<String>,
+    /// `--root` after the first: more trees to discover repos in
+    pub extra_roots: Vec<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            user_file: None,
+            project_file: PathBuf::from(PROJECT_CONFIG_FILE),
+            profile: None,
+            ignore: Setting::new(Vec::new()),
+            no_default_ignore: Setting::new(false),
+            no_global_ignore: Setting::new(false),
+            lenient_parse: Setting::new(false),
+            nested_modules: Setting::new(NestedModules::default()),
+            group_output: Setting::new(GroupOutput::default()),
+            root_markers: Setting::new(Vec::new()),
+            submodules: Setting::new(Submodules::default()),
+            jobs: Setting::new(None),
+            verbose: Setting::new(false),
+            pins: Setting::new(Vec::new()),
+            renames: Setting::new(BTreeMap::new()),
+            skip_update: Setting::new(false),
+            repeat: Setting::new(1),
+            repeat_until_stable: Setting::new(false),
+            packages: Setting::new(Vec::new()),
+            package_match: Setting::new(MatchMode::Substring),
+            fail_fast: Setting::new(false),
+            justfile: Setting::new(true),
+            justfile_mode: Setting::new(WriteMode::Create),
+            verify: Setting::new(false),
+            respect_toolchain: Setting::new(false),
+            min_age: Setting::new(None),
+            fix_yanked: Setting::new(false),
+            update_git_deps: Setting::new(false),
+            fail_on: Setting::new(FailOn::Failed),
+            network_jobs: Setting::new(None),
+            verify_jobs: Setting::new(None),
+            confirm_threshold: Setting::new(10),
+            max_error_length: Setting::new(200),
+            group_errors: Setting::new(true),
+            just_mode: Setting::new(WriteMode::Create),
+            just_task_runner: Setting::new(TaskRunner::Just),
+            just_recipes: Setting::new(Vec::new()),
+            just_extra_recipes: Setting::new(Vec::new()),
+            just_set: Setting::new(BTreeMap::new()),
+            just_repos: Setting::new(Vec::new()),
+            just_root_file: Setting::new(true),
+            gitignore_mode: Setting::new(WriteMode::Merge),
+            renovate_mode: Setting::new(WriteMode::Merge),
+            renovate_datasource_url: Setting::new(None),
+            spdx_license: Setting::new(None),
+            spdx_holder: Setting::new(None),
+            spdx_exclude: Setting::new(Vec::new()),
+            email_smtp_url: Setting::new(None),
+            email_from: Setting::new(None),
+            email_username: Setting::new(None),
+            email_password_env: Setting::new(None),
+            email_to: Setting::new(Vec::new()),
+            email_on: Setting::new(EmailOn::Always),
+            runner: Setting::new(RunnerSpec::Local),
+            runner_repos: Setting::new(Vec::new()),
+            ssh_repos: Setting::new(Vec::new()),
+            ssh_max_per_host: Setting::new(None),
+            clone_repos: Setting::new(Vec::new()),
+            clone_full: Setting::new(false),
+            pr_open: Setting::new(false),
+            pr_provider: Setting::new(None),
+            pr_api_url: Setting::new(None),
+            pr_token_env: Setting::new(None),
+            pr_branch: Setting::new("moon-dst/update-deps".to_string()),
+            pr_base: Setting::new(None),
+            pr_title: Setting::new(message::DEFAULT_TITLE.to_string()),
+            pr_body: Setting::new(message::DEFAULT_BODY.to_string()),
+            pr_repos: Setting::new(Vec::new()),
+            pr_update_submodules: Setting::new(false),
+            commit_author_name: Setting::new(None),
+            commit_author_email: Setting::new(None),
+            commit_committer_name: Setting::new(None),
+            commit_committer_email: Setting::new(None),
+            commit_sign: Setting::new(false),
+            commit_signing_key: Setting::new(None),
+            commit_signing_format: Setting::new(None),
+            changelog_file: Setting::new(None),
+            changelog_format: Setting::new(changelog::Format::default()),
+            changelog_entry: Setting::new(None),
+            issues_after: Setting::new(None),
+            issues_labels: Setting::new(Vec::new()),
+            limits_nice: Setting::new(None),
+            limits_cpu: Setting::new(None),
+            limits_memory: Setting::new(None),
+            disk_min_free: Setting::new("1G".to_string()),
+            disk_on_low: Setting::new(LowDisk::default()),
+            registry_index_url: Setting::new(None),
+            registry_max_parallel: Setting::new(fetch::MAX_PARALLEL),
+            owners_repos: Setting::new(Vec::new()),
+            owners_emails: Setting::new(BTreeMap::new()),
+            diagnostics_allow: Setting::new(Vec::new()),
+            diagnostics_warn: Setting::new(Vec::new()),
+            diagnostics_deny: Setting::new(Vec::new()),
+            gate_fail_on: Setting::new(gate::FailOn::Error),
+            gate_max_major_lag: Setting::new(1),
+            gate_checks: Setting::new(Check::ALL.to_vec()),
+            gate_yanked: Setting::new(Level::Error),
+            gate_deprecated: Setting::new(Level::Warning),
+            capabilities_allow: Setting::new(Capability::ALL.to_vec()),
+            owner_filter: Vec::new(),
+            extra_roots: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load user and project config files for the given root
+    pub fn load(root: &Path, profile: Option<&str>) -> Result<Self> {
+        Self::load_from(user_config_path(), root.join(PROJECT_CONFIG_FILE), profile)
+    }
+
+    fn load_from(
+        user_file: Option<PathBuf>,
+        project_file: PathBuf,
+        profile: Option<&str>,
+    ) -> Result<Self> {
+        let mut config = Config::default();
+        let mut profiles = Vec::new();
+        let mut known = Vec::new();
+
+        let files = [
+            user_file
+                .clone()
+                .map(|path| (Source::User(path.clone()), path)),
+            Some((Source::Project(project_file.clone()), project_file.clone())),
+        ];
+        for (source, path) in files.into_iter().flatten() {
+            let Some(mut file) = read_config_file(&path)? else {
+                continue;
+            };
+            known.extend(file.profile.keys().cloned());
+            if let Some(name) = profile {
+                if let Some(overrides) = file.profile.remove(name) {
+                    profiles.push((overrides, Source::Profile(name.to_string(), path)));
+                }
+            }
+            config.merge_file(file, &source);
+        }
+        if let Some(name) = profile {
+            if profiles.is_empty() {
+                known.sort();
+                known.dedup();
+                anyhow::bail!(Coded::new(
+                    Code::InvalidConfig,
+                    format!(
+                        "No profile {name} in the config files (defined: {})",
+                        if known.is_empty() {
+                            "none".to_string()
+                        } else {
+                            known.join(", ")
+                        }
+                    )
+                ));
+            }
+        }
+        for (overrides, source) in profiles {
+            config.merge_file(overrides, &source);
+        }
+
+        config.user_file = user_file;
+        config.project_file = project_file;
+        config.profile = profile.map(str::to_string);
+        Ok(config)
+    }
+
+    /// Load config files and apply common command-line flags on top
+    pub fn resolve(common: &CommonOptions) -> Result<Self> {
+        let mut config = Self::load(common.root(), common.profile.as_deref())?;
+        config.merge_common(common);
+        diagnostics::init(config.diagnostic_filters()?);
+        capabilities::init(config.capabilities_allow.value.clone());
+        if config.lenient_parse.value {
+            jsonc::set_lenient();
+        }
+        if common.deterministic {
+            timestamp::fix_clock();
+            output::relative_paths(common.root());
+        }
+        Ok(config)
+    }
+
+    fn merge_file(&mut self, file: ConfigFile, source: &Source) {
+        self.ignore.set(file.ignore, source);
+        self.no_default_ignore.set(file.no_default_ignore, source);
+        self.no_global_ignore.set(file.no_global_ignore, source);
+        self.lenient_parse.set(file.lenient_parse, source);
+        self.nested_modules.set(file.nested_modules, source);
+        self.group_output.set(file.group_output, source);
+        self.root_markers.set(file.root_markers, source);
+        self.submodules.set(file.submodules, source);
+        self.jobs.set(file.jobs.map(|n| Some(n.get())), source);
+        self.verbose.set(file.verbose, source);
+        self.pins.set(file.pins, source);
+        self.renames.set(file.renames, source);
+
+        let apply = file.apply;
+        self.skip_update.set(apply.skip_update, source);
+        self.repeat.set(apply.repeat.map(NonZeroU32::get), source);
+        self.repeat_until_stable
+            .set(apply.repeat_until_stable, source);
+        self.packages.set(apply.packages, source);
+        self.package_match.set(apply.package_match, source);
+        self.fail_fast.set(apply.fail_fast, source);
+        self.justfile.set(apply.justfile, source);
+        self.justfile_mode.set(apply.justfile_mode, source);
+        self.verify.set(apply.verify, source);
+        self.respect_toolchain.set(apply.respect_toolchain, source);
+        self.min_age.set(apply.min_age.map(Some), source);
+        self.fix_yanked.set(apply.fix_yanked, source);
+        self.update_git_deps.set(apply.update_git_deps, source);
+        self.fail_on.set(apply.fail_on, source);
+        self.network_jobs
+            .set(apply.network_jobs.map(|n| Some(n.get())), source);
+        self.verify_jobs
+            .set(apply.verify_jobs.map(|n| Some(n.get())), source);
+        self.confirm_threshold.set(apply.confirm_threshold, source);
+        self.max_error_length.set(apply.max_error_length, source);
+        self.group_errors.set(apply.group_errors, source);
+
+        self.just_mode.set(file.just.mode, source);
+        self.just_task_runner.set(file.just.task_runner, source);
+        self.just_recipes.set(file.just.recipes, source);
+        self.just_extra_recipes.set(file.just.extra_recipes, source);
+        self.just_set.set(file.just.set, source);
+        self.just_repos.set(file.just.repos, source);
+        self.just_root_file.set(file.just.root_file, source);
+        self.gitignore_mode.set(file.gitignore.mode, source);
+        self.renovate_mode.set(file.renovate.mode, source);
+        self.renovate_datasource_url
+            .set(file.renovate.datasource_url.map(Some), source);
+
+        let spdx = file.spdx;
+        self.spdx_license.set(spdx.license.map(Some), source);
+        self.spdx_holder.set(spdx.holder.map(Some), source);
+        self.spdx_exclude.set(spdx.exclude, source);
+
+        let email = file.email;
+        self.email_smtp_url.set(email.smtp_url.map(Some), source);
+        self.email_from.set(email.from.map(Some), source);
+        self.email_username.set(email.username.map(Some), source);
+        self.email_password_env
+            .set(email.password_env.map(Some), source);
+        self.email_to.set(email.to, source);
+        self.email_on.set(email.on, source);
+
+        self.runner.set(file.runner.default, source);
+        self.runner_repos.set(file.runner.repos, source);
+
+        self.ssh_repos.set(file.ssh.repos, source);
+        self.ssh_max_per_host
+            .set(file.ssh.max_per_host.map(|n| Some(n.get())), source);
+
+        self.clone_repos.set(file.clone.repos, source);
+        self.clone_full.set(file.clone.full, source);
+
+        let pr = file.pr;
+        self.pr_open.set(pr.open, source);
+        self.pr_provider.set(pr.provider.map(Some), source);
+        self.pr_api_url.set(pr.api_url.map(Some), source);
+        self.pr_token_env.set(pr.token_env.map(Some), source);
+        self.pr_branch.set(pr.branch, source);
+        self.pr_base.set(pr.base.map(Some), source);
+        self.pr_title.set(pr.title, source);
+        self.pr_body.set(pr.body, source);
+        self.pr_repos.set(pr.repos, source);
+        self.pr_update_submodules.set(pr.update_submodules, source);
+
+        let commit = file.commit;
+        self.commit_author_name
+            .set(commit.author_name.map(Some), source);
+        self.commit_author_email
+            .set(commit.author_email.map(Some), source);
+        self.commit_committer_name
+            .set(commit.committer_name.map(Some), source);
+        self.commit_committer_email
+            .set(commit.committer_email.map(Some), source);
+        self.commit_sign.set(commit.sign, source);
+        self.commit_signing_key
+            .set(commit.signing_key.map(Some), source);
+        self.commit_signing_format
+            .set(commit.signing_format.map(Some), source);
+
+        self.changelog_file
+            .set(file.changelog.file.map(Some), source);
+        self.changelog_format.set(file.changelog.format, source);
+        self.changelog_entry
+            .set(file.changelog.entry.map(Some), source);
+
+        self.issues_after
+            .set(file.issues.after.map(|n| Some(n.get())), source);
+        self.issues_labels.set(file.issues.labels, source);
+
+        self.limits_nice.set(file.limits.nice.map(Some), source);
+        self.limits_cpu
+            .set(file.limits.cpu.map(|n| Some(n.get())), source);
+        self.limits_memory.set(file.limits.memory.map(Some), source);
+
+        self.disk_min_free.set(file.disk.min_free, source);
+        self.disk_on_low.set(file.disk.on_low, source);
+        self.registry_index_url
+            .set(file.registry.index_url.map(Some), source);
+        self.registry_max_parallel
+            .set(file.registry.max_parallel.map(NonZeroUsize::get), source);
+
+        self.owners_repos.set(file.owners.repos, source);
+        self.owners_emails.set(file.owners.emails, source);
+
+        self.diagnostics_allow.set(file.diagnostics.allow, source);
+        self.diagnostics_warn.set(file.diagnostics.warn, source);
+        self.diagnostics_deny.set(file.diagnostics.deny, source);
+
+        self.gate_fail_on.set(file.gate.fail_on, source);
+        self.gate_max_major_lag.set(file.gate.max_major_lag, source);
+        self.gate_checks.set(file.gate.checks, source);
+        self.gate_yanked.set(file.gate.yanked, source);
+        self.gate_deprecated.set(file.gate.deprecated, source);
+
+        self.capabilities_allow.set(file.capabilities.allow, source);
+    }
+
+    fn merge_common(&mut self, common: &CommonOptions) {
+        self.ignore
+            .cli((!common.ignores.is_empty()).then(|| common.ignores.clone()));
+        self.no_default_ignore
+            .cli(common.no_default_ignore.then_some(true));
+        self.no_global_ignore
+            .cli(common.no_global_ignore.then_some(true));
+        self.lenient_parse.cli(common.lenient_parse.then_some(true));
+        self.nested_modules.cli(common.nested_modules);
+        self.group_output.cli(common.group_output);
+        self.root_markers
+            .cli((!common.root_markers.is_empty()).then(|| common.root_markers.clone()));
+        self.submodules.cli(common.submodules);
+        self.owner_filter.clone_from(&common.owners);
+        let list = |codes: &Vec<String>| (!codes.is_empty()).then(|| codes.clone());
+        self.diagnostics_allow.cli(list(&common.allow));
+        self.diagnostics_warn.cli(list(&common.warn));
+        self.diagnostics_deny.cli(list(&common.deny));
+        self.extra_roots = common.roots[1..].to_vec();
+        self.jobs.cli(common.jobs.map(Some));
+        // One job keeps the order of progress output and side effects fixed
+        self.jobs.cli(common.deterministic.then_some(Some(1)));
+        self.verbose.cli(common.verbose.then_some(true));
+        self.limits_nice.cli(common.nice.map(Some));
+        self.limits_cpu.cli(common.cpu_limit.map(Some));
+        self.limits_memory
+            .cli(common.memory_limit.clone().map(Some));
+        if let Some(image) = &common.in_container {
+            self.runner.cli(Some(RunnerSpec::Image {
+                runtime: common.container_runtime.clone(),
+                image: image.clone(),
+            }));
+            self.runner_repos.cli(Some(Vec::new()));
+        }
+    }
+
+    /// Number of parallel jobs, falling back to CPU cores / 2
+    pub fn effective_jobs(&self) -> usize {
+        self.jobs
+            .value
+            .unwrap_or_else(|| num_cpus::get() / 2)
+            .max(1)
+    }
+
+    /// Severity overrides from `--allow`, `--warn` and `--deny`
+    pub fn diagnostic_filters(&self) -> Result<diagnostics::Filters> {
+        diagnostics::Filters::new(
+            &self.diagnostics_allow.value,
+            &self.diagnostics_warn.value,
+            &self.diagnostics_deny.value,
+        )
+    }
+
+    /// Resource limits for moon processes
+    pub fn limits(&self) -> Result<limits::Limits> {
+        if self.limits_nice.value.is_some_and(|nice| nice > 19) {
+            anyhow::bail!(Coded::new(
+                Code::InvalidConfig,
+                "[limits] nice must be between 0 and 19"
+            ));
+        }
+        Ok(limits::Limits {
+            nice: self.limits_nice.value,
+            cpu_percent: self.limits_cpu.value,
+            memory: self
+                .limits_memory
+                .value
+                .as_deref()
+                .map(limits::parse_size)
+                .transpose()?,
+        })
+    }
+
+    /// The dependencies `packages` selects
+    pub fn package_filter(&self) -> Result<package_filter::Filter> {
+        package_filter::Filter::new(&self.packages.value, self.package_match.value)
+    }
+
+    /// SMTP settings for sending reports; `smtp-url` and `from` are required
+    pub fn smtp(&self) -> Result<email::SmtpSettings> {
+        let (Some(url), Some(from)) = (&self.email_smtp_url.value, &self.email_from.value) else {
+            anyhow::bail!(Coded::new(
+                Code::InvalidConfig,
+                "Sending email requires smtp-url and from in the [email] config section"
+            ));
+        };
+        Ok(email::SmtpSettings {
+            url: url.clone(),
+            from: from.clone(),
+            username: self.email_username.value.clone(),
+            password_env: self.email_password_env.value.clone(),
+        })
+    }
+
+    /// Identity and signing for commits; the committer defaults to the author
+    pub fn commit_settings(&self) -> Result<forge::CommitSettings> {
+        if self.commit_author_name.value.is_some() != self.commit_author_email.value.is_some() {
+            anyhow::bail!(Coded::new(
+                Code::InvalidConfig,
+                "Set both author-name and author-email in the [commit] config section"
+            ));
+        }
+        Ok(forge::CommitSettings {
+            author_name: self.commit_author_name.value.clone(),
+            author_email: self.commit_author_email.value.clone(),
+            committer_name: self
+                .commit_committer_name
+                .value
+                .clone()
+                .or_else(|| self.commit_author_name.value.clone()),
+            committer_email: self
+                .commit_committer_email
+                .value
+                .clone()
+                .or_else(|| self.commit_author_email.value.clone()),
+            sign: self.commit_sign.value,
+            signing_key: self.commit_signing_key.value.clone(),
+            signing_format: self.commit_signing_format.value,
+        })
+    }
+
+    /// Render the effective configuration as annotated TOML
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str(&format!(
+            "# user config:    {}\n",
+            describe_file(self.user_file.as_deref())
+        ));
+        out.push_str(&format!(
+            "# project config: {}\n",
+            describe_file(Some(&self.project_file))
+        ));
+        if let Some(profile) = &self.profile {
+            out.push_str(&format!("# profile:        {profile}\n"));
+        }
+        out.push('\n');
+
+        push_setting(&mut out, "ignore", &self.ignore);
+        push_setting(&mut out, "no-default-ignore", &self.no_default_ignore);
+        push_setting(&mut out, "no-global-ignore", &self.no_global_ignore);
+        push_setting(&mut out, "lenient-parse", &self.lenient_parse);
+        push_setting(&mut out, "nested-modules", &self.nested_modules);
+        push_setting(&mut out, "group-output", &self.group_output);
+        push_setting(&mut out, "root-markers", &self.root_markers);
+        push_setting(&mut out, "submodules", &self.submodules);
+        push_setting(
+            &mut out,
+            "jobs",
+            &Setting {
+                value: self.effective_jobs(),
+                source: self.jobs.source.clone(),
+            },
+        );
+        push_setting(&mut out, "verbose", &self.verbose);
+        push_setting(&mut out, "pins", &self.pins);
+        push_setting(&mut out, "renames", &self.renames);
+
+        out.push_str("\n[apply]\n");
+        push_setting(&mut out, "skip-update", &self.skip_update);
+        push_setting(&mut out, "repeat", &self.repeat);
+        push_setting(&mut out, "repeat-until-stable", &self.repeat_until_stable);
+        push_setting(&mut out, "packages", &self.packages);
+        push_setting(&mut out, "package-match", &self.package_match);
+        push_setting(&mut out, "fail-fast", &self.fail_fast);
+        push_setting(&mut out, "justfile", &self.justfile);
+        push_setting(&mut out, "justfile-mode", &self.justfile_mode);
+        push_setting(&mut out, "verify", &self.verify);
+        push_setting(&mut out, "respect-toolchain", &self.respect_toolchain);
+        push_setting(&mut out, "min-age", &self.min_age);
+        push_setting(&mut out, "fix-yanked", &self.fix_yanked);
+        push_setting(&mut out, "update-git-deps", &self.update_git_deps);
+        push_setting(&mut out, "fail-on", &self.fail_on);
+        push_setting(&mut out, "confirm-threshold", &self.confirm_threshold);
+        push_setting(&mut out, "max-error-length", &self.max_error_length);
+        push_setting(&mut out, "group-errors", &self.group_errors);
+        for (key, setting) in [
+            ("network-jobs", &self.network_jobs),
+            ("verify-jobs", &self.verify_jobs),
+        ] {
+            push_setting(
+                &mut out,
+                key,
+                &Setting {
+                    value: setting.value.unwrap_or_else(|| self.effective_jobs()),
+                    source: setting.source.clone(),
+                },
+            );
+        }
+
+        out.push_str("\n[just]\n");
+        push_setting(&mut out, "mode", &self.just_mode);
+        push_setting(&mut out, "task-runner", &self.just_task_runner);
+        push_setting(&mut out, "recipes", &self.just_recipes);
+        push_setting(&mut out, "extra-recipes", &self.just_extra_recipes);
+        push_setting(&mut out, "set", &self.just_set);
+        push_setting(&mut out, "repos", &self.just_repos);
+        push_setting(&mut out, "root-file", &self.just_root_file);
+
+        out.push_str("\n[gitignore]\n");
+        push_setting(&mut out, "mode", &self.gitignore_mode);
+
+        out.push_str("\n[renovate]\n");
+        push_setting(&mut out, "mode", &self.renovate_mode);
+        push_setting(&mut out, "datasource-url", &self.renovate_datasource_url);
+
+        out.push_str("\n[spdx]\n");
+        push_setting(&mut out, "license", &self.spdx_license);
+        push_setting(&mut out, "holder", &self.spdx_holder);
+        push_setting(&mut out, "exclude", &self.spdx_exclude);
+
+        out.push_str("\n[email]\n");
+        push_setting(&mut out, "smtp-url", &self.email_smtp_url);
+        push_setting(&mut out, "from", &self.email_from);
+        push_setting(&mut out, "username", &self.email_username);
+        push_setting(&mut out, "password-env", &self.email_password_env);
+        push_setting(&mut out, "to", &self.email_to);
+        push_setting(&mut out, "on", &self.email_on);
+
+        out.push_str("\n[runner]\n");
+        push_setting(&mut out, "default", &self.runner);
+        push_setting(&mut out, "repos", &self.runner_repos);
+
+        out.push_str("\n[ssh]\n");
+        push_setting(&mut out, "repos", &self.ssh_repos);
+        push_setting(&mut out, "max-per-host", &self.ssh_max_per_host);
+
+        out.push_str("\n[clone]\n");
+        push_setting(&mut out, "repos", &self.clone_repos);
+        push_setting(&mut out, "full", &self.clone_full);
+
+        out.push_str("\n[pr]\n");
+        push_setting(&mut out, "open", &self.pr_open);
+        push_setting(&mut out, "provider", &self.pr_provider);
+        push_setting(&mut out, "api-url", &self.pr_api_url);
+        push_setting(&mut out, "token-env", &self.pr_token_env);
+        push_setting(&mut out, "branch", &self.pr_branch);
+        push_setting(&mut out, "base", &self.pr_base);
+        push_setting(&mut out, "title", &self.pr_title);
+        push_setting(&mut out, "body", &self.pr_body);
+        push_setting(&mut out, "repos", &self.pr_repos);
+        push_setting(&mut out, "update-submodules", &self.pr_update_submodules);
+
+        out.push_str("\n[commit]\n");
+        push_setting(&mut out, "author-name", &self.commit_author_name);
+        push_setting(&mut out, "author-email", &self.commit_author_email);
+        push_setting(&mut out, "committer-name", &self.commit_committer_name);
+        push_setting(&mut out, "committer-email", &self.commit_committer_email);
+        push_setting(&mut out, "sign", &self.commit_sign);
+        push_setting(&mut out, "signing-key", &self.commit_signing_key);
+        push_setting(&mut out, "signing-format", &self.commit_signing_format);
+
+        out.push_str("\n[changelog]\n");
+        push_setting(&mut out, "file", &self.changelog_file);
+        push_setting(&mut out, "format", &self.changelog_format);
+        push_setting(&mut out, "entry", &self.changelog_entry);
+
+        out.push_str("\n[issues]\n");
+        push_setting(&mut out, "after", &self.issues_after);
+        push_setting(&mut out, "labels", &self.issues_labels);
+
+        out.push_str("\n[limits]\n");
+        push_setting(&mut out, "nice", &self.limits_nice);
+        push_setting(&mut out, "cpu", &self.limits_cpu);
+        push_setting(&mut out, "memory", &self.limits_memory);
+
+        out.push_str("\n[disk]\n");
+        push_setting(&mut out, "min-free", &self.disk_min_free);
+        push_setting(&mut out, "on-low", &self.disk_on_low);
+
+        out.push_str("\n[registry]\n");
+        push_setting(&mut out, "index-url", &self.registry_index_url);
+        push_setting(&mut out, "max-parallel", &self.registry_max_parallel);
+
+        out.push_str("\n[owners]\n");
+        push_setting(&mut out, "emails", &self.owners_emails);
+        push_setting(&mut out, "repos", &self.owners_repos);
+
+        out.push_str("\n[diagnostics]\n");
+        push_setting(&mut out, "allow", &self.diagnostics_allow);
+        push_setting(&mut out, "warn", &self.diagnostics_warn);
+        push_setting(&mut out, "deny", &self.diagnostics_deny);
+
+        out.push_str("\n[gate]\n");
+        push_setting(&mut out, "fail-on", &self.gate_fail_on);
+        push_setting(&mut out, "max-major-lag", &self.gate_max_major_lag);
+        push_setting(&mut out, "checks", &self.gate_checks);
+        push_setting(&mut out, "yanked", &self.gate_yanked);
+        push_setting(&mut out, "deprecated", &self.gate_deprecated);
+
+        out.push_str("\n[capabilities]\n");
+        push_setting(&mut out, "allow", &self.capabilities_allow);
+
+        out
+    }
+}
+
+fn describe_file(path: Option<&Path>) -> String {
+    match path {
+        Some(path) if path.exists() => path.display().to_string(),
+        Some(path) => format!("{} (not found)", path.display()),
+        None => "(unavailable: HOME not set)".to_string(),
+    }
+}
+
+fn push_setting<T: Serialize>(out: &mut String, key: &str, setting: &Setting<T>) {
+    // Unset optional values have no TOML representation; show them commented out
+    let line = match toml::Value::try_from(&setting.value) {
+        Ok(value) => format!("{key} = {value}"),
+        Err(_) => format!("# {key} ="),
+    };
+    out.push_str(&format!("{line:<40} # {}\n", setting.source));
+}
+
+// =============================================================================
+// File Loading
+// =============================================================================
+
+/// User config directory: `$XDG_CONFIG_HOME/moon-dst` or `~/.config/moon-dst`
+pub fn user_config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("moon-dst"))
+}
+
+/// User config path: `<user config dir>/config.toml`
+pub fn user_config_path() -> Option<PathBuf> {
+    user_config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Read and parse a config file, returning `None` if it does not exist
+pub fn read_config_file(path: &Path) -> Result<Option<ConfigFile>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file = parse_config(&content)
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    if let Some((name, _)) = file.profile.iter().find(|(_, p)| !p.profile.is_empty()) {
+        anyhow::bail!(Coded::new(
+            Code::InvalidConfig,
+            format!(
+                "Invalid config file {}: profile {name} defines profiles of its own",
+                path.display()
+            )
+        ));
+    }
+    Ok(Some(file))
+}
+
+fn parse_config(content: &str) -> Result<ConfigFile, toml::de::Error> {
+    toml::from_str(content)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_rejects_unknown_and_zero() {
+        let err = parse_config("[apply]\nrepaet = 2\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+
+        let err = parse_config("jobs = 1\n\n[apply]\nrepeat = 0\n").unwrap_err();
+        assert!(err.to_string().contains("line 4"));
+    }
+
+    #[test]
+    fn test_merge_layers_track_source() {
+        let dir = std::env::temp_dir().join("moon_dst_config_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        let project = dir.join(PROJECT_CONFIG_FILE);
+        std::fs::write(&user, "jobs = 3\n[apply]\nrepeat = 2\n").unwrap();
+        std::fs::write(&project, "[apply]\nrepeat = 5\njustfile-mode = \"skip\"\n").unwrap();
+
+        let mut config = Config::load_from(Some(user.clone()), project.clone(), None).unwrap();
+        assert_eq!(config.jobs.value, Some(3));
+        assert_eq!(config.jobs.source, Source::User(user.clone()));
+        assert_eq!(config.repeat.value, 5);
+        assert_eq!(config.repeat.source, Source::Project(project.clone()));
+        assert!(matches!(config.justfile_mode.value, WriteMode::Skip));
+        assert_eq!(config.fail_fast.source, Source::Default);
+
+        config.repeat.cli(Some(7));
+        assert_eq!(config.repeat.value, 7);
+        assert_eq!(config.repeat.source, Source::Cli);
+
+        // The project's profile beats the user's, which beats both files
+        std::fs::write(
+            &user,
+            "jobs = 3\n[profile.ci]\njobs = 1\n[profile.ci.apply]\nrepeat = 9\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &project,
+            "[apply]\nrepeat = 5\n[profile.ci.apply]\nverify = true\nrepeat = 4\n",
+        )
+        .unwrap();
+        let config = Config::load_from(Some(user.clone()), project.clone(), Some("ci")).unwrap();
+        assert_eq!(config.jobs.value, Some(1));
+        assert_eq!(
+            config.jobs.source,
+            Source::Profile("ci".to_string(), user.clone())
+        );
+        assert_eq!(config.repeat.value, 4);
+        assert!(config.verify.value);
+        let err = Config::load_from(Some(user), project, Some("weekly")).unwrap_err();
+        assert!(err.to_string().contains("(defined: ci)"));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: MIT
+//! Confirmation before an apply changes many repos
+//!
+//! A mistyped `--root` can turn an update of one project into one of the
+//! whole fleet. When an apply that is not a dry run would change more repos
+//! than `confirm-threshold`, it first shows what it is about to do and asks
+//! on the terminal. Without a terminal it refuses; `--yes` skips the
+//! question.
+
+/// What an apply is about to change
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Scope {
+    /// Repos with at least one change
+    pub repos: usize,
+    /// Repos running `moon add`, and the packages added in all of them
+    pub add_repos: usize,
+    pub adds: usize,
+    /// Git dependencies moved to a new revision
+    pub git_revs: usize,
+    /// Task runner files created, and existing ones merged into
+    pub files_created: usize,
+    pub files_merged: usize,
+    /// `justfile`, `Makefile` or `mise.toml`
+    pub file_name: &'static str,
+}
+
+impl Scope {
+    /// Whether the scope is large enough to ask; a threshold of 0 never asks
+    pub fn needs_confirmation(&self, threshold: usize) -> bool {
+        threshold > 0 && self.repos > threshold
+    }
+
+    /// `will run moon add in 73 repos (120 packages), create 12 justfile files`
+    pub fn summary(&self) -> String {
+        let plural = |n: usize, word: &str| {
+            if n == 1 {
+                format!("{n} {word}")
+            } else {
+                format!("{n} {word}s")
+            }
+        };
+        let mut parts = Vec::new();
+        if self.adds > 0 {
+            parts.push(format!(
+                "run moon add in {} ({})",
+                plural(self.add_repos, "repo"),
+                plural(self.adds, "package")
+            ));
+        }
+        if self.git_revs > 0 {
+            parts.push(format!("update {}", plural(self.git_revs, "git revision")));
+        }
+        // The file name is not a noun to pluralise (`mise.tomls`)
+        let files = format!("{} file", self.file_name);
+        if self.files_created > 0 {
+            parts.push(format!("create {}", plural(self.files_created, &files)));
+        }
+        if self.files_merged > 0 {
+            parts.push(format!("merge into {}", plural(self.files_merged, &files)));
+        }
+        if parts.is_empty() {
+            parts.push("run moon update".to_string());
+        }
+        format!(
+            "will {} across {}",
+            parts.join(", "),
+            plural(self.repos, "repo")
+        )
+    }
+}
+
+/// Whether an answer to the prompt means yes
+pub fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope() -> Scope {
+        Scope {
+            repos: 73,
+            add_repos: 73,
+            adds: 120,
+            git_revs: 1,
+            files_created: 12,
+            files_merged: 0,
+            file_name: "justfile",
+        }
+    }
+
+    #[test]
+    fn test_summary_lists_each_change() {
+        assert_eq!(
+            scope().summary(),
+            "will run moon add in 73 repos (120 packages), update 1 git revision, \
+             create 12 justfile files across 73 repos"
+        );
+        let scope = Scope {
+            files_created: 0,
+            files_merged: 1,
+            file_name: "mise.toml",
+            ..scope()
+        };
+        assert!(scope
+            .summary()
+            .contains("merge into 1 mise.toml file across"));
+    }
+
+    #[test]
+    fn test_summary_without_changes_runs_update() {
+        let scope = Scope {
+            repos: 1,
+            ..Default::default()
+        };
+        assert_eq!(scope.summary(), "will run moon update across 1 repo");
+    }
+
+    #[test]
+    fn test_needs_confirmation_above_threshold() {
+        assert!(scope().needs_confirmation(10));
+        assert!(!scope().needs_confirmation(73));
+        assert!(!scope().needs_confirmation(0));
+    }
+
+    #[test]
+    fn test_is_yes() {
+        assert!(is_yes(" Y\n"));
+        assert!(is_yes("yes"));
+        assert!(!is_yes(""));
+        assert!(!is_yes("no"));
+        assert!(!is_yes("yes please"));
+    }
+}
@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: MIT
+//! Duplicate dependencies across the fleet, for `dedupe-report`
+//!
+//! Two kinds of duplication are reported. A package can be known under
+//! several equivalent names (differing only in case, or `-` against `_`)
+//! or come from several sources (the registry, a path, a git URL) in
+//! different repos. And a registry package can be used at many versions
+//! fleet-wide. Each finding names a consolidation target: the most used
+//! name, or the highest version in use (the registry's latest when newer).
+//!
+//! Every extra version of a package is another copy in `.mooncakes`
+//! directories and package caches; the savings estimate multiplies the
+//! number of versions that would go away (all but one) by the average size
+//! of the package in the `.mooncakes` directories found.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::registry::compare_versions;
+
+/// One dependency declaration
+#[derive(Debug, Clone)]
+pub struct Usage {
+    pub package: String,
+    pub repo: String,
+    /// Declared version, for registry dependencies
+    pub version: Option<String>,
+    /// `registry`, `path` or `git`
+    pub source: &'static str,
+}
+
+/// Ways one package is referred to
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Alias {
+    /// Name (or source) to consolidate on
+    pub target: String,
+    pub names: Vec<NameUse>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct NameUse {
+    pub name: String,
+    pub source: &'static str,
+    pub repos: Vec<String>,
+}
+
+/// A registry package used at several versions
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Spread {
+    pub package: String,
+    /// Version to consolidate on
+    pub target: String,
+    /// Versions in use, ascending, with the repos using each
+    pub versions: Vec<VersionUse>,
+    /// Estimated bytes freed in caches once only `target` is used; `None`
+    /// when the package was in no cache
+    pub savings: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct VersionUse {
+    pub version: String,
+    pub repos: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub aliases: Vec<Alias>,
+    pub versions: Vec<Spread>,
+    /// Sum of the known savings
+    pub savings: u64,
+}
+
+/// Name under which equivalent spellings compare equal
+fn normalize(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
+/// Findings among `usages`; packages need at least `min_versions` versions
+/// to be listed
+///
+/// `latest` gives the registry's latest version of a package, and `size`
+/// its measured size in caches.
+pub fn report(
+    usages: &[Usage],
+    min_versions: usize,
+    latest: impl Fn(&str) -> Option<String>,
+    size: impl Fn(&str) -> Option<u64>,
+) -> Report {
+    let mut names: BTreeMap<String, BTreeMap<(&str, &'static str), BTreeSet<&str>>> =
+        BTreeMap::new();
+    let mut versions: BTreeMap<&str, BTreeMap<&str, BTreeSet<&str>>> = BTreeMap::new();
+    for usage in usages {
+        names
+            .entry(normalize(&usage.package))
+            .or_default()
+            .entry((&usage.package, usage.source))
+            .or_default()
+            .insert(&usage.repo);
+        if let (Some(version), "registry") = (&usage.version, usage.source) {
+            versions
+                .entry(&usage.package)
+                .or_default()
+                .entry(version)
+                .or_default()
+                .insert(&usage.repo);
+        }
+    }
+
+    let aliases = names
+        .into_values()
+        .filter(|uses| uses.len() > 1)
+        .map(|uses| {
+            let names: Vec<NameUse> = uses
+                .into_iter()
+                .map(|((name, source), repos)| NameUse {
+                    name: name.to_string(),
+                    source,
+                    repos: repos.into_iter().map(str::to_string).collect(),
+                })
+                .collect();
+            // The most used name, registry sources first on a tie
+            let target = names
+                .iter()
+                .max_by_key(|n| (n.repos.len(), n.source == "registry"))
+                .map(|n| format!("{} ({})", n.name, n.source))
+                .unwrap_or_default();
+            Alias { target, names }
+        })
+        .collect();
+
+    let versions: Vec<Spread> = versions
+        .into_iter()
+        .filter(|(_, in_use)| in_use.len() >= min_versions.max(2))
+        .map(|(package, in_use)| {
+            let mut in_use: Vec<VersionUse> = in_use
+                .into_iter()
+                .map(|(version, repos)| VersionUse {
+                    version: version.to_string(),
+                    repos: repos.into_iter().map(str::to_string).collect(),
+                })
+                .collect();
+            in_use.sort_by(|a, b| compare_versions(&a.version, &b.version));
+            let highest = in_use.last().map(|v| v.version.clone()).unwrap_or_default();
+            let target = latest(package)
+                .filter(|latest| compare_versions(latest, &highest).is_gt())
+                .unwrap_or(highest);
+            let dropped = in_use.len() as u64 - 1;
+            Spread {
+                package: package.to_string(),
+                target,
+                savings: size(package).map(|size| size * dropped),
+                versions: in_use,
+            }
+        })
+        .collect();
+
+    Report {
+        aliases,
+        savings: versions.iter().filter_map(|s| s.savings).sum(),
+        versions,
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(package: &str, repo: &str, version: Option<&str>, source: &'static str) -> Usage {
+        Usage {
+            package: package.to_string(),
+            repo: repo.to_string(),
+            version: version.map(str::to_string),
+            source,
+        }
+    }
+
+    fn usages() -> [Usage; 6] {
+        [
+            usage("acme/json_util", "a", Some("1.0.0"), "registry"),
+            usage("acme/json-util", "b", Some("1.2.0"), "registry"),
+            usage("acme/json-util", "c", Some("1.10.0"), "registry"),
+            usage("acme/json-util", "d", None, "path"),
+            usage("acme/x", "a", Some("0.1.0"), "registry"),
+            usage("acme/x", "b", Some("0.1.0"), "registry"),
+        ]
+    }
+
+    fn latest(package: &str) -> Option<String> {
+        (package == "acme/json-util").then(|| "2.0.0".to_string())
+    }
+
+    #[test]
+    fn test_report_finds_aliases() {
+        let report = report(&usages(), 2, latest, |_| None);
+        assert_eq!(report.aliases.len(), 1);
+        let alias = &report.aliases[0];
+        assert_eq!(alias.target, "acme/json-util (registry)");
+        assert_eq!(alias.names.len(), 3);
+    }
+
+    #[test]
+    fn test_report_finds_version_spread() {
+        let report = report(&usages(), 2, latest, |package| {
+            (package == "acme/json-util").then_some(100)
+        });
+        assert_eq!(report.versions.len(), 1);
+        let spread = &report.versions[0];
+        assert_eq!(spread.package, "acme/json-util");
+        assert_eq!(spread.target, "2.0.0");
+        let versions: Vec<&str> = spread.versions.iter().map(|v| v.version.as_str()).collect();
+        assert_eq!(versions, ["1.2.0", "1.10.0"]);
+        assert_eq!(spread.savings, Some(100));
+        assert_eq!(report.savings, 100);
+    }
+
+    #[test]
+    fn test_report_spread_below_threshold() {
+        let report = report(&usages(), 3, |_| None, |_| None);
+        assert!(report.versions.is_empty());
+    }
+}
@@ -0,0 +1,322 @@
+// SPDX-License-Identifier: MIT
+//! Warnings with codes and severities, collected during a run
+//!
+//! Warnings used to be printed as they happened, where parallel output
+//! buried them. Now each one is [`report`]ed with a [`Code`] and kept until
+//! the command is done: JSON output carries them in a `diagnostics` array,
+//! and anything not shown there is listed in a section on stderr at exit.
+//!
+//! `--allow`, `--warn` and `--deny` (or `[diagnostics]` in the config)
+//! change the severity of a code, by id (`A001`) or name
+//! (`invalid-manifest`): allowed codes are dropped, denied ones become
+//! errors and make the command fail.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::errors::{self, Coded};
+use crate::findings::{Level, Rule};
+
+/// What a diagnostic is about; indexes [`RULES`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    InvalidManifest,
+    SkippedRepo,
+    RegistryIndex,
+    Toolchain,
+    ExpiredPin,
+    ModuleLayout,
+    FlaggedVersion,
+    DiskSpace,
+    RunState,
+    Unsupported,
+    WorkspaceMember,
+    FileNotUpdated,
+    RemoteLookup,
+    SkipMarker,
+    SlowSubtree,
+    UnmatchedPackage,
+    RenamedPackage,
+    StaleBaseline,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "A001",
+        name: "invalid-manifest",
+        description: "A moon.mod.json could not be read or parsed and was skipped",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A002",
+        name: "skipped-repo",
+        description: "A repo, clone or remote could not be discovered and was left out",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A003",
+        name: "registry-index",
+        description: "The registry index is missing or lacks a package",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A004",
+        name: "toolchain",
+        description: "The installed moon is unknown or differs from a pinned toolchain",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A005",
+        name: "expired-pin",
+        description: "A dependency pin has expired",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A006",
+        name: "module-layout",
+        description: "A module is nested in another, or a module name is declared twice",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A007",
+        name: "flagged-version",
+        description: "Dependencies are pinned to yanked or deprecated versions",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A008",
+        name: "disk-space",
+        description: "Free disk space is low or could not be checked",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A009",
+        name: "run-state",
+        description: "The checkpoint or run history could not be used or written",
+        level: Level::Note,
+    },
+    Rule {
+        id: "A010",
+        name: "unsupported",
+        description: "A setting or file moon-dst cannot handle there was ignored or left as is",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A011",
+        name: "workspace-member",
+        description: "A workspace member has no moon.mod.json",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A012",
+        name: "file-not-updated",
+        description: "A file could not be written or merged and was left as is",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A013",
+        name: "remote-lookup",
+        description: "Information could not be fetched from a remote",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A014",
+        name: "skip-marker",
+        description: "A skip marker could not be read; the repo is skipped",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A015",
+        name: "slow-subtree",
+        description: "One subtree took most of the discovery walk",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A016",
+        name: "unmatched-package",
+        description: "A --package pattern matched no dependency in the fleet",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A017",
+        name: "renamed-package",
+        description: "A repo depends on a package under a name the renames config replaces",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "A018",
+        name: "stale-baseline",
+        description: "An outdated baseline accepts a dependency that is no longer outdated",
+        level: Level::Warning,
+    },
+];
+
+impl Code {
+    pub fn rule(self) -> &'static Rule {
+        &RULES[self as usize]
+    }
+}
+
+/// A reported warning at its effective severity
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub name: &'static str,
+    pub level: Level,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// `level[code]: message`
+    pub fn to_text(&self) -> String {
+        format!("{}[{}]: {}", self.level.as_str(), self.code, self.message)
+    }
+}
+
+/// Severity overrides by code id; `None` drops the code
+#[derive(Debug, Default, Clone)]
+pub struct Filters {
+    levels: BTreeMap<&'static str, Option<Level>>,
+}
+
+impl Filters {
+    /// Overrides from `--allow`, `--warn` and `--deny` lists; a code in
+    /// several lists gets the strictest
+    pub fn new(allow: &[String], warn: &[String], deny: &[String]) -> Result<Self> {
+        let mut levels = BTreeMap::new();
+        for (codes, level) in [
+            (allow, None),
+            (warn, Some(Level::Warning)),
+            (deny, Some(Level::Error)),
+        ] {
+            for code in codes {
+                levels.insert(lookup(code)?.id, level);
+            }
+        }
+        Ok(Filters { levels })
+    }
+
+    fn level(&self, rule: &Rule) -> Option<Level> {
+        self.levels
+            .get(rule.id)
+            .copied()
+            .unwrap_or(Some(rule.level))
+    }
+}
+
+fn lookup(code: &str) -> Result<&'static Rule> {
+    match RULES
+        .iter()
+        .find(|rule| rule.id.eq_ignore_ascii_case(code) || rule.name == code)
+    {
+        Some(rule) => Ok(rule),
+        None => bail!(Coded::new(
+            errors::Code::InvalidValue,
+            format!(
+                "Unknown diagnostic code {code} (known: {})",
+                RULES.iter().map(|r| r.id).collect::<Vec<_>>().join(", ")
+            )
+        )),
+    }
+}
+
+static FILTERS: OnceLock<Filters> = OnceLock::new();
+static REPORTED: Mutex<Vec<(Code, String)>> = Mutex::new(Vec::new());
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Set the severity overrides for this process; later calls are ignored
+pub fn init(filters: Filters) {
+    let _ = FILTERS.set(filters);
+}
+
+/// Record a warning; the same message for the same code is kept once
+pub fn report(code: Code, message: impl Into<String>) {
+    let message = message.into();
+    let mut reported = REPORTED.lock().unwrap_or_else(|e| e.into_inner());
+    if !reported.iter().any(|(c, m)| *c == code && *m == message) {
+        reported.push((code, message));
+    }
+}
+
+/// The diagnostics reported since the last call, at their effective
+/// severity and without allowed ones
+pub fn take() -> Vec<Diagnostic> {
+    let reported = std::mem::take(&mut *REPORTED.lock().unwrap_or_else(|e| e.into_inner()));
+    let filters = FILTERS.get().cloned().unwrap_or_default();
+    let diagnostics: Vec<Diagnostic> = reported
+        .into_iter()
+        .filter_map(|(code, message)| {
+            let rule = code.rule();
+            Some(Diagnostic {
+                code: rule.id,
+                name: rule.name,
+                level: filters.level(rule)?,
+                message,
+            })
+        })
+        .collect();
+    if diagnostics.iter().any(|d| d.level == Level::Error) {
+        FAILED.store(true, Ordering::Relaxed);
+    }
+    diagnostics
+}
+
+/// Print the diagnostics not yet shown as a section on stderr; returns
+/// false if any diagnostic of the run was an error
+pub fn finish() -> bool {
+    let diagnostics = take();
+    if !diagnostics.is_empty() {
+        eprintln!("\nDiagnostics:");
+        for diagnostic in &diagnostics {
+            eprintln!("  {}", diagnostic.to_text());
+        }
+    }
+    !FAILED.load(Ordering::Relaxed)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_rules_are_numbered_in_order() {
+        for (i, rule) in RULES.iter().enumerate() {
+            assert_eq!(rule.id, format!("A{:03}", i + 1));
+        }
+        assert_eq!(Code::SkipMarker.rule().name, "skip-marker");
+    }
+
+    #[test]
+    fn test_filters_change_severity() {
+        let filters = Filters::new(
+            &strings(&["A009", "toolchain"]),
+            &strings(&["run-state"]),
+            &strings(&["a001", "toolchain"]),
+        )
+        .unwrap();
+        let level = |code: Code| filters.level(code.rule());
+        assert_eq!(level(Code::InvalidManifest), Some(Level::Error));
+        assert_eq!(level(Code::RunState), Some(Level::Warning));
+        // deny wins over allow
+        assert_eq!(level(Code::Toolchain), Some(Level::Error));
+        assert_eq!(level(Code::SkippedRepo), Some(Level::Warning));
+        assert_eq!(level(Code::ExpiredPin), Some(Level::Warning));
+    }
+
+    #[test]
+    fn test_filters_reject_unknown_codes() {
+        assert!(Filters::new(&strings(&["A999"]), &[], &[]).is_err());
+        assert!(Filters::new(&[], &[], &strings(&["no-such-rule"])).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT
+//! Unified diff rendering for file changes
+//!
+//! Diffs printed to a terminal are colored unless `NO_COLOR` or `--plain`
+//! is set.
+
+use similar::TextDiff;
+use std::io::IsTerminal;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Render a unified diff between two texts with three lines of context
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string()
+}
+
+/// Diff of a file that would be written at `path` (`None` if it is new),
+/// colored for the terminal
+pub fn preview(path: &str, old: Option<&str>, new: &str) -> String {
+    let old_label = match old {
+        Some(_) => format!("a/{path}"),
+        None => "/dev/null".to_string(),
+    };
+    paint(&unified(
+        old.unwrap_or_default(),
+        new,
+        &old_label,
+        &format!("b/{path}"),
+    ))
+}
+
+/// Color a unified diff if stdout is a terminal and neither `NO_COLOR` nor
+/// `--plain` is set
+pub fn paint(diff: &str) -> String {
+    if std::env::var_os("NO_COLOR").is_some()
+        || !std::io::stdout().is_terminal()
+        || crate::output::plain()
+    {
+        return diff.to_string();
+    }
+    colorize(diff)
+}
+
+fn colorize(diff: &str) -> String {
+    diff.split_inclusive('\n')
+        .map(|line| {
+            let color = if line.starts_with("---") || line.starts_with("+++") {
+                BOLD
+            } else if line.starts_with("@@") {
+                CYAN
+            } else if line.starts_with('-') {
+                RED
+            } else if line.starts_with('+') {
+                GREEN
+            } else {
+                return line.to_string();
+            };
+            match line.strip_suffix('\n') {
+                Some(text) => format!("{color}{text}{RESET}\n"),
+                None => format!("{color}{line}{RESET}"),
+            }
+        })
+        .collect()
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_and_colorize() {
+        let created = preview("justfile", None, "test:\n    moon test\n");
+        assert!(created.starts_with("--- /dev/null\n+++ b/justfile\n@@ -0,0 +1,2 @@\n"));
+
+        let colored = colorize("--- a/x\n+++ b/x\n@@ -1 +1 @@\n-old\n+new\n same\n");
+        assert_eq!(
+            colored,
+            format!(
+                "{BOLD}--- a/x{RESET}\n{BOLD}+++ b/x{RESET}\n{CYAN}@@ -1 +1 @@{RESET}\n\
+                 {RED}-old{RESET}\n{GREEN}+new{RESET}\n same\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,244 @@
+// SPDX-License-Identifier: MIT
+//! Disk usage of dependency caches and build output, and free space
+//!
+//! Each module directory holds a dependency cache (`.mooncakes`) and build
+//! output (`target`, or `_build` with newer moon). `cache stats` reports
+//! them per repo; apply compares the expected growth with the free space of
+//! each filesystem first (see [`estimate_growth`]), and `clean` removes
+//! them.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+
+/// Dependency cache directory of a module
+pub const CACHE_DIR: &str = ".mooncakes";
+
+/// Build output directories of a module
+pub const BUILD_DIRS: &[&str] = &["target", "_build"];
+
+/// Assumed growth per repo when no repo has a cache or build output yet
+const DEFAULT_GROWTH: u64 = 64 << 20;
+
+/// Bytes used by caches and build output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub mooncakes: u64,
+    pub build: u64,
+}
+
+impl Usage {
+    pub fn total(&self) -> u64 {
+        self.mooncakes + self.build
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.mooncakes += other.mooncakes;
+        self.build += other.build;
+    }
+}
+
+/// Total size of the files below `path` (0 if it does not exist);
+/// symlinks are not followed
+pub fn dir_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Latest modification time of `path` or anything below it, `None` if it
+/// does not exist
+pub fn last_modified(path: &Path) -> Option<SystemTime> {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.metadata().ok()?.modified().ok())
+        .max()
+}
+
+/// Remove the directory `path` and return the bytes freed
+pub fn remove_dir(path: &Path) -> Result<u64> {
+    let size = dir_size(path);
+    std::fs::remove_dir_all(path)
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+    Ok(size)
+}
+
+/// Parse an age such as `30d`, `12h` or `2w` (units s, m, h, d and w)
+pub fn parse_age(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let (number, unit) = text.split_at(text.len() - text.chars().last().map_or(0, char::len_utf8));
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid age `{text}` (expected e.g. 30d or 12h)")
+        )),
+    };
+    match number.parse::<u64>() {
+        Ok(n) => Ok(Duration::from_secs(n.saturating_mul(seconds))),
+        Err(_) => bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid age `{text}` (expected e.g. 30d or 12h)")
+        )),
+    }
+}
+
+/// Cache and build output sizes of the module in `dir`
+pub fn module_usage(dir: &Path) -> Usage {
+    Usage {
+        mooncakes: dir_size(&dir.join(CACHE_DIR)),
+        build: BUILD_DIRS.iter().map(|d| dir_size(&dir.join(d))).sum(),
+    }
+}
+
+/// Expected growth of each repo during apply: the average cache and build
+/// size of the repos in `usages` that have any, as updates fetch new
+/// versions and checks rebuild
+pub fn estimate_growth(usages: &[Usage]) -> u64 {
+    let used: Vec<u64> = usages
+        .iter()
+        .map(Usage::total)
+        .filter(|&total| total > 0)
+        .collect();
+    if used.is_empty() {
+        DEFAULT_GROWTH
+    } else {
+        used.iter().sum::<u64>() / used.len() as u64
+    }
+}
+
+/// Mount point and available bytes of the filesystem holding each path
+pub fn free_space(paths: &[&Path]) -> Result<Vec<(String, u64)>> {
+    let output = Command::new("df")
+        .arg("-Pk")
+        .args(paths)
+        .logged_output()
+        .context("Failed to run df")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::DiskSpace,
+            format!(
+                "df failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    let free = parse_df(&String::from_utf8_lossy(&output.stdout));
+    if free.len() != paths.len() {
+        bail!(Coded::new(Code::DiskSpace, "Unexpected df output"));
+    }
+    Ok(free)
+}
+
+/// Parse `df -Pk` output into (mount point, available bytes) rows
+fn parse_df(output: &str) -> Vec<(String, u64)> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let available: u64 = fields.get(3)?.parse().ok()?;
+            // Mount points may contain spaces
+            let mount = fields.get(5..).filter(|m| !m.is_empty())?.join(" ");
+            Some((mount, available * 1024))
+        })
+        .collect()
+}
+
+/// Human-readable size in powers of 1024
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_usage_and_removal() {
+        let dir = std::env::temp_dir().join("moon_dst_disk_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(".mooncakes/a")).unwrap();
+        std::fs::create_dir_all(dir.join("_build")).unwrap();
+        std::fs::write(dir.join(".mooncakes/a/x.mbt"), [0u8; 100]).unwrap();
+        std::fs::write(dir.join("_build/out.js"), [0u8; 20]).unwrap();
+        assert!(last_modified(&dir.join("_build")).is_some());
+        assert!(last_modified(&dir.join("target")).is_none());
+        assert_eq!(
+            module_usage(&dir),
+            Usage {
+                mooncakes: 100,
+                build: 20
+            }
+        );
+        assert_eq!(remove_dir(&dir.join(".mooncakes")).unwrap(), 100);
+        assert_eq!(module_usage(&dir).total(), 20);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_estimate_growth() {
+        let usage = |total| Usage {
+            mooncakes: total,
+            build: 0,
+        };
+        assert_eq!(estimate_growth(&[usage(0), usage(100), usage(300)]), 200);
+        assert_eq!(estimate_growth(&[usage(0)]), DEFAULT_GROWTH);
+    }
+
+    #[test]
+    fn test_parse_df_keeps_mount_points_with_spaces() {
+        let df = "Filesystem 1024-blocks Used Available Capacity Mounted on\n/dev/sda1 1000 400 600 40% /\n/dev/sdb1 2000 0 2000 0% /mnt/my disk\n";
+        assert_eq!(
+            parse_df(df),
+            [
+                ("/".to_string(), 600 * 1024),
+                ("/mnt/my disk".to_string(), 2000 * 1024)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(3 << 29), "1.5 GiB");
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::from_secs(30 * 86400));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 3600));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+    }
+}
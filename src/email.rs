@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: MIT
+//! Sending reports by email through the system `curl` SMTP client
+//!
+//! SMTP settings come from the `[email]` config section. The password is
+//! never stored in the config file: `password-env` names an environment
+//! variable holding it, and it reaches curl through a config on stdin rather
+//! than the command line.
+
+use anyhow::{bail, Context, Result};
+use std::process::{Command, Stdio};
+
+use crate::audit::CommandExt;
+use crate::capabilities::{self, Capability};
+use crate::errors::{Code, Coded};
+
+/// SMTP server and sender
+#[derive(Debug, Clone)]
+pub struct SmtpSettings {
+    /// e.g. `smtps://smtp.example.com:465` or `smtp://localhost:25`
+    pub url: String,
+    pub from: String,
+    pub username: Option<String>,
+    /// Name of the environment variable holding the password
+    pub password_env: Option<String>,
+}
+
+/// A plain-text message
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Message {
+    /// RFC 5322 message with CRLF line endings
+    fn render(&self, from: &str) -> String {
+        let mut out = format!(
+            "From: {from}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.to.join(", "),
+            self.subject.replace(['\r', '\n'], " ")
+        );
+        for line in self.body.lines() {
+            // A lone "." ends the DATA section in SMTP
+            if line.starts_with('.') {
+                out.push('.');
+            }
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+        out
+    }
+}
+
+/// Send a message
+pub fn send(settings: &SmtpSettings, message: &Message) -> Result<()> {
+    if message.to.is_empty() {
+        bail!(Coded::new(Code::EmailFailed, "No email recipients"));
+    }
+    let path = std::env::temp_dir().join(format!("moon-dst-mail-{}.eml", std::process::id()));
+    std::fs::write(&path, message.render(&settings.from))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    let result = run_curl(settings, message, &path);
+    std::fs::remove_file(&path).ok();
+    result
+}
+
+fn run_curl(settings: &SmtpSettings, message: &Message, path: &std::path::Path) -> Result<()> {
+    let mut curl_config = String::new();
+    if let Some(username) = &settings.username {
+        let password = match &settings.password_env {
+            Some(var) => std::env::var(var).with_context(|| {
+                format!("Environment variable {var} (email password) is not set")
+            })?,
+            None => String::new(),
+        };
+        curl_config.push_str(&format!(
+            "user = \"{}\"\n",
+            format!("{username}:{password}")
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+        ));
+    }
+
+    capabilities::require(
+        Capability::Network,
+        &format!("Sending mail through {}", settings.url),
+    )?;
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--show-error", "--config", "-"])
+        .arg("--url")
+        .arg(&settings.url)
+        .arg("--mail-from")
+        .arg(&settings.from);
+    for to in &message.to {
+        command.arg("--mail-rcpt").arg(to);
+    }
+    let output = command
+        .arg("--upload-file")
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .logged_output_with_input(curl_config.as_bytes())
+        .context("Failed to run curl (is it installed?)")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::EmailFailed,
+            format!(
+                "Failed to send email via {}: {}",
+                settings.url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    Ok(())
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_escapes_dots_and_uses_crlf() {
+        let message = Message {
+            to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+            subject: "moon-dst\napply".to_string(),
+            body: "line\n.hidden\n".to_string(),
+        };
+        assert_eq!(
+            message.render("bot@example.com"),
+            "From: bot@example.com\r\nTo: a@example.com, b@example.com\r\nSubject: moon-dst apply\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nline\r\n..hidden\r\n"
+        );
+    }
+}
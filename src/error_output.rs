@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: MIT
+//! Full output of failures, kept for `show-error`
+//!
+//! A failed `moon add` can print pages of stderr, which used to land in the
+//! results as one summary line. Now the results show the first line, cut
+//! at `max-error-length` characters, and an ID; the whole text is stored
+//! under `errors/` in the data directory, named by the start of its
+//! SHA-256, and `moon-dst show-error <id>` prints it. The same output in
+//! several repos is stored once.
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::errors::{Code, Coded};
+
+/// Directory inside the data directory
+pub const DIR: &str = "errors";
+
+/// Hex digits of an ID
+const ID_LEN: usize = 12;
+
+struct Settings {
+    /// Where full output is stored; `None` without a data directory
+    dir: Option<PathBuf>,
+    /// 0 shows errors whole
+    max_len: usize,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Store output in `dir` and cut errors at `max_len`; later calls are ignored
+pub fn init(dir: Option<PathBuf>, max_len: usize) {
+    SETTINGS.get_or_init(|| Settings { dir, max_len });
+}
+
+/// ID of a full error text
+pub fn id(text: &str) -> String {
+    let digest = Sha256::digest(text.as_bytes());
+    digest
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()[..ID_LEN]
+        .to_string()
+}
+
+/// `error` for a results line: as is when it is one short line, else the
+/// first line cut short and the ID its full text is stored under
+pub fn brief(error: &str) -> String {
+    let error = error.trim();
+    let Some(settings) = SETTINGS.get() else {
+        return error.to_string();
+    };
+    let first = error.lines().next().unwrap_or_default();
+    let fits = settings.max_len == 0 || first.chars().count() <= settings.max_len;
+    if fits && first.len() == error.len() {
+        return error.to_string();
+    }
+    let mut line: String = match settings.max_len {
+        0 => first.to_string(),
+        max => first.chars().take(max).collect(),
+    };
+    line.push_str("...");
+    match settings.dir.as_deref().map(|dir| store(dir, error)) {
+        Some(Ok(id)) => format!("{line} (moon-dst show-error {id})"),
+        _ => line,
+    }
+}
+
+/// Store `text` in `dir`; returns its ID
+pub fn store(dir: &Path, text: &str) -> Result<String> {
+    let id = id(text);
+    let path = dir.join(format!("{id}.txt"));
+    if !path.exists() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        std::fs::write(&path, text)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(id)
+}
+
+/// The text stored in `dir` under `id` or a unique prefix of it
+pub fn load(dir: &Path, id: &str) -> Result<String> {
+    let id = id.trim().to_ascii_lowercase();
+    let mut found = Vec::new();
+    if !id.is_empty() {
+        for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(&id) && name.ends_with(".txt") {
+                found.push(entry.path());
+            }
+        }
+    }
+    match found.as_slice() {
+        [path] => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display())),
+        [] => bail!(Coded::new(
+            Code::FileNotFound,
+            format!("No stored error output with ID {id}")
+        )),
+        _ => bail!(Coded::new(
+            Code::InvalidValue,
+            format!(
+                "ID {id} matches {} stored errors; give more of it",
+                found.len()
+            )
+        )),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brief_stores_long_errors() {
+        let dir = std::env::temp_dir().join(format!("moon_dst_errors_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        init(Some(dir.clone()), 20);
+
+        assert_eq!(brief("  no such version\n"), "no such version");
+        let stderr = "error: failed to resolve acme/x\n  caused by: timeout\n";
+        let id = id(stderr.trim());
+        assert_eq!(
+            brief(stderr),
+            format!("error: failed to res... (moon-dst show-error {id})")
+        );
+        assert_eq!(load(&dir, &id[..6]).unwrap(), stderr.trim());
+        assert!(load(&dir, "ffffffff").is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
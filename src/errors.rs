@@ -0,0 +1,355 @@
+// SPDX-License-Identifier: MIT
+//! Stable codes for the ways a command can fail
+//!
+//! A failed command prints `Error[E014]: ...`, so runbooks and alert
+//! routing can match the code instead of the wording. Errors are tagged
+//! where they are raised by wrapping the message in [`Coded`]; errors from
+//! the standard library and parsers are classified by type, and anything
+//! else is `E999`. `moon-dst explain <CODE>` prints the cause and what to
+//! do about it, for these codes as well as diagnostic and lint codes.
+
+use std::fmt;
+
+/// A failure mode; indexes [`CODES`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    MoonNotFound,
+    InvalidRoot,
+    InvalidConfig,
+    InvalidValue,
+    InvalidManifest,
+    FileNotFound,
+    PermissionDenied,
+    Io,
+    InvalidJson,
+    GitFailed,
+    ForgeFailed,
+    PlanRejected,
+    NetworkFailed,
+    RegistryTimeout,
+    DiskSpace,
+    ExpiredPins,
+    ChecksumMismatch,
+    EmailFailed,
+    SshFailed,
+    ToolchainFailed,
+    UnknownPackage,
+    NotConfirmed,
+    CapabilityDenied,
+    AuditLogBroken,
+    Unclassified,
+}
+
+/// Description of an error code
+#[derive(Debug)]
+pub struct ErrorCode {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub cause: &'static str,
+    pub remedy: &'static str,
+}
+
+pub const CODES: &[ErrorCode] = &[
+    ErrorCode {
+        id: "E001",
+        name: "moon-not-found",
+        cause: "The moon CLI is neither on PATH nor in ~/.moon/bin, and the command runs moon in repos.",
+        remedy: "Install MoonBit (`moon-dst toolchain install <VERSION>`) or add it to PATH, or run moon in a container with --in-container.",
+    },
+    ErrorCode {
+        id: "E002",
+        name: "invalid-root",
+        cause: "A --root directory does not exist or cannot be resolved.",
+        remedy: "Pass existing directories to --root; several are separated by commas.",
+    },
+    ErrorCode {
+        id: "E003",
+        name: "invalid-config",
+        cause: "A config file, an [email] or [commit] section, or a message template is malformed or incomplete.",
+        remedy: "Fix the key or file named in the message; `moon-dst config show` prints the effective values and where they came from.",
+    },
+    ErrorCode {
+        id: "E004",
+        name: "invalid-value",
+        cause: "An option or config value does not have the expected form, or options were combined in a way that is not supported.",
+        remedy: "Use the form quoted in the message; `moon-dst <command> --help` lists the options.",
+    },
+    ErrorCode {
+        id: "E005",
+        name: "invalid-manifest",
+        cause: "A moon.mod.json is empty, over the size limit or not valid JSON.",
+        remedy: "Fix the manifest at the reported line, or exclude its directory with --ignore.",
+    },
+    ErrorCode {
+        id: "E006",
+        name: "file-not-found",
+        cause: "A file or directory the command needs does not exist.",
+        remedy: "Check the path in the message and create the file or pass the right one.",
+    },
+    ErrorCode {
+        id: "E007",
+        name: "permission-denied",
+        cause: "moon-dst is not allowed to read or write a file.",
+        remedy: "Fix the permissions, or run as a user who can access the tree.",
+    },
+    ErrorCode {
+        id: "E008",
+        name: "io-error",
+        cause: "Reading or writing a file or running a program failed for another reason, such as a full disk.",
+        remedy: "See the OS error at the end of the message and retry once it is fixed.",
+    },
+    ErrorCode {
+        id: "E009",
+        name: "invalid-json",
+        cause: "A JSON input (a plan, the registry index, piped scan or outdated output, an API response) could not be parsed.",
+        remedy: "Produce the input again; piped input must come from `--format json` output.",
+    },
+    ErrorCode {
+        id: "E010",
+        name: "git-failed",
+        cause: "A git command (ls-remote, ls-tree, clone, worktree) failed.",
+        remedy: "Run the git command from the message by hand; check the remote URL, credentials and that the ref exists.",
+    },
+    ErrorCode {
+        id: "E011",
+        name: "forge-failed",
+        cause: "A request to the code host's API, or pushing the update branch, failed.",
+        remedy: "Check the token, its permissions on the repo and the [pr] and [commit] settings; the message has the host's answer.",
+    },
+    ErrorCode {
+        id: "E012",
+        name: "plan-rejected",
+        cause: "A plan file was made for another root or format, was modified, or the repos changed since it was made.",
+        remedy: "Run `moon-dst plan` again and apply the new plan.",
+    },
+    ErrorCode {
+        id: "E013",
+        name: "network-failed",
+        cause: "A download failed, or a release lacks the expected file.",
+        remedy: "Check connectivity, proxy settings and the URL in the message, then retry.",
+    },
+    ErrorCode {
+        id: "E014",
+        name: "registry-timeout",
+        cause: "A request to the registry or a download server timed out.",
+        remedy: "Retry later; on slow or offline networks, check proxy settings or work from a vendored index.",
+    },
+    ErrorCode {
+        id: "E015",
+        name: "disk-space",
+        cause: "Free disk space is below `min-free` in the [disk] config section, or could not be measured.",
+        remedy: "Free space (`moon-dst clean`), or lower `min-free`.",
+    },
+    ErrorCode {
+        id: "E016",
+        name: "expired-pins",
+        cause: "--strict-pins is set and a dependency pin has expired.",
+        remedy: "Give the pin a later `expires` date, or remove it from `[[pins]]` in the config.",
+    },
+    ErrorCode {
+        id: "E017",
+        name: "checksum-mismatch",
+        cause: "A downloaded file does not match its published checksum, or no checksum was published.",
+        remedy: "Do not use the file; retry the download, and report the release if it keeps failing.",
+    },
+    ErrorCode {
+        id: "E018",
+        name: "email-failed",
+        cause: "Sending a report by email failed, or there was nobody to send it to.",
+        remedy: "Check smtp-url, from and the recipients in the [email] config section.",
+    },
+    ErrorCode {
+        id: "E019",
+        name: "ssh-failed",
+        cause: "A command on a remote host failed over SSH.",
+        remedy: "Check that `ssh <host>` works without a prompt and that the remote path exists.",
+    },
+    ErrorCode {
+        id: "E020",
+        name: "toolchain-failed",
+        cause: "Installing a MoonBit toolchain failed.",
+        remedy: "Run the installer by hand to see its output; HOME must be set and the network reachable.",
+    },
+    ErrorCode {
+        id: "E021",
+        name: "unknown-package",
+        cause: "A package is in neither the registry index nor any repo.",
+        remedy: "Check the owner/name spelling, and refresh the index with `moon update`.",
+    },
+    ErrorCode {
+        id: "E022",
+        name: "not-confirmed",
+        cause: "An apply would change more repos than `confirm-threshold` and was not confirmed, or could not ask because stdin is not a terminal.",
+        remedy: "Check --root and the selection in the summary; pass --yes to proceed without asking, or raise `confirm-threshold` in the [apply] config section.",
+    },
+    ErrorCode {
+        id: "E023",
+        name: "capability-denied",
+        cause: "The run tried to write a file in a repo, use the network, commit or push, and `[capabilities] allow` does not grant it.",
+        remedy: "Add the capability named in the message to `allow` (for example in the profile the run uses), or leave out the option that needs it.",
+    },
+    ErrorCode {
+        id: "E024",
+        name: "audit-log-broken",
+        cause: "An audit log entry was modified, removed or reordered after it was written, so the hash chain does not hold.",
+        remedy: "Treat the log as tampered with and investigate; keep the damaged file and start a new one by moving it aside.",
+    },
+    ErrorCode {
+        id: "E999",
+        name: "unclassified",
+        cause: "The error has no more specific code.",
+        remedy: "Read the message; if the failure deserves a code of its own, please report it.",
+    },
+];
+
+impl Code {
+    pub fn info(self) -> &'static ErrorCode {
+        &CODES[self as usize]
+    }
+}
+
+/// An error message tagged with its code, for `bail!` and `.context()`
+#[derive(Debug)]
+pub struct Coded {
+    pub code: Code,
+    message: String,
+}
+
+impl Coded {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Coded {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Coded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Coded {}
+
+/// Code of `error`: the outermost tag, or else the type of the first cause
+/// that has one
+pub fn classify(error: &anyhow::Error) -> Code {
+    if let Some(coded) = error.downcast_ref::<Coded>() {
+        return coded.code;
+    }
+    for cause in error.chain() {
+        if let Some(coded) = cause.downcast_ref::<Coded>() {
+            return coded.code;
+        }
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            return match io.kind() {
+                std::io::ErrorKind::NotFound => Code::FileNotFound,
+                std::io::ErrorKind::PermissionDenied => Code::PermissionDenied,
+                _ => Code::Io,
+            };
+        }
+        if cause.is::<serde_json::Error>() {
+            return Code::InvalidJson;
+        }
+        if cause.is::<toml::de::Error>() {
+            return Code::InvalidConfig;
+        }
+    }
+    Code::Unclassified
+}
+
+/// `explain` text of an error, diagnostic or lint code, by id or name
+pub fn explain(code: &str) -> Option<String> {
+    let matches = |id: &str, name: &str| id.eq_ignore_ascii_case(code) || name == code;
+    if let Some(info) = CODES.iter().find(|c| matches(c.id, c.name)) {
+        return Some(format!(
+            "{} {}\n\nCause: {}\nFix: {}",
+            info.id, info.name, info.cause, info.remedy
+        ));
+    }
+    crate::diagnostics::RULES
+        .iter()
+        .chain(crate::lint::RULES)
+        .chain(crate::gate::RULES)
+        .find(|rule| matches(rule.id, rule.name))
+        .map(|rule| {
+            format!(
+                "{} {} ({})\n\n{}",
+                rule.id,
+                rule.name,
+                rule.level.as_str(),
+                rule.description
+            )
+        })
+}
+
+/// One line per known code, for `explain` without an argument
+pub fn list() -> String {
+    let mut out = String::new();
+    for info in CODES {
+        out.push_str(&format!("{}  {:<18}  {}\n", info.id, info.name, info.cause));
+    }
+    for rule in crate::diagnostics::RULES
+        .iter()
+        .chain(crate::lint::RULES)
+        .chain(crate::gate::RULES)
+    {
+        out.push_str(&format!(
+            "{}  {:<18}  {}\n",
+            rule.id, rule.name, rule.description
+        ));
+    }
+    out
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Context, Result};
+
+    #[test]
+    fn test_codes_are_numbered_in_order() {
+        for (i, info) in CODES[..CODES.len() - 1].iter().enumerate() {
+            assert_eq!(info.id, format!("E{:03}", i + 1));
+        }
+        assert_eq!(Code::Unclassified.info().id, "E999");
+        assert_eq!(Code::RegistryTimeout.info().name, "registry-timeout");
+    }
+
+    #[test]
+    fn test_classify_finds_codes_through_context() {
+        fn timeout() -> Result<()> {
+            bail!(Coded::new(Code::RegistryTimeout, "timed out"))
+        }
+        let error = timeout().context("Failed to update").unwrap_err();
+        assert_eq!(classify(&error), Code::RegistryTimeout);
+        assert_eq!(format!("{error:#}"), "Failed to update: timed out");
+    }
+
+    #[test]
+    fn test_classify_io_and_json_errors() {
+        let error = std::fs::read("/nonexistent/moon_dst_errors_test")
+            .context("Failed to read")
+            .unwrap_err();
+        assert_eq!(classify(&error), Code::FileNotFound);
+        let error = serde_json::from_str::<u32>("x")
+            .context("parse")
+            .unwrap_err();
+        assert_eq!(classify(&error), Code::InvalidJson);
+        assert_eq!(classify(&anyhow::anyhow!("other")), Code::Unclassified);
+    }
+
+    #[test]
+    fn test_explain_by_id_or_name() {
+        assert!(explain("e014")
+            .unwrap()
+            .starts_with("E014 registry-timeout"));
+        assert!(explain("skip-marker").unwrap().starts_with("A014"));
+        assert!(explain("E998").is_none());
+    }
+}
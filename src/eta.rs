@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: MIT
+//! Estimated time remaining in apply runs
+//!
+//! Each apply run that is not a dry run keeps how long each repo took in
+//! `durations.json` in the data directory. The next run expects a repo to
+//! take about as long as before, and a repo seen for the first time as long
+//! as the others on average. The work left divided by the number of jobs is
+//! shown on the progress line and in `--results-out` events.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// State file name inside the data directory
+const FILE: &str = "durations.json";
+
+/// Seconds each repo took, smoothed over runs
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Durations {
+    repos: BTreeMap<String, f64>,
+}
+
+impl Durations {
+    /// Default state file location
+    pub fn path() -> Option<PathBuf> {
+        Some(crate::data_dir()?.join(FILE))
+    }
+
+    /// Durations saved at `path`; a missing file has none
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Take in this run's duration of `repo`; the expectation moves halfway
+    /// towards it, so one slow run does not dominate
+    pub fn record(&mut self, repo: &Path, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        self.repos
+            .entry(crate::ospath::encode(repo))
+            .and_modify(|expected| *expected = (*expected + seconds) / 2.0)
+            .or_insert(seconds);
+    }
+
+    fn get(&self, repo: &Path) -> Option<f64> {
+        self.repos.get(&crate::ospath::encode(repo)).copied()
+    }
+}
+
+/// Time left in a run, updated as repos finish
+pub struct Estimate {
+    jobs: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Unfinished repos with their expected seconds, if known
+    pending: BTreeMap<PathBuf, Option<f64>>,
+    /// Seconds taken by repos finished in this run
+    finished: Vec<f64>,
+}
+
+impl Estimate {
+    pub fn new(
+        durations: &Durations,
+        repos: impl IntoIterator<Item = PathBuf>,
+        jobs: usize,
+    ) -> Self {
+        let pending = repos
+            .into_iter()
+            .map(|repo| {
+                let expected = durations.get(&repo);
+                (repo, expected)
+            })
+            .collect();
+        Estimate {
+            jobs: jobs.max(1),
+            state: Mutex::new(State {
+                pending,
+                finished: Vec::new(),
+            }),
+        }
+    }
+
+    /// Note that `repo` finished after `duration`
+    pub fn finish(&self, repo: &Path, duration: Duration) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.pending.remove(repo);
+        state.finished.push(duration.as_secs_f64());
+    }
+
+    /// Expected time until all repos are done; `None` without any duration
+    /// to go by
+    pub fn remaining(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.pending.is_empty() {
+            return Some(Duration::ZERO);
+        }
+        let known: Vec<f64> = state
+            .pending
+            .values()
+            .flatten()
+            .chain(&state.finished)
+            .copied()
+            .collect();
+        if known.is_empty() {
+            return None;
+        }
+        let average = known.iter().sum::<f64>() / known.len() as f64;
+        let work: f64 = state.pending.values().map(|e| e.unwrap_or(average)).sum();
+        let lanes = self.jobs.min(state.pending.len());
+        Some(Duration::from_secs_f64(work / lanes as f64))
+    }
+}
+
+/// `42s`, `3m 20s` or `1h 05m`
+pub fn format(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn durations() -> Durations {
+        let mut durations = Durations::default();
+        durations.record(Path::new("/f/a"), Duration::from_secs(100));
+        durations.record(Path::new("/f/a"), Duration::from_secs(60));
+        durations.record(Path::new("/f/b"), Duration::from_secs(20));
+        durations
+    }
+
+    #[test]
+    fn test_durations_average_past_runs() {
+        assert_eq!(durations().get(Path::new("/f/a")), Some(80.0));
+        assert_eq!(durations().get(Path::new("/f/c")), None);
+    }
+
+    #[test]
+    fn test_estimate_from_past_durations() {
+        let repos = ["/f/a", "/f/b", "/f/c"].map(PathBuf::from);
+        let estimate = Estimate::new(&durations(), repos, 2);
+        // c is expected to take the average of a and b
+        assert_eq!(estimate.remaining(), Some(Duration::from_secs(75)));
+        estimate.finish(Path::new("/f/a"), Duration::from_secs(90));
+        // b 20s and c the average of b and a's actual 90s, over two jobs
+        assert_eq!(estimate.remaining().unwrap().as_secs(), 37);
+        estimate.finish(Path::new("/f/b"), Duration::from_secs(20));
+        estimate.finish(Path::new("/f/c"), Duration::from_secs(20));
+        assert_eq!(estimate.remaining(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_no_estimate_without_history() {
+        let fresh = Estimate::new(&Durations::default(), [PathBuf::from("/f/a")], 4);
+        assert_eq!(fresh.remaining(), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(Duration::from_secs(42)), "42s");
+        assert_eq!(format(Duration::from_secs(200)), "3m 20s");
+        assert_eq!(format(Duration::from_secs(3900)), "1h 05m");
+    }
+}
@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: MIT
+//! Git's exclude files during discovery
+//!
+//! Besides `ignore`, the walk for manifests leaves out what git itself
+//! would: patterns from the global excludes file (`core.excludesFile`, by
+//! default `~/.config/git/ignore`) and from `.git/info/exclude` of each repo
+//! it passes through, matched relative to that repo. `--no-global-ignore`
+//! turns this off.
+//!
+//! Patterns follow gitignore: `#` comments, `!` to re-include, a trailing
+//! `/` for directories only, and a `/` elsewhere anchoring the pattern to
+//! the repo root; anything else matches a name at any depth. Later patterns
+//! win, and `info/exclude` comes after the global file.
+
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::audit::CommandExt;
+
+/// One line of an exclude file
+#[derive(Debug, Clone)]
+struct Rule {
+    glob: GlobMatcher,
+    negated: bool,
+    dir_only: bool,
+    /// Matched against the path below the repo rather than the name
+    anchored: bool,
+}
+
+/// The rules of one exclude file
+#[derive(Debug, Clone, Default)]
+pub struct Patterns {
+    rules: Vec<Rule>,
+}
+
+impl Patterns {
+    pub fn parse(text: &str) -> Patterns {
+        let rules = text.lines().filter_map(parse_rule).collect();
+        Patterns { rules }
+    }
+
+    fn read(path: &Path) -> Patterns {
+        std::fs::read_to_string(path)
+            .map(|text| Patterns::parse(&text))
+            .unwrap_or_default()
+    }
+
+    /// `Some(true)` if the last rule matching `rel` excludes it,
+    /// `Some(false)` if it re-includes it, `None` if no rule matches
+    pub fn matched(&self, rel: &Path, is_dir: bool) -> Option<bool> {
+        let name = Path::new(rel.file_name()?);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && rule.glob.is_match(if rule.anchored { rel } else { name })
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    let glob = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .ok()?
+        .compile_matcher();
+    Some(Rule {
+        glob,
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+/// The global excludes file, read once
+fn global() -> &'static Patterns {
+    static GLOBAL: OnceLock<Patterns> = OnceLock::new();
+    GLOBAL.get_or_init(|| {
+        global_path()
+            .map(|path| Patterns::read(&path))
+            .unwrap_or_default()
+    })
+}
+
+/// `core.excludesFile`, else git's default location
+fn global_path() -> Option<PathBuf> {
+    let configured = Command::new("git")
+        .args(["config", "--type=path", "--get", "core.excludesFile"])
+        .logged_output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|path| !path.is_empty());
+    if let Some(path) = configured {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("git").join("ignore"))
+}
+
+/// Exclusion during one depth-first walk, tracking the repos it is in
+pub struct Walk {
+    global: &'static Patterns,
+    /// Repos containing the current entry, innermost last
+    repos: Vec<(PathBuf, Patterns)>,
+}
+
+impl Walk {
+    /// A walk starting at `root`
+    pub fn new(root: &Path) -> Walk {
+        let mut walk = Walk {
+            global: global(),
+            repos: Vec::new(),
+        };
+        // `root` may be inside a repo; its patterns apply from there
+        if let Some(repo) = root.ancestors().find(|dir| dir.join(".git").is_dir()) {
+            walk.enter(repo);
+        }
+        walk
+    }
+
+    fn enter(&mut self, repo: &Path) {
+        let patterns = Patterns::read(&repo.join(".git").join("info").join("exclude"));
+        self.repos.push((repo.to_path_buf(), patterns));
+    }
+
+    /// Whether git would exclude `path`; call for entries in walk order
+    pub fn excluded(&mut self, path: &Path, is_dir: bool) -> bool {
+        while self
+            .repos
+            .last()
+            .is_some_and(|(repo, _)| !path.starts_with(repo))
+        {
+            self.repos.pop();
+        }
+        let excluded = match self.repos.last() {
+            Some((repo, local)) => {
+                let rel = path.strip_prefix(repo).unwrap_or(path);
+                local
+                    .matched(rel, is_dir)
+                    .or_else(|| self.global.matched(rel, is_dir))
+                    .unwrap_or(false)
+            }
+            None => self.global.matched(path, is_dir).unwrap_or(false),
+        };
+        let entered = self.repos.last().is_some_and(|(repo, _)| repo == path);
+        if is_dir && !excluded && !entered && path.join(".git").is_dir() {
+            self.enter(path);
+        }
+        excluded
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns_skip_comments_and_blank_lines() {
+        let patterns = Patterns::parse("# *.tmp\n\n   \n\\#notes\n");
+        assert_eq!(patterns.matched(Path::new("a.tmp"), false), None);
+        assert_eq!(patterns.matched(Path::new("#notes"), false), Some(true));
+    }
+
+    #[test]
+    fn test_last_matching_pattern_wins() {
+        let patterns = Patterns::parse("*.tmp\n!keep.tmp\n");
+        assert_eq!(patterns.matched(Path::new("a/b.tmp"), false), Some(true));
+        assert_eq!(
+            patterns.matched(Path::new("a/keep.tmp"), false),
+            Some(false)
+        );
+        assert_eq!(patterns.matched(Path::new("src"), true), None);
+    }
+
+    #[test]
+    fn test_trailing_slash_matches_dirs_only() {
+        let patterns = Patterns::parse("data/\n");
+        assert_eq!(patterns.matched(Path::new("x/data"), true), Some(true));
+        assert_eq!(patterns.matched(Path::new("x/data"), false), None);
+    }
+
+    #[test]
+    fn test_slash_anchors_to_repo_root() {
+        let patterns = Patterns::parse("/fixtures/large\n");
+        assert_eq!(
+            patterns.matched(Path::new("fixtures/large"), true),
+            Some(true)
+        );
+        assert_eq!(
+            patterns.matched(Path::new("app/fixtures/large"), true),
+            None
+        );
+    }
+
+    #[test]
+    fn test_walk_applies_info_exclude_of_each_repo() {
+        let dir = std::env::temp_dir().join("moon_dst_excludes_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("outer/.git/info")).unwrap();
+        std::fs::write(dir.join("outer/.git/info/exclude"), "scratch/\n").unwrap();
+        std::fs::create_dir_all(dir.join("outer/inner/.git/info")).unwrap();
+        std::fs::write(dir.join("outer/inner/.git/info/exclude"), "/build\n").unwrap();
+
+        let mut walk = Walk::new(&dir);
+        assert!(!walk.excluded(&dir.join("outer"), true));
+        assert!(walk.excluded(&dir.join("outer/scratch"), true));
+        assert!(!walk.excluded(&dir.join("outer/build"), true));
+        assert!(!walk.excluded(&dir.join("outer/inner"), true));
+        assert!(walk.excluded(&dir.join("outer/inner/build"), true));
+        // Left the inner repo: its patterns no longer apply
+        assert!(!walk.excluded(&dir.join("outer/other/build"), true));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: MIT
+//! Atom feed of update activity, rendered from the run journal
+//!
+//! Each run with version changes or failures becomes one entry, newest
+//! first. The feed is regenerated from the journal on every write, so it
+//! can be published as a static file.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::history::Run;
+use crate::junit::escape;
+use crate::timestamp;
+
+/// Entries kept in the feed
+const MAX_ENTRIES: usize = 50;
+
+/// Write the feed for `runs` (oldest first) to `path`
+pub fn write(path: &Path, runs: &[Run]) -> Result<()> {
+    std::fs::write(path, render(runs))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn render(runs: &[Run]) -> String {
+    let entries: Vec<&Run> = runs
+        .iter()
+        .rev()
+        .filter(|run| run.change_count() > 0 || run.failure_count() > 0)
+        .take(MAX_ENTRIES)
+        .collect();
+    let updated = runs.iter().map(|run| run.started).max().unwrap_or(0);
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    out.push_str("  <id>urn:moon-dst:updates</id>\n");
+    out.push_str("  <title>moon-dst dependency updates</title>\n");
+    let _ = writeln!(out, "  <updated>{}</updated>", timestamp::rfc3339(updated));
+    out.push_str("  <author><name>moon-dst</name></author>\n");
+    for run in entries {
+        let _ = writeln!(out, "  <entry>");
+        let _ = writeln!(out, "    <id>urn:moon-dst:run:{}</id>", run.started);
+        let _ = writeln!(out, "    <title>{}</title>", escape(&title(run)));
+        let _ = writeln!(
+            out,
+            "    <updated>{}</updated>",
+            timestamp::rfc3339(run.started)
+        );
+        let _ = writeln!(
+            out,
+            "    <content type=\"text\">{}</content>",
+            escape(&summary(run))
+        );
+        let _ = writeln!(out, "  </entry>");
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+fn title(run: &Run) -> String {
+    let repos = run.repos.iter().filter(|r| !r.changes.is_empty()).count();
+    let mut title = format!("{} updates in {repos} repos", run.change_count());
+    let failures = run.failure_count();
+    if failures > 0 {
+        let _ = write!(title, ", {failures} failed");
+    }
+    title
+}
+
+/// One line per change or failure, grouped by repo
+fn summary(run: &Run) -> String {
+    let mut out = String::new();
+    for repo in &run.repos {
+        let _ = writeln!(out, "{}", repo.repo.display());
+        for change in &repo.changes {
+            let _ = writeln!(
+                out,
+                "  {}: {} -> {}",
+                change.name,
+                change.old_version.as_deref().unwrap_or("?"),
+                change.new_version.as_deref().unwrap_or("?")
+            );
+        }
+        for name in &repo.failed {
+            let _ = writeln!(out, "  {name}: FAILED");
+        }
+    }
+    out
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{Change, RepoRun};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_render_skips_quiet_runs_newest_first() {
+        let run = |started, changes: Vec<Change>| Run {
+            id: None,
+            started,
+            root: PathBuf::from("/fleet"),
+            repos: vec![RepoRun {
+                repo: PathBuf::from("/fleet/a"),
+                success: true,
+                changes,
+                failed: Vec::new(),
+            }],
+        };
+        let change = Change {
+            name: "moonbitlang/x".to_string(),
+            old_version: Some("0.1.0".to_string()),
+            new_version: Some("0.2.0".to_string()),
+        };
+        let feed = render(&[
+            run(0, vec![change.clone()]),
+            run(60, Vec::new()),
+            run(120, vec![change]),
+        ]);
+
+        assert!(feed.contains("<updated>1970-01-01T00:02:00Z</updated>\n  <author>"));
+        assert_eq!(feed.matches("<entry>").count(), 2);
+        assert!(feed.find("run:120").unwrap() < feed.find("run:0").unwrap());
+        assert!(feed.contains("moonbitlang/x: 0.1.0 -&gt; 0.2.0"));
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: MIT
+//! HTTP downloads through the system `curl`
+//!
+//! Network access is rare enough (release checks, installers, a remote
+//! registry index) that shelling out keeps the binary free of an HTTP and
+//! TLS stack. `file://` URLs work too, which is handy for mirrors and tests.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::audit::CommandExt;
+use crate::capabilities::{self, Capability};
+use crate::errors::{Code, Coded};
+
+/// Fetch a URL and return the response body as text
+pub fn fetch_text(url: &str) -> Result<String> {
+    let output = curl(url)?
+        .arg(url)
+        .logged_output()
+        .context("Failed to run curl (is it installed?)")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            failure_code(&output),
+            format!(
+                "Failed to fetch {url}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Download a URL into a file
+pub fn download(url: &str, dest: &Path) -> Result<()> {
+    let output = curl(url)?
+        .arg("-o")
+        .arg(dest)
+        .arg(url)
+        .logged_output()
+        .context("Failed to run curl (is it installed?)")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            failure_code(&output),
+            format!(
+                "Failed to download {url}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// A download that only replaces `dest` when the server has something newer
+pub struct Conditional {
+    pub url: String,
+    pub dest: PathBuf,
+}
+
+/// Outcome of a [`Conditional`] download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refreshed {
+    /// `dest` was written
+    Downloaded,
+    /// `dest` is current and was left as is
+    NotModified,
+    Failed(String),
+}
+
+/// Transfers [`refresh_all`] runs at once unless told otherwise
+pub const MAX_PARALLEL: usize = 16;
+
+/// Run all `requests` in one parallel curl invocation, at most `parallel`
+/// transfers at once
+///
+/// Each request sends the ETag saved next to `dest` (`<dest>.etag`) and the
+/// modification time of `dest` (If-None-Match and If-Modified-Since), so
+/// unchanged files cost a 304 and no body. Bodies go to `<dest>.part`
+/// first and replace `dest` only when complete. Returns one outcome per
+/// request, in order.
+pub fn refresh_all(requests: &[Conditional], parallel: usize) -> Result<Vec<Refreshed>> {
+    if requests.is_empty() {
+        return Ok(Vec::new());
+    }
+    capabilities::require(Capability::Network, "Refreshing the registry index")?;
+    let mut command = Command::new("curl");
+    command
+        .arg("--parallel")
+        .arg("--parallel-max")
+        .arg(parallel.max(1).to_string());
+    for (i, request) in requests.iter().enumerate() {
+        if i > 0 {
+            command.arg("--next");
+        }
+        if let Some(parent) = request.dest.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let etag = sibling(&request.dest, "etag");
+        command.args(["--no-progress-meter", "-L", "--retry", "2"]);
+        command.args(["-w", "%{urlnum} %{exitcode} %{response_code}\n"]);
+        if etag.exists() {
+            command.arg("--etag-compare").arg(&etag);
+        }
+        if request.dest.exists() {
+            command.arg("-z").arg(&request.dest);
+        }
+        command
+            .arg("--etag-save")
+            .arg(&etag)
+            .arg("-o")
+            .arg(sibling(&request.dest, "part"))
+            .arg(&request.url);
+    }
+    // The exit status only reflects the last failed transfer; each one is
+    // judged by its own line
+    let output = command
+        .logged_output()
+        .context("Failed to run curl (is it installed?)")?;
+
+    let mut outcomes = vec![Refreshed::Failed("no response".to_string()); requests.len()];
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut fields = line.split_whitespace().map(|f| f.parse::<usize>().ok());
+        let (Some(Some(index)), Some(Some(exit)), Some(Some(status))) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Some(request) = requests.get(index) else {
+            continue;
+        };
+        outcomes[index] = finish(request, exit, status);
+    }
+    Ok(outcomes)
+}
+
+/// Judge one transfer by curl's exit code and the response status (0 for
+/// `file://`), moving a complete body into place
+fn finish(request: &Conditional, exit: usize, status: usize) -> Refreshed {
+    let part = sibling(&request.dest, "part");
+    let outcome = match (exit, status) {
+        (0, 304) => Refreshed::NotModified,
+        // Nothing was written when the time condition was not met
+        (0, 0 | 200) if !part.exists() => Refreshed::NotModified,
+        (0, 0 | 200) => match std::fs::rename(&part, &request.dest) {
+            Ok(()) => Refreshed::Downloaded,
+            Err(e) => Refreshed::Failed(format!("{}: {e}", request.dest.display())),
+        },
+        (0, status) => Refreshed::Failed(format!("HTTP {status}")),
+        (28, _) => Refreshed::Failed("timed out".to_string()),
+        (exit, _) => Refreshed::Failed(format!("curl exit code {exit}")),
+    };
+    std::fs::remove_file(&part).ok();
+    outcome
+}
+
+/// `<path>.<extension>` next to `path`
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Timeouts (curl exit code 28) apart from other failures
+fn failure_code(output: &Output) -> Code {
+    match output.status.code() {
+        Some(28) => Code::RegistryTimeout,
+        _ => Code::NetworkFailed,
+    }
+}
+
+fn curl(url: &str) -> Result<Command> {
+    capabilities::require(Capability::Network, &format!("Fetching {url}"))?;
+    let mut command = Command::new("curl");
+    command.args(["-fsSL", "--retry", "2"]);
+    Ok(command)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors;
+
+    fn remote_file(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("moon_dst_fetch_{name}_test"));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("remote")).unwrap();
+        for (file, content) in files {
+            std::fs::write(dir.join("remote").join(file), content).unwrap();
+        }
+        dir
+    }
+
+    fn url(dir: &Path, name: &str) -> String {
+        format!("file://{}", dir.join("remote").join(name).display())
+    }
+
+    #[test]
+    fn test_fetch_text_and_download() {
+        let dir = remote_file("text", &[("a", "a1")]);
+        assert_eq!(fetch_text(&url(&dir, "a")).unwrap(), "a1");
+        let dest = dir.join("a.copy");
+        download(&url(&dir, "a"), &dest).unwrap();
+        assert_eq!(std::fs::read_to_string(dest).unwrap(), "a1");
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_fetch_failure_is_network_error() {
+        let dir = remote_file("failure", &[]);
+        let error = fetch_text(&url(&dir, "missing")).unwrap_err();
+        assert_eq!(errors::classify(&error), Code::NetworkFailed);
+        assert!(error.to_string().starts_with("Failed to fetch file://"));
+        let error = download(&url(&dir, "missing"), &dir.join("x")).unwrap_err();
+        assert_eq!(errors::classify(&error), Code::NetworkFailed);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_refresh_all_skips_current_files() {
+        let dir = remote_file("refresh", &[("a", "a1")]);
+        let request = |name: &str| Conditional {
+            url: url(&dir, name),
+            dest: dir.join("cache").join(name),
+        };
+        let requests = [request("a"), request("missing")];
+
+        let outcomes = refresh_all(&requests, MAX_PARALLEL).unwrap();
+        assert_eq!(outcomes[0], Refreshed::Downloaded);
+        assert!(matches!(outcomes[1], Refreshed::Failed(_)));
+        assert_eq!(std::fs::read_to_string(dir.join("cache/a")).unwrap(), "a1");
+        assert!(!dir.join("cache/missing").exists());
+
+        // The cached copy is newer than the remote file now
+        let outcomes = refresh_all(&requests[..1], 1).unwrap();
+        assert_eq!(outcomes, [Refreshed::NotModified]);
+        assert!(refresh_all(&[], MAX_PARALLEL).unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_finish_judges_status_and_exit_code() {
+        let request = Conditional {
+            url: String::new(),
+            dest: std::env::temp_dir().join("moon_dst_fetch_finish_test"),
+        };
+        assert_eq!(finish(&request, 0, 304), Refreshed::NotModified);
+        assert_eq!(finish(&request, 0, 200), Refreshed::NotModified);
+        assert_eq!(
+            finish(&request, 0, 404),
+            Refreshed::Failed("HTTP 404".to_string())
+        );
+        assert_eq!(
+            finish(&request, 28, 0),
+            Refreshed::Failed("timed out".to_string())
+        );
+        assert_eq!(
+            finish(&request, 6, 0),
+            Refreshed::Failed("curl exit code 6".to_string())
+        );
+        assert_eq!(
+            sibling(Path::new("cache/a.index"), "etag"),
+            Path::new("cache/a.index.etag")
+        );
+    }
+}
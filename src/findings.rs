@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MIT
+//! Findings reported against manifest files, and their SARIF rendering
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// Finding severity, named after SARIF result levels
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Note,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Note => "note",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// A rule that can produce findings
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub level: Level,
+}
+
+/// A single finding at a file location
+#[derive(Serialize, Debug, Clone)]
+pub struct Finding {
+    pub rule_id: &'static str,
+    pub level: Level,
+    pub message: String,
+    #[serde(serialize_with = "crate::ospath::serialize")]
+    pub path: PathBuf,
+    pub line: Option<usize>,
+}
+
+impl Finding {
+    /// `path:line: level[rule]: message`, with the path relative to `root`
+    pub fn to_text(&self, root: &Path) -> String {
+        let path = relative_uri(&self.path, root);
+        let location = match self.line {
+            Some(line) => format!("{path}:{line}"),
+            None => path,
+        };
+        format!(
+            "{location}: {}[{}]: {}",
+            self.level.as_str(),
+            self.rule_id,
+            self.message
+        )
+    }
+}
+
+/// 1-based line of the first line containing `needle`
+pub fn find_line(content: &str, needle: &str) -> Option<usize> {
+    content
+        .lines()
+        .position(|line| line.contains(needle))
+        .map(|i| i + 1)
+}
+
+/// Path relative to `root` with forward slashes, as used in SARIF URIs
+pub fn relative_uri(path: &Path, root: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Render findings as a SARIF 2.1.0 log with locations relative to `root`
+pub fn to_sarif(rules: &[Rule], findings: &[Finding], root: &Path) -> serde_json::Value {
+    let rule_entries: Vec<serde_json::Value> = rules
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id,
+                "name": rule.name,
+                "shortDescription": { "text": rule.description },
+                "defaultConfiguration": { "level": rule.level.as_str() },
+            })
+        })
+        .collect();
+
+    let results: Vec<serde_json::Value> = findings
+        .iter()
+        .map(|finding| {
+            let mut location = json!({
+                "artifactLocation": {
+                    "uri": relative_uri(&finding.path, root),
+                    "uriBaseId": "%SRCROOT%",
+                },
+            });
+            if let Some(line) = finding.line {
+                location["region"] = json!({ "startLine": line });
+            }
+            let mut result = json!({
+                "ruleId": finding.rule_id,
+                "level": finding.level.as_str(),
+                "message": { "text": finding.message },
+                "locations": [{ "physicalLocation": location }],
+            });
+            if let Some(index) = rules.iter().position(|r| r.id == finding.rule_id) {
+                result["ruleIndex"] = json!(index);
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rule_entries,
+                },
+            },
+            "originalUriBaseIds": {
+                "%SRCROOT%": { "uri": format!("file://{}/", root.display()) },
+            },
+            "results": results,
+        }],
+    })
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &[Rule] = &[Rule {
+        id: "L001",
+        name: "test-rule",
+        description: "Test rule",
+        level: Level::Warning,
+    }];
+
+    #[test]
+    fn test_to_sarif_locations() {
+        let findings = vec![Finding {
+            rule_id: "L001",
+            level: Level::Warning,
+            message: "something".to_string(),
+            path: PathBuf::from("/fleet/repo/moon.mod.json"),
+            line: Some(3),
+        }];
+        let sarif = to_sarif(RULES, &findings, Path::new("/fleet"));
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "L001");
+        assert_eq!(result["ruleIndex"], 0);
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "repo/moon.mod.json");
+        assert_eq!(location["region"]["startLine"], 3);
+    }
+}
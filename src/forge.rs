@@ -0,0 +1,841 @@
+// SPDX-License-Identifier: MIT
+//! Opening pull requests and issues on the forge that hosts a repo
+//!
+//! After `apply --open-pr`, each updated repo gets its manifest changes
+//! committed on a branch, pushed to `origin`, and a pull request (a merge
+//! request on GitLab) opened through the forge's REST API. The provider is
+//! detected from the `origin` URL or set with `[pr] provider`:
+//!
+//! | provider | API | token |
+//! |----------|-----|-------|
+//! | `github` | `https://api.github.com` (`/api/v3` on other hosts) | `GITHUB_TOKEN` |
+//! | `gitlab` | `https://<host>/api/v4` | `GITLAB_TOKEN` |
+//! | `gitea`  | `https://<host>/api/v1` (also Forgejo) | `GITEA_TOKEN` |
+//!
+//! The token is passed to curl on stdin, never on the command line. Issues
+//! for repos that keep failing (`[issues] after`) use the same providers.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::audit::CommandExt;
+use crate::capabilities::{self, Capability};
+use crate::errors::{Code, Coded};
+use crate::submodule;
+
+/// Which forge API to talk to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    Github,
+    Gitlab,
+    /// Gitea and Forgejo, which share an API
+    Gitea,
+}
+
+impl ProviderKind {
+    /// Guess the provider from a remote host name
+    pub fn detect(host: &str) -> Option<Self> {
+        let host = host.to_ascii_lowercase();
+        if host.contains("github") {
+            Some(Self::Github)
+        } else if host.contains("gitlab") {
+            Some(Self::Gitlab)
+        } else if ["gitea", "forgejo", "codeberg"]
+            .iter()
+            .any(|name| host.contains(name))
+        {
+            Some(Self::Gitea)
+        } else {
+            None
+        }
+    }
+
+    /// Environment variable holding the API token by default
+    pub fn token_env(self) -> &'static str {
+        match self {
+            Self::Github => "GITHUB_TOKEN",
+            Self::Gitlab => "GITLAB_TOKEN",
+            Self::Gitea => "GITEA_TOKEN",
+        }
+    }
+
+    /// API base URL for a host
+    fn default_api(self, host: &str) -> String {
+        match self {
+            Self::Github if host == "github.com" => "https://api.github.com".to_string(),
+            Self::Github => format!("https://{host}/api/v3"),
+            Self::Gitlab => format!("https://{host}/api/v4"),
+            Self::Gitea => format!("https://{host}/api/v1"),
+        }
+    }
+}
+
+/// A repo on a forge, parsed from its remote URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRepo {
+    pub host: String,
+    /// `owner/name`, or `group/subgroup/name` on GitLab
+    pub path: String,
+}
+
+impl RemoteRepo {
+    /// Parse `https://host/owner/name(.git)`, `ssh://git@host/owner/name`
+    /// or `git@host:owner/name`
+    pub fn parse(url: &str) -> Option<Self> {
+        let (host, path) = match url.split_once("://") {
+            Some((_, rest)) => rest.split_once('/')?,
+            None => url.split_once(':')?,
+        };
+        let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+        // Drop a port, which belongs to the git transport, not the API
+        let host = host.split(':').next().unwrap_or(host);
+        let path = path.trim_matches('/').trim_end_matches(".git");
+        (!host.is_empty() && path.contains('/')).then(|| Self {
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+/// A pull request to open
+#[derive(Debug, Clone)]
+pub struct PullRequest {
+    pub title: String,
+    pub body: String,
+    /// Branch with the changes
+    pub head: String,
+    /// Branch to merge into
+    pub base: String,
+}
+
+// =============================================================================
+// Providers
+// =============================================================================
+
+/// An issue to open
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub title: String,
+    pub body: String,
+    pub labels: Vec<String>,
+}
+
+/// A forge API that can open pull requests and issues
+pub trait Provider {
+    /// Open a pull request and return its web URL
+    fn open_pull_request(&self, repo: &RemoteRepo, pr: &PullRequest) -> Result<String>;
+
+    /// Web URL of an open issue with exactly this title, if there is one
+    fn find_open_issue(&self, repo: &RemoteRepo, title: &str) -> Result<Option<String>>;
+
+    /// Open an issue and return its web URL
+    fn open_issue(&self, repo: &RemoteRepo, issue: &Issue) -> Result<String>;
+}
+
+/// First issue in a JSON array whose title matches, as its `url_key` value
+fn matching_issue(issues: &serde_json::Value, title: &str, url_key: &str) -> Option<String> {
+    issues
+        .as_array()?
+        .iter()
+        // GitHub lists pull requests as issues too
+        .filter(|issue| issue.get("pull_request").is_none())
+        .find(|issue| issue["title"].as_str() == Some(title))
+        .and_then(|issue| issue[url_key].as_str())
+        .map(str::to_string)
+}
+
+struct Github {
+    api: String,
+    token: String,
+}
+
+impl Github {
+    fn auth(&self) -> String {
+        format!("Authorization: Bearer {}", self.token)
+    }
+}
+
+impl Provider for Github {
+    fn open_pull_request(&self, repo: &RemoteRepo, pr: &PullRequest) -> Result<String> {
+        let response = request_json(
+            &format!("{}/repos/{}/pulls", self.api, repo.path),
+            &self.auth(),
+            Request::Post(
+                json!({ "title": pr.title, "body": pr.body, "head": pr.head, "base": pr.base }),
+            ),
+        )?;
+        web_url(&response, "html_url")
+    }
+
+    fn find_open_issue(&self, repo: &RemoteRepo, title: &str) -> Result<Option<String>> {
+        let issues = request_json(
+            &format!("{}/repos/{}/issues", self.api, repo.path),
+            &self.auth(),
+            Request::Get(&[("state", "open"), ("per_page", "100")]),
+        )?;
+        Ok(matching_issue(&issues, title, "html_url"))
+    }
+
+    fn open_issue(&self, repo: &RemoteRepo, issue: &Issue) -> Result<String> {
+        let response = request_json(
+            &format!("{}/repos/{}/issues", self.api, repo.path),
+            &self.auth(),
+            Request::Post(
+                json!({ "title": issue.title, "body": issue.body, "labels": issue.labels }),
+            ),
+        )?;
+        web_url(&response, "html_url")
+    }
+}
+
+struct Gitlab {
+    api: String,
+    token: String,
+}
+
+impl Gitlab {
+    fn auth(&self) -> String {
+        format!("PRIVATE-TOKEN: {}", self.token)
+    }
+
+    fn project(&self, repo: &RemoteRepo) -> String {
+        format!("{}/projects/{}", self.api, repo.path.replace('/', "%2F"))
+    }
+}
+
+impl Provider for Gitlab {
+    fn open_pull_request(&self, repo: &RemoteRepo, pr: &PullRequest) -> Result<String> {
+        let response = request_json(
+            &format!("{}/merge_requests", self.project(repo)),
+            &self.auth(),
+            Request::Post(json!({
+                "title": pr.title,
+                "description": pr.body,
+                "source_branch": pr.head,
+                "target_branch": pr.base,
+            })),
+        )?;
+        web_url(&response, "web_url")
+    }
+
+    fn find_open_issue(&self, repo: &RemoteRepo, title: &str) -> Result<Option<String>> {
+        let issues = request_json(
+            &format!("{}/issues", self.project(repo)),
+            &self.auth(),
+            Request::Get(&[
+                ("state", "opened"),
+                ("in", "title"),
+                ("search", title),
+                ("per_page", "100"),
+            ]),
+        )?;
+        Ok(matching_issue(&issues, title, "web_url"))
+    }
+
+    fn open_issue(&self, repo: &RemoteRepo, issue: &Issue) -> Result<String> {
+        let response = request_json(
+            &format!("{}/issues", self.project(repo)),
+            &self.auth(),
+            Request::Post(json!({
+                "title": issue.title,
+                "description": issue.body,
+                "labels": issue.labels.join(","),
+            })),
+        )?;
+        web_url(&response, "web_url")
+    }
+}
+
+struct Gitea {
+    api: String,
+    token: String,
+}
+
+impl Gitea {
+    fn auth(&self) -> String {
+        format!("Authorization: token {}", self.token)
+    }
+}
+
+impl Provider for Gitea {
+    fn open_pull_request(&self, repo: &RemoteRepo, pr: &PullRequest) -> Result<String> {
+        let response = request_json(
+            &format!("{}/repos/{}/pulls", self.api, repo.path),
+            &self.auth(),
+            Request::Post(
+                json!({ "title": pr.title, "body": pr.body, "head": pr.head, "base": pr.base }),
+            ),
+        )?;
+        web_url(&response, "html_url")
+    }
+
+    fn find_open_issue(&self, repo: &RemoteRepo, title: &str) -> Result<Option<String>> {
+        let issues = request_json(
+            &format!("{}/repos/{}/issues", self.api, repo.path),
+            &self.auth(),
+            Request::Get(&[("state", "open"), ("type", "issues"), ("q", title)]),
+        )?;
+        Ok(matching_issue(&issues, title, "html_url"))
+    }
+
+    /// Labels are left out: Gitea takes label IDs, not names
+    fn open_issue(&self, repo: &RemoteRepo, issue: &Issue) -> Result<String> {
+        let response = request_json(
+            &format!("{}/repos/{}/issues", self.api, repo.path),
+            &self.auth(),
+            Request::Post(json!({ "title": issue.title, "body": issue.body })),
+        )?;
+        web_url(&response, "html_url")
+    }
+}
+
+/// Provider for a repo; `api` overrides the API base URL and `token_env`
+/// the variable holding the token
+pub fn provider(
+    kind: ProviderKind,
+    repo: &RemoteRepo,
+    api: Option<&str>,
+    token_env: Option<&str>,
+) -> Result<Box<dyn Provider>> {
+    let var = token_env.unwrap_or(kind.token_env());
+    let token = std::env::var(var)
+        .with_context(|| format!("Environment variable {var} (API token) is not set"))?;
+    let api = api
+        .map(|api| api.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| kind.default_api(&repo.host));
+    Ok(match kind {
+        ProviderKind::Github => Box::new(Github { api, token }),
+        ProviderKind::Gitlab => Box::new(Gitlab { api, token }),
+        ProviderKind::Gitea => Box::new(Gitea { api, token }),
+    })
+}
+
+fn web_url(response: &serde_json::Value, key: &str) -> Result<String> {
+    response[key]
+        .as_str()
+        .map(str::to_string)
+        .with_context(|| format!("Response has no {key}: {response}"))
+}
+
+/// An API request: a GET with query parameters or a POST with a JSON body
+enum Request<'a> {
+    Get(&'a [(&'a str, &'a str)]),
+    Post(serde_json::Value),
+}
+
+/// Send a request and parse the JSON response
+///
+/// The header carrying the token goes through a curl config on stdin.
+fn request_json(url: &str, auth_header: &str, request: Request) -> Result<serde_json::Value> {
+    capabilities::require(Capability::Network, &format!("Requesting {url}"))?;
+    let mut command = Command::new("curl");
+    command
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail-with-body",
+            "--config",
+            "-",
+        ])
+        .args(["--header", "Accept: application/json"]);
+    let method = match &request {
+        Request::Get(query) => {
+            command.arg("--get");
+            for (key, value) in *query {
+                command
+                    .arg("--data-urlencode")
+                    .arg(format!("{key}={value}"));
+            }
+            "GET"
+        }
+        Request::Post(body) => {
+            command
+                .args(["--header", "Content-Type: application/json"])
+                .arg("--data-binary")
+                .arg(body.to_string());
+            "POST"
+        }
+    };
+    let curl_config = format!(
+        "header = \"{}\"\n",
+        auth_header.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+    let output = command
+        .arg(url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_output_with_input(curl_config.as_bytes())
+        .context("Failed to run curl (is it installed?)")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::ForgeFailed,
+            format!(
+                "{method} {url} failed: {} {}",
+                String::from_utf8_lossy(&output.stderr).trim(),
+                String::from_utf8_lossy(&output.stdout).trim()
+            )
+        ));
+    }
+    serde_json::from_slice(&output.stdout).with_context(|| format!("Invalid JSON from {url}"))
+}
+
+// =============================================================================
+// Branch and Commit
+// =============================================================================
+
+pub fn git(repo: &Path, args: &[&str]) -> Result<String> {
+    capabilities::require_git(args)?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .logged_output()
+        .context("Failed to run git (is it installed?)")?;
+    if !output.status.success() {
+        // Leave commit messages out of the error
+        let command: Vec<&str> = args.iter().copied().take_while(|a| *a != "-m").collect();
+        bail!(Coded::new(
+            Code::ForgeFailed,
+            format!(
+                "git {} failed in {}: {}",
+                command.join(" "),
+                repo.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// URL of the `origin` remote
+pub fn origin_url(repo: &Path) -> Result<String> {
+    git(repo, &["remote", "get-url", "origin"])
+}
+
+/// Branch checked out in `repo`
+pub fn current_branch(repo: &Path) -> Result<String> {
+    git(repo, &["symbolic-ref", "--short", "HEAD"])
+}
+
+/// `gpg.format` values for signing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningFormat {
+    Openpgp,
+    Ssh,
+    X509,
+}
+
+/// Identity and signing for commits; unset values come from git config
+#[derive(Debug, Clone, Default)]
+pub struct CommitSettings {
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    pub committer_name: Option<String>,
+    pub committer_email: Option<String>,
+    pub sign: bool,
+    /// `user.signingKey`: a GPG key ID or an SSH key path
+    pub signing_key: Option<String>,
+    pub signing_format: Option<SigningFormat>,
+}
+
+impl CommitSettings {
+    /// `git commit` invocation, with `-c` overrides before the subcommand
+    fn commit_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut config = |key: &str, value: &str| {
+            args.extend(["-c".to_string(), format!("{key}={value}")]);
+        };
+        if let Some(name) = &self.committer_name {
+            config("user.name", name);
+        }
+        if let Some(email) = &self.committer_email {
+            config("user.email", email);
+        }
+        if self.sign {
+            if let Some(key) = &self.signing_key {
+                config("user.signingKey", key);
+            }
+            if let Some(format) = self.signing_format {
+                let format = match format {
+                    SigningFormat::Openpgp => "openpgp",
+                    SigningFormat::Ssh => "ssh",
+                    SigningFormat::X509 => "x509",
+                };
+                config("gpg.format", format);
+            }
+        }
+        args.extend(["commit".to_string(), "--quiet".to_string()]);
+        if self.sign {
+            args.push("--gpg-sign".to_string());
+        }
+        if let (Some(name), Some(email)) = (&self.author_name, &self.author_email) {
+            args.push(format!("--author={name} <{email}>"));
+        }
+        args
+    }
+}
+
+/// Commit `files` and the submodule `pointers` on `branch` (reset to the
+/// current commit), push it to `origin`, and switch back to the branch that
+/// was checked out, returning the new commit
+///
+/// The committed changes leave the working tree of the original branch.
+/// Pointers are recorded in the index only; submodule checkouts stay as
+/// they are.
+pub fn commit_and_push(
+    repo: &Path,
+    branch: &str,
+    files: &[PathBuf],
+    pointers: &[submodule::Pointer],
+    title: &str,
+    body: &str,
+    settings: &CommitSettings,
+) -> Result<String> {
+    let original = current_branch(repo)?;
+    git(repo, &["switch", "--quiet", "-C", branch])?;
+    let result = (|| {
+        let mut add = vec!["add", "--"];
+        let files: Vec<String> = files
+            .iter()
+            .map(|f| f.to_string_lossy().into_owned())
+            .collect();
+        add.extend(files.iter().map(String::as_str));
+        if !files.is_empty() {
+            git(repo, &add)?;
+        }
+        for pointer in pointers {
+            let entry = format!(
+                "160000,{},{}",
+                pointer.commit,
+                pointer.path.to_string_lossy()
+            );
+            git(repo, &["update-index", "--cacheinfo", &entry])?;
+        }
+        let mut commit = settings.commit_args();
+        commit.extend(["-m", title, "-m", body].map(str::to_string));
+        let commit: Vec<&str> = commit.iter().map(String::as_str).collect();
+        git(repo, &commit).map_err(|e| {
+            if settings.sign {
+                e.context(
+                    "Failed to sign the commit; check signing-key and signing-format in [commit], \
+                     or user.signingKey and gpg.format in git config",
+                )
+            } else {
+                e
+            }
+        })?;
+        git(repo, &["push", "--quiet", "--force", "origin", branch])?;
+        git(repo, &["rev-parse", "HEAD"])
+    })();
+    git(repo, &["switch", "--quiet", &original])?;
+    result
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors;
+
+    /// A directory standing in for a forge API, read over `file://`
+    fn fake_api(name: &str, files: &[(&str, &str)]) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("moon_dst_forge_{name}_test"));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, body) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, body).unwrap();
+        }
+        let api = format!("file://{}", dir.display());
+        (dir, api)
+    }
+
+    fn remote(url: &str) -> RemoteRepo {
+        RemoteRepo::parse(url).unwrap()
+    }
+
+    fn pull_request() -> PullRequest {
+        PullRequest {
+            title: "Update deps".to_string(),
+            body: "body".to_string(),
+            head: "moon-dst/update".to_string(),
+            base: "main".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_remote_urls() {
+        assert_eq!(
+            remote("git@github.com:acme/app.git"),
+            RemoteRepo {
+                host: "github.com".to_string(),
+                path: "acme/app".to_string()
+            }
+        );
+        assert_eq!(
+            remote("ssh://git@gitlab.example.com:2222/group/sub/lib.git").path,
+            "group/sub/lib"
+        );
+        assert_eq!(remote("https://codeberg.org/acme/app").host, "codeberg.org");
+        assert!(RemoteRepo::parse("/srv/git/app.git").is_none());
+        assert!(RemoteRepo::parse("https://example.com/app").is_none());
+    }
+
+    #[test]
+    fn test_detect_provider_and_api() {
+        assert_eq!(
+            ProviderKind::detect("github.com"),
+            Some(ProviderKind::Github)
+        );
+        assert_eq!(
+            ProviderKind::detect("gitlab.example.com"),
+            Some(ProviderKind::Gitlab)
+        );
+        assert_eq!(
+            ProviderKind::detect("codeberg.org"),
+            Some(ProviderKind::Gitea)
+        );
+        assert_eq!(
+            ProviderKind::detect("forgejo.example.com"),
+            Some(ProviderKind::Gitea)
+        );
+        assert_eq!(ProviderKind::detect("git.example.com"), None);
+
+        assert_eq!(
+            ProviderKind::Github.default_api("github.com"),
+            "https://api.github.com"
+        );
+        assert_eq!(
+            ProviderKind::Github.default_api("ghe.example.com"),
+            "https://ghe.example.com/api/v3"
+        );
+        assert_eq!(
+            ProviderKind::Gitlab.default_api("gitlab.example.com"),
+            "https://gitlab.example.com/api/v4"
+        );
+        assert_eq!(
+            ProviderKind::Gitea.default_api("codeberg.org"),
+            "https://codeberg.org/api/v1"
+        );
+    }
+
+    #[test]
+    fn test_matching_issue_skips_pull_requests() {
+        let issues = json!([
+            { "title": "moon-dst: x", "html_url": "u1", "pull_request": {} },
+            { "title": "moon-dst: x", "html_url": "u2" },
+        ]);
+        assert_eq!(
+            matching_issue(&issues, "moon-dst: x", "html_url").as_deref(),
+            Some("u2")
+        );
+        assert_eq!(matching_issue(&issues, "other", "html_url"), None);
+        assert_eq!(matching_issue(&json!({}), "moon-dst: x", "html_url"), None);
+    }
+
+    #[test]
+    fn test_github_pull_requests_and_issues() {
+        let (dir, api) = fake_api(
+            "github",
+            &[
+                (
+                    "repos/acme/app/pulls",
+                    r#"{"html_url":"https://github.com/acme/app/pull/1"}"#,
+                ),
+                (
+                    "repos/acme/app/issues",
+                    r#"[{"title":"moon-dst: failing","html_url":"https://github.com/acme/app/issues/2"}]"#,
+                ),
+            ],
+        );
+        let github = Github {
+            api,
+            token: "t".to_string(),
+        };
+        let repo = remote("git@github.com:acme/app.git");
+        assert_eq!(
+            github.open_pull_request(&repo, &pull_request()).unwrap(),
+            "https://github.com/acme/app/pull/1"
+        );
+        assert_eq!(
+            github
+                .find_open_issue(&repo, "moon-dst: failing")
+                .unwrap()
+                .as_deref(),
+            Some("https://github.com/acme/app/issues/2")
+        );
+        assert_eq!(github.find_open_issue(&repo, "other").unwrap(), None);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_gitlab_merge_requests_and_issues() {
+        let (dir, api) = fake_api(
+            "gitlab",
+            &[
+                (
+                    "projects/group/sub/lib/merge_requests",
+                    r#"{"web_url":"https://gitlab.example.com/group/sub/lib/-/merge_requests/3"}"#,
+                ),
+                (
+                    "projects/group/sub/lib/issues",
+                    r#"[{"title":"moon-dst: failing","web_url":"https://gitlab.example.com/i/4"}]"#,
+                ),
+            ],
+        );
+        let gitlab = Gitlab {
+            api,
+            token: "t".to_string(),
+        };
+        let repo = remote("git@gitlab.example.com:group/sub/lib.git");
+        assert!(gitlab
+            .project(&repo)
+            .ends_with("/projects/group%2Fsub%2Flib"));
+        assert_eq!(
+            gitlab.open_pull_request(&repo, &pull_request()).unwrap(),
+            "https://gitlab.example.com/group/sub/lib/-/merge_requests/3"
+        );
+        assert_eq!(
+            gitlab
+                .find_open_issue(&repo, "moon-dst: failing")
+                .unwrap()
+                .as_deref(),
+            Some("https://gitlab.example.com/i/4")
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_gitea_pull_requests_and_issues() {
+        let (dir, api) = fake_api(
+            "gitea",
+            &[
+                (
+                    "repos/acme/app/pulls",
+                    r#"{"html_url":"https://codeberg.org/acme/app/pulls/5"}"#,
+                ),
+                (
+                    "repos/acme/app/issues",
+                    r#"{"html_url":"https://codeberg.org/acme/app/issues/6"}"#,
+                ),
+            ],
+        );
+        let gitea = Gitea {
+            api,
+            token: "t".to_string(),
+        };
+        let repo = remote("https://codeberg.org/acme/app.git");
+        assert_eq!(
+            gitea.open_pull_request(&repo, &pull_request()).unwrap(),
+            "https://codeberg.org/acme/app/pulls/5"
+        );
+        let issue = Issue {
+            title: "moon-dst: failing".to_string(),
+            body: "body".to_string(),
+            labels: vec!["moon-dst".to_string()],
+        };
+        assert_eq!(
+            gitea.open_issue(&repo, &issue).unwrap(),
+            "https://codeberg.org/acme/app/issues/6"
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_provider_needs_token() {
+        let repo = remote("git@github.com:acme/app.git");
+        let error = provider(
+            ProviderKind::Github,
+            &repo,
+            None,
+            Some("MOON_DST_FORGE_TEST_UNSET_TOKEN"),
+        )
+        .err()
+        .unwrap();
+        assert!(error
+            .to_string()
+            .contains("MOON_DST_FORGE_TEST_UNSET_TOKEN (API token) is not set"));
+    }
+
+    #[test]
+    fn test_failed_request_is_forge_error() {
+        let (dir, api) = fake_api("missing", &[]);
+        let github = Github {
+            api,
+            token: "t".to_string(),
+        };
+        let error = github
+            .open_pull_request(&remote("git@github.com:acme/app.git"), &pull_request())
+            .unwrap_err();
+        assert_eq!(errors::classify(&error), Code::ForgeFailed);
+        assert!(error.to_string().starts_with("POST file://"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_response_without_web_url_fails() {
+        let (dir, api) = fake_api("no_url", &[("repos/acme/app/pulls", r#"{"id":1}"#)]);
+        let gitea = Gitea {
+            api,
+            token: "t".to_string(),
+        };
+        let error = gitea
+            .open_pull_request(&remote("https://codeberg.org/acme/app"), &pull_request())
+            .unwrap_err();
+        assert_eq!(error.to_string(), r#"Response has no html_url: {"id":1}"#);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_git_failure_leaves_out_commit_message() {
+        let dir = std::env::temp_dir().join("moon_dst_forge_git_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let error = git(&dir, &["commit", "--quiet", "-m", "secret title"]).unwrap_err();
+        assert_eq!(errors::classify(&error), Code::ForgeFailed);
+        let message = error.to_string();
+        assert!(
+            message.starts_with("git commit --quiet failed in "),
+            "{message}"
+        );
+        assert!(!message.contains("secret title"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_commit_args_with_identity_and_signing() {
+        let settings = CommitSettings {
+            author_name: Some("Bot".to_string()),
+            author_email: Some("bot@example.com".to_string()),
+            committer_email: Some("bot@example.com".to_string()),
+            sign: true,
+            signing_format: Some(SigningFormat::Ssh),
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.commit_args(),
+            [
+                "-c",
+                "user.email=bot@example.com",
+                "-c",
+                "gpg.format=ssh",
+                "commit",
+                "--quiet",
+                "--gpg-sign",
+                "--author=Bot <bot@example.com>"
+            ]
+        );
+        assert_eq!(
+            CommitSettings::default().commit_args(),
+            ["commit", "--quiet"]
+        );
+    }
+}
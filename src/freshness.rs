@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: MIT
+//! Dependency freshness scores per repo and for the whole fleet
+//!
+//! Each dependency gets a penalty from 0 (latest) to 1 (far behind). The
+//! semver distance to the latest release sets the weight: 1.0 for a major
+//! bump (or a minor bump of a 0.x version), 0.5 for minor, 0.2 for patch.
+//! Time since the first newer release was published scales the weight from
+//! half (just released) to full (a year or more); an unknown publish date
+//! counts as half a year.
+//!
+//! A repo scores `100 * (1 - mean penalty)` over dependencies found in the
+//! registry index; the fleet score is the mean over repos with such
+//! dependencies.
+
+use serde::{Serialize, Serializer};
+
+use crate::registry::{self, Registry, Release};
+use crate::timestamp;
+use crate::RepoInfo;
+
+/// Freshness of one repo's dependencies
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    /// 0 to 100, higher is fresher
+    #[serde(serialize_with = "one_decimal")]
+    pub score: f64,
+    /// Dependencies found in the registry index
+    pub scored: usize,
+    pub outdated: usize,
+}
+
+fn one_decimal<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64((value * 10.0).round() / 10.0)
+}
+
+/// Score a repo against the registry at time `now`
+pub fn repo_score(repo: &RepoInfo, registry: &Registry, now: u64) -> Score {
+    let penalties: Vec<f64> = repo
+        .moon_mods
+        .iter()
+        .flat_map(|m| &m.deps)
+        .filter(|dep| dep.is_registry())
+        .filter_map(|dep| {
+            let declared = dep.version.as_deref()?;
+            let latest = registry.latest(&dep.name)?;
+            Some(penalty(
+                declared,
+                &registry.releases(&dep.name),
+                &latest,
+                now,
+            ))
+        })
+        .collect();
+    let outdated = penalties.iter().filter(|&&p| p > 0.0).count();
+    let mean = if penalties.is_empty() {
+        0.0
+    } else {
+        penalties.iter().sum::<f64>() / penalties.len() as f64
+    };
+    Score {
+        score: 100.0 * (1.0 - mean),
+        scored: penalties.len(),
+        outdated,
+    }
+}
+
+/// Mean score over repos with scored dependencies (100 if there are none)
+pub fn fleet_score(scores: &[Score]) -> f64 {
+    let scored: Vec<f64> = scores
+        .iter()
+        .filter(|s| s.scored > 0)
+        .map(|s| s.score)
+        .collect();
+    if scored.is_empty() {
+        100.0
+    } else {
+        scored.iter().sum::<f64>() / scored.len() as f64
+    }
+}
+
+/// Penalty of a declared version given the package's releases (oldest first)
+fn penalty(declared: &str, releases: &[Release], latest: &Release, now: u64) -> f64 {
+    if !registry::is_outdated(declared, &latest.version) {
+        return 0.0;
+    }
+    let weight = match (
+        semver::Version::parse(declared),
+        semver::Version::parse(&latest.version),
+    ) {
+        (Ok(a), Ok(b)) if a.major != b.major || (a.major == 0 && a.minor != b.minor) => 1.0,
+        (Ok(a), Ok(b)) if a.minor != b.minor => 0.5,
+        (Ok(_), Ok(_)) => 0.2,
+        _ => 0.5,
+    };
+    let published = releases
+        .iter()
+        .find(|r| registry::is_outdated(declared, &r.version))
+        .and_then(Release::published);
+    let years = match published {
+        Some(published) => (timestamp::days_between(published, now) as f64 / 365.0).min(1.0),
+        None => 0.5,
+    };
+    weight * (0.5 + 0.5 * years)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(version: &str, created_at: Option<&str>) -> Release {
+        Release {
+            version: version.to_string(),
+            created_at: created_at.map(str::to_string),
+            ..Release::default()
+        }
+    }
+
+    #[test]
+    fn test_penalty_weights_distance_and_age() {
+        let now = timestamp::parse_rfc3339("2026-01-01T00:00:00Z").unwrap();
+        let releases = vec![
+            release("1.0.0", None),
+            release("1.0.1", Some("2025-01-01T00:00:00Z")),
+            release("1.1.0", Some("2025-12-01T00:00:00Z")),
+            release("2.0.0", Some("2025-12-31T00:00:00Z")),
+        ];
+        let latest = releases.last().unwrap();
+
+        assert_eq!(penalty("2.0.0", &releases, latest, now), 0.0);
+        // Major behind, first newer release a year old
+        assert_eq!(penalty("1.0.0", &releases, latest, now), 1.0);
+        // Major behind, newer release one day old
+        let fresh = penalty("1.1.0", &releases, latest, now);
+        assert!((fresh - (0.5 + 0.5 / 365.0)).abs() < 1e-9);
+
+        let scores = [
+            Score {
+                score: 50.0,
+                scored: 2,
+                outdated: 1,
+            },
+            Score {
+                score: 100.0,
+                scored: 0,
+                outdated: 0,
+            },
+        ];
+        assert_eq!(fleet_score(&scores), 50.0);
+    }
+}
@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: MIT
+//! One pass/fail verdict for CI
+//!
+//! `gate` runs the checks a pipeline would otherwise wire up one by one and
+//! judges their findings against a single policy (`[gate]` in the config,
+//! or flags):
+//!
+//! - `outdated`: dependencies behind the registry, each a warning, or an
+//!   error (`major-lag`) when more than `max-major-lag` major versions
+//!   behind; an outdated baseline leaves accepted ones out
+//! - `lint`: the `lint` rules on the manifests
+//! - `audit`: yanked and deprecated versions, the advisories the registry
+//!   index records; error and warning unless `yanked` / `deprecated` set
+//!   another level
+//! - `policy`: expired pins (error) and dependencies under names `[renames]`
+//!   replaces (warning)
+//!
+//! The gate fails when a finding reaches `fail-on` (`error` by default).
+//! For 0.x versions each minor version counts as a major one, as semver
+//! makes them breaking.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::findings::{Finding, Level, Rule};
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "G001",
+        name: "major-lag",
+        description: "Dependency is more major versions behind than the gate allows",
+        level: Level::Error,
+    },
+    Rule {
+        id: "G002",
+        name: "pin-expired",
+        description: "A dependency pin has expired",
+        level: Level::Error,
+    },
+    Rule {
+        id: "G003",
+        name: "old-package-name",
+        description: "Dependency on a package under a name the renames config replaces",
+        level: Level::Warning,
+    },
+];
+
+/// A group of gate findings
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Check {
+    Outdated,
+    Lint,
+    Audit,
+    Policy,
+}
+
+impl Check {
+    pub const ALL: [Check; 4] = [Check::Outdated, Check::Lint, Check::Audit, Check::Policy];
+
+    /// The check a finding of `rule_id` belongs to
+    pub fn of(rule_id: &str) -> Check {
+        match rule_id {
+            "L002" | "G001" => Check::Outdated,
+            "L004" | "L005" => Check::Audit,
+            "G002" | "G003" => Check::Policy,
+            _ => Check::Lint,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Check::Outdated => "outdated",
+            Check::Lint => "lint",
+            Check::Audit => "audit",
+            Check::Policy => "policy",
+        }
+    }
+}
+
+/// Lowest finding level that fails the gate
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailOn {
+    Warning,
+    #[default]
+    Error,
+}
+
+impl FailOn {
+    pub fn fails(self, level: Level) -> bool {
+        match self {
+            FailOn::Warning => level >= Level::Warning,
+            FailOn::Error => level >= Level::Error,
+        }
+    }
+}
+
+/// Major versions `latest` is ahead of `declared`; `None` unless both
+/// are semver
+pub fn major_lag(declared: &str, latest: &str) -> Option<u64> {
+    let declared = semver::Version::parse(declared).ok()?;
+    let latest = semver::Version::parse(latest).ok()?;
+    Some(match (declared.major, latest.major) {
+        (0, 0) => latest.minor.saturating_sub(declared.minor),
+        (declared, latest) => latest.saturating_sub(declared),
+    })
+}
+
+/// Findings of one check by level
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Tally {
+    pub check: Check,
+    pub errors: usize,
+    pub warnings: usize,
+    pub notes: usize,
+}
+
+/// The gate's judgement of `findings`
+#[derive(Serialize, Debug, Clone)]
+pub struct Verdict {
+    pub passed: bool,
+    pub fail_on: FailOn,
+    pub checks: Vec<Tally>,
+    pub findings: Vec<Finding>,
+}
+
+impl Verdict {
+    /// Judge `findings` of the `checks` that ran
+    pub fn new(checks: &[Check], findings: Vec<Finding>, fail_on: FailOn) -> Verdict {
+        let checks = checks
+            .iter()
+            .map(|&check| {
+                let count = |level| {
+                    findings
+                        .iter()
+                        .filter(|f| Check::of(f.rule_id) == check && f.level == level)
+                        .count()
+                };
+                Tally {
+                    check,
+                    errors: count(Level::Error),
+                    warnings: count(Level::Warning),
+                    notes: count(Level::Note),
+                }
+            })
+            .collect();
+        Verdict {
+            passed: !findings.iter().any(|f| fail_on.fails(f.level)),
+            fail_on,
+            checks,
+            findings,
+        }
+    }
+
+    /// `Gate: FAILED (2 errors, 3 warnings; fails on error)`, then a line
+    /// per check
+    pub fn lines(&self) -> Vec<String> {
+        let total = |f: fn(&Tally) -> usize| self.checks.iter().map(f).sum::<usize>();
+        let fail_on = match self.fail_on {
+            FailOn::Warning => "warning",
+            FailOn::Error => "error",
+        };
+        let mut lines = vec![format!(
+            "Gate: {} ({}; fails on {fail_on})",
+            if self.passed { "PASSED" } else { "FAILED" },
+            counts(total(|t| t.errors), total(|t| t.warnings))
+        )];
+        for tally in &self.checks {
+            let result = match tally.errors + tally.warnings {
+                0 => "ok".to_string(),
+                _ => counts(tally.errors, tally.warnings),
+            };
+            lines.push(format!("  {}: {result}", tally.check.as_str()));
+        }
+        lines
+    }
+}
+
+fn counts(errors: usize, warnings: usize) -> String {
+    let plural = |n: usize, word: &str| match n {
+        1 => format!("1 {word}"),
+        n => format!("{n} {word}s"),
+    };
+    format!(
+        "{}, {}",
+        plural(errors, "error"),
+        plural(warnings, "warning")
+    )
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn finding(rule_id: &'static str, level: Level) -> Finding {
+        Finding {
+            rule_id,
+            level,
+            message: String::new(),
+            path: PathBuf::from("moon.mod.json"),
+            line: None,
+        }
+    }
+
+    #[test]
+    fn test_major_lag() {
+        assert_eq!(major_lag("1.2.0", "3.0.0"), Some(2));
+        assert_eq!(major_lag("0.2.0", "0.5.1"), Some(3));
+        assert_eq!(major_lag("0.9.0", "1.0.0"), Some(1));
+        assert_eq!(major_lag("1.0.0", "1.4.0"), Some(0));
+        assert_eq!(major_lag("2.0.0", "1.0.0"), Some(0));
+        assert_eq!(major_lag("v1", "2.0.0"), None);
+    }
+
+    #[test]
+    fn test_check_of_rule() {
+        assert_eq!(Check::of("G001"), Check::Outdated);
+        assert_eq!(Check::of("L002"), Check::Outdated);
+        assert_eq!(Check::of("L004"), Check::Audit);
+        assert_eq!(Check::of("L005"), Check::Audit);
+        assert_eq!(Check::of("G003"), Check::Policy);
+        assert_eq!(Check::of("L001"), Check::Lint);
+    }
+
+    #[test]
+    fn test_verdict_lines_count_by_check() {
+        let findings = vec![
+            finding("G001", Level::Error),
+            finding("L002", Level::Warning),
+            finding("L005", Level::Warning),
+        ];
+        let verdict = Verdict::new(&Check::ALL, findings, FailOn::Error);
+        assert!(!verdict.passed);
+        assert_eq!(
+            verdict.lines(),
+            [
+                "Gate: FAILED (1 error, 2 warnings; fails on error)",
+                "  outdated: 1 error, 1 warning",
+                "  lint: ok",
+                "  audit: 0 errors, 1 warning",
+                "  policy: ok",
+            ]
+        );
+        let verdict = Verdict::new(&[Check::Lint], Vec::new(), FailOn::Error);
+        assert_eq!(
+            verdict.lines(),
+            [
+                "Gate: PASSED (0 errors, 0 warnings; fails on error)",
+                "  lint: ok"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fail_on_threshold() {
+        let warnings = vec![
+            finding("L002", Level::Warning),
+            finding("L005", Level::Note),
+        ];
+        assert!(Verdict::new(&Check::ALL, warnings.clone(), FailOn::Error).passed);
+        assert!(!Verdict::new(&Check::ALL, warnings, FailOn::Warning).passed);
+        let notes = vec![finding("L004", Level::Note)];
+        assert!(Verdict::new(&Check::ALL, notes, FailOn::Warning).passed);
+    }
+}
@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: MIT
+//! Git dependencies: resolving branch heads and bumping pinned revisions
+//!
+//! A git dependency is declared as
+//! `"owner/pkg": { "git": "<url>", "branch": "main", "rev": "<sha>" }`.
+//! Bumping rewrites only the `rev` value in the manifest text so the rest of
+//! the file keeps its formatting.
+
+use anyhow::{bail, Context, Result};
+use std::process::Command;
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+
+/// Latest commit on `branch` (or the remote HEAD) of a git remote
+pub fn latest_commit(url: &str, branch: Option<&str>) -> Result<String> {
+    let reference = match branch {
+        Some(branch) => format!("refs/heads/{branch}"),
+        None => "HEAD".to_string(),
+    };
+    crate::capabilities::require_git(&["ls-remote"])?;
+    let output = Command::new("git")
+        .args(["ls-remote", url, &reference])
+        .logged_output()
+        .context("Failed to run git (is it installed?)")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::GitFailed,
+            format!(
+                "git ls-remote {url} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.split_whitespace().next() {
+        Some(sha) => Ok(sha.to_string()),
+        None => bail!(Coded::new(
+            Code::GitFailed,
+            format!("{url} has no {reference}")
+        )),
+    }
+}
+
+/// Set the `rev` of dependency `name` in manifest text
+///
+/// Replaces an existing `"rev"` value or inserts one at the start of the
+/// dependency object. Returns `None` if the dependency object is not found.
+pub fn set_rev(content: &str, name: &str, rev: &str) -> Option<String> {
+    let key = format!("\"{name}\"");
+    let key_end = content.find(&key)? + key.len();
+    let open = key_end + content[key_end..].find('{')?;
+    if !content[key_end..open].trim().starts_with(':') {
+        return None;
+    }
+    let close = open + content[open..].find('}')?;
+    let object = &content[open..close];
+
+    let mut out = String::with_capacity(content.len() + rev.len() + 10);
+    match object.find("\"rev\"") {
+        Some(rev_key) => {
+            let after_key = open + rev_key + "\"rev\"".len();
+            let quote = after_key + content[after_key..].find('"')?;
+            let end = quote + 1 + content[quote + 1..].find('"')?;
+            out.push_str(&content[..=quote]);
+            out.push_str(rev);
+            out.push_str(&content[end..]);
+        }
+        None => {
+            out.push_str(&content[..=open]);
+            out.push_str(&format!(" \"rev\": \"{rev}\","));
+            out.push_str(&content[open + 1..]);
+        }
+    }
+    Some(out)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_rev() {
+        let manifest = r#"{
+  "deps": {
+    "a/lib": { "git": "https://example.com/lib.git", "rev": "aaa" },
+    "b/lib": {"git": "https://example.com/b.git", "branch": "main"}
+  }
+}"#;
+        let bumped = set_rev(manifest, "a/lib", "bbb").unwrap();
+        assert!(
+            bumped.contains(r#""a/lib": { "git": "https://example.com/lib.git", "rev": "bbb" }"#)
+        );
+
+        let inserted = set_rev(&bumped, "b/lib", "ccc").unwrap();
+        assert!(inserted.contains(r#""b/lib": { "rev": "ccc","git": "https://example.com/b.git""#));
+        assert_eq!(set_rev(manifest, "c/lib", "ddd"), None);
+    }
+}
@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: MIT
+//! GitHub Actions workflow commands and step summaries
+
+use anyhow::{Context, Result};
+use std::io::Write;
+
+/// Annotation severity (`::notice`, `::warning`, `::error`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationLevel {
+    Notice,
+    Warning,
+    Error,
+}
+
+impl AnnotationLevel {
+    fn command(self) -> &'static str {
+        match self {
+            AnnotationLevel::Notice => "notice",
+            AnnotationLevel::Warning => "warning",
+            AnnotationLevel::Error => "error",
+        }
+    }
+}
+
+impl From<crate::findings::Level> for AnnotationLevel {
+    fn from(level: crate::findings::Level) -> Self {
+        match level {
+            crate::findings::Level::Note => AnnotationLevel::Notice,
+            crate::findings::Level::Warning => AnnotationLevel::Warning,
+            crate::findings::Level::Error => AnnotationLevel::Error,
+        }
+    }
+}
+
+/// Format an annotation workflow command, e.g. `::error file=a.json,line=3::message`
+pub fn annotation(
+    level: AnnotationLevel,
+    file: Option<&str>,
+    line: Option<usize>,
+    title: Option<&str>,
+    message: &str,
+) -> String {
+    let mut properties = Vec::new();
+    if let Some(file) = file {
+        properties.push(format!("file={}", escape_property(file)));
+        if let Some(line) = line {
+            properties.push(format!("line={line}"));
+        }
+    }
+    if let Some(title) = title {
+        properties.push(format!("title={}", escape_property(title)));
+    }
+
+    let properties = if properties.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", properties.join(","))
+    };
+    format!(
+        "::{}{properties}::{}",
+        level.command(),
+        escape_data(message.trim_end())
+    )
+}
+
+/// Append Markdown to the job summary if running inside GitHub Actions
+pub fn append_step_summary(markdown: &str) -> Result<()> {
+    let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY").filter(|p| !p.is_empty()) else {
+        return Ok(());
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.to_string_lossy()))?;
+    writeln!(file, "{markdown}")
+        .with_context(|| format!("Failed to write {}", path.to_string_lossy()))
+}
+
+/// Escape a table cell for Markdown summaries
+pub fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotation_escaping() {
+        assert_eq!(
+            annotation(
+                AnnotationLevel::Error,
+                Some("a,b/moon.mod.json"),
+                Some(3),
+                Some("moon-dst: lint"),
+                "50% broken\nsecond line"
+            ),
+            "::error file=a%2Cb/moon.mod.json,line=3,title=moon-dst%3A lint::50%25 broken%0Asecond line"
+        );
+        assert_eq!(
+            annotation(AnnotationLevel::Warning, None, Some(1), None, "plain"),
+            "::warning::plain"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: MIT
+//! Standard `.gitignore` entries for MoonBit projects
+//!
+//! Merging appends only the entries that are not already covered, so existing
+//! rules and their order are preserved and repeated runs are no-ops.
+
+/// Entries every MoonBit repo should ignore
+pub const ENTRIES: &[&str] = &["target/", ".mooncakes/", ".DS_Store"];
+
+/// Comment line introducing the appended block
+const HEADER: &str = "# MoonBit";
+
+/// Content of a freshly created `.gitignore`
+pub fn template() -> String {
+    let mut out = format!("{HEADER}\n");
+    for entry in ENTRIES {
+        out.push_str(entry);
+        out.push('\n');
+    }
+    out
+}
+
+/// Standard entries not yet covered by the existing content
+///
+/// `target`, `/target`, `target/` and `/target/` are all treated as covering
+/// `target/`; negated and commented-out lines never count.
+pub fn missing(existing: &str) -> Vec<&'static str> {
+    let present: Vec<&str> = existing
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('!'))
+        .map(normalize)
+        .collect();
+    ENTRIES
+        .iter()
+        .copied()
+        .filter(|entry| !present.contains(&normalize(entry)))
+        .collect()
+}
+
+/// Append missing standard entries to the existing content
+pub fn merge(existing: &str) -> String {
+    let missing = missing(existing);
+    if missing.is_empty() {
+        return existing.to_string();
+    }
+
+    let mut out = existing.to_string();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    if !out.trim().is_empty() {
+        out.push('\n');
+    }
+    out.push_str(HEADER);
+    out.push('\n');
+    for entry in missing {
+        out.push_str(entry);
+        out.push('\n');
+    }
+    out
+}
+
+fn normalize(pattern: &str) -> &str {
+    pattern.trim_start_matches('/').trim_end_matches('/')
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_appends_missing_idempotently() {
+        let existing = "/target\nnode_modules/\n!.mooncakes/";
+        let merged = merge(existing);
+        assert_eq!(
+            merged,
+            "/target\nnode_modules/\n!.mooncakes/\n\n# MoonBit\n.mooncakes/\n.DS_Store\n"
+        );
+        assert_eq!(merge(&merged), merged);
+        assert_eq!(merge(""), template());
+    }
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: MIT
+//! Reading manifests from committed git objects instead of the worktree
+//!
+//! With `--git-ref <REF>`, repos are found by their git directories (working
+//! trees or bare repos) and `moon.mod.json` blobs are read at `REF` with
+//! `git ls-tree` and `git cat-file`, so reports reflect what is committed
+//! rather than local edits.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+
+/// Whether `dir` is a bare repo (`HEAD`, `objects/` and `refs/` at its top)
+fn is_bare(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
+
+/// Git repos (working trees and bare repos) below `root`, not descending
+/// into a repo once found
+pub fn find_repos(root: &Path, ignore: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let mut repos = Vec::new();
+    let mut walker = WalkDir::new(root)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| !ignore(e.path().strip_prefix(root).unwrap_or(e.path())));
+    while let Some(entry) = walker.next() {
+        let entry = entry?;
+        if entry.file_type().is_dir()
+            && (entry.path().join(".git").exists() || is_bare(entry.path()))
+        {
+            repos.push(entry.path().to_path_buf());
+            walker.skip_current_dir();
+        }
+    }
+    Ok(repos)
+}
+
+fn git(repo: &Path, args: &[&str]) -> Result<std::process::Output> {
+    Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .logged_output()
+        .context("Failed to run git (is it installed?)")
+}
+
+/// Whether `git_ref` names a commit in `repo`
+pub fn has_ref(repo: &Path, git_ref: &str) -> bool {
+    git(
+        repo,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{git_ref}^{{commit}}"),
+        ],
+    )
+    .is_ok_and(|output| output.status.success())
+}
+
+/// Paths of `moon.mod.json` files in the tree at `git_ref`, skipping dot
+/// directories and directories `ignore` rejects
+pub fn list_manifests(
+    repo: &Path,
+    git_ref: &str,
+    ignore: impl Fn(&Path) -> bool,
+) -> Result<Vec<String>> {
+    let output = git(repo, &["ls-tree", "-r", "-z", "--name-only", git_ref])?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::GitFailed,
+            format!(
+                "git ls-tree {git_ref} failed in {}: {}",
+                repo.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|path| *path == "moon.mod.json" || path.ends_with("/moon.mod.json"))
+        .filter(|path| !ignore(Path::new(path)))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Content of the blob at `path` in `git_ref`, or `None` if there is none
+pub fn read_blob(repo: &Path, git_ref: &str, path: &str) -> Result<Option<String>> {
+    let output = git(repo, &["cat-file", "blob", &format!("{git_ref}:{path}")])?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    String::from_utf8(output.stdout)
+        .map(Some)
+        .with_context(|| format!("{git_ref}:{path} in {} is not UTF-8", repo.display()))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_manifests_at_ref() {
+        let dir = std::env::temp_dir().join("moon_dst_gitref_test");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let run = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?}");
+        };
+        run(&["init", "-q"]);
+        std::fs::write(dir.join("moon.mod.json"), r#"{"name":"a/root"}"#).unwrap();
+        std::fs::write(dir.join("lib/moon.mod.json"), r#"{"name":"a/lib"}"#).unwrap();
+        run(&["add", "."]);
+        run(&["commit", "-q", "-m", "init"]);
+        // Local edits are not seen at HEAD
+        std::fs::write(dir.join("moon.mod.json"), r#"{"name":"a/edited"}"#).unwrap();
+
+        let manifests = list_manifests(&dir, "HEAD", |p| p.starts_with("lib")).unwrap();
+        assert_eq!(manifests, ["moon.mod.json"]);
+        assert_eq!(
+            read_blob(&dir, "HEAD", "moon.mod.json").unwrap().as_deref(),
+            Some(r#"{"name":"a/root"}"#)
+        );
+        assert_eq!(read_blob(&dir, "HEAD", "missing").unwrap(), None);
+        assert!(has_ref(&dir, "HEAD"));
+        assert!(!has_ref(&dir, "origin/nope"));
+        assert_eq!(find_repos(&dir, |_| false).unwrap(), vec![dir.clone()]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,384 @@
+// SPDX-License-Identifier: MIT
+//! Cross-repo dependency graph
+//!
+//! Nodes are the modules of the fleet (internal) and the packages they
+//! depend on from outside it (external). Each edge runs from a module to
+//! one of its dependencies. It is internal when the dependency is a fleet
+//! module and external otherwise. Its scope is runtime for `deps`, or dev
+//! for `bin-deps` (tools used while building).
+//!
+//! [`Graph::stats`] ranks packages by how many repos an update would reach.
+//!
+//! The JSON form carries [`SCHEMA_VERSION`]. It changes only when fields are
+//! removed or change meaning, so catalogs ingesting it can reject documents
+//! they do not understand.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::registry::Registry;
+use crate::{verify, RepoInfo};
+
+/// Version of the JSON document layout
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Whether a node or edge stays within the fleet
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Internal,
+    External,
+}
+
+/// Whether a dependency is linked into the module or only used to build it
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Runtime,
+    Dev,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    /// Module name (`owner/name`), or its path for unnamed modules
+    pub id: String,
+    pub kind: Kind,
+    /// Repo holding the module (internal nodes)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::ospath::option::serialize"
+    )]
+    pub manifest: Option<PathBuf>,
+    /// Latest registry version (external registry packages)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: Kind,
+    pub scope: Scope,
+    /// Declared version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// `registry`, `path` or `git`
+    pub source: &'static str,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Graph {
+    pub schema_version: u32,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// Fan-in, fan-out and reach of one node
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeStats {
+    pub id: String,
+    pub kind: Kind,
+    /// Repos with a module depending on it directly
+    pub fan_in: usize,
+    /// Its direct dependencies (fleet modules only; the dependencies of
+    /// external packages are not part of the graph)
+    pub fan_out: usize,
+    /// Repos depending on it directly or through other fleet modules,
+    /// i.e. the repos an update of it would reach
+    pub impact: usize,
+}
+
+impl Graph {
+    /// The dependency graph of the modules of `repos`
+    pub fn build(repos: &[RepoInfo], registry: &Registry) -> Self {
+        let mut nodes: BTreeMap<String, Node> = BTreeMap::new();
+        let mut modules = Vec::new();
+        for repo in repos {
+            for moon_mod in &repo.moon_mods {
+                let id = moon_mod.name.clone().unwrap_or_else(|| {
+                    let dir = moon_mod.path.parent().unwrap_or(&repo.root);
+                    format!(
+                        "{}/{}",
+                        repo.location(),
+                        verify::module_label(&repo.root, dir)
+                    )
+                });
+                nodes.insert(
+                    id.clone(),
+                    Node {
+                        id: id.clone(),
+                        kind: Kind::Internal,
+                        repo: Some(repo.location()),
+                        manifest: Some(moon_mod.path.clone()),
+                        latest: None,
+                    },
+                );
+                modules.push((id, moon_mod));
+            }
+        }
+
+        let mut edges = Vec::new();
+        for (id, moon_mod) in modules {
+            let scoped = (moon_mod.deps.iter().map(|d| (d, Scope::Runtime)))
+                .chain(moon_mod.bin_deps.iter().map(|d| (d, Scope::Dev)));
+            for (dep, scope) in scoped {
+                let kind = match nodes.get(&dep.name) {
+                    Some(node) if node.kind == Kind::Internal => Kind::Internal,
+                    _ => Kind::External,
+                };
+                if kind == Kind::External {
+                    nodes.entry(dep.name.clone()).or_insert_with(|| Node {
+                        id: dep.name.clone(),
+                        kind,
+                        repo: None,
+                        manifest: None,
+                        latest: dep
+                            .is_registry()
+                            .then(|| registry.latest(&dep.name))
+                            .flatten()
+                            .map(|r| r.version),
+                    });
+                }
+                edges.push(Edge {
+                    from: id.clone(),
+                    to: dep.name.clone(),
+                    kind,
+                    scope,
+                    version: dep.version.clone(),
+                    source: dep.source.kind(),
+                });
+            }
+        }
+        edges.sort_by(|a, b| (&a.from, &a.to, a.scope).cmp(&(&b.from, &b.to, b.scope)));
+
+        Graph {
+            schema_version: SCHEMA_VERSION,
+            nodes: nodes.into_values().collect(),
+            edges,
+        }
+    }
+
+    /// Stats of every node, highest impact first
+    pub fn stats(&self) -> Vec<NodeStats> {
+        let repo_of: BTreeMap<&str, &str> = self
+            .nodes
+            .iter()
+            .filter_map(|n| Some((n.id.as_str(), n.repo.as_deref()?)))
+            .collect();
+        let mut dependents: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        let mut fan_out: BTreeMap<&str, usize> = BTreeMap::new();
+        for edge in &self.edges {
+            dependents.entry(&edge.to).or_default().push(&edge.from);
+            *fan_out.entry(&edge.from).or_default() += 1;
+        }
+        let repos = |modules: &mut dyn Iterator<Item = &str>| {
+            modules
+                .filter_map(|m| repo_of.get(m))
+                .collect::<BTreeSet<_>>()
+                .len()
+        };
+
+        let mut stats: Vec<NodeStats> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let direct = dependents.get(node.id.as_str());
+                let mut reached = BTreeSet::new();
+                let mut pending: Vec<&str> = direct.cloned().unwrap_or_default();
+                while let Some(module) = pending.pop() {
+                    if reached.insert(module) {
+                        pending.extend(dependents.get(module).into_iter().flatten());
+                    }
+                }
+                NodeStats {
+                    id: node.id.clone(),
+                    kind: node.kind,
+                    fan_in: repos(&mut direct.into_iter().flatten().copied()),
+                    fan_out: fan_out.get(node.id.as_str()).copied().unwrap_or(0),
+                    impact: repos(&mut reached.into_iter()),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            (b.impact, b.fan_in)
+                .cmp(&(a.impact, a.fan_in))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        stats
+    }
+
+    /// Graphviz DOT: fleet modules as boxes, dev edges dashed
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph dependencies {\n  rankdir=LR;\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                Kind::Internal => "box",
+                Kind::External => "ellipse",
+            };
+            let _ = writeln!(out, "  \"{}\" [shape={shape}];", node.id);
+        }
+        for edge in &self.edges {
+            let mut attrs = Vec::new();
+            if let Some(version) = &edge.version {
+                attrs.push(format!("label=\"{version}\""));
+            }
+            if edge.scope == Scope::Dev {
+                attrs.push("style=dashed".to_string());
+            }
+            let attrs = if attrs.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", attrs.join(", "))
+            };
+            let _ = writeln!(out, "  \"{}\" -> \"{}\"{attrs};", edge.from, edge.to);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Mermaid flowchart: fleet modules as rectangles, dev edges dotted
+    pub fn to_mermaid(&self) -> String {
+        let ids: BTreeMap<&str, String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id.as_str(), format!("n{i}")))
+            .collect();
+        let mut out = String::from("graph LR\n");
+        for node in &self.nodes {
+            let (open, close) = match node.kind {
+                Kind::Internal => ("[", "]"),
+                Kind::External => ("([", "])"),
+            };
+            let _ = writeln!(
+                out,
+                "  {}{open}\"{}\"{close}",
+                ids[node.id.as_str()],
+                node.id
+            );
+        }
+        for edge in &self.edges {
+            let arrow = match edge.scope {
+                Scope::Runtime => "-->",
+                Scope::Dev => "-.->",
+            };
+            let label = edge
+                .version
+                .as_deref()
+                .map(|v| format!("|{v}|"))
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "  {} {arrow}{label} {}",
+                ids[edge.from.as_str()],
+                ids[edge.to.as_str()]
+            );
+        }
+        out
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_moon_mod_content;
+    use std::path::Path;
+
+    /// me/app uses me/lib, a/x and the tool a/tool; me/lib uses a/y
+    fn graph() -> Graph {
+        let app = parse_moon_mod_content(
+            r#"{"name":"me/app","deps":{"me/lib":"0.1.0","a/x":"1.0.0"},"bin-deps":{"a/tool":"2.0.0"}}"#,
+            Path::new("/work/app/moon.mod.json"),
+        )
+        .unwrap();
+        let lib = parse_moon_mod_content(
+            r#"{"name":"me/lib","deps":{"a/y":"1.0.0"}}"#,
+            Path::new("/work/lib/moon.mod.json"),
+        )
+        .unwrap();
+        let repos = [
+            crate::RepoInfo::local(PathBuf::from("/work/app"), vec![app]),
+            crate::RepoInfo::local(PathBuf::from("/work/lib"), vec![lib]),
+        ];
+        Graph::build(&repos, &Registry::with_index_dir(PathBuf::from("/none")))
+    }
+
+    #[test]
+    fn test_build_types_nodes() {
+        let graph = graph();
+        let kinds: Vec<(&str, Kind)> = graph
+            .nodes
+            .iter()
+            .map(|n| (n.id.as_str(), n.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("a/tool", Kind::External),
+                ("a/x", Kind::External),
+                ("a/y", Kind::External),
+                ("me/app", Kind::Internal),
+                ("me/lib", Kind::Internal)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_types_edges() {
+        let graph = graph();
+        let edges: Vec<(&str, Kind, Scope)> = graph
+            .edges
+            .iter()
+            .map(|e| (e.to.as_str(), e.kind, e.scope))
+            .collect();
+        assert_eq!(
+            edges,
+            [
+                ("a/tool", Kind::External, Scope::Dev),
+                ("a/x", Kind::External, Scope::Runtime),
+                ("me/lib", Kind::Internal, Scope::Runtime),
+                ("a/y", Kind::External, Scope::Runtime)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dot_mermaid_and_json_output() {
+        let graph = graph();
+        assert!(graph
+            .to_dot()
+            .contains("\"me/app\" -> \"a/tool\" [label=\"2.0.0\", style=dashed];"));
+        assert!(graph.to_mermaid().contains("n3 -.->|2.0.0| n0"));
+        let json = serde_json::to_value(&graph).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_stats_count_transitive_impact() {
+        // a/y reaches app through me/lib
+        let stats = graph().stats();
+        let stats: Vec<(&str, usize, usize, usize)> = stats
+            .iter()
+            .map(|s| (s.id.as_str(), s.fan_in, s.fan_out, s.impact))
+            .collect();
+        assert_eq!(
+            stats,
+            [
+                ("a/y", 1, 0, 2),
+                ("a/tool", 1, 0, 1),
+                ("a/x", 1, 0, 1),
+                ("me/lib", 1, 1, 1),
+                ("me/app", 0, 3, 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// SPDX-License-Identifier: MIT
+//! Text output grouped by host and organization
+//!
+//! A ghq-style tree (`host/org/repo`) can hold hundreds of repos. With
+//! `--group-output host` or `org`, the text output of `scan` and `apply`
+//! lists repos depth first under a heading per host, and per organization
+//! within it, each heading with subtotals for its repos. A repo's host and
+//! org are the first directories of its path below the `--root` it was
+//! found under; remote repos are grouped by their SSH host.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// How text output is grouped
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupOutput {
+    /// One list of repos
+    #[default]
+    Flat,
+    /// Repos under their host
+    Host,
+    /// Repos under their host and organization
+    Org,
+}
+
+impl GroupOutput {
+    /// Number of heading levels
+    fn depth(self) -> usize {
+        match self {
+            GroupOutput::Flat => 0,
+            GroupOutput::Host => 1,
+            GroupOutput::Org => 2,
+        }
+    }
+}
+
+/// Label of the group of repos right below a root
+const TOP: &str = ".";
+
+/// Repos sharing a host, or a host and organization
+pub struct Group<'a, T> {
+    /// `host` or `host/org`
+    pub label: String,
+    /// Every item in the group, subgroups included
+    pub items: Vec<&'a T>,
+    /// Items in no subgroup, listed before the subgroups
+    pub own: Vec<&'a T>,
+    pub subgroups: Vec<Group<'a, T>>,
+}
+
+/// Heading labels of the repo at `location` (a path, or `host:/path` for a
+/// remote repo), relative to the first of `roots` containing it
+///
+/// A repo with fewer directories above it than `mode` has levels gets
+/// fewer labels and is listed directly under its last one.
+pub fn labels(location: &Path, roots: &[PathBuf], mode: GroupOutput) -> Vec<String> {
+    let mut dirs: Vec<String> = match location.to_str().and_then(remote_host) {
+        Some(host) => vec![host.to_string()],
+        None => {
+            let rel = roots
+                .iter()
+                .find_map(|root| location.strip_prefix(root).ok())
+                .unwrap_or(location);
+            let rel = rel.parent().unwrap_or(Path::new(""));
+            rel.components()
+                .filter_map(|c| match c {
+                    Component::Normal(name) => Some(crate::ospath::encode(Path::new(name))),
+                    _ => None,
+                })
+                .collect()
+        }
+    };
+    dirs.truncate(mode.depth());
+    if dirs.is_empty() && mode != GroupOutput::Flat {
+        dirs.push(TOP.to_string());
+    }
+    dirs
+}
+
+fn remote_host(location: &str) -> Option<&str> {
+    if location.starts_with('/') {
+        return None;
+    }
+    location.split_once(':').map(|(host, _)| host)
+}
+
+/// `items` grouped by their [`labels`], groups sorted by label
+pub fn group<'a, T>(items: &'a [T], labels: impl Fn(&T) -> Vec<String>) -> Vec<Group<'a, T>> {
+    let entries = items.iter().map(|item| (labels(item), item)).collect();
+    build(entries, 0, "")
+}
+
+fn build<'a, T>(
+    entries: Vec<(Vec<String>, &'a T)>,
+    level: usize,
+    prefix: &str,
+) -> Vec<Group<'a, T>> {
+    let mut by_label: BTreeMap<String, Vec<(Vec<String>, &'a T)>> = BTreeMap::new();
+    for (labels, item) in entries {
+        if let Some(label) = labels.get(level).cloned() {
+            by_label.entry(label).or_default().push((labels, item));
+        }
+    }
+    by_label
+        .into_iter()
+        .map(|(label, entries)| {
+            let label = if prefix.is_empty() {
+                label
+            } else {
+                format!("{prefix}/{label}")
+            };
+            Group {
+                items: entries.iter().map(|(_, item)| *item).collect(),
+                own: entries
+                    .iter()
+                    .filter(|(labels, _)| labels.len() == level + 1)
+                    .map(|(_, item)| *item)
+                    .collect(),
+                subgroups: build(entries, level + 1, &label),
+                label,
+            }
+        })
+        .collect()
+}
+
+/// Call `visit` with each group and its level, parents before children
+pub fn walk<'a, T>(groups: &[Group<'a, T>], visit: &mut impl FnMut(usize, &Group<'a, T>)) {
+    walk_at(groups, 0, visit);
+}
+
+fn walk_at<'a, T>(
+    groups: &[Group<'a, T>],
+    level: usize,
+    visit: &mut impl FnMut(usize, &Group<'a, T>),
+) {
+    for group in groups {
+        visit(level, group);
+        walk_at(&group.subgroups, level + 1, visit);
+    }
+}
+
+/// Heading line of a group at `level`; plain output has no rules
+pub fn heading(level: usize, label: &str, subtotals: &str) -> String {
+    if crate::output::plain() {
+        return format!("Group {label}: {subtotals}");
+    }
+    match level {
+        0 => format!("=== {label}: {subtotals} ==="),
+        _ => format!("--- {label}: {subtotals} ---"),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCATIONS: [&str; 6] = [
+        "/ghq/github.com/acme/app",
+        "/ghq/github.com/acme/lib",
+        "/ghq/github.com/other/tool",
+        "/ghq/gitlab.com/acme/x",
+        "/work/mono",
+        "build01:/srv/repo",
+    ];
+
+    fn roots() -> [PathBuf; 2] {
+        [PathBuf::from("/ghq"), PathBuf::from("/work")]
+    }
+
+    #[test]
+    fn test_labels_by_mode() {
+        let roots = roots();
+        let labels = |location: &str, mode| labels(Path::new(location), &roots, mode);
+        assert_eq!(
+            labels(LOCATIONS[0], GroupOutput::Org),
+            ["github.com", "acme"]
+        );
+        assert_eq!(labels(LOCATIONS[4], GroupOutput::Org), ["."]);
+        assert_eq!(labels(LOCATIONS[5], GroupOutput::Org), ["build01"]);
+        assert!(labels(LOCATIONS[0], GroupOutput::Flat).is_empty());
+    }
+
+    #[test]
+    fn test_group_by_org_nests_under_hosts() {
+        let roots = roots();
+        let groups = group(&LOCATIONS, |location: &&str| {
+            labels(Path::new(location), &roots, GroupOutput::Org)
+        });
+        let mut seen = Vec::new();
+        walk(&groups, &mut |level, group| {
+            seen.push(format!(
+                "{level} {} {}/{}",
+                group.label,
+                group.own.len(),
+                group.items.len()
+            ))
+        });
+        assert_eq!(
+            seen,
+            [
+                "0 . 1/1",
+                "0 build01 1/1",
+                "0 github.com 0/3",
+                "1 github.com/acme 2/2",
+                "1 github.com/other 1/1",
+                "0 gitlab.com 0/1",
+                "1 gitlab.com/acme 1/1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_by_host_is_flat() {
+        let roots = roots();
+        let hosts = group(&LOCATIONS, |location: &&str| {
+            labels(Path::new(location), &roots, GroupOutput::Host)
+        });
+        assert_eq!(hosts.len(), 4);
+        assert!(hosts
+            .iter()
+            .all(|g| g.subgroups.is_empty() && g.own.len() == g.items.len()));
+    }
+}
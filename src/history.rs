@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: MIT
+//! Journal of apply runs
+//!
+//! Every apply run that is not a dry run appends one JSON line to
+//! `history.jsonl` in the data directory (`$MOON_DST_HOME` or
+//! `~/.moon-dst`). Only repos with version changes or failures are recorded.
+//! Runs carry their run ID, which `history show` looks up.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Journal file name inside the data directory
+const JOURNAL_FILE: &str = "history.jsonl";
+
+/// One apply run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Run {
+    /// Run ID; absent in entries from older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Start time in seconds since the Unix epoch
+    pub started: u64,
+    #[serde(with = "crate::ospath")]
+    pub root: PathBuf,
+    pub repos: Vec<RepoRun>,
+}
+
+/// Changes and failures in one repo during a run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepoRun {
+    #[serde(with = "crate::ospath")]
+    pub repo: PathBuf,
+    pub success: bool,
+    #[serde(default)]
+    pub changes: Vec<Change>,
+    #[serde(default)]
+    pub failed: Vec<String>,
+}
+
+/// A dependency version change
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+    pub name: String,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+}
+
+impl Run {
+    pub fn change_count(&self) -> usize {
+        self.repos.iter().map(|r| r.changes.len()).sum()
+    }
+
+    pub fn failure_count(&self) -> usize {
+        self.repos.iter().filter(|r| !r.success).count()
+    }
+}
+
+/// Number of most recent runs over `root` in which `repo` failed in a row
+///
+/// A run that did not record the repo means it succeeded without changes.
+pub fn failure_streak(runs: &[Run], root: &Path, repo: &Path) -> usize {
+    runs.iter()
+        .rev()
+        .filter(|run| run.root == root)
+        .take_while(|run| run.repos.iter().any(|r| r.repo == repo && !r.success))
+        .count()
+}
+
+/// The run with ID `id` (ignoring case)
+pub fn find<'a>(runs: &'a [Run], id: &str) -> Option<&'a Run> {
+    runs.iter().find(|run| {
+        run.id
+            .as_deref()
+            .is_some_and(|r| r.eq_ignore_ascii_case(id))
+    })
+}
+
+/// Default journal location
+pub fn journal_path() -> Option<PathBuf> {
+    Some(crate::data_dir()?.join(JOURNAL_FILE))
+}
+
+/// Append a run to the journal, creating it if needed
+pub fn append(path: &Path, run: &Run) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(run)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// All runs in the journal, oldest first; a missing journal is empty
+pub fn read(path: &Path) -> Result<Vec<Run>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid entry on line {} of {}", i + 1, path.display()))
+        })
+        .collect()
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_read() {
+        let path = std::env::temp_dir().join("moon_dst_history_test/history.jsonl");
+        std::fs::remove_file(&path).ok();
+        assert!(read(&path).unwrap().is_empty());
+
+        for started in [1, 2] {
+            let run = Run {
+                id: Some(format!("01J{started}")),
+                started,
+                root: PathBuf::from("/fleet"),
+                repos: vec![RepoRun {
+                    repo: PathBuf::from("/fleet/a"),
+                    success: false,
+                    changes: Vec::new(),
+                    failed: vec!["nope/pkg".to_string()],
+                }],
+            };
+            append(&path, &run).unwrap();
+        }
+        let runs = read(&path).unwrap();
+        assert_eq!(runs.iter().map(|r| r.started).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(runs[1].failure_count(), 1);
+        assert_eq!(find(&runs, "01j2").map(|r| r.started), Some(2));
+        assert!(find(&runs, "01J3").is_none());
+        let fleet = Path::new("/fleet");
+        assert_eq!(failure_streak(&runs, fleet, Path::new("/fleet/a")), 2);
+        assert_eq!(failure_streak(&runs, fleet, Path::new("/fleet/b")), 0);
+
+        std::fs::remove_file(&path).ok();
+    }
+}
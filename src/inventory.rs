@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: MIT
+//! Static HTML inventory of the discovered fleet
+//!
+//! Renders `index.html` with one row per repo, plus `repos/<slug>.html` pages
+//! listing each module's dependencies with declared/latest versions and links
+//! to the repos that provide or consume them.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::process::Command;
+
+use crate::audit::CommandExt;
+use crate::registry::{self, Registry};
+use crate::RepoInfo;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+th,td{border:1px solid #ccc;padding:4px 10px;text-align:left}\
+th{background:#f4f4f4}.outdated{color:#b35c00;font-weight:bold}\
+.ok{color:#2a7a2a}.unknown{color:#888}";
+
+/// Per-repo data collected for rendering
+struct RepoPage<'a> {
+    repo: &'a RepoInfo,
+    slug: String,
+    title: String,
+    last_update: Option<String>,
+    outdated: usize,
+    deps: usize,
+}
+
+/// Write the inventory site into `out_dir`, returning the number of repo pages
+pub fn write_html(
+    repos: &[RepoInfo],
+    root: &Path,
+    registry: &Registry,
+    out_dir: &Path,
+) -> Result<usize> {
+    let pages_dir = out_dir.join("repos");
+    std::fs::create_dir_all(&pages_dir)
+        .with_context(|| format!("Failed to create {}", pages_dir.display()))?;
+
+    let mut pages: Vec<RepoPage> = repos
+        .iter()
+        .map(|repo| {
+            let title = repo_title(&repo.root, root);
+            RepoPage {
+                repo,
+                slug: slugify(&title),
+                title,
+                last_update: last_update_date(&repo.root),
+                outdated: 0,
+                deps: 0,
+            }
+        })
+        .collect();
+
+    // Module name -> providing repo, and repo -> repos depending on its modules
+    let mut providers: BTreeMap<&str, usize> = BTreeMap::new();
+    for (i, page) in pages.iter().enumerate() {
+        for moon_mod in &page.repo.moon_mods {
+            if let Some(name) = &moon_mod.name {
+                providers.insert(name, i);
+            }
+        }
+    }
+    let mut dependents: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); pages.len()];
+    for (i, page) in pages.iter().enumerate() {
+        for dep in page.repo.moon_mods.iter().flat_map(|m| &m.deps) {
+            if let Some(&provider) = providers.get(dep.name.as_str()) {
+                if provider != i {
+                    dependents[provider].insert(i);
+                }
+            }
+        }
+    }
+
+    let titles: Vec<(String, String)> = pages
+        .iter()
+        .map(|p| (p.title.clone(), p.slug.clone()))
+        .collect();
+    for (i, page) in pages.iter_mut().enumerate() {
+        let (html, deps, outdated) =
+            render_repo(page, &titles, &providers, &dependents[i], registry);
+        page.deps = deps;
+        page.outdated = outdated;
+        let path = pages_dir.join(format!("{}.html", page.slug));
+        std::fs::write(&path, html)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    let index = render_index(&pages, root);
+    let index_path = out_dir.join("index.html");
+    std::fs::write(&index_path, index)
+        .with_context(|| format!("Failed to write {}", index_path.display()))?;
+
+    Ok(pages.len())
+}
+
+fn render_repo(
+    page: &RepoPage,
+    titles: &[(String, String)],
+    providers: &BTreeMap<&str, usize>,
+    dependents: &BTreeSet<usize>,
+    registry: &Registry,
+) -> (String, usize, usize) {
+    let mut body = String::new();
+    let mut dep_count = 0;
+    let mut outdated_count = 0;
+
+    let _ = writeln!(
+        body,
+        "<p><a href=\"../index.html\">&larr; Inventory</a></p>"
+    );
+    let _ = writeln!(body, "<h1>{}</h1>", escape(&page.title));
+    let _ = writeln!(
+        body,
+        "<p>Path: <code>{}</code><br>Last update: {}</p>",
+        escape(&crate::ospath::display(&page.repo.root).to_string()),
+        escape(page.last_update.as_deref().unwrap_or("unknown"))
+    );
+
+    for moon_mod in &page.repo.moon_mods {
+        let rel_path = moon_mod
+            .path
+            .strip_prefix(&page.repo.root)
+            .unwrap_or(&moon_mod.path);
+        let _ = writeln!(
+            body,
+            "<h2>{} <small><code>{}</code></small></h2>",
+            escape(moon_mod.name.as_deref().unwrap_or("(unnamed module)")),
+            escape(&rel_path.display().to_string())
+        );
+
+        if moon_mod.deps.is_empty() {
+            let _ = writeln!(body, "<p>No dependencies.</p>");
+            continue;
+        }
+
+        let _ = writeln!(
+            body,
+            "<table>\n<tr><th>Dependency</th><th>Declared</th><th>Latest</th><th>Published</th><th>Status</th></tr>"
+        );
+        for dep in &moon_mod.deps {
+            dep_count += 1;
+            let release = dep
+                .is_registry()
+                .then(|| registry.latest(&dep.name))
+                .flatten();
+            let published = release
+                .as_ref()
+                .and_then(|r| r.created_at.as_deref())
+                .map(|date| date.get(..10).unwrap_or(date).to_string());
+            let latest = release.map(|r| r.version);
+            let (class, status) = match (&dep.version, &latest) {
+                (Some(declared), Some(latest)) if registry::is_outdated(declared, latest) => {
+                    outdated_count += 1;
+                    ("outdated", "outdated")
+                }
+                (Some(_), Some(_)) => ("ok", "up to date"),
+                _ => ("unknown", "unknown"),
+            };
+            let name = match providers.get(dep.name.as_str()) {
+                Some(&i) => format!(
+                    "<a href=\"{}.html\">{}</a>",
+                    escape(&titles[i].1),
+                    escape(&dep.name)
+                ),
+                None => escape(&dep.name),
+            };
+            let _ = writeln!(
+                body,
+                "<tr><td>{name}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"{class}\">{status}</td></tr>",
+                escape(dep.version.as_deref().unwrap_or("-")),
+                escape(latest.as_deref().unwrap_or("-")),
+                escape(published.as_deref().unwrap_or("-")),
+            );
+        }
+        let _ = writeln!(body, "</table>");
+    }
+
+    if !dependents.is_empty() {
+        let _ = writeln!(body, "<h2>Used by</h2>\n<ul>");
+        for &i in dependents {
+            let _ = writeln!(
+                body,
+                "<li><a href=\"{}.html\">{}</a></li>",
+                escape(&titles[i].1),
+                escape(&titles[i].0)
+            );
+        }
+        let _ = writeln!(body, "</ul>");
+    }
+
+    (document(&page.title, &body), dep_count, outdated_count)
+}
+
+fn render_index(pages: &[RepoPage], root: &Path) -> String {
+    let mut body = String::new();
+    let total_deps: usize = pages.iter().map(|p| p.deps).sum();
+    let total_outdated: usize = pages.iter().map(|p| p.outdated).sum();
+
+    let _ = writeln!(body, "<h1>MoonBit Fleet Inventory</h1>");
+    let _ = writeln!(
+        body,
+        "<p>Root: <code>{}</code><br>{} repos, {} dependencies, {} outdated</p>",
+        escape(&crate::ospath::display(root).to_string()),
+        pages.len(),
+        total_deps,
+        total_outdated
+    );
+    let _ = writeln!(
+        body,
+        "<table>\n<tr><th>Repository</th><th>Modules</th><th>Dependencies</th><th>Outdated</th><th>Last update</th></tr>"
+    );
+    for page in pages {
+        let class = if page.outdated > 0 { "outdated" } else { "ok" };
+        let _ = writeln!(
+            body,
+            "<tr><td><a href=\"repos/{}.html\">{}</a></td><td>{}</td><td>{}</td><td class=\"{class}\">{}</td><td>{}</td></tr>",
+            escape(&page.slug),
+            escape(&page.title),
+            page.repo.moon_mods.len(),
+            page.deps,
+            page.outdated,
+            escape(page.last_update.as_deref().unwrap_or("unknown"))
+        );
+    }
+    let _ = writeln!(body, "</table>");
+
+    document("MoonBit Fleet Inventory", &body)
+}
+
+fn document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n{body}</body>\n</html>\n",
+        escape(title)
+    )
+}
+
+/// Repo path relative to the scan root, used as the page title
+fn repo_title(repo_root: &Path, root: &Path) -> String {
+    match repo_root.strip_prefix(root) {
+        Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+        Ok(rel) => rel.display().to_string(),
+        Err(_) => crate::ospath::display(repo_root).to_string(),
+    }
+}
+
+fn slugify(title: &str) -> String {
+    let slug: String = title
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "root".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Date of the last commit in the repo, if it is a git repository
+fn last_update_date(repo_root: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["log", "-1", "--format=%cs"])
+        .current_dir(repo_root)
+        .logged_output()
+        .ok()?;
+    let date = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !date.is_empty()).then_some(date)
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("github.com/org/repo"), "github-com-org-repo");
+        assert_eq!(slugify("."), "root");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: MIT
+//! Hand-edited manifests: comments, trailing commas and other JSON5 syntax
+//!
+//! moon only reads strict JSON, but hand-edited `moon.mod.json` files pick
+//! up `//` and `/* */` comments, trailing commas, single-quoted strings and
+//! unquoted keys. By default such a file is skipped with a diagnostic; with
+//! `--lenient-parse` it is translated to strict JSON before parsing. `lint`
+//! always reads them leniently, flags them (`non-strict-json`) and with
+//! `--fix` writes the strict translation back, keeping the layout.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+/// Read manifests leniently from now on
+pub fn set_lenient() {
+    LENIENT.store(true, Ordering::Relaxed);
+}
+
+pub fn lenient() -> bool {
+    LENIENT.load(Ordering::Relaxed)
+}
+
+/// Strict JSON for lenient `text`; `None` if it is not valid even leniently
+///
+/// Comments are removed along with lines they leave empty; strict JSON
+/// comes back unchanged.
+pub fn to_strict(text: &str) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('"' | '\'', _) => i = copy_string(&chars, i, &mut out)?,
+            ('/', Some('/')) => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                i = end_comment(&chars, i, &mut out);
+            }
+            ('/', Some('*')) => {
+                let end = (i + 2..chars.len().saturating_sub(1))
+                    .find(|&j| chars[j] == '*' && chars[j + 1] == '/')?;
+                i = end_comment(&chars, end + 2, &mut out);
+            }
+            ('}' | ']', _) => {
+                let kept = out.trim_end().len();
+                if out[..kept].ends_with(',') {
+                    out.remove(kept - 1);
+                }
+                out.push(chars[i]);
+                i += 1;
+            }
+            (c, _) if (c.is_alphabetic() || c == '_' || c == '$') && expects_key(&out) => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                // `true` in `[false, true]` is a value
+                let is_key = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&':');
+                if is_key {
+                    out.push_str(&format!("\"{word}\""));
+                } else {
+                    out.push_str(&word);
+                }
+            }
+            (c, _) => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    serde_json::from_str::<serde_json::Value>(&out).ok()?;
+    Some(out)
+}
+
+/// Whether the last significant character written opens a key
+fn expects_key(out: &str) -> bool {
+    matches!(out.trim_end().chars().last(), Some('{' | ','))
+}
+
+/// Copy the string starting at `chars[start]` as a double-quoted string;
+/// returns the index after it
+fn copy_string(chars: &[char], start: usize, out: &mut String) -> Option<usize> {
+    let quote = chars[start];
+    out.push('"');
+    let mut i = start + 1;
+    loop {
+        match *chars.get(i)? {
+            '\\' => {
+                let escaped = *chars.get(i + 1)?;
+                if escaped == '\'' {
+                    out.push('\'');
+                } else {
+                    out.push('\\');
+                    out.push(escaped);
+                }
+                i += 2;
+            }
+            c if c == quote => {
+                out.push('"');
+                return Some(i + 1);
+            }
+            '"' => {
+                out.push_str("\\\"");
+                i += 1;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Continue after a comment ending before `chars[at]`: a line left with
+/// nothing but the comment is dropped, otherwise spaces before it are
+fn end_comment(chars: &[char], at: usize, out: &mut String) -> usize {
+    let mut rest = at;
+    while rest < chars.len() && (chars[rest] == ' ' || chars[rest] == '\t') {
+        rest += 1;
+    }
+    let line_start = out.rfind('\n').map_or(0, |n| n + 1);
+    let blank_before = out[line_start..].trim().is_empty();
+    let blank_after = rest >= chars.len() || chars[rest] == '\n';
+    if blank_before && blank_after {
+        out.truncate(line_start);
+        return (rest + 1).min(chars.len());
+    }
+    let kept = out.trim_end_matches([' ', '\t']).len();
+    out.truncate(kept);
+    if blank_after {
+        rest
+    } else {
+        at
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments_and_trailing_commas_are_dropped() {
+        let lenient = "{\n  // the module\n  \"name\": \"a/b\", /* renamed in 0.2 */\n  \"deps\": {\n    /* pinned\n       for now */\n    \"z\": \"0.2.0\",\n  },\n}\n";
+        assert_eq!(
+            to_strict(lenient).unwrap(),
+            "{\n  \"name\": \"a/b\",\n  \"deps\": {\n    \"z\": \"0.2.0\"\n  }\n}\n"
+        );
+        assert_eq!(
+            to_strict("{\"a\": [true, null,]}").unwrap(),
+            "{\"a\": [true, null]}"
+        );
+    }
+
+    #[test]
+    fn test_single_quotes_and_bare_keys_are_quoted() {
+        assert_eq!(
+            to_strict("{ 'deps': { \"x/y\": 'it\\'s \"0.1\"', z: \"0.2.0\" } }").unwrap(),
+            "{ \"deps\": { \"x/y\": \"it's \\\"0.1\\\"\", \"z\": \"0.2.0\" } }"
+        );
+    }
+
+    #[test]
+    fn test_strict_json_is_unchanged() {
+        let strict = "{ \"name\": \"a/b\", \"url\": \"https://x//y\", \"deps\": {} }";
+        assert_eq!(to_strict(strict).unwrap(), strict);
+    }
+
+    #[test]
+    fn test_invalid_json5_is_rejected() {
+        assert!(to_strict("{ \"name\": }").is_none());
+        assert!(to_strict("{ /* open").is_none());
+        assert!(to_strict("{ \"name\": \"open }").is_none());
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: MIT
+//! JUnit XML reports for CI systems (Jenkins, GitLab)
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::Duration;
+
+/// A single test case in the report
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub classname: String,
+    pub name: String,
+    pub time: Duration,
+    pub failure: Option<String>,
+}
+
+/// Write a report with a single test suite to `path`
+pub fn write(path: &Path, suite: &str, cases: &[TestCase]) -> Result<()> {
+    std::fs::write(path, render(suite, cases))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn render(suite: &str, cases: &[TestCase]) -> String {
+    let failures = cases.iter().filter(|c| c.failure.is_some()).count();
+    let total: f64 = cases.iter().map(|c| c.time.as_secs_f64()).sum();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        out,
+        "<testsuites name=\"moon-dst\" tests=\"{}\" failures=\"{failures}\" time=\"{total:.3}\">",
+        cases.len()
+    );
+    let _ = writeln!(
+        out,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" time=\"{total:.3}\">",
+        escape(suite),
+        cases.len()
+    );
+    for case in cases {
+        let open = format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            escape(&case.classname),
+            escape(&case.name),
+            case.time.as_secs_f64()
+        );
+        match &case.failure {
+            None => {
+                let _ = writeln!(out, "{open}/>");
+            }
+            Some(message) => {
+                let summary = message.lines().next().unwrap_or_default();
+                let _ = writeln!(out, "{open}>");
+                let _ = writeln!(
+                    out,
+                    "      <failure message=\"{}\">{}</failure>",
+                    escape(summary),
+                    escape(message)
+                );
+                let _ = writeln!(out, "    </testcase>");
+            }
+        }
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+/// Escape text for XML content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Control characters other than tab/newline are not valid XML 1.0
+            c if c.is_control() && c != '\n' && c != '\t' && c != '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counts_and_escapes() {
+        let cases = vec![
+            TestCase {
+                classname: "/fleet/a".to_string(),
+                name: ".".to_string(),
+                time: Duration::from_millis(1500),
+                failure: None,
+            },
+            TestCase {
+                classname: "/fleet/b".to_string(),
+                name: "lib".to_string(),
+                time: Duration::from_millis(250),
+                failure: Some("exit code 1: <boom>\ndetails".to_string()),
+            },
+        ];
+        let xml = render("moon-dst test", &cases);
+        assert!(xml.contains("tests=\"2\" failures=\"1\" time=\"1.750\""));
+        assert!(xml.contains("<testcase classname=\"/fleet/a\" name=\".\" time=\"1.500\"/>"));
+        assert!(xml.contains("<failure message=\"exit code 1: &lt;boom&gt;\">"));
+    }
+}
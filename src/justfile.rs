@@ -0,0 +1,536 @@
+// SPDX-License-Identifier: MIT
+//! Justfile parsing, template merging and drift detection
+//!
+//! A justfile is split into top-level items: variable assignments, recipes
+//! (header line plus indented body) and everything else (comments, settings).
+//! Merging only ever adds template items that are missing, so customized
+//! recipes are left untouched.
+//!
+//! [`customize`] narrows the template to chosen recipes and appends extra
+//! ones before it is written or merged. Variable overrides ([`Template`])
+//! are applied to the template and, when merging, to the assignments already
+//! in the justfile.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::errors::{Code, Coded};
+
+/// Kind of a top-level justfile item
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ItemKind {
+    Variable(String),
+    Recipe(String),
+    Other,
+}
+
+/// A top-level item with its source text (without trailing newline)
+#[derive(Debug, Clone)]
+struct Item {
+    kind: ItemKind,
+    text: String,
+}
+
+impl Item {
+    fn name(&self) -> Option<&str> {
+        match &self.kind {
+            ItemKind::Variable(name) | ItemKind::Recipe(name) => Some(name),
+            ItemKind::Other => None,
+        }
+    }
+
+    /// Text with trailing whitespace removed from each line, for comparison
+    fn normalized(&self) -> String {
+        self.text
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn parse(content: &str) -> Vec<Item> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut items = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let kind = classify(line);
+        let mut end = i + 1;
+
+        if matches!(kind, ItemKind::Recipe(_)) {
+            // Body: indented lines, allowing blank lines between them
+            let mut j = end;
+            while j < lines.len() {
+                if is_indented(lines[j]) {
+                    j += 1;
+                    end = j;
+                } else if lines[j].trim().is_empty() {
+                    j += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        items.push(Item {
+            kind,
+            text: lines[i..end].join("\n"),
+        });
+        i = end;
+    }
+    items
+}
+
+fn classify(line: &str) -> ItemKind {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') || is_indented(line) {
+        return ItemKind::Other;
+    }
+    let first_word = trimmed.split_whitespace().next().unwrap_or_default();
+    if matches!(
+        first_word,
+        "set" | "alias" | "import" | "mod" | "export" | "import?" | "mod?"
+    ) {
+        return ItemKind::Other;
+    }
+
+    if let Some((name, _)) = trimmed.split_once(":=") {
+        return ItemKind::Variable(name.trim().to_string());
+    }
+    if trimmed.contains(':') {
+        let name = trimmed
+            .split(|c: char| c == ':' || c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches('@');
+        if !name.is_empty() {
+            return ItemKind::Recipe(name.to_string());
+        }
+    }
+    ItemKind::Other
+}
+
+fn is_indented(line: &str) -> bool {
+    line.starts_with([' ', '\t']) && !line.trim().is_empty()
+}
+
+/// Names listed after the colon of a recipe header
+fn recipe_dependencies(header: &str) -> Vec<&str> {
+    let Some((_, deps)) = header.split_once(':') else {
+        return Vec::new();
+    };
+    deps.split('#')
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|dep| dep.trim_start_matches('@'))
+        .collect()
+}
+
+/// A recipe reduced to what other task runners can express
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipe {
+    pub name: String,
+    pub deps: Vec<String>,
+    /// Body lines, unindented; `{{name}}` interpolations are kept
+    pub commands: Vec<String>,
+}
+
+/// Leading comment lines of a justfile
+pub fn header_comments(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .take_while(|line| line.trim_start().starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Variable assignments in order, with string values unquoted
+pub fn variables(content: &str) -> Vec<(String, String)> {
+    parse(content)
+        .into_iter()
+        .filter_map(|item| {
+            let ItemKind::Variable(name) = item.kind else {
+                return None;
+            };
+            let (_, value) = item.text.split_once(":=")?;
+            let value = value.trim();
+            let value = (value.strip_prefix('"').and_then(|v| v.strip_suffix('"')))
+                .map(|v| v.replace("\\\"", "\"").replace("\\\\", "\\"))
+                .unwrap_or_else(|| value.to_string());
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Recipes in order
+pub fn recipes(content: &str) -> Vec<Recipe> {
+    parse(content)
+        .into_iter()
+        .filter_map(|item| {
+            let ItemKind::Recipe(name) = &item.kind else {
+                return None;
+            };
+            let mut lines = item.text.lines();
+            let header = lines.next().unwrap_or_default();
+            Some(Recipe {
+                name: name.clone(),
+                deps: recipe_dependencies(header)
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+                commands: lines
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Parse an `--extra-recipe` value, `name:command`
+pub fn parse_extra_recipe(spec: &str) -> Result<(String, String)> {
+    let Some((name, command)) = spec.split_once(':') else {
+        bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid extra recipe `{spec}` (expected name:command)")
+        ));
+    };
+    let name = name.trim();
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) || command.trim().is_empty() {
+        bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid extra recipe `{spec}` (expected name:command)")
+        ));
+    }
+    Ok((name.to_string(), command.trim().to_string()))
+}
+
+/// The template with only the recipes in `recipes` (all if empty) and the
+/// recipes they depend on, followed by `extra` recipes (replacing template
+/// recipes of the same name)
+pub fn customize(template: &str, recipes: &[String], extra: &[(String, String)]) -> Result<String> {
+    let items = parse(template);
+    let recipe = |name: &str| {
+        items
+            .iter()
+            .find(|item| item.kind == ItemKind::Recipe(name.to_string()))
+    };
+
+    let mut keep: BTreeSet<&str> = BTreeSet::new();
+    if recipes.is_empty() {
+        keep.extend(items.iter().filter_map(|item| match &item.kind {
+            ItemKind::Recipe(name) => Some(name.as_str()),
+            _ => None,
+        }));
+    } else {
+        let mut pending: Vec<&str> = Vec::new();
+        for name in recipes {
+            if recipe(name).is_none() {
+                bail!(Coded::new(
+                    Code::InvalidValue,
+                    format!("Unknown justfile recipe `{name}`")
+                ));
+            }
+            pending.push(name);
+        }
+        while let Some(name) = pending.pop() {
+            if let Some(item) = recipe(name).filter(|_| keep.insert(name)) {
+                let header = item.text.lines().next().unwrap_or_default();
+                pending.extend(recipe_dependencies(header));
+            }
+        }
+    }
+    for (name, _) in extra {
+        keep.remove(name.as_str());
+    }
+
+    let mut lines: Vec<&str> = Vec::new();
+    for item in &items {
+        if let ItemKind::Recipe(name) = &item.kind {
+            if !keep.contains(name.as_str()) {
+                continue;
+            }
+        }
+        for line in item.text.split('\n') {
+            // Dropped recipes leave runs of blank lines behind
+            if line.trim().is_empty() && lines.last().is_none_or(|l| l.trim().is_empty()) {
+                continue;
+            }
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    for (name, command) in extra {
+        out.push_str(&format!("\n{name}:\n    {command}\n"));
+    }
+    Ok(out)
+}
+
+/// Parse a `--set` value, `name=value`
+pub fn parse_assignment(spec: &str) -> Result<(String, String)> {
+    match spec.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid variable `{spec}` (expected name=value)")
+        )),
+    }
+}
+
+/// Set each variable in `vars` to a string value: existing assignments are
+/// rewritten, missing ones are inserted after the last assignment (or before
+/// the first recipe)
+pub fn set_variables(content: &str, vars: &BTreeMap<String, String>) -> String {
+    if vars.is_empty() {
+        return content.to_string();
+    }
+    let assignment = |name: &str, value: &str| {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+        format!("{name} := \"{escaped}\"")
+    };
+    let items = parse(content);
+    let mut missing: Vec<String> = vars
+        .iter()
+        .filter(|(name, _)| {
+            !items
+                .iter()
+                .any(|item| item.kind == ItemKind::Variable(name.to_string()))
+        })
+        .map(|(name, value)| assignment(name, value))
+        .collect();
+
+    let last_variable = items
+        .iter()
+        .rposition(|item| matches!(item.kind, ItemKind::Variable(_)));
+
+    let mut out = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        if last_variable.is_none()
+            && !missing.is_empty()
+            && matches!(item.kind, ItemKind::Recipe(_))
+        {
+            out.append(&mut missing);
+            out.push(String::new());
+        }
+        match &item.kind {
+            ItemKind::Variable(name) if vars.contains_key(name) => {
+                out.push(assignment(name, &vars[name]))
+            }
+            _ => out.push(item.text.clone()),
+        }
+        if last_variable == Some(i) {
+            out.append(&mut missing);
+        }
+    }
+    out.append(&mut missing);
+    let mut updated = out.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// A `[[just.repos]]` entry: repos matching `path` get the variables in `set`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoVariables {
+    /// Glob matched against the repo path relative to the root
+    pub path: String,
+    pub set: BTreeMap<String, String>,
+}
+
+/// The justfile template of a run and the variables to override per repo
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+    pub text: String,
+    /// `[just] set`
+    pub set: BTreeMap<String, String>,
+    /// `[[just.repos]]`
+    pub repos: Vec<RepoVariables>,
+    /// `--set`, over everything else
+    pub overrides: BTreeMap<String, String>,
+}
+
+impl Template {
+    /// Variables to override in the repo at `rel` (relative to the root):
+    /// `set`, then each matching repo entry in order, then `overrides`
+    pub fn variables(&self, rel: &Path) -> BTreeMap<String, String> {
+        let mut vars = self.set.clone();
+        for entry in &self.repos {
+            let matches = globset::Glob::new(&entry.path)
+                .is_ok_and(|glob| glob.compile_matcher().is_match(rel));
+            if matches {
+                vars.extend(entry.set.clone());
+            }
+        }
+        vars.extend(self.overrides.clone());
+        vars
+    }
+}
+
+/// How an existing justfile differs from the template
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Drift {
+    /// Template variables/recipes absent from the justfile
+    pub missing: Vec<String>,
+    /// Template variables/recipes present with different content
+    pub changed: Vec<String>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare an existing justfile against the template
+pub fn drift(existing: &str, template: &str) -> Drift {
+    let existing_items = parse(existing);
+    let mut drift = Drift::default();
+
+    for item in parse(template) {
+        let Some(name) = item.name() else {
+            continue;
+        };
+        match existing_items.iter().find(|e| e.kind == item.kind) {
+            None => drift.missing.push(name.to_string()),
+            Some(found) if found.normalized() != item.normalized() => {
+                drift.changed.push(name.to_string())
+            }
+            Some(_) => {}
+        }
+    }
+    drift
+}
+
+/// Add template variables and recipes missing from the existing justfile
+///
+/// Missing variables are inserted before the first recipe; missing recipes
+/// are appended at the end. Existing items are never modified.
+pub fn merge(existing: &str, template: &str) -> String {
+    let existing_items = parse(existing);
+    let template_items = parse(template);
+    let is_missing =
+        |item: &&Item| item.name().is_some() && !existing_items.iter().any(|e| e.kind == item.kind);
+
+    let missing_vars: Vec<&Item> = template_items
+        .iter()
+        .filter(|i| matches!(i.kind, ItemKind::Variable(_)))
+        .filter(is_missing)
+        .collect();
+    let missing_recipes: Vec<&Item> = template_items
+        .iter()
+        .filter(|i| matches!(i.kind, ItemKind::Recipe(_)))
+        .filter(is_missing)
+        .collect();
+
+    if missing_vars.is_empty() && missing_recipes.is_empty() {
+        return existing.to_string();
+    }
+
+    let mut out = Vec::new();
+    let mut vars_inserted = missing_vars.is_empty();
+    for item in &existing_items {
+        if !vars_inserted && matches!(item.kind, ItemKind::Recipe(_)) {
+            out.extend(missing_vars.iter().map(|v| v.text.clone()));
+            out.push(String::new());
+            vars_inserted = true;
+        }
+        out.push(item.text.clone());
+    }
+    if !vars_inserted {
+        out.extend(missing_vars.iter().map(|v| v.text.clone()));
+    }
+
+    for recipe in missing_recipes {
+        if out.last().is_some_and(|l| !l.trim().is_empty()) {
+            out.push(String::new());
+        }
+        out.push(recipe.text.clone());
+    }
+
+    let mut merged = out.join("\n");
+    merged.push('\n');
+    merged
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "target := \"js\"\n\ndefault: check\n\ncheck:\n    moon check --target {{target}}\n\nfmt:\n    moon fmt\n";
+
+    #[test]
+    fn test_drift_detects_missing_and_changed() {
+        let existing = "default: check\n\ncheck:\n    moon check\n";
+        let drift = drift(existing, TEMPLATE);
+        assert_eq!(drift.missing, vec!["target", "fmt"]);
+        assert_eq!(drift.changed, vec!["check"]);
+        assert!(super::drift(TEMPLATE, TEMPLATE).is_empty());
+    }
+
+    #[test]
+    fn test_merge_adds_missing_only() {
+        let existing =
+            "# custom\ndefault: check\n\ncheck:\n    moon check\n\ndeploy:\n    ./deploy.sh\n";
+        let merged = merge(existing, TEMPLATE);
+        assert_eq!(
+            merged,
+            "# custom\ntarget := \"js\"\n\ndefault: check\n\ncheck:\n    moon check\n\ndeploy:\n    ./deploy.sh\n\nfmt:\n    moon fmt\n"
+        );
+        assert_eq!(merge(&merged, TEMPLATE), merged);
+    }
+
+    #[test]
+    fn test_customize_selects_recipes() {
+        let extra = [parse_extra_recipe("docs: moon doc").unwrap()];
+        let custom = customize(TEMPLATE, &["default".to_string()], &extra).unwrap();
+        assert_eq!(
+            custom,
+            "target := \"js\"\n\ndefault: check\n\ncheck:\n    moon check --target {{target}}\n\ndocs:\n    moon doc\n"
+        );
+        assert_eq!(customize(TEMPLATE, &[], &[]).unwrap(), TEMPLATE);
+        assert!(customize(TEMPLATE, &["deploy".to_string()], &[]).is_err());
+        assert!(parse_extra_recipe("no command").is_err());
+    }
+
+    #[test]
+    fn test_set_variables_per_repo() {
+        let template = Template {
+            text: TEMPLATE.to_string(),
+            set: [("target".to_string(), "native".to_string())].into(),
+            repos: vec![RepoVariables {
+                path: "wasm/*".to_string(),
+                set: [("target".to_string(), "wasm-gc".to_string())].into(),
+            }],
+            overrides: [("mode".to_string(), "release".to_string())].into(),
+        };
+        let vars = template.variables(Path::new("wasm/app"));
+        assert_eq!(vars["target"], "wasm-gc");
+        assert_eq!(template.variables(Path::new("cli"))["target"], "native");
+
+        let existing = "# custom\ntarget := \"js\"\n\ncheck:\n    moon check\n";
+        assert_eq!(
+            set_variables(existing, &vars),
+            "# custom\ntarget := \"wasm-gc\"\nmode := \"release\"\n\ncheck:\n    moon check\n"
+        );
+        assert_eq!(parse_assignment("target=wasm-gc").unwrap().1, "wasm-gc");
+        assert!(parse_assignment("=x").is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: MIT
+//! Sanity checks of where manifests sit
+//!
+//! Two layouts make grouped results confusing: a module nested in another
+//! module's directory (a vendored copy, an example or a fixture inside a
+//! library), and several manifests claiming the same module name. Both are
+//! reported by `scan` and `lint`. How nested modules are discovered is set
+//! with `--nested-modules` (`nested-modules` in the config): grouped with
+//! their repo as before, split into units of their own, or skipped.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::{ssh, RepoInfo};
+
+/// What discovery does with modules inside another module's directory
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NestedModules {
+    /// Keep them in their repo with the enclosing module
+    #[default]
+    Group,
+    /// Make each one a unit of its own, rooted at its directory
+    Separate,
+    /// Leave them out
+    Skip,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IssueKind {
+    NestedModule,
+    DuplicateName,
+}
+
+/// A layout problem with the manifests involved
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// Nested module first, then its parent; or every claimant of the name
+    #[serde(serialize_with = "serialize_paths")]
+    pub manifests: Vec<PathBuf>,
+    pub message: String,
+}
+
+fn serialize_paths<S: serde::Serializer>(
+    paths: &[PathBuf],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|p| crate::ospath::encode(p)))
+}
+
+/// For each manifest inside the directory of another, the manifest of the
+/// closest enclosing module
+pub fn parents<'a>(manifests: impl IntoIterator<Item = &'a Path>) -> BTreeMap<PathBuf, PathBuf> {
+    let manifests: Vec<&Path> = manifests.into_iter().collect();
+    let dirs: BTreeMap<&Path, &Path> = manifests
+        .iter()
+        .filter_map(|m| Some((m.parent()?, *m)))
+        .collect();
+    manifests
+        .iter()
+        .filter_map(|manifest| {
+            let parent = manifest
+                .parent()?
+                .ancestors()
+                .skip(1)
+                .find_map(|dir| dirs.get(dir))?;
+            Some((manifest.to_path_buf(), parent.to_path_buf()))
+        })
+        .collect()
+}
+
+/// Duplicate module names across `repos`, and nested modules unless
+/// discovery already split or skipped them
+pub fn check(repos: &[RepoInfo], mode: NestedModules) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    // Only modules grouped into one repo; a nested repo (a submodule, say)
+    // is a unit of its own already
+    for repo in repos.iter().filter(|_| mode == NestedModules::Group) {
+        for (child, parent) in parents(repo.moon_mods.iter().map(|m| m.path.as_path())) {
+            issues.push(Issue {
+                kind: IssueKind::NestedModule,
+                message: format!(
+                    "{} is nested inside the module of {}; use --nested-modules separate or skip, or --ignore its directory",
+                    crate::ospath::display(&child),
+                    crate::ospath::display(&parent)
+                ),
+                manifests: vec![child, parent],
+            });
+        }
+    }
+
+    // Manifest paths with where they are shown, host included
+    let mut by_name: BTreeMap<&str, Vec<(PathBuf, String)>> = BTreeMap::new();
+    for repo in repos {
+        for moon_mod in &repo.moon_mods {
+            if let Some(name) = &moon_mod.name {
+                let rel = moon_mod
+                    .path
+                    .strip_prefix(&repo.root)
+                    .unwrap_or(&moon_mod.path);
+                let shown = crate::ospath::encode(&repo.location_path().join(rel));
+                by_name
+                    .entry(name)
+                    .or_default()
+                    .push((moon_mod.path.clone(), shown));
+            }
+        }
+    }
+    for (name, claimants) in by_name {
+        if claimants.len() > 1 {
+            let shown: Vec<&str> = claimants.iter().map(|(_, s)| s.as_str()).collect();
+            issues.push(Issue {
+                kind: IssueKind::DuplicateName,
+                message: format!(
+                    "module name {name} is declared by {} manifests ({}); rename one or --ignore the copies",
+                    claimants.len(),
+                    shown.join(", ")
+                ),
+                manifests: claimants.into_iter().map(|(path, _)| path).collect(),
+            });
+        }
+    }
+    issues
+}
+
+/// `repo` with its nested modules handled as `mode` says
+pub fn split(repo: RepoInfo, mode: NestedModules, hosts: &mut ssh::Hosts) -> Vec<RepoInfo> {
+    if mode == NestedModules::Group {
+        return vec![repo];
+    }
+    let nested = parents(repo.moon_mods.iter().map(|m| m.path.as_path()));
+    if nested.is_empty() {
+        return vec![repo];
+    }
+    let (inner, outer): (Vec<_>, Vec<_>) = repo
+        .moon_mods
+        .iter()
+        .cloned()
+        .partition(|m| nested.contains_key(&m.path));
+    let mut units = Vec::new();
+    if mode == NestedModules::Separate {
+        for moon_mod in inner {
+            let mut unit = repo.clone();
+            unit.root = moon_mod.path.parent().unwrap_or(&repo.root).to_path_buf();
+            unit.toolchain = moon_mod.moon_version.clone().or(repo.toolchain.clone());
+            unit.runner = unit.runner_spec.build(&unit.root, hosts);
+            unit.moon_mods = vec![moon_mod];
+            units.push(unit);
+        }
+    }
+    let mut outer_repo = repo;
+    outer_repo.moon_mods = outer;
+    units.insert(0, outer_repo);
+    units
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parents_finds_closest_enclosing_module() {
+        let manifests = [
+            Path::new("/r/moon.mod.json"),
+            Path::new("/r/examples/demo/moon.mod.json"),
+            Path::new("/r/examples/demo/vendor/x/moon.mod.json"),
+            Path::new("/other/moon.mod.json"),
+            Path::new("/r2/moon.mod.json"),
+        ];
+        let found = parents(manifests);
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            found[Path::new("/r/examples/demo/moon.mod.json")],
+            Path::new("/r/moon.mod.json")
+        );
+        assert_eq!(
+            found[Path::new("/r/examples/demo/vendor/x/moon.mod.json")],
+            Path::new("/r/examples/demo/moon.mod.json")
+        );
+    }
+}
@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: MIT
+//! Resource limits for moon processes
+//!
+//! `--nice`, `--cpu-limit` and `--memory-limit` (or `[limits]` in config)
+//! wrap every moon process started on this machine:
+//!
+//! - niceness with `nice -n`
+//! - CPU and memory in a transient cgroup with `systemd-run --user --scope`
+//! - without systemd, memory as an address-space limit with `prlimit --as`
+//!
+//! Containers started by `docker-run:`/`podman-run:` runners get `--cpus`
+//! and `--memory` instead. Limits this machine cannot enforce are reported
+//! once and ignored.
+
+use anyhow::{bail, Result};
+use std::ffi::OsStr;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+
+/// Limits for each moon process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Niceness, 0 (normal) to 19 (lowest priority)
+    pub nice: Option<u8>,
+    /// CPU time in percent of one core (200 = two cores)
+    pub cpu_percent: Option<u32>,
+    /// Memory in bytes
+    pub memory: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// `docker run` / `podman run` flags for these limits
+    pub fn container_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(percent) = self.cpu_percent {
+            args.push(format!("--cpus={}", f64::from(percent) / 100.0));
+        }
+        if let Some(bytes) = self.memory {
+            args.push(format!("--memory={bytes}"));
+        }
+        args
+    }
+}
+
+/// Parse a size such as `512M`, `4G` or `1048576` (bytes; K, M, G and T
+/// are powers of 1024)
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => text.split_at(at),
+        None => (text, ""),
+    };
+    let shift = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid size `{text}` (expected e.g. 512M or 4G)")
+        )),
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => n
+            .checked_mul(1 << shift)
+            .ok_or_else(|| anyhow::anyhow!("size `{text}` is too large")),
+        _ => bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid size `{text}` (expected e.g. 512M or 4G)")
+        )),
+    }
+}
+
+/// Tools available to enforce limits
+#[derive(Debug, Clone, Copy, Default)]
+struct Tools {
+    nice: bool,
+    systemd_run: bool,
+    prlimit: bool,
+}
+
+impl Tools {
+    fn detect(limits: &Limits) -> Self {
+        let works = |program: &str, args: &[&str]| {
+            Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .logged_status()
+                .is_ok_and(|status| status.success())
+        };
+        let cgroup = limits.cpu_percent.is_some() || limits.memory.is_some();
+        Tools {
+            nice: limits.nice.is_some() && works("nice", &["true"]),
+            systemd_run: cgroup && works("systemd-run", &["--user", "--scope", "--quiet", "true"]),
+            prlimit: limits.memory.is_some() && works("prlimit", &["--version"]),
+        }
+    }
+}
+
+/// Command prefix enforcing `limits` with `tools`, and warnings for the
+/// limits left unenforced
+fn wrapper(limits: &Limits, tools: Tools) -> (Vec<String>, Vec<String>) {
+    let mut prefix = Vec::new();
+    let mut warnings = Vec::new();
+
+    if tools.systemd_run {
+        prefix
+            .extend(["systemd-run", "--user", "--scope", "--quiet", "--collect"].map(String::from));
+        if let Some(percent) = limits.cpu_percent {
+            prefix.extend(["-p".to_string(), format!("CPUQuota={percent}%")]);
+        }
+        if let Some(bytes) = limits.memory {
+            prefix.extend(["-p".to_string(), format!("MemoryMax={bytes}")]);
+        }
+        prefix.push("--".to_string());
+    } else {
+        if let Some(bytes) = limits.memory {
+            if tools.prlimit {
+                prefix.extend([
+                    "prlimit".to_string(),
+                    format!("--as={bytes}"),
+                    "--".to_string(),
+                ]);
+            } else {
+                warnings.push("the memory limit needs systemd-run or prlimit; ignored".to_string());
+            }
+        }
+        if limits.cpu_percent.is_some() {
+            warnings.push("the CPU limit needs systemd-run (cgroups); ignored".to_string());
+        }
+    }
+
+    if let Some(nice) = limits.nice {
+        if tools.nice {
+            prefix.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        } else {
+            warnings.push("niceness needs the nice command; ignored".to_string());
+        }
+    }
+    (prefix, warnings)
+}
+
+static LIMITS: OnceLock<(Limits, Vec<String>)> = OnceLock::new();
+
+/// Set the limits for this process's moon commands; later calls are ignored
+pub fn init(limits: Limits) {
+    LIMITS.get_or_init(|| {
+        if limits.is_empty() {
+            return (limits, Vec::new());
+        }
+        let (prefix, warnings) = wrapper(&limits, Tools::detect(&limits));
+        for warning in warnings {
+            crate::diagnostics::report(crate::diagnostics::Code::Unsupported, warning);
+        }
+        (limits, prefix)
+    });
+}
+
+/// Limits set by [`init`]
+pub fn current() -> Limits {
+    LIMITS.get().map(|(limits, _)| *limits).unwrap_or_default()
+}
+
+/// A command running `program` under the limits set by [`init`]
+pub fn command(program: &OsStr) -> Command {
+    match LIMITS.get().map(|(_, prefix)| prefix.as_slice()) {
+        Some([wrapper, args @ ..]) => {
+            let mut command = Command::new(wrapper);
+            command.args(args).arg(program);
+            command
+        }
+        _ => Command::new(program),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        nice: Some(10),
+        cpu_percent: Some(150),
+        memory: Some(1 << 30),
+    };
+
+    const ALL_TOOLS: Tools = Tools {
+        nice: true,
+        systemd_run: true,
+        prlimit: true,
+    };
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("4g").unwrap(), 4 << 30);
+        assert_eq!(parse_size("2GB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+    }
+
+    #[test]
+    fn test_parse_size_rejects_zero_and_unknown_units() {
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("4X").is_err());
+    }
+
+    #[test]
+    fn test_wrap_in_systemd_scope() {
+        let (prefix, warnings) = wrapper(&LIMITS, ALL_TOOLS);
+        assert_eq!(
+            prefix.join(" "),
+            "systemd-run --user --scope --quiet --collect -p CPUQuota=150% -p MemoryMax=1073741824 -- nice -n 10"
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_fall_back_to_prlimit_without_systemd() {
+        let tools = Tools {
+            systemd_run: false,
+            ..ALL_TOOLS
+        };
+        let (prefix, warnings) = wrapper(&LIMITS, tools);
+        assert_eq!(prefix.join(" "), "prlimit --as=1073741824 -- nice -n 10");
+        // The CPU quota has no fallback
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_container_args() {
+        assert_eq!(
+            LIMITS.container_args(),
+            ["--cpus=1.5", "--memory=1073741824"]
+        );
+    }
+}
@@ -0,0 +1,313 @@
+// SPDX-License-Identifier: MIT
+//! Manifest lint rules
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+use crate::findings::{self, Finding, Level, Rule};
+use crate::jsonc;
+use crate::layout::{self, IssueKind, NestedModules};
+use crate::registry::{self, Registry};
+use crate::RepoInfo;
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "L001",
+        name: "missing-module-name",
+        description: "moon.mod.json does not declare a module name",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "L002",
+        name: "outdated-dependency",
+        description: "Dependency is behind the latest registry version",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "L003",
+        name: "unknown-package",
+        description: "Dependency is neither in the registry index nor in the scanned repos",
+        level: Level::Error,
+    },
+    Rule {
+        id: "L004",
+        name: "yanked-version",
+        description: "Dependency is pinned to a version yanked from the registry",
+        level: Level::Error,
+    },
+    Rule {
+        id: "L005",
+        name: "deprecated-version",
+        description: "Dependency is pinned to a deprecated version",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "L006",
+        name: "nested-module",
+        description: "moon.mod.json sits inside the directory of another module",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "L007",
+        name: "duplicate-module-name",
+        description: "Several manifests declare the same module name",
+        level: Level::Warning,
+    },
+    Rule {
+        id: "L008",
+        name: "non-strict-json",
+        description: "moon.mod.json has comments, trailing commas or other JSON5 syntax that moon cannot read (fixable with --fix)",
+        level: Level::Error,
+    },
+];
+
+/// Run all lint rules over the discovered repos
+pub fn lint(repos: &[RepoInfo], registry: &Registry, nested: NestedModules) -> Vec<Finding> {
+    let have_index = registry.index_dir().exists();
+    let local_modules: HashSet<&str> = repos
+        .iter()
+        .flat_map(|r| &r.moon_mods)
+        .filter_map(|m| m.name.as_deref())
+        .collect();
+
+    let mut findings = Vec::new();
+    for moon_mod in repos.iter().flat_map(|r| &r.moon_mods) {
+        let content = std::fs::read_to_string(&moon_mod.path).unwrap_or_default();
+        let finding = |rule_id: &'static str, message: String, line: Option<usize>| Finding {
+            rule_id,
+            level: rule_level(rule_id),
+            message,
+            path: moon_mod.path.clone(),
+            line,
+        };
+
+        let text = content.strip_prefix('\u{feff}').unwrap_or(&content);
+        if let Err(e) = serde_json::from_str::<serde_json::Value>(text) {
+            findings.push(finding(
+                "L008",
+                format!("not strict JSON: {e}"),
+                Some(e.line().max(1)),
+            ));
+        }
+
+        if moon_mod.name.is_none() {
+            findings.push(finding(
+                "L001",
+                "module has no \"name\" field".to_string(),
+                Some(1),
+            ));
+        }
+
+        // Path and git dependencies are not versioned by the registry
+        for dep in moon_mod.deps.iter().filter(|d| d.is_registry()) {
+            let line = findings::find_line(&content, &format!("\"{}\"", dep.name));
+            let latest = registry.latest(&dep.name);
+
+            if let (Some(declared), Some(latest)) = (&dep.version, &latest) {
+                if registry::is_outdated(declared, &latest.version) {
+                    findings.push(finding(
+                        "L002",
+                        format!(
+                            "{} {declared} is outdated (latest: {})",
+                            dep.name, latest.version
+                        ),
+                        line,
+                    ));
+                }
+            }
+
+            if let Some(declared) = &dep.version {
+                match registry.flag(&dep.name, declared) {
+                    Some(registry::VersionFlag::Yanked) => findings.push(finding(
+                        "L004",
+                        format!("{} {declared} has been yanked", dep.name),
+                        line,
+                    )),
+                    Some(flag @ registry::VersionFlag::Deprecated(_)) => findings.push(finding(
+                        "L005",
+                        format!("{} {declared} is {flag}", dep.name),
+                        line,
+                    )),
+                    None => {}
+                }
+            }
+
+            if have_index
+                && dep.version.is_some()
+                && latest.is_none()
+                && !local_modules.contains(dep.name.as_str())
+            {
+                findings.push(finding(
+                    "L003",
+                    format!("{} was not found in the registry index", dep.name),
+                    line,
+                ));
+            }
+        }
+    }
+
+    for issue in layout::check(repos, nested) {
+        let (rule_id, manifests) = match issue.kind {
+            IssueKind::NestedModule => ("L006", &issue.manifests[..1]),
+            IssueKind::DuplicateName => ("L007", &issue.manifests[..]),
+        };
+        for path in manifests {
+            let content = std::fs::read_to_string(path).unwrap_or_default();
+            findings.push(Finding {
+                rule_id,
+                level: rule_level(rule_id),
+                message: issue.message.clone(),
+                path: path.clone(),
+                line: findings::find_line(&content, "\"name\"").or(Some(1)),
+            });
+        }
+    }
+    findings
+}
+
+/// The `--fix` for an L008 finding: the manifest's content and the same
+/// manifest as strict JSON, or `None` when it cannot be converted
+pub fn fix(finding: &Finding) -> Result<Option<(String, String)>> {
+    let content = std::fs::read_to_string(&finding.path)
+        .with_context(|| format!("Failed to read {}", finding.path.display()))?;
+    Ok(jsonc::to_strict(&content).map(|strict| (content, strict)))
+}
+
+fn rule_level(rule_id: &str) -> Level {
+    RULES
+        .iter()
+        .find(|r| r.id == rule_id)
+        .map(|r| r.level)
+        .unwrap_or(Level::Warning)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_moon_mod, MoonModInfo};
+    use std::path::Path;
+
+    fn module(dir: &Path, rel: &str, content: &str) -> MoonModInfo {
+        let manifest = dir.join(rel).join("moon.mod.json");
+        std::fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+        std::fs::write(&manifest, content).unwrap();
+        parse_moon_mod(&manifest).unwrap()
+    }
+
+    /// `(rule, manifest directory, line)` of each finding
+    fn found(findings: &[Finding], dir: &Path) -> Vec<(&'static str, String, Option<usize>)> {
+        let mut found: Vec<_> = findings
+            .iter()
+            .map(|f| {
+                let rel = f.path.parent().unwrap().strip_prefix(dir).unwrap();
+                (f.rule_id, rel.display().to_string(), f.line)
+            })
+            .collect();
+        found.sort();
+        found
+    }
+
+    /// An index with an outdated, a yanked and a deprecated package
+    fn registry(dir: &Path) -> Registry {
+        let index = dir.join("index/user/acme");
+        std::fs::create_dir_all(&index).unwrap();
+        for (package, versions) in [
+            (
+                "old",
+                r#""version":"1.0.0"}"#.to_string() + "\n" + r#"{"version":"2.0.0"}"#,
+            ),
+            (
+                "gone",
+                r#""version":"0.1.0","yanked":true}"#.to_string() + "\n" + r#"{"version":"0.1.1"}"#,
+            ),
+            (
+                "dep",
+                r#""version":"1.0.0","deprecated":"use acme/new"}"#.to_string(),
+            ),
+        ] {
+            std::fs::write(
+                index.join(format!("{package}.index")),
+                format!("{{{versions}\n"),
+            )
+            .unwrap();
+        }
+        Registry::with_index_dir(dir.join("index"))
+    }
+
+    #[test]
+    fn test_lint_rules() {
+        let dir = std::env::temp_dir().join("moon_dst_lint_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let registry = registry(&dir);
+
+        let app = module(
+            &dir,
+            "app",
+            concat!(
+                "{\n",
+                "  \"name\": \"me/app\",\n",
+                "  \"deps\": {\n",
+                "    \"acme/old\": \"1.0.0\",\n",
+                "    \"acme/gone\": \"0.1.0\",\n",
+                "    \"acme/dep\": \"1.0.0\",\n",
+                "    \"acme/nowhere\": \"1.0.0\",\n",
+                "    \"me/tool\": { \"path\": \"../tool\" }\n",
+                "  }\n",
+                "}\n",
+            ),
+        );
+        let tool = module(&dir, "tool", "{\n  \"name\": \"me/app\"\n}\n");
+        let unnamed = module(&dir, "unnamed", "{}");
+        let repos = vec![
+            RepoInfo::local(dir.join("app"), vec![app]),
+            RepoInfo::local(dir.join("tool"), vec![tool]),
+            RepoInfo::local(dir.join("unnamed"), vec![unnamed]),
+        ];
+
+        let findings = lint(&repos, &registry, NestedModules::default());
+        assert_eq!(
+            found(&findings, &dir),
+            [
+                ("L001", "unnamed".to_string(), Some(1)),
+                ("L002", "app".to_string(), Some(4)),
+                ("L002", "app".to_string(), Some(5)),
+                ("L003", "app".to_string(), Some(7)),
+                ("L004", "app".to_string(), Some(5)),
+                ("L005", "app".to_string(), Some(6)),
+                ("L007", "app".to_string(), Some(2)),
+                ("L007", "tool".to_string(), Some(2)),
+            ]
+        );
+        assert!(findings.iter().all(|f| f.level == rule_level(f.rule_id)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fix_lenient_manifest() {
+        let dir = std::env::temp_dir().join("moon_dst_lint_fix_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let registry = registry(&dir);
+        let module = module(&dir, "app", "{ \"name\": \"me/app\" }");
+        let repos = vec![RepoInfo::local(dir.join("app"), vec![module])];
+
+        // A manifest moon cannot read is fixed into strict JSON
+        std::fs::write(
+            dir.join("app/moon.mod.json"),
+            "{\n  // no deps yet\n  \"name\": \"me/app\",\n}\n",
+        )
+        .unwrap();
+        let findings = lint(&repos, &registry, NestedModules::default());
+        let strict_json = findings.iter().find(|f| f.rule_id == "L008").unwrap();
+        assert_eq!(strict_json.line, Some(2));
+        let (_, strict) = fix(strict_json).unwrap().unwrap();
+        std::fs::write(&strict_json.path, strict).unwrap();
+        let findings = lint(&repos, &registry, NestedModules::default());
+        assert!(findings.iter().all(|f| f.rule_id != "L008"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
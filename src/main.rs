@@ -5,13 +5,100 @@ use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand, ValueEnum};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitCode};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use walkdir::WalkDir;
 
+mod align;
+mod audit;
+mod baseline;
+mod cancel;
+mod capabilities;
+mod changelog;
+mod checkpoint;
+mod clone;
+mod config;
+mod confirm;
+mod dedupe;
+mod diagnostics;
+mod diff;
+mod disk;
+mod email;
+mod error_output;
+mod errors;
+mod eta;
+mod excludes;
+mod feed;
+mod fetch;
+mod findings;
+mod forge;
+mod freshness;
+mod gate;
+mod gitdeps;
+mod github;
+mod gitignore;
+mod gitref;
+mod graph;
+mod grouping;
+mod history;
+mod inventory;
+mod jsonc;
+mod junit;
+mod justfile;
+mod layout;
+mod limits;
+mod lint;
+mod manifest;
+mod message;
+mod observer;
+mod ospath;
+mod outdated;
+mod output;
+mod owners;
+mod package_filter;
+mod pins;
+mod pipe;
+mod plan;
+mod project_index;
+mod provenance;
+mod registry;
+mod rename;
+mod renovate;
+mod repeated;
+mod repo_root;
+mod run_id;
+mod runner;
+mod sandbox;
+mod scheduler;
+mod self_update;
+mod skip;
+mod spdx;
+mod ssh;
+mod submodule;
+mod taskfile;
+mod templates;
+mod timestamp;
+mod toolchain;
+mod traversal;
+mod try_version;
+mod usage;
+mod vendor;
+mod verify;
+mod vscode;
+mod workspace;
+
+use audit::CommandExt;
+use config::Config;
+use output::OutputFormat;
+use runner::Runner;
+
 // =============================================================================
 // CLI Definitions
 // =============================================================================
@@ -23,6 +110,11 @@ use walkdir::WalkDir;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Screen-reader-friendly text output: no color, progress line or
+    /// decorations, PASS/FAIL words and one fact per line
+    #[arg(long, global = true)]
+    plain: bool,
 }
 
 #[derive(Subcommand)]
@@ -32,647 +124,8116 @@ enum Commands {
         #[command(flatten)]
         common: CommonOptions,
 
-        /// Output in JSON format
-        #[arg(long)]
+        /// Output in JSON format (same as --format json)
+        #[arg(long, conflicts_with = "format")]
         json: bool,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// Read manifests committed at this git ref instead of the worktree
+        #[arg(long, value_name = "REF")]
+        git_ref: Option<String>,
+
+        /// Exit with an error if any dependency pin has expired
+        #[arg(long)]
+        strict_pins: bool,
+
+        /// Stop after this many repos
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Print the path of the first repo found and stop; fails if there is none
+        #[arg(long, conflicts_with_all = ["json", "format", "git_ref", "limit"])]
+        first_match: bool,
+
+        /// After the repos, list large directories without modules and an
+        /// `ignore` setting leaving them out
+        #[arg(long, conflicts_with_all = ["json", "format", "git_ref", "first_match"])]
+        suggest_ignores: bool,
     },
 
-    /// Apply dependency updates (moon update + moon add)
-    Apply {
+    /// List dependencies behind the latest registry version
+    Outdated {
         #[command(flatten)]
         common: CommonOptions,
 
-        /// Skip initial moon update
-        #[arg(long)]
-        skip_update: bool,
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
 
-        /// Number of times to repeat moon add (default: 1)
-        #[arg(long, default_value = "1")]
-        repeat: u32,
+        /// Read manifests committed at this git ref instead of the worktree
+        #[arg(long, value_name = "REF")]
+        git_ref: Option<String>,
 
-        /// Only update specific packages (can be specified multiple times)
-        #[arg(long = "package", short = 'p')]
-        packages: Vec<String>,
+        /// Leave out the outdated dependencies this file accepts and fail
+        /// if any other is outdated
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<PathBuf>,
 
-        /// Stop on first failure
+        /// Record every outdated dependency in the baseline file (--baseline,
+        /// else moon-dst.baseline.toml), keeping the reasons already given
         #[arg(long)]
-        fail_fast: bool,
+        write_baseline: bool,
+    },
 
-        /// Skip adding justfile to repos
-        #[arg(long)]
-        no_justfile: bool,
+    /// Search the registry index for packages by name, description or keyword
+    Search {
+        #[command(flatten)]
+        common: CommonOptions,
 
-        /// Justfile handling mode
-        #[arg(long, value_enum, default_value = "create")]
-        justfile_mode: JustfileMode,
+        /// Text to look for (case-insensitive)
+        query: String,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
     },
 
-    /// Add justfile to repos
-    Just {
+    /// Show registry metadata of a package and the repos depending on it
+    Info {
         #[command(flatten)]
         common: CommonOptions,
 
-        /// Justfile handling mode
-        #[arg(long, value_enum, default_value = "create")]
-        mode: JustfileMode,
+        /// Package name (`owner/name`)
+        package: String,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
     },
-}
 
-#[derive(Parser)]
-struct CommonOptions {
-    /// Root directory to search from
-    #[arg(long, default_value = ".")]
-    root: PathBuf,
+    /// Download every registry dependency of the fleet into an offline mirror
+    Vendor {
+        #[command(flatten)]
+        common: CommonOptions,
 
-    /// Paths or directory names to ignore (can be specified multiple times)
-    #[arg(long = "ignore", short = 'i')]
-    ignores: Vec<String>,
+        /// Mirror directory
+        #[arg(short, long, value_name = "DIR", default_value = "vendor")]
+        out: PathBuf,
 
-    /// Disable default ignore rules
-    #[arg(long)]
-    no_default_ignore: bool,
+        /// Registry to download archives from
+        #[arg(long, value_name = "URL", default_value = vendor::DEFAULT_REGISTRY)]
+        registry_url: String,
 
-    /// Number of parallel jobs (default: CPU cores / 2)
-    #[arg(long, short = 'j')]
-    jobs: Option<usize>,
+        /// Check every archive against the checksum in the registry index
+        #[arg(long)]
+        verify_checksums: bool,
+    },
 
-    /// Show commands without executing
-    #[arg(long)]
-    dry_run: bool,
+    /// Recompute the checksums of a vendor mirror and report tampering or corruption
+    Verify {
+        /// Mirror directory created by `vendor`
+        #[arg(default_value = "vendor")]
+        dir: PathBuf,
 
-    /// Enable verbose output
-    #[arg(long, short = 'v')]
-    verbose: bool,
-}
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
 
-#[derive(Clone, Copy, ValueEnum, Default)]
-enum JustfileMode {
-    /// Skip if justfile exists
-    Skip,
-    /// Create only if missing
-    #[default]
-    Create,
-    /// Merge with existing (not implemented)
-    Merge,
-}
+    /// Report the source repository and commit of each dependency version in use
+    Provenance {
+        #[command(flatten)]
+        common: CommonOptions,
 
-// =============================================================================
-// Data Structures
-// =============================================================================
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
 
-/// moon.mod.json structure
-#[derive(Deserialize, Debug)]
-struct MoonMod {
-    #[serde(default)]
-    deps: HashMap<String, serde_json::Value>,
-}
+        /// Do not look up release tags on the source repositories
+        #[arg(long)]
+        offline: bool,
+    },
 
-/// Discovered moon.mod.json info
-#[derive(Debug, Clone)]
-struct MoonModInfo {
-    path: PathBuf,
-    deps: Vec<String>,
-}
+    /// Export the cross-repo dependency graph
+    #[command(args_conflicts_with_subcommands = true)]
+    Graph {
+        #[command(flatten)]
+        common: CommonOptions,
 
-/// Repository information
-#[derive(Debug, Clone)]
-struct RepoInfo {
-    root: PathBuf,
-    moon_mods: Vec<MoonModInfo>,
-}
+        /// Output format
+        #[arg(long, value_enum, default_value = "json")]
+        format: GraphFormat,
 
-/// JSON output structure for scan
-#[derive(Serialize)]
-struct ScanOutput {
-    repos: Vec<RepoOutput>,
-}
+        #[command(subcommand)]
+        action: Option<GraphAction>,
+    },
 
-#[derive(Serialize)]
-struct RepoOutput {
-    repo_root: String,
-    moon_mods: Vec<MoonModOutput>,
-}
+    /// Record the updates apply would make in a plan file for review
+    Plan {
+        #[command(flatten)]
+        common: CommonOptions,
 
-#[derive(Serialize)]
-struct MoonModOutput {
-    path: String,
-    deps: Vec<String>,
-}
+        /// Only plan updates of specific packages (can be specified multiple times)
+        #[arg(long = "package", short = 'p')]
+        packages: Vec<String>,
 
-/// Execution result for a repo
-#[derive(Debug)]
-struct RepoResult {
-    repo_root: PathBuf,
-    success: bool,
-    updated_packages: Vec<String>,
-    failed_packages: Vec<(String, String)>,
-    errors: Vec<String>,
-}
+        /// How --package matches dependency names (default: substring)
+        #[arg(long, value_enum)]
+        package_match: Option<package_filter::MatchMode>,
 
-// =============================================================================
-// Default Ignore Rules
-// =============================================================================
+        /// Only plan versions published at least this many days ago
+        #[arg(long, value_name = "DAYS")]
+        min_age: Option<u64>,
 
-const DEFAULT_IGNORES: &[&str] = &[
-    "target",
-    "node_modules",
-    "dist",
-    "build",
-    "vendor",
-    "skills",
-];
+        /// Plan file to write
+        #[arg(
+            long,
+            short = 'o',
+            value_name = "PATH",
+            default_value = "moon-dst.plan.json"
+        )]
+        out: PathBuf,
+    },
 
-// =============================================================================
-// Justfile Template
-// =============================================================================
+    /// Apply dependency updates (moon update + moon add)
+    Apply(ApplyArgs),
 
-const JUSTFILE_TEMPLATE: &str = r#"# https://github.com/mizchi/moonbit-template
-# SPDX-License-Identifier: MIT
-# MoonBit Project Commands
+    /// Run moon test in every module (fan-out)
+    Test {
+        #[command(flatten)]
+        common: CommonOptions,
 
-target := "js"
+        /// Write a JUnit XML report (one test case per module)
+        #[arg(long, value_name = "PATH")]
+        junit: Option<PathBuf>,
 
-default: check test
+        /// Extra arguments passed to moon test
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
 
-fmt:
-    moon fmt
+    /// Add justfile to repos
+    Just {
+        #[command(flatten)]
+        common: CommonOptions,
 
-check:
-    moon check --deny-warn --target {{target}}
+        /// Justfile handling mode (default: create)
+        #[arg(long, value_enum)]
+        mode: Option<WriteMode>,
 
-test:
-    moon test --target {{target}}
+        /// Write the recipes for this task runner (default: just)
+        #[arg(long, value_enum)]
+        task_runner: Option<taskfile::TaskRunner>,
 
-test-update:
-    moon test --update --target {{target}}
+        /// Only these template recipes, plus the recipes they depend on
+        #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+        recipes: Vec<String>,
 
-run:
-    moon run src/main --target {{target}}
+        /// Append a recipe running one command (repeatable)
+        #[arg(long = "extra-recipe", value_name = "NAME:COMMAND")]
+        extra_recipes: Vec<String>,
 
-info:
-    moon info
+        /// Override a template variable, e.g. target=wasm-gc (repeatable)
+        #[arg(long = "set", value_name = "NAME=VALUE", value_parser = justfile::parse_assignment)]
+        set: Vec<(String, String)>,
 
-clean:
-    moon clean
+        /// In repos with several modules, write only the modules' files, no
+        /// root file running fmt/check/test in all of them
+        #[arg(long)]
+        no_root_file: bool,
+    },
 
-release-check: fmt info check test
-"#;
+    /// Ensure .gitignore contains the standard MoonBit entries
+    Gitignore {
+        #[command(flatten)]
+        common: CommonOptions,
 
-// =============================================================================
-// Core Logic
-// =============================================================================
+        /// .gitignore handling mode (default: merge)
+        #[arg(long, value_enum)]
+        mode: Option<WriteMode>,
+    },
 
-fn main() -> ExitCode {
-    let cli = Cli::parse();
+    /// Count source lines using a package (or one of its symbols) per repo
+    GrepUsage {
+        #[command(flatten)]
+        common: CommonOptions,
 
-    match run(cli) {
-        Ok(success) => {
-            if success {
-                ExitCode::SUCCESS
-            } else {
-                ExitCode::from(1)
-            }
-        }
-        Err(e) => {
-            eprintln!("Error: {e:#}");
-            ExitCode::from(1)
-        }
-    }
-}
+        /// Package, e.g. alice/json (packages below it are included)
+        package: String,
 
-fn run(cli: Cli) -> Result<bool> {
-    // Check moon CLI availability
-    check_moon_available()?;
+        /// Function, type or value of the package
+        symbol: Option<String>,
 
-    match cli.command {
-        Commands::Scan { common, json } => cmd_scan(common, json),
-        Commands::Apply {
-            common,
-            skip_update,
-            repeat,
-            packages,
-            fail_fast,
-            no_justfile,
-            justfile_mode,
-        } => cmd_apply(
-            common,
-            skip_update,
-            repeat,
-            packages,
-            fail_fast,
-            !no_justfile,
-            justfile_mode,
-        ),
-        Commands::Just { common, mode } => cmd_just(common, mode),
-    }
-}
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
 
-/// Get the moon binary path, checking common installation locations
-fn get_moon_bin() -> PathBuf {
-    // First check if moon is in PATH
-    if let Ok(output) = Command::new("moon").arg("version").output() {
-        if output.status.success() {
-            return PathBuf::from("moon");
-        }
-    }
+    /// Rename a package in manifests, imports and (optionally) sources
+    Rename {
+        #[command(flatten)]
+        common: CommonOptions,
 
-    // Check ~/.moon/bin/moon (common MoonBit installation path)
-    if let Some(home) = std::env::var_os("HOME") {
-        let moon_path = PathBuf::from(home).join(".moon/bin/moon");
-        if moon_path.exists() {
-            return moon_path;
-        }
-    }
+        /// Current package name, e.g. alice/json
+        old: String,
 
-    // Fallback to "moon" and let it fail with a clear error
-    PathBuf::from("moon")
-}
+        /// New package name
+        new: String,
 
-fn check_moon_available() -> Result<()> {
-    let moon_bin = get_moon_bin();
-    let output = Command::new(&moon_bin).arg("version").output();
+        /// Version to depend on under the new name
+        #[arg(long)]
+        version: Option<String>,
 
-    match output {
-        Ok(o) if o.status.success() => Ok(()),
-        _ => bail!(
-            "'moon' CLI not found. Checked PATH and ~/.moon/bin/moon. Please install MoonBit first."
-        ),
-    }
-}
+        /// Also rewrite `@alias.` qualifiers in .mbt files when the default
+        /// alias changes
+        #[arg(long)]
+        sources: bool,
+    },
 
-// =============================================================================
-// Scan Command
-// =============================================================================
+    /// Check and test the dependents of a package against a candidate
+    /// version in temporary copies, reporting which would break
+    TryVersion {
+        #[command(flatten)]
+        common: CommonOptions,
 
-fn cmd_scan(common: CommonOptions, json_output: bool) -> Result<bool> {
-    let repos = discover_repos(&common)?;
+        /// Candidate, e.g. alice/json@0.5.0
+        #[arg(value_parser = try_version::parse_spec)]
+        candidate: (String, String),
 
-    if json_output {
-        let output = ScanOutput {
-            repos: repos
-                .iter()
-                .map(|r| RepoOutput {
-                    repo_root: r.root.display().to_string(),
-                    moon_mods: r
-                        .moon_mods
-                        .iter()
-                        .map(|m| MoonModOutput {
-                            path: m
-                                .path
-                                .strip_prefix(&r.root)
-                                .unwrap_or(&m.path)
-                                .display()
-                                .to_string(),
-                            deps: m.deps.clone(),
-                        })
-                        .collect(),
-                })
-                .collect(),
-        };
-        println!("{}", serde_json::to_string_pretty(&output)?);
-    } else {
-        for repo in &repos {
-            println!("Repository: {}", repo.root.display());
-            for moon_mod in &repo.moon_mods {
-                let rel_path = moon_mod
-                    .path
-                    .strip_prefix(&repo.root)
-                    .unwrap_or(&moon_mod.path);
-                println!("  {}", rel_path.display());
-                for dep in &moon_mod.deps {
-                    println!("    - {dep}");
-                }
-            }
-            println!();
-        }
+        /// Only run moon check
+        #[arg(long)]
+        no_test: bool,
 
-        let total_mods: usize = repos.iter().map(|r| r.moon_mods.len()).sum();
-        let total_deps: usize = repos
-            .iter()
-            .flat_map(|r| &r.moon_mods)
-            .map(|m| m.deps.len())
-            .sum();
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
 
-        println!(
-            "Summary: {} repos, {} moon.mod.json files, {} dependencies",
-            repos.len(),
-            total_mods,
-            total_deps
-        );
-    }
+    /// Write or update renovate.json per repo from the update policies
+    RenovateConfig {
+        #[command(flatten)]
+        common: CommonOptions,
 
-    Ok(true)
-}
+        /// renovate.json handling mode (default: merge)
+        #[arg(long, value_enum)]
+        mode: Option<WriteMode>,
+
+        /// URL template of the mooncakes datasource (`{{packageName}}` is
+        /// replaced with the package)
+        #[arg(long, value_name = "URL")]
+        datasource_url: Option<String>,
+    },
+
+    /// Generate .vscode/tasks.json per repo and a multi-root workspace file
+    Vscode {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// tasks.json handling mode
+        #[arg(long, value_enum, default_value = "merge")]
+        mode: WriteMode,
+
+        /// Workspace file covering all modules (relative to the root)
+        #[arg(long, value_name = "FILE", default_value = "fleet.code-workspace")]
+        workspace: PathBuf,
+
+        /// Only write tasks.json files
+        #[arg(long)]
+        no_workspace: bool,
+    },
+
+    /// Check or insert SPDX license headers in .mbt files
+    Spdx {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// SPDX license identifier, e.g. MIT
+        #[arg(long)]
+        license: Option<String>,
+
+        /// Copyright holder written as SPDX-FileCopyrightText
+        #[arg(long)]
+        holder: Option<String>,
+
+        /// Glob of files to skip, relative to the repo root (can be repeated)
+        #[arg(long = "exclude", value_name = "GLOB")]
+        excludes: Vec<String>,
+
+        /// Insert or correct headers instead of only checking
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Stamp files from a template pack into repos
+    ApplyTemplates {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Template pack name (~/.config/moon-dst/templates/<pack>/)
+        #[arg(long, default_value = templates::DEFAULT_PACK)]
+        pack: String,
+
+        /// How to handle files that already exist
+        #[arg(long, value_enum, default_value = "create")]
+        mode: TemplateMode,
+    },
+
+    /// Report template files (justfile etc.) that drifted from their pack
+    Drift {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Template pack to compare against (default: the pack recorded in each repo)
+        #[arg(long)]
+        pack: Option<String>,
+
+        /// Update drifted or missing files
+        #[arg(long)]
+        fix: bool,
+
+        /// How --fix updates existing justfiles
+        #[arg(long, value_enum, default_value = "merge")]
+        fix_mode: FixMode,
+    },
+
+    /// Check manifests for problems (outdated or unknown dependencies, missing names)
+    Lint {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Rewrite manifests flagged non-strict-json as strict JSON
+        #[arg(long)]
+        fix: bool,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+
+    /// Run the outdated, lint, audit and policy checks and pass or fail
+    /// them by one policy, for CI
+    Gate {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// Lowest finding level that fails the gate (default: error)
+        #[arg(long, value_enum)]
+        fail_on: Option<gate::FailOn>,
+
+        /// Count dependencies more than N major versions behind as errors
+        /// (default: 1)
+        #[arg(long, value_name = "N")]
+        max_major_lag: Option<u64>,
+
+        /// Run only this check (can be specified multiple times)
+        #[arg(long = "check", value_enum)]
+        checks: Vec<gate::Check>,
+
+        /// Leave out the outdated dependencies this baseline accepts
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<PathBuf>,
+    },
+
+    /// Align internal packages used at different versions across the fleet
+    Align {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Pick versions by this policy instead of asking
+        #[arg(long, value_enum)]
+        choose: Option<align::Policy>,
+
+        /// Only align specific packages (can be specified multiple times)
+        #[arg(long = "package", short = 'p')]
+        packages: Vec<String>,
+    },
+
+    /// Report packages used under several names or sources, or at many
+    /// versions, with consolidation targets and cache savings
+    DedupeReport {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// List packages used at this many versions or more
+        #[arg(long, value_name = "N", default_value = "2", value_parser = clap::value_parser!(u64).range(2..))]
+        min_versions: u64,
+    },
+
+    /// Render a static HTML inventory of repos, modules and dependencies
+    Inventory {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Output directory for the generated site
+        #[arg(long, value_name = "DIR")]
+        html: PathBuf,
+
+        /// Read manifests committed at this git ref instead of the worktree
+        #[arg(long, value_name = "REF")]
+        git_ref: Option<String>,
+    },
+
+    /// Inspect and validate configuration files
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Report or clean dependency caches and build output
+    Cache {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Remove build output and dependency caches across all repos
+    Clean {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Only remove build output (target, _build)
+        #[arg(long, conflicts_with = "mooncakes_only")]
+        targets_only: bool,
+
+        /// Only remove dependency caches (.mooncakes)
+        #[arg(long)]
+        mooncakes_only: bool,
+
+        /// Only remove artifacts not modified for this long (e.g. 30d, 12h)
+        #[arg(long, value_name = "AGE", value_parser = disk::parse_age)]
+        older_than: Option<Duration>,
+    },
+
+    /// Manage moon toolchains used for repos with pinned versions
+    Toolchain {
+        #[command(subcommand)]
+        action: ToolchainAction,
+    },
+
+    /// Show dependency pins from config
+    Pins {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        #[command(subcommand)]
+        action: PinsAction,
+    },
+
+    /// Maintain the member list of a monorepo's moon-workspace.toml
+    Workspace {
+        /// Directory containing moon-workspace.toml
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+
+        #[command(subcommand)]
+        action: WorkspaceAction,
+    },
+
+    /// Check the local setup and whether the moon toolchain is up to date
+    SelfCheck {
+        /// Compare the installed moon with the latest release
+        #[arg(long)]
+        toolchain: bool,
+
+        /// Run the official installer if moon is outdated (implies --toolchain)
+        #[arg(long)]
+        update_toolchain: bool,
+
+        /// Release feed announcing the latest toolchain version
+        #[arg(long, value_name = "URL", default_value = toolchain::RELEASE_FEED)]
+        feed: String,
+
+        /// Show what would be updated without running the installer
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Update the moon-dst binary to the latest GitHub release
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+
+        /// Release metadata URL (GitHub releases API format)
+        #[arg(long, value_name = "URL", default_value = self_update::RELEASE_URL)]
+        release_url: String,
+    },
+
+    /// Explain an error, diagnostic or lint code; list all codes without one
+    Explain {
+        /// Code id (E014) or name (registry-timeout)
+        code: Option<String>,
+    },
+
+    /// Show apply runs recorded in the run journal
+    History {
+        /// Only runs over this root
+        #[arg(long, value_name = "DIR", global = true)]
+        root: Option<PathBuf>,
+
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+
+    /// Show or verify the audit log of programs moon-dst has run
+    AuditLog {
+        #[command(subcommand)]
+        action: AuditLogAction,
+    },
+
+    /// Print the full output of a failure shortened in apply results
+    ShowError {
+        /// ID from the results line, or a unique prefix of it
+        id: String,
+    },
+}
+
+impl Commands {
+    /// Whether the command runs moon and needs it installed on the host
+    fn requires_moon(&self) -> bool {
+        let common = match self {
+            Commands::Scan { common, .. }
+            | Commands::Test { common, .. }
+            | Commands::Just { common, .. } => common,
+            Commands::Apply(args) => &args.common,
+            _ => return false,
+        };
+        common.in_container.is_none()
+    }
+}
+
+// Parsed once per run; boxing the options buys nothing
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective configuration and where each value came from
+    Show {
+        #[command(flatten)]
+        common: CommonOptions,
+    },
+
+    /// Validate config files (user and project config by default)
+    Validate {
+        /// Root directory containing the project config
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+
+        /// Config files to validate instead of the default locations
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ToolchainAction {
+    /// List the default and managed toolchains
+    List,
+
+    /// Install a moon version as a managed toolchain
+    Install {
+        /// MoonBit version to install, e.g. 0.1.20250108
+        version: String,
+
+        /// Show what would be installed without installing
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum GraphAction {
+    /// Rank packages by fan-in, fan-out and the repos an update would reach
+    Stats {
+        #[command(flatten)]
+        common: CommonOptions,
+
+        /// Show only the N highest-impact packages
+        #[arg(long, value_name = "N")]
+        top: Option<usize>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Show .mooncakes and build output sizes per repo
+    Stats {
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Run moon clean in every module and report the space freed
+    Clean,
+}
+
+#[derive(Subcommand)]
+enum PinsAction {
+    /// List pins with their reasons, expiry and the repos they hold
+    List {
+        /// Include expired pins
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// List recorded runs, newest first
+    List {
+        /// Show only this many runs
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Show the changes and failures of one run
+    Show {
+        /// Run ID, as printed in the apply results and embedded in reports,
+        /// commits and pull requests
+        run_id: String,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditLogAction {
+    /// List logged program runs, oldest first
+    Show {
+        /// Show only the last N entries
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Show only the programs run by this run ID
+        #[arg(long, value_name = "RUN_ID")]
+        run: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Check that no entry was modified, removed or reordered
+    Verify,
+}
+
+#[derive(Subcommand)]
+enum WorkspaceAction {
+    /// Add a module directory (relative to the workspace root)
+    Add {
+        /// Module directory containing moon.mod.json
+        path: String,
+    },
+
+    /// Remove a module directory from the members
+    Remove {
+        /// Module directory as listed in the members
+        path: String,
+    },
+
+    /// List the members and whether their manifests exist
+    List,
+}
+
+#[derive(Parser)]
+struct ApplyArgs {
+    #[command(flatten)]
+    common: CommonOptions,
+
+    /// Skip initial moon update
+    #[arg(long)]
+    skip_update: bool,
+
+    /// Maximum number of moon add passes (default: 1); later passes only
+    /// re-add packages whose version changed in the previous pass
+    #[arg(long, conflicts_with = "repeat_until_stable")]
+    repeat: Option<u32>,
+
+    /// Repeat moon add passes until a pass changes no versions
+    #[arg(long)]
+    repeat_until_stable: bool,
+
+    /// Only update specific packages (can be specified multiple times)
+    #[arg(long = "package", short = 'p')]
+    packages: Vec<String>,
+
+    /// How --package matches dependency names (default: substring)
+    #[arg(long, value_enum)]
+    package_match: Option<package_filter::MatchMode>,
+
+    /// Move dependencies on packages listed in `[renames]` to their new
+    /// names (manifests, imports and sources) before updating
+    #[arg(long)]
+    apply_renames: bool,
+
+    /// Stop on first failure
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Parallel moon update runs and target version lookups (default: --jobs)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    network_jobs: Option<u64>,
+
+    /// Parallel moon check runs with --verify (default: --jobs)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    verify_jobs: Option<u64>,
+
+    /// Skip adding justfile to repos
+    #[arg(long)]
+    no_justfile: bool,
+
+    /// Justfile handling mode (default: create)
+    #[arg(long, value_enum)]
+    justfile_mode: Option<WriteMode>,
+
+    /// Run moon check in each module after updating
+    #[arg(long)]
+    verify: bool,
+
+    /// Skip repos whose pinned toolchain differs from the installed moon
+    #[arg(long)]
+    respect_toolchain: bool,
+
+    /// Only update to versions published at least this many days ago
+    #[arg(long, value_name = "DAYS")]
+    min_age: Option<u64>,
+
+    /// Bump git dependencies to the latest commit of their tracked branch
+    #[arg(long)]
+    update_git_deps: bool,
+
+    /// Always update dependencies pinned to yanked or deprecated versions,
+    /// even if --package or --min-age would leave them out
+    #[arg(long)]
+    fix_yanked: bool,
+
+    /// Email the report to this address (can be specified multiple times)
+    #[arg(long, value_name = "ADDRESS")]
+    email_to: Vec<String>,
+
+    /// When to send the email report (default: always)
+    #[arg(long, value_enum)]
+    email_on: Option<EmailOn>,
+
+    /// Which outcome makes the exit code non-zero (default: failed)
+    #[arg(long, value_enum)]
+    fail_on: Option<FailOn>,
+
+    /// Write a JUnit XML report (one test case per repo)
+    #[arg(long, value_name = "PATH")]
+    junit: Option<PathBuf>,
+
+    /// Write each repo's result to this file as one JSON line as soon as the
+    /// repo is done
+    #[arg(long, value_name = "PATH")]
+    results_out: Option<PathBuf>,
+
+    /// Continue an interrupted run: skip the repos its checkpoint lists as
+    /// done and report their recorded results
+    #[arg(long)]
+    resume: bool,
+
+    /// Proceed without asking, however many repos the run changes
+    #[arg(long, short = 'y')]
+    yes: bool,
+
+    /// Characters of an error shown in the results; longer output is
+    /// stored for `show-error` (0: show errors whole)
+    #[arg(long, value_name = "N")]
+    max_error_length: Option<usize>,
+
+    /// List every failure under its repo, even an error many repos share
+    #[arg(long)]
+    no_group_errors: bool,
+
+    /// Write an Atom feed of update activity from the run history
+    #[arg(long, value_name = "PATH")]
+    feed_out: Option<PathBuf>,
+
+    /// Commit the manifest changes of each updated repo on a branch, push
+    /// it and open a pull request (GitHub, GitLab or Gitea/Forgejo)
+    #[arg(long)]
+    open_pr: bool,
+
+    /// After a pull request inside a git submodule, open one in its
+    /// superproject that moves the submodule to the new commit
+    #[arg(long)]
+    update_submodules: bool,
+
+    /// Add an entry per update to this changelog in each updated repo
+    /// (relative to the repo root, e.g. CHANGELOG.md)
+    #[arg(long, value_name = "FILE")]
+    changelog_file: Option<PathBuf>,
+
+    /// Open an issue on repos that failed this many runs in a row
+    #[arg(long, value_name = "RUNS", value_parser = clap::value_parser!(u32).range(1..))]
+    issue_after: Option<u32>,
+
+    /// Check out `[clone] repos` in full instead of sparse, shallow clones
+    #[arg(long)]
+    full_clone: bool,
+
+    /// Free space to keep on each filesystem after the expected cache and
+    /// build growth (default: 1G)
+    #[arg(long, value_name = "SIZE")]
+    min_free: Option<String>,
+
+    /// What to do when free space would fall below --min-free (default: warn)
+    #[arg(long, value_enum)]
+    on_low_disk: Option<LowDisk>,
+
+    /// Refuse to run if any dependency pin has expired
+    #[arg(long)]
+    strict_pins: bool,
+
+    /// Update exactly as recorded by `moon-dst plan`, refusing if any
+    /// manifest changed since
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["packages", "min_age", "fix_yanked", "update_git_deps"])]
+    plan: Option<PathBuf>,
+
+    /// Apply to the repos listed by `scan --format json` output in this
+    /// file (`-` for standard input) instead of discovering them
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["plan", "from_outdated"])]
+    from_scan: Option<PathBuf>,
+
+    /// Update exactly the packages reported by `outdated --format json`
+    /// output in this file (`-` for standard input) to the reported versions
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["plan", "packages", "min_age", "fix_yanked", "update_git_deps"])]
+    from_outdated: Option<PathBuf>,
+
+    /// Output format for results (default: table on a terminal, text
+    /// otherwise)
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+}
+
+#[derive(Parser)]
+struct CommonOptions {
+    /// Root directory to search from (can be specified multiple times or
+    /// as a comma-separated list; config and relative paths use the first)
+    #[arg(
+        long = "root",
+        value_name = "DIR",
+        value_delimiter = ',',
+        default_value = "."
+    )]
+    roots: Vec<PathBuf>,
+
+    /// Config profile to apply on top of the config files ([profile.<NAME>])
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Paths or directory names to ignore (can be specified multiple times)
+    #[arg(long = "ignore", short = 'i')]
+    ignores: Vec<String>,
+
+    /// Disable default ignore rules
+    #[arg(long)]
+    no_default_ignore: bool,
+
+    /// Do not leave out what git's global excludes file and the repos'
+    /// .git/info/exclude exclude
+    #[arg(long)]
+    no_global_ignore: bool,
+
+    /// Read manifests with comments, trailing commas and other JSON5 syntax
+    /// instead of skipping them
+    #[arg(long)]
+    lenient_parse: bool,
+
+    /// Extra file or directory name marking a repo root, besides .git,
+    /// .jj, .hg and .moon-dst-root (can be specified multiple times)
+    #[arg(long = "root-marker", value_name = "NAME")]
+    root_markers: Vec<String>,
+
+    /// Only repos owned by this team or user, from CODEOWNERS or
+    /// [[owners.repos]] (can be specified multiple times)
+    #[arg(long = "owner", value_name = "OWNER")]
+    owners: Vec<String>,
+
+    /// Git submodules: make each a repo of its own, or skip them
+    /// [default: separate]
+    #[arg(long, value_name = "MODE")]
+    submodules: Option<submodule::Submodules>,
+
+    /// Modules inside another module's directory: group them with their
+    /// repo, make each a unit of its own, or skip them [default: group]
+    #[arg(long, value_name = "MODE")]
+    nested_modules: Option<layout::NestedModules>,
+
+    /// Drop diagnostics with this code or name, e.g. A009 or run-state
+    /// (can be specified multiple times)
+    #[arg(long, value_name = "CODE")]
+    allow: Vec<String>,
+
+    /// Report diagnostics with this code as warnings (can be specified
+    /// multiple times)
+    #[arg(long, value_name = "CODE")]
+    warn: Vec<String>,
+
+    /// Make diagnostics with this code errors that fail the command (can be
+    /// specified multiple times)
+    #[arg(long, value_name = "CODE")]
+    deny: Vec<String>,
+
+    /// Group scan and apply text output by host, or by host and
+    /// organization, with subtotals [default: flat]
+    #[arg(long, value_name = "MODE")]
+    group_output: Option<grouping::GroupOutput>,
+
+    /// Number of parallel jobs (default: CPU cores / 2)
+    #[arg(long, short = 'j')]
+    jobs: Option<usize>,
+
+    /// Show commands without executing
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Enable verbose output
+    #[arg(long, short = 'v')]
+    verbose: bool,
+
+    /// Print statistics to stderr at the end, such as how registry lookups
+    /// were answered
+    #[arg(long)]
+    stats: bool,
+
+    /// Run moon inside a container from this image (moon is not needed on the host)
+    #[arg(long, value_name = "IMAGE")]
+    in_container: Option<String>,
+
+    /// Container runtime for --in-container
+    #[arg(long, value_name = "RUNTIME", default_value = "docker", value_parser = ["docker", "podman"])]
+    container_runtime: String,
+
+    /// Run moon processes at this niceness (0-19)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=19))]
+    nice: Option<u8>,
+
+    /// Limit each moon process to this share of CPU time, in percent of one
+    /// core (200 = two cores)
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..))]
+    cpu_limit: Option<u32>,
+
+    /// Limit the memory of each moon process (e.g. 512M, 4G)
+    #[arg(long, value_name = "SIZE")]
+    memory_limit: Option<String>,
+
+    /// Reproducible output: one job, a fixed clock (SOURCE_DATE_EPOCH, else
+    /// the epoch) and paths in JSON relative to --root
+    #[arg(long)]
+    deterministic: bool,
+}
+
+impl CommonOptions {
+    /// The first `--root`, which holds the project config
+    fn root(&self) -> &Path {
+        &self.roots[0]
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum WriteMode {
+    /// Leave the file untouched
+    Skip,
+    /// Create only if missing
+    #[default]
+    Create,
+    /// Create if missing, otherwise add missing template entries
+    Merge,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
+enum TemplateMode {
+    /// Only create missing files
+    Create,
+    /// Overwrite existing files
+    Overwrite,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
+enum FixMode {
+    /// Add missing template recipes and variables, keep the rest
+    Merge,
+    /// Overwrite with the template
+    Regenerate,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FailOn {
+    /// Fail when a package or repo failed
+    #[default]
+    Failed,
+    /// Also fail when no package was updated
+    Unchanged,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EmailOn {
+    /// Send after every run
+    #[default]
+    Always,
+    /// Send only when something failed, listing only the failures
+    Failure,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
+enum GraphFormat {
+    /// Versioned JSON document with typed nodes and edges
+    Json,
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum LowDisk {
+    /// Warn and continue
+    #[default]
+    Warn,
+    /// Stop before changing anything
+    Abort,
+    /// Skip the free space check
+    Ignore,
+}
+
+// =============================================================================
+// Data Structures
+// =============================================================================
+
+/// moon.mod.json structure
+#[derive(Deserialize, Debug)]
+struct MoonMod {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    deps: HashMap<String, serde_json::Value>,
+    #[serde(default, rename = "bin-deps")]
+    bin_deps: HashMap<String, serde_json::Value>,
+    #[serde(default, rename = "moon-version")]
+    moon_version: Option<String>,
+}
+
+/// Discovered moon.mod.json info
+#[derive(Debug, Clone)]
+struct MoonModInfo {
+    path: PathBuf,
+    name: Option<String>,
+    deps: Vec<Dep>,
+    /// `bin-deps`: tools used while building, not linked into the module
+    bin_deps: Vec<Dep>,
+    moon_version: Option<String>,
+}
+
+/// A dependency declared in moon.mod.json
+#[derive(Debug, Clone)]
+struct Dep {
+    name: String,
+    /// Declared version (`"0.1.0"` or `{ "version": "0.1.0" }`)
+    version: Option<String>,
+    source: DepSource,
+}
+
+impl Dep {
+    /// Whether the dependency is resolved from the mooncakes registry
+    fn is_registry(&self) -> bool {
+        self.source == DepSource::Registry
+    }
+
+    /// Yanked or deprecated marker of the declared registry version
+    fn flag(&self, registry: &registry::Registry) -> Option<registry::VersionFlag> {
+        if !self.is_registry() {
+            return None;
+        }
+        registry.flag(&self.name, self.version.as_deref()?)
+    }
+}
+
+/// Where a dependency comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DepSource {
+    Registry,
+    /// `{ "path": "../lib" }`
+    Path(String),
+    /// `{ "git": "<url>", "branch": "main", "rev": "<sha>" }`
+    Git {
+        url: String,
+        branch: Option<String>,
+        rev: Option<String>,
+    },
+}
+
+impl DepSource {
+    fn kind(&self) -> &'static str {
+        match self {
+            DepSource::Registry => "registry",
+            DepSource::Path(_) => "path",
+            DepSource::Git { .. } => "git",
+        }
+    }
+}
+
+impl std::fmt::Display for DepSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DepSource::Registry => write!(f, "registry"),
+            DepSource::Path(path) => write!(f, "path {path}"),
+            DepSource::Git { url, branch, rev } => {
+                write!(f, "git {url}")?;
+                if let Some(branch) = branch {
+                    write!(f, " branch {branch}")?;
+                }
+                if let Some(rev) = rev {
+                    write!(f, " rev {}", rev.get(..12).unwrap_or(rev))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Repository information
+#[derive(Debug, Clone)]
+struct RepoInfo {
+    root: PathBuf,
+    moon_mods: Vec<MoonModInfo>,
+    /// Pinned toolchain version (`moon-version` file or manifest field)
+    toolchain: Option<String>,
+    /// Toolchain used to run moon in this repo
+    moon: toolchain::Toolchain,
+    /// Opt-out marker placed in the repo root
+    skip: Option<skip::Marker>,
+    /// Host of a remote repo (`host:/path`); `root` is the path on that host
+    host: Option<String>,
+    /// Where moon commands run for this repo
+    runner_spec: runner::RunnerSpec,
+    runner: Arc<dyn runner::Runner>,
+    /// Teams or users owning the repo's modules
+    owners: Vec<String>,
+}
+
+impl RepoInfo {
+    /// A local repo without toolchain pin, skip marker or runner settings
+    fn local(root: PathBuf, moon_mods: Vec<MoonModInfo>) -> Self {
+        RepoInfo {
+            root,
+            moon_mods,
+            toolchain: None,
+            moon: toolchain::Toolchain::Default,
+            skip: None,
+            host: None,
+            runner_spec: runner::RunnerSpec::Local,
+            runner: Arc::new(runner::Local),
+            owners: Vec::new(),
+        }
+    }
+
+    /// `root`, prefixed with the host for remote repos
+    fn location(&self) -> String {
+        match &self.host {
+            Some(host) => format!("{host}:{}", ospath::display(&self.root)),
+            None => ospath::encode(&self.root),
+        }
+    }
+
+    /// [`location`](Self::location) as a path that keeps bytes which are
+    /// not UTF-8, for keying results
+    fn location_path(&self) -> PathBuf {
+        match &self.host {
+            Some(host) => {
+                let mut location = std::ffi::OsString::from(format!("{host}:"));
+                location.push(&self.root);
+                PathBuf::from(location)
+            }
+            None => self.root.clone(),
+        }
+    }
+
+    /// Yanked or deprecated marker of the version a dependency is pinned to
+    fn flagged_version(
+        &self,
+        dep: &str,
+        registry: &registry::Registry,
+    ) -> Option<registry::VersionFlag> {
+        self.moon_mods
+            .iter()
+            .flat_map(|m| &m.deps)
+            .find(|d| d.name == dep)?
+            .flag(registry)
+    }
+
+    /// Version of a dependency as first declared in the repo's modules
+    fn declared_version(&self, name: &str) -> Option<String> {
+        self.moon_mods
+            .iter()
+            .flat_map(|m| &m.deps)
+            .find(|dep| dep.name == name)
+            .and_then(|dep| dep.version.clone())
+    }
+
+    /// Toolchain satisfying the repo's pin, `None` if nothing installed does
+    fn select_toolchain(
+        &self,
+        installed: Option<&str>,
+        managed: &[(String, PathBuf)],
+    ) -> Option<toolchain::Toolchain> {
+        match &self.toolchain {
+            Some(required) => toolchain::select(required, installed, managed),
+            None => Some(toolchain::Toolchain::Default),
+        }
+    }
+}
+
+/// JSON output structure for scan
+#[derive(Serialize)]
+struct ScanOutput {
+    repos: Vec<RepoOutput>,
+    /// Mean freshness score over repos (0-100)
+    fleet_freshness: f64,
+    /// Nested modules and duplicate module names
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    layout: Vec<layout::Issue>,
+}
+
+#[derive(Serialize)]
+struct RepoOutput {
+    repo_root: String,
+    toolchain: Option<String>,
+    toolchain_mismatch: bool,
+    /// Where moon commands run (`local`, `ssh:<host>`, `docker:<container>`, ...)
+    runner: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    owners: Vec<String>,
+    /// Active opt-out marker
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<skip::Marker>,
+    freshness: freshness::Score,
+    moon_mods: Vec<MoonModOutput>,
+}
+
+#[derive(Serialize)]
+struct MoonModOutput {
+    path: String,
+    deps: Vec<String>,
+    /// Declared dependencies with their source
+    dependencies: Vec<DepOutput>,
+    /// Dependencies pinned to yanked or deprecated versions
+    flagged: Vec<FlaggedDep>,
+}
+
+#[derive(Serialize)]
+struct DepOutput {
+    name: String,
+    /// `registry`, `path` or `git`
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+}
+
+impl From<&Dep> for DepOutput {
+    fn from(dep: &Dep) -> Self {
+        let mut output = DepOutput {
+            name: dep.name.clone(),
+            kind: dep.source.kind(),
+            version: dep.version.clone(),
+            path: None,
+            git: None,
+            branch: None,
+            rev: None,
+        };
+        match &dep.source {
+            DepSource::Registry => {}
+            DepSource::Path(path) => output.path = Some(path.clone()),
+            DepSource::Git { url, branch, rev } => {
+                output.git = Some(url.clone());
+                output.branch = branch.clone();
+                output.rev = rev.clone();
+            }
+        }
+        output
+    }
+}
+
+#[derive(Serialize)]
+struct FlaggedDep {
+    name: String,
+    version: String,
+    flag: String,
+}
+
+/// JSON output structure for outdated
+#[derive(Serialize)]
+struct OutdatedOutput {
+    outdated: Vec<outdated::DepRow>,
+    /// Outdated dependencies the baseline accepts
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    accepted: Vec<outdated::DepRow>,
+}
+
+/// JSON output structure for apply
+#[derive(Serialize)]
+struct ApplyOutput<'a> {
+    run_id: &'a str,
+    repos: &'a [RepoResult],
+}
+
+/// Execution result for a repo
+#[derive(Debug, Serialize, Deserialize)]
+struct RepoResult {
+    #[serde(with = "crate::ospath")]
+    repo_root: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    owners: Vec<String>,
+    success: bool,
+    packages: Vec<PackageRecord>,
+    errors: Vec<String>,
+    #[serde(skip)]
+    checks: Vec<verify::ModuleCheck>,
+    #[serde(
+        rename = "duration_secs",
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    duration: Duration,
+}
+
+impl RepoResult {
+    fn count(&self, status: PackageStatus) -> usize {
+        self.packages.iter().filter(|p| p.status == status).count()
+    }
+
+    fn failed_packages(&self) -> impl Iterator<Item = &PackageRecord> {
+        self.packages
+            .iter()
+            .filter(|p| p.status == PackageStatus::Failed)
+    }
+
+    /// One line per failed package and other error
+    fn failure_messages(&self) -> Vec<String> {
+        self.failed_packages()
+            .map(|p| {
+                format!(
+                    "moon add {} failed: {}",
+                    p.name,
+                    p.error.as_deref().unwrap_or_default()
+                )
+            })
+            .chain(self.errors.iter().cloned())
+            .collect()
+    }
+}
+
+/// What happened to one package during apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PackageStatus {
+    /// Its declared version changed
+    Updated,
+    /// moon add ran but the version stayed the same
+    Unchanged,
+    Failed,
+    /// moon add never ran (dry run, failed update, fail-fast)
+    Skipped,
+}
+
+/// Per-package outcome of apply
+#[derive(Debug, Serialize, Deserialize)]
+struct PackageRecord {
+    name: String,
+    status: PackageStatus,
+    old_version: Option<String>,
+    new_version: Option<String>,
+    /// Time spent in moon add across all attempts
+    #[serde(
+        rename = "duration_secs",
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    duration: Duration,
+    /// Number of moon add runs (more than one with --repeat)
+    attempts: u32,
+    error: Option<String>,
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(timestamp::reported(*duration).as_secs_f64())
+}
+
+fn deserialize_secs<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+/// Resolved options for processing a single repo
+struct ApplyOptions {
+    skip_update: bool,
+    repeat: u32,
+    until_stable: bool,
+    packages: package_filter::Filter,
+    write_justfile: bool,
+    justfile_mode: WriteMode,
+    /// Justfile template with the configured recipes
+    justfile_template: justfile::Template,
+    task_runner: taskfile::TaskRunner,
+    verify: bool,
+    /// Minimum release age in days for moon add targets
+    min_age: Option<u64>,
+    fix_yanked: bool,
+    update_git_deps: bool,
+    /// Packages held at their current version
+    pins: Vec<pins::Pin>,
+    /// Registry index for `--min-age`, `--fix-yanked` and dry-run previews
+    registry: registry::Registry,
+    /// Reviewed plan fixing the packages and versions to update
+    plan: Option<plan::Plan>,
+    /// Cancels the run; steps in progress stop at their next check
+    cancel: cancel::CancellationToken,
+    /// Told about repos, commands and results as the run goes
+    observer: Arc<dyn observer::Observer>,
+    /// Targets looked up in the resolve phase
+    resolved: Resolved,
+    dry_run: bool,
+    verbose: bool,
+}
+
+/// What the resolve phase found for the other steps
+#[derive(Default)]
+struct Resolved {
+    /// `moon add` spec by repo root and package; `None` to leave it as is
+    specs: Mutex<HashMap<(PathBuf, String), Option<String>>>,
+    /// Head commit (or the lookup error) by manifest and git dependency
+    revs: Mutex<HashMap<(PathBuf, String), std::result::Result<String, String>>>,
+}
+
+/// Upper bound on passes for `--repeat-until-stable`
+const MAX_STABLE_PASSES: u32 = 10;
+
+impl ApplyOptions {
+    /// `moon add` spec for `dep`, as resolved or else looked up now
+    fn add_spec(&self, repo: &RepoInfo, dep: &str) -> Option<String> {
+        let key = (repo.root.clone(), dep.to_string());
+        match self.resolved.specs.lock().unwrap().get(&key) {
+            Some(spec) => spec.clone(),
+            None => add_spec(repo, dep, self),
+        }
+    }
+
+    /// Maximum number of moon add passes
+    fn max_passes(&self) -> u32 {
+        if self.until_stable {
+            MAX_STABLE_PASSES
+        } else {
+            self.repeat
+        }
+    }
+}
+
+// =============================================================================
+// Default Ignore Rules
+// =============================================================================
+
+const DEFAULT_IGNORES: &[&str] = &[
+    "target",
+    "node_modules",
+    "dist",
+    "build",
+    "vendor",
+    "skills",
+];
+
+// =============================================================================
+// Justfile Template
+// =============================================================================
+
+const JUSTFILE_TEMPLATE: &str = r#"# https://github.com/mizchi/moonbit-template
+# SPDX-License-Identifier: MIT
+# MoonBit Project Commands
+
+target := "js"
+
+default: check test
+
+fmt:
+    moon fmt
+
+check:
+    moon check --deny-warn --target {{target}}
+
+test:
+    moon test --target {{target}}
+
+test-update:
+    moon test --update --target {{target}}
+
+run:
+    moon run src/main --target {{target}}
+
+info:
+    moon info
+
+clean:
+    moon clean
+
+release-check: fmt info check test
+"#;
+
+// =============================================================================
+// Core Logic
+// =============================================================================
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.plain {
+        output::set_plain();
+    }
+    if let Some(dir) = data_dir() {
+        audit::init(dir.join(audit::FILE));
+    }
+
+    let result = run(cli);
+    // Denied diagnostics fail the run, as do errors
+    let clean = diagnostics::finish();
+    match result {
+        Ok(success) => {
+            if success && clean {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            }
+        }
+        Err(e) => {
+            let code = errors::classify(&e);
+            eprintln!("Error[{}]: {e:#}", code.info().id);
+            if code != errors::Code::Unclassified {
+                eprintln!(
+                    "(run `moon-dst explain {}` for the cause and how to fix it)",
+                    code.info().id
+                );
+            }
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<bool> {
+    // Check moon CLI availability
+    if cli.command.requires_moon() {
+        check_moon_available()?;
+    }
+
+    match cli.command {
+        Commands::Scan {
+            common,
+            json,
+            format,
+            git_ref,
+            strict_pins,
+            limit,
+            first_match,
+            suggest_ignores,
+        } => cmd_scan(
+            common,
+            if json { OutputFormat::Json } else { format },
+            git_ref,
+            strict_pins,
+            if first_match { Some(1) } else { limit },
+            first_match,
+            suggest_ignores,
+        ),
+        Commands::Outdated {
+            common,
+            format,
+            git_ref,
+            baseline,
+            write_baseline,
+        } => cmd_outdated(common, format, git_ref, baseline, write_baseline),
+        Commands::Search {
+            common,
+            query,
+            format,
+        } => cmd_search(common, query, format),
+        Commands::Info {
+            common,
+            package,
+            format,
+        } => cmd_info(common, package, format),
+        Commands::Vendor {
+            common,
+            out,
+            registry_url,
+            verify_checksums,
+        } => cmd_vendor(common, out, registry_url, verify_checksums),
+        Commands::Verify { dir, format } => cmd_verify(dir, format),
+        Commands::Graph {
+            common,
+            format,
+            action,
+        } => match action {
+            None => cmd_graph(common, format),
+            Some(GraphAction::Stats {
+                common,
+                top,
+                format,
+            }) => cmd_graph_stats(common, top, format),
+        },
+        Commands::Provenance {
+            common,
+            format,
+            offline,
+        } => cmd_provenance(common, format, offline),
+        Commands::Lint {
+            common,
+            fix,
+            format,
+        } => cmd_lint(common, fix, format),
+        Commands::Gate {
+            common,
+            format,
+            fail_on,
+            max_major_lag,
+            checks,
+            baseline,
+        } => cmd_gate(common, format, fail_on, max_major_lag, checks, baseline),
+        Commands::Pins { common, action } => cmd_pins(common, action),
+        Commands::Align {
+            common,
+            choose,
+            packages,
+        } => cmd_align(common, choose, packages),
+        Commands::DedupeReport {
+            common,
+            format,
+            min_versions,
+        } => cmd_dedupe_report(common, format, min_versions as usize),
+        Commands::ApplyTemplates { common, pack, mode } => cmd_apply_templates(common, pack, mode),
+        Commands::Drift {
+            common,
+            pack,
+            fix,
+            fix_mode,
+        } => cmd_drift(common, pack, fix, fix_mode),
+        Commands::Plan {
+            common,
+            packages,
+            package_match,
+            min_age,
+            out,
+        } => cmd_plan(common, packages, package_match, min_age, out),
+        Commands::Apply(args) => cmd_apply(args),
+        Commands::Test {
+            common,
+            junit,
+            args,
+        } => cmd_test(common, junit, args),
+        Commands::Just {
+            common,
+            mode,
+            task_runner,
+            recipes,
+            extra_recipes,
+            set,
+            no_root_file,
+        } => cmd_just(
+            common,
+            mode,
+            task_runner,
+            recipes,
+            extra_recipes,
+            set,
+            no_root_file,
+        ),
+        Commands::Gitignore { common, mode } => cmd_gitignore(common, mode),
+        Commands::GrepUsage {
+            common,
+            package,
+            symbol,
+            format,
+        } => cmd_grep_usage(common, &package, symbol.as_deref(), format),
+        Commands::Rename {
+            common,
+            old,
+            new,
+            version,
+            sources,
+        } => cmd_rename(common, &old, &new, version.as_deref(), sources),
+        Commands::TryVersion {
+            common,
+            candidate: (package, version),
+            no_test,
+            format,
+        } => cmd_try_version(common, &package, &version, !no_test, format),
+        Commands::RenovateConfig {
+            common,
+            mode,
+            datasource_url,
+        } => cmd_renovate_config(common, mode, datasource_url),
+        Commands::Vscode {
+            common,
+            mode,
+            workspace,
+            no_workspace,
+        } => cmd_vscode(common, mode, workspace, no_workspace),
+        Commands::Spdx {
+            common,
+            license,
+            holder,
+            excludes,
+            fix,
+        } => cmd_spdx(common, license, holder, excludes, fix),
+        Commands::Inventory {
+            common,
+            html,
+            git_ref,
+        } => cmd_inventory(common, html, git_ref),
+        Commands::Config { action } => cmd_config(action),
+        Commands::Cache { common, action } => cmd_cache(common, action),
+        Commands::Clean {
+            common,
+            targets_only,
+            mooncakes_only,
+            older_than,
+        } => cmd_clean(common, targets_only, mooncakes_only, older_than),
+        Commands::Toolchain { action } => cmd_toolchain(action),
+        Commands::Workspace { root, action } => cmd_workspace(&root, action),
+        Commands::SelfCheck {
+            toolchain,
+            update_toolchain,
+            feed,
+            dry_run,
+        } => cmd_self_check(
+            toolchain || update_toolchain,
+            update_toolchain,
+            &feed,
+            dry_run,
+        ),
+        Commands::SelfUpdate { check, release_url } => cmd_self_update(check, &release_url),
+        Commands::Explain { code } => cmd_explain(code.as_deref()),
+        Commands::History { root, action } => cmd_history(root.as_deref(), action),
+        Commands::AuditLog { action } => cmd_audit_log(action),
+        Commands::ShowError { id } => cmd_show_error(&id),
+    }
+}
+
+/// Get the moon binary path, checking common installation locations
+fn get_moon_bin() -> PathBuf {
+    // First check if moon is in PATH
+    if let Ok(output) = Command::new("moon").arg("version").logged_output() {
+        if output.status.success() {
+            return PathBuf::from("moon");
+        }
+    }
+
+    // Check ~/.moon/bin/moon (common MoonBit installation path)
+    if let Some(home) = std::env::var_os("HOME") {
+        let moon_path = PathBuf::from(home).join(".moon/bin/moon");
+        if moon_path.exists() {
+            return moon_path;
+        }
+    }
+
+    // Fallback to "moon" and let it fail with a clear error
+    PathBuf::from("moon")
+}
+
+/// moon-dst's own data directory: `$MOON_DST_HOME` or `~/.moon-dst`
+fn data_dir() -> Option<PathBuf> {
+    std::env::var_os("MOON_DST_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".moon-dst")))
+}
+
+fn check_moon_available() -> Result<()> {
+    let moon_bin = get_moon_bin();
+    let output = Command::new(&moon_bin).arg("version").logged_output();
+
+    match output {
+        Ok(o) if o.status.success() => Ok(()),
+        _ => bail!(errors::Coded::new(errors::Code::MoonNotFound, "'moon' CLI not found. Checked PATH and ~/.moon/bin/moon. Please install MoonBit first.")),
+    }
+}
+
+/// Installed toolchain version, queried only if some repo pins one
+fn installed_toolchain(repos: &[RepoInfo]) -> Option<String> {
+    if repos.iter().all(|r| r.toolchain.is_none()) {
+        return None;
+    }
+    let installed = toolchain::installed();
+    if installed.is_none() {
+        diagnostics::report(
+            diagnostics::Code::Toolchain,
+            "Could not determine the installed moon version",
+        );
+    }
+    installed
+}
+
+/// Drop repos with an active `.moon-dst-skip` marker
+fn skip_marked(repos: &mut Vec<RepoInfo>) {
+    let now = timestamp::now();
+    repos.retain(|repo| match &repo.skip {
+        Some(marker) if marker.is_active(now) => {
+            println!("[{}] Skipping: {marker}", ospath::display(&repo.root));
+            false
+        }
+        _ => true,
+    });
+}
+
+/// Assign each pinned repo the toolchain satisfying its pin
+///
+/// Repos that nothing installed satisfies keep the default toolchain, or are
+/// dropped with a message when `respect` is set.
+fn select_toolchains(repos: &mut Vec<RepoInfo>, respect: bool) {
+    let installed = installed_toolchain(repos);
+    let managed = toolchain::managed();
+    repos.retain_mut(
+        |repo| match repo.select_toolchain(installed.as_deref(), &managed) {
+            Some(selected) => {
+                repo.moon = selected;
+                true
+            }
+            None if respect => {
+                println!(
+                    "[{}] Skipping: requires moon {}, installed {}",
+                    ospath::display(&repo.root),
+                    repo.toolchain.as_deref().unwrap_or_default(),
+                    installed.as_deref().unwrap_or("?")
+                );
+                false
+            }
+            None => true,
+        },
+    );
+}
+
+// =============================================================================
+// Scan Command
+// =============================================================================
+
+fn cmd_scan(
+    common: CommonOptions,
+    format: OutputFormat,
+    git_ref: Option<String>,
+    strict_pins: bool,
+    limit: Option<usize>,
+    first_match: bool,
+    suggest_ignores: bool,
+) -> Result<bool> {
+    format.check_supported(
+        "scan",
+        &[
+            OutputFormat::Text,
+            OutputFormat::Json,
+            OutputFormat::Csv,
+            OutputFormat::Tsv,
+            OutputFormat::Ctags,
+            OutputFormat::LspWorkspace,
+        ],
+    )?;
+    let config = Config::resolve(&common)?;
+    let index = matches!(format, OutputFormat::Ctags | OutputFormat::LspWorkspace);
+    if index && git_ref.is_some() {
+        bail!(errors::Coded::new(errors::Code::InvalidValue, "--format ctags and lsp-workspace index the worktree and cannot be combined with --git-ref"));
+    }
+    if common.stats || suggest_ignores {
+        traversal::enable();
+    }
+    let flat_text =
+        format == OutputFormat::Text && config.group_output.value == grouping::GroupOutput::Flat;
+    if git_ref.is_none() && (flat_text || first_match) {
+        return scan_streaming(
+            &common,
+            &config,
+            limit,
+            first_match,
+            suggest_ignores,
+            strict_pins,
+        );
+    }
+    let mut repos = discover_report(common.root(), &config, git_ref.as_deref())?;
+    if let Some(limit) = limit {
+        repos.truncate(limit);
+    }
+    if common.stats {
+        traversal::report();
+    }
+    if index {
+        let modules = project_index::modules(&repos);
+        if format == OutputFormat::Ctags {
+            let base = common.root().canonicalize()?;
+            print!("{}", project_index::ctags(&modules, &base));
+        } else {
+            let workspace = project_index::lsp_workspace(modules);
+            println!("{}", output::json(&workspace)?);
+        }
+        return Ok(true);
+    }
+    let installed = installed_toolchain(&repos);
+    let installed = installed.as_deref();
+    let managed = toolchain::managed();
+    let mismatch = |repo: &RepoInfo| repo.select_toolchain(installed, &managed).is_none();
+    let registry = registry_reader(&config);
+    prefetch_deps(&registry, &repos);
+    let now = timestamp::now();
+    let scores: Vec<freshness::Score> = repos
+        .iter()
+        .map(|repo| freshness::repo_score(repo, &registry, now))
+        .collect();
+    report_registry_stats(&common, &registry);
+    let fleet_freshness = (freshness::fleet_score(&scores) * 10.0).round() / 10.0;
+    let layout_issues = layout::check(&repos, config.nested_modules.value);
+    for issue in &layout_issues {
+        diagnostics::report(diagnostics::Code::ModuleLayout, issue.message.clone());
+    }
+    warn_renamed_deps(&config, &repos);
+
+    if let Some(delimiter) = format.delimiter() {
+        let rows = outdated::collect_rows(&repos, &registry);
+        let fields: Vec<Vec<String>> = rows.iter().map(|r| r.fields()).collect();
+        print!(
+            "{}",
+            output::render_delimited(delimiter, outdated::HEADER, &fields)
+        );
+    } else if format == OutputFormat::Json {
+        let output = ScanOutput {
+            repos: repos
+                .iter()
+                .zip(&scores)
+                .map(|(r, &freshness)| RepoOutput {
+                    repo_root: r.location(),
+                    toolchain: r.toolchain.clone(),
+                    toolchain_mismatch: mismatch(r),
+                    runner: r.runner_spec.to_string(),
+                    owners: r.owners.clone(),
+                    skipped: r.skip.clone().filter(|m| m.is_active(now)),
+                    freshness,
+                    moon_mods: r
+                        .moon_mods
+                        .iter()
+                        .map(|m| MoonModOutput {
+                            path: m
+                                .path
+                                .strip_prefix(&r.root)
+                                .unwrap_or(&m.path)
+                                .display()
+                                .to_string(),
+                            deps: m.deps.iter().map(|d| d.name.clone()).collect(),
+                            dependencies: m.deps.iter().map(DepOutput::from).collect(),
+                            flagged: m
+                                .deps
+                                .iter()
+                                .filter_map(|d| {
+                                    Some(FlaggedDep {
+                                        flag: d.flag(&registry)?.to_string(),
+                                        name: d.name.clone(),
+                                        version: d.version.clone()?,
+                                    })
+                                })
+                                .collect(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            fleet_freshness,
+            layout: layout_issues,
+        };
+        println!("{}", output::json(&output)?);
+    } else {
+        let mut flagged = 0;
+        let mut print_repo = |repo: &RepoInfo, score: &freshness::Score| {
+            flagged += print_scan_repo(repo, score, &registry, installed, &managed, now);
+        };
+        let rows: Vec<(&RepoInfo, &freshness::Score)> = repos.iter().zip(&scores).collect();
+        match config.group_output.value {
+            grouping::GroupOutput::Flat => {
+                for (repo, score) in &rows {
+                    print_repo(repo, score);
+                }
+            }
+            mode => {
+                let roots = canonical_roots(common.root(), &config);
+                let groups = grouping::group(&rows, |(repo, _)| {
+                    grouping::labels(&repo.location_path(), &roots, mode)
+                });
+                grouping::walk(&groups, &mut |level, group| {
+                    let deps: usize = group
+                        .items
+                        .iter()
+                        .flat_map(|(repo, _)| &repo.moon_mods)
+                        .map(|m| m.deps.len())
+                        .sum();
+                    let subtotals = format!("{} repos, {deps} dependencies", group.items.len());
+                    println!("{}\n", grouping::heading(level, &group.label, &subtotals));
+                    for (repo, score) in &group.own {
+                        print_repo(repo, score);
+                    }
+                });
+            }
+        }
+
+        print_scan_summary(&repos, flagged, fleet_freshness, installed, &managed);
+        if suggest_ignores {
+            print_ignore_suggestions(&config)?;
+        }
+    }
+
+    Ok(warn_expired_pins(&config, now) || !strict_pins)
+}
+
+/// `scan` printing each repo in text as soon as discovery has it, so a
+/// large tree shows progress; stops after `limit` repos
+fn scan_streaming(
+    common: &CommonOptions,
+    config: &Config,
+    limit: Option<usize>,
+    first_match: bool,
+    suggest_ignores: bool,
+    strict_pins: bool,
+) -> Result<bool> {
+    let registry = registry_reader(config);
+    let managed = toolchain::managed();
+    let now = timestamp::now();
+    // Asked once, when the first repo pinning a toolchain shows up
+    let installed: OnceCell<Option<String>> = OnceCell::new();
+    let mut repos = Vec::new();
+    let mut scores = Vec::new();
+    let mut flagged = 0;
+    stream_fleet(common.root(), config, &mut |repo| {
+        if first_match {
+            println!("{}", repo.location());
+        } else {
+            let one = std::slice::from_ref(&repo);
+            let installed = match repo.toolchain {
+                Some(_) => installed
+                    .get_or_init(|| installed_toolchain(one))
+                    .as_deref(),
+                None => None,
+            };
+            prefetch_deps(&registry, one);
+            let score = freshness::repo_score(&repo, &registry, now);
+            flagged += print_scan_repo(&repo, &score, &registry, installed, &managed, now);
+            scores.push(score);
+        }
+        repos.push(repo);
+        Ok(match limit {
+            Some(limit) if repos.len() >= limit => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        })
+    })?;
+    if first_match {
+        return Ok(!repos.is_empty());
+    }
+    report_registry_stats(common, &registry);
+    if common.stats {
+        traversal::report();
+    }
+    for issue in layout::check(&repos, config.nested_modules.value) {
+        diagnostics::report(diagnostics::Code::ModuleLayout, issue.message);
+    }
+    warn_renamed_deps(config, &repos);
+    let fleet_freshness = (freshness::fleet_score(&scores) * 10.0).round() / 10.0;
+    let installed = installed.get().cloned().flatten();
+    print_scan_summary(
+        &repos,
+        flagged,
+        fleet_freshness,
+        installed.as_deref(),
+        &managed,
+    );
+    if limit.is_some_and(|limit| repos.len() >= limit) {
+        println!("Stopped after {} repos (--limit)", repos.len());
+    }
+    if suggest_ignores {
+        print_ignore_suggestions(config)?;
+    }
+    Ok(warn_expired_pins(config, now) || !strict_pins)
+}
+
+/// `scan --suggest-ignores`: large directories the walk found no modules
+/// in, then the `ignore` setting with them added, ready to paste
+fn print_ignore_suggestions(config: &Config) -> Result<()> {
+    let suggestions = traversal::suggestions();
+    println!();
+    if suggestions.is_empty() {
+        println!("No ignore suggestions: every large directory holds modules");
+        return Ok(());
+    }
+    println!("Ignore suggestions:");
+    let mut ignore = config.ignore.value.clone();
+    for suggestion in &suggestions {
+        let name = suggestion.name();
+        println!("  {suggestion} — consider --ignore {name}");
+        if !ignore.contains(&name) {
+            ignore.push(name);
+        }
+    }
+    let snippet = toml::to_string(&BTreeMap::from([("ignore", ignore)]))?;
+    print!("\n# {}\n{snippet}", config::PROJECT_CONFIG_FILE);
+    Ok(())
+}
+
+/// Print one repo of `scan` in text; returns how many of its dependencies
+/// are pinned to yanked or deprecated versions
+fn print_scan_repo(
+    repo: &RepoInfo,
+    score: &freshness::Score,
+    registry: &registry::Registry,
+    installed: Option<&str>,
+    managed: &[(String, PathBuf)],
+    now: u64,
+) -> usize {
+    let mut flagged = 0;
+    match &repo.skip {
+        Some(marker) if marker.is_active(now) => {
+            println!("Repository: {} skipped ({marker})", repo.location())
+        }
+        Some(marker) => {
+            println!("Repository: {}", repo.location());
+            if let Some(until) = marker.until {
+                println!("  Skip marker expired on {}", skip::date(until));
+            }
+        }
+        None => println!("Repository: {}", repo.location()),
+    }
+    if score.scored > 0 {
+        println!(
+            "  Freshness: {:.1} ({}/{} outdated)",
+            score.score, score.outdated, score.scored
+        );
+    }
+    if repo.runner_spec != runner::RunnerSpec::Local {
+        println!("  Runner: {}", repo.runner_spec);
+    }
+    if !repo.owners.is_empty() {
+        println!("  Owners: {}", repo.owners.join(" "));
+    }
+    if let Some(required) = &repo.toolchain {
+        match repo.select_toolchain(installed, managed) {
+            None => println!(
+                "  Toolchain: {required} (MISMATCH: installed {})",
+                installed.unwrap_or("?")
+            ),
+            Some(toolchain::Toolchain::Default) => println!("  Toolchain: {required}"),
+            Some(selected) => println!("  Toolchain: {required} ({selected})"),
+        }
+    }
+    for moon_mod in &repo.moon_mods {
+        let rel_path = moon_mod
+            .path
+            .strip_prefix(&repo.root)
+            .unwrap_or(&moon_mod.path);
+        println!("  {}", ospath::display(rel_path));
+        for dep in &moon_mod.deps {
+            match (&dep.source, dep.flag(registry)) {
+                (_, Some(flag)) => {
+                    flagged += 1;
+                    let text = format!(
+                        "{} ({} {})",
+                        dep.name,
+                        dep.version.as_deref().unwrap_or_default(),
+                        flag.to_string().to_uppercase()
+                    );
+                    println!("    {}", output::item("Dependency", &text));
+                }
+                (DepSource::Registry, None) => {
+                    println!("    {}", output::item("Dependency", &dep.name))
+                }
+                (source, None) => println!(
+                    "    {}",
+                    output::item("Dependency", &format!("{} ({source})", dep.name))
+                ),
+            }
+        }
+    }
+    println!();
+    flagged
+}
+
+/// The totals after the repos of `scan` in text
+fn print_scan_summary(
+    repos: &[RepoInfo],
+    flagged: usize,
+    fleet_freshness: f64,
+    installed: Option<&str>,
+    managed: &[(String, PathBuf)],
+) {
+    let total_mods: usize = repos.iter().map(|r| r.moon_mods.len()).sum();
+    let total_deps: usize = repos
+        .iter()
+        .flat_map(|r| &r.moon_mods)
+        .map(|m| m.deps.len())
+        .sum();
+
+    let summary = [
+        format!("{} repos", repos.len()),
+        format!("{total_mods} moon.mod.json files"),
+        format!("{total_deps} dependencies"),
+    ];
+    for line in output::facts("Summary", &summary) {
+        println!("{line}");
+    }
+    println!("Fleet freshness: {fleet_freshness:.1}/100");
+    if flagged > 0 {
+        diagnostics::report(
+            diagnostics::Code::FlaggedVersion,
+            format!("{flagged} dependencies are pinned to yanked or deprecated versions (fix with `moon-dst apply --fix-yanked`)"),
+        );
+    }
+
+    let mismatched = repos
+        .iter()
+        .filter(|r| r.select_toolchain(installed, managed).is_none())
+        .count();
+    if mismatched > 0 {
+        diagnostics::report(
+            diagnostics::Code::Toolchain,
+            format!(
+                "{mismatched} repos pin a toolchain other than the installed moon {}",
+                installed.unwrap_or("?")
+            ),
+        );
+    }
+}
+
+/// Warn about expired pins; returns false if there are any
+fn warn_expired_pins(config: &Config, now: u64) -> bool {
+    let mut none = true;
+    for pin in pins::expired(&config.pins.value, now) {
+        diagnostics::report(
+            diagnostics::Code::ExpiredPin,
+            format!("pin {pin} has expired; renew or remove it"),
+        );
+        none = false;
+    }
+    none
+}
+
+// =============================================================================
+// Outdated Command
+// =============================================================================
+
+fn cmd_outdated(
+    common: CommonOptions,
+    format: OutputFormat,
+    git_ref: Option<String>,
+    baseline_file: Option<PathBuf>,
+    write_baseline: bool,
+) -> Result<bool> {
+    format.check_supported(
+        "outdated",
+        &[
+            OutputFormat::Text,
+            OutputFormat::Json,
+            OutputFormat::Csv,
+            OutputFormat::Tsv,
+            OutputFormat::Github,
+        ],
+    )?;
+    let config = Config::resolve(&common)?;
+    let repos = discover_report(common.root(), &config, git_ref.as_deref())?;
+
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+
+    let rows: Vec<outdated::DepRow> = outdated::collect_rows(&repos, &registry)
+        .into_iter()
+        .filter(|r| r.outdated)
+        .collect();
+    report_registry_stats(&common, &registry);
+
+    let root = common.root().canonicalize()?;
+    let repo_key = |row: &outdated::DepRow| baseline_repo(row, &root);
+    if write_baseline {
+        let path = baseline_file.unwrap_or_else(|| PathBuf::from(baseline::DEFAULT_FILE));
+        let previous = match path.exists() {
+            true => baseline::Baseline::read(&path)?,
+            false => baseline::Baseline::default(),
+        };
+        let entries = rows
+            .iter()
+            .map(|row| baseline::Entry {
+                repo: repo_key(row),
+                module: row.module.clone(),
+                dep: row.dep.clone(),
+                declared: row.declared.clone(),
+                reason: String::new(),
+            })
+            .collect();
+        let written = previous.rewritten(entries);
+        written.write(&path)?;
+        println!(
+            "Wrote {} accepted outdated dependencies to {}",
+            written.accepted.len(),
+            path.display()
+        );
+        return Ok(true);
+    }
+    let baseline = baseline_file
+        .as_deref()
+        .map(baseline::Baseline::read)
+        .transpose()?;
+    let (accepted, rows): (Vec<outdated::DepRow>, Vec<outdated::DepRow>) =
+        rows.into_iter().partition(|row| {
+            baseline
+                .as_ref()
+                .is_some_and(|b| b.accepts(&repo_key(row), &row.module, &row.dep).is_some())
+        });
+    for entry in baseline.iter().flat_map(|b| &b.accepted) {
+        let outdated = accepted.iter().any(|row| {
+            repo_key(row) == entry.repo && row.module == entry.module && row.dep == entry.dep
+        });
+        if !outdated {
+            diagnostics::report(
+                diagnostics::Code::StaleBaseline,
+                format!(
+                    "{} in {} ({}) is no longer outdated; drop it with --write-baseline",
+                    entry.dep, entry.repo, entry.module
+                ),
+            );
+        }
+    }
+    let failed = baseline.is_some() && !rows.is_empty();
+
+    if let Some(delimiter) = format.delimiter() {
+        let fields: Vec<Vec<String>> = rows.iter().map(|r| r.fields()).collect();
+        print!(
+            "{}",
+            output::render_delimited(delimiter, outdated::HEADER, &fields)
+        );
+    } else if format == OutputFormat::Json {
+        println!(
+            "{}",
+            output::json(&OutdatedOutput {
+                outdated: rows,
+                accepted
+            })?
+        );
+    } else if format == OutputFormat::Github {
+        let root = common.root().canonicalize()?;
+        let mut summary = format!(
+            "## moon-dst outdated\n\n{} outdated dependencies\n\n| Repository | Module | Dependency | Declared | Latest |\n|---|---|---|---|---|\n",
+            rows.len()
+        );
+        for row in &rows {
+            let file = findings::relative_uri(&row.manifest, &root);
+            let line = std::fs::read_to_string(&row.manifest)
+                .ok()
+                .and_then(|content| findings::find_line(&content, &format!("\"{}\"", row.dep)));
+            let declared = row.declared.as_deref().unwrap_or("?");
+            let latest = row.latest.as_deref().unwrap_or("?");
+            println!(
+                "{}",
+                github::annotation(
+                    github::AnnotationLevel::Warning,
+                    Some(&file),
+                    line,
+                    Some("outdated dependency"),
+                    &format!("{} {declared} -> {latest}", row.dep),
+                )
+            );
+            summary.push_str(&format!(
+                "| {} | {} | {} | {declared} | {latest} |\n",
+                github::escape_cell(&row.repo),
+                github::escape_cell(&row.module),
+                github::escape_cell(&row.dep)
+            ));
+        }
+        github::append_step_summary(&summary)?;
+    } else {
+        let mut current_repo = None;
+        for row in &rows {
+            if current_repo != Some(&row.repo) {
+                if current_repo.is_some() {
+                    println!();
+                }
+                println!("Repository: {}", row.repo);
+                current_repo = Some(&row.repo);
+            }
+            println!(
+                "  {} ({}): {} -> {}",
+                row.dep,
+                row.module,
+                row.declared.as_deref().unwrap_or("?"),
+                row.latest.as_deref().unwrap_or("?")
+            );
+        }
+        if !rows.is_empty() {
+            println!();
+        }
+
+        let repo_count = rows
+            .iter()
+            .map(|r| &r.repo)
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+        let mut summary = format!(
+            "Summary: {} outdated dependencies in {} repos",
+            rows.len(),
+            repo_count
+        );
+        if baseline.is_some() {
+            summary.push_str(&format!(" ({} accepted in the baseline)", accepted.len()));
+        }
+        println!("{summary}");
+    }
+
+    Ok(!failed)
+}
+
+/// The repo of `row` as outdated baselines name it: its path below `root`,
+/// so a baseline holds on any checkout
+fn baseline_repo(row: &outdated::DepRow, root: &Path) -> String {
+    match Path::new(&row.repo).strip_prefix(root) {
+        Ok(rel) => ospath::display(rel).to_string(),
+        Err(_) => row.repo.clone(),
+    }
+}
+
+// =============================================================================
+// Search Command
+// =============================================================================
+
+/// A module depending on a registry package
+#[derive(Serialize, Debug, Clone)]
+struct PackageUser {
+    repo: String,
+    module: String,
+    version: Option<String>,
+}
+
+impl std::fmt::Display for PackageUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.repo,
+            self.version.as_deref().unwrap_or("unversioned")
+        )
+    }
+}
+
+/// Registry dependencies of `repos`: package name to the modules using it
+fn package_users(repos: &[RepoInfo]) -> BTreeMap<String, Vec<PackageUser>> {
+    let mut users: BTreeMap<String, Vec<PackageUser>> = BTreeMap::new();
+    for repo in repos {
+        for moon_mod in &repo.moon_mods {
+            let module = moon_mod.name.clone().unwrap_or_else(|| {
+                verify::module_label(&repo.root, moon_mod.path.parent().unwrap_or(&repo.root))
+            });
+            for dep in moon_mod.deps.iter().filter(|dep| dep.is_registry()) {
+                users
+                    .entry(dep.name.clone())
+                    .or_default()
+                    .push(PackageUser {
+                        repo: repo.location(),
+                        module: module.clone(),
+                        version: dep.version.clone(),
+                    });
+            }
+        }
+    }
+    users
+}
+
+/// The registry index to read: the `index-url` of the `[registry]` config
+/// section, mirrored into the data directory, or else moon's local copy
+fn registry_reader(config: &Config) -> registry::Registry {
+    match (&config.registry_index_url.value, data_dir()) {
+        (Some(url), Some(dir)) => registry::Registry::remote(
+            url,
+            dir.join("registry-index"),
+            config.registry_max_parallel.value,
+        ),
+        _ => registry::Registry::open(),
+    }
+}
+
+/// Refresh the index files of all registry dependencies of `repos` in one
+/// batch, rather than one request per lookup
+fn prefetch_deps(registry: &registry::Registry, repos: &[RepoInfo]) {
+    let names: BTreeSet<&str> = repos
+        .iter()
+        .flat_map(|repo| &repo.moon_mods)
+        .flat_map(|m| m.deps.iter().chain(&m.bin_deps))
+        .filter(|d| d.is_registry())
+        .map(|d| d.name.as_str())
+        .collect();
+    registry.prefetch(names);
+}
+
+/// How registry lookups were answered, on stderr with `--stats`
+fn report_registry_stats(common: &CommonOptions, registry: &registry::Registry) {
+    if common.stats {
+        eprintln!("{}", registry.stats().summary());
+    }
+}
+
+/// Opens the registry index, warning when `moon update` never ran
+fn open_registry(config: &Config) -> registry::Registry {
+    let registry = registry_reader(config);
+    if !registry.is_remote() && !registry.index_dir().exists() {
+        diagnostics::report(
+            diagnostics::Code::RegistryIndex,
+            format!(
+                "registry index not found at {} (run `moon update`)",
+                registry.index_dir().display()
+            ),
+        );
+    }
+    registry
+}
+
+/// A `search` match, for `--format json`
+#[derive(Serialize)]
+struct SearchResult {
+    name: String,
+    latest: Option<String>,
+    versions: Vec<String>,
+    description: Option<String>,
+    used_by: Vec<PackageUser>,
+}
+
+/// Versions shown per package in text output
+const SEARCH_VERSIONS: usize = 5;
+
+fn cmd_search(common: CommonOptions, query: String, format: OutputFormat) -> Result<bool> {
+    format.check_supported("search", &[OutputFormat::Text, OutputFormat::Json])?;
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let mut users = package_users(&repos);
+    let registry = open_registry(&config);
+
+    let names = registry.search(&query);
+    registry.prefetch(names.iter().map(String::as_str));
+    let results: Vec<SearchResult> = names
+        .into_iter()
+        .map(|name| {
+            let latest = registry.latest(&name);
+            SearchResult {
+                latest: latest.as_ref().map(|r| r.version.clone()),
+                versions: registry
+                    .releases(&name)
+                    .into_iter()
+                    .map(|r| r.version)
+                    .collect(),
+                description: latest.and_then(|r| r.description),
+                used_by: users.remove(&name).unwrap_or_default(),
+                name,
+            }
+        })
+        .collect();
+
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&results)?);
+        return Ok(true);
+    }
+    if results.is_empty() {
+        println!("No packages match `{query}`");
+        return Ok(true);
+    }
+    for result in &results {
+        println!(
+            "{} {}",
+            result.name,
+            result.latest.as_deref().unwrap_or("(no versions)")
+        );
+        if let Some(description) = &result.description {
+            println!("  {description}");
+        }
+        let shown = result.versions.len().saturating_sub(SEARCH_VERSIONS);
+        let mut versions = result.versions[shown..].join(", ");
+        if shown > 0 {
+            versions = format!("{versions} (and {shown} older)");
+        }
+        println!("  Versions: {versions}");
+        if !result.used_by.is_empty() {
+            let used_by: Vec<String> = result.used_by.iter().map(|u| u.to_string()).collect();
+            println!("  Used by: {}", used_by.join(", "));
+        }
+    }
+    println!("\nSummary: {} packages match `{query}`", results.len());
+    Ok(true)
+}
+
+// =============================================================================
+// Info Command
+// =============================================================================
+
+/// `info` output, for `--format json`
+#[derive(Serialize)]
+struct PackageInfo {
+    name: String,
+    latest: Option<String>,
+    description: Option<String>,
+    license: Option<String>,
+    repository: Option<String>,
+    /// Newest first
+    versions: Vec<VersionInfo>,
+    used_by: Vec<PackageUser>,
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    version: String,
+    /// Publish date (YYYY-MM-DD)
+    published: Option<String>,
+    /// `yanked` or `deprecated[: message]`
+    flag: Option<String>,
+}
+
+fn cmd_info(common: CommonOptions, package: String, format: OutputFormat) -> Result<bool> {
+    format.check_supported("info", &[OutputFormat::Text, OutputFormat::Json])?;
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let used_by = package_users(&repos).remove(&package).unwrap_or_default();
+    let registry = open_registry(&config);
+
+    let releases = registry.releases(&package);
+    if releases.is_empty() && used_by.is_empty() {
+        bail!(errors::Coded::new(
+            errors::Code::UnknownPackage,
+            format!("Package `{package}` not found in the registry index or any repo")
+        ));
+    }
+    let latest = registry.latest(&package);
+    let info = PackageInfo {
+        latest: latest.as_ref().map(|r| r.version.clone()),
+        description: latest.as_ref().and_then(|r| r.description.clone()),
+        license: latest.as_ref().and_then(|r| r.license.clone()),
+        repository: latest.as_ref().and_then(|r| r.repository.clone()),
+        versions: releases
+            .iter()
+            .rev()
+            .map(|r| VersionInfo {
+                version: r.version.clone(),
+                published: r.published().map(skip::date),
+                flag: r.flag().map(|flag| flag.to_string()),
+            })
+            .collect(),
+        used_by,
+        name: package,
+    };
+
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&info)?);
+        return Ok(true);
+    }
+    println!("{}", info.name);
+    let fields = [
+        ("Latest", &info.latest),
+        ("Description", &info.description),
+        ("License", &info.license),
+        ("Repository", &info.repository),
+    ];
+    for (label, value) in fields {
+        if let Some(value) = value {
+            println!("  {:<12} {value}", format!("{label}:"));
+        }
+    }
+    if info.versions.is_empty() {
+        println!("\nNot in the registry index (run `moon update`)");
+    } else {
+        println!("\nVersions ({}):", info.versions.len());
+        for version in &info.versions {
+            let mut line = format!(
+                "  {:<16} {}",
+                version.version,
+                version.published.as_deref().unwrap_or("-")
+            );
+            if let Some(flag) = &version.flag {
+                line.push_str(&format!("  [{flag}]"));
+            }
+            println!("{line}");
+        }
+    }
+    if info.used_by.is_empty() {
+        println!("\nNot used by any repo");
+    } else {
+        println!("\nUsed by ({}):", info.used_by.len());
+        for user in &info.used_by {
+            println!(
+                "  {}  {}  {}",
+                user.repo,
+                user.module,
+                user.version.as_deref().unwrap_or("unversioned")
+            );
+        }
+    }
+    Ok(true)
+}
+
+// =============================================================================
+// Vendor Command
+// =============================================================================
+
+fn cmd_vendor(
+    common: CommonOptions,
+    out: PathBuf,
+    registry_url: String,
+    verify_checksums: bool,
+) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let registry = open_registry(&config);
+
+    let mut roots = Vec::new();
+    for (name, users) in package_users(&repos) {
+        for user in users {
+            match user.version {
+                Some(version) => roots.push((name.clone(), version)),
+                None => diagnostics::report(
+                    diagnostics::Code::Unsupported,
+                    format!(
+                        "{name} has no version in {} ({}); not vendored",
+                        user.repo, user.module
+                    ),
+                ),
+            }
+        }
+    }
+    let (packages, missing) = vendor::resolve(&registry, roots);
+    for package in &missing {
+        diagnostics::report(
+            diagnostics::Code::RegistryIndex,
+            format!("{package} is not in the registry index (run `moon update`)"),
+        );
+    }
+
+    let releases: Vec<(String, registry::Release)> = packages
+        .iter()
+        .flat_map(|(name, versions)| {
+            registry
+                .releases(name)
+                .into_iter()
+                .filter(|r| versions.contains(&r.version))
+                .map(|r| (name.clone(), r))
+        })
+        .collect();
+    if common.dry_run {
+        for (name, release) in &releases {
+            println!(
+                "Would vendor {name}@{} into {}",
+                release.version,
+                vendor::archive_path(&out, name, &release.version).display()
+            );
+        }
+        return Ok(missing.is_empty());
+    }
+
+    init_thread_pool(config.effective_jobs());
+    let results: Vec<Result<vendor::Fetched>> = releases
+        .par_iter()
+        .map(|(name, release)| {
+            vendor::fetch_archive(&out, &registry_url, name, release, verify_checksums)
+        })
+        .collect();
+    let mut downloaded = 0;
+    let mut failed = 0;
+    for ((name, release), result) in releases.iter().zip(&results) {
+        match result {
+            Ok(vendor::Fetched::Downloaded) => {
+                downloaded += 1;
+                println!(
+                    "[{}] {name}@{} (downloaded)",
+                    output::status(true),
+                    release.version
+                );
+            }
+            Ok(vendor::Fetched::Present) => {
+                if config.verbose.value {
+                    println!(
+                        "[{}] {name}@{} (present)",
+                        output::status(true),
+                        release.version
+                    );
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "[{}] {name}@{}: {e:#}",
+                    output::status(false),
+                    release.version
+                );
+            }
+        }
+    }
+    for (name, versions) in &packages {
+        vendor::write_index(&out, registry.index_dir(), name, versions)?;
+    }
+
+    println!(
+        "\nSummary: {} package versions in {} ({downloaded} downloaded), {failed} failed, {} missing",
+        releases.len() - failed,
+        out.display(),
+        missing.len()
+    );
+    Ok(failed == 0 && missing.is_empty())
+}
+
+fn cmd_verify(dir: PathBuf, format: OutputFormat) -> Result<bool> {
+    format.check_supported("verify", &[OutputFormat::Text, OutputFormat::Json])?;
+    let checked = vendor::verify_mirror(&dir)?;
+    let problems = checked.iter().filter(|c| c.problem.is_some()).count();
+
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&checked)?);
+        return Ok(problems == 0);
+    }
+    for check in &checked {
+        if let Some(problem) = &check.problem {
+            println!(
+                "[{}] {}@{}: {problem}",
+                output::status(false),
+                check.package,
+                check.version
+            );
+        }
+    }
+    println!(
+        "Summary: {} package versions in {}, {problems} with problems",
+        checked.len(),
+        dir.display()
+    );
+    Ok(problems == 0)
+}
+
+// =============================================================================
+// Provenance Command
+// =============================================================================
+
+fn cmd_provenance(common: CommonOptions, format: OutputFormat, offline: bool) -> Result<bool> {
+    format.check_supported("provenance", &[OutputFormat::Text, OutputFormat::Json])?;
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+
+    let mut entries: BTreeMap<(String, String), provenance::Entry> = BTreeMap::new();
+    for repo in &repos {
+        for dep in repo.moon_mods.iter().flat_map(|m| &m.deps) {
+            let (version, kind, repository, commit) = match &dep.source {
+                DepSource::Registry => {
+                    let Some(version) = dep.version.clone() else {
+                        continue;
+                    };
+                    let repository = registry
+                        .releases(&dep.name)
+                        .into_iter()
+                        .find(|r| r.version == version)
+                        .and_then(|r| r.repository);
+                    (version, "registry", repository, None)
+                }
+                DepSource::Git { url, branch, rev } => {
+                    let version = rev.clone().or_else(|| branch.clone()).unwrap_or_default();
+                    (version, "git", Some(url.clone()), rev.clone())
+                }
+                DepSource::Path(_) => continue,
+            };
+            let entry = entries
+                .entry((dep.name.clone(), version.clone()))
+                .or_insert_with(|| provenance::Entry {
+                    package: dep.name.clone(),
+                    version,
+                    kind,
+                    repository,
+                    tag: None,
+                    commit,
+                    used_by: Vec::new(),
+                });
+            let location = repo.location();
+            if !entry.used_by.contains(&location) {
+                entry.used_by.push(location);
+            }
+        }
+    }
+    let mut entries: Vec<provenance::Entry> = entries.into_values().collect();
+
+    if !offline {
+        let urls: BTreeSet<&str> = entries
+            .iter()
+            .filter(|e| e.kind == "registry")
+            .filter_map(|e| e.repository.as_deref())
+            .collect();
+        init_thread_pool(config.effective_jobs());
+        let tags: HashMap<String, String> = urls
+            .into_par_iter()
+            .filter_map(|url| match provenance::remote_tags(url) {
+                Ok(tags) => Some((url.to_string(), tags)),
+                Err(e) => {
+                    diagnostics::report(diagnostics::Code::RemoteLookup, format!("{e:#}"));
+                    None
+                }
+            })
+            .collect();
+        for entry in entries.iter_mut().filter(|e| e.kind == "registry") {
+            let found = entry
+                .repository
+                .as_ref()
+                .and_then(|url| tags.get(url))
+                .and_then(|tags| provenance::find_tag(tags, &entry.version));
+            if let Some((tag, commit)) = found {
+                entry.tag = Some(tag);
+                entry.commit = Some(commit);
+            }
+        }
+    }
+
+    let untraceable = entries.iter().filter(|e| !e.traceable()).count();
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&entries)?);
+        return Ok(true);
+    }
+    for entry in &entries {
+        println!("{}@{} ({})", entry.package, entry.version, entry.kind);
+        match &entry.repository {
+            Some(repository) => println!("  Source: {repository}"),
+            None => println!("  UNTRACEABLE: no source repository in the registry index"),
+        }
+        match (&entry.tag, &entry.commit) {
+            (Some(tag), Some(commit)) => println!("  Tag: {tag} ({commit})"),
+            (None, Some(commit)) => println!("  Commit: {commit}"),
+            _ if entry.traceable() && !offline => println!("  Tag: not found"),
+            _ => {}
+        }
+        println!("  Used by: {}", entry.used_by.join(", "));
+    }
+    println!(
+        "\nSummary: {} dependency versions, {untraceable} without a traceable source",
+        entries.len()
+    );
+    Ok(true)
+}
+
+// =============================================================================
+// Graph Command
+// =============================================================================
+
+fn cmd_graph(common: CommonOptions, format: GraphFormat) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+    let graph = graph::Graph::build(&repos, &registry);
+    report_registry_stats(&common, &registry);
+    match format {
+        GraphFormat::Json => println!("{}", output::json(&graph)?),
+        GraphFormat::Dot => print!("{}", graph.to_dot()),
+        GraphFormat::Mermaid => print!("{}", graph.to_mermaid()),
+    }
+    Ok(true)
+}
+
+fn cmd_graph_stats(
+    common: CommonOptions,
+    top: Option<usize>,
+    format: OutputFormat,
+) -> Result<bool> {
+    format.check_supported("graph stats", &[OutputFormat::Text, OutputFormat::Json])?;
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+    let mut stats = graph::Graph::build(&repos, &registry).stats();
+    report_registry_stats(&common, &registry);
+    if let Some(top) = top {
+        stats.truncate(top);
+    }
+
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&stats)?);
+        return Ok(true);
+    }
+    println!(
+        "{:>4}  {:>6}  {:>6}  {:>7}  Package",
+        "Rank", "Impact", "Fan-in", "Fan-out"
+    );
+    for (rank, stat) in stats.iter().enumerate() {
+        let internal = if stat.kind == graph::Kind::Internal {
+            " (fleet)"
+        } else {
+            ""
+        };
+        println!(
+            "{:>4}  {:>6}  {:>6}  {:>7}  {}{internal}",
+            rank + 1,
+            stat.impact,
+            stat.fan_in,
+            stat.fan_out,
+            stat.id
+        );
+    }
+    println!("\nImpact: repos an update reaches, directly or through fleet modules; fan-in: repos depending on it directly");
+    Ok(true)
+}
+
+// =============================================================================
+// Pins Command
+// =============================================================================
+
+fn cmd_pins(common: CommonOptions, action: PinsAction) -> Result<bool> {
+    let PinsAction::List { all } = action;
+    let config = Config::resolve(&common)?;
+    let now = timestamp::now();
+    let shown: Vec<&pins::Pin> = config
+        .pins
+        .value
+        .iter()
+        .filter(|pin| all || !pin.is_expired(now))
+        .collect();
+    if shown.is_empty() {
+        println!("No active pins.");
+        return Ok(true);
+    }
+
+    let repos = discover_fleet(common.root(), &config)?;
+    let root = common.root().canonicalize()?;
+    for pin in &shown {
+        let status = if pin.is_expired(now) {
+            "expired"
+        } else {
+            "active"
+        };
+        println!("{} [{status}]", pin.package);
+        println!("  Reason: {}", pin.reason);
+        if let Some(expires) = pin.expires {
+            println!("  Expires: {}", skip::date(expires.0));
+        }
+        if let Some(pattern) = &pin.repos {
+            println!("  Repos: {pattern}");
+        }
+        let held: Vec<String> = repos
+            .iter()
+            .filter(|repo| {
+                pin.covers(repo.root.strip_prefix(&root).unwrap_or(&repo.root))
+                    && repo
+                        .moon_mods
+                        .iter()
+                        .any(|m| m.deps.iter().any(|d| d.name == pin.package))
+            })
+            .map(|repo| repo.location())
+            .collect();
+        if held.is_empty() {
+            println!("  Held in: (no repo depends on it)");
+        } else {
+            println!("  Held in: {}", held.join(", "));
+        }
+    }
+    warn_expired_pins(&config, now);
+    Ok(true)
+}
+
+// =============================================================================
+// Align Command
+// =============================================================================
+
+fn cmd_dedupe_report(
+    common: CommonOptions,
+    format: OutputFormat,
+    min_versions: usize,
+) -> Result<bool> {
+    format.check_supported("dedupe-report", &[OutputFormat::Text, OutputFormat::Json])?;
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+
+    let usages: Vec<dedupe::Usage> = repos
+        .iter()
+        .flat_map(|repo| {
+            repo.moon_mods
+                .iter()
+                .flat_map(|m| &m.deps)
+                .map(|dep| dedupe::Usage {
+                    package: dep.name.clone(),
+                    repo: repo.location(),
+                    version: dep.version.clone(),
+                    source: dep.source.kind(),
+                })
+        })
+        .collect();
+    // Average size of the package in the .mooncakes directories that have it
+    let cached_size = |package: &str| {
+        let sizes: Vec<u64> = repos
+            .iter()
+            .filter(|repo| repo.host.is_none())
+            .flat_map(|repo| &repo.moon_mods)
+            .filter_map(|m| {
+                let dir = m.path.parent()?.join(".mooncakes").join(package);
+                dir.is_dir().then(|| disk::dir_size(&dir))
+            })
+            .collect();
+        (!sizes.is_empty()).then(|| sizes.iter().sum::<u64>() / sizes.len() as u64)
+    };
+    let report = dedupe::report(
+        &usages,
+        min_versions,
+        |package| registry.latest(package).map(|r| r.version),
+        cached_size,
+    );
+    report_registry_stats(&common, &registry);
+
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&report)?);
+        return Ok(true);
+    }
+    if report.aliases.is_empty() && report.versions.is_empty() {
+        println!("No duplicate dependencies found.");
+        return Ok(true);
+    }
+    if !report.aliases.is_empty() {
+        println!("Packages under several names or sources:");
+        for alias in &report.aliases {
+            println!("  consolidate on {}", alias.target);
+            for name in &alias.names {
+                println!(
+                    "    {} ({}): {}",
+                    name.name,
+                    name.source,
+                    name.repos.join(", ")
+                );
+            }
+        }
+        println!();
+    }
+    if !report.versions.is_empty() {
+        println!("Packages at {min_versions} or more versions:");
+        for spread in &report.versions {
+            let savings = spread
+                .savings
+                .map(|bytes| format!(", saves about {}", disk::format_size(bytes)))
+                .unwrap_or_default();
+            println!(
+                "  {}: {} versions, consolidate on {}{savings}",
+                spread.package,
+                spread.versions.len(),
+                spread.target
+            );
+            for version in &spread.versions {
+                println!("    {}: {}", version.version, version.repos.join(", "));
+            }
+        }
+        println!();
+    }
+    println!(
+        "Summary: {} aliased packages, {} packages at several versions, about {} of cache savings",
+        report.aliases.len(),
+        report.versions.len(),
+        disk::format_size(report.savings)
+    );
+    Ok(true)
+}
+
+fn cmd_align(
+    common: CommonOptions,
+    choose: Option<align::Policy>,
+    packages: Vec<String>,
+) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos = discover_repos(common.root(), &config)?;
+    let modules = repos.iter().flat_map(|repo| &repo.moon_mods);
+
+    let internal: BTreeSet<String> = modules
+        .clone()
+        .filter_map(|m| m.name.clone())
+        .filter(|name| packages.is_empty() || packages.contains(name))
+        .collect();
+    let usages = modules.flat_map(|m| {
+        m.deps
+            .iter()
+            .filter(|dep| dep.is_registry())
+            .filter_map(|dep| Some((dep.name.clone(), m.path.clone(), dep.version.clone()?)))
+    });
+    let conflicts = align::find_conflicts(&internal, usages);
+    if conflicts.is_empty() {
+        println!("All internal packages are aligned.");
+        return Ok(true);
+    }
+
+    let interactive = choose.is_none() && std::io::stdin().is_terminal();
+    let registry = registry_reader(&config);
+    registry.prefetch(conflicts.iter().map(|c| c.package.as_str()));
+    let mut unresolved = 0;
+    for conflict in &conflicts {
+        let latest = registry.latest(&conflict.package).map(|r| r.version);
+        println!(
+            "{} is used at {} versions:",
+            conflict.package,
+            conflict.versions.len()
+        );
+        for (i, (version, manifests)) in conflict.versions.iter().enumerate() {
+            println!("  {}) {version}", i + 1);
+            for manifest in manifests {
+                println!("       {}", manifest.display());
+            }
+        }
+        if let Some(latest) = latest
+            .as_deref()
+            .filter(|latest| align::registry_is_newer(conflict, latest))
+        {
+            println!("  r) {latest} (latest in the registry)");
+        }
+
+        let chosen = match choose {
+            Some(policy) => {
+                let chosen = conflict.choose(policy, latest.as_deref());
+                if chosen.is_none() {
+                    println!("  No registry version; skipped");
+                }
+                chosen
+            }
+            None if interactive => prompt_version(conflict, latest.as_deref())?,
+            None => None,
+        };
+        let Some(chosen) = chosen else {
+            unresolved += 1;
+            println!();
+            continue;
+        };
+
+        println!("  Using {chosen}");
+        for manifest in conflict.stale(&chosen) {
+            let content = std::fs::read_to_string(manifest)
+                .with_context(|| format!("Failed to read {}", manifest.display()))?;
+            let Some(new) = manifest::set_version(&content, &conflict.package, &chosen) else {
+                diagnostics::report(
+                    diagnostics::Code::Unsupported,
+                    format!(
+                        "{}: {} is not declared with a plain version; left as is",
+                        manifest.display(),
+                        conflict.package
+                    ),
+                );
+                continue;
+            };
+            if common.dry_run {
+                print!(
+                    "{}",
+                    diff::preview(&manifest.display().to_string(), Some(&content), &new)
+                );
+            } else {
+                capabilities::write(manifest, new)?;
+                println!("  Updated {}", manifest.display());
+            }
+        }
+        println!();
+    }
+
+    if unresolved > 0 {
+        if choose.is_none() && !interactive {
+            println!("{unresolved} conflicts left; run in a terminal or pass --choose");
+        } else {
+            println!("{unresolved} conflicts left unresolved");
+        }
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Ask which version to use until the answer is valid; `None` skips
+fn prompt_version(conflict: &align::Conflict, latest: Option<&str>) -> Result<Option<String>> {
+    let latest = latest.filter(|latest| align::registry_is_newer(conflict, latest));
+    loop {
+        let options = if latest.is_some() { ", r" } else { "" };
+        print!(
+            "Choose [1-{}{options}, s to skip]: ",
+            conflict.versions.len()
+        );
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        match align::parse_answer(&answer, conflict, latest) {
+            Ok(chosen) => return Ok(chosen),
+            Err(e) => println!("  Invalid choice: {e}"),
+        }
+    }
+}
+
+// =============================================================================
+// Lint Command
+// =============================================================================
+
+fn cmd_lint(common: CommonOptions, fix: bool, format: OutputFormat) -> Result<bool> {
+    format.check_supported(
+        "lint",
+        &[
+            OutputFormat::Text,
+            OutputFormat::Json,
+            OutputFormat::Sarif,
+            OutputFormat::Github,
+        ],
+    )?;
+
+    let config = Config::resolve(&common)?;
+    // Manifests in JSON5 syntax are findings here, not skipped files
+    jsonc::set_lenient();
+    let repos = discover_fleet(common.root(), &config)?;
+    let root = common.root().canonicalize()?;
+
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+    let mut found = lint::lint(&repos, &registry, config.nested_modules.value);
+    report_registry_stats(&common, &registry);
+    if fix {
+        let mut fixed = BTreeSet::new();
+        for finding in found.iter().filter(|f| f.rule_id == "L008") {
+            let path = &finding.path;
+            let Some((content, strict)) = lint::fix(finding)? else {
+                continue;
+            };
+            if common.dry_run {
+                print!(
+                    "{}",
+                    diff::preview(&path.display().to_string(), Some(&content), &strict)
+                );
+            } else {
+                capabilities::write(path, &strict)?;
+                if format == OutputFormat::Text {
+                    println!("Fixed {}", path.display());
+                }
+            }
+            fixed.insert(path.clone());
+        }
+        if !common.dry_run {
+            found.retain(|f| !(f.rule_id == "L008" && fixed.contains(&f.path)));
+        }
+    }
+    let has_errors = found.iter().any(|f| f.level == findings::Level::Error);
+
+    match format {
+        OutputFormat::Sarif => {
+            let sarif = findings::to_sarif(lint::RULES, &found, &root);
+            println!("{}", output::json(&sarif)?);
+        }
+        OutputFormat::Github => {
+            let mut summary = format!(
+                "## moon-dst lint\n\n{} findings\n\n| Location | Level | Rule | Message |\n|---|---|---|---|\n",
+                found.len()
+            );
+            for finding in &found {
+                let file = findings::relative_uri(&finding.path, &root);
+                println!(
+                    "{}",
+                    github::annotation(
+                        finding.level.into(),
+                        Some(&file),
+                        finding.line,
+                        Some(finding.rule_id),
+                        &finding.message,
+                    )
+                );
+                summary.push_str(&format!(
+                    "| {}:{} | {} | {} | {} |\n",
+                    github::escape_cell(&file),
+                    finding.line.unwrap_or(1),
+                    finding.level.as_str(),
+                    finding.rule_id,
+                    github::escape_cell(&finding.message)
+                ));
+            }
+            github::append_step_summary(&summary)?;
+        }
+        OutputFormat::Json => {
+            println!("{}", output::json(&found)?);
+        }
+        _ => {
+            for finding in &found {
+                println!("{}", finding.to_text(&root));
+            }
+            let count = |level| found.iter().filter(|f| f.level == level).count();
+            println!(
+                "\nSummary: {} errors, {} warnings, {} notes",
+                count(findings::Level::Error),
+                count(findings::Level::Warning),
+                count(findings::Level::Note)
+            );
+        }
+    }
+
+    Ok(!has_errors)
+}
+
+// =============================================================================
+// Gate Command
+// =============================================================================
+
+fn cmd_gate(
+    common: CommonOptions,
+    format: OutputFormat,
+    fail_on: Option<gate::FailOn>,
+    max_major_lag: Option<u64>,
+    checks: Vec<gate::Check>,
+    baseline_file: Option<PathBuf>,
+) -> Result<bool> {
+    format.check_supported(
+        "gate",
+        &[
+            OutputFormat::Text,
+            OutputFormat::Json,
+            OutputFormat::Github,
+            OutputFormat::Sarif,
+        ],
+    )?;
+    let mut config = Config::resolve(&common)?;
+    config.gate_fail_on.cli(fail_on);
+    config.gate_max_major_lag.cli(max_major_lag);
+    config
+        .gate_checks
+        .cli((!checks.is_empty()).then_some(checks));
+    let mut checks = config.gate_checks.value.clone();
+    checks.sort_unstable();
+    checks.dedup();
+    let baseline = baseline_file
+        .as_deref()
+        .map(baseline::Baseline::read)
+        .transpose()?;
+
+    // Manifests in JSON5 syntax are findings here, not skipped files
+    jsonc::set_lenient();
+    let repos = discover_fleet(common.root(), &config)?;
+    let root = common.root().canonicalize()?;
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+
+    // Outdated dependencies are judged by their lag below instead
+    let mut found: Vec<findings::Finding> =
+        lint::lint(&repos, &registry, config.nested_modules.value)
+            .into_iter()
+            .filter(|f| f.rule_id != "L002")
+            .collect();
+    for finding in &mut found {
+        match finding.rule_id {
+            "L004" => finding.level = config.gate_yanked.value,
+            "L005" => finding.level = config.gate_deprecated.value,
+            _ => {}
+        }
+    }
+    let line_of = |path: &Path, needle: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| findings::find_line(&content, needle))
+    };
+    for row in outdated::collect_rows(&repos, &registry) {
+        let (true, Some(declared), Some(latest)) = (row.outdated, &row.declared, &row.latest)
+        else {
+            continue;
+        };
+        let accepted = baseline
+            .as_ref()
+            .and_then(|b| b.accepts(&baseline_repo(&row, &root), &row.module, &row.dep));
+        if accepted.is_some() {
+            continue;
+        }
+        let lag = gate::major_lag(declared, latest).unwrap_or(0);
+        let (rule_id, level, message) = if lag > config.gate_max_major_lag.value {
+            (
+                "G001",
+                findings::Level::Error,
+                format!(
+                    "{} {declared} is {lag} major versions behind (latest: {latest})",
+                    row.dep
+                ),
+            )
+        } else {
+            (
+                "L002",
+                findings::Level::Warning,
+                format!("{} {declared} is outdated (latest: {latest})", row.dep),
+            )
+        };
+        found.push(findings::Finding {
+            rule_id,
+            level,
+            message,
+            line: line_of(&row.manifest, &format!("\"{}\"", row.dep)),
+            path: row.manifest,
+        });
+    }
+    for pin in pins::expired(&config.pins.value, timestamp::now()) {
+        let needle = format!("\"{}\"", pin.package);
+        let file = [Some(&config.project_file), config.user_file.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|file| line_of(file, &needle).is_some())
+            .unwrap_or(&config.project_file);
+        found.push(findings::Finding {
+            rule_id: "G002",
+            level: findings::Level::Error,
+            message: format!("pin {pin} has expired; renew or remove it"),
+            line: line_of(file, &needle),
+            path: file.clone(),
+        });
+    }
+    for moon_mod in repos.iter().flat_map(|r| &r.moon_mods) {
+        for dep in &moon_mod.deps {
+            let Some(new) = config.renames.value.get(&dep.name) else {
+                continue;
+            };
+            found.push(findings::Finding {
+                rule_id: "G003",
+                level: findings::Level::Warning,
+                message: format!(
+                    "{} is renamed to {new} (migrate with `moon-dst apply --apply-renames`)",
+                    dep.name
+                ),
+                line: line_of(&moon_mod.path, &format!("\"{}\"", dep.name)),
+                path: moon_mod.path.clone(),
+            });
+        }
+    }
+    found.retain(|f| checks.contains(&gate::Check::of(f.rule_id)));
+    report_registry_stats(&common, &registry);
+
+    let verdict = gate::Verdict::new(&checks, found, config.gate_fail_on.value);
+    match format {
+        OutputFormat::Json => println!("{}", output::json(&verdict)?),
+        OutputFormat::Sarif => {
+            let rules = [lint::RULES, gate::RULES].concat();
+            let sarif = findings::to_sarif(&rules, &verdict.findings, &root);
+            println!("{}", output::json(&sarif)?);
+        }
+        OutputFormat::Github => {
+            let mut summary = format!(
+                "## moon-dst gate\n\n```\n{}\n```\n\n| Location | Level | Rule | Message |\n|---|---|---|---|\n",
+                verdict.lines().join("\n")
+            );
+            for finding in &verdict.findings {
+                let file = findings::relative_uri(&finding.path, &root);
+                println!(
+                    "{}",
+                    github::annotation(
+                        finding.level.into(),
+                        Some(&file),
+                        finding.line,
+                        Some(finding.rule_id),
+                        &finding.message,
+                    )
+                );
+                summary.push_str(&format!(
+                    "| {}:{} | {} | {} | {} |\n",
+                    github::escape_cell(&file),
+                    finding.line.unwrap_or(1),
+                    finding.level.as_str(),
+                    finding.rule_id,
+                    github::escape_cell(&finding.message)
+                ));
+            }
+            github::append_step_summary(&summary)?;
+            println!("{}", verdict.lines()[0]);
+        }
+        _ => {
+            for finding in &verdict.findings {
+                println!("{}", finding.to_text(&root));
+            }
+            if !verdict.findings.is_empty() {
+                println!();
+            }
+            for line in verdict.lines() {
+                println!("{line}");
+            }
+        }
+    }
+    Ok(verdict.passed)
+}
+
+// =============================================================================
+// Plan Command
+// =============================================================================
+
+fn cmd_plan(
+    common: CommonOptions,
+    packages: Vec<String>,
+    package_match: Option<package_filter::MatchMode>,
+    min_age: Option<u64>,
+    out: PathBuf,
+) -> Result<bool> {
+    let mut config = Config::resolve(&common)?;
+    config
+        .packages
+        .cli((!packages.is_empty()).then_some(packages));
+    config.package_match.cli(package_match);
+    config.min_age.cli(min_age.map(Some));
+    let package_filter = config.package_filter()?;
+    let now = timestamp::now();
+    warn_expired_pins(&config, now);
+
+    let mut repos = discover_fleet(common.root(), &config)?;
+    skip_marked(&mut repos);
+    warn_unmatched_packages(&package_filter, &repos);
+    let root = common.root().canonicalize()?;
+
+    let registry = open_registry(&config);
+    prefetch_deps(&registry, &repos);
+
+    let mut planned = Vec::new();
+    for repo in &repos {
+        let rel = repo.root.strip_prefix(&root).unwrap_or(&repo.root);
+        let updates = plan_updates(repo, rel, &config, &package_filter, &registry, now);
+        if updates.is_empty() {
+            continue;
+        }
+        planned.push(plan::RepoPlan {
+            repo: repo.root.clone(),
+            manifests: manifest_hashes(repo)?,
+            updates,
+        });
+    }
+    report_registry_stats(&common, &registry);
+    let plan = plan::Plan::new(now, root, planned);
+
+    for repo in &plan.repos {
+        println!("Repository: {}", repo.repo.display());
+        for update in &repo.updates {
+            println!(
+                "  {}: {} -> {}",
+                update.name,
+                update.from.as_deref().unwrap_or("?"),
+                update.to
+            );
+        }
+        println!();
+    }
+    let count: usize = plan.repos.iter().map(|r| r.updates.len()).sum();
+    println!(
+        "Plan: {count} updates in {} repos (hash {})",
+        plan.repos.len(),
+        plan.hash
+    );
+
+    if common.dry_run {
+        println!("[dry-run] Would write {}", out.display());
+    } else {
+        plan.write(&out)?;
+        println!(
+            "Wrote {}; run `moon-dst apply --plan {0}` to apply it",
+            out.display()
+        );
+    }
+    Ok(true)
+}
+
+/// The `[renames]` entries the dependencies of `repo` still use
+fn pending_renames<'a>(config: &'a Config, repo: &RepoInfo) -> Vec<(&'a str, &'a str)> {
+    config
+        .renames
+        .value
+        .iter()
+        .filter(|(old, _)| {
+            repo.moon_mods
+                .iter()
+                .flat_map(|m| &m.deps)
+                .any(|d| d.name == **old)
+        })
+        .map(|(old, new)| (old.as_str(), new.as_str()))
+        .collect()
+}
+
+/// Report each repo depending on a package under a name `[renames]` replaces
+fn warn_renamed_deps(config: &Config, repos: &[RepoInfo]) {
+    for repo in repos {
+        let pending = pending_renames(config, repo);
+        if pending.is_empty() {
+            continue;
+        }
+        let renames: Vec<String> = pending
+            .iter()
+            .map(|(old, new)| format!("{old} -> {new}"))
+            .collect();
+        diagnostics::report(
+            diagnostics::Code::RenamedPackage,
+            format!(
+                "{} depends on renamed packages: {} (migrate with `moon-dst apply --apply-renames`)",
+                repo.location(),
+                renames.join(", ")
+            ),
+        );
+    }
+}
+
+/// `apply --apply-renames`: move the dependencies of `repos` to the new
+/// names in `[renames]`, as `rename` would, and update them under those
+/// names; with `dry_run` only print the diffs. False if a file could not be
+/// written
+fn apply_renames(
+    config: &Config,
+    repos: &mut [RepoInfo],
+    root: &Path,
+    dry_run: bool,
+) -> Result<bool> {
+    let mut failed = 0;
+    for repo in repos.iter_mut() {
+        let pending: Vec<(String, String)> = pending_renames(config, repo)
+            .into_iter()
+            .map(|(old, new)| (old.to_string(), new.to_string()))
+            .collect();
+        if pending.is_empty() {
+            continue;
+        }
+        if repo.host.is_some() {
+            diagnostics::report(
+                diagnostics::Code::Unsupported,
+                format!(
+                    "{}: renames are not applied to remote repos",
+                    repo.location()
+                ),
+            );
+            continue;
+        }
+        let modules = project_index::modules(std::slice::from_ref(repo));
+        for (old, new) in pending {
+            let edits = rename::plan(&modules, &old, &new, None, true)?;
+            let errors = write_edits(&edits, root, dry_run);
+            failed += errors;
+            if errors > 0 {
+                continue;
+            }
+            let verb = if dry_run { "would rename" } else { "renamed" };
+            println!("[{}] {verb} {old} -> {new}", ospath::display(&repo.root));
+            for dep in repo.moon_mods.iter_mut().flat_map(|m| &mut m.deps) {
+                if dep.name == old {
+                    dep.name = new.clone();
+                }
+            }
+        }
+    }
+    Ok(failed == 0)
+}
+
+/// Report each `--package` pattern that no registry dependency of `repos`
+/// matches, with the names it may have meant
+fn warn_unmatched_packages(packages: &package_filter::Filter, repos: &[RepoInfo]) {
+    if packages.is_empty() {
+        return;
+    }
+    let mut names: Vec<&str> = repos
+        .iter()
+        .flat_map(|repo| &repo.moon_mods)
+        .flat_map(|m| &m.deps)
+        .filter(|d| d.is_registry())
+        .map(|d| d.name.as_str())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    for unmatched in packages.unmatched(&names) {
+        diagnostics::report(diagnostics::Code::UnmatchedPackage, unmatched.to_string());
+    }
+}
+
+/// Updates of a repo's registry dependencies to the newest version allowed
+/// by `packages`, `--min-age` and the pins
+fn plan_updates(
+    repo: &RepoInfo,
+    rel: &Path,
+    config: &Config,
+    packages: &package_filter::Filter,
+    registry: &registry::Registry,
+    now: u64,
+) -> Vec<plan::PlannedUpdate> {
+    let mut names: Vec<&str> = repo
+        .moon_mods
+        .iter()
+        .flat_map(|m| &m.deps)
+        .filter(|d| d.is_registry())
+        .map(|d| d.name.as_str())
+        .filter(|dep| packages.matches(dep))
+        .filter(|dep| !pins::is_pinned(&config.pins.value, dep, rel, now))
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let release = match config.min_age.value {
+                Some(days) => registry.latest_published_before(
+                    name,
+                    now.saturating_sub(days * timestamp::SECS_PER_DAY),
+                ),
+                None => registry.latest(name),
+            }?;
+            let outdated = repo
+                .moon_mods
+                .iter()
+                .flat_map(|m| &m.deps)
+                .filter(|d| d.name == name)
+                .any(|d| {
+                    d.version
+                        .as_deref()
+                        .is_none_or(|v| registry::is_outdated(v, &release.version))
+                });
+            outdated.then(|| plan::PlannedUpdate {
+                name: name.to_string(),
+                from: repo.declared_version(name),
+                to: release.version,
+            })
+        })
+        .collect()
+}
+
+/// SHA-256 of each of a repo's manifests, keyed by path
+fn manifest_hashes(repo: &RepoInfo) -> Result<BTreeMap<PathBuf, String>> {
+    repo.moon_mods
+        .iter()
+        .map(|m| {
+            let content = repo
+                .runner
+                .read_file(&m.path)
+                .with_context(|| format!("Failed to read {}", m.path.display()))?;
+            Ok((m.path.clone(), plan::sha256(content.as_bytes())))
+        })
+        .collect()
+}
+
+/// Fail unless the fleet under `root` is still in the state `plan` was
+/// made from
+fn check_plan_drift(plan: &plan::Plan, path: &Path, root: &Path, repos: &[RepoInfo]) -> Result<()> {
+    if plan.root != root {
+        bail!(errors::Coded::new(
+            errors::Code::PlanRejected,
+            format!(
+                "Plan {} was made for {}, not {}",
+                path.display(),
+                ospath::display(&plan.root),
+                ospath::display(root)
+            )
+        ));
+    }
+    let mut drifted = Vec::new();
+    for planned in &plan.repos {
+        match repos.iter().find(|r| r.root == planned.repo) {
+            Some(repo) => drifted.extend(plan::drift(planned, &manifest_hashes(repo)?)),
+            None => drifted.push(format!("{}: repository not found", planned.repo.display())),
+        }
+    }
+    if !drifted.is_empty() {
+        bail!(errors::Coded::new(
+            errors::Code::PlanRejected,
+            format!(
+                "Repository state drifted from plan {}; run `moon-dst plan` again:\n  {}",
+                path.display(),
+                drifted.join("\n  ")
+            )
+        ));
+    }
+    Ok(())
+}
+
+// =============================================================================
+// Apply Command
+// =============================================================================
+
+fn cmd_apply(args: ApplyArgs) -> Result<bool> {
+    let format = args
+        .format
+        .unwrap_or(if std::io::stdout().is_terminal() && !output::plain() {
+            OutputFormat::Table
+        } else {
+            OutputFormat::Text
+        });
+    format.check_supported(
+        "apply",
+        &[
+            OutputFormat::Text,
+            OutputFormat::Table,
+            OutputFormat::Json,
+            OutputFormat::Github,
+        ],
+    )?;
+
+    let common = args.common;
+    let mut config = Config::resolve(&common)?;
+    config.skip_update.cli(args.skip_update.then_some(true));
+    config.repeat.cli(args.repeat);
+    // An explicit --repeat overrides repeat-until-stable from config
+    config.repeat_until_stable.cli(
+        args.repeat_until_stable
+            .then_some(true)
+            .or(args.repeat.map(|_| false)),
+    );
+    config
+        .packages
+        .cli((!args.packages.is_empty()).then_some(args.packages));
+    config.package_match.cli(args.package_match);
+    config.fail_fast.cli(args.fail_fast.then_some(true));
+    config
+        .network_jobs
+        .cli(args.network_jobs.map(|n| Some(n as usize)));
+    config
+        .verify_jobs
+        .cli(args.verify_jobs.map(|n| Some(n as usize)));
+    config.justfile.cli(args.no_justfile.then_some(false));
+    config.justfile_mode.cli(args.justfile_mode);
+    config.verify.cli(args.verify.then_some(true));
+    config
+        .respect_toolchain
+        .cli(args.respect_toolchain.then_some(true));
+    config.min_age.cli(args.min_age.map(Some));
+    config.fix_yanked.cli(args.fix_yanked.then_some(true));
+    config
+        .update_git_deps
+        .cli(args.update_git_deps.then_some(true));
+    config.clone_full.cli(args.full_clone.then_some(true));
+    config.pr_open.cli(args.open_pr.then_some(true));
+    config
+        .pr_update_submodules
+        .cli(args.update_submodules.then_some(true));
+    config.changelog_file.cli(args.changelog_file.map(Some));
+    config.issues_after.cli(args.issue_after.map(Some));
+    config.fail_on.cli(args.fail_on);
+    config
+        .email_to
+        .cli((!args.email_to.is_empty()).then_some(args.email_to));
+    config.email_on.cli(args.email_on);
+    config.disk_min_free.cli(args.min_free);
+    config.disk_on_low.cli(args.on_low_disk);
+    config.confirm_threshold.cli(args.yes.then_some(0));
+    config.max_error_length.cli(args.max_error_length);
+    config
+        .group_errors
+        .cli(args.no_group_errors.then_some(false));
+
+    limits::init(config.limits()?);
+    let package_filter = config.package_filter()?;
+    error_output::init(
+        data_dir().map(|dir| dir.join(error_output::DIR)),
+        config.max_error_length.value,
+    );
+
+    if !warn_expired_pins(&config, timestamp::now()) && args.strict_pins {
+        bail!(errors::Coded::new(
+            errors::Code::ExpiredPins,
+            "Expired dependency pins (--strict-pins); renew or remove them first"
+        ));
+    }
+
+    // Repo roots are canonical; per-repo globs match paths relative to this
+    let base = common.root().canonicalize()?;
+    let from_outdated = match &args.from_outdated {
+        Some(path) => Some(pipe::OutdatedDocument::read(path)?.plan(timestamp::now(), &base)),
+        None => None,
+    };
+    let mut repos = match (&args.from_scan, &from_outdated) {
+        (Some(path), _) => {
+            discover_scanned(common.root(), &config, &pipe::ScanDocument::read(path)?)?
+        }
+        (None, Some(plan)) => discover_planned(common.root(), &config, plan)?,
+        (None, None) => discover_fleet(common.root(), &config)?,
+    };
+
+    if repos.is_empty() {
+        println!("No moon.mod.json files found.");
+        return Ok(true);
+    }
+
+    skip_marked(&mut repos);
+    let renamed = match args.apply_renames {
+        true => apply_renames(&config, &mut repos, &base, common.dry_run)?,
+        false => {
+            warn_renamed_deps(&config, &repos);
+            true
+        }
+    };
+    warn_unmatched_packages(&package_filter, &repos);
+    if !common.dry_run {
+        check_disk_space(&config, &repos)?;
+    }
+    let plan = match &args.plan {
+        Some(path) => {
+            let plan = plan::Plan::read(path)?;
+            check_plan_drift(&plan, path, &base, &repos)?;
+            repos.retain(|repo| plan.repo(&repo.root).is_some());
+            Some(plan)
+        }
+        None => from_outdated,
+    };
+    select_toolchains(&mut repos, config.respect_toolchain.value);
+
+    let fail_fast = config.fail_fast.value;
+    let fail_on = config.fail_on.value;
+    let jobs = config.effective_jobs();
+    let mut observers: Vec<Box<dyn observer::Observer>> = vec![Box::new(observer::CommandLog {
+        enabled: config.verbose.value || common.dry_run,
+    })];
+    // Durations of a --deterministic run are zeroed and say nothing
+    let durations_path = eta::Durations::path().filter(|_| !timestamp::is_fixed());
+    let mut durations = match &durations_path {
+        Some(path) => eta::Durations::read(path).unwrap_or_else(|e| {
+            diagnostics::report(diagnostics::Code::RunState, format!("{e:#}"));
+            eta::Durations::default()
+        }),
+        None => eta::Durations::default(),
+    };
+    let estimate = Arc::new(eta::Estimate::new(
+        &durations,
+        repos.iter().map(|repo| repo.location_path()),
+        jobs,
+    ));
+    observers.push(Box::new(estimate.clone()));
+    let progress = (std::io::stderr().is_terminal() && !config.verbose.value && !output::plain())
+        .then(|| Arc::new(observer::ProgressLine::new(repos.len(), estimate.clone())));
+    if let Some(progress) = &progress {
+        observers.push(Box::new(progress.clone()));
+    }
+    // Add specs, yanked versions and dry-run previews all read the index
+    let registry = registry_reader(&config);
+    prefetch_deps(&registry, &repos);
+    let options = ApplyOptions {
+        skip_update: config.skip_update.value,
+        repeat: config.repeat.value,
+        until_stable: config.repeat_until_stable.value,
+        packages: package_filter,
+        write_justfile: config.justfile.value,
+        justfile_mode: config.justfile_mode.value,
+        justfile_template: justfile_template(&config, BTreeMap::new())?,
+        task_runner: config.just_task_runner.value,
+        verify: config.verify.value,
+        min_age: config.min_age.value,
+        fix_yanked: config.fix_yanked.value,
+        update_git_deps: config.update_git_deps.value,
+        pins: config.pins.value.clone(),
+        registry,
+        plan,
+        cancel: cancel::CancellationToken::new(),
+        observer: Arc::new(observer::Observers(observers)),
+        resolved: Resolved::default(),
+        dry_run: common.dry_run,
+        verbose: config.verbose.value,
+    };
+
+    // A resumed run was confirmed when it started
+    if !common.dry_run && !args.resume {
+        let graph = build_apply_graph(&repos, &base, &options, &HashMap::new());
+        confirm_apply(&repos, &graph, &options, config.confirm_threshold.value)?;
+    }
+
+    let started = timestamp::now();
+    let (checkpoint, resumed) = if common.dry_run {
+        (None, HashMap::new())
+    } else {
+        open_checkpoint(&base, started, args.resume)?
+    };
+    let graph = build_apply_graph(&repos, &base, &options, &resumed);
+    let mut collector = ResultCollector::new(
+        &repos,
+        &graph,
+        args.results_out
+            .as_deref()
+            .map(|path| (path, durations_path.is_some().then(|| estimate.clone()))),
+        options.observer.clone(),
+        checkpoint,
+        resumed,
+        options.cancel.clone(),
+    )?;
+    let lanes = [
+        config.network_jobs.value.unwrap_or(jobs),
+        jobs,
+        config.verify_jobs.value.unwrap_or(jobs),
+    ];
+    let limits = scheduler::Limits {
+        // --deterministic runs one task at a time, like --jobs 1
+        total: if common.deterministic {
+            1
+        } else {
+            lanes.iter().sum()
+        },
+        lanes: lanes.to_vec(),
+    };
+    run_apply_graph(&repos, &graph, &limits, fail_fast, &options, &mut collector);
+    let results = collector.finish();
+    if let Some(progress) = &progress {
+        progress.clear();
+    }
+    if let Some(path) = durations_path.filter(|_| !common.dry_run) {
+        for result in &results {
+            durations.record(&result.repo_root, result.duration);
+        }
+        if let Err(e) = durations.write(&path) {
+            diagnostics::report(diagnostics::Code::RunState, format!("{e:#}"));
+        }
+    }
+
+    let mut all_success = renamed && results.iter().all(|r| r.success);
+    let updated: usize = results
+        .iter()
+        .map(|r| r.count(PackageStatus::Updated))
+        .sum();
+
+    if format == OutputFormat::Json {
+        let output = ApplyOutput {
+            run_id: run_id::current(),
+            repos: &results,
+        };
+        println!("{}", output::json(&output)?);
+    } else {
+        let roots = canonical_roots(common.root(), &config);
+        if format == OutputFormat::Table {
+            print_apply_table(&results, &roots, config.group_errors.value);
+        } else {
+            print_apply_results(
+                &results,
+                config.group_output.value,
+                &roots,
+                config.group_errors.value,
+            );
+        }
+    }
+
+    if format == OutputFormat::Github {
+        report_apply_github(&results)?;
+    }
+
+    let run_id = run_id::current();
+    if config.changelog_file.value.is_some() {
+        all_success &= update_changelogs(&config, run_id, &repos, &results, common.dry_run);
+    }
+
+    if config.pr_open.value {
+        all_success &= open_pull_requests(&config, &base, run_id, &repos, &results, common.dry_run);
+    }
+
+    let run = history_run(started, common.root(), &results);
+    if !common.dry_run {
+        record_history(&run, args.feed_out.as_deref())?;
+    }
+
+    if let Some(after) = config.issues_after.value {
+        all_success &= file_failure_issues(&config, after, &run, &repos, &results, common.dry_run);
+    }
+
+    if !config.email_to.value.is_empty() || !config.owners_emails.value.is_empty() {
+        email_apply_report(&config, &results, common.dry_run)?;
+    }
+
+    if let Some(path) = &args.junit {
+        let cases: Vec<junit::TestCase> = results
+            .iter()
+            .map(|result| junit::TestCase {
+                classname: "moon-dst.apply".to_string(),
+                name: ospath::display(&result.repo_root).to_string(),
+                time: result.duration,
+                failure: (!result.success).then(|| result.failure_messages().join("\n")),
+            })
+            .collect();
+        junit::write(path, "moon-dst apply", &cases)?;
+    }
+
+    if fail_on == FailOn::Unchanged && updated == 0 {
+        if format != OutputFormat::Json {
+            println!("No packages were updated (--fail-on unchanged)");
+        }
+        return Ok(false);
+    }
+    Ok(all_success)
+}
+
+/// Cache and build output sizes of a repo's modules
+fn repo_usage(repo: &RepoInfo) -> disk::Usage {
+    let mut dirs: Vec<&Path> = repo
+        .moon_mods
+        .iter()
+        .map(|m| m.path.parent().unwrap_or(&repo.root))
+        .collect();
+    dirs.sort_unstable();
+    dirs.dedup();
+    let mut usage = disk::Usage::default();
+    for dir in dirs {
+        usage += disk::module_usage(dir);
+    }
+    usage
+}
+
+/// Warn or fail when the expected cache and build growth would leave less
+/// than `[disk] min-free` on a filesystem holding local repos
+fn check_disk_space(config: &Config, repos: &[RepoInfo]) -> Result<()> {
+    let on_low = config.disk_on_low.value;
+    if on_low == LowDisk::Ignore {
+        return Ok(());
+    }
+    let min_free =
+        limits::parse_size(&config.disk_min_free.value).context("Invalid [disk] min-free")?;
+    let local: Vec<&RepoInfo> = repos.iter().filter(|r| r.host.is_none()).collect();
+    if local.is_empty() {
+        return Ok(());
+    }
+    let roots: Vec<&Path> = local.iter().map(|r| r.root.as_path()).collect();
+    let free = match disk::free_space(&roots) {
+        Ok(free) => free,
+        Err(e) => {
+            diagnostics::report(
+                diagnostics::Code::DiskSpace,
+                format!("cannot check free disk space: {e:#}"),
+            );
+            return Ok(());
+        }
+    };
+    let usages: Vec<disk::Usage> = local.par_iter().map(|r| repo_usage(r)).collect();
+    let growth = disk::estimate_growth(&usages);
+
+    // Mount point to (available, repos on it)
+    let mut filesystems: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for (mount, available) in free {
+        let entry = filesystems.entry(mount).or_insert((available, 0));
+        entry.1 += 1;
+    }
+    let mut low = Vec::new();
+    for (mount, (available, count)) in &filesystems {
+        let needed = growth * count;
+        if available.saturating_sub(needed) < min_free {
+            low.push(format!(
+                "{} free on {mount}, and {count} repos may use about {} (minimum free: {})",
+                disk::format_size(*available),
+                disk::format_size(needed),
+                disk::format_size(min_free)
+            ));
+        }
+    }
+    if low.is_empty() {
+        return Ok(());
+    }
+    if on_low == LowDisk::Abort {
+        bail!(errors::Coded::new(
+            errors::Code::DiskSpace,
+            format!("Not enough disk space:\n  {}", low.join("\n  "))
+        ));
+    }
+    for line in low {
+        diagnostics::report(
+            diagnostics::Code::DiskSpace,
+            format!("low disk space: {line}"),
+        );
+    }
+    Ok(())
+}
+
+/// Ask before an apply that changes more than `threshold` repos; fails
+/// when the answer is no or there is no terminal to ask on
+fn confirm_apply(
+    repos: &[RepoInfo],
+    graph: &scheduler::Graph<ApplyTask>,
+    options: &ApplyOptions,
+    threshold: usize,
+) -> Result<()> {
+    let mut scope = confirm::Scope {
+        file_name: options.task_runner.file_name(),
+        ..Default::default()
+    };
+    let mut changed = BTreeSet::new();
+    let mut adding = BTreeSet::new();
+    for task in graph.tasks() {
+        match &task.step {
+            ApplyStep::Resolve { .. } | ApplyStep::Repeat(_) => {}
+            ApplyStep::Add(_) => {
+                scope.adds += 1;
+                adding.insert(task.repo);
+            }
+            ApplyStep::GitRev(..) => scope.git_revs += 1,
+            ApplyStep::Check(_) => continue,
+            ApplyStep::Justfile(_) => {
+                let exists = repos[task.repo].root.join(scope.file_name).exists();
+                match options.justfile_mode {
+                    WriteMode::Create | WriteMode::Merge if !exists => scope.files_created += 1,
+                    WriteMode::Merge => scope.files_merged += 1,
+                    _ => continue,
+                }
+            }
+        }
+        changed.insert(task.repo);
+    }
+    scope.repos = changed.len();
+    scope.add_repos = adding.len();
+    if !scope.needs_confirmation(threshold) {
+        return Ok(());
+    }
+
+    let summary = scope.summary();
+    if !std::io::stdin().is_terminal() {
+        bail!(errors::Coded::new(
+            errors::Code::NotConfirmed,
+            format!("This apply {summary}; pass --yes to proceed without a terminal")
+        ));
+    }
+    eprint!("This apply {summary}. Proceed? [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !confirm::is_yes(&answer) {
+        bail!(errors::Coded::new(
+            errors::Code::NotConfirmed,
+            "Apply cancelled; nothing was changed"
+        ));
+    }
+    Ok(())
+}
+
+/// Checkpoint for this run and the results of repos already done
+///
+/// With `resume`, the checkpoint of an interrupted run over `root` is
+/// continued; otherwise a new one replaces it. Runs go without a checkpoint
+/// when it cannot be written.
+fn open_checkpoint(
+    root: &Path,
+    started: u64,
+    resume: bool,
+) -> Result<(Option<checkpoint::Writer>, HashMap<PathBuf, RepoResult>)> {
+    let Some(path) = data_dir().map(|dir| checkpoint::path(&dir, root)) else {
+        if resume {
+            diagnostics::report(
+                diagnostics::Code::RunState,
+                "no data directory (set MOON_DST_HOME); cannot resume",
+            );
+        }
+        return Ok((None, HashMap::new()));
+    };
+
+    match checkpoint::read::<RepoResult>(&path)? {
+        Some((header, records)) if resume => {
+            println!(
+                "Resuming the run started {}: {} repos already done",
+                timestamp::rfc3339(header.started),
+                records.len()
+            );
+            let resumed = records
+                .into_iter()
+                .map(|result| (result.repo_root.clone(), result))
+                .collect();
+            Ok((Some(checkpoint::Writer::resume(&path)?), resumed))
+        }
+        previous => {
+            if resume {
+                diagnostics::report(
+                    diagnostics::Code::RunState,
+                    "no interrupted run to resume; starting from the beginning",
+                );
+            } else if previous.is_some() {
+                diagnostics::report(
+                    diagnostics::Code::RunState,
+                    "discarding the checkpoint of an interrupted run (use --resume to continue it)",
+                );
+            }
+            let header = checkpoint::Header {
+                root: root.to_path_buf(),
+                started,
+            };
+            match checkpoint::Writer::create(&path, &header) {
+                Ok(writer) => Ok((Some(writer), HashMap::new())),
+                Err(e) => {
+                    diagnostics::report(
+                        diagnostics::Code::RunState,
+                        format!("{e:#}; running without a checkpoint"),
+                    );
+                    Ok((None, HashMap::new()))
+                }
+            }
+        }
+    }
+}
+
+/// Print per-repo results and the summary line, repos grouped as `mode`
+/// says by their path below `roots`; with `group_errors`, errors many repos
+/// share are listed once before the summary
+fn print_apply_results(
+    results: &[RepoResult],
+    mode: grouping::GroupOutput,
+    roots: &[PathBuf],
+    group_errors: bool,
+) {
+    println!("\n=== Results (run {}) ===\n", run_id::current());
+    let repeated = match group_errors {
+        true => repeated::find(&apply_failures(results, |r| {
+            ospath::display(&r.repo_root).to_string()
+        })),
+        false => Vec::new(),
+    };
+    let shared: HashSet<&str> = repeated.iter().map(|r| r.error.as_str()).collect();
+    let print_apply_result = |result: &RepoResult| print_apply_result(result, &shared);
+    if mode == grouping::GroupOutput::Flat {
+        results.iter().for_each(print_apply_result);
+    } else {
+        let groups = grouping::group(results, |r| grouping::labels(&r.repo_root, roots, mode));
+        grouping::walk(&groups, &mut |level, group| {
+            let succeeded = group.items.iter().filter(|r| r.success).count();
+            let count = |status| -> usize { group.items.iter().map(|r| r.count(status)).sum() };
+            let subtotals = format!(
+                "{succeeded}/{} repos succeeded, {} packages updated, {} failed",
+                group.items.len(),
+                count(PackageStatus::Updated),
+                count(PackageStatus::Failed)
+            );
+            println!("{}", grouping::heading(level, &group.label, &subtotals));
+            group.own.iter().for_each(|r| print_apply_result(r));
+            println!();
+        });
+    }
+
+    println!();
+    print_repeated_failures(&repeated);
+    print_apply_summary(results);
+}
+
+/// Each failure of `results` as the results show it, its repo labelled by
+/// `repo`
+fn apply_failures(
+    results: &[RepoResult],
+    repo: impl Fn(&RepoResult) -> String,
+) -> Vec<repeated::Failure> {
+    let mut failures = Vec::new();
+    for result in results {
+        let label = repo(result);
+        for package in result.failed_packages() {
+            failures.push(repeated::Failure {
+                repo: label.clone(),
+                package: Some(package.name.clone()),
+                error: error_output::brief(package.error.as_deref().unwrap_or_default()),
+            });
+        }
+        for err in &result.errors {
+            failures.push(repeated::Failure {
+                repo: label.clone(),
+                package: None,
+                error: error_output::brief(err),
+            });
+        }
+    }
+    failures
+}
+
+/// Print the errors many repos share, each once with the repos it hit
+fn print_repeated_failures(repeated: &[repeated::Repeated]) {
+    if repeated.is_empty() {
+        return;
+    }
+    println!("Repeated failures:");
+    for line in repeated.iter().flat_map(|r| r.lines()) {
+        println!("  {line}");
+    }
+    println!();
+}
+
+/// Stands in for an error listed under "Repeated failures"
+const SEE_REPEATED: &str = "see Repeated failures below";
+
+/// Print a table of the results, one row per repo (its path below the
+/// first of `roots` it is in), then each failure; with `group_errors`, one
+/// many repos share is listed once for all of them
+fn print_apply_table(results: &[RepoResult], roots: &[PathBuf], group_errors: bool) {
+    println!("\n=== Results (run {}) ===\n", run_id::current());
+    let repo = |result: &RepoResult| {
+        let path = roots
+            .iter()
+            .find_map(|root| result.repo_root.strip_prefix(root).ok())
+            .filter(|rel| !rel.as_os_str().is_empty())
+            .unwrap_or(&result.repo_root);
+        ospath::display(path).to_string()
+    };
+    let rows: Vec<Vec<String>> = results
+        .iter()
+        .map(|result| {
+            let mut notes: Vec<String> = [
+                (PackageStatus::Unchanged, "unchanged"),
+                (PackageStatus::Skipped, "skipped"),
+            ]
+            .iter()
+            .map(|&(status, label)| (result.count(status), label))
+            .filter(|&(count, _)| count > 0)
+            .map(|(count, label)| format!("{count} {label}"))
+            .collect();
+            match result.errors.len() {
+                0 => {}
+                1 => notes.push("1 error".to_string()),
+                n => notes.push(format!("{n} errors")),
+            }
+            vec![
+                repo(result),
+                output::status(result.success).to_string(),
+                result.count(PackageStatus::Updated).to_string(),
+                result.count(PackageStatus::Failed).to_string(),
+                format!("{:.1}s", timestamp::reported(result.duration).as_secs_f64()),
+                notes.join(", "),
+            ]
+        })
+        .collect();
+    print!(
+        "{}",
+        output::render_table(
+            &[
+                "Repository",
+                "Status",
+                "Updated",
+                "Failed",
+                "Duration",
+                "Notes"
+            ],
+            &rows
+        )
+    );
+
+    let failures = apply_failures(results, repo);
+    let repeated = match group_errors {
+        true => repeated::find(&failures),
+        false => Vec::new(),
+    };
+    let shared: HashSet<&str> = repeated.iter().map(|r| r.error.as_str()).collect();
+    let mut last = None;
+    for failure in failures
+        .iter()
+        .filter(|f| !shared.contains(f.error.as_str()))
+    {
+        if last.is_none() {
+            println!("\nFailures:");
+        }
+        if last != Some(&failure.repo) {
+            println!("  {}", failure.repo);
+            last = Some(&failure.repo);
+        }
+        match &failure.package {
+            Some(package) => println!("    moon add {package}: {}", failure.error),
+            None => println!("    {}", failure.error),
+        }
+    }
+    println!();
+    print_repeated_failures(&repeated);
+    print_apply_summary(results);
+}
+
+/// The totals of an apply and the failed repos by owner
+fn print_apply_summary(results: &[RepoResult]) {
+    let success_count = results.iter().filter(|r| r.success).count();
+    let count = |status| -> usize { results.iter().map(|r| r.count(status)).sum() };
+    let summary = [
+        format!("{}/{} repos succeeded", success_count, results.len()),
+        format!("{} packages updated", count(PackageStatus::Updated)),
+        format!("{} unchanged", count(PackageStatus::Unchanged)),
+        format!("{} failed", count(PackageStatus::Failed)),
+    ];
+    for line in output::facts("Summary", &summary) {
+        println!("{line}");
+    }
+    let by_owner = failures_by_owner(results);
+    if by_owner.keys().any(|owner| *owner != UNOWNED) {
+        println!("\nFailures by owner:");
+        for (owner, failed) in by_owner {
+            let repos: Vec<String> = failed
+                .iter()
+                .map(|r| ospath::display(&r.repo_root).to_string())
+                .collect();
+            for line in output::facts(owner, &repos) {
+                println!("  {line}");
+            }
+        }
+    }
+}
+
+/// Print one repo's result; errors in `shared` point to where they are
+/// listed once
+fn print_apply_result(result: &RepoResult, shared: &HashSet<&str>) {
+    let brief = |error: &str| match error_output::brief(error) {
+        error if shared.contains(error.as_str()) => SEE_REPEATED.to_string(),
+        error => error,
+    };
+    println!(
+        "[{}] {}",
+        output::status(result.success),
+        ospath::display(&result.repo_root)
+    );
+
+    let counts: Vec<String> = [
+        (PackageStatus::Updated, "updated"),
+        (PackageStatus::Unchanged, "unchanged"),
+        (PackageStatus::Failed, "failed"),
+        (PackageStatus::Skipped, "skipped"),
+    ]
+    .iter()
+    .map(|&(status, label)| (result.count(status), label))
+    .filter(|&(count, _)| count > 0)
+    .map(|(count, label)| format!("{count} {label}"))
+    .collect();
+    if !counts.is_empty() {
+        for line in output::facts("Packages", &counts) {
+            println!("  {line}");
+        }
+    }
+
+    for package in &result.packages {
+        match package.status {
+            PackageStatus::Updated => println!(
+                "    {}",
+                output::item(
+                    "Updated",
+                    &format!(
+                        "{}: {}",
+                        package.name,
+                        output::change(
+                            package.old_version.as_deref().unwrap_or("?"),
+                            package.new_version.as_deref().unwrap_or("?")
+                        )
+                    )
+                )
+            ),
+            PackageStatus::Failed => {
+                let error = brief(package.error.as_deref().unwrap_or_default());
+                match output::plain() {
+                    true => println!("    FAIL: {}: {error}", package.name),
+                    false => println!("    - {}: FAILED: {error}", package.name),
+                }
+            }
+            PackageStatus::Unchanged | PackageStatus::Skipped => {}
+        }
+    }
+
+    for err in &result.errors {
+        println!("  Error: {}", brief(err));
+    }
+}
+
+/// Owner label of repos nobody owns
+const UNOWNED: &str = "(no owner)";
+
+/// Failed repos under each of their owners
+fn failures_by_owner<'a>(
+    results: impl IntoIterator<Item = &'a RepoResult>,
+) -> BTreeMap<&'a str, Vec<&'a RepoResult>> {
+    let mut by_owner: BTreeMap<&str, Vec<&RepoResult>> = BTreeMap::new();
+    for result in results.into_iter().filter(|r| !r.success) {
+        if result.owners.is_empty() {
+            by_owner.entry(UNOWNED).or_default().push(result);
+        }
+        for owner in &result.owners {
+            by_owner.entry(owner).or_default().push(result);
+        }
+    }
+    by_owner
+}
+
+/// Emit failure annotations and a results table for GitHub Actions
+fn report_apply_github(results: &[RepoResult]) -> Result<()> {
+    for result in results {
+        let repo = ospath::display(&result.repo_root).to_string();
+        for package in result.failed_packages() {
+            println!(
+                "{}",
+                github::annotation(
+                    github::AnnotationLevel::Error,
+                    None,
+                    None,
+                    Some("moon-dst apply"),
+                    &format!(
+                        "{repo}: moon add {} failed: {}",
+                        package.name,
+                        package.error.as_deref().unwrap_or_default()
+                    ),
+                )
+            );
+        }
+        for err in &result.errors {
+            println!(
+                "{}",
+                github::annotation(
+                    github::AnnotationLevel::Error,
+                    None,
+                    None,
+                    Some("moon-dst apply"),
+                    &format!("{repo}: {err}"),
+                )
+            );
+        }
+    }
+
+    let results: Vec<&RepoResult> = results.iter().collect();
+    github::append_step_summary(&apply_markdown(&results, false))
+}
+
+/// Markdown report of an apply run: a results table and the failures
+///
+/// With `only_failed`, successful repos are left out of the table.
+fn apply_markdown(results: &[&RepoResult], only_failed: bool) -> String {
+    let success_count = results.iter().filter(|r| r.success).count();
+    let mut summary = format!(
+        "## moon-dst apply\n\n{success_count}/{} repos succeeded\n\n| Repository | Status | Updated | Failed |\n|---|---|---|---|\n",
+        results.len()
+    );
+    let shown: Vec<&RepoResult> = results
+        .iter()
+        .copied()
+        .filter(|r| !only_failed || !r.success)
+        .collect();
+    for result in &shown {
+        let failed: Vec<&str> = result.failed_packages().map(|p| p.name.as_str()).collect();
+        summary.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            github::escape_cell(&ospath::display(&result.repo_root).to_string()),
+            if result.success { "OK" } else { "FAILED" },
+            result.count(PackageStatus::Updated),
+            github::escape_cell(&failed.join(", "))
+        ));
+    }
+
+    let mut failures = Vec::new();
+    for result in &shown {
+        let repo = ospath::display(&result.repo_root);
+        for package in result.failed_packages() {
+            failures.push(format!(
+                "- {repo}: moon add {} failed: {}",
+                package.name,
+                error_output::brief(package.error.as_deref().unwrap_or_default())
+            ));
+        }
+        for err in &result.errors {
+            failures.push(format!("- {repo}: {}", error_output::brief(err)));
+        }
+    }
+    if !failures.is_empty() {
+        summary.push_str("\n### Failures\n\n");
+        summary.push_str(&failures.join("\n"));
+        summary.push('\n');
+    }
+    let by_owner = failures_by_owner(results.iter().copied());
+    if by_owner.keys().any(|owner| *owner != UNOWNED) {
+        summary.push_str("\n### Failures by owner\n\n");
+        for (owner, failed) in by_owner {
+            let repos: Vec<String> = failed
+                .iter()
+                .map(|r| ospath::display(&r.repo_root).to_string())
+                .collect();
+            summary.push_str(&format!("- {owner}: {}\n", repos.join(", ")));
+        }
+    }
+    summary
+}
+
+/// Journal entry for an apply run
+fn history_run(started: u64, root: &Path, results: &[RepoResult]) -> history::Run {
+    let repos = results
+        .iter()
+        .map(|result| history::RepoRun {
+            repo: result.repo_root.clone(),
+            success: result.success,
+            changes: result
+                .packages
+                .iter()
+                .filter(|p| p.status == PackageStatus::Updated)
+                .map(|p| history::Change {
+                    name: p.name.clone(),
+                    old_version: p.old_version.clone(),
+                    new_version: p.new_version.clone(),
+                })
+                .collect(),
+            failed: result.failed_packages().map(|p| p.name.clone()).collect(),
+        })
+        .filter(|repo| !repo.success || !repo.changes.is_empty())
+        .collect();
+    history::Run {
+        id: Some(run_id::current().to_string()),
+        started,
+        root: std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf()),
+        repos,
+    }
+}
+
+/// Append the run to the journal and regenerate the feed if requested
+///
+/// A journal that cannot be written only produces a warning; the feed then
+/// covers this run alone.
+fn record_history(run: &history::Run, feed_out: Option<&Path>) -> Result<()> {
+    let mut runs = None;
+    match history::journal_path() {
+        Some(path) => match history::append(&path, run) {
+            Ok(()) if feed_out.is_some() => runs = Some(history::read(&path)?),
+            Ok(()) => {}
+            Err(e) => diagnostics::report(
+                diagnostics::Code::RunState,
+                format!("failed to record run history: {e:#}"),
+            ),
+        },
+        None => diagnostics::report(
+            diagnostics::Code::RunState,
+            "cannot record run history (HOME not set)",
+        ),
+    }
+    if let Some(feed_out) = feed_out {
+        let runs = runs.unwrap_or_else(|| vec![run.clone()]);
+        feed::write(feed_out, &runs)?;
+    }
+    Ok(())
+}
+
+/// Send the Markdown report to the configured recipients, and each owner
+/// in `[owners] emails` the report on their repos
+fn email_apply_report(config: &Config, results: &[RepoResult], dry_run: bool) -> Result<()> {
+    let mut reports = Vec::new();
+    if !config.email_to.value.is_empty() {
+        reports.push((config.email_to.value.clone(), results.iter().collect()));
+    }
+    for (owner, to) in &config.owners_emails.value {
+        let owned: Vec<&RepoResult> = results
+            .iter()
+            .filter(|r| r.owners.contains(owner))
+            .collect();
+        if !owned.is_empty() {
+            reports.push((to.clone(), owned));
+        }
+    }
+    for (to, results) in reports {
+        email_report(config, to, &results, dry_run)?;
+    }
+    Ok(())
+}
+
+/// Email the report on `results` to `to`, unless only failures are
+/// reported and there are none
+fn email_report(
+    config: &Config,
+    to: Vec<String>,
+    results: &[&RepoResult],
+    dry_run: bool,
+) -> Result<()> {
+    let only_failed = config.email_on.value == EmailOn::Failure;
+    let failed = results.iter().filter(|r| !r.success).count();
+    if only_failed && failed == 0 {
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("Would email the report to {}", to.join(", "));
+        return Ok(());
+    }
+    let subject = if failed > 0 {
+        format!("moon-dst apply: {failed}/{} repos failed", results.len())
+    } else {
+        format!("moon-dst apply: {} repos succeeded", results.len())
+    };
+    let message = email::Message {
+        to,
+        subject,
+        body: format!(
+            "{}\nRun {}\n",
+            apply_markdown(results, only_failed),
+            run_id::current()
+        ),
+    };
+    email::send(&config.smtp()?, &message)?;
+    println!("Emailed the report to {}", message.to.join(", "));
+    Ok(())
+}
+
+/// Title of issues opened for failing repos, also used to find them again
+const FAILURE_ISSUE_TITLE: &str = "moon-dst: dependency updates keep failing";
+
+/// Longest log included in an issue body
+const MAX_ISSUE_LOG: usize = 30_000;
+
+/// Open an issue on each repo that has failed `after` runs in a row,
+/// unless one is already open
+///
+/// Returns false if any issue could not be checked or opened.
+fn file_failure_issues(
+    config: &Config,
+    after: u32,
+    run: &history::Run,
+    repos: &[RepoInfo],
+    results: &[RepoResult],
+    dry_run: bool,
+) -> bool {
+    let mut runs = match history::journal_path().map(|path| history::read(&path)) {
+        Some(Ok(runs)) => runs,
+        Some(Err(e)) => {
+            eprintln!("Error: cannot read the run history: {e:#}");
+            return false;
+        }
+        None => Vec::new(),
+    };
+    if dry_run {
+        runs.push(run.clone());
+    }
+
+    let mut ok = true;
+    for (repo, result) in repos.iter().zip(results) {
+        if result.success || repo.host.is_some() {
+            continue;
+        }
+        let streak = history::failure_streak(&runs, &run.root, &result.repo_root);
+        if streak < after as usize {
+            continue;
+        }
+        let mut log = result.failure_messages().join("\n");
+        if log.len() > MAX_ISSUE_LOG {
+            let mut end = MAX_ISSUE_LOG;
+            while !log.is_char_boundary(end) {
+                end -= 1;
+            }
+            log.truncate(end);
+            log.push_str("\n(truncated)");
+        }
+        let issue = forge::Issue {
+            title: FAILURE_ISSUE_TITLE.to_string(),
+            body: format!(
+                "`moon-dst apply` has failed in this repository for {streak} consecutive runs.\n\n```text\n{log}\n```\n"
+            ),
+            labels: config.issues_labels.value.clone(),
+        };
+        match file_issue(config, repo, &issue, dry_run) {
+            Ok(Some(url)) => println!("Opened {url}"),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Error: {}: {e:#}", ospath::display(&repo.root));
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+fn file_issue(
+    config: &Config,
+    repo: &RepoInfo,
+    issue: &forge::Issue,
+    dry_run: bool,
+) -> Result<Option<String>> {
+    let (remote, kind) = forge_repo(config, &repo.root)?;
+    if dry_run {
+        println!(
+            "Would open an issue on {}/{} unless one is open: {}",
+            remote.host, remote.path, issue.title
+        );
+        return Ok(None);
+    }
+    let provider = forge::provider(
+        kind,
+        &remote,
+        config.pr_api_url.value.as_deref(),
+        config.pr_token_env.value.as_deref(),
+    )?;
+    if let Some(url) = provider.find_open_issue(&remote, &issue.title)? {
+        println!("Issue already open: {url}");
+        return Ok(None);
+    }
+    provider.open_issue(&remote, issue).map(Some)
+}
+
+/// The forge repo behind `origin` and its provider
+fn forge_repo(
+    config: &Config,
+    repo_root: &Path,
+) -> Result<(forge::RemoteRepo, forge::ProviderKind)> {
+    let url = forge::origin_url(repo_root)?;
+    let remote = forge::RemoteRepo::parse(&url)
+        .with_context(|| format!("Cannot tell the forge repo from origin {url}"))?;
+    let kind = config
+        .pr_provider
+        .value
+        .or_else(|| forge::ProviderKind::detect(&remote.host))
+        .with_context(|| {
+            format!(
+                "Unknown forge {}; set provider in the [pr] config section",
+                remote.host
+            )
+        })?;
+    Ok((remote, kind))
+}
+
+/// Version changes of a repo, for message templates
+fn template_updates(result: &RepoResult) -> Vec<message::Update> {
+    result
+        .packages
+        .iter()
+        .filter(|p| p.status == PackageStatus::Updated)
+        .map(|p| message::Update {
+            name: p.name.clone(),
+            from: p.old_version.clone().unwrap_or_else(|| "?".to_string()),
+            to: p.new_version.clone().unwrap_or_else(|| "?".to_string()),
+        })
+        .collect()
+}
+
+/// Add entries for the updates of each repo to its changelog
+///
+/// Returns false if any changelog could not be written.
+fn update_changelogs(
+    config: &Config,
+    run_id: &str,
+    repos: &[RepoInfo],
+    results: &[RepoResult],
+    dry_run: bool,
+) -> bool {
+    let Some(file) = &config.changelog_file.value else {
+        return true;
+    };
+    let format = config.changelog_format.value;
+    let entry = config
+        .changelog_entry
+        .value
+        .as_deref()
+        .unwrap_or(format.default_entry());
+    let template = format!("{{{{#each updates}}}}{}\n{{{{/each}}}}", entry.trim_end());
+
+    let mut ok = true;
+    for (repo, result) in repos.iter().zip(results) {
+        let updates = template_updates(result);
+        if updates.is_empty() || repo.host.is_some() {
+            continue;
+        }
+        let vars = message::Vars {
+            repo_path: &repo.root,
+            run_id,
+            updates: &updates,
+        };
+        let path = repo.root.join(file);
+        let written = message::render(&template, &vars)
+            .context("Invalid [changelog] entry template")
+            .and_then(|entries| {
+                let entries: Vec<String> = entries.lines().map(str::to_string).collect();
+                let current = std::fs::read_to_string(&path).ok();
+                let new = changelog::insert(current.as_deref(), format, &entries);
+                if dry_run {
+                    print!(
+                        "{}",
+                        diff::preview(&file.display().to_string(), current.as_deref(), &new)
+                    );
+                    return Ok(());
+                }
+                capabilities::write(&path, new)
+            });
+        if let Err(e) = written {
+            eprintln!("Error: {}: {e:#}", ospath::display(&repo.root));
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Commit, push and open a pull request for each repo with updates
+///
+/// Returns false if any repo failed; the others are still processed.
+fn open_pull_requests(
+    config: &Config,
+    root: &Path,
+    run_id: &str,
+    repos: &[RepoInfo],
+    results: &[RepoResult],
+    dry_run: bool,
+) -> bool {
+    let mut ok = true;
+    let superprojects: Vec<Option<PathBuf>> = repos
+        .iter()
+        .map(|repo| {
+            let local = config.pr_update_submodules.value && repo.host.is_none();
+            local.then(|| submodule::superproject(&repo.root)).flatten()
+        })
+        .collect();
+    // Submodules first, deepest first, so that their superprojects can
+    // record the new commits
+    let mut order: Vec<usize> = (0..repos.len()).collect();
+    order.sort_by_key(|&i| {
+        (
+            superprojects[i].is_none(),
+            std::cmp::Reverse(repos[i].root.components().count()),
+        )
+    });
+    let mut pointers: BTreeMap<PathBuf, Vec<submodule::Pointer>> = BTreeMap::new();
+    let mut open =
+        |repo: &RepoInfo, updates: &[message::Update], pending: &[submodule::Pointer]| {
+            let vars = message::Vars {
+                repo_path: &repo.root,
+                run_id,
+                updates,
+            };
+            let rel = repo.root.strip_prefix(root).unwrap_or(&repo.root);
+            match open_pull_request(config, repo, rel, &vars, pending, dry_run) {
+                Ok(Some((url, commit))) => {
+                    println!("Opened {url}");
+                    Some(commit)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    eprintln!("Error: {}: {e:#}", ospath::display(&repo.root));
+                    ok = false;
+                    None
+                }
+            }
+        };
+    for i in order {
+        let (repo, result) = (&repos[i], &results[i]);
+        let updates = template_updates(result);
+        let pending = pointers.remove(&repo.root).unwrap_or_default();
+        if !result.success || (updates.is_empty() && pending.is_empty()) {
+            continue;
+        }
+        if repo.host.is_some() {
+            diagnostics::report(
+                diagnostics::Code::Unsupported,
+                format!(
+                    "{}: pull requests are not supported for remote repos",
+                    repo.location()
+                ),
+            );
+            continue;
+        }
+        let commit = open(repo, &updates, &pending);
+        if let (Some(superproject), Some(commit)) = (&superprojects[i], commit) {
+            pointers
+                .entry(superproject.clone())
+                .or_default()
+                .push(submodule::pointer(superproject, &repo.root, &commit));
+        }
+    }
+    // Superprojects without MoonBit modules of their own
+    for (superproject, pending) in pointers {
+        open(&RepoInfo::local(superproject, Vec::new()), &[], &pending);
+    }
+    ok
+}
+
+/// Open a pull request for `repo`, returning its URL and the pushed commit
+fn open_pull_request(
+    config: &Config,
+    repo: &RepoInfo,
+    rel: &Path,
+    vars: &message::Vars,
+    pointers: &[submodule::Pointer],
+    dry_run: bool,
+) -> Result<Option<(String, String)>> {
+    let (title, body) = message::select(
+        rel,
+        &config.pr_repos.value,
+        &config.pr_title.value,
+        &config.pr_body.value,
+    );
+    let title = message::render(title, vars).context("Invalid [pr] title template")?;
+    let body = message::render(body, vars).context("Invalid [pr] body template")?;
+    let (remote, kind) = forge_repo(config, &repo.root)?;
+    let base = match &config.pr_base.value {
+        Some(base) => base.clone(),
+        None => forge::current_branch(&repo.root)?,
+    };
+    let pr = forge::PullRequest {
+        title: title.trim().to_string(),
+        body: body.trim_end().to_string(),
+        head: config.pr_branch.value.clone(),
+        base,
+    };
+    if dry_run {
+        println!(
+            "Would open a pull request {} -> {} on {}/{}",
+            pr.head, pr.base, remote.host, remote.path
+        );
+        return Ok(None);
+    }
+    for pointer in pointers {
+        println!(
+            "[{}] Moving submodule {} to {}",
+            ospath::display(&repo.root),
+            pointer.path.display(),
+            pointer.commit
+        );
+    }
+
+    let provider = forge::provider(
+        kind,
+        &remote,
+        config.pr_api_url.value.as_deref(),
+        config.pr_token_env.value.as_deref(),
+    )?;
+    let mut files: Vec<PathBuf> = repo.moon_mods.iter().map(|m| m.path.clone()).collect();
+    if let Some(changelog) = &config.changelog_file.value {
+        // Not written when only submodule pointers move
+        if !vars.updates.is_empty() {
+            files.push(repo.root.join(changelog));
+        }
+    }
+    let commit = forge::commit_and_push(
+        &repo.root,
+        &pr.head,
+        &files,
+        pointers,
+        &pr.title,
+        &pr.body,
+        &config.commit_settings()?,
+    )?;
+    let url = provider.open_pull_request(&remote, &pr)?;
+    Ok(Some((url, commit)))
+}
+
+/// Phase of an apply step; indexes the per-phase job limits
+#[derive(Clone, Copy)]
+enum Phase {
+    /// `moon update` and target lookups, mostly network bound
+    Resolve,
+    /// moon add runs and file edits in the repo
+    Change,
+    /// `moon check`, CPU bound
+    Verify,
+}
+
+/// One step of applying updates to a repo
+enum ApplyStep<'a> {
+    /// Run `moon update`, then look up the target of each registry package
+    /// and the head commit of each git dependency
+    Resolve {
+        deps: Vec<&'a str>,
+        git: Vec<(&'a MoonModInfo, &'a Dep)>,
+    },
+    Add(&'a str),
+    /// Further moon add passes for packages that changed in the last pass
+    Repeat(Vec<&'a str>),
+    /// Bump the `rev` of a git dependency in a module's manifest
+    GitRev(&'a MoonModInfo, &'a Dep),
+    Check(&'a MoonModInfo),
+    /// Write the justfile with these variable overrides
+    Justfile(BTreeMap<String, String>),
+}
+
+impl ApplyStep<'_> {
+    fn phase(&self) -> Phase {
+        match self {
+            ApplyStep::Resolve { .. } => Phase::Resolve,
+            ApplyStep::Check(_) => Phase::Verify,
+            _ => Phase::Change,
+        }
+    }
+}
+
+/// A step bound to the index of its repo
+struct ApplyTask<'a> {
+    repo: usize,
+    step: ApplyStep<'a>,
+}
+
+/// What a finished step reports back
+enum StepReport {
+    Done,
+    Added(Vec<AddAttempt>),
+    /// A git dependency now pinned to this revision
+    Revision(AddAttempt, String),
+    Check(verify::ModuleCheck),
+    Error(String),
+}
+
+/// One `moon add` run
+struct AddAttempt {
+    dep: String,
+    duration: Duration,
+    error: Option<String>,
+}
+
+impl AddAttempt {
+    /// Run `moon add <spec>` for the package `dep`
+    fn run(repo: &RepoInfo, dep: &str, spec: &str) -> Self {
+        let started = Instant::now();
+        let error = run_moon_command(repo.runner.as_ref(), &repo.moon, &["add", spec], &repo.root)
+            .err()
+            .map(|e| e.to_string());
+        AddAttempt {
+            dep: dep.to_string(),
+            duration: started.elapsed(),
+            error,
+        }
+    }
+}
+
+/// Succeed unless one of the attempts failed
+fn added(attempts: Vec<AddAttempt>) -> std::result::Result<StepReport, StepReport> {
+    if attempts.iter().all(|a| a.error.is_none()) {
+        Ok(StepReport::Added(attempts))
+    } else {
+        Err(StepReport::Added(attempts))
+    }
+}
+
+/// Build the task graph for all repos
+///
+/// Per repo: a resolve step first (`moon update` and target lookups); every
+/// other step requires it to succeed. `moon add` calls rewrite
+/// moon.mod.json, so they run one after another, followed by a single task
+/// for any further passes and the git revision bumps. Module checks run in
+/// parallel after the last add; the justfile step is independent of the adds.
+/// Each step runs in the lane of its [`Phase`]. Repos already done in a
+/// resumed run get no tasks.
+fn build_apply_graph<'a>(
+    repos: &'a [RepoInfo],
+    root: &Path,
+    options: &ApplyOptions,
+    resumed: &HashMap<PathBuf, RepoResult>,
+) -> scheduler::Graph<ApplyTask<'a>> {
+    let mut graph = scheduler::Graph::new();
+    let registry = &options.registry;
+    let now = timestamp::now();
+
+    for (index, repo) in repos.iter().enumerate() {
+        if resumed.contains_key(&repo.location_path()) {
+            continue;
+        }
+        let rel = repo.root.strip_prefix(root).unwrap_or(&repo.root);
+        let selected = |dep: &str| options.packages.matches(dep);
+        let pinned = |dep: &str| pins::is_pinned(&options.pins, dep, rel, now);
+        let mut deps: Vec<&str> = repo
+            .moon_mods
+            .iter()
+            .flat_map(|m| &m.deps)
+            .filter(|d| d.is_registry())
+            .map(|d| d.name.as_str())
+            .filter(|dep| match &options.plan {
+                Some(plan) => plan.target(&repo.root, dep).is_some(),
+                None => {
+                    (selected(dep) && !pinned(dep))
+                        || (options.fix_yanked && repo.flagged_version(dep, registry).is_some())
+                }
+            })
+            .collect();
+        deps.sort_unstable();
+        deps.dedup();
+        if options.verbose {
+            let mut held: Vec<&str> = repo
+                .moon_mods
+                .iter()
+                .flat_map(|m| &m.deps)
+                .map(|d| d.name.as_str())
+                .filter(|dep| pinned(dep) && !deps.contains(dep))
+                .collect();
+            held.sort_unstable();
+            held.dedup();
+            for dep in held {
+                println!("[{}] Pinned: {dep}", repo.location());
+            }
+        }
+
+        // Manifest and justfile edits are local file writes
+        let local = repo.host.is_none();
+        let git: Vec<(&MoonModInfo, &Dep)> = if options.update_git_deps && local {
+            repo.moon_mods
+                .iter()
+                .flat_map(|m| m.deps.iter().map(move |d| (m, d)))
+                .filter(|(_, d)| matches!(d.source, DepSource::Git { .. }) && selected(&d.name))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let task = |step: ApplyStep<'a>| {
+            let lane = step.phase() as usize;
+            (ApplyTask { repo: index, step }, lane)
+        };
+        let resolve = (!options.skip_update || !deps.is_empty() || !git.is_empty()).then(|| {
+            let (task, lane) = task(ApplyStep::Resolve {
+                deps: deps.clone(),
+                git: git.clone(),
+            });
+            graph.add_in(task, lane)
+        });
+        let add_after_update = |graph: &mut scheduler::Graph<ApplyTask<'a>>, step| {
+            let (task, lane) = task(step);
+            let id = graph.add_in(task, lane);
+            if let Some(resolve) = resolve {
+                graph.requires(id, resolve);
+            }
+            id
+        };
+
+        let mut last_add = None;
+        for dep in &deps {
+            let id = add_after_update(&mut graph, ApplyStep::Add(dep));
+            if let Some(previous) = last_add {
+                graph.after(id, previous);
+            }
+            last_add = Some(id);
+        }
+        if let Some(previous) = last_add.filter(|_| options.max_passes() > 1) {
+            let id = add_after_update(&mut graph, ApplyStep::Repeat(deps.clone()));
+            graph.after(id, previous);
+            last_add = Some(id);
+        }
+        for (moon_mod, dep) in git {
+            let id = add_after_update(&mut graph, ApplyStep::GitRev(moon_mod, dep));
+            if let Some(previous) = last_add {
+                graph.after(id, previous);
+            }
+            last_add = Some(id);
+        }
+
+        if options.verify {
+            for moon_mod in &repo.moon_mods {
+                let id = add_after_update(&mut graph, ApplyStep::Check(moon_mod));
+                if let Some(last_add) = last_add {
+                    graph.after(id, last_add);
+                }
+            }
+        }
+
+        if options.write_justfile && local {
+            let vars = options.justfile_template.variables(rel);
+            add_after_update(&mut graph, ApplyStep::Justfile(vars));
+        }
+    }
+    graph
+}
+
+/// Run one step; `Err` marks the step (and the repo) as failed
+/// Run the tasks of `graph` within `limits`, telling the observer when a
+/// repo's first task starts and folding each outcome into `collector`
+fn run_apply_graph<'a>(
+    repos: &[RepoInfo],
+    graph: &'a scheduler::Graph<ApplyTask<'a>>,
+    limits: &scheduler::Limits,
+    fail_fast: bool,
+    options: &ApplyOptions,
+    collector: &mut ResultCollector<'a>,
+) {
+    let started_repos: Vec<AtomicBool> = repos.iter().map(|_| AtomicBool::new(false)).collect();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            scheduler::stream(
+                graph,
+                limits,
+                fail_fast,
+                &options.cancel,
+                |task| {
+                    let repo = &repos[task.repo];
+                    if !started_repos[task.repo].swap(true, Ordering::Relaxed) {
+                        options.observer.on_repo_start(&repo.root);
+                    }
+                    run_apply_task(repo, &task.step, options)
+                },
+                sender,
+            );
+        });
+        for (task, done) in receiver {
+            collector.record(task, done);
+        }
+    });
+}
+
+fn run_apply_task(
+    repo: &RepoInfo,
+    step: &ApplyStep,
+    options: &ApplyOptions,
+) -> std::result::Result<StepReport, StepReport> {
+    let dry_run = options.dry_run;
+    let verbose = options.verbose;
+
+    match step {
+        ApplyStep::Resolve { deps, git } => {
+            if !options.skip_update {
+                options.observer.on_command(&repo.root, &["update"]);
+                if !dry_run {
+                    if let Err(e) =
+                        run_moon_command(repo.runner.as_ref(), &repo.moon, &["update"], &repo.root)
+                    {
+                        return Err(StepReport::Error(e.to_string()));
+                    }
+                    if verbose {
+                        println!("[{}] moon update succeeded", ospath::display(&repo.root));
+                    }
+                }
+            }
+            // Looked up here so the change phase only runs moon and edits files
+            let specs: Vec<_> = deps
+                .iter()
+                .map(|dep| {
+                    let key = (repo.root.clone(), dep.to_string());
+                    (key, add_spec(repo, dep, options))
+                })
+                .collect();
+            options.resolved.specs.lock().unwrap().extend(specs);
+            if !dry_run {
+                for (moon_mod, dep) in git {
+                    let DepSource::Git { url, branch, .. } = &dep.source else {
+                        continue;
+                    };
+                    let rev = gitdeps::latest_commit(url, branch.as_deref())
+                        .map_err(|e| format!("{e:#}"));
+                    let key = (moon_mod.path.clone(), dep.name.clone());
+                    options.resolved.revs.lock().unwrap().insert(key, rev);
+                }
+            }
+            Ok(StepReport::Done)
+        }
+        ApplyStep::Add(dep) => {
+            let Some(spec) = options.add_spec(repo, dep) else {
+                if verbose || dry_run {
+                    println!(
+                        "[{}] skip {dep}: no newer release published at least {} days ago",
+                        ospath::display(&repo.root),
+                        options.min_age.unwrap_or_default()
+                    );
+                }
+                return Ok(StepReport::Added(Vec::new()));
+            };
+            options.observer.on_command(&repo.root, &["add", &spec]);
+            if dry_run {
+                print!("{}", preview_add(&options.registry, repo, dep, &spec));
+                return Ok(StepReport::Added(Vec::new()));
+            }
+            added(vec![AddAttempt::run(repo, dep, &spec)])
+        }
+        ApplyStep::Repeat(deps) => added(repeat_passes(repo, deps, options)),
+        ApplyStep::GitRev(moon_mod, dep) => {
+            let DepSource::Git { url, branch, .. } = &dep.source else {
+                return Ok(StepReport::Done);
+            };
+            if verbose || dry_run {
+                println!(
+                    "[{}] bump git rev of {} to the head of {url}{}",
+                    ospath::display(&repo.root),
+                    dep.name,
+                    branch
+                        .as_deref()
+                        .map(|b| format!(" ({b})"))
+                        .unwrap_or_default()
+                );
+            }
+            if dry_run {
+                return Ok(StepReport::Added(Vec::new()));
+            }
+            let started = Instant::now();
+            let key = (moon_mod.path.clone(), dep.name.clone());
+            let resolved = options.resolved.revs.lock().unwrap().remove(&key);
+            let result = match resolved {
+                Some(rev) => rev.map_err(anyhow::Error::msg),
+                None => gitdeps::latest_commit(url, branch.as_deref()),
+            }
+            .and_then(|rev| set_git_rev(moon_mod, dep, rev));
+            let attempt = AddAttempt {
+                dep: dep.name.clone(),
+                duration: started.elapsed(),
+                error: result.as_ref().err().map(|e| format!("{e:#}")),
+            };
+            match result {
+                Ok(rev) => Ok(StepReport::Revision(attempt, rev)),
+                Err(_) => Err(StepReport::Added(vec![attempt])),
+            }
+        }
+        ApplyStep::Check(moon_mod) => {
+            let check = verify::run_in_module(
+                repo,
+                moon_mod,
+                &["check"],
+                dry_run,
+                options.observer.as_ref(),
+            );
+            if check.success() {
+                Ok(StepReport::Check(check))
+            } else {
+                Err(StepReport::Check(check))
+            }
+        }
+        // Justfile problems are reported but never fail the repo
+        ApplyStep::Justfile(vars) => Ok(
+            match handle_justfile(
+                &repo.root,
+                options.task_runner,
+                &options.justfile_template.text,
+                vars,
+                options.justfile_mode,
+                dry_run,
+                verbose,
+            ) {
+                Ok(_) => StepReport::Done,
+                Err(e) => StepReport::Error(e.to_string()),
+            },
+        ),
+    }
+}
+
+/// Point a git dependency at the commit `rev` and return it
+fn set_git_rev(moon_mod: &MoonModInfo, dep: &Dep, rev: String) -> Result<String> {
+    let path = &moon_mod.path;
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let updated = gitdeps::set_rev(&content, &dep.name, &rev).with_context(|| {
+        format!(
+            "{} is not declared as an object in {}",
+            dep.name,
+            path.display()
+        )
+    })?;
+    if updated != content {
+        capabilities::write(path, updated)?;
+    }
+    Ok(rev)
+}
+
+/// Run moon add passes 2..=max for packages whose declared version changed
+/// in the previous pass, stopping once a pass changes nothing
+///
+/// Versions before the first pass come from discovery; after each pass the
+/// manifests are re-read.
+fn repeat_passes(repo: &RepoInfo, deps: &[&str], options: &ApplyOptions) -> Vec<AddAttempt> {
+    let mut attempts = Vec::new();
+    if options.dry_run {
+        println!(
+            "[{}] repeat moon add for changed packages (up to {} passes)",
+            ospath::display(&repo.root),
+            options.max_passes()
+        );
+        return attempts;
+    }
+
+    let mut before: DeclaredVersions = repo
+        .moon_mods
+        .iter()
+        .flat_map(|m| m.deps.iter().map(move |dep| (m, dep)))
+        .map(|(m, dep)| ((m.path.clone(), dep.name.clone()), dep.version.clone()))
+        .collect();
+    let mut candidates: Vec<&str> = deps.to_vec();
+    for pass in 2..=options.max_passes() {
+        let after = declared_versions(repo);
+        candidates.retain(|dep| versions_of(&before, dep) != versions_of(&after, dep));
+        if candidates.is_empty() {
+            if options.verbose {
+                println!(
+                    "[{}] stable after {} passes",
+                    ospath::display(&repo.root),
+                    pass - 1
+                );
+            }
+            break;
+        }
+        for dep in &candidates {
+            // Cancelled runs keep the passes done so far
+            if options.cancel.is_cancelled() {
+                return attempts;
+            }
+            let Some(spec) = add_spec(repo, dep, options) else {
+                continue;
+            };
+            if options.verbose {
+                println!("[{}] pass {pass}", ospath::display(&repo.root));
+            }
+            options.observer.on_command(&repo.root, &["add", &spec]);
+            attempts.push(AddAttempt::run(repo, dep, &spec));
+        }
+        before = after;
+    }
+    attempts
+}
+
+/// Argument for `moon add`: the package name, or with `--min-age` the newest
+/// release old enough as `<package>@<version>`
+///
+/// Returns `None` when `--min-age` leaves no release newer than the declared
+/// one. With `--fix-yanked`, yanked or deprecated pins ignore `--min-age`.
+/// With `--plan`, the planned version is always used.
+/// The registry is read at call time, after `moon update` refreshed it.
+fn add_spec(repo: &RepoInfo, dep: &str, options: &ApplyOptions) -> Option<String> {
+    if let Some(plan) = &options.plan {
+        return plan
+            .target(&repo.root, dep)
+            .map(|version| format!("{dep}@{version}"));
+    }
+    let Some(days) = options.min_age else {
+        return Some(dep.to_string());
+    };
+    let registry = &options.registry;
+    if options.fix_yanked && repo.flagged_version(dep, registry).is_some() {
+        return Some(dep.to_string());
+    }
+    let cutoff = timestamp::now().saturating_sub(days * timestamp::SECS_PER_DAY);
+    let release = registry.latest_published_before(dep, cutoff)?;
+    let newer = repo
+        .declared_version(dep)
+        .is_none_or(|declared| registry::is_outdated(&declared, &release.version));
+    newer.then(|| format!("{dep}@{}", release.version))
+}
+
+/// Manifest diffs `moon add <spec>` is expected to make, for dry runs
+///
+/// The target version comes from the spec or the latest registry release;
+/// nothing is shown when neither is known.
+fn preview_add(registry: &registry::Registry, repo: &RepoInfo, dep: &str, spec: &str) -> String {
+    let version = match spec.split_once('@') {
+        Some((_, version)) => Some(version.to_string()),
+        None => registry.latest(dep).map(|r| r.version),
+    };
+    let Some(version) = version else {
+        return String::new();
+    };
+    let mut out = String::new();
+    for moon_mod in &repo.moon_mods {
+        let declared = moon_mod
+            .deps
+            .iter()
+            .any(|d| d.name == dep && d.version.as_deref().is_some_and(|v| v != version));
+        if !declared {
+            continue;
+        }
+        let Ok(content) = std::fs::read_to_string(&moon_mod.path) else {
+            continue;
+        };
+        if let Some(new) = manifest::set_version(&content, dep, &version) {
+            let rel = moon_mod
+                .path
+                .strip_prefix(&repo.root)
+                .unwrap_or(&moon_mod.path);
+            out.push_str(&diff::preview(
+                &rel.display().to_string(),
+                Some(&content),
+                &new,
+            ));
+        }
+    }
+    out
+}
+
+/// Declared version of each dependency, by manifest and dependency name
+type DeclaredVersions = HashMap<(PathBuf, String), Option<String>>;
+
+/// Declared dependency versions across a repo's manifests, read again
+fn declared_versions(repo: &RepoInfo) -> DeclaredVersions {
+    repo.moon_mods
+        .iter()
+        .filter_map(|m| {
+            let content = repo.runner.read_file(&m.path).ok()?;
+            parse_moon_mod_content(&content, &m.path).ok()
+        })
+        .flat_map(|m| {
+            let path = m.path;
+            m.deps
+                .into_iter()
+                .map(move |dep| ((path.clone(), dep.name), dep.version))
+        })
+        .collect()
+}
+
+/// The version of `dep` in each manifest of `versions` declaring it
+fn versions_of<'a>(
+    versions: &'a DeclaredVersions,
+    dep: &str,
+) -> BTreeMap<&'a Path, &'a Option<String>> {
+    versions
+        .iter()
+        .filter(|((_, name), _)| name == dep)
+        .map(|((path, _), version)| (path.as_path(), version))
+        .collect()
+}
+
+/// A `--results-out` line: a final repo result with the estimated seconds
+/// left in the run
+#[derive(Serialize)]
+struct ResultEvent<'a> {
+    run_id: &'a str,
+    #[serde(flatten)]
+    result: &'a RepoResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eta_secs: Option<u64>,
+}
+
+/// Folds task outcomes into one result per repo as tasks end
+///
+/// A repo's result is final once all its tasks ended: package versions are
+/// then re-read from the manifests to tell updated packages from unchanged
+/// ones. Final results update the live summary line (on a terminal) and are
+/// appended to the `--results-out` NDJSON file and the checkpoint right
+/// away, so an interrupted run keeps what it finished. Results of repos done
+/// before a resume are taken as they are. Repos whose steps were all
+/// cancelled are left out; repos cancelled midway keep the results of the
+/// steps they finished.
+struct ResultCollector<'a> {
+    repos: &'a [RepoInfo],
+    steps: Vec<&'a ApplyTask<'a>>,
+    results: Vec<RepoResult>,
+    tasks: Vec<usize>,
+    ended: Vec<usize>,
+    cancelled: Vec<usize>,
+    windows: Vec<Option<(Instant, Instant)>>,
+    /// Repos with a final result, and how many of those failed
+    completed: usize,
+    failed: usize,
+    ndjson: Option<(PathBuf, std::fs::File)>,
+    observer: Arc<dyn observer::Observer>,
+    /// Time left for `--results-out` events; `None` under `--deterministic`
+    estimate: Option<Arc<eta::Estimate>>,
+    checkpoint: Option<checkpoint::Writer>,
+    cancel: cancel::CancellationToken,
+}
+
+impl<'a> ResultCollector<'a> {
+    fn new(
+        repos: &'a [RepoInfo],
+        graph: &'a scheduler::Graph<ApplyTask<'a>>,
+        ndjson: Option<(&Path, Option<Arc<eta::Estimate>>)>,
+        observer: Arc<dyn observer::Observer>,
+        checkpoint: Option<checkpoint::Writer>,
+        mut resumed: HashMap<PathBuf, RepoResult>,
+        cancel: cancel::CancellationToken,
+    ) -> Result<Self> {
+        let estimate = ndjson.as_ref().and_then(|(_, estimate)| estimate.clone());
+        let ndjson = ndjson
+            .map(|(path, _)| {
+                std::fs::File::create(path)
+                    .map(|file| (path.to_path_buf(), file))
+                    .with_context(|| format!("Failed to create {}", path.display()))
+            })
+            .transpose()?;
+        let mut collector = ResultCollector {
+            repos,
+            steps: graph.tasks().collect(),
+            results: repos
+                .iter()
+                .map(|repo| RepoResult {
+                    repo_root: repo.location_path(),
+                    owners: repo.owners.clone(),
+                    success: true,
+                    packages: Vec::new(),
+                    errors: Vec::new(),
+                    checks: Vec::new(),
+                    duration: Duration::ZERO,
+                })
+                .collect(),
+            tasks: vec![0; repos.len()],
+            ended: vec![0; repos.len()],
+            cancelled: vec![0; repos.len()],
+            windows: vec![None; repos.len()],
+            completed: 0,
+            failed: 0,
+            ndjson,
+            observer,
+            estimate,
+            checkpoint,
+            cancel,
+        };
+
+        // Records in graph order, whatever order the tasks end in
+        for task in &collector.steps {
+            let index = task.repo;
+            collector.tasks[index] += 1;
+            let package = match &task.step {
+                ApplyStep::Add(dep) => Some((dep.to_string(), repos[index].declared_version(dep))),
+                ApplyStep::GitRev(_, dep) => match &dep.source {
+                    DepSource::Git { rev, .. } => Some((dep.name.clone(), rev.clone())),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some((name, old_version)) = package {
+                collector.results[index].packages.push(PackageRecord {
+                    name,
+                    status: PackageStatus::Skipped,
+                    old_version,
+                    new_version: None,
+                    duration: Duration::ZERO,
+                    attempts: 0,
+                    error: None,
+                });
+            }
+        }
+        for (index, repo) in repos.iter().enumerate() {
+            if let Some(result) = resumed.remove(&repo.location_path()) {
+                collector.results[index] = result;
+                collector.report(index, false);
+            } else if collector.tasks[index] == 0 {
+                collector.complete(index);
+            }
+        }
+        Ok(collector)
+    }
+
+    /// Fold in the outcome of task `task`
+    fn record(&mut self, task: usize, done: scheduler::Finished<StepReport>) {
+        let step = self.steps[task];
+        let index = step.repo;
+        self.ended[index] += 1;
+        self.fold(step, index, done);
+        if self.ended[index] == self.tasks[index] {
+            self.complete(index);
+        }
+    }
+
+    fn fold(&mut self, task: &ApplyTask, index: usize, done: scheduler::Finished<StepReport>) {
+        let result = &mut self.results[index];
+        if let (Some(started), Some(ended)) = (done.started, done.finished) {
+            let window = self.windows[index].get_or_insert((started, ended));
+            window.0 = window.0.min(started);
+            window.1 = window.1.max(ended);
+        }
+
+        let (succeeded, report) = match done.outcome {
+            scheduler::Outcome::Succeeded(report) => (true, report),
+            scheduler::Outcome::Failed(report) => (false, report),
+            scheduler::Outcome::Skipped => return,
+            scheduler::Outcome::Cancelled => {
+                self.cancelled[index] += 1;
+                return;
+            }
+        };
+        match (&task.step, report) {
+            (ApplyStep::Resolve { .. }, StepReport::Error(e)) => {
+                result.errors.push(format!("moon update failed: {e}"));
+            }
+            (ApplyStep::GitRev(..), StepReport::Revision(attempt, rev)) => {
+                if let Some(record) = result.packages.iter_mut().find(|p| p.name == attempt.dep) {
+                    record.attempts += 1;
+                    record.duration += attempt.duration;
+                    record.new_version = Some(rev);
+                }
+            }
+            (
+                ApplyStep::Add(_) | ApplyStep::Repeat(_) | ApplyStep::GitRev(..),
+                StepReport::Added(attempts),
+            ) => {
+                for attempt in attempts {
+                    let Some(record) = result.packages.iter_mut().find(|p| p.name == attempt.dep)
+                    else {
+                        continue;
+                    };
+                    record.attempts += 1;
+                    record.duration += attempt.duration;
+                    if attempt.error.is_some() {
+                        record.error = attempt.error;
+                    }
+                }
+            }
+            (ApplyStep::Check(_), StepReport::Check(check)) => {
+                if !check.success() {
+                    result.errors.push(format!(
+                        "moon check failed in {}: {}",
+                        check.module_label(),
+                        check.error.as_deref().unwrap_or_default()
+                    ));
+                }
+                result.checks.push(check);
+            }
+            (ApplyStep::Justfile(_), StepReport::Error(e)) => {
+                result.errors.push(format!("justfile handling failed: {e}"));
+            }
+            _ => {}
+        }
+        if !succeeded {
+            result.success = false;
+        }
+    }
+
+    /// Finalize the result of repo `index` once all its tasks ended
+    fn complete(&mut self, index: usize) {
+        let reported = self.reported(index);
+        let result = &mut self.results[index];
+        if self.cancelled[index] > 0 {
+            result.success = false;
+            result.errors.push(format!(
+                "{} steps cancelled by {}",
+                self.cancelled[index],
+                self.cancel.reason().unwrap_or("request")
+            ));
+        }
+        if let Some((started, ended)) = self.windows[index] {
+            result.duration = ended - started;
+        }
+
+        let ran = result.packages.iter().any(|p| p.attempts > 0);
+        let current = if ran {
+            declared_versions(&self.repos[index])
+        } else {
+            HashMap::new()
+        };
+        for record in &mut result.packages {
+            // Git revisions are reported by their step; registry versions
+            // come from the manifests
+            if record.new_version.is_none() {
+                // From the first manifest declaring it, as the old version
+                record.new_version = self.repos[index]
+                    .moon_mods
+                    .iter()
+                    .find_map(|m| current.get(&(m.path.clone(), record.name.clone())))
+                    .cloned()
+                    .flatten();
+            }
+            record.status = if record.error.is_some() {
+                PackageStatus::Failed
+            } else if record.attempts == 0 {
+                PackageStatus::Skipped
+            } else if record.new_version != record.old_version {
+                PackageStatus::Updated
+            } else {
+                PackageStatus::Unchanged
+            };
+            self.observer.on_package_result(&result.repo_root, record);
+        }
+        if reported {
+            self.report(index, true);
+        }
+    }
+
+    /// Count a final result and write it out
+    fn report(&mut self, index: usize, checkpoint: bool) {
+        let result = &self.results[index];
+        self.completed += 1;
+        if !result.success {
+            self.failed += 1;
+        }
+        if let Some(writer) = self.checkpoint.as_mut().filter(|_| checkpoint) {
+            if let Err(e) = writer.append(result) {
+                diagnostics::report(diagnostics::Code::RunState, format!("{e:#}"));
+                self.checkpoint = None;
+            }
+        }
+        // Observers first, so the estimate counts this result
+        self.observer.on_repo_finish(result);
+        if let Some((path, file)) = &mut self.ndjson {
+            let event = ResultEvent {
+                run_id: run_id::current(),
+                result,
+                eta_secs: self
+                    .estimate
+                    .as_ref()
+                    .and_then(|estimate| estimate.remaining())
+                    .map(|left| left.as_secs()),
+            };
+            let written = serde_json::to_string(&event)
+                .map_err(anyhow::Error::from)
+                .and_then(|line| Ok(writeln!(file, "{line}")?));
+            if let Err(e) = written {
+                diagnostics::report(
+                    diagnostics::Code::FileNotUpdated,
+                    format!("Failed to write {}: {e:#}", path.display()),
+                );
+                self.ndjson = None;
+            }
+        }
+    }
+
+    /// Whether repo `index` has a result (not all its steps were cancelled)
+    fn reported(&self, index: usize) -> bool {
+        self.tasks[index] == 0 || self.cancelled[index] < self.tasks[index]
+    }
+
+    /// Final results in repo order; the run is complete, so its checkpoint
+    /// is removed
+    fn finish(mut self) -> Vec<RepoResult> {
+        if let Some(writer) = self.checkpoint.take() {
+            if let Err(e) = writer.remove() {
+                diagnostics::report(diagnostics::Code::RunState, format!("{e:#}"));
+            }
+        }
+        let reported: Vec<bool> = (0..self.repos.len()).map(|i| self.reported(i)).collect();
+        self.results
+            .into_iter()
+            .zip(reported)
+            .filter_map(|(result, reported)| reported.then_some(result))
+            .collect()
+    }
+}
+
+/// Configure the global rayon pool; later calls are ignored
+fn init_thread_pool(jobs: usize) {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build_global()
+        .ok(); // Ignore if already initialized
+}
+
+fn run_moon_command(
+    runner: &dyn runner::Runner,
+    toolchain: &toolchain::Toolchain,
+    args: &[&str],
+    cwd: &Path,
+) -> Result<String> {
+    for capability in capabilities::for_moon(args[0]) {
+        capabilities::require(*capability, &format!("moon {}", args[0]))?;
+    }
+    let program = toolchain.program(runner.is_local());
+    let output = runner
+        .output(&runner::Invocation {
+            program: program.as_os_str(),
+            args,
+            env: &toolchain.env(),
+            cwd,
+        })
+        .with_context(|| format!("Failed to execute moon {}", args.join(" ")))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let code = output.status.code().unwrap_or(-1);
+        bail!("exit code {code}: {stderr}")
+    }
+}
+
+// =============================================================================
+// Test Command
+// =============================================================================
+
+fn cmd_test(common: CommonOptions, junit_path: Option<PathBuf>, args: Vec<String>) -> Result<bool> {
+    let mut config = Config::resolve(&common)?;
+    // Tests need the sources, not just manifests
+    config.clone_full.cli(Some(true));
+    limits::init(config.limits()?);
+    let mut repos = discover_fleet(common.root(), &config)?;
+
+    if repos.is_empty() {
+        println!("No moon.mod.json files found.");
+        return Ok(true);
+    }
+
+    select_toolchains(&mut repos, false);
+
+    init_thread_pool(config.effective_jobs());
+
+    let moon_args: Vec<&str> = std::iter::once("test")
+        .chain(args.iter().map(String::as_str))
+        .collect();
+    let dry_run = common.dry_run;
+    let log = observer::CommandLog {
+        enabled: config.verbose.value || dry_run,
+    };
+
+    let mut checks: Vec<verify::ModuleCheck> = repos
+        .par_iter()
+        .flat_map(|repo| verify::run_in_modules(repo, &moon_args, dry_run, &log))
+        .collect();
+    checks.sort_by(|a, b| a.module_dir.cmp(&b.module_dir));
+
+    println!("\n=== Results ===\n");
+    for check in &checks {
+        let status = if check.success() { "PASS" } else { "FAIL" };
+        println!(
+            "[{status}] {} ({:.1}s)",
+            check.module_dir.display(),
+            check.duration.as_secs_f64()
+        );
+        if let Some(err) = &check.error {
+            println!("  Error: {err}");
+        }
+    }
+
+    let passed = checks.iter().filter(|c| c.success()).count();
+    println!("\nSummary: {}/{} modules passed", passed, checks.len());
+
+    if let Some(path) = &junit_path {
+        let cases: Vec<junit::TestCase> = checks.iter().map(|c| c.test_case()).collect();
+        junit::write(path, "moon-dst test", &cases)?;
+    }
+
+    Ok(passed == checks.len())
+}
+
+fn cmd_try_version(
+    common: CommonOptions,
+    package: &str,
+    version: &str,
+    test: bool,
+    format: OutputFormat,
+) -> Result<bool> {
+    format.check_supported("try-version", &[OutputFormat::Text, OutputFormat::Json])?;
+    let config = Config::resolve(&common)?;
+    limits::init(config.limits()?);
+    let stale = sandbox::remove_stale();
+    if stale > 0 && config.verbose.value {
+        println!("Removed {stale} sandboxes left by earlier runs");
+    }
+    let mut repos = discover_repos(common.root(), &config)?;
+    repos.retain(|repo| try_version::depends_on(repo, package));
+    if repos.is_empty() {
+        println!("No repos depend on {package}.");
+        return Ok(true);
+    }
+    select_toolchains(&mut repos, false);
+    init_thread_pool(config.effective_jobs());
+
+    let verbose = config.verbose.value;
+    if common.dry_run {
+        for repo in &repos {
+            println!(
+                "[DRY-RUN] {}: would try {package}@{version}",
+                ospath::display(&repo.root)
+            );
+        }
+        return Ok(true);
+    }
+    let mut results: Vec<try_version::RepoResult> = repos
+        .par_iter()
+        .map(|repo| try_version::try_repo(repo, package, version, test, verbose))
+        .collect();
+    results.sort_by(|a, b| a.repo.cmp(&b.repo));
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let broken = count(try_version::Status::Broken);
+    let errors = count(try_version::Status::Error);
+
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&results)?);
+        return Ok(broken + errors == 0);
+    }
+    for result in &results {
+        let status = match result.status {
+            try_version::Status::Passed => output::status(true),
+            try_version::Status::Broken => "BROKEN",
+            try_version::Status::Error => "ERROR",
+        };
+        println!(
+            "[{status}] {} ({} -> {version})",
+            result.repo,
+            result.declared.join(", ")
+        );
+        if let Some(failed) = &result.failed {
+            println!("  Failed: {failed}");
+        }
+        if let Some(error) = &result.error {
+            println!("  Error: {}", error.trim());
+        }
+    }
+    print!(
+        "\nSummary: {broken} of {} dependents would break with {package}@{version}",
+        results.len()
+    );
+    if errors > 0 {
+        print!(" ({errors} could not be checked)");
+    }
+    println!();
+    Ok(broken + errors == 0)
+}
+
+// =============================================================================
+// Just Command
+// =============================================================================
+
+fn cmd_just(
+    common: CommonOptions,
+    mode: Option<WriteMode>,
+    task_runner: Option<taskfile::TaskRunner>,
+    recipes: Vec<String>,
+    extra_recipes: Vec<String>,
+    set: Vec<(String, String)>,
+    no_root_file: bool,
+) -> Result<bool> {
+    let mut config = Config::resolve(&common)?;
+    config.just_mode.cli(mode);
+    config.just_root_file.cli(no_root_file.then_some(false));
+    config.just_task_runner.cli(task_runner);
+    config
+        .just_recipes
+        .cli((!recipes.is_empty()).then_some(recipes));
+    config
+        .just_extra_recipes
+        .cli((!extra_recipes.is_empty()).then_some(extra_recipes));
+    let mode = config.just_mode.value;
+    let runner = config.just_task_runner.value;
+    let root_file = config.just_root_file.value;
+    let template = justfile_template(&config, set.into_iter().collect())?;
+    let root = common
+        .root()
+        .canonicalize()
+        .unwrap_or_else(|_| common.root().to_path_buf());
+    let repos = discover_repos(common.root(), &config)?;
+    write_to_repos(&common, &config, &repos, |repo, dry_run, verbose| {
+        let rel = repo.root.strip_prefix(&root).unwrap_or(&repo.root);
+        let vars = template.variables(rel);
+        let dirs = module_dirs(repo);
+        if dirs.len() < 2 {
+            return handle_justfile(
+                &repo.root,
+                runner,
+                &template.text,
+                &vars,
+                mode,
+                dry_run,
+                verbose,
+            );
+        }
+        // Several modules: one file each, and a root file running them all
+        let mut written = false;
+        for dir in dirs.iter().filter(|dir| *dir != ".") {
+            written |= handle_justfile(
+                &repo.root.join(dir),
+                runner,
+                &template.text,
+                &vars,
+                mode,
+                dry_run,
+                verbose,
+            )?;
+        }
+        if root_file || dirs.iter().any(|dir| dir == ".") {
+            let text = match root_file {
+                true => taskfile::root_template(runner, &template.text, &dirs),
+                false => template.text.clone(),
+            };
+            written |= handle_justfile(&repo.root, runner, &text, &vars, mode, dry_run, verbose)?;
+        }
+        Ok(written)
+    })
+}
+
+/// Directories of the repo's modules below its root (`.` for the root),
+/// with `/` separators, sorted
+fn module_dirs(repo: &RepoInfo) -> Vec<String> {
+    let mut dirs: Vec<String> = repo
+        .moon_mods
+        .iter()
+        .filter_map(|m| m.path.parent()?.strip_prefix(&repo.root).ok())
+        .map(|dir| match dir.as_os_str().is_empty() {
+            true => ".".to_string(),
+            false => dir.to_string_lossy().replace('\\', "/"),
+        })
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// The justfile template narrowed to `[just] recipes`, plus `extra-recipes`,
+/// with the variables of `[just] set`, `[[just.repos]]` and `overrides`
+fn justfile_template(
+    config: &Config,
+    overrides: BTreeMap<String, String>,
+) -> Result<justfile::Template> {
+    let extra = config
+        .just_extra_recipes
+        .value
+        .iter()
+        .map(|spec| justfile::parse_extra_recipe(spec))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(justfile::Template {
+        text: justfile::customize(JUSTFILE_TEMPLATE, &config.just_recipes.value, &extra)?,
+        set: config.just_set.value.clone(),
+        repos: config.just_repos.value.clone(),
+        overrides,
+    })
+}
+
+fn cmd_gitignore(common: CommonOptions, mode: Option<WriteMode>) -> Result<bool> {
+    let mut config = Config::resolve(&common)?;
+    config.gitignore_mode.cli(mode);
+    let mode = config.gitignore_mode.value;
+    let repos = discover_repos(common.root(), &config)?;
+    write_to_repos(&common, &config, &repos, |repo, dry_run, verbose| {
+        handle_gitignore(&repo.root, mode, dry_run, verbose)
+    })
+}
+
+fn cmd_grep_usage(
+    common: CommonOptions,
+    package: &str,
+    symbol: Option<&str>,
+    format: OutputFormat,
+) -> Result<bool> {
+    format.check_supported("grep-usage", &[OutputFormat::Text, OutputFormat::Json])?;
+    let config = Config::resolve(&common)?;
+    let repos = discover_fleet(common.root(), &config)?;
+    let modules = project_index::modules(&repos);
+    let usages = usage::scan(&modules, package, symbol)?;
+
+    if format == OutputFormat::Json {
+        println!("{}", output::json(&usages)?);
+        return Ok(true);
+    }
+    let target = match symbol {
+        Some(symbol) => format!("{package} ({symbol})"),
+        None => package.to_string(),
+    };
+    if usages.is_empty() {
+        println!("No usages of {target} found.");
+        return Ok(true);
+    }
+    println!("Usages of {target}:");
+    println!("{:>6}  {:>6}  Repository", "lines", "files");
+    for repo in &usages {
+        println!("{:>6}  {:>6}  {}", repo.lines, repo.files, repo.repo);
+        if config.verbose.value {
+            for m in &repo.matches {
+                println!("          {}:{}: {}", m.file.display(), m.line, m.text);
+            }
+        }
+    }
+    println!(
+        "{:>6}  {:>6}  Total ({} repos)",
+        usages.iter().map(|u| u.lines).sum::<usize>(),
+        usages.iter().map(|u| u.files).sum::<usize>(),
+        usages.len()
+    );
+    Ok(true)
+}
+
+fn cmd_rename(
+    common: CommonOptions,
+    old: &str,
+    new: &str,
+    version: Option<&str>,
+    sources: bool,
+) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos = discover_repos(common.root(), &config)?;
+    let modules = project_index::modules(&repos);
+    let edits = rename::plan(&modules, old, new, version, sources)?;
+    if edits.is_empty() {
+        println!("No references to {old} found.");
+        return Ok(true);
+    }
+
+    let root = common.root().canonicalize()?;
+    let failed = write_edits(&edits, &root, common.dry_run);
+    let verb = if common.dry_run {
+        "would change"
+    } else {
+        "changed"
+    };
+    println!(
+        "\nSummary: {verb} {} files ({old} -> {new})",
+        edits.len() - failed
+    );
+    Ok(failed == 0)
+}
+
+/// Write `edits`, or with `dry_run` print their diffs, paths shown below
+/// `root`; returns how many failed
+fn write_edits(edits: &[rename::Edit], root: &Path, dry_run: bool) -> usize {
+    let mut failed = 0;
+    for edit in edits {
+        let shown = edit.path.strip_prefix(root).unwrap_or(&edit.path);
+        if dry_run {
+            print!(
+                "{}",
+                diff::preview(&shown.display().to_string(), Some(&edit.old), &edit.new)
+            );
+            continue;
+        }
+        match capabilities::write(&edit.path, &edit.new) {
+            Ok(()) => println!("[{}] {}", output::status(true), shown.display()),
+            Err(e) => {
+                eprintln!("[{}] {}: {e:#}", output::status(false), shown.display());
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+fn cmd_renovate_config(
+    common: CommonOptions,
+    mode: Option<WriteMode>,
+    datasource_url: Option<String>,
+) -> Result<bool> {
+    let mut config = Config::resolve(&common)?;
+    config.renovate_mode.cli(mode);
+    config.renovate_datasource_url.cli(datasource_url.map(Some));
+    let mode = config.renovate_mode.value;
+    if config.renovate_datasource_url.value.is_none() {
+        diagnostics::report(
+            diagnostics::Code::Unsupported,
+            "no [renovate] datasource-url; Renovate cannot look up mooncakes versions without one",
+        );
+    }
+    let policy = renovate::Policy {
+        datasource_url: config.renovate_datasource_url.value.clone(),
+        min_age: config.min_age.value,
+        packages: config.packages.value.clone(),
+        pins: config.pins.value.clone(),
+    };
+    let root = common
+        .root()
+        .canonicalize()
+        .unwrap_or_else(|_| common.root().to_path_buf());
+    let now = timestamp::now();
+    let repos = discover_repos(common.root(), &config)?;
+    write_to_repos(&common, &config, &repos, |repo, dry_run, verbose| {
+        let rel = repo.root.strip_prefix(&root).unwrap_or(&repo.root);
+        let policy = policy.clone().for_repo(rel, now);
+        write_managed_file(
+            &repo.root,
+            renovate::FILE,
+            &renovate::update(None, &policy)?,
+            |content| {
+                renovate::update(Some(content), &policy).unwrap_or_else(|e| {
+                    diagnostics::report(
+                        diagnostics::Code::FileNotUpdated,
+                        format!("{}: {e:#}", ospath::display(&repo.root)),
+                    );
+                    content.to_string()
+                })
+            },
+            mode,
+            dry_run,
+            verbose,
+        )
+    })
+}
+
+fn cmd_vscode(
+    common: CommonOptions,
+    mode: WriteMode,
+    workspace: PathBuf,
+    no_workspace: bool,
+) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos = discover_repos(common.root(), &config)?;
+    let ok = write_to_repos(&common, &config, &repos, |repo, dry_run, verbose| {
+        handle_vscode_tasks(repo, mode, dry_run, verbose)
+    })?;
+    if no_workspace || repos.is_empty() {
+        return Ok(ok);
+    }
+
+    let workspace = common.root().join(workspace);
+    let file_name = workspace
+        .file_name()
+        .context("Invalid workspace file path")?
+        .to_string_lossy()
+        .into_owned();
+    let dir = workspace.parent().unwrap_or(common.root());
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let mut folders: Vec<vscode::Folder> = repos
+        .iter()
+        .flat_map(|repo| &repo.moon_mods)
+        .map(|moon_mod| {
+            let module_dir = moon_mod.path.parent().unwrap_or(&moon_mod.path);
+            let path = match module_dir.strip_prefix(&dir) {
+                Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+                Ok(rel) => rel.display().to_string(),
+                Err(_) => module_dir.display().to_string(),
+            };
+            vscode::Folder {
+                name: moon_mod.name.clone().unwrap_or_else(|| path.clone()),
+                path,
+            }
+        })
+        .collect();
+    folders.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let verbose = config.verbose.value;
+    let written = write_managed_file(
+        &dir,
+        &file_name,
+        &vscode::workspace(None, &folders)?,
+        |content| {
+            vscode::workspace(Some(content), &folders).unwrap_or_else(|e| {
+                diagnostics::report(diagnostics::Code::FileNotUpdated, format!("{e:#}"));
+                content.to_string()
+            })
+        },
+        WriteMode::Merge,
+        common.dry_run,
+        verbose,
+    )?;
+    if written && !common.dry_run {
+        println!(
+            "Wrote {} ({} folders)",
+            dir.join(&file_name).display(),
+            folders.len()
+        );
+    }
+    Ok(ok)
+}
+
+/// Create or merge .vscode/tasks.json with moon tasks for each module
+fn handle_vscode_tasks(
+    repo: &RepoInfo,
+    mode: WriteMode,
+    dry_run: bool,
+    verbose: bool,
+) -> Result<bool> {
+    let modules: Vec<String> = repo
+        .moon_mods
+        .iter()
+        .map(|m| verify::module_label(&repo.root, m.path.parent().unwrap_or(&repo.root)))
+        .collect();
+    let generated = vscode::tasks(&modules);
+    write_managed_file(
+        &repo.root,
+        vscode::TASKS_FILE,
+        &generated,
+        |content| {
+            vscode::merge_tasks(content, &generated).unwrap_or_else(|e| {
+                diagnostics::report(
+                    diagnostics::Code::FileNotUpdated,
+                    format!("{}: {e:#}", ospath::display(&repo.root)),
+                );
+                content.to_string()
+            })
+        },
+        mode,
+        dry_run,
+        verbose,
+    )
+}
+
+/// Run a per-repo file writer over `repos` and print a summary
+fn write_to_repos(
+    common: &CommonOptions,
+    config: &Config,
+    repos: &[RepoInfo],
+    handle: impl Fn(&RepoInfo, bool, bool) -> Result<bool>,
+) -> Result<bool> {
+    if repos.is_empty() {
+        println!("No moon.mod.json files found.");
+        return Ok(true);
+    }
+
+    let dry_run = common.dry_run;
+    let verbose = config.verbose.value;
+    let mut success_count = 0;
+    let mut skip_count = 0;
+
+    for repo in repos {
+        match handle(repo, dry_run, verbose) {
+            Ok(created) => {
+                if created {
+                    success_count += 1;
+                } else {
+                    skip_count += 1;
+                }
+            }
+            Err(e) => {
+                eprintln!("[{}] Error: {e}", ospath::display(&repo.root));
+            }
+        }
+    }
+
+    println!("\nSummary: {success_count} created or updated, {skip_count} skipped");
+    Ok(true)
+}
+
+/// Write the justfile template, or its equivalent for `runner`
+fn handle_justfile(
+    repo_root: &Path,
+    runner: taskfile::TaskRunner,
+    template: &str,
+    vars: &BTreeMap<String, String>,
+    mode: WriteMode,
+    dry_run: bool,
+    verbose: bool,
+) -> Result<bool> {
+    let generated = taskfile::render(runner, &justfile::set_variables(template, vars));
+    write_managed_file(
+        repo_root,
+        runner.file_name(),
+        &generated,
+        |content| {
+            let merged = taskfile::merge(runner, content, &generated);
+            taskfile::set_variables(runner, &merged, vars)
+        },
+        mode,
+        dry_run,
+        verbose,
+    )
+}
+
+fn handle_gitignore(
+    repo_root: &Path,
+    mode: WriteMode,
+    dry_run: bool,
+    verbose: bool,
+) -> Result<bool> {
+    write_managed_file(
+        repo_root,
+        ".gitignore",
+        &gitignore::template(),
+        gitignore::merge,
+        mode,
+        dry_run,
+        verbose,
+    )
+}
+
+/// Create or merge a repo file according to `mode`; returns whether it was written
+fn write_managed_file(
+    repo_root: &Path,
+    file_name: &str,
+    template: &str,
+    merge: impl Fn(&str) -> String,
+    mode: WriteMode,
+    dry_run: bool,
+    verbose: bool,
+) -> Result<bool> {
+    let path = repo_root.join(file_name);
+    let exists = path.exists();
+
+    match mode {
+        WriteMode::Skip => {
+            if verbose {
+                println!(
+                    "[{}] Skipping {file_name} (skip mode)",
+                    ospath::display(repo_root)
+                );
+            }
+            Ok(false)
+        }
+        WriteMode::Create | WriteMode::Merge if !exists => {
+            if verbose || dry_run {
+                println!("[{}] Creating {file_name}", ospath::display(repo_root));
+            }
+            if dry_run {
+                print!("{}", diff::preview(file_name, None, template));
+            } else {
+                capabilities::write(&path, template)?;
+            }
+            Ok(true)
+        }
+        WriteMode::Create => {
+            if verbose {
+                println!(
+                    "[{}] {file_name} already exists, skipping",
+                    ospath::display(repo_root)
+                );
+            }
+            Ok(false)
+        }
+        WriteMode::Merge => {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let merged = merge(&content);
+            if merged == content {
+                if verbose {
+                    println!(
+                        "[{}] {file_name} up to date, skipping",
+                        ospath::display(repo_root)
+                    );
+                }
+                return Ok(false);
+            }
+            if verbose || dry_run {
+                println!("[{}] Merging {file_name}", ospath::display(repo_root));
+            }
+            if dry_run {
+                print!("{}", diff::preview(file_name, Some(&content), &merged));
+            } else {
+                capabilities::write(&path, merged)?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+// =============================================================================
+// SPDX Command
+// =============================================================================
+
+fn cmd_spdx(
+    common: CommonOptions,
+    license: Option<String>,
+    holder: Option<String>,
+    excludes: Vec<String>,
+    fix: bool,
+) -> Result<bool> {
+    let mut config = Config::resolve(&common)?;
+    config.spdx_license.cli(license.map(Some));
+    config.spdx_holder.cli(holder.map(Some));
+    config
+        .spdx_exclude
+        .cli((!excludes.is_empty()).then_some(excludes));
+
+    let Some(license) = config.spdx_license.value.clone() else {
+        bail!(errors::Coded::new(
+            errors::Code::InvalidValue,
+            "No license given; pass --license or set `license` in the [spdx] config section"
+        ));
+    };
+    let header = spdx::Header {
+        license,
+        holder: config.spdx_holder.value.clone(),
+    };
+    let excludes = spdx::exclude_set(&config.spdx_exclude.value)?;
+    let ignores = ignore_list(&config);
+
+    let repos = discover_repos(common.root(), &config)?;
+    if repos.is_empty() {
+        println!("No moon.mod.json files found.");
+        return Ok(true);
+    }
+
+    let mut checked = 0;
+    let mut issues = 0;
+    let mut fixed = 0;
+
+    for repo in &repos {
+        for path in spdx::source_files(&repo.root, &ignores, &excludes)? {
+            checked += 1;
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let Some(issue) = header.check(&content) else {
+                continue;
+            };
+            issues += 1;
+            let rel = path.strip_prefix(&repo.root).unwrap_or(&path);
+            println!(
+                "[{}] {}: {issue}",
+                ospath::display(&repo.root),
+                rel.display()
+            );
+
+            if fix {
+                if common.dry_run {
+                    println!("  Would fix {}", rel.display());
+                    let rel = rel.display().to_string();
+                    print!(
+                        "{}",
+                        diff::preview(&rel, Some(&content), &header.apply(&content))
+                    );
+                } else {
+                    capabilities::write(&path, header.apply(&content))?;
+                }
+                fixed += 1;
+            }
+        }
+    }
+
+    if fix {
+        println!("\nSummary: {checked} files checked, {fixed} fixed");
+        Ok(true)
+    } else {
+        println!("\nSummary: {checked} files checked, {issues} with header issues");
+        Ok(issues == 0)
+    }
+}
+
+// =============================================================================
+// Drift Command
+// =============================================================================
+
+/// Drift state of one template file in a repo
+enum FileDrift {
+    UpToDate,
+    Missing,
+    Drifted { current: String, details: String },
+}
+
+fn cmd_drift(
+    common: CommonOptions,
+    pack_name: Option<String>,
+    fix: bool,
+    fix_mode: FixMode,
+) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos = discover_repos(common.root(), &config)?;
+
+    let mut packs: HashMap<String, templates::Pack> = HashMap::new();
+    let mut up_to_date = 0;
+    let mut drifted = 0;
+    let mut missing = 0;
+    let mut fixed = 0;
+
+    for repo in &repos {
+        let record = templates::read_record(&repo.root)?;
+        let name = pack_name
+            .clone()
+            .or_else(|| record.as_ref().map(|r| r.pack.clone()))
+            .unwrap_or_else(|| templates::DEFAULT_PACK.to_string());
+        if !packs.contains_key(&name) {
+            packs.insert(name.clone(), templates::load(&name)?);
+        }
+        let pack = &packs[&name];
+        let mut repo_changed = false;
+
+        if let Some(record) = record.as_ref().filter(|r| r.pack == pack.name) {
+            if record.version != pack.version {
+                println!(
+                    "[DRIFT] {}: template pack {} {} -> {}",
+                    ospath::display(&repo.root),
+                    pack.name,
+                    record.version,
+                    pack.version
+                );
+                drifted += 1;
+                repo_changed = true;
+            }
+        }
+
+        for file in &pack.files {
+            let path = repo.root.join(&file.path);
+            let (current, new) = match file_drift(&path, file)? {
+                FileDrift::UpToDate => {
+                    up_to_date += 1;
+                    if config.verbose.value {
+                        println!(
+                            "[{}] {}: {}",
+                            output::status(true),
+                            ospath::display(&repo.root),
+                            file.path
+                        );
+                    }
+                    continue;
+                }
+                FileDrift::Missing => {
+                    missing += 1;
+                    println!("[MISSING] {}: {}", ospath::display(&repo.root), file.path);
+                    (None, file.content.clone())
+                }
+                FileDrift::Drifted { current, details } => {
+                    drifted += 1;
+                    println!(
+                        "[DRIFT] {}: {}{details}",
+                        ospath::display(&repo.root),
+                        file.path
+                    );
+
+                    let new = match fix_mode {
+                        FixMode::Merge if file.path == "justfile" => {
+                            justfile::merge(&current, &file.content)
+                        }
+                        FixMode::Merge => current.clone(),
+                        FixMode::Regenerate => file.content.clone(),
+                    };
+                    let shown = if new == current { &file.content } else { &new };
+                    print!("{}", diff::preview(&file.path, Some(&current), shown));
+                    if new == current {
+                        println!("  (merge keeps changed content; use --fix-mode regenerate to overwrite)");
+                        continue;
+                    }
+                    (Some(current), new)
+                }
+            };
+
+            if fix && current.as_deref() != Some(new.as_str()) {
+                if !common.dry_run {
+                    capabilities::write(&path, &new)?;
+                }
+                fixed += 1;
+                repo_changed = true;
+            }
+        }
+
+        // Keep the record in sync for stamped repos (or an explicitly chosen pack)
+        if fix && repo_changed && (record.is_some() || pack_name.is_some()) {
+            if !common.dry_run {
+                templates::write_record(&repo.root, pack)?;
+            }
+            if record.as_ref().is_some_and(|r| r.version != pack.version) {
+                fixed += 1;
+            }
+        }
+    }
+
+    println!(
+        "\nSummary: {up_to_date} up to date, {drifted} drifted, {missing} missing{}",
+        if fix {
+            format!(", {fixed} fixed")
+        } else {
+            String::new()
+        }
+    );
+
+    Ok(drifted + missing == 0 || (fix && fixed == drifted + missing))
+}
+
+fn file_drift(path: &Path, file: &templates::PackFile) -> Result<FileDrift> {
+    if !path.exists() {
+        return Ok(FileDrift::Missing);
+    }
+    let current = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    if file.path == "justfile" {
+        let drift = justfile::drift(&current, &file.content);
+        if drift.is_empty() {
+            return Ok(FileDrift::UpToDate);
+        }
+        let mut details = Vec::new();
+        if !drift.missing.is_empty() {
+            details.push(format!("missing: {}", drift.missing.join(", ")));
+        }
+        if !drift.changed.is_empty() {
+            details.push(format!("changed: {}", drift.changed.join(", ")));
+        }
+        return Ok(FileDrift::Drifted {
+            current,
+            details: format!(" ({})", details.join("; ")),
+        });
+    }
+
+    if current == file.content {
+        Ok(FileDrift::UpToDate)
+    } else {
+        Ok(FileDrift::Drifted {
+            current,
+            details: String::new(),
+        })
+    }
+}
 
 // =============================================================================
-// Apply Command
+// Apply Templates Command
 // =============================================================================
 
-fn cmd_apply(
+fn cmd_apply_templates(
     common: CommonOptions,
-    skip_update: bool,
-    repeat: u32,
-    packages: Vec<String>,
-    fail_fast: bool,
-    write_justfile: bool,
-    justfile_mode: JustfileMode,
+    pack_name: String,
+    mode: TemplateMode,
 ) -> Result<bool> {
-    let repos = discover_repos(&common)?;
-
-    if repos.is_empty() {
-        println!("No moon.mod.json files found.");
-        return Ok(true);
-    }
+    let config = Config::resolve(&common)?;
+    let repos = discover_repos(common.root(), &config)?;
+    let pack = templates::load(&pack_name)?;
+    let dry_run = common.dry_run;
+    let verbose = config.verbose.value;
 
-    // Configure thread pool
-    let jobs = common.jobs.unwrap_or_else(|| num_cpus::get() / 2).max(1);
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(jobs)
-        .build_global()
-        .ok(); // Ignore if already initialized
+    println!(
+        "Template pack: {} {}{}",
+        pack.name,
+        pack.version,
+        pack.description
+            .as_deref()
+            .map(|d| format!(" - {d}"))
+            .unwrap_or_default()
+    );
 
-    let verbose = common.verbose;
-    let dry_run = common.dry_run;
+    let mut written = 0;
+    let mut skipped = 0;
+    for repo in &repos {
+        let mut repo_written = 0;
+        for file in &pack.files {
+            let path = repo.root.join(&file.path);
+            let exists = path.exists();
+            if exists && mode == TemplateMode::Create {
+                skipped += 1;
+                if verbose {
+                    println!(
+                        "[{}] {} exists, skipping",
+                        ospath::display(&repo.root),
+                        file.path
+                    );
+                }
+                continue;
+            }
+            if exists && std::fs::read_to_string(&path).is_ok_and(|current| current == file.content)
+            {
+                skipped += 1;
+                continue;
+            }
 
-    // Track if we should stop early
-    let should_stop = AtomicBool::new(false);
-    let results: Mutex<Vec<RepoResult>> = Mutex::new(Vec::new());
+            if verbose || dry_run {
+                let action = if exists { "Overwriting" } else { "Creating" };
+                println!("[{}] {action} {}", ospath::display(&repo.root), file.path);
+            }
+            if dry_run {
+                let current = std::fs::read_to_string(&path).ok();
+                print!(
+                    "{}",
+                    diff::preview(&file.path, current.as_deref(), &file.content)
+                );
+            } else {
+                capabilities::write(&path, &file.content)?;
+            }
+            written += 1;
+            repo_written += 1;
+        }
 
-    repos.par_iter().for_each(|repo| {
-        if fail_fast && should_stop.load(Ordering::Relaxed) {
-            return;
+        // Only repos the pack was actually applied to get a record
+        if !dry_run && repo_written > 0 {
+            templates::write_record(&repo.root, &pack)?;
         }
+    }
 
-        let result = process_repo(
-            repo,
-            skip_update,
-            repeat,
-            &packages,
-            dry_run,
-            verbose,
-            write_justfile,
-            justfile_mode,
-        );
+    println!(
+        "\nSummary: {written} files written, {skipped} skipped in {} repos",
+        repos.len()
+    );
+    Ok(true)
+}
 
-        let success = result.success;
-        results.lock().unwrap().push(result);
+// =============================================================================
+// Inventory Command
+// =============================================================================
 
-        if fail_fast && !success {
-            should_stop.store(true, Ordering::Relaxed);
-        }
-    });
+fn cmd_inventory(common: CommonOptions, out_dir: PathBuf, git_ref: Option<String>) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos = discover_report(common.root(), &config, git_ref.as_deref())?;
+    let root = common.root().canonicalize()?;
 
-    // Print results
-    let results = results.into_inner().unwrap();
-    let mut all_success = true;
+    let registry = registry_reader(&config);
+    prefetch_deps(&registry, &repos);
+    if !registry.is_remote() && !registry.index_dir().exists() {
+        diagnostics::report(
+            diagnostics::Code::RegistryIndex,
+            format!(
+                "registry index not found at {} (run `moon update`); latest versions will be unknown",
+                registry.index_dir().display()
+            ),
+        );
+    }
 
-    println!("\n=== Results ===\n");
-    for result in &results {
-        let status = if result.success { "OK" } else { "FAILED" };
-        println!("[{status}] {}", result.repo_root.display());
+    let count = inventory::write_html(&repos, &root, &registry, &out_dir)?;
+    println!(
+        "Wrote inventory for {count} repos to {}",
+        out_dir.join("index.html").display()
+    );
+    Ok(true)
+}
 
-        if !result.updated_packages.is_empty() {
-            println!("  Updated: {} packages", result.updated_packages.len());
+// =============================================================================
+// Config Command
+// =============================================================================
+
+fn cmd_config(action: ConfigAction) -> Result<bool> {
+    match action {
+        ConfigAction::Show { common } => {
+            let config = Config::resolve(&common)?;
+            print!("{}", config.render());
+            Ok(true)
         }
+        ConfigAction::Validate { root, files } => {
+            let files = if files.is_empty() {
+                config::user_config_path()
+                    .into_iter()
+                    .chain([root.join(config::PROJECT_CONFIG_FILE)])
+                    .filter(|path| path.exists())
+                    .collect()
+            } else {
+                files
+            };
 
-        if !result.failed_packages.is_empty() {
-            println!("  Failed packages:");
-            for (pkg, err) in &result.failed_packages {
-                println!("    - {pkg}: {err}");
+            if files.is_empty() {
+                println!("No config files found.");
+                return Ok(true);
             }
-        }
 
-        for err in &result.errors {
-            println!("  Error: {err}");
+            let mut all_valid = true;
+            for path in &files {
+                match config::read_config_file(path) {
+                    Ok(Some(_)) => println!("[OK] {}", path.display()),
+                    Ok(None) => {
+                        println!("[FAILED] {}: file not found", path.display());
+                        all_valid = false;
+                    }
+                    Err(e) => {
+                        println!("[FAILED] {e:#}");
+                        all_valid = false;
+                    }
+                }
+            }
+            Ok(all_valid)
         }
+    }
+}
 
-        if !result.success {
-            all_success = false;
+// =============================================================================
+// Cache Command
+// =============================================================================
+
+/// Cache and build output sizes of one repo, for `cache stats --format json`
+#[derive(Serialize)]
+struct CacheStats {
+    repo: String,
+    #[serde(flatten)]
+    usage: disk::Usage,
+    total: u64,
+}
+
+fn cmd_cache(common: CommonOptions, action: CacheAction) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let repos: Vec<RepoInfo> = discover_fleet(common.root(), &config)?
+        .into_iter()
+        .filter(|repo| repo.host.is_none())
+        .collect();
+    init_thread_pool(config.effective_jobs());
+
+    match action {
+        CacheAction::Stats { format } => {
+            format.check_supported("cache stats", &[OutputFormat::Text, OutputFormat::Json])?;
+            let mut stats: Vec<CacheStats> = repos
+                .par_iter()
+                .map(|repo| {
+                    let usage = repo_usage(repo);
+                    CacheStats {
+                        repo: repo.location(),
+                        usage,
+                        total: usage.total(),
+                    }
+                })
+                .collect();
+            stats.sort_by(|a, b| b.total.cmp(&a.total).then(a.repo.cmp(&b.repo)));
+
+            if format == OutputFormat::Json {
+                println!("{}", output::json(&stats)?);
+                return Ok(true);
+            }
+            let size = disk::format_size;
+            println!(
+                "{:>10}  {:>10}  {:>10}  Repository",
+                "mooncakes", "build", "total"
+            );
+            let mut sum = disk::Usage::default();
+            for stat in &stats {
+                println!(
+                    "{:>10}  {:>10}  {:>10}  {}",
+                    size(stat.usage.mooncakes),
+                    size(stat.usage.build),
+                    size(stat.total),
+                    stat.repo
+                );
+                sum += stat.usage;
+            }
+            println!(
+                "{:>10}  {:>10}  {:>10}  Total ({} repos)",
+                size(sum.mooncakes),
+                size(sum.build),
+                size(sum.total()),
+                stats.len()
+            );
+            Ok(true)
+        }
+        CacheAction::Clean => {
+            let mut repos = repos;
+            select_toolchains(&mut repos, false);
+            Ok(clean_fleet(
+                &repos,
+                CleanScope::Moon,
+                None,
+                common.dry_run,
+                config.verbose.value,
+            ))
         }
     }
+}
 
-    let success_count = results.iter().filter(|r| r.success).count();
+// =============================================================================
+// Clean Command
+// =============================================================================
+
+/// What `clean` removes in each module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CleanScope {
+    /// Whatever `moon clean` removes
+    Moon,
+    /// Build output directories
+    Targets,
+    /// The `.mooncakes` dependency cache
+    Mooncakes,
+}
+
+/// Outcome of cleaning one module
+struct CleanedModule {
+    check: verify::ModuleCheck,
+    freed: u64,
+    /// Nothing old enough to remove
+    skipped: bool,
+}
+
+fn cmd_clean(
+    common: CommonOptions,
+    targets_only: bool,
+    mooncakes_only: bool,
+    older_than: Option<Duration>,
+) -> Result<bool> {
+    let config = Config::resolve(&common)?;
+    let mut repos: Vec<RepoInfo> = discover_fleet(common.root(), &config)?
+        .into_iter()
+        .filter(|repo| repo.host.is_none())
+        .collect();
+    init_thread_pool(config.effective_jobs());
+    let scope = if targets_only {
+        CleanScope::Targets
+    } else if mooncakes_only {
+        CleanScope::Mooncakes
+    } else {
+        select_toolchains(&mut repos, false);
+        CleanScope::Moon
+    };
+    Ok(clean_fleet(
+        &repos,
+        scope,
+        older_than,
+        common.dry_run,
+        config.verbose.value,
+    ))
+}
+
+/// Clean every module of `repos` in parallel and print the space freed;
+/// false if any module failed
+fn clean_fleet(
+    repos: &[RepoInfo],
+    scope: CleanScope,
+    older_than: Option<Duration>,
+    dry_run: bool,
+    verbose: bool,
+) -> bool {
+    let cutoff = older_than.and_then(|age| std::time::SystemTime::now().checked_sub(age));
+    let mut cleaned: Vec<CleanedModule> = repos
+        .par_iter()
+        .flat_map(|repo| {
+            repo.moon_mods
+                .par_iter()
+                .map(move |moon_mod| clean_module(repo, moon_mod, scope, cutoff, dry_run, verbose))
+        })
+        .collect();
+    cleaned.sort_by(|a, b| a.check.module_dir.cmp(&b.check.module_dir));
+
+    let mut freed = 0;
+    for module in &cleaned {
+        let dir = module.check.module_dir.display();
+        match &module.check.error {
+            None if module.skipped => println!("[SKIP] {dir} (modified recently)"),
+            None => {
+                freed += module.freed;
+                println!("[OK] {dir} (freed {})", disk::format_size(module.freed));
+            }
+            Some(err) => println!("[FAIL] {dir}: {err}"),
+        }
+    }
+    let skipped = cleaned.iter().filter(|m| m.skipped).count();
+    let failed = cleaned.iter().filter(|m| !m.check.success()).count();
     println!(
-        "\nSummary: {}/{} repos succeeded",
-        success_count,
-        results.len()
+        "\nSummary: {} {} in {} modules, {skipped} skipped, {failed} failed",
+        if dry_run { "would free" } else { "freed" },
+        disk::format_size(freed),
+        cleaned.len() - skipped
     );
-
-    Ok(all_success)
+    failed == 0
 }
 
-fn process_repo(
+/// Clean one module: run `moon clean`, or remove its build output or
+/// dependency cache directly. With a `cutoff`, only artifacts not modified
+/// since then are removed (for `moon clean`, the build output decides).
+fn clean_module(
     repo: &RepoInfo,
-    skip_update: bool,
-    repeat: u32,
-    filter_packages: &[String],
+    moon_mod: &MoonModInfo,
+    scope: CleanScope,
+    cutoff: Option<std::time::SystemTime>,
     dry_run: bool,
     verbose: bool,
-    write_justfile: bool,
-    justfile_mode: JustfileMode,
-) -> RepoResult {
-    let mut result = RepoResult {
-        repo_root: repo.root.clone(),
-        success: true,
-        updated_packages: Vec::new(),
-        failed_packages: Vec::new(),
-        errors: Vec::new(),
-    };
-
-    // 1. Run moon update (unless skipped)
-    if !skip_update {
+) -> CleanedModule {
+    let dir = moon_mod.path.parent().unwrap_or(&repo.root);
+    let stale = |path: &Path| {
+        cutoff.is_none_or(|cutoff| disk::last_modified(path).is_none_or(|time| time < cutoff))
+    };
+    let builds: Vec<PathBuf> = disk::BUILD_DIRS.iter().map(|d| dir.join(d)).collect();
+    let started = Instant::now();
+    let done = |freed, skipped, error| CleanedModule {
+        check: verify::ModuleCheck {
+            repo_root: repo.root.clone(),
+            module_dir: dir.to_path_buf(),
+            duration: started.elapsed(),
+            error,
+        },
+        freed,
+        skipped,
+    };
+
+    if scope == CleanScope::Moon {
+        if !builds.iter().all(|path| stale(path)) {
+            return done(0, true, None);
+        }
+        let before = disk::module_usage(dir);
+        let log = observer::CommandLog {
+            enabled: verbose || dry_run,
+        };
+        let check = verify::run_in_module(repo, moon_mod, &["clean"], dry_run, &log);
+        let freed = if dry_run {
+            before.build
+        } else {
+            before
+                .total()
+                .saturating_sub(disk::module_usage(dir).total())
+        };
+        return CleanedModule {
+            check,
+            freed,
+            skipped: false,
+        };
+    }
+
+    let candidates = match scope {
+        CleanScope::Mooncakes => vec![dir.join(disk::CACHE_DIR)],
+        _ => builds,
+    };
+    let existing: Vec<PathBuf> = candidates.into_iter().filter(|p| p.is_dir()).collect();
+    let targets: Vec<&PathBuf> = existing.iter().filter(|path| stale(path)).collect();
+    let mut freed = 0;
+    let mut error = None;
+    for path in targets.iter().copied() {
         if verbose || dry_run {
-            println!("[{}] moon update", repo.root.display());
+            println!("[{}] rm -r {}", dir.display(), path.display());
         }
-        if !dry_run {
-            match run_moon_command(&["update"], &repo.root) {
-                Ok(_) => {
-                    if verbose {
-                        println!("[{}] moon update succeeded", repo.root.display());
-                    }
+        let removed = if dry_run {
+            Ok(disk::dir_size(path))
+        } else {
+            disk::remove_dir(path)
+        };
+        match removed {
+            Ok(bytes) => freed += bytes,
+            Err(e) => {
+                error = Some(format!("{e:#}"));
+                break;
+            }
+        }
+    }
+    done(freed, targets.is_empty() && !existing.is_empty(), error)
+}
+
+// =============================================================================
+// Toolchain Command
+// =============================================================================
+
+fn cmd_toolchain(action: ToolchainAction) -> Result<bool> {
+    match action {
+        ToolchainAction::List => {
+            match toolchain::installed() {
+                Some(version) => println!("{version:<16} default ({})", get_moon_bin().display()),
+                None => println!("{:<16} default (moon not found)", "-"),
+            }
+            let managed = toolchain::managed();
+            for (version, home) in &managed {
+                println!("{version:<16} managed ({})", home.display());
+            }
+            if managed.is_empty() {
+                println!(
+                    "\nNo managed toolchains in {}",
+                    toolchain::toolchains_dir()
+                        .map(|d| d.display().to_string())
+                        .unwrap_or_else(|| "~/.moon-dst/toolchains".to_string())
+                );
+            }
+            Ok(true)
+        }
+        ToolchainAction::Install { version, dry_run } => {
+            let home = toolchain::install(&version, dry_run)?;
+            if !dry_run {
+                println!("Installed moon {version} into {}", home.display());
+            }
+            Ok(true)
+        }
+    }
+}
+
+fn cmd_workspace(root: &Path, action: WorkspaceAction) -> Result<bool> {
+    let existing = workspace::Workspace::read(root)?;
+    let path = root.join(workspace::FILE_NAME);
+    match action {
+        WorkspaceAction::List => {
+            let Some(workspace) = existing else {
+                println!("No {} in {}", workspace::FILE_NAME, ospath::display(root));
+                return Ok(true);
+            };
+            let mut ok = true;
+            for (member, manifest) in workspace.members.iter().zip(workspace.manifests(root)) {
+                if manifest.is_file() {
+                    println!("{member}");
+                } else {
+                    println!("{member} (missing moon.mod.json)");
+                    ok = false;
                 }
-                Err(e) => {
-                    result.errors.push(format!("moon update failed: {e}"));
-                    result.success = false;
-                    return result;
+            }
+            Ok(ok)
+        }
+        WorkspaceAction::Add { path: member } => {
+            if !root.join(&member).join("moon.mod.json").is_file() {
+                bail!(errors::Coded::new(
+                    errors::Code::FileNotFound,
+                    format!("{} has no moon.mod.json", root.join(&member).display())
+                ));
+            }
+            let mut workspace = existing.unwrap_or_default();
+            if workspace.add(&member)? {
+                workspace.write(root)?;
+                println!("Added {member} to {}", path.display());
+            } else {
+                println!("{member} is already a member");
+            }
+            Ok(true)
+        }
+        WorkspaceAction::Remove { path: member } => {
+            let Some(mut workspace) = existing else {
+                bail!(errors::Coded::new(
+                    errors::Code::FileNotFound,
+                    format!("No {} in {}", workspace::FILE_NAME, ospath::display(root))
+                ));
+            };
+            if workspace.remove(&member)? {
+                workspace.write(root)?;
+                println!("Removed {member} from {}", path.display());
+                Ok(true)
+            } else {
+                println!("{member} is not a member");
+                Ok(false)
+            }
+        }
+    }
+}
+
+fn cmd_self_check(check_latest: bool, update: bool, feed: &str, dry_run: bool) -> Result<bool> {
+    println!("moon-dst {}", env!("CARGO_PKG_VERSION"));
+
+    let Some(installed) = toolchain::installed() else {
+        println!("moon: not found (checked PATH and ~/.moon/bin/moon)");
+        if update {
+            toolchain::update_default(dry_run)?;
+        }
+        return Ok(false);
+    };
+    println!("moon {installed} ({})", get_moon_bin().display());
+    println!("managed toolchains: {}", toolchain::managed().len());
+
+    if !check_latest {
+        return Ok(true);
+    }
+
+    let latest = toolchain::latest_release(feed)?;
+    if !registry::is_outdated(&installed, &latest) {
+        println!("moon is up to date (latest: {latest})");
+        return Ok(true);
+    }
+
+    println!("Warning: moon {installed} is older than the latest release {latest}");
+    if update {
+        toolchain::update_default(dry_run)?;
+        return Ok(true);
+    }
+    println!("Run `moon-dst self-check --update-toolchain` to update");
+    Ok(false)
+}
+
+fn cmd_explain(code: Option<&str>) -> Result<bool> {
+    let Some(code) = code else {
+        print!("{}", errors::list());
+        return Ok(true);
+    };
+    match errors::explain(code) {
+        Some(text) => println!("{text}"),
+        None => bail!(errors::Coded::new(
+            errors::Code::InvalidValue,
+            format!("Unknown code {code}; run `moon-dst explain` for the list")
+        )),
+    }
+    Ok(true)
+}
+
+fn cmd_history(root: Option<&Path>, action: HistoryAction) -> Result<bool> {
+    let Some(path) = history::journal_path() else {
+        bail!(errors::Coded::new(
+            errors::Code::FileNotFound,
+            "Cannot find the run journal (HOME not set)"
+        ));
+    };
+    let mut runs = history::read(&path)?;
+    if let Some(root) = root {
+        let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        runs.retain(|run| run.root == root);
+    }
+    match action {
+        HistoryAction::List { limit } => {
+            let shown = runs.iter().rev().take(limit.unwrap_or(usize::MAX));
+            for run in shown {
+                println!(
+                    "{}  {}  {} changes, {} failed repos  {}",
+                    run.id.as_deref().unwrap_or("-"),
+                    timestamp::rfc3339(run.started),
+                    run.change_count(),
+                    run.failure_count(),
+                    ospath::display(&run.root)
+                );
+            }
+            Ok(true)
+        }
+        HistoryAction::Show { run_id, format } => {
+            format.check_supported("history show", &[OutputFormat::Text, OutputFormat::Json])?;
+            if !run_id::is_valid(&run_id) {
+                bail!(errors::Coded::new(
+                    errors::Code::InvalidValue,
+                    format!(
+                        "{run_id} is not a run ID (26 characters, like 01J9Z3K8Q0AB12CD34EF56GH78)"
+                    )
+                ));
+            }
+            let Some(run) = history::find(&runs, &run_id) else {
+                bail!(errors::Coded::new(
+                    errors::Code::InvalidValue,
+                    format!("No run {run_id} in {}", path.display())
+                ));
+            };
+            if format == OutputFormat::Json {
+                println!("{}", output::json(run)?);
+                return Ok(true);
+            }
+            println!("Run {run_id}");
+            println!("  Started: {}", timestamp::rfc3339(run.started));
+            println!("  Root: {}", ospath::display(&run.root));
+            for line in output::facts(
+                "  Summary",
+                &[
+                    format!("{} changes", run.change_count()),
+                    format!("{} failed repos", run.failure_count()),
+                ],
+            ) {
+                println!("{line}");
+            }
+            for repo in &run.repos {
+                println!(
+                    "[{}] {}",
+                    output::status(repo.success),
+                    ospath::display(&repo.repo)
+                );
+                for change in &repo.changes {
+                    let old = change.old_version.as_deref().unwrap_or("?");
+                    let new = change.new_version.as_deref().unwrap_or("?");
+                    println!("  {}: {}", change.name, output::change(old, new));
+                }
+                for name in &repo.failed {
+                    println!("  {name}: failed");
                 }
             }
+            Ok(true)
+        }
+    }
+}
+
+fn cmd_show_error(id: &str) -> Result<bool> {
+    let Some(dir) = data_dir().map(|dir| dir.join(error_output::DIR)) else {
+        bail!(errors::Coded::new(
+            errors::Code::FileNotFound,
+            "Cannot find stored error output (HOME not set)"
+        ));
+    };
+    let text = error_output::load(&dir, id)?;
+    println!("{}", text.trim_end());
+    Ok(true)
+}
+
+fn cmd_audit_log(action: AuditLogAction) -> Result<bool> {
+    let Some(path) = data_dir().map(|dir| dir.join(audit::FILE)) else {
+        bail!(errors::Coded::new(
+            errors::Code::FileNotFound,
+            "Cannot find the audit log (HOME not set)"
+        ));
+    };
+    let entries = audit::read(&path)?;
+    match action {
+        AuditLogAction::Show { limit, run, format } => {
+            format.check_supported("audit-log show", &[OutputFormat::Text, OutputFormat::Json])?;
+            let selected: Vec<&audit::Entry> = entries
+                .iter()
+                .filter(|e| {
+                    run.as_ref()
+                        .is_none_or(|id| e.run_id.eq_ignore_ascii_case(id))
+                })
+                .collect();
+            let skip = selected.len().saturating_sub(limit.unwrap_or(usize::MAX));
+            let shown = &selected[skip..];
+            if format == OutputFormat::Json {
+                println!("{}", output::json(&shown)?);
+                return Ok(true);
+            }
+            for entry in shown {
+                let exit = match (entry.exit_code, &entry.error) {
+                    (Some(code), _) => format!("exit {code}"),
+                    (None, Some(_)) => "not run".to_string(),
+                    (None, None) => "killed".to_string(),
+                };
+                println!(
+                    "{:>6}  {}  {}  {exit:<8} {:>7}ms  {} {}  (in {})",
+                    entry.seq,
+                    entry.time,
+                    entry.run_id,
+                    entry.duration_ms,
+                    entry.program,
+                    entry.args.join(" "),
+                    entry.cwd
+                );
+            }
+            Ok(true)
+        }
+        AuditLogAction::Verify => {
+            let head = audit::verify(&entries)?;
+            println!(
+                "{} entries verified in {}; head {head}",
+                entries.len(),
+                path.display()
+            );
+            Ok(true)
+        }
+    }
+}
+
+fn cmd_self_update(check: bool, release_url: &str) -> Result<bool> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = self_update::latest_release(release_url)?;
+    let latest = release.version();
+
+    if !registry::is_outdated(current, latest) {
+        println!("moon-dst {current} is up to date (latest: {latest})");
+        return Ok(true);
+    }
+    println!("moon-dst {current} -> {latest}");
+    if check {
+        return Ok(false);
+    }
+
+    let exe = std::env::current_exe().context("Failed to locate the running executable")?;
+    let exe = exe.canonicalize().unwrap_or(exe);
+    let downloaded = self_update::download_verified(&release, &exe)?;
+    println!("Verified {} (sha256)", self_update::asset_name());
+    self_update::replace_exe(&exe, &downloaded)?;
+    println!("Updated {} to {latest}", exe.display());
+    Ok(true)
+}
+
+// =============================================================================
+// Discovery Logic
+// =============================================================================
+
+/// Local repos below `root` and the other `--root`s
+fn discover_repos(root: &Path, config: &Config) -> Result<Vec<RepoInfo>> {
+    let mut repos = discover_roots(
+        root,
+        config,
+        &mut ssh::Hosts::new(config.ssh_max_per_host.value),
+    )?;
+    assign_owners(&mut repos, root, config)?;
+    Ok(repos)
+}
+
+/// Local repos below `root` and the other `--root`s plus the remote repos from `[ssh] repos` and
+/// the clones of `[clone] repos`
+///
+/// Only commands that read repos and run moon use this; commands writing
+/// files stay local.
+fn discover_fleet(root: &Path, config: &Config) -> Result<Vec<RepoInfo>> {
+    let mut hosts = ssh::Hosts::new(config.ssh_max_per_host.value);
+    let mut repos = discover_roots(root, config, &mut hosts)?;
+    for dir in synced_clones(config)? {
+        repos.extend(discover_local(&dir, config, &mut hosts)?);
+    }
+    let ignores = ignore_list(config);
+    for remote in &config.ssh_repos.value {
+        repos.extend(remote_repo(remote, &mut hosts, &ignores, config));
+    }
+    assign_owners(&mut repos, root, config)?;
+    Ok(repos)
+}
+
+/// The repos of [`discover_fleet`], each passed to `emit` with its owners
+/// as soon as it is found, until `emit` breaks
+///
+/// Local repos come in walk order; a repo found again under another
+/// `--root` is not passed on twice.
+fn stream_fleet(
+    root: &Path,
+    config: &Config,
+    emit: &mut dyn FnMut(RepoInfo) -> Result<ControlFlow<()>>,
+) -> Result<()> {
+    let mut hosts = ssh::Hosts::new(config.ssh_max_per_host.value);
+    let mut seen = HashSet::new();
+    let mut owned = |repo: RepoInfo| {
+        if !seen.insert(repo.location()) {
+            return Ok(ControlFlow::Continue(()));
+        }
+        let mut repos = vec![repo];
+        assign_owners(&mut repos, root, config)?;
+        match repos.pop() {
+            Some(repo) => emit(repo),
+            None => Ok(ControlFlow::Continue(())),
+        }
+    };
+    for search in search_roots(root, config) {
+        if stream_local(search, config, &mut hosts, &mut owned)?.is_break() {
+            return Ok(());
+        }
+    }
+    for dir in synced_clones(config)? {
+        if stream_local(&dir, config, &mut hosts, &mut owned)?.is_break() {
+            return Ok(());
+        }
+    }
+    let ignores = ignore_list(config);
+    for remote in &config.ssh_repos.value {
+        if let Some(repo) = remote_repo(remote, &mut hosts, &ignores, config) {
+            if owned(repo)?.is_break() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Working copies of `[clone] repos`, cloned or updated first; a repo that
+/// cannot be synced is skipped unless an earlier copy exists
+fn synced_clones(config: &Config) -> Result<Vec<PathBuf>> {
+    if config.clone_repos.value.is_empty() {
+        return Ok(Vec::new());
+    }
+    let clones = clone::clones_dir().context("Cannot locate the clones directory")?;
+    let mut dirs = Vec::new();
+    for url in &config.clone_repos.value {
+        let dir = clones.join(clone::dir_name(url));
+        if let Err(e) = clone::sync(url, &dir, config.clone_full.value, config.verbose.value) {
+            diagnostics::report(diagnostics::Code::SkippedRepo, format!("{url}: {e:#}"));
+            if !dir.exists() {
+                continue;
+            }
         }
+        dirs.push(dir);
     }
+    Ok(dirs)
+}
 
-    // 2. Collect all deps from all moon.mod.json files
-    let all_deps: Vec<String> = repo
-        .moon_mods
-        .iter()
-        .flat_map(|m| m.deps.iter())
-        .filter(|dep| filter_packages.is_empty() || filter_packages.iter().any(|p| dep.contains(p)))
-        .cloned()
-        .collect::<std::collections::HashSet<_>>()
-        .into_iter()
-        .collect();
+/// The repo at `remote`, or `None` with a diagnostic
+fn remote_repo(
+    remote: &ssh::Remote,
+    hosts: &mut ssh::Hosts,
+    ignores: &[String],
+    config: &Config,
+) -> Option<RepoInfo> {
+    match discover_remote(remote, hosts.runner(&remote.host), ignores, config) {
+        Ok(Some(repo)) => Some(repo),
+        Ok(None) => {
+            diagnostics::report(
+                diagnostics::Code::SkippedRepo,
+                format!("No moon.mod.json files in {remote}"),
+            );
+            None
+        }
+        Err(e) => {
+            diagnostics::report(
+                diagnostics::Code::SkippedRepo,
+                format!("Skipping {remote}: {e:#}"),
+            );
+            None
+        }
+    }
+}
 
-    // 3. Run moon add for each package (repeated as specified)
-    for _ in 0..repeat {
-        for dep in &all_deps {
-            if verbose || dry_run {
-                println!("[{}] moon add {}", repo.root.display(), dep);
-            }
-            if !dry_run {
-                match run_moon_command(&["add", dep], &repo.root) {
-                    Ok(_) => {
-                        if !result.updated_packages.contains(dep) {
-                            result.updated_packages.push(dep.clone());
+/// `root` followed by the other `--root`s
+fn search_roots<'a>(root: &'a Path, config: &'a Config) -> impl Iterator<Item = &'a Path> {
+    std::iter::once(root).chain(config.extra_roots.iter().map(PathBuf::as_path))
+}
+
+/// [`search_roots`] canonicalized, for making repo paths relative
+fn canonical_roots(root: &Path, config: &Config) -> Vec<PathBuf> {
+    search_roots(root, config)
+        .map(|root| root.canonicalize().unwrap_or_else(|_| root.to_path_buf()))
+        .collect()
+}
+
+/// Local repos below every search root
+///
+/// A repo found under more than one root is listed once, with the modules
+/// found from each.
+fn discover_roots(root: &Path, config: &Config, hosts: &mut ssh::Hosts) -> Result<Vec<RepoInfo>> {
+    let mut repos: Vec<RepoInfo> = Vec::new();
+    let mut index: HashMap<PathBuf, usize> = HashMap::new();
+    for root in search_roots(root, config) {
+        for repo in discover_local(root, config, hosts)? {
+            match index.get(&repo.root) {
+                Some(&i) => {
+                    let known = &mut repos[i].moon_mods;
+                    for moon_mod in repo.moon_mods {
+                        if !known.iter().any(|m| m.path == moon_mod.path) {
+                            known.push(moon_mod);
                         }
                     }
-                    Err(e) => {
-                        result.failed_packages.push((dep.clone(), e.to_string()));
-                        result.success = false;
-                    }
+                    known.sort_by(|a, b| a.path.cmp(&b.path));
+                }
+                None => {
+                    index.insert(repo.root.clone(), repos.len());
+                    repos.push(repo);
                 }
             }
         }
     }
+    repos.sort_by(|a, b| a.root.cmp(&b.root));
+    Ok(repos)
+}
 
-    // 4. Handle justfile
-    if write_justfile {
-        if let Err(e) = handle_justfile(&repo.root, justfile_mode, dry_run, verbose) {
-            result.errors.push(format!("justfile handling failed: {e}"));
-        }
+/// Set the owners of `repos` and keep only those selected with `--owner`
+///
+/// A local repo is matched against the first search root it is below.
+fn assign_owners(repos: &mut Vec<RepoInfo>, root: &Path, config: &Config) -> Result<()> {
+    let mut roots = Vec::new();
+    for root in search_roots(root, config) {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let file = owners::CodeOwners::read(&root)?;
+        roots.push((root, file));
     }
-
-    result
+    for repo in repos.iter_mut() {
+        let (base, root_file) = roots
+            .iter()
+            .find(|(root, _)| repo.root.starts_with(root))
+            .unwrap_or(&roots[0]);
+        let (rel, repo_file) = match &repo.host {
+            Some(_) => (PathBuf::from(repo.location()), None),
+            None => (
+                repo.root
+                    .strip_prefix(base)
+                    .unwrap_or(&repo.root)
+                    .to_path_buf(),
+                owners::CodeOwners::read(&repo.root)?,
+            ),
+        };
+        let manifests: Vec<&Path> = repo.moon_mods.iter().map(|m| m.path.as_path()).collect();
+        repo.owners = owners::resolve(
+            &rel,
+            &repo.root,
+            &manifests,
+            &config.owners_repos.value,
+            repo_file.as_ref(),
+            root_file.as_ref().map(|file| (base.as_path(), file)),
+        );
+    }
+    if !config.owner_filter.is_empty() {
+        repos.retain(|repo| repo.owners.iter().any(|o| config.owner_filter.contains(o)));
+    }
+    Ok(())
 }
 
-fn run_moon_command(args: &[&str], cwd: &Path) -> Result<String> {
-    let moon_bin = get_moon_bin();
-    let output = Command::new(&moon_bin)
-        .args(args)
-        .current_dir(cwd)
-        .output()
-        .with_context(|| format!("Failed to execute moon {}", args.join(" ")))?;
-
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let code = output.status.code().unwrap_or(-1);
-        bail!("exit code {code}: {stderr}")
+/// Repos for read-only reports: the fleet, or committed manifests at `git_ref`
+fn discover_report(root: &Path, config: &Config, git_ref: Option<&str>) -> Result<Vec<RepoInfo>> {
+    match git_ref {
+        Some(git_ref) => discover_at_ref(root, config, git_ref),
+        None => discover_fleet(root, config),
     }
 }
 
-// =============================================================================
-// Just Command
-// =============================================================================
-
-fn cmd_just(common: CommonOptions, mode: JustfileMode) -> Result<bool> {
-    let repos = discover_repos(&common)?;
-
-    if repos.is_empty() {
-        println!("No moon.mod.json files found.");
-        return Ok(true);
+/// Git repos below every search root with their manifests read from
+/// `git_ref`
+///
+/// Repos without the ref are skipped with a warning.
+fn discover_at_ref(root: &Path, config: &Config, git_ref: &str) -> Result<Vec<RepoInfo>> {
+    let ignores = ignore_list(config);
+    let ignore = |path: &Path| should_ignore(path, &ignores);
+    let mut repo_roots = BTreeSet::new();
+    for root in search_roots(root, config) {
+        let root = root.canonicalize().with_context(|| {
+            errors::Coded::new(
+                errors::Code::InvalidRoot,
+                format!("Invalid root path: {}", ospath::display(root)),
+            )
+        })?;
+        repo_roots.extend(gitref::find_repos(&root, ignore)?);
     }
 
-    let dry_run = common.dry_run;
-    let verbose = common.verbose;
-    let mut success_count = 0;
-    let mut skip_count = 0;
-
-    for repo in &repos {
-        match handle_justfile(&repo.root, mode, dry_run, verbose) {
-            Ok(created) => {
-                if created {
-                    success_count += 1;
-                } else {
-                    skip_count += 1;
+    let mut repos = Vec::new();
+    for repo_root in repo_roots {
+        if !gitref::has_ref(&repo_root, git_ref) {
+            diagnostics::report(
+                diagnostics::Code::SkippedRepo,
+                format!("{} has no ref {git_ref}", ospath::display(&repo_root)),
+            );
+            continue;
+        }
+        let mut moon_mods = Vec::new();
+        for rel in gitref::list_manifests(&repo_root, git_ref, ignore)? {
+            let path = repo_root.join(&rel);
+            let Some(content) = gitref::read_blob(&repo_root, git_ref, &rel)? else {
+                continue;
+            };
+            match parse_moon_mod_content(&content, &path) {
+                Ok(moon_mod) => {
+                    if config.verbose.value {
+                        println!("Found: {git_ref}:{rel} in {}", ospath::display(&repo_root));
+                    }
+                    moon_mods.push(moon_mod);
                 }
-            }
-            Err(e) => {
-                eprintln!("[{}] Error: {e}", repo.root.display());
+                Err(e) => diagnostics::report(
+                    diagnostics::Code::InvalidManifest,
+                    format!("{git_ref}: {e:#}"),
+                ),
             }
         }
+        if moon_mods.is_empty() {
+            continue;
+        }
+        let toolchain = gitref::read_blob(&repo_root, git_ref, toolchain::VERSION_FILE)?
+            .and_then(|content| toolchain::parse_pin(&content))
+            .or_else(|| moon_mods.iter().find_map(|m| m.moon_version.clone()));
+        repos.push(RepoInfo {
+            root: repo_root,
+            moon_mods,
+            toolchain,
+            moon: toolchain::Toolchain::Default,
+            skip: None,
+            host: None,
+            runner_spec: runner::RunnerSpec::Local,
+            runner: Arc::new(runner::Local),
+            owners: Vec::new(),
+        });
     }
-
-    println!("\nSummary: {success_count} created, {skip_count} skipped");
-    Ok(true)
+    assign_owners(&mut repos, root, config)?;
+    Ok(repos)
 }
 
-fn handle_justfile(
-    repo_root: &Path,
-    mode: JustfileMode,
-    dry_run: bool,
-    verbose: bool,
-) -> Result<bool> {
-    let justfile_path = repo_root.join("justfile");
-    let exists = justfile_path.exists();
-
-    match mode {
-        JustfileMode::Skip => {
-            if verbose {
-                println!("[{}] Skipping justfile (skip mode)", repo_root.display());
-            }
-            Ok(false)
-        }
-        JustfileMode::Create => {
-            if exists {
-                if verbose {
-                    println!(
-                        "[{}] justfile already exists, skipping",
-                        repo_root.display()
-                    );
-                }
-                Ok(false)
-            } else {
-                if verbose || dry_run {
-                    println!("[{}] Creating justfile", repo_root.display());
-                }
-                if !dry_run {
-                    std::fs::write(&justfile_path, JUSTFILE_TEMPLATE)
-                        .with_context(|| format!("Failed to write {}", justfile_path.display()))?;
+/// A remote repo, with manifests and markers read over SFTP
+fn discover_remote(
+    remote: &ssh::Remote,
+    runner: ssh::Ssh,
+    ignores: &[String],
+    config: &Config,
+) -> Result<Option<RepoInfo>> {
+    let mut moon_mods = Vec::new();
+    for path in ssh::find_manifests(&runner, &remote.path, ignores)? {
+        let content = runner
+            .read_file(&path)
+            .with_context(|| format!("Failed to read {}:{}", remote.host, path.display()))?;
+        match parse_moon_mod_content(&content, &path) {
+            Ok(moon_mod) => {
+                if config.verbose.value {
+                    println!("Found: {}:{}", remote.host, path.display());
                 }
-                Ok(true)
+                moon_mods.push(moon_mod);
             }
-        }
-        JustfileMode::Merge => {
-            // Merge mode is not implemented as per spec (just mentioned)
-            if verbose {
-                println!("[{}] Merge mode not implemented", repo_root.display());
-            }
-            Ok(false)
+            Err(e) => diagnostics::report(
+                diagnostics::Code::InvalidManifest,
+                format!("{}: {e:#}", remote.host),
+            ),
         }
     }
-}
-
-// =============================================================================
-// Discovery Logic
-// =============================================================================
+    if moon_mods.is_empty() {
+        return Ok(None);
+    }
 
-fn discover_repos(common: &CommonOptions) -> Result<Vec<RepoInfo>> {
-    let root = common
-        .root
-        .canonicalize()
-        .with_context(|| format!("Invalid root path: {}", common.root.display()))?;
+    let read = |name: &str| runner.read_file(&remote.path.join(name)).ok();
+    let toolchain = read(toolchain::VERSION_FILE)
+        .and_then(|content: String| toolchain::parse_pin(&content))
+        .or_else(|| moon_mods.iter().find_map(|m| m.moon_version.clone()));
+    let skip = read(skip::MARKER_FILE).map(|content: String| {
+        skip::Marker::parse(&content).unwrap_or_else(|e| {
+            diagnostics::report(diagnostics::Code::SkipMarker, format!("{remote}: {e:#}"));
+            skip::Marker::default()
+        })
+    });
+    Ok(Some(RepoInfo {
+        root: remote.path.clone(),
+        moon_mods,
+        toolchain,
+        moon: toolchain::Toolchain::Default,
+        skip,
+        host: Some(remote.host.clone()),
+        runner_spec: runner::RunnerSpec::Ssh(remote.host.clone()),
+        runner: Arc::new(runner),
+        owners: Vec::new(),
+    }))
+}
 
-    // Build ignore list
-    let mut ignores: Vec<String> = common.ignores.clone();
-    if !common.no_default_ignore {
-        ignores.extend(DEFAULT_IGNORES.iter().map(|s| s.to_string()));
-    }
+fn discover_local(root: &Path, config: &Config, hosts: &mut ssh::Hosts) -> Result<Vec<RepoInfo>> {
+    let root = root.canonicalize().with_context(|| {
+        errors::Coded::new(
+            errors::Code::InvalidRoot,
+            format!("Invalid root path: {}", ospath::display(root)),
+        )
+    })?;
 
     // Find all moon.mod.json files
-    let moon_mods = find_moon_mods(&root, &ignores, common.verbose)?;
+    let moon_mods = find_moon_mods(&root, config)?;
 
     // Group by repo root
     let mut repo_map: HashMap<PathBuf, Vec<MoonModInfo>> = HashMap::new();
     for moon_mod in moon_mods {
-        let repo_root = find_repo_root(&moon_mod.path);
+        let repo_root = repo_root::find(&moon_mod.path, &config.root_markers.value);
         repo_map.entry(repo_root).or_default().push(moon_mod);
     }
 
     // Convert to Vec<RepoInfo>
-    let mut repos: Vec<RepoInfo> = repo_map
-        .into_iter()
-        .map(|(root, moon_mods)| RepoInfo { root, moon_mods })
-        .collect();
+    let mut repos = Vec::with_capacity(repo_map.len());
+    for (repo_root, moon_mods) in repo_map {
+        repos.extend(local_repos(repo_root, moon_mods, &root, config, hosts)?);
+    }
 
     // Sort for consistent output
     repos.sort_by(|a, b| a.root.cmp(&b.root));
@@ -680,32 +8241,316 @@ fn discover_repos(common: &CommonOptions) -> Result<Vec<RepoInfo>> {
     Ok(repos)
 }
 
-fn find_moon_mods(root: &Path, ignores: &[String], verbose: bool) -> Result<Vec<MoonModInfo>> {
+/// Local repos below `root`, each passed to `emit` as soon as the walk has
+/// left its directory, until `emit` breaks
+///
+/// The order is the walk's rather than sorted, and a repo whose workspace
+/// members lie outside its directory may be passed on more than once.
+fn stream_local(
+    root: &Path,
+    config: &Config,
+    hosts: &mut ssh::Hosts,
+    emit: &mut dyn FnMut(RepoInfo) -> Result<ControlFlow<()>>,
+) -> Result<ControlFlow<()>> {
+    let root = root.canonicalize().with_context(|| {
+        errors::Coded::new(
+            errors::Code::InvalidRoot,
+            format!("Invalid root path: {}", ospath::display(root)),
+        )
+    })?;
+    let mut pending: Vec<(PathBuf, Vec<MoonModInfo>)> = Vec::new();
+    let mut flow = ControlFlow::Continue(());
+    let mut finish = |repo_root: PathBuf, moon_mods: Vec<MoonModInfo>, hosts: &mut ssh::Hosts| {
+        for repo in local_repos(repo_root, moon_mods, &root, config, hosts)? {
+            if emit(repo)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok::<_, anyhow::Error>(ControlFlow::Continue(()))
+    };
+    walk_moon_mods(&root, config, &mut |walked| {
+        match walked {
+            Walked::Dir(dir) => {
+                while let Some(i) = pending.iter().position(|(r, _)| !dir.starts_with(r)) {
+                    let (repo_root, moon_mods) = pending.remove(i);
+                    flow = finish(repo_root, moon_mods, hosts)?;
+                    if flow.is_break() {
+                        return Ok(flow);
+                    }
+                }
+            }
+            Walked::Manifest(moon_mod) => {
+                let repo_root = repo_root::find(&moon_mod.path, &config.root_markers.value);
+                match pending.iter_mut().find(|(r, _)| *r == repo_root) {
+                    Some((_, moon_mods)) => moon_mods.push(moon_mod),
+                    None => pending.push((repo_root, vec![moon_mod])),
+                }
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+    if flow.is_break() {
+        return Ok(flow);
+    }
+    for (repo_root, moon_mods) in pending {
+        if finish(repo_root, moon_mods, hosts)?.is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+/// The repos at `root` (below `base`) with `moon_mods`: none for a skipped
+/// submodule, more than one for nested modules split into their own repos
+fn local_repos(
+    root: PathBuf,
+    moon_mods: Vec<MoonModInfo>,
+    base: &Path,
+    config: &Config,
+    hosts: &mut ssh::Hosts,
+) -> Result<Vec<RepoInfo>> {
+    let repo = local_repo(root, moon_mods, base, config, hosts)?;
+    if config.submodules.value == submodule::Submodules::Skip {
+        if let Some(superproject) = submodule::superproject(&repo.root) {
+            if config.verbose.value {
+                println!(
+                    "Skipping submodule {} of {}",
+                    ospath::display(&repo.root),
+                    ospath::display(&superproject)
+                );
+            }
+            return Ok(Vec::new());
+        }
+    }
+    Ok(layout::split(repo, config.nested_modules.value, hosts))
+}
+
+/// The local repo at `root` (below `base`) with `moon_mods`, its toolchain
+/// pin, skip marker and runner
+fn local_repo(
+    root: PathBuf,
+    moon_mods: Vec<MoonModInfo>,
+    base: &Path,
+    config: &Config,
+    hosts: &mut ssh::Hosts,
+) -> Result<RepoInfo> {
+    let toolchain = match toolchain::read_pin(&root)? {
+        Some(pin) => Some(pin),
+        None => moon_mods.iter().find_map(|m| m.moon_version.clone()),
+    };
+    // A marker we cannot read still opts the repo out
+    let skip = skip::Marker::read(&root).unwrap_or_else(|e| {
+        diagnostics::report(diagnostics::Code::SkipMarker, format!("{e:#}"));
+        Some(skip::Marker::default())
+    });
+    let rel = root.strip_prefix(base).unwrap_or(&root);
+    let runner_spec = runner::select(rel, &config.runner_repos.value, &config.runner.value).clone();
+    Ok(RepoInfo {
+        runner: runner_spec.build(&root, hosts),
+        host: None,
+        runner_spec,
+        root,
+        moon_mods,
+        toolchain,
+        moon: toolchain::Toolchain::Default,
+        skip,
+        owners: Vec::new(),
+    })
+}
+
+/// The repos listed in a `scan --format json` document, their listed
+/// manifests read again from disk but nothing else discovered
+fn discover_scanned(
+    root: &Path,
+    config: &Config,
+    scan: &pipe::ScanDocument,
+) -> Result<Vec<RepoInfo>> {
+    let base = root.canonicalize().with_context(|| {
+        errors::Coded::new(
+            errors::Code::InvalidRoot,
+            format!("Invalid root path: {}", ospath::display(root)),
+        )
+    })?;
+    let (local, remote) = scan.repos(&base);
+    for location in remote {
+        diagnostics::report(
+            diagnostics::Code::SkippedRepo,
+            format!("Skipping {location}: remote repos cannot be applied from a scan document"),
+        );
+    }
+    let mut hosts = ssh::Hosts::new(config.ssh_max_per_host.value);
+    let mut repos = Vec::new();
+    for (repo_root, manifests) in local {
+        let mut moon_mods = Vec::new();
+        for manifest in manifests {
+            match parse_moon_mod(&manifest) {
+                Ok(moon_mod) => moon_mods.push(moon_mod),
+                Err(e) => diagnostics::report(
+                    diagnostics::Code::InvalidManifest,
+                    format!("Skipping manifest: {e:#}"),
+                ),
+            }
+        }
+        if moon_mods.is_empty() {
+            continue;
+        }
+        repos.push(local_repo(repo_root, moon_mods, &base, config, &mut hosts)?);
+    }
+    repos.sort_by(|a, b| a.root.cmp(&b.root));
+    assign_owners(&mut repos, root, config)?;
+    Ok(repos)
+}
+
+/// The local repos a plan names, each discovered within its own directory
+fn discover_planned(root: &Path, config: &Config, plan: &plan::Plan) -> Result<Vec<RepoInfo>> {
+    let mut hosts = ssh::Hosts::new(config.ssh_max_per_host.value);
+    let mut repos = Vec::new();
+    for planned in &plan.repos {
+        if !planned.repo.is_dir() {
+            diagnostics::report(
+                diagnostics::Code::SkippedRepo,
+                format!(
+                    "Skipping {}: not a directory",
+                    ospath::display(&planned.repo)
+                ),
+            );
+            continue;
+        }
+        repos.extend(
+            discover_local(&planned.repo, config, &mut hosts)?
+                .into_iter()
+                .filter(|repo| repo.root == planned.repo),
+        );
+    }
+    assign_owners(&mut repos, root, config)?;
+    Ok(repos)
+}
+
+/// Configured ignores plus the defaults unless disabled
+fn ignore_list(config: &Config) -> Vec<String> {
+    let mut ignores: Vec<String> = config.ignore.value.clone();
+    if !config.no_default_ignore.value {
+        ignores.extend(DEFAULT_IGNORES.iter().map(|s| s.to_string()));
+    }
+    ignores
+}
+
+/// What [`walk_moon_mods`] passes on, in walk order
+enum Walked<'a> {
+    /// A directory the walk entered
+    Dir(&'a Path),
+    Manifest(MoonModInfo),
+}
+
+fn find_moon_mods(root: &Path, config: &Config) -> Result<Vec<MoonModInfo>> {
     let mut moon_mods = Vec::new();
+    walk_moon_mods(root, config, &mut |walked| {
+        if let Walked::Manifest(moon_mod) = walked {
+            moon_mods.push(moon_mod);
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+    Ok(moon_mods)
+}
+
+/// Walk `root` depth first in name order, passing on directories and the
+/// manifests that parse, until `visit` breaks
+///
+/// Besides `ignore`, entries git excludes are left out unless
+/// `--no-global-ignore` is given.
+fn walk_moon_mods(
+    root: &Path,
+    config: &Config,
+    visit: &mut dyn FnMut(Walked) -> Result<ControlFlow<()>>,
+) -> Result<()> {
+    let ignores = &ignore_list(config);
+    let verbose = config.verbose.value;
+    let mut git_excludes = (!config.no_global_ignore.value).then(|| excludes::Walk::new(root));
+    let found = |path: &Path, visit: &mut dyn FnMut(Walked) -> Result<ControlFlow<()>>| {
+        match parse_moon_mod(path) {
+            Ok(moon_mod) => {
+                if verbose {
+                    println!("Found: {}", path.display());
+                }
+                visit(Walked::Manifest(moon_mod))
+            }
+            Err(e) => {
+                diagnostics::report(
+                    diagnostics::Code::InvalidManifest,
+                    format!("Skipping manifest: {e:#}"),
+                );
+                Ok(ControlFlow::Continue(()))
+            }
+        }
+    };
+
+    // With `--stats`; the filter and the loop take turns with it
+    let stats = RefCell::new(traversal::enabled().then(|| traversal::Walk::new(root)));
+    let record = |update: &dyn Fn(&mut traversal::Walk)| {
+        if let Some(walk) = stats.borrow_mut().as_mut() {
+            update(walk);
+        }
+    };
+    let rel = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
 
-    for entry in WalkDir::new(root)
+    let mut walker = WalkDir::new(root)
         .follow_links(false)
+        .sort_by_file_name()
         .into_iter()
-        .filter_entry(|e| !should_ignore(e.path(), ignores))
-    {
+        .filter_entry(|e| {
+            let ignored = should_ignore(e.path().strip_prefix(root).unwrap_or(e.path()), ignores)
+                || git_excludes
+                    .as_mut()
+                    .is_some_and(|walk| walk.excluded(e.path(), e.file_type().is_dir()));
+            if ignored {
+                record(&|walk| walk.ignored());
+            }
+            !ignored
+        });
+    while let Some(entry) = walker.next() {
         let entry = entry?;
-        if entry.file_type().is_file() && entry.file_name() == "moon.mod.json" {
-            let path = entry.path().to_path_buf();
-            match parse_moon_mod(&path) {
-                Ok(deps) => {
-                    if verbose {
-                        println!("Found: {}", path.display());
-                    }
-                    moon_mods.push(MoonModInfo { path, deps });
+        let is_dir = entry.file_type().is_dir();
+        record(&|walk| walk.entry(&rel(entry.path()), is_dir));
+        let flow = if is_dir {
+            // A workspace file replaces discovery below its directory
+            if let Some(workspace) = workspace::Workspace::read(entry.path())? {
+                if verbose {
+                    println!(
+                        "Workspace: {}",
+                        entry.path().join(workspace::FILE_NAME).display()
+                    );
                 }
-                Err(e) => {
-                    eprintln!("Warning: Failed to parse {}: {e}", path.display());
+                for manifest in workspace.manifests(entry.path()) {
+                    if manifest.is_file() {
+                        record(&|walk| walk.manifest(&rel(&manifest)));
+                        if found(&manifest, visit)?.is_break() {
+                            return Ok(());
+                        }
+                    } else {
+                        diagnostics::report(
+                            diagnostics::Code::WorkspaceMember,
+                            format!(
+                                "Workspace member {} has no moon.mod.json",
+                                manifest.parent().unwrap_or(&manifest).display()
+                            ),
+                        );
+                    }
                 }
+                walker.skip_current_dir();
             }
+            visit(Walked::Dir(entry.path()))?
+        } else if entry.file_type().is_file() && entry.file_name() == "moon.mod.json" {
+            record(&|walk| walk.manifest(&rel(entry.path())));
+            found(entry.path(), visit)?
+        } else {
+            ControlFlow::Continue(())
+        };
+        if flow.is_break() {
+            return Ok(());
         }
     }
 
-    Ok(moon_mods)
+    Ok(())
 }
 
 fn should_ignore(path: &Path, ignores: &[String]) -> bool {
@@ -726,35 +8571,127 @@ fn should_ignore(path: &Path, ignores: &[String]) -> bool {
     false
 }
 
-fn parse_moon_mod(path: &Path) -> Result<Vec<String>> {
+/// Manifests larger than this are rejected unread
+const MAX_MANIFEST_BYTES: u64 = 4 << 20;
+
+fn parse_moon_mod(path: &Path) -> Result<MoonModInfo> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len();
+    if size > MAX_MANIFEST_BYTES {
+        bail!(errors::Coded::new(
+            errors::Code::InvalidManifest,
+            format!(
+                "{} is {}, over the {} limit for manifests",
+                path.display(),
+                disk::format_size(size),
+                disk::format_size(MAX_MANIFEST_BYTES)
+            )
+        ));
+    }
     let content = std::fs::read_to_string(path)
         .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_moon_mod_content(&content, path)
+}
+
+/// Parse manifest text read from `path`
+fn parse_moon_mod_content(content: &str, path: &Path) -> Result<MoonModInfo> {
+    // Editors on Windows may save with a byte order mark
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    if content.trim().is_empty() {
+        bail!(errors::Coded::new(
+            errors::Code::InvalidManifest,
+            format!("{} is empty", path.display())
+        ));
+    }
+    let moon_mod: MoonMod = match serde_json::from_str(content) {
+        Ok(moon_mod) => moon_mod,
+        Err(e) => match jsonc::to_strict(content) {
+            Some(strict) if jsonc::lenient() => {
+                serde_json::from_str(&strict).map_err(|e| manifest_error(&strict, path, &e))?
+            }
+            Some(_) => bail!(errors::Coded::new(
+                errors::Code::InvalidManifest,
+                format!(
+                    "{}\n  = the file has comments, trailing commas or other JSON5 syntax; \
+                     read it with --lenient-parse, or make it strict with `moon-dst lint --fix`",
+                    manifest_error(content, path, &e)
+                )
+            )),
+            None => return Err(manifest_error(content, path, &e)),
+        },
+    };
+
+    let parse_deps = |declared: HashMap<String, serde_json::Value>| {
+        let mut deps: Vec<Dep> = declared
+            .into_iter()
+            .map(|(name, value)| Dep {
+                name,
+                version: declared_version(&value),
+                source: dep_source(&value),
+            })
+            .collect();
+        deps.sort_by(|a, b| a.name.cmp(&b.name));
+        deps
+    };
 
-    let moon_mod: MoonMod = serde_json::from_str(&content)
-        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(MoonModInfo {
+        path: path.to_path_buf(),
+        name: moon_mod.name,
+        deps: parse_deps(moon_mod.deps),
+        bin_deps: parse_deps(moon_mod.bin_deps),
+        moon_version: moon_mod.moon_version,
+    })
+}
 
-    let mut deps: Vec<String> = moon_mod.deps.keys().cloned().collect();
-    deps.sort();
-    Ok(deps)
+/// Parse error pointing at the offending line, with a hint for fields of
+/// the wrong type
+fn manifest_error(content: &str, path: &Path, error: &serde_json::Error) -> anyhow::Error {
+    let (line, column) = (error.line(), error.column());
+    let message = error.to_string();
+    let message = message
+        .strip_suffix(&format!(" at line {line} column {column}"))
+        .unwrap_or(&message);
+    let mut text = format!("{}:{line}:{column}: {message}", path.display());
+    if let Some(source) = content.lines().nth(line.saturating_sub(1)) {
+        // Long lines (minified manifests) are cut around the column
+        let start = column.saturating_sub(40);
+        let excerpt: String = source.chars().skip(start).take(80).collect();
+        let caret = column.saturating_sub(start + 1);
+        text.push_str(&format!("\n  | {excerpt}\n  | {}^", " ".repeat(caret)));
+    }
+    if error.classify() == serde_json::error::Category::Data {
+        text.push_str(
+            "\n  = `deps` and `bin-deps` must be objects; `name` and `moon-version` strings",
+        );
+    }
+    errors::Coded::new(errors::Code::InvalidManifest, text).into()
 }
 
-fn find_repo_root(moon_mod_path: &Path) -> PathBuf {
-    let dir = moon_mod_path.parent().unwrap_or(moon_mod_path);
+fn declared_version(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(version) => Some(version.clone()),
+        serde_json::Value::Object(fields) => fields
+            .get("version")
+            .and_then(|v| v.as_str())
+            .map(str::to_string),
+        _ => None,
+    }
+}
 
-    // Walk up looking for .git
-    let mut current = dir;
-    loop {
-        if current.join(".git").exists() {
-            return current.to_path_buf();
-        }
-        match current.parent() {
-            Some(parent) => current = parent,
-            None => break,
+fn dep_source(value: &serde_json::Value) -> DepSource {
+    let field = |key: &str| value.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    if let Some(path) = field("path") {
+        DepSource::Path(path)
+    } else if let Some(url) = field("git") {
+        DepSource::Git {
+            url,
+            branch: field("branch"),
+            rev: field("rev"),
         }
+    } else {
+        DepSource::Registry
     }
-
-    // No .git found, use the moon.mod.json's directory
-    dir.to_path_buf()
 }
 
 // =============================================================================
@@ -781,14 +8718,171 @@ mod tests {
         let temp_file = temp_dir.join("test_moon_mod.json");
         std::fs::write(&temp_file, json).unwrap();
 
-        let deps = parse_moon_mod(&temp_file).unwrap();
+        let moon_mod = parse_moon_mod(&temp_file).unwrap();
+        let deps = &moon_mod.deps;
         assert_eq!(deps.len(), 2);
-        assert!(deps.contains(&"moonbitlang/core".to_string()));
-        assert!(deps.contains(&"moonbitlang/x".to_string()));
+        assert_eq!(deps[0].name, "moonbitlang/core");
+        assert_eq!(deps[0].version.as_deref(), Some("0.1.0"));
+        assert_eq!(deps[1].name, "moonbitlang/x");
 
         std::fs::remove_file(temp_file).ok();
     }
 
+    /// Apply options for a dry run over an empty registry index
+    fn dry_run_options(observer: Arc<dyn observer::Observer>) -> ApplyOptions {
+        ApplyOptions {
+            skip_update: false,
+            repeat: 1,
+            until_stable: false,
+            packages: package_filter::Filter::default(),
+            write_justfile: false,
+            justfile_mode: WriteMode::Create,
+            justfile_template: justfile::Template::default(),
+            task_runner: taskfile::TaskRunner::Just,
+            verify: false,
+            min_age: None,
+            fix_yanked: false,
+            update_git_deps: false,
+            pins: Vec::new(),
+            registry: registry::Registry::with_index_dir(PathBuf::from("/nonexistent")),
+            plan: None,
+            cancel: cancel::CancellationToken::new(),
+            observer,
+            resolved: Resolved::default(),
+            dry_run: true,
+            verbose: false,
+        }
+    }
+
+    /// Events as `start`, `moon <args>`, `package <name>`, `finish`
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl observer::Observer for Recorder {
+        fn on_repo_start(&self, _repo: &Path) {
+            self.0.lock().unwrap().push("start".to_string());
+        }
+
+        fn on_command(&self, _dir: &Path, args: &[&str]) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("moon {}", args.join(" ")));
+        }
+
+        fn on_package_result(&self, _repo: &Path, package: &PackageRecord) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("package {}", package.name));
+        }
+
+        fn on_repo_finish(&self, _result: &RepoResult) {
+            self.0.lock().unwrap().push("finish".to_string());
+        }
+    }
+
+    #[test]
+    fn test_observer_sees_apply_events_in_order() {
+        let dir = std::env::temp_dir().join("moon_dst_observer_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("moon.mod.json");
+        std::fs::write(
+            &manifest,
+            r#"{"deps": {"acme/x": "0.1.0", "acme/y": "1.0.0"}}"#,
+        )
+        .unwrap();
+        let repos = vec![RepoInfo::local(
+            dir.clone(),
+            vec![parse_moon_mod(&manifest).unwrap()],
+        )];
+
+        let recorder = Arc::new(Recorder::default());
+        let options = dry_run_options(recorder.clone());
+        let graph = build_apply_graph(&repos, &dir, &options, &HashMap::new());
+        let mut collector = ResultCollector::new(
+            &repos,
+            &graph,
+            None,
+            options.observer.clone(),
+            None,
+            HashMap::new(),
+            options.cancel.clone(),
+        )
+        .unwrap();
+        let limits = scheduler::Limits {
+            total: 1,
+            lanes: Vec::new(),
+        };
+        run_apply_graph(&repos, &graph, &limits, false, &options, &mut collector);
+        let results = collector.finish();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "start",
+                "moon update",
+                "moon add acme/x",
+                "moon add acme/y",
+                "package acme/x",
+                "package acme/y",
+                "finish",
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// moon that succeeds without touching anything, recording its commands
+    #[derive(Debug, Default)]
+    struct IdleMoon(Mutex<Vec<String>>);
+
+    impl runner::Runner for IdleMoon {
+        fn output(&self, invocation: &runner::Invocation) -> std::io::Result<std::process::Output> {
+            self.0.lock().unwrap().push(invocation.args.join(" "));
+            Ok(std::process::Output {
+                status: std::process::ExitStatus::default(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_repeat_passes_stop_once_stable() {
+        let dir = std::env::temp_dir().join("moon_dst_repeat_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut moon_mods = Vec::new();
+        for (module, deps) in [
+            ("a", r#"{"acme/x": "1.0.0"}"#),
+            ("b", r#"{"acme/x": "0.9.0", "acme/y": "1.0.0"}"#),
+        ] {
+            let manifest = dir.join(module).join("moon.mod.json");
+            std::fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+            std::fs::write(&manifest, format!(r#"{{"deps": {deps}}}"#)).unwrap();
+            moon_mods.push(parse_moon_mod(&manifest).unwrap());
+        }
+        let moon = Arc::new(IdleMoon::default());
+        let mut repo = RepoInfo::local(dir.clone(), moon_mods);
+        repo.runner = moon.clone();
+        // The first pass moved acme/x in one of the two modules declaring it
+        std::fs::write(
+            dir.join("a/moon.mod.json"),
+            r#"{"deps": {"acme/x": "1.1.0"}}"#,
+        )
+        .unwrap();
+
+        let mut options = dry_run_options(Arc::new(observer::Observers(Vec::new())));
+        options.dry_run = false;
+        options.repeat = 5;
+        let attempts = repeat_passes(&repo, &["acme/x", "acme/y"], &options);
+        let added: Vec<&str> = attempts.iter().map(|a| a.dep.as_str()).collect();
+        assert_eq!(added, ["acme/x"]);
+        assert_eq!(*moon.0.lock().unwrap(), ["add acme/x"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_should_ignore_dotfiles() {
         let ignores = vec![];
@@ -797,4 +8891,124 @@ mod tests {
         assert!(should_ignore(Path::new("/foo/.hidden"), &ignores));
         assert!(!should_ignore(Path::new("/foo/src/main.rs"), &ignores));
     }
+
+    /// Deterministic xorshift generator for the randomized tests below
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    #[test]
+    fn test_parse_moon_mod_garbled_never_panics() {
+        let path = Path::new("/r/moon.mod.json");
+        let valid = r#"{"name": "me/app", "deps": {"a/x": "0.1.0", "a/y": {"version": "1.0.0"}, "a/z": {"path": "../z"}}, "bin-deps": {"a/t": "0.2.0"}}"#;
+        let junk = [
+            "{", "}", "[", "]", "\"", ",", ":", "\\", "\n", "null", "1e999", "\u{0}", "é",
+        ];
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            let mut chars: Vec<char> = valid.chars().collect();
+            match rng.below(3) {
+                0 => chars.truncate(rng.below(chars.len())),
+                1 => {
+                    let at = rng.below(chars.len());
+                    chars.splice(at..at, rng.pick(&junk).chars());
+                }
+                _ => {
+                    chars.remove(rng.below(chars.len()));
+                }
+            }
+            let text: String = chars.into_iter().collect();
+            if let Err(e) = parse_moon_mod_content(&text, path) {
+                let message = format!("{e:#}");
+                assert!(message.starts_with("/r/moon.mod.json"), "{message}");
+            }
+        }
+        let error = parse_moon_mod_content("{\"deps\": {\"a/x\" \"0.1.0\"}}", path).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "/r/moon.mod.json:1:17: expected `:`\n  | {\"deps\": {\"a/x\" \"0.1.0\"}}\n  |                 ^"
+        );
+
+        // Odd dependency shapes are kept, without a version where none fits
+        let odd = r#"{"deps": {"a/n": null, "a/i": 3, "a/l": ["1.0.0"], "a/o": {"version": 2}}}"#;
+        let info = parse_moon_mod_content(odd, path).unwrap();
+        assert_eq!(info.deps.len(), 4);
+        let error = parse_moon_mod_content(r#"{"deps": ["a/x"]}"#, path).unwrap_err();
+        assert!(format!("{error:#}").contains("must be objects"));
+        assert!(parse_moon_mod_content("\u{feff}{}", path).is_ok());
+
+        // Large but legal manifests parse; oversized files are refused
+        let deps: Vec<String> = (0..20_000)
+            .map(|i| format!("\"a/p{i}\": \"0.{i}.0\""))
+            .collect();
+        let big = format!("{{\"deps\": {{{}}}}}", deps.join(","));
+        assert_eq!(
+            parse_moon_mod_content(&big, path).unwrap().deps.len(),
+            20_000
+        );
+        let dir = std::env::temp_dir().join("moon_dst_huge_manifest_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("moon.mod.json");
+        std::fs::write(&file, " ".repeat(MAX_MANIFEST_BYTES as usize + 1)).unwrap();
+        assert!(parse_moon_mod(&file)
+            .unwrap_err()
+            .to_string()
+            .contains("limit"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_should_ignore_random_paths() {
+        let parts = [
+            "src",
+            ".git",
+            "node_modules",
+            "_build",
+            "a.b",
+            ".",
+            "..",
+            "x y",
+            "é",
+            "",
+        ];
+        let ignores = vec!["node_modules".to_string(), "_build".to_string()];
+        let mut rng = Rng(42);
+        for _ in 0..2000 {
+            let path: PathBuf = (0..rng.below(6)).map(|_| rng.pick(&parts)).collect();
+            let expected = path.components().any(|c| match c {
+                std::path::Component::Normal(name) => {
+                    let name = name.to_string_lossy();
+                    name.starts_with('.') || ignores.iter().any(|i| *i == name)
+                }
+                _ => false,
+            });
+            assert_eq!(
+                should_ignore(&path, &ignores),
+                expected,
+                "{}",
+                path.display()
+            );
+            // Ignoring more never un-ignores
+            if expected {
+                let more = [ignores.clone(), vec!["src".to_string()]].concat();
+                assert!(should_ignore(&path, &more));
+            }
+        }
+    }
 }
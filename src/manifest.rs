@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT
+//! Textual edits of `moon.mod.json` that keep the rest of the file as written
+//!
+//! Used to preview the manifest changes `moon add` would make in dry runs,
+//! to align internal package versions and to rename dependencies.
+
+/// Set the version of registry dependency `name` in manifest text
+///
+/// Returns `None` if the dependency is not declared with a plain version string.
+pub fn set_version(content: &str, name: &str, version: &str) -> Option<String> {
+    let key = format!("\"{name}\"");
+    let deps = content.find("\"deps\"").unwrap_or(0);
+    let key_end = deps + content[deps..].find(&key)? + key.len();
+    let rest = content[key_end..].trim_start().strip_prefix(':')?;
+    let value = rest.trim_start().strip_prefix('"')?;
+    let start = content.len() - value.len();
+    let end = start + value.find('"')?;
+
+    let mut out = String::with_capacity(content.len() + version.len());
+    out.push_str(&content[..start]);
+    out.push_str(version);
+    out.push_str(&content[end..]);
+    Some(out)
+}
+
+/// Rename dependency `old` to `new` in `deps` and `bin-deps`, keeping its
+/// specification
+///
+/// Returns `None` if `old` is not declared.
+pub fn rename_dep(content: &str, old: &str, new: &str) -> Option<String> {
+    let key = format!("\"{old}\"");
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+    let mut found = false;
+    while let Some(at) = rest.find(&key) {
+        let after = &rest[at + key.len()..];
+        out.push_str(&rest[..at]);
+        // Only keys: the name of the module itself is a value
+        if after.trim_start().starts_with(':') {
+            out.push_str(&format!("\"{new}\""));
+            found = true;
+        } else {
+            out.push_str(&key);
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    found.then_some(out)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_version() {
+        let manifest = "{\n  \"deps\": {\n    \"a/lib\" : \"0.1.0\",\n    \"b/lib\": { \"path\": \"../b\" }\n  }\n}\n";
+        assert_eq!(
+            set_version(manifest, "a/lib", "0.2.0").unwrap(),
+            manifest.replace("0.1.0", "0.2.0")
+        );
+        assert_eq!(set_version(manifest, "b/lib", "0.2.0"), None);
+        assert_eq!(set_version(manifest, "c/lib", "0.2.0"), None);
+
+        let renamed = rename_dep(manifest, "a/lib", "a/core").unwrap();
+        assert_eq!(renamed, manifest.replace("\"a/lib\"", "\"a/core\""));
+        assert_eq!(rename_dep("{\"name\": \"a/lib\"}", "a/lib", "a/core"), None);
+    }
+}
@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: MIT
+//! Templates for commit messages and pull request titles and bodies
+//!
+//! Templates use a small Handlebars-like syntax:
+//!
+//! ```text
+//! chore(deps): bump {{packages}} in {{repo}}
+//!
+//! {{#each updates}}- {{name}}: {{from}} -> {{to}}
+//! {{/each}}
+//! Run {{run_id}}
+//! ```
+//!
+//! Top-level variables are `repo` (directory name), `repo_path`, `run_id`,
+//! `count` and `packages` (comma-separated names). Inside
+//! `{{#each updates}}`, `name`, `from` and `to` describe one update.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::errors::{Code, Coded};
+
+/// Default commit subject and pull request title
+pub const DEFAULT_TITLE: &str = "chore(deps): update MoonBit dependencies";
+
+/// Default commit body and pull request body; the run ID ties the change
+/// to the run's journal entry and reports
+pub const DEFAULT_BODY: &str =
+    "{{#each updates}}- `{{name}}` {{from}} → {{to}}\n{{/each}}\nmoon-dst run {{run_id}}\n";
+
+/// One dependency version change
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Values available to templates
+#[derive(Debug, Clone)]
+pub struct Vars<'a> {
+    pub repo_path: &'a Path,
+    pub run_id: &'a str,
+    pub updates: &'a [Update],
+}
+
+impl Vars<'_> {
+    fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "repo" => self
+                .repo_path
+                .file_name()
+                .unwrap_or(self.repo_path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            "repo_path" => self.repo_path.display().to_string(),
+            "run_id" => self.run_id.to_string(),
+            "count" => self.updates.len().to_string(),
+            "packages" => self
+                .updates
+                .iter()
+                .map(|u| u.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => return None,
+        })
+    }
+}
+
+impl Update {
+    fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "name" => self.name.clone(),
+            "from" => self.from.clone(),
+            "to" => self.to.clone(),
+            _ => return None,
+        })
+    }
+}
+
+/// Render a template, failing on unknown variables and unbalanced blocks
+pub fn render(template: &str, vars: &Vars) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            bail!(Coded::new(Code::InvalidConfig, "Unclosed {{ in template"));
+        };
+        let tag = after[..end].trim();
+        rest = &after[end + 2..];
+
+        if tag == "#each updates" {
+            let Some(close) = rest.find("{{/each}}") else {
+                bail!(Coded::new(
+                    Code::InvalidConfig,
+                    "{{#each updates}} without {{/each}}"
+                ));
+            };
+            let block = &rest[..close];
+            for update in vars.updates {
+                out.push_str(&render_update(block, update, vars)?);
+            }
+            rest = &rest[close + "{{/each}}".len()..];
+        } else if let Some(value) = vars.get(tag) {
+            out.push_str(&value);
+        } else {
+            bail!(Coded::new(
+                Code::InvalidConfig,
+                format!("Unknown template variable `{tag}`")
+            ));
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Render the body of an `{{#each updates}}` block for one update
+fn render_update(block: &str, update: &Update, vars: &Vars) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = block;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            bail!(Coded::new(Code::InvalidConfig, "Unclosed {{ in template"));
+        };
+        let tag = after[..end].trim();
+        match update.get(tag).or_else(|| vars.get(tag)) {
+            Some(value) => out.push_str(&value),
+            None => bail!(Coded::new(
+                Code::InvalidConfig,
+                format!("Unknown template variable `{tag}` in {{{{#each updates}}}}")
+            )),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// A `[[pr.repos]]` entry: templates for repos matching `path`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoTemplates {
+    /// Glob matched against the repo path relative to the root
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// Title and body templates for a repo at `rel`: the first matching entry
+/// that sets each one, or the defaults
+pub fn select<'a>(
+    rel: &Path,
+    repos: &'a [RepoTemplates],
+    title: &'a str,
+    body: &'a str,
+) -> (&'a str, &'a str) {
+    let matching: Vec<&RepoTemplates> = repos
+        .iter()
+        .filter(|entry| {
+            globset::Glob::new(&entry.path).is_ok_and(|glob| glob.compile_matcher().is_match(rel))
+        })
+        .collect();
+    (
+        matching
+            .iter()
+            .find_map(|entry| entry.title.as_deref())
+            .unwrap_or(title),
+        matching
+            .iter()
+            .find_map(|entry| entry.body.as_deref())
+            .unwrap_or(body),
+    )
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn updates() -> Vec<Update> {
+        vec![
+            Update {
+                name: "a/x".to_string(),
+                from: "0.1.0".to_string(),
+                to: "0.2.0".to_string(),
+            },
+            Update {
+                name: "a/y".to_string(),
+                from: "1.0.0".to_string(),
+                to: "1.1.0".to_string(),
+            },
+        ]
+    }
+
+    fn vars(updates: &[Update]) -> Vars<'_> {
+        Vars {
+            repo_path: Path::new("/work/app"),
+            run_id: "1700000000",
+            updates,
+        }
+    }
+
+    #[test]
+    fn test_render_variables() {
+        let updates = updates();
+        assert_eq!(
+            render(
+                "fix(deps): bump {{ packages }} in {{repo}}",
+                &vars(&updates)
+            )
+            .unwrap(),
+            "fix(deps): bump a/x, a/y in app"
+        );
+        assert_eq!(
+            render(DEFAULT_BODY, &vars(&updates)).unwrap(),
+            "- `a/x` 0.1.0 → 0.2.0\n- `a/y` 1.0.0 → 1.1.0\n\nmoon-dst run 1700000000\n"
+        );
+    }
+
+    #[test]
+    fn test_render_each_block() {
+        let updates = updates();
+        assert_eq!(
+            render(
+                "{{#each updates}}{{repo}}:{{name}} {{/each}}({{count}}, {{run_id}})",
+                &vars(&updates)
+            )
+            .unwrap(),
+            "app:a/x app:a/y (2, 1700000000)"
+        );
+    }
+
+    #[test]
+    fn test_render_rejects_unknown_and_unclosed() {
+        let updates = updates();
+        assert!(render("{{nope}}", &vars(&updates)).is_err());
+        assert!(render("{{#each updates}}{{name}}", &vars(&updates)).is_err());
+    }
+
+    #[test]
+    fn test_select_templates_by_repo_path() {
+        let repos = vec![RepoTemplates {
+            path: "services/*".to_string(),
+            title: Some("build(deps): {{packages}}".to_string()),
+            body: None,
+        }];
+        assert_eq!(
+            select(
+                Path::new("services/api"),
+                &repos,
+                DEFAULT_TITLE,
+                DEFAULT_BODY
+            ),
+            ("build(deps): {{packages}}", DEFAULT_BODY)
+        );
+        assert_eq!(
+            select(Path::new("libs/core"), &repos, DEFAULT_TITLE, DEFAULT_BODY).0,
+            DEFAULT_TITLE
+        );
+    }
+}
@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: MIT
+//! Hooks into a running apply
+//!
+//! An [`Observer`] is told when a repo starts, which moon commands run, how
+//! each package ended and each repo's final result. Every method has an
+//! empty default, so observers implement only what they need; they are
+//! called from worker threads. The CLI's own output goes through the same
+//! hooks: [`CommandLog`] echoes commands for `--verbose` and `--dry-run`,
+//! and [`ProgressLine`] keeps the live summary line, with the estimated
+//! time left, on a terminal.
+//! [`Observers`] fans events out to several of them.
+//!
+//! moon-dst is a binary without a library target, so the trait is internal:
+//! new hooks are added here, next to the CLI's own observers.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::{eta, PackageRecord, RepoResult};
+
+pub trait Observer: Send + Sync {
+    /// The first step of the repo at `repo` starts
+    fn on_repo_start(&self, _repo: &Path) {}
+
+    /// `moon <args>` is about to run in `dir` (or would, in a dry run)
+    fn on_command(&self, _dir: &Path, _args: &[&str]) {}
+
+    /// A package of `repo` has its final status
+    fn on_package_result(&self, _repo: &Path, _package: &PackageRecord) {}
+
+    /// The result of a repo is final
+    fn on_repo_finish(&self, _result: &RepoResult) {}
+}
+
+/// Shared observers, e.g. a progress line cleared by its owner afterwards
+impl<T: Observer + ?Sized> Observer for Arc<T> {
+    fn on_repo_start(&self, repo: &Path) {
+        (**self).on_repo_start(repo);
+    }
+
+    fn on_command(&self, dir: &Path, args: &[&str]) {
+        (**self).on_command(dir, args);
+    }
+
+    fn on_package_result(&self, repo: &Path, package: &PackageRecord) {
+        (**self).on_package_result(repo, package);
+    }
+
+    fn on_repo_finish(&self, result: &RepoResult) {
+        (**self).on_repo_finish(result);
+    }
+}
+
+/// Prints each command as `[dir] moon <args>`
+pub struct CommandLog {
+    /// Print only when set (`--verbose` or `--dry-run`)
+    pub enabled: bool,
+}
+
+impl Observer for CommandLog {
+    fn on_command(&self, dir: &Path, args: &[&str]) {
+        if self.enabled {
+            println!("[{}] moon {}", dir.display(), args.join(" "));
+        }
+    }
+}
+
+/// `N/M repos done, K failed, about T left` on stderr, rewritten in place
+pub struct ProgressLine {
+    total: usize,
+    /// Finished and failed repos
+    counts: Mutex<(usize, usize)>,
+    estimate: Arc<eta::Estimate>,
+}
+
+impl ProgressLine {
+    pub fn new(total: usize, estimate: Arc<eta::Estimate>) -> Self {
+        Self {
+            total,
+            counts: Mutex::new((0, 0)),
+            estimate,
+        }
+    }
+
+    /// Erase the line before the final report
+    pub fn clear(&self) {
+        eprint!("\r\x1b[K");
+        let _ = std::io::stderr().flush();
+    }
+}
+
+impl Observer for ProgressLine {
+    fn on_repo_finish(&self, result: &RepoResult) {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        counts.0 += 1;
+        if !result.success {
+            counts.1 += 1;
+        }
+        let left = match self.estimate.remaining() {
+            Some(left) if counts.0 < self.total => format!(", about {} left", eta::format(left)),
+            _ => String::new(),
+        };
+        eprint!(
+            "\r\x1b[K{}/{} repos done, {} failed{left}",
+            counts.0, self.total, counts.1
+        );
+    }
+}
+
+impl Observer for eta::Estimate {
+    fn on_repo_finish(&self, result: &RepoResult) {
+        self.finish(&result.repo_root, result.duration);
+    }
+}
+
+/// Passes every event to each observer in turn
+#[derive(Default)]
+pub struct Observers(pub Vec<Box<dyn Observer>>);
+
+impl Observer for Observers {
+    fn on_repo_start(&self, repo: &Path) {
+        self.0.iter().for_each(|o| o.on_repo_start(repo));
+    }
+
+    fn on_command(&self, dir: &Path, args: &[&str]) {
+        self.0.iter().for_each(|o| o.on_command(dir, args));
+    }
+
+    fn on_package_result(&self, repo: &Path, package: &PackageRecord) {
+        self.0
+            .iter()
+            .for_each(|o| o.on_package_result(repo, package));
+    }
+
+    fn on_repo_finish(&self, result: &RepoResult) {
+        self.0.iter().for_each(|o| o.on_repo_finish(result));
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Observer for Recorder {
+        fn on_command(&self, dir: &Path, args: &[&str]) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", dir.display(), args.join(" ")));
+        }
+    }
+
+    #[test]
+    fn test_observers_fan_out() {
+        let recorder = Arc::new(Recorder::default());
+        let observers = Observers(vec![
+            Box::new(CommandLog { enabled: false }),
+            Box::new(recorder.clone()),
+        ]);
+        observers.on_repo_start(Path::new("/r"));
+        observers.on_command(Path::new("/r"), &["add", "a/x"]);
+        assert_eq!(*recorder.0.lock().unwrap(), ["/r add a/x"]);
+    }
+}
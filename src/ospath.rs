@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: MIT
+//! Paths in output, including ones that are not valid UTF-8
+//!
+//! `Path::display` replaces bytes that are not UTF-8 with U+FFFD, so two
+//! different directories can print the same and a path read back from JSON
+//! no longer names the directory it came from. Here UTF-8 paths, non-ASCII
+//! ones included, are printed unchanged; any other path has its invalid
+//! bytes percent-encoded (`%FF`), with `%` itself written as `%25`.
+//!
+//! [`decode`] reverses this: text whose escapes decode to bytes that are
+//! not UTF-8 must have come from [`encode`]. A UTF-8 path that literally
+//! contains such an escape is the one case that reads back differently.
+//!
+//! Serialized `PathBuf` fields use this module through
+//! `#[serde(with = "crate::ospath")]` (or [`option`] and [`keys`]).
+
+use serde::{Deserialize, Deserializer, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// `path` as text, percent-encoding bytes that are not UTF-8
+pub fn encode(path: &Path) -> String {
+    if let Some(text) = path.to_str() {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        out.push_str(&chunk.valid().replace('%', "%25"));
+        for byte in chunk.invalid() {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+/// The path `text` was made from by [`encode`]
+pub fn decode(text: &str) -> PathBuf {
+    if !text.contains('%') {
+        return PathBuf::from(text);
+    }
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            // from_str_radix alone would take a sign, as in `%+1`
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    if std::str::from_utf8(&out).is_ok() {
+        return PathBuf::from(text);
+    }
+    from_bytes(out).unwrap_or_else(|| PathBuf::from(text))
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn from_bytes(_bytes: Vec<u8>) -> Option<PathBuf> {
+    None
+}
+
+/// Drop-in for `Path::display` that prints [`encode`]d paths
+pub fn display(path: &Path) -> Display<'_> {
+    Display(path)
+}
+
+pub struct Display<'a>(&'a Path);
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.to_str() {
+            Some(text) => f.write_str(text),
+            None => f.write_str(&encode(self.0)),
+        }
+    }
+}
+
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode(path))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    Ok(decode(&String::deserialize(deserializer)?))
+}
+
+/// For `Option<PathBuf>` fields
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match path {
+            Some(path) => serializer.serialize_some(&encode(path)),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// For maps keyed by path
+pub mod keys {
+    use super::*;
+    use serde::Serialize;
+
+    pub fn serialize<S: Serializer, V: Serialize>(
+        map: &BTreeMap<PathBuf, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(path, value)| (encode(path), value)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<PathBuf, V>, D::Error> {
+        let map = BTreeMap::<String, V>::deserialize(deserializer)?;
+        Ok(map
+            .into_iter()
+            .map(|(text, value)| (decode(&text), value))
+            .collect())
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unicode_paths_are_unchanged() {
+        let plain = Path::new("/work/日本語/50%");
+        assert_eq!(encode(plain), "/work/日本語/50%");
+        assert_eq!(decode("/work/日本語/50%"), plain);
+        assert_eq!(decode("/work/a%20b"), Path::new("/work/a%20b"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_unicode_paths_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let raw = Path::new(std::ffi::OsStr::from_bytes(b"/work/bad\xff/50%"));
+        assert_eq!(encode(raw), "/work/bad%FF/50%25");
+        assert_eq!(display(raw).to_string(), "/work/bad%FF/50%25");
+        assert_eq!(decode(&encode(raw)), raw);
+        let json = serde_json::to_string(&encode(raw)).unwrap();
+        let back: String = serde_json::from_str(&json).unwrap();
+        assert_eq!(decode(&back), raw);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_decode_only_two_hex_digit_escapes() {
+        use std::os::unix::ffi::OsStrExt;
+        let signed = Path::new(std::ffi::OsStr::from_bytes(b"/work/bad\xff/%+1"));
+        assert_eq!(decode("/work/bad%FF/%+1"), signed);
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT
+//! Flat dependency rows with declared vs. latest registry versions
+
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::registry::{self, Registry};
+use crate::RepoInfo;
+
+/// Column names matching [`DepRow::fields`]
+pub const HEADER: &[&str] = &["repo", "module", "dep", "declared", "latest", "outdated"];
+
+/// One dependency of one module
+#[derive(Serialize, Debug, Clone)]
+pub struct DepRow {
+    pub repo: String,
+    pub module: String,
+    pub dep: String,
+    pub declared: Option<String>,
+    pub latest: Option<String>,
+    pub outdated: bool,
+    /// moon.mod.json declaring the dependency
+    #[serde(skip)]
+    pub manifest: PathBuf,
+}
+
+impl DepRow {
+    pub fn fields(&self) -> Vec<String> {
+        vec![
+            self.repo.clone(),
+            self.module.clone(),
+            self.dep.clone(),
+            self.declared.clone().unwrap_or_default(),
+            self.latest.clone().unwrap_or_default(),
+            self.outdated.to_string(),
+        ]
+    }
+}
+
+/// Collect one row per declared dependency across all repos
+pub fn collect_rows(repos: &[RepoInfo], registry: &Registry) -> Vec<DepRow> {
+    let mut rows = Vec::new();
+    for repo in repos {
+        for moon_mod in &repo.moon_mods {
+            let module = moon_mod.name.clone().unwrap_or_else(|| {
+                moon_mod
+                    .path
+                    .strip_prefix(&repo.root)
+                    .unwrap_or(&moon_mod.path)
+                    .display()
+                    .to_string()
+            });
+            for dep in &moon_mod.deps {
+                // Path and git dependencies are not versioned by the registry
+                let latest = dep
+                    .is_registry()
+                    .then(|| registry.latest(&dep.name))
+                    .flatten()
+                    .map(|r| r.version);
+                let outdated = match (&dep.version, &latest) {
+                    (Some(declared), Some(latest)) => registry::is_outdated(declared, latest),
+                    _ => false,
+                };
+                rows.push(DepRow {
+                    repo: crate::ospath::display(&repo.root).to_string(),
+                    module: module.clone(),
+                    dep: dep.name.clone(),
+                    declared: dep.version.clone(),
+                    latest,
+                    outdated,
+                    manifest: moon_mod.path.clone(),
+                });
+            }
+        }
+    }
+    rows
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output, parse_moon_mod};
+
+    #[test]
+    fn test_collect_rows_and_render() {
+        let dir = std::env::temp_dir().join("moon_dst_outdated_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let index = dir.join("index/user/acme");
+        std::fs::create_dir_all(&index).unwrap();
+        for (package, latest) in [("old", "2.0.0"), ("new", "1.0.0")] {
+            std::fs::write(
+                index.join(format!("{package}.index")),
+                format!("{{\"version\":\"1.0.0\"}}\n{{\"version\":\"{latest}\"}}\n"),
+            )
+            .unwrap();
+        }
+        let manifest = dir.join("app/moon.mod.json");
+        std::fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+        std::fs::write(
+            &manifest,
+            r#"{"name": "me/app", "deps": {"acme/old": "1.0.0", "acme/new": "1.0.0", "acme/unknown": "0.1.0", "me/lib": {"path": "../lib"}}}"#,
+        )
+        .unwrap();
+        let repos = vec![RepoInfo::local(
+            dir.join("app"),
+            vec![parse_moon_mod(&manifest).unwrap()],
+        )];
+
+        let rows = collect_rows(&repos, &Registry::with_index_dir(dir.join("index")));
+        let summary: Vec<(&str, Option<&str>, bool)> = rows
+            .iter()
+            .map(|r| (r.dep.as_str(), r.latest.as_deref(), r.outdated))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("acme/new", Some("1.0.0"), false),
+                ("acme/old", Some("2.0.0"), true),
+                ("acme/unknown", None, false),
+                ("me/lib", None, false),
+            ]
+        );
+        assert!(rows
+            .iter()
+            .all(|r| r.module == "me/app" && r.manifest == manifest));
+
+        let fields: Vec<Vec<String>> = rows[1..3]
+            .iter()
+            .map(|r| DepRow {
+                repo: "app".to_string(),
+                ..r.clone()
+            })
+            .map(|r| r.fields())
+            .collect();
+        assert_eq!(
+            output::render_delimited(',', HEADER, &fields),
+            "repo,module,dep,declared,latest,outdated\napp,me/app,acme/old,1.0.0,2.0.0,true\napp,me/app,acme/unknown,0.1.0,,false\n"
+        );
+        assert_eq!(
+            output::render_delimited('\t', HEADER, &fields[..1]),
+            "repo\tmodule\tdep\tdeclared\tlatest\toutdated\napp\tme/app\tacme/old\t1.0.0\t2.0.0\ttrue\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: MIT
+//! Output formats shared by reporting subcommands
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::errors::{Code, Coded};
+
+/// Root that JSON paths are relative to under `--deterministic`
+static RELATIVE_TO: OnceLock<String> = OnceLock::new();
+
+/// Whether text output is plain (`--plain`)
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Output format for reporting subcommands
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON document
+    Json,
+    /// Comma-separated rows with a header line
+    Csv,
+    /// Tab-separated rows with a header line
+    Tsv,
+    /// SARIF 2.1.0 log (findings only)
+    Sarif,
+    /// GitHub Actions annotations plus a job step summary
+    Github,
+    /// ctags file with a tag per module and package
+    Ctags,
+    /// Workspace folders and package dirs for language servers (JSON)
+    LspWorkspace,
+    /// Aligned table, one row per repo, then the failures
+    Table,
+}
+
+impl OutputFormat {
+    /// Field delimiter for tabular formats
+    pub fn delimiter(self) -> Option<char> {
+        match self {
+            OutputFormat::Csv => Some(','),
+            OutputFormat::Tsv => Some('\t'),
+            _ => None,
+        }
+    }
+
+    /// Fail with a clear error if the command does not support this format
+    pub fn check_supported(self, command: &str, supported: &[OutputFormat]) -> Result<()> {
+        if supported.contains(&self) {
+            return Ok(());
+        }
+        let name = |f: OutputFormat| {
+            f.to_possible_value()
+                .map(|v| v.get_name().to_string())
+                .unwrap_or_default()
+        };
+        let names: Vec<String> = supported.iter().map(|&f| name(f)).collect();
+        bail!(Coded::new(
+            Code::InvalidValue,
+            format!(
+                "`{command}` does not support --format {} (supported: {})",
+                name(self),
+                names.join(", ")
+            )
+        ))
+    }
+}
+
+/// Make text output screen-reader friendly from now on: no color, no
+/// progress line or decorated headings, PASS/FAIL words and one fact per
+/// line
+pub fn set_plain() {
+    PLAIN.store(true, Ordering::Relaxed);
+}
+
+pub fn plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Word for a step that passed or failed
+pub fn status(success: bool) -> &'static str {
+    match (plain(), success) {
+        (false, true) => "OK",
+        (false, false) => "FAILED",
+        (true, true) => "PASS",
+        (true, false) => "FAIL",
+    }
+}
+
+/// `label: a, b, c` as one line, or as a `label: a` line per item in plain
+/// output
+pub fn facts(label: &str, items: &[String]) -> Vec<String> {
+    facts_in(plain(), label, items)
+}
+
+fn facts_in(plain: bool, label: &str, items: &[String]) -> Vec<String> {
+    if plain {
+        items
+            .iter()
+            .map(|item| format!("{label}: {item}"))
+            .collect()
+    } else {
+        vec![format!("{label}: {}", items.join(", "))]
+    }
+}
+
+/// A list entry: `- text`, or `kind: text` in plain output
+pub fn item(kind: &str, text: &str) -> String {
+    match plain() {
+        true => format!("{kind}: {text}"),
+        false => format!("- {text}"),
+    }
+}
+
+/// A version change: `old -> new`, or `from old to new` in plain output
+pub fn change(old: &str, new: &str) -> String {
+    match plain() {
+        true => format!("from {old} to {new}"),
+        false => format!("{old} -> {new}"),
+    }
+}
+
+/// Print paths below `root` relative to it in JSON output from now on
+pub fn relative_paths(root: &Path) {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let _ = RELATIVE_TO.set(crate::ospath::encode(&root));
+}
+
+/// `value` as pretty-printed JSON
+///
+/// Diagnostics reported so far are added to an object as `diagnostics`.
+/// With [`relative_paths`] in effect, every mention of the root in string
+/// values (paths, error messages) is made relative, and object keys come
+/// out sorted.
+pub fn json<T: Serialize>(value: &T) -> Result<String> {
+    if serde_json::to_value(value)?.is_object() {
+        let diagnostics = crate::diagnostics::take();
+        return render(&Report { value, diagnostics });
+    }
+    render(value)
+}
+
+#[derive(Serialize)]
+struct Report<'a, T> {
+    #[serde(flatten)]
+    value: &'a T,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diagnostics: Vec<crate::diagnostics::Diagnostic>,
+}
+
+fn render<T: Serialize>(value: &T) -> Result<String> {
+    let Some(root) = RELATIVE_TO.get() else {
+        return Ok(serde_json::to_string_pretty(value)?);
+    };
+    let mut value = serde_json::to_value(value)?;
+    relativize(&mut value, root);
+    Ok(serde_json::to_string_pretty(&value)?)
+}
+
+fn relativize(value: &mut Value, root: &str) {
+    match value {
+        Value::String(text) if text == root => *text = ".".to_string(),
+        Value::String(text) => *text = text.replace(&format!("{root}/"), ""),
+        Value::Array(items) => items.iter_mut().for_each(|v| relativize(v, root)),
+        Value::Object(map) => map.values_mut().for_each(|v| relativize(v, root)),
+        _ => {}
+    }
+}
+
+/// Render rows as delimiter-separated values with a header line
+pub fn render_delimited(delimiter: char, header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let fields: Vec<String> = row.iter().map(|f| escape_field(f, delimiter)).collect();
+        out.push_str(&fields.join(&delimiter.to_string()));
+        out.push('\n');
+    }
+    out
+}
+
+/// Render rows as columns padded to their widest cell, under a header
+pub fn render_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{cell:<width$}"))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn escape_field(field: &str, delimiter: char) -> String {
+    if delimiter == '\t' {
+        // TSV has no quoting; flatten characters that would break the row
+        return field.replace(['\t', '\n', '\r'], " ");
+    }
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_csv_quotes_fields() {
+        let rows = vec![vec!["a,b".to_string(), "say \"hi\"".to_string()]];
+        assert_eq!(
+            render_delimited(',', &["x", "y"], &rows),
+            "x,y\n\"a,b\",\"say \"\"hi\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_render_tsv_replaces_tabs() {
+        let rows = vec![vec!["a\tb".to_string(), "c".to_string()]];
+        assert_eq!(render_delimited('\t', &["x", "y"], &rows), "x\ty\na b\tc\n");
+    }
+
+    #[test]
+    fn test_render_table_trims_trailing_space() {
+        let rows = vec![vec!["app".to_string(), "OK".to_string(), String::new()]];
+        assert_eq!(
+            render_table(&["Repository", "Status", "Notes"], &rows),
+            "Repository  Status  Notes\napp         OK\n"
+        );
+    }
+
+    #[test]
+    fn test_relativize_paths_under_root() {
+        let mut value = serde_json::json!({
+            "root": "/work",
+            "repos": ["/work/app", "/workshop"],
+            "error": "Failed to read /work/app/moon.mod.json",
+        });
+        relativize(&mut value, "/work");
+        assert_eq!(value["root"], ".");
+        assert_eq!(value["repos"], serde_json::json!(["app", "/workshop"]));
+        assert_eq!(value["error"], "Failed to read app/moon.mod.json");
+    }
+
+    #[test]
+    fn test_facts_in_one_or_many_lines() {
+        let items = ["3 repos".to_string(), "1 failed".to_string()];
+        assert_eq!(
+            facts_in(false, "Summary", &items),
+            ["Summary: 3 repos, 1 failed"]
+        );
+        assert_eq!(
+            facts_in(true, "Summary", &items),
+            ["Summary: 3 repos", "Summary: 1 failed"]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+// SPDX-License-Identifier: MIT
+//! Which team owns each repo
+//!
+//! Owners of a module come from, in order:
+//!
+//! 1. the first `[[owners.repos]]` entry whose `path` glob matches the repo
+//!    (relative to `--root`),
+//! 2. the repo's own CODEOWNERS (`CODEOWNERS`, `.github/CODEOWNERS` or
+//!    `docs/CODEOWNERS`) for the module's manifest,
+//! 3. a CODEOWNERS in the `--root` directory for the manifest's path below
+//!    it.
+//!
+//! A repo is owned by the owners of its modules. Apply reports group
+//! failures by owner, `[owners] emails` sends each owner the part of the
+//! report about their repos, and `--owner` selects repos by owner.
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Where CODEOWNERS is looked up, relative to a repo, in GitHub's order
+const LOCATIONS: &[&str] = &["CODEOWNERS", ".github/CODEOWNERS", "docs/CODEOWNERS"];
+
+/// `[[owners.repos]]` entry: owners of the repos matching `path`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoOwners {
+    /// Glob over repo paths relative to `--root`
+    pub path: String,
+    pub owners: Vec<String>,
+}
+
+/// Rules of a CODEOWNERS file
+#[derive(Debug, Default)]
+pub struct CodeOwners {
+    rules: Vec<(GlobMatcher, Vec<String>)>,
+}
+
+impl CodeOwners {
+    /// Parse CODEOWNERS text; lines with invalid patterns are skipped
+    pub fn parse(text: &str) -> Self {
+        let rules = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let pattern = fields.next()?;
+                let owners = fields.map(str::to_string).collect();
+                Some((matcher(pattern)?, owners))
+            })
+            .collect();
+        CodeOwners { rules }
+    }
+
+    /// The first CODEOWNERS file of `dir`, if any
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        for location in LOCATIONS {
+            let path = dir.join(location);
+            if path.is_file() {
+                let text = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                return Ok(Some(Self::parse(&text)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Owners of `rel`: the last matching rule wins, and a rule without
+    /// owners leaves the path unowned
+    pub fn owners(&self, rel: &Path) -> Option<&[String]> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.is_match(rel))
+            .map(|(_, owners)| owners.as_slice())
+            .filter(|owners| !owners.is_empty())
+    }
+}
+
+/// Matcher for a gitignore-style CODEOWNERS pattern
+///
+/// A pattern with a slash other than a trailing one is anchored at the
+/// root; others match at any depth. A matching directory owns everything
+/// below it.
+fn matcher(pattern: &str) -> Option<GlobMatcher> {
+    let dir_only = pattern.ends_with('/');
+    let trimmed = pattern.trim_end_matches('/');
+    let anchored = trimmed.contains('/');
+    let trimmed = trimmed.trim_start_matches('/');
+    let mut glob = if anchored || trimmed.starts_with("**") {
+        trimmed.to_string()
+    } else {
+        format!("**/{trimmed}")
+    };
+    if trimmed.is_empty() || trimmed == "*" && !anchored {
+        glob = "**".to_string();
+    } else if dir_only {
+        glob.push_str("/**");
+    } else {
+        glob = format!("{{{glob},{glob}/**}}");
+    }
+    GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .build()
+        .ok()
+        .map(|g| g.compile_matcher())
+}
+
+/// Owners of the repo at `repo_root` (`rel` below `--root`) with modules at
+/// `manifests`, deduplicated in order of appearance
+pub fn resolve(
+    rel: &Path,
+    repo_root: &Path,
+    manifests: &[&Path],
+    configured: &[RepoOwners],
+    repo_file: Option<&CodeOwners>,
+    root_file: Option<(&Path, &CodeOwners)>,
+) -> Vec<String> {
+    if let Some(entry) = configured.iter().find(|entry| {
+        globset::Glob::new(&entry.path).is_ok_and(|glob| glob.compile_matcher().is_match(rel))
+    }) {
+        return entry.owners.clone();
+    }
+    let mut owners: Vec<String> = Vec::new();
+    for manifest in manifests {
+        let from_repo = repo_file
+            .zip(manifest.strip_prefix(repo_root).ok())
+            .and_then(|(file, path)| file.owners(path));
+        let from_root =
+            || root_file.and_then(|(root, file)| file.owners(manifest.strip_prefix(root).ok()?));
+        for owner in from_repo.or_else(from_root).unwrap_or_default() {
+            if !owners.contains(owner) {
+                owners.push(owner.clone());
+            }
+        }
+    }
+    owners
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codeowners() -> CodeOwners {
+        CodeOwners::parse(
+            "# fleet owners\n\
+             *              @acme/platform\n\
+             services/      @acme/services\n\
+             /libs/core/    @acme/core  # core team\n\
+             moon.mod.json  @acme/deps\n\
+             libs/core/vendor/\n",
+        )
+    }
+
+    fn owners(file: &CodeOwners, path: &str) -> Option<String> {
+        file.owners(Path::new(path)).map(|o| o.join(" "))
+    }
+
+    #[test]
+    fn test_codeowners_last_match_wins() {
+        let file = codeowners();
+        assert_eq!(
+            owners(&file, "README.md").as_deref(),
+            Some("@acme/platform")
+        );
+        assert_eq!(
+            owners(&file, "services/api/src/lib.mbt").as_deref(),
+            Some("@acme/services")
+        );
+        assert_eq!(
+            owners(&file, "libs/core/src/a.mbt").as_deref(),
+            Some("@acme/core")
+        );
+        assert_eq!(
+            owners(&file, "libs/core/moon.mod.json").as_deref(),
+            Some("@acme/deps")
+        );
+    }
+
+    #[test]
+    fn test_codeowners_entry_without_owners_unsets() {
+        assert_eq!(owners(&codeowners(), "libs/core/vendor/x/a.mbt"), None);
+    }
+
+    #[test]
+    fn test_codeowners_leading_slash_anchors() {
+        assert_eq!(
+            owners(&codeowners(), "other/libs/core/a.mbt").as_deref(),
+            Some("@acme/platform")
+        );
+    }
+
+    #[test]
+    fn test_resolve_from_config_or_codeowners() {
+        let configured = vec![RepoOwners {
+            path: "apps/*".to_string(),
+            owners: vec!["@acme/apps".to_string()],
+        }];
+        let root = Path::new("/fleet");
+        let manifest = Path::new("/fleet/services/api/moon.mod.json");
+        assert_eq!(
+            resolve(
+                Path::new("apps/web"),
+                Path::new("/fleet/apps/web"),
+                &[],
+                &configured,
+                None,
+                None
+            ),
+            ["@acme/apps"]
+        );
+        assert_eq!(
+            resolve(
+                Path::new("services/api"),
+                Path::new("/fleet/services/api"),
+                &[manifest],
+                &configured,
+                None,
+                Some((root, &CodeOwners::parse("/services/ @acme/services")))
+            ),
+            ["@acme/services"]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+// SPDX-License-Identifier: MIT
+//! Which dependencies `--package` selects
+//!
+//! `--package` used to select every dependency whose name contained the
+//! pattern, and a typo selected nothing without a word: the run went
+//! through the whole fleet and updated no package. `--package-match` (or
+//! `package-match` under `[apply]`) now picks how a pattern matches:
+//! `substring` (the default, as before), `exact`, `glob` (`acme/*`) or
+//! `regex` (unanchored, like grep). After discovery, a pattern that matched
+//! no dependency in any repo is reported as `unmatched-package`, with the
+//! names a few edits away from it.
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use globset::{Glob, GlobMatcher};
+use regex_automata::meta::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{Code, Coded};
+
+/// How a `--package` pattern matches a dependency name
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// The name contains the pattern
+    #[default]
+    Substring,
+    /// The name is the pattern
+    Exact,
+    /// The name matches the glob, e.g. `acme/*`
+    Glob,
+    /// The regular expression matches somewhere in the name
+    Regex,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Substring,
+    Exact,
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+/// The `--package` patterns of a run; without any it selects everything
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    patterns: Vec<(String, Matcher)>,
+}
+
+/// A pattern that matched no dependency
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unmatched {
+    pub pattern: String,
+    /// Dependency names close to the pattern, closest first
+    pub close: Vec<String>,
+}
+
+impl std::fmt::Display for Unmatched {
+    /// `--package acme/jsn matched no dependency; did you mean acme/json?`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "--package {} matched no dependency", self.pattern)?;
+        if !self.close.is_empty() {
+            write!(f, "; did you mean {}?", self.close.join(" or "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Suggestions listed for one pattern
+const MAX_CLOSE: usize = 3;
+
+impl Filter {
+    pub fn new(patterns: &[String], mode: MatchMode) -> Result<Filter> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Ok((pattern.clone(), matcher(pattern, mode)?)))
+            .collect::<Result<_>>()?;
+        Ok(Filter { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether `--package` selects the dependency `name`
+    pub fn matches(&self, name: &str) -> bool {
+        self.is_empty()
+            || self
+                .patterns
+                .iter()
+                .any(|(pattern, matcher)| is_match(pattern, matcher, name))
+    }
+
+    /// The patterns matching none of `names`, with the names close to each
+    pub fn unmatched(&self, names: &[&str]) -> Vec<Unmatched> {
+        self.patterns
+            .iter()
+            .filter(|(pattern, matcher)| !names.iter().any(|name| is_match(pattern, matcher, name)))
+            .map(|(pattern, matcher)| Unmatched {
+                pattern: pattern.clone(),
+                close: match matcher {
+                    Matcher::Substring | Matcher::Exact => close(pattern, names),
+                    Matcher::Glob(_) | Matcher::Regex(_) => Vec::new(),
+                },
+            })
+            .collect()
+    }
+}
+
+fn matcher(pattern: &str, mode: MatchMode) -> Result<Matcher> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        Coded::new(
+            Code::InvalidValue,
+            format!("Invalid --package pattern {pattern}: {e}"),
+        )
+    };
+    Ok(match mode {
+        MatchMode::Substring => Matcher::Substring,
+        MatchMode::Exact => Matcher::Exact,
+        MatchMode::Glob => match Glob::new(pattern) {
+            Ok(glob) => Matcher::Glob(glob.compile_matcher()),
+            Err(e) => bail!(invalid(&e)),
+        },
+        MatchMode::Regex => match Regex::new(pattern) {
+            Ok(regex) => Matcher::Regex(regex),
+            Err(e) => bail!(invalid(&e)),
+        },
+    })
+}
+
+fn is_match(pattern: &str, matcher: &Matcher, name: &str) -> bool {
+    match matcher {
+        Matcher::Substring => name.contains(pattern),
+        Matcher::Exact => name == pattern,
+        Matcher::Glob(glob) => glob.is_match(name),
+        Matcher::Regex(regex) => regex.is_match(name),
+    }
+}
+
+/// Names within a third of `pattern`'s length in edits of it, as a whole
+/// or of their last segment (`json` in `acme/json`)
+fn close(pattern: &str, names: &[&str]) -> Vec<String> {
+    let max = (pattern.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &str)> = names
+        .iter()
+        .map(|&name| {
+            let last = name.rsplit('/').next().unwrap_or(name);
+            let distance =
+                strsim::levenshtein(pattern, name).min(strsim::levenshtein(pattern, last));
+            (distance, name)
+        })
+        .filter(|&(distance, _)| distance <= max)
+        .collect();
+    close.sort_unstable();
+    close.dedup();
+    close
+        .into_iter()
+        .take(MAX_CLOSE)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 3] = ["acme/json", "acme/jsonc", "moonbitlang/x"];
+
+    fn filter(pattern: &str, mode: MatchMode) -> Filter {
+        Filter::new(&[pattern.to_string()], mode).unwrap()
+    }
+
+    #[test]
+    fn test_match_modes() {
+        assert!(Filter::default().matches("acme/json"));
+        assert!(filter("json", MatchMode::Substring).matches("acme/jsonc"));
+        assert!(!filter("acme/json", MatchMode::Exact).matches("acme/jsonc"));
+        assert!(filter("acme/*", MatchMode::Glob).matches("acme/json"));
+        assert!(!filter("acme/*", MatchMode::Glob).matches("moonbitlang/x"));
+        assert!(filter("^acme/json$", MatchMode::Regex).matches("acme/json"));
+        assert!(!filter("^acme/json$", MatchMode::Regex).matches("acme/jsonc"));
+    }
+
+    #[test]
+    fn test_invalid_regex_is_rejected() {
+        assert!(Filter::new(&["(".to_string()], MatchMode::Regex).is_err());
+    }
+
+    #[test]
+    fn test_unmatched_patterns_suggest_close_names() {
+        let unmatched = filter("acme/jsn", MatchMode::Substring).unmatched(&NAMES);
+        assert_eq!(
+            unmatched[0].to_string(),
+            "--package acme/jsn matched no dependency; did you mean acme/json or acme/jsonc?"
+        );
+        let unmatched = filter("jsn", MatchMode::Exact).unmatched(&NAMES);
+        assert_eq!(unmatched[0].close, ["acme/json"]);
+        assert_eq!(
+            filter("zzz/*", MatchMode::Glob).unmatched(&NAMES)[0].close,
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_matched_patterns_are_not_reported() {
+        assert!(filter("json", MatchMode::Substring)
+            .unmatched(&NAMES)
+            .is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: MIT
+//! Dependency pins: packages apply must leave at their current version
+//!
+//! Pins live in config, each with a reason and optionally an expiry date and
+//! a glob limiting the repos it covers:
+//!
+//! ```toml
+//! [[pins]]
+//! package = "moonbitlang/x"
+//! reason = "0.5 changes the JSON API; migration tracked in #42"
+//! expires = "2026-12-01"
+//! repos = "services/*"
+//! ```
+//!
+//! Expired pins no longer hold; they are reported until removed or renewed.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+use crate::skip::date;
+use crate::timestamp;
+
+/// A calendar date, stored as seconds since the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Date(pub u64);
+
+impl TryFrom<String> for Date {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        timestamp::parse_rfc3339(&value)
+            .map(Date)
+            .with_context(|| format!("invalid date `{value}` (expected YYYY-MM-DD)"))
+    }
+}
+
+impl From<Date> for String {
+    fn from(value: Date) -> Self {
+        date(value.0)
+    }
+}
+
+/// A `[[pins]]` entry
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pin {
+    pub package: String,
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<Date>,
+    /// Glob matched against the repo path relative to the root; all repos
+    /// if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repos: Option<String>,
+}
+
+impl Pin {
+    /// Whether the pin has expired at time `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| now >= expires.0)
+    }
+
+    /// Whether the pin covers the repo at `rel` (relative to the root)
+    pub fn covers(&self, rel: &Path) -> bool {
+        self.repos.as_deref().is_none_or(|pattern| {
+            globset::Glob::new(pattern).is_ok_and(|glob| glob.compile_matcher().is_match(rel))
+        })
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.package, self.reason)?;
+        if let Some(expires) = self.expires {
+            write!(f, ", until {}", date(expires.0))?;
+        }
+        if let Some(repos) = &self.repos {
+            write!(f, ", repos {repos}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Whether an active pin holds `package` in the repo at `rel`
+pub fn is_pinned(pins: &[Pin], package: &str, rel: &Path, now: u64) -> bool {
+    pins.iter()
+        .any(|pin| pin.package == package && !pin.is_expired(now) && pin.covers(rel))
+}
+
+/// Pins that have expired at time `now`
+pub fn expired(pins: &[Pin], now: u64) -> impl Iterator<Item = &Pin> {
+    pins.iter().filter(move |pin| pin.is_expired(now))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins_hold_until_expiry() {
+        let pin: Pin = toml::from_str(
+            "package = \"a/x\"\nreason = \"waiting\"\nexpires = \"2026-12-01\"\nrepos = \"services/*\"\n",
+        )
+        .unwrap();
+        let expires = pin.expires.unwrap().0;
+        let pins = vec![pin];
+        let api = Path::new("services/api");
+
+        assert!(is_pinned(&pins, "a/x", api, expires - 1));
+        assert!(!is_pinned(&pins, "a/x", api, expires));
+        assert!(!is_pinned(&pins, "a/x", Path::new("libs/core"), 0));
+        assert!(!is_pinned(&pins, "a/y", api, 0));
+        assert_eq!(expired(&pins, expires).count(), 1);
+        assert_eq!(
+            pins[0].to_string(),
+            "a/x (waiting, until 2026-12-01, repos services/*)"
+        );
+        assert!(
+            toml::from_str::<Pin>("package = \"a\"\nreason = \"r\"\nexpires = \"soon\"").is_err()
+        );
+    }
+}
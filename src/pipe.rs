@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: MIT
+//! Reading the JSON output of one command as the input of another
+//!
+//! `scan --format json | apply --from-scan -` applies to the repos and
+//! manifests listed by the scan instead of walking `--root` again, and
+//! `outdated --format json | apply --from-outdated -` updates exactly the
+//! reported packages to the reported versions. Only the fields needed are
+//! read, so documents from older or newer versions work as long as those
+//! fields are there. Relative paths (from `--deterministic` output) are
+//! taken relative to `--root`.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::plan::{Plan, PlannedUpdate, RepoPlan};
+
+/// Path that stands for standard input
+pub const STDIN: &str = "-";
+
+/// The content of `path`, or of standard input for `-`
+pub fn read_input(path: &Path) -> Result<String> {
+    if path == Path::new(STDIN) {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read standard input")?;
+        return Ok(content);
+    }
+    std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(path: &Path, what: &str) -> Result<T> {
+    let content = read_input(path)?;
+    let name = if path == Path::new(STDIN) {
+        "standard input".to_string()
+    } else {
+        path.display().to_string()
+    };
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {name} as `{what} --format json` output"))
+}
+
+/// `scan --format json` output
+#[derive(Deserialize, Debug)]
+pub struct ScanDocument {
+    pub repos: Vec<ScannedRepo>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ScannedRepo {
+    pub repo_root: String,
+    pub moon_mods: Vec<ScannedModule>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ScannedModule {
+    /// Manifest path relative to the repo root
+    pub path: String,
+}
+
+impl ScanDocument {
+    pub fn read(path: &Path) -> Result<Self> {
+        parse(path, "scan")
+    }
+
+    /// Each listed repo below `base` with its manifests; remote repos
+    /// (`host:/path`) are returned apart
+    pub fn repos(&self, base: &Path) -> (Vec<(PathBuf, Vec<PathBuf>)>, Vec<&str>) {
+        let mut local = Vec::new();
+        let mut remote = Vec::new();
+        for repo in &self.repos {
+            if is_remote(&repo.repo_root) {
+                remote.push(repo.repo_root.as_str());
+                continue;
+            }
+            let root = resolve(base, &repo.repo_root);
+            let manifests = repo
+                .moon_mods
+                .iter()
+                .map(|m| root.join(crate::ospath::decode(&m.path)))
+                .collect();
+            local.push((root, manifests));
+        }
+        (local, remote)
+    }
+}
+
+/// `outdated --format json` output
+#[derive(Deserialize, Debug)]
+pub struct OutdatedDocument {
+    pub outdated: Vec<OutdatedRow>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OutdatedRow {
+    pub repo: String,
+    pub dep: String,
+    pub declared: Option<String>,
+    pub latest: Option<String>,
+    pub outdated: bool,
+}
+
+impl OutdatedDocument {
+    pub fn read(path: &Path) -> Result<Self> {
+        parse(path, "outdated")
+    }
+
+    /// A plan moving every reported dependency to its reported latest
+    /// version, without manifest hashes to check drift against
+    pub fn plan(&self, created: u64, base: &Path) -> Plan {
+        let mut repos: BTreeMap<PathBuf, Vec<PlannedUpdate>> = BTreeMap::new();
+        for row in self.outdated.iter().filter(|row| row.outdated) {
+            let Some(latest) = &row.latest else {
+                continue;
+            };
+            let updates = repos.entry(resolve(base, &row.repo)).or_default();
+            if !updates.iter().any(|u| u.name == row.dep) {
+                updates.push(PlannedUpdate {
+                    name: row.dep.clone(),
+                    from: row.declared.clone(),
+                    to: latest.clone(),
+                });
+            }
+        }
+        let repos = repos
+            .into_iter()
+            .map(|(repo, updates)| RepoPlan {
+                repo,
+                manifests: BTreeMap::new(),
+                updates,
+            })
+            .collect();
+        Plan::new(created, base.to_path_buf(), repos)
+    }
+}
+
+fn is_remote(location: &str) -> bool {
+    !location.starts_with('/') && location.split('/').next().is_some_and(|c| c.contains(':'))
+}
+
+/// `text` as a path, relative ones below `base`
+fn resolve(base: &Path, text: &str) -> PathBuf {
+    match text {
+        "." => base.to_path_buf(),
+        _ => base.join(crate::ospath::decode(text)),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_document_resolves_against_root() {
+        let scan: ScanDocument = serde_json::from_str(
+            r#"{"repos": [
+                {"repo_root": "app", "toolchain": null,
+                 "moon_mods": [{"path": "moon.mod.json", "deps": []}]},
+                {"repo_root": "/abs/lib", "moon_mods": []},
+                {"repo_root": "build01:/srv/x", "moon_mods": []}
+            ], "fleet_freshness": 100.0}"#,
+        )
+        .unwrap();
+        let (local, remote) = scan.repos(Path::new("/fleet"));
+        assert_eq!(
+            local,
+            [
+                (
+                    PathBuf::from("/fleet/app"),
+                    vec![PathBuf::from("/fleet/app/moon.mod.json")]
+                ),
+                (PathBuf::from("/abs/lib"), vec![])
+            ]
+        );
+        assert_eq!(remote, ["build01:/srv/x"]);
+    }
+
+    #[test]
+    fn test_outdated_document_plans_outdated_rows() {
+        let outdated: OutdatedDocument = serde_json::from_str(
+            r#"{"outdated": [
+                {"repo": "app", "module": "me/app", "dep": "acme/x",
+                 "declared": "0.1.0", "latest": "0.2.0", "outdated": true},
+                {"repo": "app", "module": "me/app/sub", "dep": "acme/x",
+                 "declared": "0.1.0", "latest": "0.2.0", "outdated": true},
+                {"repo": "app", "module": "me/app", "dep": "acme/y",
+                 "declared": "1.0.0", "latest": "1.0.0", "outdated": false}
+            ]}"#,
+        )
+        .unwrap();
+        let plan = outdated.plan(0, Path::new("/fleet"));
+        assert_eq!(plan.repos.len(), 1);
+        assert_eq!(
+            plan.target(Path::new("/fleet/app"), "acme/x"),
+            Some("0.2.0")
+        );
+        assert_eq!(plan.target(Path::new("/fleet/app"), "acme/y"), None);
+    }
+}
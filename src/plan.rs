@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: MIT
+//! Plans: the updates apply will make, recorded for review
+//!
+//! `moon-dst plan` writes a JSON plan with the target version of each
+//! dependency per repo and the SHA-256 of every manifest it read. The plan
+//! also records a hash of its own contents. `apply --plan` refuses a plan
+//! whose hash no longer matches (edited after review) or whose repos
+//! drifted (a manifest changed, appeared or disappeared), then updates each
+//! dependency to exactly the planned version.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{Code, Coded};
+
+/// Plan file format version
+const FORMAT: u32 = 1;
+
+/// A reviewed set of updates
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    pub format: u32,
+    /// Creation time in seconds since the Unix epoch
+    pub created: u64,
+    #[serde(with = "crate::ospath")]
+    pub root: PathBuf,
+    pub repos: Vec<RepoPlan>,
+    /// SHA-256 of the plan with this field empty
+    #[serde(default)]
+    pub hash: String,
+}
+
+/// Planned updates of one repo and the manifests they were planned from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoPlan {
+    #[serde(with = "crate::ospath")]
+    pub repo: PathBuf,
+    /// Manifest path to the SHA-256 of its content
+    #[serde(with = "crate::ospath::keys")]
+    pub manifests: BTreeMap<PathBuf, String>,
+    pub updates: Vec<PlannedUpdate>,
+}
+
+/// One dependency moved to a fixed version
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedUpdate {
+    pub name: String,
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// Hex-encoded SHA-256 of `content`
+pub fn sha256(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl Plan {
+    /// A plan over `repos`, hashed
+    pub fn new(created: u64, root: PathBuf, repos: Vec<RepoPlan>) -> Self {
+        let mut plan = Plan {
+            format: FORMAT,
+            created,
+            root,
+            repos,
+            hash: String::new(),
+        };
+        plan.hash = plan.content_hash();
+        plan
+    }
+
+    fn content_hash(&self) -> String {
+        let unhashed = Plan {
+            hash: String::new(),
+            ..self.clone()
+        };
+        // Field order is fixed by the struct, so the encoding is stable
+        sha256(&serde_json::to_vec(&unhashed).unwrap_or_default())
+    }
+
+    /// Read a plan, rejecting other formats and edited plans
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan {}", path.display()))?;
+        let plan: Plan = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse plan {}", path.display()))?;
+        if plan.format != FORMAT {
+            bail!(Coded::new(
+                Code::PlanRejected,
+                format!(
+                    "Plan {} has format {}, expected {FORMAT}",
+                    path.display(),
+                    plan.format
+                )
+            ));
+        }
+        if plan.hash != plan.content_hash() {
+            bail!(Coded::new(
+                Code::PlanRejected,
+                format!(
+                    "Plan {} was modified after it was created (hash mismatch)",
+                    path.display()
+                )
+            ));
+        }
+        Ok(plan)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write plan {}", path.display()))
+    }
+
+    /// Planned updates of the repo at `repo`
+    pub fn repo(&self, repo: &Path) -> Option<&RepoPlan> {
+        self.repos.iter().find(|r| r.repo == repo)
+    }
+
+    /// Planned version of `dep` in the repo at `repo`
+    pub fn target(&self, repo: &Path, dep: &str) -> Option<&str> {
+        self.repo(repo)?
+            .updates
+            .iter()
+            .find(|u| u.name == dep)
+            .map(|u| u.to.as_str())
+    }
+}
+
+/// Differences between the manifests a repo was planned from and
+/// `current` (manifest path to content hash), one line each
+pub fn drift(planned: &RepoPlan, current: &BTreeMap<PathBuf, String>) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (path, hash) in &planned.manifests {
+        match current.get(path) {
+            None => changes.push(format!("{}: removed", path.display())),
+            Some(now) if now != hash => changes.push(format!("{}: modified", path.display())),
+            Some(_) => {}
+        }
+    }
+    for path in current.keys() {
+        if !planned.manifests.contains_key(path) {
+            changes.push(format!("{}: added", path.display()));
+        }
+    }
+    changes
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> PathBuf {
+        PathBuf::from("/work/app/moon.mod.json")
+    }
+
+    fn plan() -> Plan {
+        let repo = RepoPlan {
+            repo: PathBuf::from("/work/app"),
+            manifests: [(manifest(), sha256(b"{}"))].into(),
+            updates: vec![PlannedUpdate {
+                name: "a/x".to_string(),
+                from: Some("0.1.0".to_string()),
+                to: "0.2.0".to_string(),
+            }],
+        };
+        Plan::new(1_700_000_000, PathBuf::from("/work"), vec![repo])
+    }
+
+    #[test]
+    fn test_plan_hash_and_targets() {
+        let plan = plan();
+        assert_eq!(plan.hash, plan.content_hash());
+        assert_eq!(plan.target(Path::new("/work/app"), "a/x"), Some("0.2.0"));
+        assert_eq!(plan.target(Path::new("/work/app"), "a/y"), None);
+    }
+
+    #[test]
+    fn test_read_rejects_edited_plan() {
+        let plan = plan();
+        let dir = std::env::temp_dir().join("moon_dst_plan_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("plan.json");
+        plan.write(&path).unwrap();
+        assert_eq!(Plan::read(&path).unwrap(), plan);
+        let edited = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("0.2.0", "9.9.9");
+        std::fs::write(&path, edited).unwrap();
+        assert!(Plan::read(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_drift_lists_changed_manifests() {
+        let plan = plan();
+        let planned = &plan.repos[0];
+        assert!(drift(planned, &planned.manifests).is_empty());
+        let other = PathBuf::from("/work/app/lib/moon.mod.json");
+        let current = [(manifest(), sha256(b"{ }")), (other, sha256(b"{}"))].into();
+        assert_eq!(
+            drift(planned, &current),
+            [
+                "/work/app/moon.mod.json: modified",
+                "/work/app/lib/moon.mod.json: added"
+            ]
+        );
+    }
+}
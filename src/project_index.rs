@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: MIT
+//! Machine-readable index of module roots and package directories
+//!
+//! `scan --format ctags` prints a tags file with one tag per module and per
+//! package (so editors can jump to them by name), and `--format
+//! lsp-workspace` a JSON document whose `workspaceFolders` can be handed to
+//! a language server as is. Packages are the directories holding a
+//! `moon.pkg.json` (or `moon.pkg`) below the module's `source` directory;
+//! nested modules, build output and hidden directories are not descended
+//! into.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::{disk, RepoInfo};
+
+/// Package manifest file names
+const PACKAGE_FILES: &[&str] = &["moon.pkg.json", "moon.pkg"];
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    /// Import path: module name plus the directory below `source`
+    pub path: String,
+    #[serde(serialize_with = "crate::ospath::serialize")]
+    pub dir: PathBuf,
+    #[serde(serialize_with = "crate::ospath::serialize")]
+    pub manifest: PathBuf,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// Module name (`owner/name`), or its path for unnamed modules
+    pub name: String,
+    pub repo: String,
+    #[serde(serialize_with = "crate::ospath::serialize")]
+    pub root: PathBuf,
+    #[serde(serialize_with = "crate::ospath::serialize")]
+    pub manifest: PathBuf,
+    /// Source directory (`source` in moon.mod.json, else the module root)
+    #[serde(serialize_with = "crate::ospath::serialize")]
+    pub source: PathBuf,
+    pub packages: Vec<Package>,
+}
+
+/// A workspace folder as in the LSP `initialize` request
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceFolder {
+    pub uri: String,
+    pub name: String,
+}
+
+/// The `lsp-workspace` document
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LspWorkspace {
+    pub workspace_folders: Vec<WorkspaceFolder>,
+    pub modules: Vec<Module>,
+}
+
+/// Modules of the local repos in `repos` with their packages
+pub fn modules(repos: &[RepoInfo]) -> Vec<Module> {
+    let mut modules = Vec::new();
+    for repo in repos.iter().filter(|r| r.host.is_none()) {
+        for moon_mod in &repo.moon_mods {
+            let root = moon_mod.path.parent().unwrap_or(&repo.root).to_path_buf();
+            let source = std::fs::read_to_string(&moon_mod.path)
+                .ok()
+                .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+                .and_then(|json| json["source"].as_str().map(|s| root.join(s)))
+                .unwrap_or_else(|| root.clone());
+            let name = moon_mod
+                .name
+                .clone()
+                .unwrap_or_else(|| root.display().to_string());
+            modules.push(Module {
+                packages: packages(&name, &root, &source),
+                name,
+                repo: repo.location(),
+                manifest: moon_mod.path.clone(),
+                root,
+                source,
+            });
+        }
+    }
+    modules.sort_by(|a, b| a.name.cmp(&b.name));
+    modules
+}
+
+/// Packages below `source` of the module at `root`
+fn packages(module: &str, root: &Path, source: &Path) -> Vec<Package> {
+    let descend = |entry: &walkdir::DirEntry| {
+        let name = entry.file_name().to_string_lossy();
+        let dir = entry.path();
+        entry.depth() == 0
+            || !entry.file_type().is_dir()
+            || !(name.starts_with('.')
+                || disk::BUILD_DIRS.contains(&name.as_ref())
+                || (dir != root && dir.join("moon.mod.json").exists()))
+    };
+    let mut packages: Vec<Package> = walkdir::WalkDir::new(source)
+        .into_iter()
+        .filter_entry(descend)
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| PACKAGE_FILES.contains(&e.file_name().to_string_lossy().as_ref()))
+        .filter_map(|e| {
+            let dir = e.path().parent()?.to_path_buf();
+            let rel = dir
+                .strip_prefix(source)
+                .ok()?
+                .to_string_lossy()
+                .replace('\\', "/");
+            Some(Package {
+                path: if rel.is_empty() {
+                    module.to_string()
+                } else {
+                    format!("{module}/{rel}")
+                },
+                manifest: e.path().to_path_buf(),
+                dir,
+            })
+        })
+        .collect();
+    packages.sort_by(|a, b| a.path.cmp(&b.path));
+    packages.dedup_by(|a, b| a.path == b.path);
+    packages
+}
+
+/// `file://` URI of an absolute path
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().replace('\\', "/").bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+/// The `lsp-workspace` document: one workspace folder per module
+pub fn lsp_workspace(modules: Vec<Module>) -> LspWorkspace {
+    LspWorkspace {
+        workspace_folders: modules
+            .iter()
+            .map(|m| WorkspaceFolder {
+                uri: file_uri(&m.root),
+                name: m.name.clone(),
+            })
+            .collect(),
+        modules,
+    }
+}
+
+/// A ctags file (extended format, sorted) with a `m` tag per module and a
+/// `p` tag per package, pointing at their manifests relative to `base`
+pub fn ctags(modules: &[Module], base: &Path) -> String {
+    let rel = |path: &Path| {
+        path.strip_prefix(base)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    let mut tags: Vec<String> = Vec::new();
+    for module in modules {
+        tags.push(format!(
+            "{}\t{}\t1;\"\tm",
+            module.name,
+            rel(&module.manifest)
+        ));
+        for package in &module.packages {
+            tags.push(format!(
+                "{}\t{}\t1;\"\tp",
+                package.path,
+                rel(&package.manifest)
+            ));
+        }
+    }
+    tags.sort();
+    tags.dedup();
+    let mut out = String::from(
+        "!_TAG_FILE_FORMAT\t2\t/extended format/\n!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted/\n!_TAG_PROGRAM_NAME\tmoon-dst\t//\n",
+    );
+    for tag in tags {
+        out.push_str(&tag);
+        out.push('\n');
+    }
+    out
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_moon_mod_content;
+
+    /// A module with packages, build output and a nested module
+    fn fleet(name: &str) -> (PathBuf, Vec<RepoInfo>) {
+        let dir = std::env::temp_dir().join(format!("moon_dst_project_index_{name}_test"));
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel: &str, content: &str| {
+            let path = dir.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        let manifest = r#"{"name":"me/app","source":"src"}"#;
+        write("app/moon.mod.json", manifest);
+        write("app/src/moon.pkg.json", "{}");
+        write("app/src/lib/moon.pkg.json", "{}");
+        write("app/src/_build/x/moon.pkg.json", "{}");
+        write("app/src/inner/moon.mod.json", "{}");
+        write("app/src/inner/moon.pkg.json", "{}");
+
+        let moon_mod = parse_moon_mod_content(manifest, &dir.join("app/moon.mod.json")).unwrap();
+        let repos = vec![RepoInfo::local(dir.join("app"), vec![moon_mod])];
+        (dir, repos)
+    }
+
+    #[test]
+    fn test_modules_skip_build_output_and_nested_modules() {
+        let (dir, repos) = fleet("modules");
+        let modules = modules(&repos);
+        let paths: Vec<&str> = modules[0]
+            .packages
+            .iter()
+            .map(|p| p.path.as_str())
+            .collect();
+        assert_eq!(paths, ["me/app", "me/app/lib"]);
+        assert_eq!(modules[0].source, dir.join("app/src"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ctags() {
+        let (dir, repos) = fleet("ctags");
+        let tags = ctags(&modules(&repos), &dir);
+        assert!(tags.contains("me/app\tapp/moon.mod.json\t1;\"\tm\n"));
+        assert!(tags.contains("me/app/lib\tapp/src/lib/moon.pkg.json\t1;\"\tp\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lsp_workspace() {
+        let (dir, repos) = fleet("lsp");
+        let lsp = lsp_workspace(modules(&repos));
+        assert_eq!(lsp.workspace_folders[0].name, "me/app");
+        assert_eq!(file_uri(Path::new("/work/my app")), "file:///work/my%20app");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: MIT
+//! Provenance: where each dependency version in use was published from
+//!
+//! Registry packages name their source repository in the index
+//! (`repository`). The release tag (`v<version>` or `<version>`) and the
+//! commit it points to are looked up on that repository with
+//! `git ls-remote --tags`. Git dependencies carry their URL and pinned
+//! `rev` in the manifest. A dependency without a source repository cannot
+//! be traced.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::process::Command;
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+
+/// Source of one dependency version
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub package: String,
+    /// Version, or the pinned rev/branch of a git dependency
+    pub version: String,
+    /// `registry` or `git`
+    pub kind: &'static str,
+    pub repository: Option<String>,
+    pub tag: Option<String>,
+    pub commit: Option<String>,
+    /// Repos using this version
+    pub used_by: Vec<String>,
+}
+
+impl Entry {
+    /// Whether the source repository is known
+    pub fn traceable(&self) -> bool {
+        self.repository.is_some()
+    }
+}
+
+/// Tags of the remote at `url` as `git ls-remote --tags` prints them
+pub fn remote_tags(url: &str) -> Result<String> {
+    let output = Command::new("git")
+        .args(["ls-remote", "--tags", url])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .logged_output()
+        .context("Failed to run git (is it installed?)")?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::GitFailed,
+            format!(
+                "git ls-remote {url} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Release tag of `version` in `git ls-remote --tags` output and the commit
+/// it points to (the peeled commit for annotated tags)
+pub fn find_tag(ls_remote: &str, version: &str) -> Option<(String, String)> {
+    let refs: Vec<(&str, &str)> = ls_remote
+        .lines()
+        .filter_map(|line| {
+            let (sha, name) = line.split_once(char::is_whitespace)?;
+            Some((sha.trim(), name.trim().strip_prefix("refs/tags/")?))
+        })
+        .collect();
+    [format!("v{version}"), version.to_string()]
+        .into_iter()
+        .find_map(|tag| {
+            let peeled = format!("{tag}^{{}}");
+            let commit = refs
+                .iter()
+                .find(|(_, name)| *name == peeled)
+                .or_else(|| refs.iter().find(|(_, name)| *name == tag))?
+                .0;
+            Some((tag, commit.to_string()))
+        })
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_tag() {
+        let ls_remote = "\
+1111\trefs/tags/v0.1.0
+2222\trefs/tags/v0.2.0
+3333\trefs/tags/v0.2.0^{}
+4444\trefs/tags/0.3.0
+";
+        assert_eq!(
+            find_tag(ls_remote, "0.1.0"),
+            Some(("v0.1.0".to_string(), "1111".to_string()))
+        );
+        assert_eq!(
+            find_tag(ls_remote, "0.2.0"),
+            Some(("v0.2.0".to_string(), "3333".to_string()))
+        );
+        assert_eq!(
+            find_tag(ls_remote, "0.3.0"),
+            Some(("0.3.0".to_string(), "4444".to_string()))
+        );
+        assert_eq!(find_tag(ls_remote, "0.4.0"), None);
+    }
+}
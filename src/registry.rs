@@ -0,0 +1,502 @@
+// SPDX-License-Identifier: MIT
+//! Read access to the local mooncakes registry index
+//!
+//! `moon update` keeps a copy of the registry index under
+//! `~/.moon/registry/index`, with one `user/<owner>/<package>.index` file per
+//! package holding one JSON object per published version.
+//!
+//! Lookups in the local index are plain file reads, cached per package; the
+//! index is refreshed by `moon update`, not by moon-dst.
+//!
+//! With `index-url` in the `[registry]` config section, index files come
+//! from that URL instead and are kept in `registry-index` in the data
+//! directory. [`Registry::prefetch`] refreshes the files of many packages
+//! in one batch of conditional requests, so only changed files are
+//! downloaded; [`Stats`] counts how lookups were answered for `--stats`.
+//! A batch is a single curl process running at most `max-parallel` (16 by
+//! default) transfers at once, so checking 500 packages takes neither 500
+//! requests in a row nor a thread per request.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::fetch::{self, Conditional, Refreshed};
+use crate::{diagnostics, timestamp};
+
+/// A published package version from the registry index
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Release {
+    pub version: String,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub yanked: bool,
+    /// `true` or a deprecation message
+    #[serde(default)]
+    pub deprecated: Option<serde_json::Value>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+    /// Source repository URL
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// SHA-256 of the package archive
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Dependency name to version (`"0.1.0"` or `{ "version": "0.1.0" }`)
+    #[serde(default)]
+    pub deps: BTreeMap<String, serde_json::Value>,
+}
+
+/// Why a published version should not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionFlag {
+    Yanked,
+    Deprecated(Option<String>),
+}
+
+impl fmt::Display for VersionFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionFlag::Yanked => write!(f, "yanked"),
+            VersionFlag::Deprecated(None) => write!(f, "deprecated"),
+            VersionFlag::Deprecated(Some(message)) => write!(f, "deprecated: {message}"),
+        }
+    }
+}
+
+impl Release {
+    fn semver(&self) -> Option<semver::Version> {
+        semver::Version::parse(&self.version).ok()
+    }
+
+    /// Yanked or deprecated marker, if any
+    pub fn flag(&self) -> Option<VersionFlag> {
+        if self.yanked {
+            return Some(VersionFlag::Yanked);
+        }
+        match &self.deprecated {
+            Some(serde_json::Value::String(message)) => {
+                Some(VersionFlag::Deprecated(Some(message.clone())))
+            }
+            Some(serde_json::Value::Bool(true)) => Some(VersionFlag::Deprecated(None)),
+            _ => None,
+        }
+    }
+
+    /// A stable release that is neither yanked nor deprecated
+    fn usable(&self) -> bool {
+        self.semver().is_some_and(|v| v.pre.is_empty()) && self.flag().is_none()
+    }
+
+    /// Name and version of each versioned dependency of this release
+    pub fn dependencies(&self) -> Vec<(String, String)> {
+        self.deps
+            .iter()
+            .filter_map(|(name, spec)| {
+                let version = match spec {
+                    serde_json::Value::String(version) => version.as_str(),
+                    serde_json::Value::Object(fields) => fields.get("version")?.as_str()?,
+                    _ => return None,
+                };
+                Some((name.clone(), version.to_string()))
+            })
+            .collect()
+    }
+
+    /// Publish time in seconds since the Unix epoch, if recorded
+    pub fn published(&self) -> Option<u64> {
+        self.created_at
+            .as_deref()
+            .and_then(timestamp::parse_rfc3339)
+    }
+}
+
+/// How registry lookups were answered
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Lookups answered from memory
+    pub memory_hits: usize,
+    /// Index files read from disk
+    pub file_reads: usize,
+    /// Remote index files still current (HTTP 304)
+    pub not_modified: usize,
+    /// Remote index files downloaded
+    pub downloaded: usize,
+    /// Remote index files that could not be refreshed
+    pub failed: usize,
+    /// Batches of remote requests
+    pub batches: usize,
+}
+
+impl Stats {
+    /// One line for `--stats`
+    pub fn summary(&self) -> String {
+        let mut line = format!(
+            "registry: {} lookups from memory, {} index files read",
+            self.memory_hits, self.file_reads
+        );
+        if self.batches > 0 {
+            line.push_str(&format!(
+                "; remote: {} not modified, {} downloaded, {} failed in {} batches",
+                self.not_modified, self.downloaded, self.failed, self.batches
+            ));
+        }
+        line
+    }
+}
+
+/// Registry index reader with a per-package cache
+pub struct Registry {
+    index_dir: PathBuf,
+    /// Base URL of a remote index mirrored into `index_dir`
+    remote: Option<String>,
+    /// Transfers a prefetch runs at once
+    max_parallel: usize,
+    cache: Mutex<HashMap<String, Vec<Release>>>,
+    /// Packages whose remote index file was refreshed by this reader
+    refreshed: Mutex<HashSet<String>>,
+    stats: Mutex<Stats>,
+}
+
+impl Registry {
+    /// Open the registry index of the local moon installation
+    pub fn open() -> Self {
+        Self::with_index_dir(moon_home().join("registry").join("index"))
+    }
+
+    pub fn with_index_dir(index_dir: PathBuf) -> Self {
+        Self {
+            index_dir,
+            remote: None,
+            max_parallel: fetch::MAX_PARALLEL,
+            cache: Mutex::new(HashMap::new()),
+            refreshed: Mutex::new(HashSet::new()),
+            stats: Mutex::new(Stats::default()),
+        }
+    }
+
+    /// Read the index at `url`, keeping the files in `cache_dir` and
+    /// downloading at most `max_parallel` at once
+    pub fn remote(url: &str, cache_dir: PathBuf, max_parallel: usize) -> Self {
+        Self {
+            remote: Some(url.trim_end_matches('/').to_string()),
+            max_parallel,
+            ..Self::with_index_dir(cache_dir)
+        }
+    }
+
+    pub fn is_remote(&self) -> bool {
+        self.remote.is_some()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Refresh the remote index files of `names` in one batch; a no-op for
+    /// a local index and for packages already refreshed
+    ///
+    /// Files that cannot be refreshed are reported and the cached copy, if
+    /// any, is used.
+    pub fn prefetch<'a>(&self, names: impl IntoIterator<Item = &'a str>) {
+        let Some(url) = &self.remote else {
+            return;
+        };
+        let names: Vec<&str> = {
+            let mut refreshed = self.refreshed.lock().unwrap();
+            names
+                .into_iter()
+                .filter(|name| refreshed.insert(name.to_string()))
+                .collect()
+        };
+        let requests: Vec<Conditional> = names
+            .iter()
+            .filter_map(|name| {
+                let (owner, package) = name.split_once('/')?;
+                Some(Conditional {
+                    url: format!("{url}/user/{owner}/{package}.index"),
+                    dest: index_file(&self.index_dir, name)?,
+                })
+            })
+            .collect();
+        if requests.is_empty() {
+            return;
+        }
+        let outcomes = match fetch::refresh_all(&requests, self.max_parallel) {
+            Ok(outcomes) => outcomes,
+            Err(e) => {
+                diagnostics::report(
+                    diagnostics::Code::RegistryIndex,
+                    format!("could not refresh the registry index from {url}: {e:#}"),
+                );
+                return;
+            }
+        };
+        let mut stats = self.stats.lock().unwrap();
+        stats.batches += 1;
+        for (request, outcome) in requests.iter().zip(outcomes) {
+            match outcome {
+                Refreshed::Downloaded => stats.downloaded += 1,
+                Refreshed::NotModified => stats.not_modified += 1,
+                Refreshed::Failed(error) => {
+                    stats.failed += 1;
+                    diagnostics::report(
+                        diagnostics::Code::RegistryIndex,
+                        format!("could not refresh {}: {error}", request.url),
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn index_dir(&self) -> &Path {
+        &self.index_dir
+    }
+
+    /// All known releases of a package, oldest first
+    pub fn releases(&self, name: &str) -> Vec<Release> {
+        if let Some(releases) = self.cache.lock().unwrap().get(name) {
+            self.stats.lock().unwrap().memory_hits += 1;
+            return releases.clone();
+        }
+        self.prefetch([name]);
+        self.stats.lock().unwrap().file_reads += 1;
+        let releases = read_releases(&self.index_dir, name);
+        let mut cache = self.cache.lock().unwrap();
+        cache.entry(name.to_string()).or_insert(releases).clone()
+    }
+
+    /// Highest stable, non-yanked, non-deprecated release of a package (or
+    /// the highest release if there is none)
+    pub fn latest(&self, name: &str) -> Option<Release> {
+        let releases = self.releases(name);
+        releases
+            .iter()
+            .rev()
+            .find(|r| r.usable())
+            .or_else(|| releases.last())
+            .cloned()
+    }
+
+    /// Yanked or deprecated marker of a specific version
+    pub fn flag(&self, name: &str, version: &str) -> Option<VersionFlag> {
+        self.releases(name)
+            .iter()
+            .find(|r| r.version == version)
+            .and_then(Release::flag)
+    }
+
+    /// Names of all packages in the index, sorted
+    pub fn packages(&self) -> Vec<String> {
+        let mut names: Vec<String> = walkdir::WalkDir::new(self.index_dir.join("user"))
+            .min_depth(2)
+            .max_depth(2)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let package = entry.path().file_name()?.to_str()?.strip_suffix(".index")?;
+                let owner = entry.path().parent()?.file_name()?.to_str()?;
+                Some(format!("{owner}/{package}"))
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Packages whose name, or the description or keywords of their latest
+    /// release, contain `query` (ignoring case)
+    pub fn search(&self, query: &str) -> Vec<String> {
+        let query = query.to_lowercase();
+        let contains = |text: &str| text.to_lowercase().contains(&query);
+        self.packages()
+            .into_iter()
+            .filter(|name| {
+                contains(name)
+                    || self.latest(name).is_some_and(|latest| {
+                        latest.description.as_deref().is_some_and(contains)
+                            || latest.keywords.iter().any(|k| contains(k))
+                    })
+            })
+            .collect()
+    }
+
+    /// Highest stable release published at or before `cutoff`
+    ///
+    /// Releases without a publish date never qualify.
+    pub fn latest_published_before(&self, name: &str, cutoff: u64) -> Option<Release> {
+        self.releases(name)
+            .into_iter()
+            .rev()
+            .filter(Release::usable)
+            .find(|r| r.published().is_some_and(|t| t <= cutoff))
+    }
+}
+
+/// `$MOON_HOME` or `~/.moon`
+pub fn moon_home() -> PathBuf {
+    if let Some(home) = std::env::var_os("MOON_HOME").filter(|v| !v.is_empty()) {
+        return PathBuf::from(home);
+    }
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".moon"))
+        .unwrap_or_else(|| PathBuf::from(".moon"))
+}
+
+/// Index file of package `name` (`owner/package`) below `index_dir`
+pub fn index_file(index_dir: &Path, name: &str) -> Option<PathBuf> {
+    let (owner, package) = name.split_once('/')?;
+    Some(
+        index_dir
+            .join("user")
+            .join(owner)
+            .join(format!("{package}.index")),
+    )
+}
+
+fn read_releases(index_dir: &Path, name: &str) -> Vec<Release> {
+    let Some(path) = index_file(index_dir, name) else {
+        return Vec::new();
+    };
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    let mut releases: Vec<Release> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    releases.sort_by(|a, b| compare_versions(&a.version, &b.version));
+    releases
+}
+
+/// Compare two version strings, using semver ordering when both parse
+pub fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    match (semver::Version::parse(a), semver::Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// Whether a declared version is behind the latest available version
+pub fn is_outdated(declared: &str, latest: &str) -> bool {
+    compare_versions(declared, latest).is_lt()
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latest_prefers_stable() {
+        let dir = std::env::temp_dir().join("moon_dst_registry_test");
+        let owner_dir = dir.join("user").join("moonbitlang");
+        std::fs::create_dir_all(&owner_dir).unwrap();
+        std::fs::write(
+            owner_dir.join("x.index"),
+            concat!(
+                r#"{"name":"moonbitlang/x","version":"0.10.0-rc.1"}"#,
+                "\n",
+                r#"{"name":"moonbitlang/x","version":"0.9.0","created_at":"2025-01-02T00:00:00Z","keywords":["json"],"license":"Apache-2.0","deps":{"a/b":"1.0.0","a/c":{"version":"2.0.0"}}}"#,
+                "\n",
+                r#"{"name":"moonbitlang/x","version":"0.2.0"}"#,
+                "\n",
+                r#"{"name":"moonbitlang/x","version":"0.9.1","yanked":true}"#,
+                "\n",
+                r#"{"name":"moonbitlang/x","version":"0.9.2","deprecated":"use 0.9.0"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        let registry = Registry::with_index_dir(dir.clone());
+        assert_eq!(registry.releases("moonbitlang/x").len(), 5);
+        let latest = registry.latest("moonbitlang/x").unwrap();
+        assert_eq!(latest.version, "0.9.0");
+        assert_eq!(latest.created_at.as_deref(), Some("2025-01-02T00:00:00Z"));
+        assert_eq!(latest.license.as_deref(), Some("Apache-2.0"));
+        assert_eq!(
+            latest.dependencies(),
+            [
+                ("a/b".to_string(), "1.0.0".to_string()),
+                ("a/c".to_string(), "2.0.0".to_string())
+            ]
+        );
+        assert!(registry.latest("moonbitlang/missing").is_none());
+        assert_eq!(
+            registry.flag("moonbitlang/x", "0.9.1"),
+            Some(VersionFlag::Yanked)
+        );
+        assert_eq!(
+            registry.flag("moonbitlang/x", "0.9.2"),
+            Some(VersionFlag::Deprecated(Some("use 0.9.0".to_string())))
+        );
+        assert_eq!(registry.flag("moonbitlang/x", "0.9.0"), None);
+        assert_eq!(registry.packages(), ["moonbitlang/x"]);
+        assert_eq!(registry.search("JSON"), ["moonbitlang/x"]);
+        assert!(registry.search("yaml").is_empty());
+
+        let cutoff = timestamp::parse_rfc3339("2025-01-02").unwrap();
+        let old_enough = registry.latest_published_before("moonbitlang/x", cutoff);
+        assert_eq!(old_enough.unwrap().version, "0.9.0");
+        assert!(registry
+            .latest_published_before("moonbitlang/x", cutoff - 1)
+            .is_none());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_remote_index_refreshes_once() {
+        let dir = std::env::temp_dir().join("moon_dst_registry_remote_test");
+        std::fs::remove_dir_all(&dir).ok();
+        let remote = dir.join("remote");
+        std::fs::create_dir_all(remote.join("user/acme")).unwrap();
+        for package in ["x", "y"] {
+            std::fs::write(
+                remote.join(format!("user/acme/{package}.index")),
+                format!(r#"{{"name":"acme/{package}","version":"1.0.0"}}"#),
+            )
+            .unwrap();
+        }
+
+        let url = format!("file://{}", remote.display());
+        let registry = Registry::remote(&url, dir.join("cache"), 2);
+        registry.prefetch(["acme/x", "acme/y", "acme/x"]);
+        assert_eq!(registry.latest("acme/x").unwrap().version, "1.0.0");
+        assert_eq!(registry.latest("acme/y").unwrap().version, "1.0.0");
+        assert_eq!(registry.releases("acme/x").len(), 1);
+        let stats = registry.stats();
+        assert_eq!(
+            (stats.batches, stats.downloaded, stats.file_reads),
+            (1, 2, 2)
+        );
+        assert_eq!(stats.memory_hits, 1);
+
+        // A new reader asks again, and the cached files are current
+        let registry = Registry::remote(&url, dir.join("cache"), 2);
+        assert_eq!(registry.latest("acme/y").unwrap().version, "1.0.0");
+        let stats = registry.stats();
+        assert_eq!((stats.not_modified, stats.downloaded), (1, 0));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_is_outdated() {
+        assert!(is_outdated("0.2.0", "0.10.0"));
+        assert!(!is_outdated("0.10.0", "0.10.0"));
+        assert!(!is_outdated("1.0.0", "0.9.0"));
+    }
+}
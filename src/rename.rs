@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MIT
+//! Package renames across the fleet
+//!
+//! `rename` moves every reference of a package to its new name: the
+//! dependency key in moon.mod.json (see [`manifest::rename_dep`]), the
+//! import paths in moon.pkg.json (the package itself and any package below
+//! it), and optionally the `@alias.` qualifiers in the .mbt sources of
+//! packages whose default alias (the last path segment) changes with it.
+//! Imports with an explicit alias keep their qualifiers.
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+use crate::manifest;
+use crate::project_index::Module;
+use crate::usage::{self, Import};
+
+/// A file whose content changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub path: PathBuf,
+    pub old: String,
+    pub new: String,
+}
+
+/// `path` under the new name, if it is `old` or a package below it
+pub fn renamed(path: &str, old: &str, new: &str) -> Option<String> {
+    if path == old {
+        return Some(new.to_string());
+    }
+    let rest = path.strip_prefix(old)?.strip_prefix('/')?;
+    Some(format!("{new}/{rest}"))
+}
+
+/// Rewrite the import paths in moon.pkg.json text, returning the new text
+/// and the (old, new) aliases of changed imports that use the default alias
+pub fn rewrite_imports(
+    content: &str,
+    old: &str,
+    new: &str,
+) -> Result<(String, Vec<(String, String)>)> {
+    let mut text = content.to_string();
+    let mut aliases = Vec::new();
+    for import in usage::imports(content)? {
+        let Some(path) = renamed(&import.path, old, new) else {
+            continue;
+        };
+        text = text.replace(&format!("\"{}\"", import.path), &format!("\"{path}\""));
+        if import.alias.is_some() {
+            continue;
+        }
+        let moved = Import { path, alias: None };
+        let change = (
+            import.qualifier().to_string(),
+            moved.qualifier().to_string(),
+        );
+        if change.0 != change.1 && !aliases.contains(&change) {
+            aliases.push(change);
+        }
+    }
+    Ok((text, aliases))
+}
+
+/// Replace the `@old.` qualifier with `@new.` in MoonBit source
+pub fn rewrite_qualifiers(source: &str, old_alias: &str, new_alias: &str) -> String {
+    source.replace(&format!("@{old_alias}."), &format!("@{new_alias}."))
+}
+
+/// The edits renaming package `old` to `new` in `modules`, setting the
+/// dependency to `version` if given
+pub fn plan(
+    modules: &[Module],
+    old: &str,
+    new: &str,
+    version: Option<&str>,
+    sources: bool,
+) -> Result<Vec<Edit>> {
+    let read = |path: &PathBuf| {
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+    };
+    let mut edits = Vec::new();
+    for module in modules {
+        let content = read(&module.manifest)?;
+        if let Some(mut updated) = manifest::rename_dep(&content, old, new) {
+            if let Some(version) = version {
+                updated = manifest::set_version(&updated, new, version).with_context(|| {
+                    format!(
+                        "{}: {new} is not declared with a plain version",
+                        module.manifest.display()
+                    )
+                })?;
+            }
+            edits.push(Edit {
+                path: module.manifest.clone(),
+                old: content,
+                new: updated,
+            });
+        }
+
+        for package in &module.packages {
+            let content = read(&package.manifest)?;
+            if package
+                .manifest
+                .file_name()
+                .is_none_or(|n| n != "moon.pkg.json")
+            {
+                if content.contains(&format!("\"{old}")) {
+                    crate::diagnostics::report(
+                        crate::diagnostics::Code::Unsupported,
+                        format!(
+                            "{} may import {old}; only moon.pkg.json files are rewritten",
+                            package.manifest.display()
+                        ),
+                    );
+                }
+                continue;
+            }
+            let (updated, aliases) = rewrite_imports(&content, old, new)
+                .with_context(|| format!("Invalid {}", package.manifest.display()))?;
+            if updated != content {
+                edits.push(Edit {
+                    path: package.manifest.clone(),
+                    old: content,
+                    new: updated,
+                });
+            }
+            if !sources || aliases.is_empty() {
+                continue;
+            }
+            let entries = std::fs::read_dir(&package.dir)
+                .with_context(|| format!("Failed to read {}", package.dir.display()))?;
+            let mut files: Vec<PathBuf> = entries
+                .filter_map(|e| Some(e.ok()?.path()))
+                .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "mbt"))
+                .collect();
+            files.sort();
+            for path in files {
+                let content = read(&path)?;
+                let updated = aliases.iter().fold(content.clone(), |text, (from, to)| {
+                    rewrite_qualifiers(&text, from, to)
+                });
+                if updated != content {
+                    edits.push(Edit {
+                        path,
+                        old: content,
+                        new: updated,
+                    });
+                }
+            }
+        }
+    }
+    Ok(edits)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_imports_keeps_other_packages() {
+        let pkg = r#"{
+  "import": [
+    "a/json",
+    "a/json/lex",
+    { "path": "a/json/parse", "alias": "p" },
+    "a/jsonx"
+  ],
+  "test-import": ["a/json"]
+}"#;
+        let (updated, aliases) = rewrite_imports(pkg, "a/json", "b/json5").unwrap();
+        assert!(updated.contains("\"b/json5\",\n    \"b/json5/lex\""));
+        assert!(updated.contains("\"b/json5/parse\", \"alias\": \"p\""));
+        assert!(updated.contains("\"a/jsonx\""));
+        assert_eq!(aliases, [("json".to_string(), "json5".to_string())]);
+    }
+
+    #[test]
+    fn test_rewrite_qualifiers_whole_names_only() {
+        let source = "let v = @json.parse(s)\nlet w = @jsonx.parse(s)\n";
+        assert_eq!(
+            rewrite_qualifiers(source, "json", "json5"),
+            "let v = @json5.parse(s)\nlet w = @jsonx.parse(s)\n"
+        );
+    }
+
+    #[test]
+    fn test_renamed_matches_package_prefix() {
+        assert_eq!(
+            renamed("a/json/lex", "a/json", "b/json5").as_deref(),
+            Some("b/json5/lex")
+        );
+        assert_eq!(renamed("a/jsonx", "a/json", "b/json5"), None);
+    }
+}
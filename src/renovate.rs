@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: MIT
+//! renovate.json stubs carrying the update policies of the config
+//!
+//! Renovate has no MoonBit manager, so the stub declares a regex manager
+//! for the registry dependencies in `moon.mod.json` and a custom
+//! `mooncakes` datasource (`[renovate] datasource-url`). The policies map
+//! as follows:
+//!
+//! | moon-dst | Renovate |
+//! |----------|----------|
+//! | `min-age` | `minimumReleaseAge` |
+//! | `packages` | only matching packages enabled |
+//! | `[[pins]]` covering the repo | package disabled, reason as description |
+//!
+//! Entries written here are marked with a `moon-dst` description (or are
+//! the keys above); updating replaces only those and keeps everything else.
+//! Dependabot does not support MoonBit yet, so there is no dependabot.yml
+//! counterpart.
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+use crate::pins::Pin;
+use crate::skip::date;
+
+/// Config file, relative to a repo root
+pub const FILE: &str = "renovate.json";
+
+/// Name of the custom datasource
+const DATASOURCE: &str = "mooncakes";
+
+/// Description prefix of managed entries
+const MARK: &str = "moon-dst";
+
+/// Update policies of one repo
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// URL template returning the versions of `{{packageName}}`
+    pub datasource_url: Option<String>,
+    /// Days a release must be public before it is proposed
+    pub min_age: Option<u64>,
+    /// Only update packages whose name contains one of these
+    pub packages: Vec<String>,
+    /// Pins in force for the repo
+    pub pins: Vec<Pin>,
+}
+
+impl Policy {
+    /// The policy for the repo at `rel`: pins that cover it and have not
+    /// expired at `now`
+    pub fn for_repo(mut self, rel: &Path, now: u64) -> Self {
+        self.pins
+            .retain(|pin| pin.covers(rel) && !pin.is_expired(now));
+        self
+    }
+
+    fn package_rules(&self) -> Vec<Value> {
+        let mut rules = Vec::new();
+        if !self.packages.is_empty() {
+            rules.push(json!({
+                "description": format!("{MARK}: only the packages selected by `packages`"),
+                "matchDatasources": [format!("custom.{DATASOURCE}")],
+                "enabled": false,
+            }));
+            let patterns: Vec<String> = self
+                .packages
+                .iter()
+                .map(|p| format!("/{}/", regex_escape(p)))
+                .collect();
+            rules.push(json!({
+                "description": format!("{MARK}: selected packages"),
+                "matchPackageNames": patterns,
+                "enabled": true,
+            }));
+        }
+        for pin in &self.pins {
+            let mut description = format!("{MARK} pin: {}", pin.reason);
+            if let Some(expires) = pin.expires {
+                description.push_str(&format!(" (until {})", date(expires.0)));
+            }
+            rules.push(json!({
+                "description": description,
+                "matchPackageNames": [pin.package],
+                "enabled": false,
+            }));
+        }
+        rules
+    }
+}
+
+fn regex_escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$/".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn is_managed(entry: &Value) -> bool {
+    entry["description"]
+        .as_str()
+        .is_some_and(|d| d.starts_with(MARK))
+}
+
+/// The manager picking up `"owner/name": "<version>"` entries of moon.mod.json
+fn manager() -> Value {
+    json!({
+        "description": format!("{MARK}: MoonBit registry dependencies"),
+        "customType": "regex",
+        "fileMatch": ["(^|/)moon\\.mod\\.json$"],
+        "matchStrings": [
+            "\"(?<depName>[\\w.-]+/[\\w./-]+)\"\\s*:\\s*\"(?<currentValue>\\d[^\"]*)\""
+        ],
+        "datasourceTemplate": format!("custom.{DATASOURCE}"),
+        "versioningTemplate": "semver",
+    })
+}
+
+/// renovate.json with `policy` applied: a new stub for `None`, otherwise
+/// `existing` with its managed entries replaced
+pub fn update(existing: Option<&str>, policy: &Policy) -> Result<String> {
+    let mut doc: Value = match existing {
+        Some(text) => serde_json::from_str(text).context("renovate.json is not plain JSON")?,
+        None => json!({
+            "$schema": "https://docs.renovatebot.com/renovate-schema.json",
+            "extends": ["config:recommended"],
+        }),
+    };
+    let root = doc
+        .as_object_mut()
+        .context("renovate.json is not a JSON object")?;
+
+    let replace = |root: &mut Map<String, Value>, key: &str, new: Vec<Value>| -> Result<()> {
+        let list = root
+            .entry(key)
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+            .with_context(|| format!("`{key}` in renovate.json is not an array"))?;
+        list.retain(|entry| !is_managed(entry));
+        list.extend(new);
+        if list.is_empty() {
+            root.remove(key);
+        }
+        Ok(())
+    };
+    replace(root, "customManagers", vec![manager()])?;
+    replace(root, "packageRules", policy.package_rules())?;
+
+    match &policy.datasource_url {
+        Some(url) => {
+            let datasources = root
+                .entry("customDatasources")
+                .or_insert_with(|| json!({}))
+                .as_object_mut()
+                .context("`customDatasources` in renovate.json is not an object")?;
+            datasources.insert(
+                DATASOURCE.to_string(),
+                json!({ "defaultRegistryUrlTemplate": url }),
+            );
+        }
+        None => {
+            if let Some(datasources) = root
+                .get_mut("customDatasources")
+                .and_then(Value::as_object_mut)
+            {
+                datasources.remove(DATASOURCE);
+                if datasources.is_empty() {
+                    root.remove("customDatasources");
+                }
+            }
+        }
+    }
+    match policy.min_age {
+        Some(days) => root.insert(
+            "minimumReleaseAge".to_string(),
+            json!(format!("{days} days")),
+        ),
+        None => root.remove("minimumReleaseAge"),
+    };
+
+    if let Some(old) = existing {
+        if serde_json::from_str::<Value>(old).ok().as_ref() == Some(&doc) {
+            return Ok(old.to_string());
+        }
+    }
+    let mut text = serde_json::to_string_pretty(&doc)?;
+    text.push('\n');
+    Ok(text)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::Date;
+
+    fn pin(package: &str, repos: Option<&str>, expires: Option<u64>) -> Pin {
+        Pin {
+            package: package.to_string(),
+            reason: "API change".to_string(),
+            expires: expires.map(Date),
+            repos: repos.map(str::to_string),
+        }
+    }
+
+    fn created() -> Value {
+        let policy = Policy {
+            datasource_url: Some("https://example.com/{{packageName}}".to_string()),
+            min_age: Some(3),
+            packages: vec!["moonbitlang/".to_string()],
+            pins: vec![
+                pin("a/x", None, Some(2_000_000_000)),
+                pin("a/y", Some("other/*"), None),
+                pin("a/z", None, Some(1)),
+            ],
+        }
+        .for_repo(Path::new("app"), 1_000);
+        serde_json::from_str(&update(None, &policy).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_create_config_from_policy() {
+        let created = created();
+        assert_eq!(created["minimumReleaseAge"], "3 days");
+        assert_eq!(created["extends"][0], "config:recommended");
+        assert_eq!(
+            created["packageRules"][1]["matchPackageNames"][0],
+            "/moonbitlang\\//"
+        );
+    }
+
+    #[test]
+    fn test_pins_for_other_repos_and_expired_pins_are_left_out() {
+        let created = created();
+        let rules = created["packageRules"].as_array().unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[2]["matchPackageNames"][0], "a/x");
+        assert_eq!(
+            rules[2]["description"],
+            "moon-dst pin: API change (until 2033-05-18)"
+        );
+    }
+
+    #[test]
+    fn test_update_replaces_managed_entries() {
+        let existing = r#"{
+  "extends": ["local>acme/renovate"],
+  "packageRules": [
+    {"description": "moon-dst pin: old", "matchPackageNames": ["a/old"], "enabled": false},
+    {"matchPackageNames": ["actions/checkout"], "automerge": true}
+  ],
+  "minimumReleaseAge": "9 days"
+}"#;
+        let updated = update(Some(existing), &Policy::default()).unwrap();
+        let doc: Value = serde_json::from_str(&updated).unwrap();
+        assert_eq!(doc["extends"][0], "local>acme/renovate");
+        assert_eq!(doc["packageRules"].as_array().unwrap().len(), 1);
+        assert!(doc.get("minimumReleaseAge").is_none());
+        assert_eq!(doc["customManagers"][0]["customType"], "regex");
+        assert_eq!(update(Some(&updated), &Policy::default()).unwrap(), updated);
+    }
+}
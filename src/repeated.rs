@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: MIT
+//! Errors many repos share
+//!
+//! A registry outage or one broken package fails the same way in every repo
+//! that touches it, and the results used to repeat the error under each of
+//! them. Apply's text and table output now list an error seen in at least
+//! [`MIN_REPOS`] repos once, before the summary, with the repos (and
+//! packages) it hit; the per-repo lines only point there. Errors count as
+//! the same when their shortened form is. `--no-group-errors` (or
+//! `group-errors = false` under `[apply]`) turns this off.
+
+use std::collections::HashMap;
+
+/// Repos an error must occur in to be listed once
+pub const MIN_REPOS: usize = 3;
+
+/// One failure as shown in the results
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub repo: String,
+    /// The package `moon add` failed for, if any
+    pub package: Option<String>,
+    pub error: String,
+}
+
+/// An error with every failure showing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repeated {
+    pub error: String,
+    pub failures: Vec<Failure>,
+}
+
+impl Repeated {
+    /// Distinct repos the error occurred in
+    pub fn repos(&self) -> usize {
+        let mut repos: Vec<&str> = self.failures.iter().map(|f| f.repo.as_str()).collect();
+        repos.dedup();
+        repos.len()
+    }
+
+    /// `registry timeout (17 repos)`, then one indented line per failure
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("{} ({} repos)", self.error, self.repos())];
+        lines.extend(self.failures.iter().map(|f| match &f.package {
+            Some(package) => format!("  {} ({package})", f.repo),
+            None => format!("  {}", f.repo),
+        }));
+        lines
+    }
+}
+
+/// The errors of `failures`, given repo by repo, that occur in at least
+/// [`MIN_REPOS`] repos; most repos first
+pub fn find(failures: &[Failure]) -> Vec<Repeated> {
+    let mut by_error: HashMap<&str, Vec<&Failure>> = HashMap::new();
+    for failure in failures {
+        by_error.entry(&failure.error).or_default().push(failure);
+    }
+    let mut repeated: Vec<Repeated> = by_error
+        .into_iter()
+        .map(|(error, failures)| Repeated {
+            error: error.to_string(),
+            failures: failures.into_iter().cloned().collect(),
+        })
+        .filter(|r| r.repos() >= MIN_REPOS)
+        .collect();
+    repeated.sort_by(|a, b| b.repos().cmp(&a.repos()).then(a.error.cmp(&b.error)));
+    repeated
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_groups_errors_across_repos() {
+        let failure = |repo: &str, package: Option<&str>, error: &str| Failure {
+            repo: repo.to_string(),
+            package: package.map(str::to_string),
+            error: error.to_string(),
+        };
+        let failures = [
+            failure("a", Some("acme/x"), "registry timeout"),
+            failure("a", Some("acme/y"), "registry timeout"),
+            failure("b", Some("acme/x"), "registry timeout"),
+            failure("b", None, "moon check failed"),
+            failure("c", Some("acme/z"), "registry timeout"),
+            failure("c", None, "moon check failed"),
+        ];
+        let repeated = find(&failures);
+        assert_eq!(repeated.len(), 1);
+        assert_eq!(repeated[0].repos(), 3);
+        assert_eq!(
+            repeated[0].lines(),
+            [
+                "registry timeout (3 repos)",
+                "  a (acme/x)",
+                "  a (acme/y)",
+                "  b (acme/x)",
+                "  c (acme/z)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: MIT
+//! Finding the repo a manifest belongs to
+//!
+//! Manifests are grouped by the closest directory above them holding a
+//! marker: a git checkout (a `.git` directory, or the `.git` file of a
+//! worktree or submodule), a Jujutsu or Mercurial repo, or a
+//! `.moon-dst-root` file for trees under no version control. More marker
+//! names come from `--root-marker` and `root-markers` in the config.
+
+use std::path::{Path, PathBuf};
+
+/// Empty file marking a repo root explicitly
+pub const MARKER_FILE: &str = ".moon-dst-root";
+
+/// Markers looked for in every directory
+pub const DEFAULT_MARKERS: &[&str] = &[".git", ".jj", ".hg", MARKER_FILE];
+
+/// Repo root of the manifest at `manifest`, or its directory when no
+/// directory above has a marker
+pub fn find(manifest: &Path, extra_markers: &[String]) -> PathBuf {
+    let dir = manifest.parent().unwrap_or(manifest);
+    dir.ancestors()
+        .find(|dir| {
+            DEFAULT_MARKERS
+                .iter()
+                .copied()
+                .chain(extra_markers.iter().map(String::as_str))
+                .any(|marker| has_marker(dir, marker))
+        })
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+fn has_marker(dir: &Path, marker: &str) -> bool {
+    let path = dir.join(marker);
+    if marker == ".git" {
+        return path.is_dir() || is_gitfile(&path);
+    }
+    path.exists()
+}
+
+/// Whether `path` is the `.git` file of a worktree or submodule, which
+/// points at the real git dir; other files named `.git` mark nothing
+pub fn is_gitfile(path: &Path) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|text| text.starts_with("gitdir:"))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_repo_root_markers() {
+        let dir = std::env::temp_dir().join("moon_dst_repo_root_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        for sub in [
+            "git/.git",
+            "git/sub/a",
+            "jj/.jj",
+            "plain/x",
+            "custom/y",
+            "stray/z",
+        ] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        std::fs::write(dir.join("git/sub/.git"), "gitdir: ../.git/modules/sub\n").unwrap();
+        std::fs::write(dir.join("plain").join(MARKER_FILE), "").unwrap();
+        std::fs::write(dir.join("custom/WORKSPACE"), "").unwrap();
+        std::fs::write(dir.join("stray/.git"), "not a gitfile").unwrap();
+        let manifest = |sub: &str| dir.join(sub).join("moon.mod.json");
+
+        assert_eq!(find(&manifest("git/sub/a"), &[]), dir.join("git/sub"));
+        assert_eq!(find(&manifest("git"), &[]), dir.join("git"));
+        assert_eq!(find(&manifest("jj"), &[]), dir.join("jj"));
+        assert_eq!(find(&manifest("plain/x"), &[]), dir.join("plain"));
+        let workspace = ["WORKSPACE".to_string()];
+        assert_eq!(find(&manifest("custom/y"), &workspace), dir.join("custom"));
+        assert_eq!(find(&manifest("stray/z"), &[]), dir.join("stray/z"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: MIT
+//! One ID per invocation, to correlate what a run left behind
+//!
+//! The ID is a ULID: 26 Crockford base32 characters, a millisecond
+//! timestamp followed by 80 random bits, so IDs sort by start time. Apply
+//! puts it in `--results-out` events, JSON output, email reports, commit
+//! messages and pull request bodies, changelog entries and the run
+//! journal, where `history show <run-id>` finds it again. Under
+//! `--deterministic` the random part is zero and the time is the fixed
+//! clock, so the ID is the same on every run.
+
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Crockford's base32 alphabet (no I, L, O or U)
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+static CURRENT: OnceLock<String> = OnceLock::new();
+
+/// ID of this invocation, made on first use
+pub fn current() -> &'static str {
+    CURRENT.get_or_init(|| {
+        if crate::timestamp::is_fixed() {
+            return encode(crate::timestamp::now() * 1000, [0; 10]);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        encode(now.as_millis() as u64, entropy(now.as_nanos()))
+    })
+}
+
+/// 80 bits that differ between processes started in the same millisecond
+fn entropy(nanos: u128) -> [u8; 10] {
+    let local = 0u8;
+    let mut hasher = Sha256::new();
+    hasher.update(nanos.to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update((&local as *const u8 as usize).to_le_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0; 10];
+    bytes.copy_from_slice(&digest[..10]);
+    bytes
+}
+
+/// ULID text of a 48-bit millisecond time and 80 random bits
+fn encode(millis: u64, random: [u8; 10]) -> String {
+    let mut value = u128::from(millis & 0xFFFF_FFFF_FFFF) << 80;
+    for (i, byte) in random.iter().enumerate() {
+        value |= u128::from(*byte) << (72 - 8 * i);
+    }
+    (0..26)
+        .rev()
+        .map(|i| ALPHABET[(value >> (5 * i)) as usize & 31] as char)
+        .collect()
+}
+
+/// Whether `text` has the form of a run ID (case is ignored)
+pub fn is_valid(text: &str) -> bool {
+    text.len() == 26
+        && text.as_bytes()[0] <= b'7'
+        && text
+            .bytes()
+            .all(|b| ALPHABET.contains(&b.to_ascii_uppercase()))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ulid() {
+        assert_eq!(encode(0, [0; 10]), "00000000000000000000000000");
+        // Example from the ULID spec's timestamp part
+        let id = encode(1_469_918_176_385, [0xFF; 10]);
+        assert_eq!(id, "01ARYZ6S41ZZZZZZZZZZZZZZZZ");
+        assert!(is_valid(&id));
+        assert!(is_valid(&id.to_lowercase()));
+        assert!(!is_valid("01ARYZ6S41"));
+        assert!(!is_valid("81ARYZ6S41ZZZZZZZZZZZZZZZZ"));
+        assert!(encode(2, [0; 10]) > encode(1, [0xFF; 10]));
+        assert_ne!(entropy(1), entropy(2));
+    }
+}
@@ -0,0 +1,348 @@
+// SPDX-License-Identifier: MIT
+//! Where moon commands run: locally, over SSH, or inside a build container
+//!
+//! Each repo gets a runner from config, matched by its path relative to the
+//! root:
+//!
+//! ```toml
+//! [runner]
+//! default = "local"
+//!
+//! [[runner.repos]]
+//! path = "services/*"
+//! runner = "docker:moonbit-build"
+//! ```
+//!
+//! Runner specs are `local`, `ssh:<host>`, `docker:<container>` and
+//! `podman:<container>`. Remote and container runners use the same paths as
+//! the local checkout, so the repos must be mounted at the same location.
+//!
+//! `docker-run:<image>` and `podman-run:<image>` start a throwaway container
+//! from an image per repo instead, with the repo mounted at its host path and
+//! commands run as the owner of the repo directory.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+use crate::{limits, ssh};
+
+/// A program to run with its arguments, environment and working directory
+pub struct Invocation<'a> {
+    pub program: &'a OsStr,
+    pub args: &'a [&'a str],
+    pub env: &'a [(&'a str, OsString)],
+    pub cwd: &'a Path,
+}
+
+/// Executes invocations somewhere and captures their output
+pub trait Runner: fmt::Debug + Send + Sync {
+    fn output(&self, invocation: &Invocation) -> io::Result<Output>;
+
+    /// Read a file as the runner sees it
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    /// Whether programs run on this machine, so host paths resolve
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+/// Run directly on this machine
+#[derive(Debug)]
+pub struct Local;
+
+impl Runner for Local {
+    fn output(&self, invocation: &Invocation) -> io::Result<Output> {
+        limits::command(invocation.program)
+            .args(invocation.args)
+            .envs(invocation.env.iter().map(|(k, v)| (k, v)))
+            .current_dir(invocation.cwd)
+            .logged_output()
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+/// Run inside a running container with `docker exec` or `podman exec`
+#[derive(Debug)]
+pub struct Exec {
+    /// Container runtime binary, `docker` or `podman`
+    pub runtime: String,
+    pub container: String,
+}
+
+impl Runner for Exec {
+    fn output(&self, invocation: &Invocation) -> io::Result<Output> {
+        let mut command = Command::new(&self.runtime);
+        command.arg("exec").arg("-w").arg(invocation.cwd);
+        for (key, value) in invocation.env {
+            let mut pair = OsString::from(format!("{key}="));
+            pair.push(value);
+            command.arg("-e").arg(pair);
+        }
+        command
+            .arg(&self.container)
+            .arg(invocation.program)
+            .args(invocation.args)
+            .logged_output()
+    }
+}
+
+/// Run inside a container started from an image on first use
+///
+/// The container lives until the runner is dropped, so state such as the
+/// registry index fetched by `moon update` carries over between commands.
+#[derive(Debug)]
+pub struct Container {
+    pub runtime: String,
+    pub image: String,
+    /// Host directory mounted at the same path in the container
+    pub mount: PathBuf,
+    id: Mutex<Option<String>>,
+}
+
+impl Container {
+    pub fn new(runtime: &str, image: &str, mount: &Path) -> Self {
+        Self {
+            runtime: runtime.to_string(),
+            image: image.to_string(),
+            mount: mount.to_path_buf(),
+            id: Mutex::new(None),
+        }
+    }
+
+    fn start(&self) -> io::Result<String> {
+        let mut volume = self.mount.clone().into_os_string();
+        volume.push(":");
+        volume.push(&self.mount);
+        let mut command = Command::new(&self.runtime);
+        command
+            .args(["run", "--detach", "--rm", "--entrypoint", "sleep"])
+            .arg("--volume")
+            .arg(volume)
+            .args(limits::current().container_args());
+        // Files written in the container keep the owner of the checkout
+        #[cfg(unix)]
+        if let Ok(metadata) = std::fs::metadata(&self.mount) {
+            use std::os::unix::fs::MetadataExt;
+            command
+                .arg("--user")
+                .arg(format!("{}:{}", metadata.uid(), metadata.gid()));
+        }
+        let output = command.arg(&self.image).arg("infinity").logged_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} run {} failed: {}",
+                self.runtime,
+                self.image,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+impl Runner for Container {
+    fn output(&self, invocation: &Invocation) -> io::Result<Output> {
+        let container = {
+            let mut id = self.id.lock().unwrap_or_else(|e| e.into_inner());
+            match &*id {
+                Some(container) => container.clone(),
+                None => id.insert(self.start()?).clone(),
+            }
+        };
+        Exec {
+            runtime: self.runtime.clone(),
+            container,
+        }
+        .output(invocation)
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.get_mut().ok().and_then(Option::take) {
+            let _ = Command::new(&self.runtime)
+                .args(["rm", "--force", &id])
+                .logged_output();
+        }
+    }
+}
+
+/// Quote a word for a POSIX shell
+pub fn quote(word: &OsStr) -> String {
+    let word = word.to_string_lossy();
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+=:@".contains(c))
+    {
+        return word.into_owned();
+    }
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// A runner as written in config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RunnerSpec {
+    #[default]
+    Local,
+    Ssh(String),
+    Exec {
+        runtime: String,
+        container: String,
+    },
+    Image {
+        runtime: String,
+        image: String,
+    },
+}
+
+impl RunnerSpec {
+    /// Runner for the repo at `repo_root`; SSH runners share `hosts` limits
+    pub fn build(&self, repo_root: &Path, hosts: &mut ssh::Hosts) -> Arc<dyn Runner> {
+        match self {
+            RunnerSpec::Local => Arc::new(Local),
+            RunnerSpec::Ssh(host) => Arc::new(hosts.runner(host)),
+            RunnerSpec::Exec { runtime, container } => Arc::new(Exec {
+                runtime: runtime.clone(),
+                container: container.clone(),
+            }),
+            RunnerSpec::Image { runtime, image } => {
+                Arc::new(Container::new(runtime, image, repo_root))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for RunnerSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: String) -> Result<Self> {
+        if spec == "local" {
+            return Ok(RunnerSpec::Local);
+        }
+        match spec.split_once(':') {
+            Some(("ssh", host)) if !host.is_empty() => Ok(RunnerSpec::Ssh(host.to_string())),
+            Some((runtime @ ("docker" | "podman"), container)) if !container.is_empty() => {
+                Ok(RunnerSpec::Exec {
+                    runtime: runtime.to_string(),
+                    container: container.to_string(),
+                })
+            }
+            Some((kind @ ("docker-run" | "podman-run"), image)) if !image.is_empty() => {
+                Ok(RunnerSpec::Image {
+                    runtime: kind.trim_end_matches("-run").to_string(),
+                    image: image.to_string(),
+                })
+            }
+            _ => bail!(Coded::new(Code::InvalidValue, format!("invalid runner `{spec}` (expected local, ssh:<host>, docker:<container>, podman:<container>, docker-run:<image> or podman-run:<image>)"))),
+        }
+    }
+}
+
+impl From<RunnerSpec> for String {
+    fn from(spec: RunnerSpec) -> Self {
+        spec.to_string()
+    }
+}
+
+impl fmt::Display for RunnerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunnerSpec::Local => write!(f, "local"),
+            RunnerSpec::Ssh(host) => write!(f, "ssh:{host}"),
+            RunnerSpec::Exec { runtime, container } => write!(f, "{runtime}:{container}"),
+            RunnerSpec::Image { runtime, image } => write!(f, "{runtime}-run:{image}"),
+        }
+    }
+}
+
+/// A `[[runner.repos]]` entry: repos matching `path` use `runner`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoRunner {
+    /// Glob matched against the repo path relative to the root
+    pub path: String,
+    pub runner: RunnerSpec,
+}
+
+/// Runner for a repo at `rel` (relative to the root): the first matching
+/// entry, or the default
+pub fn select<'a>(rel: &Path, repos: &'a [RepoRunner], default: &'a RunnerSpec) -> &'a RunnerSpec {
+    repos
+        .iter()
+        .find(|entry| {
+            globset::Glob::new(&entry.path).is_ok_and(|glob| glob.compile_matcher().is_match(rel))
+        })
+        .map(|entry| &entry.runner)
+        .unwrap_or(default)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(s: &str) -> anyhow::Result<RunnerSpec> {
+        RunnerSpec::try_from(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_runner_specs() {
+        assert_eq!(spec("local").unwrap(), RunnerSpec::Local);
+        assert_eq!(spec("ssh:builder-1").unwrap().to_string(), "ssh:builder-1");
+        assert_eq!(
+            spec("podman-run:ghcr.io/acme/moonbit:0.1").unwrap(),
+            RunnerSpec::Image {
+                runtime: "podman".to_string(),
+                image: "ghcr.io/acme/moonbit:0.1".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_reject_invalid_runner_specs() {
+        assert!(spec("docker:").is_err());
+        assert!(spec("lxc:box").is_err());
+    }
+
+    #[test]
+    fn test_select_runner_by_repo_path() {
+        let repos = vec![RepoRunner {
+            path: "services/*".to_string(),
+            runner: spec("podman:build").unwrap(),
+        }];
+        let default = RunnerSpec::Local;
+        assert_eq!(
+            select(Path::new("services/api"), &repos, &default).to_string(),
+            "podman:build"
+        );
+        assert_eq!(select(Path::new("libs/core"), &repos, &default), &default);
+    }
+
+    #[test]
+    fn test_quote_for_shell() {
+        assert_eq!(quote(OsStr::new("it's here")), r"'it'\''s here'");
+    }
+}
@@ -0,0 +1,298 @@
+// SPDX-License-Identifier: MIT
+//! Temporary copies of repos for experimental operations
+//!
+//! A sandbox is a detached `git worktree` of HEAD for git repos, or a copy
+//! without `.git`, dependency caches and build output otherwise, in the
+//! temp dir. Operations run in it through [`Sandbox::repo`], a `RepoInfo`
+//! pointing into the copy, and map what they find there back to the
+//! original repo with [`Sandbox::unmap`] and [`Sandbox::unmap_text`].
+//!
+//! A sandbox is removed (and its worktree unregistered) when dropped.
+//! Directory names carry the id of the creating process, so sandboxes left
+//! behind by an interrupted run are removed by [`remove_stale`] later.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+use crate::{disk, ssh, RepoInfo};
+
+/// Directory name prefix of sandboxes
+const PREFIX: &str = "moon-dst-sandbox-";
+
+/// Age after which a sandbox counts as stale where process ids cannot be
+/// checked
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How a sandbox was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Detached worktree of HEAD: committed state only
+    Worktree,
+    /// Copy of the working tree, including uncommitted changes
+    Copy,
+}
+
+/// A temporary copy of a repo, removed when dropped
+#[derive(Debug)]
+pub struct Sandbox {
+    original: PathBuf,
+    dir: PathBuf,
+    kind: Kind,
+}
+
+impl Sandbox {
+    /// Create a sandbox of the repo at `repo_root`
+    pub fn create(repo_root: &Path) -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "{PREFIX}{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // Built first so a failed copy is cleaned up by drop
+        let sandbox = Sandbox {
+            original: repo_root.to_path_buf(),
+            dir,
+            kind: if repo_root.join(".git").is_dir()
+                || crate::repo_root::is_gitfile(&repo_root.join(".git"))
+            {
+                Kind::Worktree
+            } else {
+                Kind::Copy
+            },
+        };
+        match sandbox.kind {
+            Kind::Worktree => {
+                let output = Command::new("git")
+                    .arg("-C")
+                    .arg(repo_root)
+                    .args(["worktree", "add", "--detach", "--quiet"])
+                    .arg(&sandbox.dir)
+                    .arg("HEAD")
+                    .logged_output()
+                    .context("Failed to run git (is it installed?)")?;
+                if !output.status.success() {
+                    bail!(Coded::new(
+                        Code::GitFailed,
+                        format!(
+                            "git worktree add failed: {}",
+                            String::from_utf8_lossy(&output.stderr).trim()
+                        )
+                    ));
+                }
+            }
+            Kind::Copy => copy_tree(repo_root, &sandbox.dir)?,
+        }
+        Ok(sandbox)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Where `path` of the original repo is in the sandbox
+    pub fn map(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.original) {
+            Ok(rel) => self.dir.join(rel),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// Where `path` of the sandbox is in the original repo
+    pub fn unmap(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.dir) {
+            Ok(rel) => self.original.join(rel),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// `text` (e.g. compiler output) with sandbox paths replaced by the
+    /// original ones
+    pub fn unmap_text(&self, text: &str) -> String {
+        text.replace(
+            &self.dir.display().to_string(),
+            &self.original.display().to_string(),
+        )
+    }
+
+    /// `repo` with its root, manifests and runner moved into the sandbox
+    pub fn repo(&self, repo: &RepoInfo) -> RepoInfo {
+        let mut copy = repo.clone();
+        copy.root = self.map(&repo.root);
+        for moon_mod in &mut copy.moon_mods {
+            moon_mod.path = self.map(&moon_mod.path);
+        }
+        copy.runner = copy
+            .runner_spec
+            .build(&copy.root, &mut ssh::Hosts::new(None));
+        copy
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if self.kind == Kind::Worktree {
+            let _ = Command::new("git")
+                .arg("-C")
+                .arg(&self.original)
+                .args(["worktree", "remove", "--force"])
+                .arg(&self.dir)
+                .logged_output();
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Copy `from` to `to`, leaving out `.git`, dependency caches and build
+/// output
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    let walker = walkdir::WalkDir::new(from).into_iter().filter_entry(|e| {
+        let name = e.file_name().to_string_lossy();
+        e.depth() == 0
+            || !e.file_type().is_dir()
+            || !(name == ".git"
+                || name == disk::CACHE_DIR
+                || disk::BUILD_DIRS.contains(&name.as_ref()))
+    });
+    for entry in walker {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)
+        } else if entry.file_type().is_file() {
+            std::fs::copy(entry.path(), &target).map(|_| ())
+        } else {
+            continue;
+        }
+        .with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+    }
+    Ok(())
+}
+
+/// Whether the sandbox at `dir`, created by process `pid`, is left over:
+/// the process is gone, or (without /proc) the sandbox is older than a day
+fn is_stale(dir: &Path, pid: u32) -> bool {
+    if pid == std::process::id() {
+        return false;
+    }
+    let proc = Path::new("/proc");
+    if proc.is_dir() {
+        return !proc.join(pid.to_string()).exists();
+    }
+    std::fs::metadata(dir)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .is_some_and(|age| age > MAX_AGE)
+}
+
+/// Remove sandboxes left behind by earlier runs, returning how many
+pub fn remove_stale() -> usize {
+    let Ok(entries) = std::fs::read_dir(std::env::temp_dir()) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in entries.filter_map(Result::ok) {
+        let dir = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(pid) = name
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split('-').next())
+            .and_then(|pid| pid.parse().ok())
+        else {
+            continue;
+        };
+        if !is_stale(&dir, pid) {
+            continue;
+        }
+        // A worktree's `.git` file points at its entry in the original repo
+        let git_dir = std::fs::read_to_string(dir.join(".git"))
+            .ok()
+            .and_then(|text| Some(PathBuf::from(text.trim().strip_prefix("gitdir: ")?)));
+        if std::fs::remove_dir_all(&dir).is_ok() {
+            removed += 1;
+        }
+        if let Some(common) = git_dir.as_deref().and_then(|d| d.parent()?.parent()) {
+            let _ = Command::new("git")
+                .arg("--git-dir")
+                .arg(common)
+                .args(["worktree", "prune"])
+                .logged_output();
+        }
+    }
+    removed
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module directory with a source file and build output
+    fn module(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("moon_dst_sandbox_{name}_test"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("_build")).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("moon.mod.json"), "{}").unwrap();
+        std::fs::write(dir.join("src/a.mbt"), "").unwrap();
+        std::fs::write(dir.join("_build/out"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_copy_leaves_out_build_output() {
+        let dir = module("copy");
+        let sandbox = Sandbox::create(&dir).unwrap();
+        assert_eq!(sandbox.kind(), Kind::Copy);
+        assert!(sandbox.dir().join("src/a.mbt").exists());
+        assert!(!sandbox.dir().join("_build").exists());
+        let copy_dir = sandbox.dir().to_path_buf();
+        drop(sandbox);
+        assert!(!copy_dir.exists());
+        assert!(dir.join("_build/out").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_map_paths_and_text_back() {
+        let dir = module("map");
+        let sandbox = Sandbox::create(&dir).unwrap();
+        let copied = sandbox.map(&dir.join("src/a.mbt"));
+        assert!(copied.starts_with(sandbox.dir()) && copied.exists());
+        assert_eq!(sandbox.unmap(&copied), dir.join("src/a.mbt"));
+        let error = format!("{}:1:1 error", copied.display());
+        assert_eq!(
+            sandbox.unmap_text(&error),
+            format!("{}:1:1 error", dir.join("src/a.mbt").display())
+        );
+        drop(sandbox);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_stale_sandboxes() {
+        // Pid 0 is never a live user process
+        let stale = std::env::temp_dir().join(format!("{PREFIX}0-0"));
+        std::fs::create_dir_all(&stale).unwrap();
+        if Path::new("/proc").is_dir() {
+            assert!(remove_stale() >= 1);
+            assert!(!stale.exists());
+        }
+        let _ = std::fs::remove_dir_all(&stale);
+    }
+}
@@ -0,0 +1,408 @@
+// SPDX-License-Identifier: MIT
+//! Dependency-aware task executor with a bounded number of workers
+//!
+//! Tasks form a DAG built in topological order (a task can only depend on
+//! tasks added before it). Two kinds of edges exist:
+//!
+//! - `requires`: the task is skipped unless the dependency succeeded
+//! - `after`: ordering only; the task runs once the dependency finished,
+//!   whatever its outcome
+//!
+//! Ready tasks are started longest-remaining-chain first so long pipelines
+//! begin early. Each task runs in a lane, and [`Limits`] caps the running
+//! tasks per lane as well as in total, so slow tasks of one kind (network
+//! requests, say) cannot take the workers another kind needs. Once the
+//! run's [`CancellationToken`] is cancelled (by the caller, or by the first
+//! failure with fail-fast), tasks that have not started yet end as
+//! cancelled; running tasks finish normally.
+//!
+//! Outcomes are sent over a channel as tasks end, so callers can report
+//! progress while the graph runs.
+
+use std::collections::BinaryHeap;
+use std::sync::mpsc::Sender;
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+use crate::cancel::CancellationToken;
+
+/// Handle to a task in a [`Graph`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskId(usize);
+
+struct Node<T> {
+    task: T,
+    lane: usize,
+    requires: Vec<usize>,
+    after: Vec<usize>,
+    dependents: Vec<usize>,
+}
+
+/// A task graph
+pub struct Graph<T> {
+    nodes: Vec<Node<T>>,
+}
+
+impl<T> Default for Graph<T> {
+    fn default() -> Self {
+        Self { nodes: Vec::new() }
+    }
+}
+
+impl<T> Graph<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a task that runs in `lane`
+    pub fn add_in(&mut self, task: T, lane: usize) -> TaskId {
+        self.nodes.push(Node {
+            task,
+            lane,
+            requires: Vec::new(),
+            after: Vec::new(),
+            dependents: Vec::new(),
+        });
+        TaskId(self.nodes.len() - 1)
+    }
+
+    /// `task` runs only if `dep` succeeds
+    pub fn requires(&mut self, task: TaskId, dep: TaskId) {
+        self.link(task, dep);
+        self.nodes[task.0].requires.push(dep.0);
+    }
+
+    /// `task` runs after `dep` finishes, regardless of its outcome
+    pub fn after(&mut self, task: TaskId, dep: TaskId) {
+        self.link(task, dep);
+        self.nodes[task.0].after.push(dep.0);
+    }
+
+    fn link(&mut self, task: TaskId, dep: TaskId) {
+        assert!(dep.0 < task.0, "dependencies must be added first");
+        self.nodes[dep.0].dependents.push(task.0);
+    }
+
+    /// Tasks in the order they were added (matching result indices)
+    pub fn tasks(&self) -> impl Iterator<Item = &T> {
+        self.nodes.iter().map(|node| &node.task)
+    }
+
+    /// Length of the longest chain starting at each task
+    fn priorities(&self) -> Vec<usize> {
+        let mut priority = vec![1; self.nodes.len()];
+        for i in (0..self.nodes.len()).rev() {
+            priority[i] = 1 + self.nodes[i]
+                .dependents
+                .iter()
+                .map(|&d| priority[d])
+                .max()
+                .unwrap_or(0);
+        }
+        priority
+    }
+}
+
+/// How a task ended
+#[derive(Debug, Clone)]
+pub enum Outcome<R> {
+    Succeeded(R),
+    Failed(R),
+    /// A required dependency did not succeed
+    Skipped,
+    /// Not started because the run was cancelled
+    Cancelled,
+}
+
+impl<R> Outcome<R> {
+    pub fn succeeded(&self) -> bool {
+        matches!(self, Outcome::Succeeded(_))
+    }
+}
+
+/// Outcome of one task with its run time window
+#[derive(Debug, Clone)]
+pub struct Finished<R> {
+    pub outcome: Outcome<R>,
+    pub started: Option<Instant>,
+    pub finished: Option<Instant>,
+}
+
+/// How many tasks may run at once, in total and per lane
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub total: usize,
+    /// Caps by lane index; lanes without one are only held to `total`
+    pub lanes: Vec<usize>,
+}
+
+impl Limits {
+    fn cap(&self, lane: usize) -> usize {
+        self.lanes.get(lane).copied().unwrap_or(self.total).max(1)
+    }
+}
+
+struct State {
+    ready: BinaryHeap<(usize, std::cmp::Reverse<usize>)>,
+    pending: Vec<usize>,
+    succeeded: Vec<bool>,
+    /// Running tasks per lane
+    running: Vec<usize>,
+    done: usize,
+}
+
+impl State {
+    /// The most urgent ready task whose lane has room
+    fn next<T>(&mut self, graph: &Graph<T>, limits: &Limits) -> Option<usize> {
+        let mut held = Vec::new();
+        let found = loop {
+            let Some(entry) = self.ready.pop() else {
+                break None;
+            };
+            let lane = graph.nodes[entry.1 .0].lane;
+            if self.running[lane] < limits.cap(lane) {
+                break Some(entry.1 .0);
+            }
+            held.push(entry);
+        };
+        self.ready.extend(held);
+        found
+    }
+}
+
+/// Run all tasks within `limits`
+///
+/// `execute` returns `Ok` for success and `Err` for failure; with
+/// `fail_fast`, a failure cancels `cancel`. Each outcome is
+/// sent to `results` with its task index (as in [`Graph::tasks`]) as soon as
+/// the task ends; every task is sent exactly once, skipped and cancelled
+/// ones included. Returns when all tasks have ended.
+pub fn stream<T, R, F>(
+    graph: &Graph<T>,
+    limits: &Limits,
+    fail_fast: bool,
+    cancel: &CancellationToken,
+    execute: F,
+    results: Sender<(usize, Finished<R>)>,
+) where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R, R> + Sync,
+{
+    let total = graph.nodes.len();
+    let priorities = graph.priorities();
+    let mut ready = BinaryHeap::new();
+    let pending: Vec<usize> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let count = node.requires.len() + node.after.len();
+            if count == 0 {
+                ready.push((priorities[i], std::cmp::Reverse(i)));
+            }
+            count
+        })
+        .collect();
+
+    let lanes = graph
+        .nodes
+        .iter()
+        .map(|node| node.lane + 1)
+        .max()
+        .unwrap_or(0);
+    let state = Mutex::new(State {
+        ready,
+        pending,
+        succeeded: vec![false; total],
+        running: vec![0; lanes],
+        done: 0,
+    });
+    let wakeup = Condvar::new();
+
+    let worker = || loop {
+        let mut guard = state.lock().unwrap();
+        let index = loop {
+            if guard.done == total {
+                return;
+            }
+            if let Some(index) = guard.next(graph, limits) {
+                break index;
+            }
+            guard = wakeup.wait(guard).unwrap();
+        };
+        let node = &graph.nodes[index];
+        guard.running[node.lane] += 1;
+
+        let blocked = node.requires.iter().any(|&dep| !guard.succeeded[dep]);
+        let finished = if cancel.is_cancelled() {
+            Finished {
+                outcome: Outcome::Cancelled,
+                started: None,
+                finished: None,
+            }
+        } else if blocked {
+            Finished {
+                outcome: Outcome::Skipped,
+                started: None,
+                finished: None,
+            }
+        } else {
+            drop(guard);
+            let started = Instant::now();
+            let outcome = match execute(&node.task) {
+                Ok(value) => Outcome::Succeeded(value),
+                Err(value) => {
+                    if fail_fast {
+                        cancel.cancel("--fail-fast");
+                    }
+                    Outcome::Failed(value)
+                }
+            };
+            guard = state.lock().unwrap();
+            Finished {
+                outcome,
+                started: Some(started),
+                finished: Some(Instant::now()),
+            }
+        };
+
+        guard.running[node.lane] -= 1;
+        guard.succeeded[index] = finished.outcome.succeeded();
+        guard.done += 1;
+        // The receiver may have gone away; the remaining tasks still run
+        let _ = results.send((index, finished));
+        for &dependent in &node.dependents {
+            guard.pending[dependent] -= 1;
+            if guard.pending[dependent] == 0 {
+                guard
+                    .ready
+                    .push((priorities[dependent], std::cmp::Reverse(dependent)));
+            }
+        }
+        wakeup.notify_all();
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..limits.total.clamp(1, total.max(1)) {
+            scope.spawn(worker);
+        }
+    });
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outcomes of all tasks, indexed like the graph's tasks
+    fn run<T: Sync, R: Send>(
+        graph: &Graph<T>,
+        jobs: usize,
+        fail_fast: bool,
+        execute: impl Fn(&T) -> Result<R, R> + Sync,
+    ) -> Vec<Outcome<R>> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        stream(
+            graph,
+            &Limits {
+                total: jobs,
+                lanes: Vec::new(),
+            },
+            fail_fast,
+            &CancellationToken::new(),
+            execute,
+            sender,
+        );
+        let mut outcomes: Vec<(usize, Outcome<R>)> =
+            receiver.into_iter().map(|(i, f)| (i, f.outcome)).collect();
+        assert_eq!(outcomes.len(), graph.nodes.len());
+        outcomes.sort_by_key(|(i, _)| *i);
+        outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+    }
+
+    #[test]
+    fn test_requires_skips_and_after_runs() {
+        let mut graph = Graph::new();
+        let a = graph.add_in("a", 0);
+        let fail = graph.add_in("fail", 0);
+        let b = graph.add_in("b", 0);
+        let c = graph.add_in("c", 0);
+        graph.requires(fail, a);
+        graph.requires(b, fail);
+        graph.after(c, fail);
+
+        let order = Mutex::new(Vec::new());
+        let results = run(&graph, 4, false, |&name| {
+            order.lock().unwrap().push(name);
+            if name == "fail" {
+                Err(name)
+            } else {
+                Ok(name)
+            }
+        });
+
+        assert!(results[a.0].succeeded());
+        assert!(matches!(results[fail.0], Outcome::Failed("fail")));
+        assert!(matches!(results[b.0], Outcome::Skipped));
+        assert!(results[c.0].succeeded());
+        assert_eq!(*order.lock().unwrap(), vec!["a", "fail", "c"]);
+    }
+
+    #[test]
+    fn test_fail_fast_cancels_queued_tasks() {
+        let mut graph = Graph::new();
+        let first = graph.add_in(0, 0);
+        for i in 1..5 {
+            let id = graph.add_in(i, 0);
+            graph.after(id, first);
+        }
+
+        let results = run(&graph, 1, true, |&i| if i == 0 { Err(i) } else { Ok(i) });
+        assert!(matches!(results[0], Outcome::Failed(0)));
+        assert!(results[1..].iter().all(|r| matches!(r, Outcome::Cancelled)));
+    }
+
+    #[test]
+    fn test_lane_limits_cap_running_tasks() {
+        // Four slow lane-0 tasks and four lane-1 tasks; lane 0 may only run
+        // one at a time, so lane 1 keeps the other workers busy
+        let mut graph = Graph::new();
+        for i in 0..8 {
+            graph.add_in(i, i % 2);
+        }
+        let running = Mutex::new([0usize; 2]);
+        let peak = Mutex::new([0usize; 2]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let limits = Limits {
+            total: 3,
+            lanes: vec![1],
+        };
+        stream(
+            &graph,
+            &limits,
+            false,
+            &CancellationToken::new(),
+            |&i: &usize| {
+                let lane = i % 2;
+                {
+                    let mut running = running.lock().unwrap();
+                    running[lane] += 1;
+                    let mut peak = peak.lock().unwrap();
+                    peak[lane] = peak[lane].max(running[lane]);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                running.lock().unwrap()[lane] -= 1;
+                Ok::<_, usize>(i)
+            },
+            sender,
+        );
+        assert_eq!(receiver.into_iter().count(), 8);
+        let peak = *peak.lock().unwrap();
+        assert_eq!(peak[0], 1);
+        assert!(peak[1] >= 1 && peak[1] <= 2);
+    }
+}
@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: MIT
+//! Updating the moon-dst binary from GitHub releases
+//!
+//! Each release carries one binary per platform named
+//! `moon-dst-<os>-<arch>` (`.exe` on Windows) and a `SHA256SUMS` file in
+//! `sha256sum` format. The downloaded binary must match its checksum before
+//! it replaces the running executable.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{Code, Coded};
+use crate::fetch;
+
+/// Latest release endpoint of this crate's repository
+pub const RELEASE_URL: &str = "https://api.github.com/repos/f4ah6o/moon-dst-rs/releases/latest";
+
+/// Checksum asset name
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+/// A GitHub release (only the fields we use)
+#[derive(Deserialize, Debug)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+/// A downloadable release asset
+#[derive(Deserialize, Debug)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    /// Release version without a leading `v`
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    pub fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+}
+
+/// Fetch release metadata
+pub fn latest_release(url: &str) -> Result<Release> {
+    let body = fetch::fetch_text(url)?;
+    serde_json::from_str(&body).with_context(|| format!("Invalid release metadata from {url}"))
+}
+
+/// Binary asset name for the running platform
+pub fn asset_name() -> String {
+    format!(
+        "moon-dst-{}-{}{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+/// Download the platform binary of a release and verify its checksum
+///
+/// The binary is written next to `exe` so it can be renamed into place.
+pub fn download_verified(release: &Release, exe: &Path) -> Result<PathBuf> {
+    let name = asset_name();
+    let Some(binary) = release.asset(&name) else {
+        bail!(Coded::new(
+            Code::NetworkFailed,
+            format!(
+                "Release {} has no binary for this platform ({name})",
+                release.tag_name
+            )
+        ));
+    };
+    let Some(checksums) = release.asset(CHECKSUMS_ASSET) else {
+        bail!(Coded::new(
+            Code::ChecksumMismatch,
+            format!(
+                "Release {} has no {CHECKSUMS_ASSET}; refusing to install an unverified binary",
+                release.tag_name
+            )
+        ));
+    };
+
+    let sums = fetch::fetch_text(&checksums.browser_download_url)?;
+    let Some(expected) = expected_checksum(&sums, &name) else {
+        bail!(Coded::new(
+            Code::ChecksumMismatch,
+            format!("{CHECKSUMS_ASSET} has no entry for {name}")
+        ));
+    };
+
+    let dest = exe.with_file_name(format!(".{name}.download"));
+    fetch::download(&binary.browser_download_url, &dest)?;
+    let actual = sha256_file(&dest)?;
+    if !actual.eq_ignore_ascii_case(&expected) {
+        std::fs::remove_file(&dest).ok();
+        bail!(Coded::new(
+            Code::ChecksumMismatch,
+            format!("Checksum mismatch for {name}: expected {expected}, got {actual}")
+        ));
+    }
+    Ok(dest)
+}
+
+/// Look up a file's checksum in `sha256sum` output (`<hex>  [*]<name>`)
+fn expected_checksum(sums: &str, name: &str) -> Option<String> {
+    sums.lines().find_map(|line| {
+        let (hash, file) = line.trim().split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then(|| hash.to_string())
+    })
+}
+
+/// Hex-encoded SHA-256 of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let content =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Sha256::digest(&content)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Replace the executable at `exe` with `new`
+///
+/// The old binary is moved aside first, which also works on Windows where
+/// a running executable cannot be overwritten but can be renamed.
+pub fn replace_exe(exe: &Path, new: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(new, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", new.display()))?;
+    }
+
+    let old = exe.with_extension("old");
+    std::fs::rename(exe, &old)
+        .with_context(|| format!("Failed to move {} aside", exe.display()))?;
+    if let Err(e) = std::fs::rename(new, exe) {
+        std::fs::rename(&old, exe).ok();
+        return Err(e).with_context(|| format!("Failed to install {}", exe.display()));
+    }
+    std::fs::remove_file(&old).ok();
+    Ok(())
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_checksum() {
+        let sums = "aaa  moon-dst-linux-x86_64\nbbb *moon-dst-macos-aarch64\n";
+        assert_eq!(
+            expected_checksum(sums, "moon-dst-linux-x86_64").as_deref(),
+            Some("aaa")
+        );
+        assert_eq!(
+            expected_checksum(sums, "moon-dst-macos-aarch64").as_deref(),
+            Some("bbb")
+        );
+        assert_eq!(expected_checksum(sums, "moon-dst-windows-x86_64.exe"), None);
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let path = std::env::temp_dir().join("moon_dst_sha256_test");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        std::fs::remove_file(&path).ok();
+        assert!(sha256_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_release_version_and_asset() {
+        let release: Release = serde_json::from_str(
+            r#"{"tag_name":"v1.4.0","assets":[{"name":"SHA256SUMS","browser_download_url":"u"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(release.version(), "1.4.0");
+        assert!(release.asset(CHECKSUMS_ASSET).is_some());
+        assert!(release.asset(&asset_name()).is_none());
+    }
+
+    #[test]
+    fn test_download_without_platform_asset_fails() {
+        let release = Release {
+            tag_name: "v1.4.0".to_string(),
+            assets: Vec::new(),
+        };
+        let exe = std::env::temp_dir().join("moon_dst_self_update_test");
+        assert!(download_verified(&release, &exe).is_err());
+    }
+}
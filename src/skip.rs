@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: MIT
+//! Opt-out marker files placed in repos by their owners
+//!
+//! A `.moon-dst-skip` file in a repo root excludes the repo from apply. The
+//! file may be empty or contain a free-form reason and an expiry date:
+//!
+//! ```text
+//! Waiting for the 0.5 migration
+//! until: 2026-12-01
+//! ```
+//!
+//! An expired marker is ignored.
+
+use anyhow::{Context, Result};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+
+use crate::timestamp;
+
+/// Marker file name, looked up in the repo root
+pub const MARKER_FILE: &str = ".moon-dst-skip";
+
+/// Contents of a skip marker
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Marker {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Expiry in seconds since the Unix epoch
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_date"
+    )]
+    pub until: Option<u64>,
+}
+
+fn serialize_date<S: Serializer>(until: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match until {
+        Some(secs) => serializer.serialize_str(&date(*secs)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// `YYYY-MM-DD` of a timestamp
+pub fn date(secs: u64) -> String {
+    timestamp::rfc3339(secs)[..10].to_string()
+}
+
+impl Marker {
+    /// Read the marker in a repo root, if there is one
+    pub fn read(repo_root: &Path) -> Result<Option<Self>> {
+        let path = repo_root.join(MARKER_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let marker =
+            Self::parse(&content).with_context(|| format!("Invalid {}", path.display()))?;
+        Ok(Some(marker))
+    }
+
+    /// Parse marker text: `until:`/`expires:` lines set the expiry, other
+    /// lines (optionally prefixed with `reason:`) form the reason
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut reason = Vec::new();
+        let mut until = None;
+        for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match line.split_once(':') {
+                Some((key, value)) if matches!(key.trim(), "until" | "expires") => {
+                    let value = value.trim();
+                    until = Some(
+                        timestamp::parse_rfc3339(value)
+                            .with_context(|| format!("Invalid expiry date: {value}"))?,
+                    );
+                }
+                Some((key, value)) if key.trim() == "reason" => reason.push(value.trim()),
+                _ => reason.push(line),
+            }
+        }
+        Ok(Self {
+            reason: (!reason.is_empty()).then(|| reason.join(" ")),
+            until,
+        })
+    }
+
+    /// Whether the marker still applies at time `now`
+    pub fn is_active(&self, now: u64) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.reason.as_deref().unwrap_or(MARKER_FILE))?;
+        if let Some(until) = self.until {
+            write!(f, ", until {}", date(until))?;
+        }
+        Ok(())
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reason_and_expiry() {
+        let marker = Marker::parse("Waiting for the\n0.5 migration\nuntil: 2026-12-01\n").unwrap();
+        assert_eq!(
+            marker.reason.as_deref(),
+            Some("Waiting for the 0.5 migration")
+        );
+        assert_eq!(
+            marker.to_string(),
+            "Waiting for the 0.5 migration, until 2026-12-01"
+        );
+
+        let until = marker.until.unwrap();
+        assert!(marker.is_active(until - 1));
+        assert!(!marker.is_active(until));
+
+        let empty = Marker::parse("").unwrap();
+        assert!(empty.is_active(u64::MAX));
+        assert_eq!(empty.to_string(), MARKER_FILE);
+        assert!(Marker::parse("reason: x\nuntil: soon").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MIT
+//! SPDX license header checking and stamping for `.mbt` sources
+//!
+//! The header is looked up in the leading `//` comment block of a file (doc
+//! comments starting with `///` end the block). Fixing replaces a wrong
+//! license identifier in place and inserts missing lines at the top, leaving
+//! the rest of the file untouched.
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const LICENSE_TAG: &str = "SPDX-License-Identifier:";
+const COPYRIGHT_TAG: &str = "SPDX-FileCopyrightText:";
+
+/// Expected header contents
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub license: String,
+    pub holder: Option<String>,
+}
+
+/// Problem with the header of one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    Missing,
+    WrongLicense(String),
+    MissingHolder,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Missing => write!(f, "missing SPDX header"),
+            Issue::WrongLicense(found) => write!(f, "license is {found}"),
+            Issue::MissingHolder => write!(f, "missing {COPYRIGHT_TAG} line"),
+        }
+    }
+}
+
+impl Header {
+    fn license_line(&self) -> String {
+        format!("// {LICENSE_TAG} {}", self.license)
+    }
+
+    fn copyright_line(&self) -> Option<String> {
+        self.holder
+            .as_ref()
+            .map(|holder| format!("// {COPYRIGHT_TAG} {holder}"))
+    }
+
+    /// Find the first problem with the file's header, if any
+    pub fn check(&self, content: &str) -> Option<Issue> {
+        let block = leading_comments(content);
+        match find_tag(&block, LICENSE_TAG) {
+            None => return Some(Issue::Missing),
+            Some((_, found)) if found != self.license => {
+                return Some(Issue::WrongLicense(found.to_string()))
+            }
+            Some(_) => {}
+        }
+        if self.holder.is_some() && find_tag(&block, COPYRIGHT_TAG).is_none() {
+            return Some(Issue::MissingHolder);
+        }
+        None
+    }
+
+    /// Return the content with a correct header
+    pub fn apply(&self, content: &str) -> String {
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let block = leading_comments(content);
+
+        let license_index = match find_tag(&block, LICENSE_TAG) {
+            Some((index, _)) => {
+                lines[index] = self.license_line();
+                index
+            }
+            None => {
+                if block.is_empty() && lines.first().is_some_and(|l| !l.trim().is_empty()) {
+                    lines.insert(0, String::new());
+                }
+                lines.insert(0, self.license_line());
+                0
+            }
+        };
+        if let Some(copyright) = self.copyright_line() {
+            if find_tag(&block, COPYRIGHT_TAG).is_none() {
+                lines.insert(license_index + 1, copyright);
+            }
+        }
+
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+}
+
+/// Lines of the leading `//` comment block (excluding `///` doc comments)
+fn leading_comments(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .take_while(|l| {
+            let l = l.trim_start();
+            l.starts_with("//") && !l.starts_with("///")
+        })
+        .collect()
+}
+
+/// Index and value of the first `tag` line in the comment block
+fn find_tag<'a>(block: &[&'a str], tag: &str) -> Option<(usize, &'a str)> {
+    block
+        .iter()
+        .enumerate()
+        .find_map(|(i, line)| line.split_once(tag).map(|(_, value)| (i, value.trim())))
+}
+
+/// Build a matcher from `--exclude` patterns
+pub fn exclude_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            Glob::new(pattern).with_context(|| format!("Invalid exclude pattern '{pattern}'"))?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+/// `.mbt` files of a repo, skipping ignored directories and excluded paths
+///
+/// Exclude patterns are matched against the path relative to the repo root
+/// and against the file name alone.
+pub fn source_files(
+    repo_root: &Path,
+    ignores: &[String],
+    excludes: &GlobSet,
+) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(repo_root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            let rel = e.path().strip_prefix(repo_root).unwrap_or(e.path());
+            !crate::should_ignore(rel, ignores)
+        })
+    {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension().is_none_or(|ext| ext != "mbt") {
+            continue;
+        }
+        let rel = path.strip_prefix(repo_root).unwrap_or(path);
+        if excludes.is_match(rel) || excludes.is_match(entry.file_name()) {
+            continue;
+        }
+        files.push(path.to_path_buf());
+    }
+    Ok(files)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(holder: Option<&str>) -> Header {
+        Header {
+            license: "MIT".to_string(),
+            holder: holder.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_missing_header_is_added() {
+        let mit = header(Some("Acme"));
+        let bare = "///|\nfn main {\n}\n";
+        assert_eq!(mit.check(bare), Some(Issue::Missing));
+        let fixed = mit.apply(bare);
+        assert_eq!(
+            fixed,
+            "// SPDX-License-Identifier: MIT\n// SPDX-FileCopyrightText: Acme\n\n///|\nfn main {\n}\n"
+        );
+        assert_eq!(mit.check(&fixed), None);
+        assert_eq!(mit.apply(&fixed), fixed);
+    }
+
+    #[test]
+    fn test_wrong_license_is_replaced() {
+        let apache = "// SPDX-License-Identifier: Apache-2.0\nfn f {}\n";
+        assert_eq!(
+            header(None).check(apache),
+            Some(Issue::WrongLicense("Apache-2.0".to_string()))
+        );
+        assert_eq!(
+            header(None).apply(apache),
+            "// SPDX-License-Identifier: MIT\nfn f {}\n"
+        );
+    }
+}
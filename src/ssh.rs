@@ -0,0 +1,319 @@
+// SPDX-License-Identifier: MIT
+//! Repos on remote build machines, reached over SSH
+//!
+//! Remote repos are listed in config as `host:/path`:
+//!
+//! ```toml
+//! [ssh]
+//! repos = ["builder-1:/srv/checkouts/app"]
+//! max-per-host = 2
+//! ```
+//!
+//! Commands run through `ssh`, and files such as `moon.mod.json` are read
+//! with `sftp`. Both share one OpenSSH master connection per host (a control
+//! socket under `~/.moon-dst/ssh/`), and at most `max-per-host` commands run
+//! on a host at once.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+use crate::runner::{quote, Invocation, Runner};
+
+/// Seconds an idle master connection stays open
+const CONTROL_PERSIST: u32 = 60;
+
+/// A repo addressed as `host:/path`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Remote {
+    pub host: String,
+    pub path: PathBuf,
+}
+
+impl TryFrom<String> for Remote {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: String) -> Result<Self> {
+        match spec.split_once(':') {
+            Some((host, path)) if !host.is_empty() && path.starts_with('/') => Ok(Remote {
+                host: host.to_string(),
+                path: PathBuf::from(path),
+            }),
+            _ => bail!(Coded::new(
+                Code::InvalidValue,
+                format!("invalid remote repo `{spec}` (expected host:/absolute/path)")
+            )),
+        }
+    }
+}
+
+impl From<Remote> for String {
+    fn from(remote: Remote) -> Self {
+        remote.to_string()
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.path.display())
+    }
+}
+
+// =============================================================================
+// Per-Host Limits
+// =============================================================================
+
+/// Counting semaphore bounding concurrent commands on one host
+#[derive(Debug)]
+pub struct Slots {
+    free: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Slots {
+    pub fn new(count: usize) -> Self {
+        Self {
+            free: Mutex::new(count.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) -> SlotGuard<'_> {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            free = self.released.wait(free).unwrap_or_else(|e| e.into_inner());
+        }
+        *free -= 1;
+        SlotGuard(self)
+    }
+}
+
+struct SlotGuard<'a>(&'a Slots);
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Hands out SSH runners that share one slot pool per host
+#[derive(Debug)]
+pub struct Hosts {
+    limit: Option<usize>,
+    slots: HashMap<String, Arc<Slots>>,
+}
+
+impl Hosts {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            slots: HashMap::new(),
+        }
+    }
+
+    pub fn runner(&mut self, host: &str) -> Ssh {
+        let slots = self.limit.map(|limit| {
+            self.slots
+                .entry(host.to_string())
+                .or_insert_with(|| Arc::new(Slots::new(limit)))
+                .clone()
+        });
+        Ssh {
+            host: host.to_string(),
+            slots,
+        }
+    }
+}
+
+// =============================================================================
+// Runner
+// =============================================================================
+
+/// Run on a remote host with `ssh`, reading files with `sftp`
+#[derive(Debug)]
+pub struct Ssh {
+    pub host: String,
+    slots: Option<Arc<Slots>>,
+}
+
+/// Options shared by `ssh` and `sftp`: no prompts, pooled connections
+fn options() -> Vec<String> {
+    let mut options = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+    let control_dir = crate::data_dir().map(|dir| dir.join("ssh"));
+    if let Some(dir) = control_dir.filter(|dir| std::fs::create_dir_all(dir).is_ok()) {
+        options.extend([
+            "-o".to_string(),
+            "ControlMaster=auto".to_string(),
+            "-o".to_string(),
+            format!("ControlPath={}/%C", dir.display()),
+            "-o".to_string(),
+            format!("ControlPersist={CONTROL_PERSIST}"),
+        ]);
+    }
+    options
+}
+
+impl Runner for Ssh {
+    fn output(&self, invocation: &Invocation) -> io::Result<Output> {
+        let mut script = format!("cd {} &&", quote(invocation.cwd.as_os_str()));
+        for (key, value) in invocation.env {
+            script.push_str(&format!(" {key}={}", quote(value)));
+        }
+        script.push_str(&format!(" exec {}", quote(invocation.program)));
+        for arg in invocation.args {
+            script.push(' ');
+            script.push_str(&quote(OsStr::new(arg)));
+        }
+        let _slot = self.slots.as_ref().map(|slots| slots.acquire());
+        Command::new("ssh")
+            .args(options())
+            .arg(&self.host)
+            .arg(script)
+            .logged_output()
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+        let local = std::env::temp_dir().join(format!(
+            "moon-dst-sftp-{}-{}",
+            std::process::id(),
+            DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let _slot = self.slots.as_ref().map(|slots| slots.acquire());
+        let output = Command::new("sftp")
+            .args(options())
+            .args(["-q", "-b", "-"])
+            .arg(&self.host)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .logged_output_with_input(
+                format!("get {} {}\n", sftp_quote(path), sftp_quote(&local)).as_bytes(),
+            )?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let kind = if stderr.contains("not found") || stderr.contains("No such file") {
+                io::ErrorKind::NotFound
+            } else {
+                io::ErrorKind::Other
+            };
+            return Err(io::Error::new(
+                kind,
+                format!("sftp {}:{}: {}", self.host, path.display(), stderr.trim()),
+            ));
+        }
+        let content = std::fs::read_to_string(&local);
+        std::fs::remove_file(&local).ok();
+        content
+    }
+}
+
+/// Quote a path for an sftp batch command
+fn sftp_quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `moon.mod.json` files below `root` on the remote host
+///
+/// Dot directories and directories named in `ignores` are skipped, as in a
+/// local walk.
+pub fn find_manifests(runner: &Ssh, root: &Path, ignores: &[String]) -> Result<Vec<PathBuf>> {
+    let root_arg = root.to_string_lossy();
+    let mut args: Vec<&str> = vec![&root_arg, "-mindepth", "1", "(", "-name", ".*"];
+    for ignore in ignores {
+        args.extend(["-o", "-name", ignore]);
+    }
+    args.extend([
+        ")",
+        "-prune",
+        "-o",
+        "-name",
+        "moon.mod.json",
+        "-type",
+        "f",
+        "-print",
+    ]);
+    let output = runner
+        .output(&Invocation {
+            program: OsStr::new("find"),
+            args: &args,
+            env: &[],
+            cwd: root,
+        })
+        .with_context(|| format!("Failed to run ssh {}", runner.host))?;
+    if !output.status.success() {
+        bail!(Coded::new(
+            Code::SshFailed,
+            format!(
+                "find {} failed on {}: {}",
+                root.display(),
+                runner.host,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        ));
+    }
+    let mut manifests: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    manifests.sort();
+    Ok(manifests)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote() {
+        let remote = Remote::try_from("builder-1:/srv/app".to_string()).unwrap();
+        assert_eq!(remote.host, "builder-1");
+        assert_eq!(remote.path, PathBuf::from("/srv/app"));
+        assert_eq!(remote.to_string(), "builder-1:/srv/app");
+    }
+
+    #[test]
+    fn test_reject_remote_without_absolute_path() {
+        assert!(Remote::try_from("builder-1:relative".to_string()).is_err());
+        assert!(Remote::try_from("/srv/app".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_runners_share_slots_per_host() {
+        let mut hosts = Hosts::new(Some(1));
+        let a = hosts.runner("builder-1");
+        let b = hosts.runner("builder-1");
+        assert!(Arc::ptr_eq(
+            a.slots.as_ref().unwrap(),
+            b.slots.as_ref().unwrap()
+        ));
+
+        let slots = a.slots.unwrap();
+        let held = slots.acquire();
+        assert_eq!(*slots.free.lock().unwrap(), 0);
+        drop(held);
+        assert_eq!(*slots.free.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_sftp_quote() {
+        assert_eq!(sftp_quote(Path::new("/a \"b\"")), r#""/a \"b\"""#);
+    }
+}
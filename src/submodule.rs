@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: MIT
+//! Git submodules in the fleet
+//!
+//! A submodule checkout has a `.git` file, so discovery makes it a repo of
+//! its own (see `repo_root`). `--submodules skip` leaves submodules out
+//! instead. With `[pr] update-submodules`, a pull request opened inside a
+//! submodule is followed by one in its superproject that moves the
+//! submodule pointer to the new commit.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::forge;
+
+/// What discovery does with repos that are submodules of another
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Submodules {
+    /// Treat each submodule as a repo of its own
+    #[default]
+    Separate,
+    /// Leave submodules out
+    Skip,
+}
+
+/// A submodule pointer to record in a superproject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// Submodule path relative to the superproject
+    pub path: PathBuf,
+    pub commit: String,
+}
+
+/// Working tree of the superproject when `repo` is a submodule checkout
+pub fn superproject(repo: &Path) -> Option<PathBuf> {
+    // Only submodules and worktrees have a `.git` file; skip git otherwise
+    if !crate::repo_root::is_gitfile(&repo.join(".git")) {
+        return None;
+    }
+    let dir = forge::git(repo, &["rev-parse", "--show-superproject-working-tree"]).ok()?;
+    // Canonical like discovered repo roots
+    PathBuf::from(dir).canonicalize().ok()
+}
+
+/// The pointer to record in `superproject` for the submodule at `repo`
+/// now at `commit`
+pub fn pointer(superproject: &Path, repo: &Path, commit: &str) -> Pointer {
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+    let (superproject, repo) = (canonical(superproject), canonical(repo));
+    Pointer {
+        path: repo
+            .strip_prefix(&superproject)
+            .unwrap_or(&repo)
+            .to_path_buf(),
+        commit: commit.to_string(),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_superproject_of_submodule() {
+        let dir = std::env::temp_dir().join("moon_dst_submodule_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let git = |repo: &Path, args: &[&str]| {
+            let mut all = vec![
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@example.com",
+                "-c",
+                "protocol.file.allow=always",
+            ];
+            all.extend(args);
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(repo)
+                .args(&all)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?}");
+        };
+        let lib = dir.join("lib");
+        git(&lib, &["init", "--quiet"]);
+        git(&lib, &["commit", "--quiet", "--allow-empty", "-m", "lib"]);
+        let app = dir.join("app");
+        git(&dir, &["init", "--quiet", "app"]);
+        git(
+            &app,
+            &[
+                "submodule",
+                "--quiet",
+                "add",
+                lib.to_str().unwrap(),
+                "deps/lib",
+            ],
+        );
+
+        let sub = app.join("deps/lib");
+        assert_eq!(superproject(&sub), Some(app.canonicalize().unwrap()));
+        assert_eq!(superproject(&app), None);
+        assert_eq!(pointer(&app, &sub, "abc").path, Path::new("deps/lib"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
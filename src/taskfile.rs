@@ -0,0 +1,453 @@
+// SPDX-License-Identifier: MIT
+//! Makefile and mise.toml equivalents of the justfile template
+//!
+//! The template is written for just. For repos using another task runner it
+//! is translated recipe by recipe: variables become `?=` assignments (make)
+//! or `[vars]` entries (mise), `{{name}}` becomes `$(name)` or
+//! `{{vars.name}}`, and recipe dependencies become prerequisites or
+//! `depends`. Merging follows the justfile rules: missing variables and
+//! recipes are added, existing ones are left alone, and overridden variables
+//! are rewritten in place.
+//!
+//! A repo holding several modules gets the file in every module, plus a
+//! root one whose `fmt-all`, `check-all` and `test-all` run the recipe in
+//! each module in turn (see [`root_template`]).
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::justfile;
+
+/// Task runner the template is written for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskRunner {
+    /// justfile
+    #[default]
+    Just,
+    /// Makefile
+    Make,
+    /// mise.toml tasks
+    Mise,
+}
+
+impl TaskRunner {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            TaskRunner::Just => "justfile",
+            TaskRunner::Make => "Makefile",
+            TaskRunner::Mise => "mise.toml",
+        }
+    }
+
+    /// Command running `recipe` of the module at `dir` (below the root file),
+    /// as a justfile template line; `{{MAKE}}` becomes make's `$(MAKE)`
+    fn delegate(self, dir: &str, recipe: &str) -> String {
+        match (self, dir) {
+            (TaskRunner::Just, ".") => format!("just {recipe}"),
+            (TaskRunner::Just, dir) => format!("cd {} && just {recipe}", shell_quote(dir)),
+            (TaskRunner::Make, ".") => format!("{{{{MAKE}}}} {recipe}"),
+            (TaskRunner::Make, dir) => format!("{{{{MAKE}}}} -C {} {recipe}", shell_quote(dir)),
+            (TaskRunner::Mise, ".") => format!("mise run {recipe}"),
+            (TaskRunner::Mise, dir) => format!("cd {} && mise run {recipe}", shell_quote(dir)),
+        }
+    }
+}
+
+/// `text` as one sh word: single-quoted, with inner quotes escaped
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Template recipes the root file runs in every module, as `<recipe>-all`
+pub const ROOT_RECIPES: &[&str] = &["fmt", "check", "test"];
+
+/// The root file (as a justfile template) of a repo with modules in `dirs`,
+/// `.` for one at the root: an `<recipe>-all` recipe for each of
+/// [`ROOT_RECIPES`] in `template`, running it in every module. A module at
+/// the root keeps its own recipes in the same file.
+pub fn root_template(runner: TaskRunner, template: &str, dirs: &[String]) -> String {
+    let mut out = match dirs.iter().any(|dir| dir == ".") {
+        true => template.to_string(),
+        false => format!(
+            "# MoonBit Project Commands (modules: {})\n",
+            dirs.join(", ")
+        ),
+    };
+    let recipes = justfile::recipes(template);
+    for &name in ROOT_RECIPES {
+        if !recipes.iter().any(|recipe| recipe.name == name) {
+            continue;
+        }
+        out.push_str(&format!("\n{name}-all:\n"));
+        for dir in dirs {
+            out.push_str(&format!("    {}\n", runner.delegate(dir, name)));
+        }
+    }
+    out
+}
+
+/// The justfile `template` as the file of `runner`
+pub fn render(runner: TaskRunner, template: &str) -> String {
+    if runner == TaskRunner::Just {
+        return template.to_string();
+    }
+    let variables = justfile::variables(template);
+    let recipes = justfile::recipes(template);
+    let mut out = justfile::header_comments(template).join("\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    match runner {
+        TaskRunner::Make => {
+            if !variables.is_empty() {
+                out.push('\n');
+            }
+            for (name, value) in &variables {
+                out.push_str(&format!("{}\n", assignment(runner, name, value)));
+            }
+            let names: Vec<&str> = recipes.iter().map(|r| r.name.as_str()).collect();
+            out.push_str(&format!("\n.PHONY: {}\n", names.join(" ")));
+            for recipe in &recipes {
+                let header = format!("{}: {}", recipe.name, recipe.deps.join(" "));
+                out.push_str(&format!("\n{}\n", header.trim_end()));
+                for command in &recipe.commands {
+                    let command = interpolate(&command.replace('$', "$$"), |v| format!("$({v})"));
+                    out.push_str(&format!("\t{command}\n"));
+                }
+            }
+        }
+        _ => {
+            if !variables.is_empty() {
+                out.push_str("\n[vars]\n");
+            }
+            for (name, value) in &variables {
+                out.push_str(&format!("{}\n", assignment(runner, name, value)));
+            }
+            for recipe in &recipes {
+                out.push_str(&format!("\n[tasks.{}]\n", recipe.name));
+                if !recipe.deps.is_empty() {
+                    let deps: Vec<String> = recipe.deps.iter().map(|d| toml_string(d)).collect();
+                    out.push_str(&format!("depends = [{}]\n", deps.join(", ")));
+                }
+                let commands: Vec<String> = recipe
+                    .commands
+                    .iter()
+                    .map(|c| c.trim_start_matches('@'))
+                    .map(|c| toml_string(&interpolate(c, |v| format!("{{{{vars.{v}}}}}"))))
+                    .collect();
+                match commands.as_slice() {
+                    [] => {}
+                    [command] => out.push_str(&format!("run = {command}\n")),
+                    _ => out.push_str(&format!("run = [{}]\n", commands.join(", "))),
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Add the variables and recipes of `generated` (as returned by [`render`])
+/// missing from `existing`
+pub fn merge(runner: TaskRunner, existing: &str, generated: &str) -> String {
+    if runner == TaskRunner::Just {
+        return justfile::merge(existing, generated);
+    }
+    let existing_lines = lines(existing);
+    let present: Vec<String> = variable_lines(runner, &existing_lines)
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect();
+    let generated_lines = lines(generated);
+    let missing_vars: Vec<String> = variable_lines(runner, &generated_lines)
+        .into_iter()
+        .filter(|(_, name)| !present.contains(name))
+        .map(|(i, _)| generated_lines[i].clone())
+        .collect();
+
+    let recipes = recipe_blocks(runner, &existing_lines);
+    let missing_recipes: Vec<(String, Vec<String>)> = recipe_blocks(runner, &generated_lines)
+        .into_iter()
+        .filter(|(name, _)| !recipes.iter().any(|(n, _)| n == name))
+        .collect();
+    if missing_vars.is_empty() && missing_recipes.is_empty() {
+        return existing.to_string();
+    }
+
+    let mut out = insert_lines(runner, existing_lines, missing_vars);
+    if runner == TaskRunner::Make {
+        if let Some(phony) = out.iter_mut().find(|l| l.starts_with(".PHONY:")) {
+            for (name, _) in &missing_recipes {
+                phony.push(' ');
+                phony.push_str(name);
+            }
+        }
+    }
+    for (_, block) in missing_recipes {
+        if out.last().is_some_and(|l| !l.trim().is_empty()) {
+            out.push(String::new());
+        }
+        out.extend(block);
+    }
+    join(out)
+}
+
+/// Set each variable in `vars`: existing assignments are rewritten, missing
+/// ones are added next to the others
+pub fn set_variables(runner: TaskRunner, content: &str, vars: &BTreeMap<String, String>) -> String {
+    if runner == TaskRunner::Just {
+        return justfile::set_variables(content, vars);
+    }
+    if vars.is_empty() {
+        return content.to_string();
+    }
+    let mut lines = lines(content);
+    let present = variable_lines(runner, &lines);
+    for (i, name) in &present {
+        let Some(value) = vars.get(name) else {
+            continue;
+        };
+        lines[*i] = match runner {
+            // Keep the assignment operator (`?=`, `:=`, `=`)
+            TaskRunner::Make => {
+                let (lhs, _) = lines[*i].split_once('=').unwrap_or_default();
+                format!("{lhs}= {value}")
+            }
+            _ => assignment(runner, name, value),
+        };
+    }
+    let missing = vars
+        .iter()
+        .filter(|(name, _)| !present.iter().any(|(_, n)| n == *name))
+        .map(|(name, value)| assignment(runner, name, value))
+        .collect();
+    join(insert_lines(runner, lines, missing))
+}
+
+fn assignment(runner: TaskRunner, name: &str, value: &str) -> String {
+    match runner {
+        TaskRunner::Make => format!("{name} ?= {value}"),
+        _ => format!("{name} = {}", toml_string(value)),
+    }
+}
+
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// Replace each `{{name}}` interpolation with `var(name)`
+fn interpolate(command: &str, var: impl Fn(&str) -> String) -> String {
+    let mut out = String::new();
+    let mut rest = command;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&var(rest[start + 2..start + 2 + len].trim()));
+        rest = &rest[start + 4 + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn lines(content: &str) -> Vec<String> {
+    content.lines().map(str::to_string).collect()
+}
+
+fn join(lines: Vec<String>) -> String {
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// Name of the section a mise.toml header line opens
+fn section(line: &str) -> Option<&str> {
+    let line = line.trim();
+    line.strip_prefix('[')?.strip_suffix(']').map(str::trim)
+}
+
+/// Index and name of each variable assignment: top-level `name ?= value`
+/// lines (make) or the entries of `[vars]` (mise)
+fn variable_lines(runner: TaskRunner, lines: &[String]) -> Vec<(usize, String)> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    let mut in_vars = false;
+    let mut found = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if runner == TaskRunner::Mise {
+            if let Some(name) = section(line) {
+                in_vars = name == "vars";
+                continue;
+            }
+            if !in_vars {
+                continue;
+            }
+        } else if line.starts_with([' ', '\t']) {
+            continue;
+        }
+        let Some((lhs, _)) = line.split_once('=') else {
+            continue;
+        };
+        let name = lhs.trim_end().trim_end_matches(['?', ':', '+', '!']).trim();
+        if !name.is_empty() && !line.trim_start().starts_with('#') && name.chars().all(valid) {
+            found.push((i, name.to_string()));
+        }
+    }
+    found
+}
+
+/// Name and lines of each recipe: a `name:` rule and its recipe lines
+/// (make) or a `[tasks.name]` table (mise)
+fn recipe_blocks(runner: TaskRunner, lines: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut blocks: Vec<(String, Vec<String>)> = Vec::new();
+    let mut current: Option<(String, Vec<String>)> = None;
+    for line in lines {
+        let name = match runner {
+            TaskRunner::Mise => section(line).map(|s| {
+                s.strip_prefix("tasks.")
+                    .map(|n| n.trim_matches('"').to_string())
+            }),
+            _ if line.starts_with('\t') || line.trim().is_empty() => None,
+            _ => Some(line.split_once(':').and_then(|(name, rest)| {
+                let rule = !name.starts_with('.')
+                    && !rest.starts_with('=')
+                    && !name.trim_start().starts_with('#')
+                    && !name.contains(['=', ' ', '$']);
+                rule.then(|| name.to_string())
+            })),
+        };
+        match name {
+            // A new block starts (or, for `None` inside, other content)
+            Some(name) => {
+                blocks.extend(current.take());
+                current = name.map(|name| (name, vec![line.clone()]));
+            }
+            None => {
+                if let Some((_, block)) = &mut current {
+                    block.push(line.clone());
+                }
+            }
+        }
+    }
+    blocks.extend(current);
+    for (_, block) in &mut blocks {
+        while block.last().is_some_and(|l| l.trim().is_empty()) {
+            block.pop();
+        }
+    }
+    blocks
+}
+
+/// Insert variable assignment lines after the last existing one, or in a
+/// new block before the first recipe (make) or section (mise)
+fn insert_lines(runner: TaskRunner, mut lines: Vec<String>, new: Vec<String>) -> Vec<String> {
+    if new.is_empty() {
+        return lines;
+    }
+    if let Some(&(last, _)) = variable_lines(runner, &lines).last() {
+        lines.splice(last + 1..last + 1, new);
+        return lines;
+    }
+    let (at, mut block) = match runner {
+        TaskRunner::Mise => match lines.iter().position(|l| section(l) == Some("vars")) {
+            Some(vars) => (vars + 1, new),
+            None => {
+                let at = lines.iter().position(|l| section(l).is_some());
+                let block = [vec!["[vars]".to_string()], new].concat();
+                (at.unwrap_or(lines.len()), block)
+            }
+        },
+        _ => {
+            let first = recipe_blocks(runner, &lines)
+                .first()
+                .map(|(_, b)| b[0].clone());
+            let at = lines
+                .iter()
+                .position(|l| Some(l) == first.as_ref() || l.starts_with(".PHONY:"));
+            (at.unwrap_or(lines.len()), new)
+        }
+    };
+    if at < lines.len() {
+        block.push(String::new());
+    } else if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+        block.insert(0, String::new());
+    }
+    lines.splice(at..at, block);
+    lines
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "# Commands\n\ntarget := \"js\"\n\ndefault: check\n\ncheck:\n    moon check --target {{target}}\n\nfmt:\n    moon fmt\n";
+
+    #[test]
+    fn test_render_and_merge_make_and_mise() {
+        let make = render(TaskRunner::Make, TEMPLATE);
+        assert_eq!(
+            make,
+            "# Commands\n\ntarget ?= js\n\n.PHONY: default check fmt\n\ndefault: check\n\ncheck:\n\tmoon check --target $(target)\n\nfmt:\n\tmoon fmt\n"
+        );
+        let mise = render(TaskRunner::Mise, TEMPLATE);
+        assert_eq!(
+            mise,
+            "# Commands\n\n[vars]\ntarget = \"js\"\n\n[tasks.default]\ndepends = [\"check\"]\n\n[tasks.check]\nrun = \"moon check --target {{vars.target}}\"\n\n[tasks.fmt]\nrun = \"moon fmt\"\n"
+        );
+
+        let existing = ".PHONY: check\n\ncheck:\n\tmoon check\n";
+        let merged = merge(TaskRunner::Make, existing, &make);
+        assert_eq!(
+            merged,
+            "target ?= js\n\n.PHONY: check default fmt\n\ncheck:\n\tmoon check\n\ndefault: check\n\nfmt:\n\tmoon fmt\n"
+        );
+        let vars = [("target".to_string(), "wasm-gc".to_string())].into();
+        assert!(set_variables(TaskRunner::Make, &merged, &vars).starts_with("target ?= wasm-gc\n"));
+
+        let existing = "[tools]\nmoon = \"latest\"\n\n[tasks.fmt]\nrun = \"moon fmt\"\n";
+        let merged = set_variables(
+            TaskRunner::Mise,
+            &merge(TaskRunner::Mise, existing, &mise),
+            &vars,
+        );
+        assert_eq!(
+            merged,
+            "[vars]\ntarget = \"wasm-gc\"\n\n[tools]\nmoon = \"latest\"\n\n[tasks.fmt]\nrun = \"moon fmt\"\n\n[tasks.default]\ndepends = [\"check\"]\n\n[tasks.check]\nrun = \"moon check --target {{vars.target}}\"\n"
+        );
+        assert!(toml::from_str::<toml::Table>(&merged).is_ok());
+        assert_eq!(merge(TaskRunner::Mise, &merged, &mise), merged);
+    }
+
+    #[test]
+    fn test_root_template_delegates_to_modules() {
+        let dirs = ["core".to_string(), "packages/http".to_string()];
+        assert_eq!(
+            root_template(TaskRunner::Just, TEMPLATE, &dirs),
+            "# MoonBit Project Commands (modules: core, packages/http)\n\nfmt-all:\n    cd 'core' && just fmt\n    cd 'packages/http' && just fmt\n\ncheck-all:\n    cd 'core' && just check\n    cd 'packages/http' && just check\n"
+        );
+        // Directories are quoted for sh, spaces and quotes included
+        let dirs = ["my app".to_string(), "bob's $lib".to_string()];
+        let make = render(
+            TaskRunner::Make,
+            &root_template(TaskRunner::Make, TEMPLATE, &dirs),
+        );
+        assert!(make.contains("\n.PHONY: fmt-all check-all\n"));
+        assert!(make.contains(
+            "\ncheck-all:\n\t$(MAKE) -C 'my app' check\n\t$(MAKE) -C 'bob'\\''s $$lib' check\n"
+        ));
+
+        let dirs = [".".to_string(), "my tools".to_string()];
+        let root = root_template(TaskRunner::Mise, TEMPLATE, &dirs);
+        assert!(root.starts_with(TEMPLATE));
+        assert!(root
+            .ends_with("\ncheck-all:\n    mise run check\n    cd 'my tools' && mise run check\n"));
+        let mise = render(TaskRunner::Mise, &root);
+        assert!(mise.contains("run = [\"mise run check\", \"cd 'my tools' && mise run check\"]\n"));
+    }
+}
@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: MIT
+//! Versioned template packs stamped into repos
+//!
+//! A pack lives in `~/.config/moon-dst/templates/<pack>/` and consists of a
+//! `pack.toml` manifest plus the files to stamp, laid out relative to the repo
+//! root (e.g. `justfile`, `.github/workflows/ci.yml`). The built-in `default`
+//! pack holds the justfile template and is used when no user pack of that
+//! name exists. Stamped repos record the pack name and version in
+//! `.moon-dst-template.toml` so `drift` can tell when a repo falls behind.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::capabilities;
+use crate::config;
+use crate::errors::{Code, Coded};
+
+/// Name of the built-in pack
+pub const DEFAULT_PACK: &str = "default";
+
+/// Pack manifest file name
+pub const MANIFEST_FILE: &str = "pack.toml";
+
+/// Per-repo record of the applied pack
+pub const RECORD_FILE: &str = ".moon-dst-template.toml";
+
+/// `pack.toml` structure
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    version: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// A loaded template pack
+#[derive(Debug, Clone)]
+pub struct Pack {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub files: Vec<PackFile>,
+}
+
+/// A file to stamp, with its path relative to the repo root
+#[derive(Debug, Clone)]
+pub struct PackFile {
+    pub path: String,
+    pub content: String,
+}
+
+/// Applied pack record stored in each repo
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Record {
+    pub pack: String,
+    pub version: String,
+    pub files: Vec<String>,
+}
+
+/// Directory holding user template packs
+pub fn templates_dir() -> Option<PathBuf> {
+    config::user_config_dir().map(|dir| dir.join("templates"))
+}
+
+/// Load a pack by name from the user templates directory, falling back to
+/// the built-in pack for `default`
+pub fn load(name: &str) -> Result<Pack> {
+    if let Some(dir) = templates_dir().map(|d| d.join(name)) {
+        if dir.join(MANIFEST_FILE).exists() {
+            return load_dir(name, &dir);
+        }
+    }
+    if name == DEFAULT_PACK {
+        return Ok(builtin_default());
+    }
+    bail!(Coded::new(
+        Code::FileNotFound,
+        format!(
+            "Template pack '{name}' not found (expected {}/{name}/{MANIFEST_FILE})",
+            templates_dir()
+                .map(|d| d.display().to_string())
+                .unwrap_or_else(|| "~/.config/moon-dst/templates".to_string())
+        )
+    ))
+}
+
+/// The built-in pack containing the justfile template
+pub fn builtin_default() -> Pack {
+    Pack {
+        name: DEFAULT_PACK.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        description: Some("Built-in justfile template".to_string()),
+        files: vec![PackFile {
+            path: "justfile".to_string(),
+            content: crate::JUSTFILE_TEMPLATE.to_string(),
+        }],
+    }
+}
+
+fn load_dir(name: &str, dir: &Path) -> Result<Pack> {
+    let manifest_path = dir.join(MANIFEST_FILE);
+    let manifest: Manifest = toml::from_str(
+        &std::fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Failed to read {}", manifest_path.display()))?,
+    )
+    .with_context(|| format!("Invalid pack manifest {}", manifest_path.display()))?;
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir).follow_links(true).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() || entry.path() == manifest_path {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        let path = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let content = std::fs::read_to_string(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        files.push(PackFile { path, content });
+    }
+
+    Ok(Pack {
+        name: name.to_string(),
+        version: manifest.version,
+        description: manifest.description,
+        files,
+    })
+}
+
+/// Read the applied pack record of a repo, if any
+pub fn read_record(repo_root: &Path) -> Result<Option<Record>> {
+    let path = repo_root.join(RECORD_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content)
+        .map(Some)
+        .with_context(|| format!("Invalid template record {}", path.display()))
+}
+
+/// Write the applied pack record of a repo
+pub fn write_record(repo_root: &Path, pack: &Pack) -> Result<()> {
+    let record = Record {
+        pack: pack.name.clone(),
+        version: pack.version.clone(),
+        files: pack.files.iter().map(|f| f.path.clone()).collect(),
+    };
+    let path = repo_root.join(RECORD_FILE);
+    let content = format!(
+        "# Generated by moon-dst apply-templates; used by moon-dst drift\n{}",
+        toml::to_string(&record)?
+    );
+    capabilities::write(&path, content)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `ci` pack with a justfile and a workflow
+    fn pack_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("moon_dst_templates_{name}_test"));
+        let _ = std::fs::remove_dir_all(&dir);
+        let pack_dir = dir.join("ci");
+        std::fs::create_dir_all(pack_dir.join(".github/workflows")).unwrap();
+        std::fs::write(pack_dir.join(MANIFEST_FILE), "version = \"1.2.0\"\n").unwrap();
+        std::fs::write(pack_dir.join("justfile"), "fmt:\n    moon fmt\n").unwrap();
+        std::fs::write(pack_dir.join(".github/workflows/ci.yml"), "on: push\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_dir_lists_files_but_not_manifest() {
+        let dir = pack_dir("load");
+        let pack = load_dir("ci", &dir.join("ci")).unwrap();
+        assert_eq!(pack.version, "1.2.0");
+        let paths: Vec<&str> = pack.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec![".github/workflows/ci.yml", "justfile"]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let dir = pack_dir("record");
+        assert!(read_record(&dir).unwrap().is_none());
+        let pack = load_dir("ci", &dir.join("ci")).unwrap();
+        write_record(&dir, &pack).unwrap();
+        let record = read_record(&dir).unwrap().unwrap();
+        assert_eq!(record.pack, "ci");
+        assert_eq!(record.version, "1.2.0");
+        assert_eq!(record.files.len(), 2);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: MIT
+//! Unix timestamps and RFC 3339 formatting/parsing in UTC
+//!
+//! Timestamps are whole seconds since the Unix epoch. Only the subset of
+//! RFC 3339 used by the registry index and our own output is supported.
+//!
+//! Under `--deterministic` the clock is fixed (see [`fix_clock`]): `now` is
+//! `SOURCE_DATE_EPOCH`, or the epoch itself, and measured durations are
+//! reported as zero.
+
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SECS_PER_DAY: u64 = 86_400;
+
+static FIXED: OnceLock<u64> = OnceLock::new();
+
+/// Fix the clock at `SOURCE_DATE_EPOCH` (or 0) for the rest of the process
+pub fn fix_clock() {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0);
+    let _ = FIXED.set(secs);
+}
+
+pub fn is_fixed() -> bool {
+    FIXED.get().is_some()
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn now() -> u64 {
+    if let Some(&secs) = FIXED.get() {
+        return secs;
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// `duration` as reported: zero on a fixed clock
+pub fn reported(duration: Duration) -> Duration {
+    if is_fixed() {
+        Duration::ZERO
+    } else {
+        duration
+    }
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp
+pub fn rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / SECS_PER_DAY) as i64);
+    let rem = secs % SECS_PER_DAY;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// Parse `YYYY-MM-DDTHH:MM:SS[.frac](Z|±HH:MM)` into seconds since the epoch
+///
+/// A bare date is read as midnight UTC.
+pub fn parse_rfc3339(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (date, time) = match text.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut secs = days_from_civil(year, month, day) * SECS_PER_DAY as i64;
+
+    if let Some(time) = time {
+        let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(i) => time.split_at(i),
+            None => (time, ""),
+        };
+        let clock = clock.split('.').next()?;
+        let mut hms = clock.splitn(3, ':');
+        let hours: i64 = hms.next()?.parse().ok()?;
+        let minutes: i64 = hms.next()?.parse().ok()?;
+        let seconds: i64 = hms.next().unwrap_or("0").parse().ok()?;
+        secs += hours * 3_600 + minutes * 60 + seconds;
+
+        if let Some(sign @ ('+' | '-')) = offset.chars().next() {
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let offset = hours.parse::<i64>().ok()? * 3_600 + minutes.parse::<i64>().ok()? * 60;
+            secs -= if sign == '+' { offset } else { -offset };
+        }
+    }
+    u64::try_from(secs).ok()
+}
+
+/// Whole days between two timestamps (zero if `later` is earlier)
+pub fn days_between(earlier: u64, later: u64) -> u64 {
+    later.saturating_sub(earlier) / SECS_PER_DAY
+}
+
+// Howard Hinnant's civil calendar algorithms, with eras of 400 years
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_and_parse_round_trip() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_767_225_599), "2025-12-31T23:59:59Z");
+
+        for secs in [0, 951_782_400, 1_767_225_599] {
+            assert_eq!(parse_rfc3339(&rfc3339(secs)), Some(secs));
+        }
+        assert_eq!(parse_rfc3339("2000-02-29"), Some(951_782_400));
+        assert_eq!(
+            parse_rfc3339("2000-02-29T09:00:00.123+09:00"),
+            Some(951_782_400)
+        );
+        assert_eq!(parse_rfc3339("yesterday"), None);
+    }
+}
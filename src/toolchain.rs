@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: MIT
+//! MoonBit toolchain pins and installed version detection
+//!
+//! A repo pins its toolchain with a `moon-version` file at the repo root or a
+//! `"moon-version"` field in a `moon.mod.json`. A plain version must match the
+//! installed `moon` exactly; a requirement starting with an operator
+//! (`>=0.1.20250101`, `~0.1`) is evaluated as a semver range.
+//!
+//! Repos whose pin the default `moon` does not satisfy run with a managed
+//! toolchain from `~/.moon-dst/toolchains/<version>/` when one matches. Each
+//! managed toolchain is a complete MoonBit home (`bin/moon`, `lib/core`) and
+//! is used with `MOON_HOME` pointing at it.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::audit::CommandExt;
+use crate::errors::{Code, Coded};
+use crate::{fetch, registry, runner};
+
+/// Pin file name, looked up in the repo root
+pub const VERSION_FILE: &str = "moon-version";
+
+/// Official installer script, run with `MOON_HOME` set to the target dir
+const INSTALLER_URL: &str = "https://cli.moonbitlang.com/install/unix.sh";
+
+/// Default release feed for the latest toolchain version
+pub const RELEASE_FEED: &str = "https://cli.moonbitlang.com/version.json";
+
+/// moon binary used to run commands in a repo
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Toolchain {
+    /// `moon` from PATH or `~/.moon/bin`
+    #[default]
+    Default,
+    /// A managed toolchain under the toolchains directory
+    Managed { version: String, home: PathBuf },
+}
+
+impl Toolchain {
+    /// The `moon` binary; on remote runners the default is `moon` from PATH
+    pub fn program(&self, local: bool) -> PathBuf {
+        match self {
+            Toolchain::Default if local => crate::get_moon_bin(),
+            Toolchain::Default => PathBuf::from("moon"),
+            Toolchain::Managed { home, .. } => home.join("bin").join("moon"),
+        }
+    }
+
+    /// Environment the `moon` binary needs
+    pub fn env(&self) -> Vec<(&'static str, OsString)> {
+        match self {
+            Toolchain::Default => Vec::new(),
+            Toolchain::Managed { home, .. } => vec![("MOON_HOME", home.clone().into_os_string())],
+        }
+    }
+}
+
+impl fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Toolchain::Default => write!(f, "default"),
+            Toolchain::Managed { version, home } => {
+                write!(f, "managed {version} ({})", home.display())
+            }
+        }
+    }
+}
+
+/// Read the pinned version from `<dir>/moon-version`
+///
+/// The first non-empty line that is not a `#` comment is used.
+pub fn read_pin(dir: &Path) -> Result<Option<String>> {
+    let path = dir.join(VERSION_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(parse_pin(&content))
+}
+
+/// Pinned version in the content of a `moon-version` file
+pub fn parse_pin(content: &str) -> Option<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+}
+
+/// Version of the default toolchain, from `moon version`
+pub fn installed() -> Option<String> {
+    let output = crate::run_moon_command(
+        &runner::Local,
+        &Toolchain::Default,
+        &["version"],
+        Path::new("."),
+    )
+    .ok()?;
+    parse_version_output(&output)
+}
+
+/// Extract the version from `moon version` output,
+/// e.g. `moon 0.1.20250108 (e9d6d5c 2025-01-08) ~/.moon/bin/moon`
+fn parse_version_output(output: &str) -> Option<String> {
+    output
+        .lines()
+        .flat_map(str::split_whitespace)
+        .find(|token| token.starts_with(|c: char| c.is_ascii_digit()))
+        .map(str::to_string)
+}
+
+/// Whether the installed version satisfies a pin
+pub fn satisfies(installed: &str, required: &str) -> bool {
+    let required = required.trim();
+    if required.starts_with(['>', '<', '=', '~', '^']) {
+        if let (Ok(req), Ok(version)) = (
+            semver::VersionReq::parse(required),
+            semver::Version::parse(installed),
+        ) {
+            return req.matches(&version);
+        }
+    }
+    installed == required.trim_start_matches('v')
+}
+
+/// Pick a toolchain satisfying `required`
+///
+/// The default toolchain wins when it satisfies the pin (or its version is
+/// unknown); otherwise the newest matching managed toolchain is used.
+/// Returns `None` when nothing installed satisfies the pin.
+pub fn select(
+    required: &str,
+    installed: Option<&str>,
+    managed: &[(String, PathBuf)],
+) -> Option<Toolchain> {
+    if installed.is_none_or(|v| satisfies(v, required)) {
+        return Some(Toolchain::Default);
+    }
+    managed
+        .iter()
+        .rev()
+        .find(|(version, _)| satisfies(version, required))
+        .map(|(version, home)| Toolchain::Managed {
+            version: version.clone(),
+            home: home.clone(),
+        })
+}
+
+/// Directory holding managed toolchains:
+/// `$MOON_DST_HOME/toolchains` or `~/.moon-dst/toolchains`
+pub fn toolchains_dir() -> Option<PathBuf> {
+    Some(crate::data_dir()?.join("toolchains"))
+}
+
+/// Managed toolchains with a `bin/moon`, sorted by version (oldest first)
+pub fn managed() -> Vec<(String, PathBuf)> {
+    let Some(dir) = toolchains_dir() else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut toolchains: Vec<(String, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|home| home.join("bin").join("moon").is_file())
+        .filter_map(|home| {
+            let version = home.file_name()?.to_str()?.to_string();
+            Some((version, home))
+        })
+        .collect();
+    toolchains.sort_by(|a, b| registry::compare_versions(&a.0, &b.0));
+    toolchains
+}
+
+/// Install a toolchain version into the toolchains directory using the
+/// official installer script
+pub fn install(version: &str, dry_run: bool) -> Result<PathBuf> {
+    let Some(dir) = toolchains_dir() else {
+        bail!(Coded::new(
+            Code::ToolchainFailed,
+            "Cannot locate the toolchains directory (HOME not set)"
+        ));
+    };
+    let home = dir.join(version);
+    println!(
+        "Installing moon {version} into {} ({INSTALLER_URL})",
+        home.display()
+    );
+    if dry_run {
+        return Ok(home);
+    }
+
+    std::fs::create_dir_all(&home)
+        .with_context(|| format!("Failed to create {}", home.display()))?;
+    run_installer(Some(version), Some(&home))?;
+    if !home.join("bin").join("moon").is_file() {
+        bail!(Coded::new(
+            Code::ToolchainFailed,
+            format!(
+                "Installer finished but {}/bin/moon is missing",
+                home.display()
+            )
+        ));
+    }
+    Ok(home)
+}
+
+/// Update the default toolchain to the latest release with the official
+/// installer
+pub fn update_default(dry_run: bool) -> Result<()> {
+    println!("Updating the default toolchain ({INSTALLER_URL})");
+    if dry_run {
+        return Ok(());
+    }
+    run_installer(None, None)
+}
+
+/// Run the installer script, optionally for a specific version and home
+fn run_installer(version: Option<&str>, home: Option<&Path>) -> Result<()> {
+    if cfg!(windows) {
+        bail!(Coded::new(
+            Code::ToolchainFailed,
+            "Running the MoonBit installer is only supported on Unix"
+        ));
+    }
+    let script = fetch::fetch_text(INSTALLER_URL)?;
+
+    let mut command = Command::new("bash");
+    command.arg("-s").arg("--").args(version);
+    if let Some(home) = home {
+        command.env("MOON_HOME", home);
+    }
+    // The installer's output goes to the terminal
+    let status = command
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .logged_output_with_input(script.as_bytes())
+        .context("Failed to run the MoonBit installer")?
+        .status;
+    if !status.success() {
+        bail!(Coded::new(
+            Code::ToolchainFailed,
+            format!(
+                "MoonBit installer exited with code {}",
+                status.code().unwrap_or(-1)
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// Latest toolchain version announced by a release feed
+pub fn latest_release(feed: &str) -> Result<String> {
+    let body = fetch::fetch_text(feed)?;
+    parse_release_feed(&body).with_context(|| format!("No version found in release feed {feed}"))
+}
+
+/// Accept JSON with a `version`, `latest` or `tag_name` key, or plain text
+/// containing a version
+fn parse_release_feed(body: &str) -> Option<String> {
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(body) {
+        return ["version", "latest", "tag_name"]
+            .iter()
+            .find_map(|key| json.get(key)?.as_str())
+            .map(|v| v.trim_start_matches('v').to_string());
+    }
+    parse_version_output(body)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_satisfies() {
+        let version =
+            parse_version_output("moon 0.1.20250108 (e9d6d5c 2025-01-08) ~/.moon/bin/moon\n");
+        assert_eq!(version.as_deref(), Some("0.1.20250108"));
+
+        assert!(satisfies("0.1.20250108", "0.1.20250108"));
+        assert!(satisfies("0.1.20250108", "v0.1.20250108"));
+        assert!(!satisfies("0.1.20250108", "0.1.20250201"));
+        assert!(satisfies("0.1.20250108", ">=0.1.20250101"));
+        assert!(!satisfies("0.1.20250108", ">=0.2.0"));
+
+        assert_eq!(
+            parse_release_feed(r#"{"tag_name": "v0.1.20250201"}"#).as_deref(),
+            Some("0.1.20250201")
+        );
+        assert_eq!(
+            parse_release_feed("0.1.20250201\n").as_deref(),
+            Some("0.1.20250201")
+        );
+    }
+
+    #[test]
+    fn test_select_prefers_default_then_newest_managed() {
+        let managed = vec![
+            ("0.1.20240101".to_string(), PathBuf::from("/t/0.1.20240101")),
+            ("0.1.20240601".to_string(), PathBuf::from("/t/0.1.20240601")),
+        ];
+        assert_eq!(
+            select(">=0.1.20240101", Some("0.1.20250108"), &managed),
+            Some(Toolchain::Default)
+        );
+        assert_eq!(
+            select("<0.1.20250101", Some("0.1.20250108"), &managed),
+            Some(Toolchain::Managed {
+                version: "0.1.20240601".to_string(),
+                home: PathBuf::from("/t/0.1.20240601"),
+            })
+        );
+        assert_eq!(select("0.1.20230101", Some("0.1.20250108"), &managed), None);
+    }
+}
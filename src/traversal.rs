@@ -0,0 +1,354 @@
+// SPDX-License-Identifier: MIT
+//! Where discovery spends its walk
+//!
+//! When enabled (`scan --stats`), each walk for manifests records per
+//! directory how many files and subdirectories it held, how many manifests
+//! and how long the walk spent listing it. [`report`] prints the totals
+//! (directories, files, ignored subtrees, time) and warns when one subtree
+//! took most of a walk, naming it as an ignore candidate if it holds no
+//! modules. `scan --suggest-ignores` lists every large directory without
+//! modules as one, from the same records ([`suggestions`]).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Share of a walk's time above which a subtree dominates it
+const DOMINANT_SHARE: f64 = 0.5;
+/// Walks shorter than this are not worth tuning
+const MIN_WALK: Duration = Duration::from_millis(500);
+/// Files below a directory without modules for it to be worth ignoring
+const LARGE_DIR: u64 = 10_000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static WALKS: Mutex<Vec<Recorded>> = Mutex::new(Vec::new());
+
+/// Record walks from now on
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Counts for one directory, alone or with everything below it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub dirs: u64,
+    pub files: u64,
+    pub manifests: u64,
+    pub elapsed: Duration,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.dirs += other.dirs;
+        self.files += other.files;
+        self.manifests += other.manifests;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// A walk in progress; what it recorded is kept for [`report`] when it is
+/// dropped, however the walk ended
+#[derive(Debug)]
+pub struct Walk {
+    recorded: Recorded,
+    started: Instant,
+    last: Instant,
+}
+
+/// What one walk below `root` found
+#[derive(Debug, Default)]
+struct Recorded {
+    root: PathBuf,
+    /// Directories relative to `root`, with what they hold directly
+    dirs: HashMap<PathBuf, Counts>,
+    ignored: u64,
+    elapsed: Duration,
+}
+
+impl Walk {
+    pub fn new(root: &Path) -> Walk {
+        let now = Instant::now();
+        Walk {
+            recorded: Recorded {
+                root: root.to_path_buf(),
+                ..Recorded::default()
+            },
+            started: now,
+            last: now,
+        }
+    }
+
+    /// An entry the walk yielded, at `rel` below the root; the time since
+    /// the previous one went into listing its directory
+    pub fn entry(&mut self, rel: &Path, is_dir: bool) {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        let dirs = &mut self.recorded.dirs;
+        let Some(parent) = rel.parent() else {
+            dirs.entry(PathBuf::new()).or_default().elapsed += elapsed;
+            return;
+        };
+        let counts = dirs.entry(parent.to_path_buf()).or_default();
+        counts.elapsed += elapsed;
+        if is_dir {
+            counts.dirs += 1;
+        } else {
+            counts.files += 1;
+        }
+    }
+
+    /// A manifest at `rel` below the root
+    pub fn manifest(&mut self, rel: &Path) {
+        let parent = rel.parent().unwrap_or(Path::new(""));
+        self.recorded
+            .dirs
+            .entry(parent.to_path_buf())
+            .or_default()
+            .manifests += 1;
+    }
+
+    /// A directory or file left out by the ignore list
+    pub fn ignored(&mut self) {
+        self.recorded.ignored += 1;
+    }
+}
+
+impl Drop for Walk {
+    fn drop(&mut self) {
+        let mut recorded = std::mem::take(&mut self.recorded);
+        recorded.elapsed = self.last - self.started;
+        WALKS.lock().unwrap().push(recorded);
+    }
+}
+
+/// A large directory without modules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    /// The directory, below the root of its walk
+    pub dir: PathBuf,
+    pub files: u64,
+}
+
+impl Suggestion {
+    /// The `--ignore` value for it: ignores match a directory name
+    pub fn name(&self) -> String {
+        self.dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Suggestion {
+    /// `./data (~400k files, 0 modules)`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let files = match self.files {
+            n if n >= 1_000_000 => format!("~{:.1}M", n as f64 / 1_000_000.0),
+            n if n >= 1_000 => format!("~{}k", n / 1_000),
+            n => n.to_string(),
+        };
+        write!(f, "./{} ({files} files, 0 modules)", self.dir.display())
+    }
+}
+
+/// Large directories without modules in the walks so far, largest first;
+/// of nested ones only the outermost
+pub fn suggestions() -> Vec<Suggestion> {
+    let mut suggestions: Vec<Suggestion> = WALKS
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|walk| walk.suggestions())
+        .collect();
+    suggestions.sort_by(|a, b| b.files.cmp(&a.files).then(a.dir.cmp(&b.dir)));
+    suggestions
+}
+
+impl Recorded {
+    fn suggestions(&self) -> Vec<Suggestion> {
+        let subtrees = self.subtrees();
+        let empty = |dir: &Path| subtrees.get(dir).is_some_and(|c| c.manifests == 0);
+        subtrees
+            .iter()
+            .filter(|(dir, counts)| {
+                let outermost = dir
+                    .parent()
+                    .is_some_and(|parent| parent.as_os_str().is_empty() || !empty(parent));
+                outermost && counts.manifests == 0 && counts.files >= LARGE_DIR
+            })
+            .map(|(dir, counts)| Suggestion {
+                dir: dir.clone(),
+                files: counts.files,
+            })
+            .collect()
+    }
+
+    /// Every directory with the counts of its whole subtree
+    fn subtrees(&self) -> BTreeMap<PathBuf, Counts> {
+        let mut subtrees: BTreeMap<PathBuf, Counts> = BTreeMap::new();
+        for (dir, counts) in &self.dirs {
+            for ancestor in dir.ancestors() {
+                subtrees
+                    .entry(ancestor.to_path_buf())
+                    .or_default()
+                    .add(counts);
+            }
+        }
+        subtrees
+    }
+
+    /// The subtree taking most of the walk: the first directory on the
+    /// chain of dominant ones that holds no manifests, else the deepest
+    fn dominant(&self, subtrees: &BTreeMap<PathBuf, Counts>) -> Option<(PathBuf, Counts)> {
+        let total = subtrees.get(Path::new(""))?.elapsed;
+        if total < MIN_WALK {
+            return None;
+        }
+        let dominates =
+            |counts: &Counts| counts.elapsed.as_secs_f64() >= total.as_secs_f64() * DOMINANT_SHARE;
+        let mut found = None;
+        let mut at = PathBuf::new();
+        while let Some((dir, counts)) = subtrees
+            .iter()
+            .filter(|(dir, _)| dir.parent() == Some(at.as_path()))
+            .find(|(_, counts)| dominates(counts))
+        {
+            found = Some((dir.clone(), *counts));
+            if counts.manifests == 0 {
+                break;
+            }
+            at = dir.clone();
+        }
+        found
+    }
+}
+
+/// The walks recorded so far, on stderr, with a warning for each subtree
+/// that dominated its walk
+pub fn report() {
+    let walks = WALKS.lock().unwrap();
+    let mut total = Counts::default();
+    let mut ignored = 0;
+    let mut elapsed = Duration::ZERO;
+    for walk in walks.iter() {
+        let subtrees = walk.subtrees();
+        if let Some(all) = subtrees.get(Path::new("")) {
+            total.add(all);
+        }
+        ignored += walk.ignored;
+        elapsed += walk.elapsed;
+        if let Some((dir, counts)) = walk.dominant(&subtrees) {
+            let share = counts.elapsed.as_secs_f64() / walk.elapsed.as_secs_f64();
+            let mut message = format!(
+                "{} took {:.0}% of the walk below {} ({} files, {} modules)",
+                dir.display(),
+                share.min(1.0) * 100.0,
+                walk.root.display(),
+                counts.files,
+                counts.manifests
+            );
+            if counts.manifests == 0 {
+                if let Some(name) = dir.file_name() {
+                    message.push_str(&format!("; consider --ignore {}", name.to_string_lossy()));
+                }
+            }
+            crate::diagnostics::report(crate::diagnostics::Code::SlowSubtree, message);
+        }
+    }
+    eprintln!(
+        "discovery: {} directories, {} files, {ignored} ignored subtrees, {} manifests in {:.1}s",
+        total.dirs + walks.len() as u64,
+        total.files,
+        total.manifests,
+        crate::timestamp::reported(elapsed).as_secs_f64()
+    );
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn counts(files: u64, manifests: u64, elapsed: Duration) -> Counts {
+        Counts {
+            dirs: 0,
+            files,
+            manifests,
+            elapsed,
+        }
+    }
+
+    /// A walk where `data/raw` took most of the time
+    fn walk() -> Recorded {
+        let mut walk = Recorded::default();
+        walk.dirs.insert(PathBuf::new(), counts(2, 0, ms(10)));
+        walk.dirs.insert("app".into(), counts(5, 1, ms(20)));
+        walk.dirs.insert("data".into(), counts(10, 0, ms(100)));
+        walk.dirs
+            .insert("data/raw".into(), counts(4000, 0, ms(900)));
+        walk
+    }
+
+    #[test]
+    fn test_subtrees_sum_their_dirs() {
+        let subtrees = walk().subtrees();
+        assert_eq!(subtrees[Path::new("")].files, 4017);
+        assert_eq!(subtrees[Path::new("data")].elapsed, ms(1000));
+    }
+
+    #[test]
+    fn test_dominant_subtree_without_modules() {
+        let walk = walk();
+        let (dir, data) = walk.dominant(&walk.subtrees()).unwrap();
+        assert_eq!(dir, Path::new("data"));
+        assert_eq!(data.files, 4010);
+    }
+
+    #[test]
+    fn test_dominant_subtree_follows_modules_down() {
+        // With modules below it, the chain goes on to the deepest
+        let mut walk = walk();
+        walk.dirs.get_mut(Path::new("data/raw")).unwrap().manifests = 1;
+        let (dir, _) = walk.dominant(&walk.subtrees()).unwrap();
+        assert_eq!(dir, Path::new("data/raw"));
+    }
+
+    #[test]
+    fn test_no_dominant_subtree_when_time_is_spread() {
+        let mut walk = walk();
+        walk.dirs.insert("data/raw".into(), counts(4000, 0, ms(1)));
+        assert_eq!(walk.dominant(&walk.subtrees()), None);
+    }
+
+    #[test]
+    fn test_suggestions_for_large_dirs() {
+        let mut walk = walk();
+        walk.dirs
+            .insert("data/raw".into(), counts(412_000, 0, ms(1)));
+        walk.dirs
+            .insert("app/fixtures".into(), counts(20_000, 0, ms(1)));
+        walk.dirs
+            .insert("app/fixtures/x".into(), counts(20_000, 0, ms(1)));
+        let suggestions = walk.suggestions();
+        assert_eq!(suggestions.len(), 2);
+        assert_eq!(
+            suggestions[1].to_string(),
+            "./data (~412k files, 0 modules)"
+        );
+        assert_eq!(suggestions[0].name(), "fixtures");
+    }
+}
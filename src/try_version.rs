@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: MIT
+//! Pre-flight of a package release against the repos depending on it
+//!
+//! `try-version <pkg>@<version>` copies every local repo declaring a
+//! registry dependency on the package to a [`Sandbox`], sets the dependency
+//! to the candidate version there and runs `moon check` and `moon test` in
+//! each module. The real working trees are never written. Since worktrees
+//! start from HEAD, uncommitted changes are not part of the check, and path
+//! dependencies pointing outside the repo do not resolve from the copy.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+use crate::errors::{Code, Coded};
+use crate::observer::CommandLog;
+use crate::sandbox::{Kind, Sandbox};
+use crate::{manifest, verify, RepoInfo};
+
+/// `owner/name@version` split into package and version
+pub fn parse_spec(spec: &str) -> Result<(String, String)> {
+    match spec.rsplit_once('@') {
+        Some((package, version)) if package.contains('/') && !version.is_empty() => {
+            Ok((package.to_string(), version.to_string()))
+        }
+        _ => bail!(Coded::new(
+            Code::InvalidValue,
+            format!("invalid candidate `{spec}` (expected owner/name@version)")
+        )),
+    }
+}
+
+/// Outcome for one dependent repo
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Check (and test) passed with the candidate
+    Passed,
+    /// A module failed to check or test with the candidate
+    Broken,
+    /// The repo could not be prepared
+    Error,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RepoResult {
+    pub repo: String,
+    /// Versions of the package declared in the repo before the bump
+    pub declared: Vec<String>,
+    pub status: Status,
+    /// `moon <step>` and module that failed first
+    pub failed: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Whether `repo` declares `package` as a registry dependency
+pub fn depends_on(repo: &RepoInfo, package: &str) -> bool {
+    declared(repo, package).next().is_some()
+}
+
+fn declared<'a>(repo: &'a RepoInfo, package: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    repo.moon_mods
+        .iter()
+        .flat_map(|m| &m.deps)
+        .filter(move |d| d.name == package && d.is_registry())
+        .map(|d| d.version.as_deref().unwrap_or("?"))
+}
+
+/// Check `repo` against `package` at `version` in a temporary copy
+pub fn try_repo(
+    repo: &RepoInfo,
+    package: &str,
+    version: &str,
+    test: bool,
+    verbose: bool,
+) -> RepoResult {
+    let mut declared: Vec<String> = declared(repo, package).map(str::to_string).collect();
+    declared.sort();
+    declared.dedup();
+    let mut result = RepoResult {
+        repo: repo.location(),
+        declared,
+        status: Status::Passed,
+        failed: None,
+        error: None,
+    };
+    match run(repo, package, version, test, verbose) {
+        Ok(None) => {}
+        Ok(Some((failed, error))) => {
+            result.status = Status::Broken;
+            result.failed = Some(failed);
+            result.error = Some(error);
+        }
+        Err(e) => {
+            result.status = Status::Error;
+            result.error = Some(format!("{e:#}"));
+        }
+    }
+    result
+}
+
+/// The first failing step and its error, if any
+fn run(
+    repo: &RepoInfo,
+    package: &str,
+    version: &str,
+    test: bool,
+    verbose: bool,
+) -> Result<Option<(String, String)>> {
+    let sandbox = Sandbox::create(&repo.root)?;
+    if verbose {
+        let how = match sandbox.kind() {
+            Kind::Worktree => "HEAD checked out",
+            Kind::Copy => "copied",
+        };
+        println!(
+            "[{}] {how} to {}",
+            crate::ospath::display(&repo.root),
+            sandbox.dir().display()
+        );
+    }
+
+    let copy = sandbox.repo(repo);
+    for moon_mod in &copy.moon_mods {
+        if !moon_mod
+            .deps
+            .iter()
+            .any(|d| d.name == package && d.is_registry())
+        {
+            continue;
+        }
+        let content = std::fs::read_to_string(&moon_mod.path).with_context(|| {
+            format!("Failed to read {}", sandbox.unmap(&moon_mod.path).display())
+        })?;
+        let updated = manifest::set_version(&content, package, version).with_context(|| {
+            format!(
+                "{}: {package} is not declared with a plain version",
+                sandbox.unmap(&moon_mod.path).display()
+            )
+        })?;
+        std::fs::write(&moon_mod.path, updated)?;
+    }
+
+    let log = CommandLog { enabled: verbose };
+    let steps: &[&str] = if test { &["check", "test"] } else { &["check"] };
+    for step in steps {
+        for check in verify::run_in_modules(&copy, &[step], false, &log) {
+            if let Some(error) = &check.error {
+                let failed = format!("moon {step} ({})", check.module_label());
+                return Ok(Some((failed, sandbox.unmap_text(error))));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(
+            parse_spec("moonbitlang/x@0.4.0-rc.1").unwrap(),
+            ("moonbitlang/x".to_string(), "0.4.0-rc.1".to_string())
+        );
+        assert!(parse_spec("moonbitlang/x").is_err());
+        assert!(parse_spec("x@1.0.0").is_err());
+    }
+}
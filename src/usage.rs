@@ -0,0 +1,200 @@
+// SPDX-License-Identifier: MIT
+//! Source-level usages of a package across the fleet
+//!
+//! A package is used by the packages importing it (or a package below it)
+//! in moon.pkg.json. In their .mbt files every reference goes through the
+//! import alias (`@json.parse`), so counting `@alias.` (or
+//! `@alias.symbol`) occurrences gives the lines an API change would touch.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::project_index::Module;
+
+/// An `import` entry of moon.pkg.json
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub path: String,
+    /// Explicit alias
+    pub alias: Option<String>,
+}
+
+impl Import {
+    /// Alias used in sources: the explicit one, else the last path segment
+    pub fn qualifier(&self) -> &str {
+        self.alias
+            .as_deref()
+            .unwrap_or_else(|| self.path.rsplit('/').next().unwrap_or(&self.path))
+    }
+}
+
+/// Imports of moon.pkg.json text, including test imports
+pub fn imports(content: &str) -> Result<Vec<Import>> {
+    let json: serde_json::Value = serde_json::from_str(content)?;
+    Ok(["import", "test-import", "wbtest-import"]
+        .iter()
+        .filter_map(|key| json[key].as_array())
+        .flatten()
+        .filter_map(|import| match import {
+            serde_json::Value::String(path) => Some(Import {
+                path: path.clone(),
+                alias: None,
+            }),
+            other => Some(Import {
+                path: other["path"].as_str()?.to_string(),
+                alias: other["alias"].as_str().map(str::to_string),
+            }),
+        })
+        .collect())
+}
+
+/// Whether `path` is `package` or a package below it
+pub fn is_within(path: &str, package: &str) -> bool {
+    path == package
+        || path
+            .strip_prefix(package)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// One line referring to the package
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    #[serde(serialize_with = "crate::ospath::serialize")]
+    pub file: PathBuf,
+    pub line: usize,
+    pub text: String,
+}
+
+/// Usages within one repo
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RepoUsage {
+    pub repo: String,
+    pub files: usize,
+    pub lines: usize,
+    pub matches: Vec<Match>,
+}
+
+/// Line numbers and text of the lines of `source` with `@qualifier.`, or
+/// `@qualifier.symbol` followed by a non-identifier character
+pub fn find_in_source(source: &str, qualifier: &str, symbol: Option<&str>) -> Vec<(usize, String)> {
+    let needle = format!("@{qualifier}.{}", symbol.unwrap_or_default());
+    let ident = |c: char| c.is_alphanumeric() || c == '_';
+    source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            line.match_indices(&needle).any(|(at, _)| {
+                let before = line[..at].chars().next_back();
+                let after = line[at + needle.len()..].chars().next();
+                !before.is_some_and(ident) && (symbol.is_none() || !after.is_some_and(ident))
+            })
+        })
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .collect()
+}
+
+/// Usages of `package` (or of `symbol` in it) in `modules`, per repo
+pub fn scan(modules: &[Module], package: &str, symbol: Option<&str>) -> Result<Vec<RepoUsage>> {
+    let mut by_repo: BTreeMap<&str, Vec<Match>> = BTreeMap::new();
+    for module in modules {
+        for pkg in &module.packages {
+            if pkg
+                .manifest
+                .file_name()
+                .is_none_or(|n| n != "moon.pkg.json")
+            {
+                continue;
+            }
+            let content = std::fs::read_to_string(&pkg.manifest)
+                .with_context(|| format!("Failed to read {}", pkg.manifest.display()))?;
+            let imports =
+                imports(&content).with_context(|| format!("Invalid {}", pkg.manifest.display()))?;
+            let mut qualifiers: Vec<&str> = imports
+                .iter()
+                .filter(|import| is_within(&import.path, package))
+                .map(Import::qualifier)
+                .collect();
+            qualifiers.sort_unstable();
+            qualifiers.dedup();
+            if qualifiers.is_empty() {
+                continue;
+            }
+
+            let entries = std::fs::read_dir(&pkg.dir)
+                .with_context(|| format!("Failed to read {}", pkg.dir.display()))?;
+            let mut files: Vec<PathBuf> = entries
+                .filter_map(|e| Some(e.ok()?.path()))
+                .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "mbt"))
+                .collect();
+            files.sort();
+            let matches = by_repo.entry(&module.repo).or_default();
+            for file in files {
+                let source = std::fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                let mut lines: Vec<(usize, String)> = qualifiers
+                    .iter()
+                    .flat_map(|q| find_in_source(&source, q, symbol))
+                    .collect();
+                lines.sort();
+                lines.dedup();
+                matches.extend(lines.into_iter().map(|(line, text)| Match {
+                    file: file.clone(),
+                    line,
+                    text,
+                }));
+            }
+        }
+    }
+
+    let mut usages: Vec<RepoUsage> = by_repo
+        .into_iter()
+        .filter(|(_, matches)| !matches.is_empty())
+        .map(|(repo, matches)| {
+            let mut files: Vec<&PathBuf> = matches.iter().map(|m| &m.file).collect();
+            files.dedup();
+            RepoUsage {
+                repo: repo.to_string(),
+                files: files.len(),
+                lines: matches.len(),
+                matches,
+            }
+        })
+        .collect();
+    usages.sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.repo.cmp(&b.repo)));
+    Ok(usages)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_qualifiers() {
+        let imports =
+            imports(r#"{"import": ["a/json/lex", {"path": "a/json", "alias": "j"}, "a/jsonx"]}"#)
+                .unwrap();
+        let qualifiers: Vec<&str> = imports
+            .iter()
+            .filter(|i| is_within(&i.path, "a/json"))
+            .map(Import::qualifier)
+            .collect();
+        assert_eq!(qualifiers, ["lex", "j"]);
+    }
+
+    #[test]
+    fn test_find_in_source() {
+        let source =
+            "let a = @j.parse(s)\nlet b = @j.parse_all(s)\nlet c = x@j.parse\n// @jx.parse\n";
+        assert_eq!(
+            find_in_source(source, "j", Some("parse")),
+            [(1, "let a = @j.parse(s)".to_string())]
+        );
+        assert_eq!(find_in_source(source, "j", None).len(), 2);
+    }
+}
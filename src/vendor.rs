@@ -0,0 +1,414 @@
+// SPDX-License-Identifier: MIT
+//! Offline mirror of the registry packages a fleet depends on
+//!
+//! `moon-dst vendor` copies the declared version of every registry
+//! dependency, and their dependencies in turn, into a directory laid out
+//! like the registry:
+//!
+//! - `index/user/<owner>/<package>.index`: index lines of the vendored versions
+//! - `user/<owner>/<package>/<version>.zip`: package archives
+//!
+//! Serving the directory over HTTP gives a mirror for machines without
+//! internet access, and `index/` can replace `~/.moon/registry/index` there.
+//! Archives are checked against the SHA-256 `checksum` of their index entry,
+//! while vendoring or later with `moon-dst verify` ([`verify_mirror`]).
+
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{Code, Coded};
+use crate::fetch;
+use crate::registry::{self, Registry};
+use crate::self_update::sha256_file;
+
+/// Registry downloads are fetched from by default
+pub const DEFAULT_REGISTRY: &str = "https://mooncakes.io";
+
+/// Versions to vendor per package: `roots` and everything they depend on,
+/// plus the `name@version` of each one missing from the registry index
+pub fn resolve(
+    registry: &Registry,
+    roots: impl IntoIterator<Item = (String, String)>,
+) -> (BTreeMap<String, BTreeSet<String>>, Vec<String>) {
+    let mut packages: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut missing = Vec::new();
+    let mut pending: Vec<(String, String)> = roots.into_iter().collect();
+    // Level by level, so a remote index is fetched in one batch per level
+    while !pending.is_empty() {
+        registry.prefetch(pending.iter().map(|(name, _)| name.as_str()));
+        for (name, version) in std::mem::take(&mut pending) {
+            if packages.get(&name).is_some_and(|v| v.contains(&version)) {
+                continue;
+            }
+            let release = registry
+                .releases(&name)
+                .into_iter()
+                .find(|r| r.version == version);
+            let Some(release) = release else {
+                missing.push(format!("{name}@{version}"));
+                continue;
+            };
+            pending.extend(release.dependencies());
+            packages.entry(name).or_default().insert(version);
+        }
+    }
+    missing.sort();
+    missing.dedup();
+    (packages, missing)
+}
+
+/// Path of the archive of `name@version` below `dir` (also its path on the
+/// registry)
+pub fn archive_path(dir: &Path, name: &str, version: &str) -> PathBuf {
+    let mut path = dir.join("user");
+    path.extend(name.split('/'));
+    path.join(format!("{version}.zip"))
+}
+
+/// Download URL of the archive of `name@version`
+pub fn archive_url(registry_url: &str, name: &str, version: &str) -> String {
+    format!(
+        "{}/user/{name}/{version}.zip",
+        registry_url.trim_end_matches('/')
+    )
+}
+
+/// Fail unless the SHA-256 of the file at `path` is `expected`
+pub fn verify_archive(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!(Coded::new(
+            Code::ChecksumMismatch,
+            format!(
+                "Checksum mismatch for {}: expected {expected}, got {actual}",
+                path.display()
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// Whether an archive had to be downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fetched {
+    Present,
+    Downloaded,
+}
+
+/// Make sure the archive of `release` of `name` is in `dir`, downloading it
+/// from `registry_url` if needed; with `verify`, the archive (new or
+/// present) must match its checksum
+pub fn fetch_archive(
+    dir: &Path,
+    registry_url: &str,
+    name: &str,
+    release: &registry::Release,
+    verify: bool,
+) -> Result<Fetched> {
+    let path = archive_path(dir, name, &release.version);
+    let check = |path: &Path| match (&release.checksum, verify) {
+        (_, false) => Ok(()),
+        (Some(checksum), true) => verify_archive(path, checksum),
+        (None, true) => bail!(Coded::new(
+            Code::ChecksumMismatch,
+            format!(
+                "No checksum in the registry index for {name}@{}",
+                release.version
+            )
+        )),
+    };
+    if path.exists() {
+        check(&path)?;
+        return Ok(Fetched::Present);
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let partial = path.with_extension("zip.part");
+    let downloaded = fetch::download(&archive_url(registry_url, name, &release.version), &partial)
+        .and_then(|()| check(&partial));
+    if let Err(e) = downloaded {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, &path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(Fetched::Downloaded)
+}
+
+/// Write the index file of `name` below `dir`, keeping the lines of
+/// `index_dir`'s index for `versions`
+pub fn write_index(
+    dir: &Path,
+    index_dir: &Path,
+    name: &str,
+    versions: &BTreeSet<String>,
+) -> Result<()> {
+    let (Some(source), Some(dest)) = (
+        registry::index_file(index_dir, name),
+        registry::index_file(&dir.join("index"), name),
+    ) else {
+        bail!(Coded::new(
+            Code::InvalidValue,
+            format!("Invalid package name `{name}`")
+        ));
+    };
+    let content = std::fs::read_to_string(&source)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+    let mut lines = String::new();
+    for line in content.lines() {
+        let vendored = serde_json::from_str::<registry::Release>(line)
+            .is_ok_and(|release| versions.contains(&release.version));
+        if vendored {
+            lines.push_str(line);
+            lines.push('\n');
+        }
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(&dest, lines).with_context(|| format!("Failed to write {}", dest.display()))
+}
+
+/// What is wrong with a vendored package version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The archive does not match its checksum: tampered with or corrupt
+    Mismatch { expected: String, actual: String },
+    /// Listed in the index, but the archive is missing or unreadable
+    Missing,
+    /// The index entry has no checksum to compare with
+    NoChecksum,
+    /// An archive no index entry lists
+    Unlisted,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Mismatch { expected, actual } => {
+                write!(f, "checksum mismatch (expected {expected}, got {actual})")
+            }
+            Problem::Missing => write!(f, "archive missing"),
+            Problem::NoChecksum => write!(f, "no checksum in the index"),
+            Problem::Unlisted => write!(f, "archive not listed in the index"),
+        }
+    }
+}
+
+/// Verification result of one package version
+#[derive(Debug, Clone, Serialize)]
+pub struct Checked {
+    pub package: String,
+    pub version: String,
+    #[serde(serialize_with = "serialize_problem")]
+    pub problem: Option<Problem>,
+}
+
+fn serialize_problem<S: serde::Serializer>(
+    problem: &Option<Problem>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match problem {
+        Some(problem) => serializer.serialize_some(&problem.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Recompute the checksum of every archive in the mirror at `dir` and
+/// compare it with the mirror's own index, sorted by package and version
+pub fn verify_mirror(dir: &Path) -> Result<Vec<Checked>> {
+    let index_dir = dir.join("index");
+    if !index_dir.is_dir() {
+        bail!(Coded::new(
+            Code::FileNotFound,
+            format!("{} is not a vendor directory (no index/)", dir.display())
+        ));
+    }
+    let index = Registry::with_index_dir(index_dir);
+    let releases: Vec<(String, registry::Release)> = index
+        .packages()
+        .into_iter()
+        .flat_map(|name| {
+            index
+                .releases(&name)
+                .into_iter()
+                .map(move |release| (name.clone(), release))
+        })
+        .collect();
+
+    let mut checked: Vec<Checked> = releases
+        .par_iter()
+        .map(|(name, release)| {
+            let path = archive_path(dir, name, &release.version);
+            let problem = match (&release.checksum, sha256_file(&path)) {
+                (_, Err(_)) => Some(Problem::Missing),
+                (None, Ok(_)) => Some(Problem::NoChecksum),
+                (Some(expected), Ok(actual)) => {
+                    (!actual.eq_ignore_ascii_case(expected)).then(|| Problem::Mismatch {
+                        expected: expected.clone(),
+                        actual,
+                    })
+                }
+            };
+            Checked {
+                package: name.clone(),
+                version: release.version.clone(),
+                problem,
+            }
+        })
+        .collect();
+
+    let listed: BTreeSet<PathBuf> = releases
+        .iter()
+        .map(|(name, release)| archive_path(dir, name, &release.version))
+        .collect();
+    for entry in walkdir::WalkDir::new(dir.join("user"))
+        .min_depth(3)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(Result::ok)
+    {
+        let path = entry.path();
+        if listed.contains(path) {
+            continue;
+        }
+        let (Some(version), Some(package), Some(owner)) = (
+            path.file_name()
+                .and_then(|f| f.to_str())
+                .and_then(|f| f.strip_suffix(".zip")),
+            path.parent().and_then(|p| p.file_name()),
+            path.parent()
+                .and_then(Path::parent)
+                .and_then(|p| p.file_name()),
+        ) else {
+            continue;
+        };
+        checked.push(Checked {
+            package: format!("{}/{}", owner.to_string_lossy(), package.to_string_lossy()),
+            version: version.to_string(),
+            problem: Some(Problem::Unlisted),
+        });
+    }
+    checked.sort_by(|a, b| {
+        a.package
+            .cmp(&b.package)
+            .then_with(|| registry::compare_versions(&a.version, &b.version))
+    });
+    Ok(checked)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::sha256;
+
+    #[test]
+    fn test_resolve_and_verify() {
+        let dir = std::env::temp_dir().join("moon_dst_vendor_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let index = dir.join("registry");
+        std::fs::create_dir_all(index.join("user/a")).unwrap();
+        std::fs::write(
+            index.join("user/a/app.index"),
+            concat!(
+                r#"{"name":"a/app","version":"1.0.0","deps":{"a/lib":"0.2.0"}}"#,
+                "\n",
+                r#"{"name":"a/app","version":"1.1.0"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            index.join("user/a/lib.index"),
+            concat!(
+                r#"{"name":"a/lib","version":"0.2.0","deps":{"a/gone":"1.0.0"}}"#,
+                "\n"
+            ),
+        )
+        .unwrap();
+
+        let registry = Registry::with_index_dir(index.clone());
+        let (packages, missing) = resolve(&registry, [("a/app".into(), "1.0.0".into())]);
+        let versions = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(packages["a/app"], versions(&["1.0.0"]));
+        assert_eq!(packages["a/lib"], versions(&["0.2.0"]));
+        assert_eq!(missing, ["a/gone@1.0.0"]);
+
+        let mirror = dir.join("mirror");
+        write_index(&mirror, &index, "a/app", &packages["a/app"]).unwrap();
+        let written = std::fs::read_to_string(mirror.join("index/user/a/app.index")).unwrap();
+        assert_eq!(written.lines().count(), 1);
+        assert!(written.contains("1.0.0"));
+
+        let archive = archive_path(&mirror, "a/lib", "0.2.0");
+        assert!(archive.ends_with("user/a/lib/0.2.0.zip"));
+        assert_eq!(
+            archive_url("https://example.com/", "a/lib", "0.2.0"),
+            "https://example.com/user/a/lib/0.2.0.zip"
+        );
+        std::fs::create_dir_all(archive.parent().unwrap()).unwrap();
+        std::fs::write(&archive, b"zip").unwrap();
+        assert!(verify_archive(&archive, &sha256(b"zip")).is_ok());
+        assert!(verify_archive(&archive, &sha256(b"zap")).is_err());
+
+        // The mirror lists a/app@1.0.0 with no archive and no checksum, and
+        // holds an unlisted a/lib@0.2.0 archive
+        let checked = verify_mirror(&mirror).unwrap();
+        let problems: Vec<_> = checked
+            .iter()
+            .map(|c| (c.package.as_str(), c.problem.clone()))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                ("a/app", Some(Problem::Missing)),
+                ("a/lib", Some(Problem::Unlisted))
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_fetches_one_batch_per_level() {
+        let dir = std::env::temp_dir().join("moon_dst_vendor_batch_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let remote = dir.join("remote");
+        std::fs::create_dir_all(remote.join("user/a")).unwrap();
+        let index = |name: &str, deps: &str| {
+            let line = format!(r#"{{"name":"a/{name}","version":"1.0.0","deps":{{{deps}}}}}"#);
+            std::fs::write(remote.join(format!("user/a/{name}.index")), line).unwrap();
+        };
+        index("app", r#""a/x":"1.0.0","a/y":"1.0.0""#);
+        index("cli", r#""a/y":"1.0.0","a/z":"1.0.0""#);
+        index("x", r#""a/leaf":"1.0.0""#);
+        index("y", "");
+        index("z", "");
+        index("leaf", "");
+
+        let url = format!("file://{}", remote.display());
+        let registry = Registry::remote(&url, dir.join("cache"), 4);
+        let roots = [("a/app", "1.0.0"), ("a/cli", "1.0.0")];
+        let (packages, missing) = resolve(
+            &registry,
+            roots.map(|(n, v)| (n.to_string(), v.to_string())),
+        );
+        assert_eq!(packages.len(), 6);
+        assert!(missing.is_empty());
+        // Roots, their deps, then a/leaf: three levels, six downloads
+        let stats = registry.stats();
+        assert_eq!((stats.batches, stats.downloaded), (3, 6));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
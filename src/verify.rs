@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: MIT
+//! Running moon commands in every module directory of a repo
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::junit::TestCase;
+use crate::observer::Observer;
+use crate::{ospath, run_moon_command, MoonModInfo, RepoInfo};
+
+/// Outcome of running a moon command in one module directory
+#[derive(Debug, Clone)]
+pub struct ModuleCheck {
+    pub repo_root: PathBuf,
+    pub module_dir: PathBuf,
+    pub duration: Duration,
+    pub error: Option<String>,
+}
+
+impl ModuleCheck {
+    pub fn success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Module directory relative to the repo root (`.` for the root module)
+    pub fn module_label(&self) -> String {
+        module_label(&self.repo_root, &self.module_dir)
+    }
+
+    /// The module as a JUnit case of its repo, failed with the error
+    pub fn test_case(&self) -> TestCase {
+        TestCase {
+            classname: ospath::display(&self.repo_root).to_string(),
+            name: self.module_label(),
+            time: self.duration,
+            failure: self.error.clone(),
+        }
+    }
+}
+
+/// Run `moon <args>` in the directory of each moon.mod.json in the repo
+pub fn run_in_modules(
+    repo: &RepoInfo,
+    args: &[&str],
+    dry_run: bool,
+    observer: &dyn Observer,
+) -> Vec<ModuleCheck> {
+    repo.moon_mods
+        .iter()
+        .map(|moon_mod| run_in_module(repo, moon_mod, args, dry_run, observer))
+        .collect()
+}
+
+/// Run `moon <args>` in the directory of one moon.mod.json
+pub fn run_in_module(
+    repo: &RepoInfo,
+    moon_mod: &MoonModInfo,
+    args: &[&str],
+    dry_run: bool,
+    observer: &dyn Observer,
+) -> ModuleCheck {
+    let module_dir = moon_mod.path.parent().unwrap_or(&repo.root).to_path_buf();
+    observer.on_command(&module_dir, args);
+
+    let started = Instant::now();
+    let error = if dry_run {
+        None
+    } else {
+        run_moon_command(repo.runner.as_ref(), &repo.moon, args, &module_dir)
+            .err()
+            .map(|e| e.to_string())
+    };
+    ModuleCheck {
+        repo_root: repo.root.clone(),
+        module_dir,
+        duration: started.elapsed(),
+        error,
+    }
+}
+
+pub fn module_label(repo_root: &Path, module_dir: &Path) -> String {
+    match module_dir.strip_prefix(repo_root) {
+        Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+        Ok(rel) => rel.display().to_string(),
+        Err(_) => module_dir.display().to_string(),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::CommandLog;
+    use crate::parse_moon_mod;
+    use crate::runner::{Invocation, Runner};
+    use std::io;
+    use std::process::{ExitStatus, Output};
+    use std::sync::Arc;
+
+    /// Succeeds everywhere but in module directories named `broken`
+    #[derive(Debug)]
+    struct FakeMoon;
+
+    impl Runner for FakeMoon {
+        fn output(&self, invocation: &Invocation) -> io::Result<Output> {
+            if invocation.cwd.ends_with("broken") {
+                return Err(io::Error::other("no moon here"));
+            }
+            Ok(Output {
+                status: ExitStatus::default(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_run_in_modules_folds_results() {
+        let dir = std::env::temp_dir().join("moon_dst_verify_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut moon_mods = Vec::new();
+        for module in ["", "broken"] {
+            let manifest = dir.join(module).join("moon.mod.json");
+            std::fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+            std::fs::write(&manifest, "{}").unwrap();
+            moon_mods.push(parse_moon_mod(&manifest).unwrap());
+        }
+        let mut repo = RepoInfo::local(dir.clone(), moon_mods);
+        repo.runner = Arc::new(FakeMoon);
+        let log = CommandLog { enabled: false };
+
+        let checks = run_in_modules(&repo, &["check"], false, &log);
+        let labels: Vec<String> = checks.iter().map(|c| c.module_label()).collect();
+        assert_eq!(labels, [".", "broken"]);
+        assert!(checks[0].success());
+        assert!(!checks[1].success());
+        let case = checks[1].test_case();
+        assert_eq!(case.name, "broken");
+        assert!(case.failure.unwrap().contains("moon check"));
+        assert!(checks[0].test_case().failure.is_none());
+
+        // Dry runs only announce the command
+        let checks = run_in_modules(&repo, &["check"], true, &log);
+        assert!(checks.iter().all(|c| c.success()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
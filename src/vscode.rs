@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: MIT
+//! VS Code config: `.vscode/tasks.json` per repo and a multi-root
+//! `.code-workspace` file for the fleet
+//!
+//! Tasks run moon check, test and fmt in each module of a repo. Merging adds
+//! the tasks whose labels are missing and leaves the others alone. The
+//! workspace file is regenerated from the discovered modules; settings and
+//! other keys already in it are kept. Both files must be plain JSON (no
+//! comments) to be merged.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Tasks file, relative to a repo root
+pub const TASKS_FILE: &str = ".vscode/tasks.json";
+
+/// moon subcommands with a task, and the task group of each
+const COMMANDS: &[(&str, Option<&str>)] = &[
+    ("check", Some("build")),
+    ("test", Some("test")),
+    ("fmt", None),
+];
+
+/// A folder of the workspace file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folder {
+    pub name: String,
+    /// Path relative to the workspace file
+    pub path: String,
+}
+
+#[derive(Serialize)]
+struct TasksFile {
+    version: &'static str,
+    tasks: Vec<Task>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Task {
+    label: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    command: &'static str,
+    args: [&'static str; 1],
+    options: TaskOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<&'static str>,
+    problem_matcher: [&'static str; 0],
+}
+
+#[derive(Serialize)]
+struct TaskOptions {
+    cwd: String,
+}
+
+fn to_string<T: Serialize>(value: &T) -> String {
+    let mut text = serde_json::to_string_pretty(value).unwrap_or_default();
+    text.push('\n');
+    text
+}
+
+/// tasks.json for a repo whose modules are in `modules` (relative to the
+/// repo root, `.` for the root itself)
+pub fn tasks(modules: &[String]) -> String {
+    let mut tasks = Vec::new();
+    for module in modules {
+        for (command, group) in COMMANDS {
+            let mut label = format!("moon {command}");
+            let mut cwd = "${workspaceFolder}".to_string();
+            if module != "." {
+                cwd.push('/');
+                cwd.push_str(module);
+            }
+            if modules.len() > 1 {
+                label.push_str(&format!(" ({module})"));
+            }
+            tasks.push(Task {
+                label,
+                kind: "shell",
+                command: "moon",
+                args: [command],
+                options: TaskOptions { cwd },
+                group: *group,
+                problem_matcher: [],
+            });
+        }
+    }
+    to_string(&TasksFile {
+        version: "2.0.0",
+        tasks,
+    })
+}
+
+/// Add the tasks of `generated` whose labels are missing from `existing`
+pub fn merge_tasks(existing: &str, generated: &str) -> Result<String> {
+    let mut doc: Value = serde_json::from_str(existing).context("tasks.json is not plain JSON")?;
+    let generated: Value = serde_json::from_str(generated)?;
+    let tasks = doc
+        .as_object_mut()
+        .context("tasks.json is not a JSON object")?
+        .entry("tasks")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .context("`tasks` in tasks.json is not an array")?;
+    let labels: Vec<Value> = tasks
+        .iter()
+        .filter_map(|t| t.get("label").cloned())
+        .collect();
+    let missing: Vec<Value> = generated["tasks"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|task| !labels.contains(&task["label"]))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        return Ok(existing.to_string());
+    }
+    tasks.extend(missing);
+    Ok(to_string(&doc))
+}
+
+/// The workspace file listing `folders`, keeping everything but the folder
+/// list of `existing`
+pub fn workspace(existing: Option<&str>, folders: &[Folder]) -> Result<String> {
+    let mut doc: Value = match existing {
+        Some(text) => serde_json::from_str(text).context("Workspace file is not plain JSON")?,
+        None => json!({}),
+    };
+    let folders: Vec<Value> = folders
+        .iter()
+        .map(|f| json!({ "name": f.name, "path": f.path }))
+        .collect();
+    doc.as_object_mut()
+        .context("Workspace file is not a JSON object")?
+        .insert("folders".to_string(), json!(folders));
+    // Unchanged folders keep the file as written
+    match existing {
+        Some(old) if serde_json::from_str::<Value>(old).ok().as_ref() == Some(&doc) => {
+            Ok(old.to_string())
+        }
+        _ => Ok(to_string(&doc)),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generated() -> String {
+        tasks(&[".".to_string(), "cli".to_string()])
+    }
+
+    fn folders() -> [Folder; 1] {
+        [Folder {
+            name: "me/app".to_string(),
+            path: "app".to_string(),
+        }]
+    }
+
+    #[test]
+    fn test_tasks_per_module() {
+        let doc: Value = serde_json::from_str(&generated()).unwrap();
+        assert_eq!(doc["tasks"].as_array().unwrap().len(), 6);
+        assert_eq!(doc["tasks"][0]["label"], "moon check (.)");
+        assert_eq!(doc["tasks"][4]["options"]["cwd"], "${workspaceFolder}/cli");
+        assert_eq!(doc["tasks"][4]["group"], "test");
+    }
+
+    #[test]
+    fn test_merge_tasks_keeps_existing_labels() {
+        let generated = generated();
+        let existing = r#"{"version": "2.0.0", "tasks": [{"label": "moon check (.)", "type": "shell", "command": "make"}]}"#;
+        let merged: Value =
+            serde_json::from_str(&merge_tasks(existing, &generated).unwrap()).unwrap();
+        assert_eq!(merged["tasks"].as_array().unwrap().len(), 6);
+        assert_eq!(merged["tasks"][0]["command"], "make");
+        assert_eq!(merge_tasks(&generated, &generated).unwrap(), generated);
+    }
+
+    #[test]
+    fn test_merge_tasks_rejects_comments() {
+        assert!(merge_tasks("// comment\n{}", &generated()).is_err());
+    }
+
+    #[test]
+    fn test_workspace_replaces_folders_only() {
+        let existing = r#"{"folders": [{"path": "old"}], "settings": {"editor.tabSize": 2}}"#;
+        let updated: Value =
+            serde_json::from_str(&workspace(Some(existing), &folders()).unwrap()).unwrap();
+        assert_eq!(updated["folders"][0]["path"], "app");
+        assert_eq!(updated["settings"]["editor.tabSize"], 2);
+        let created = workspace(None, &folders()).unwrap();
+        assert_eq!(workspace(Some(&created), &folders()).unwrap(), created);
+    }
+}
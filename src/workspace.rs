@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: MIT
+//! Virtual workspace files listing the modules of a monorepo
+//!
+//! A `moon-workspace.toml` next to the monorepo's modules lists them
+//! explicitly:
+//!
+//! ```toml
+//! members = ["core", "packages/http"]
+//! ```
+//!
+//! Discovery reads `<member>/moon.mod.json` for each member instead of
+//! walking the directory below the workspace file.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{Code, Coded};
+
+/// Workspace file name
+pub const FILE_NAME: &str = "moon-workspace.toml";
+
+/// Contents of a workspace file
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    /// Module directories relative to the workspace file
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+impl Workspace {
+    /// Read the workspace file in `dir`, if there is one
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let workspace = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(workspace))
+    }
+
+    /// Write the workspace file into `dir`
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(FILE_NAME);
+        std::fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Manifest paths of all members below `dir`
+    pub fn manifests(&self, dir: &Path) -> Vec<PathBuf> {
+        self.members
+            .iter()
+            .map(|member| dir.join(member).join("moon.mod.json"))
+            .collect()
+    }
+
+    /// Add a member; returns false if it is already listed
+    pub fn add(&mut self, member: &str) -> Result<bool> {
+        let member = normalize(member)?;
+        if self.members.contains(&member) {
+            return Ok(false);
+        }
+        self.members.push(member);
+        self.members.sort();
+        Ok(true)
+    }
+
+    /// Remove a member; returns false if it was not listed
+    pub fn remove(&mut self, member: &str) -> Result<bool> {
+        let member = normalize(member)?;
+        let before = self.members.len();
+        self.members.retain(|m| *m != member);
+        Ok(self.members.len() != before)
+    }
+}
+
+/// Member path with `/` separators and no leading `./` or trailing `/`
+fn normalize(member: &str) -> Result<String> {
+    let member = member.replace('\\', "/");
+    let parts: Vec<&str> = member
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    if parts.is_empty() || parts.contains(&"..") || member.starts_with('/') {
+        bail!(Coded::new(
+            Code::InvalidValue,
+            format!("Workspace member must be a path below the workspace root: {member}")
+        ));
+    }
+    Ok(parts.join("/"))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_remove_normalizes_members() {
+        let mut workspace: Workspace = toml::from_str(r#"members = ["core"]"#).unwrap();
+
+        assert!(workspace.add("./packages/http/").unwrap());
+        assert!(!workspace.add("packages/http").unwrap());
+        assert_eq!(workspace.members, ["core", "packages/http"]);
+        assert!(workspace.add("../other").is_err());
+        assert!(workspace.add("/abs").is_err());
+
+        assert!(workspace.remove("core/").unwrap());
+        assert!(!workspace.remove("core").unwrap());
+        assert_eq!(
+            workspace.manifests(Path::new("/repo")),
+            [PathBuf::from("/repo/packages/http/moon.mod.json")]
+        );
+    }
+}
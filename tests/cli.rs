@@ -0,0 +1,673 @@
+// SPDX-License-Identifier: MIT
+//! End-to-end runs of scan, apply and just against a fake `moon`
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{Fleet, Reply};
+
+#[test]
+fn test_scan_lists_repos_and_deps() {
+    let fleet = Fleet::new("scan");
+    fleet
+        .repo("app", "me/app", &[("acme/x", "0.2.0")])
+        .repo("lib", "me/lib", &[]);
+
+    let (ok, json) = fleet.json(&["scan", "--format", "json", "--deterministic"]);
+    assert!(ok);
+    let repos = json["repos"].as_array().unwrap();
+    assert_eq!(repos.len(), 2);
+    assert_eq!(repos[0]["repo_root"], "app");
+    assert_eq!(repos[1]["repo_root"], "lib");
+    assert!(json.to_string().contains("acme/x"));
+    assert!(fleet.invocations().is_empty());
+}
+
+#[test]
+fn test_scan_streams_and_stops() {
+    let fleet = Fleet::new("scan_stream");
+    fleet
+        .repo("a", "me/a", &[])
+        .repo("b", "me/b", &[("acme/x", "0.2.0")])
+        .repo("c", "me/c", &[]);
+
+    let output = fleet.run(&["scan", "--limit", "2"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("acme/x"), "{stdout}");
+    assert_eq!(stdout.matches("Repository: ").count(), 2, "{stdout}");
+    assert!(stdout.contains("Stopped after 2 repos"));
+
+    let output = fleet.run(&["scan", "--first-match"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+    assert!(stdout.trim_end().ends_with('a'), "{stdout}");
+}
+
+#[test]
+fn test_scan_honors_git_excludes() {
+    let fleet = Fleet::new("scan_excludes");
+    fleet
+        .repo("app", "me/app", &[])
+        .repo("legacy", "me/legacy", &[])
+        .repo("mono", "me/mono", &[])
+        .file("mono/old/moon.mod.json", "{\"name\": \"me/old\"}\n")
+        .file("mono/.git/info/exclude", "/old\n");
+    let xdg_ignore = fleet.dir.join("xdg/git/ignore");
+    std::fs::create_dir_all(xdg_ignore.parent().unwrap()).unwrap();
+    std::fs::write(xdg_ignore, "# scratch\nlegacy/\n").unwrap();
+
+    let names = |args: &[&str]| -> Vec<String> {
+        let (ok, json) = fleet.json(args);
+        assert!(ok, "{json}");
+        json["repos"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|r| r["moon_mods"].as_array().unwrap().clone())
+            .map(|m| m["path"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(names(&["scan", "--json"]).len(), 2);
+    assert_eq!(names(&["scan", "--json", "--no-global-ignore"]).len(), 4);
+}
+
+#[test]
+fn test_apply_updates_and_repeats() {
+    let mut fleet = Fleet::new("apply");
+    fleet
+        .repo("app", "me/app", &[("acme/x", "0.2.0"), ("acme/y", "1.0.0")])
+        .repo("lib", "me/lib", &[("acme/y", "1.0.0")]);
+    fleet.on("add acme/x", Reply::bump("acme/x", "0.2.0", "0.3.0"));
+    fleet.on("add acme/y", Reply::ok());
+
+    let (ok, json) = fleet.json(&[
+        "apply",
+        "--format",
+        "json",
+        "--no-justfile",
+        "--repeat",
+        "2",
+        "--deterministic",
+    ]);
+    assert!(ok, "{json}");
+    let app = &json["repos"][0];
+    assert_eq!(app["repo_root"], "app");
+    assert_eq!(app["packages"][0]["status"], "updated");
+    assert_eq!(app["packages"][0]["new_version"], "0.3.0");
+    assert_eq!(app["packages"][1]["status"], "unchanged");
+    assert!(fleet
+        .read("app/moon.mod.json")
+        .contains("\"acme/x\": \"0.3.0\""));
+
+    // Repos interleave; within one, the second pass only re-adds the
+    // package that changed
+    let calls = fleet.invocations();
+    let repo_calls = |repo: &str| -> Vec<&str> {
+        calls
+            .iter()
+            .filter_map(|c| c.strip_prefix(&format!("{repo}: ")))
+            .collect()
+    };
+    assert_eq!(
+        repo_calls("app"),
+        ["update", "add acme/x", "add acme/y", "add acme/x"]
+    );
+    assert_eq!(repo_calls("lib"), ["update", "add acme/y"]);
+}
+
+#[test]
+fn test_apply_fail_fast_cancels_queued_steps() {
+    let mut fleet = Fleet::new("fail_fast");
+    fleet
+        .repo("a", "me/a", &[("acme/x", "0.2.0")])
+        .repo("b", "me/b", &[("acme/x", "0.2.0")]);
+    fleet.on("add acme/x", Reply::fail(1, "error: no such version"));
+
+    let (ok, json) = fleet.json(&[
+        "apply",
+        "--format",
+        "json",
+        "--no-justfile",
+        "--fail-fast",
+        "--deterministic",
+    ]);
+    assert!(!ok);
+    let repos = &json["repos"];
+    assert_eq!(repos[0]["packages"][0]["status"], "failed");
+    assert!(repos[0]["packages"][0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("no such version"));
+    // b was updated before a failed; its add never started
+    assert_eq!(repos[1]["packages"][0]["status"], "skipped");
+    assert_eq!(repos[1]["errors"][0], "1 steps cancelled by --fail-fast");
+    assert!(!fleet.invocations().contains(&"b: add acme/x".to_string()));
+}
+
+#[test]
+fn test_apply_shortens_errors_for_show_error() {
+    let mut fleet = Fleet::new("show_error");
+    fleet.repo("a", "me/a", &[("acme/x", "0.2.0")]);
+    let stderr = "error: failed to resolve acme/x from the registry index\n  caused by: timeout";
+    fleet.on("add acme/x", Reply::fail(1, stderr));
+
+    let output = fleet.run(&[
+        "apply",
+        "--no-justfile",
+        "--deterministic",
+        "--max-error-length",
+        "24",
+    ]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout
+        .lines()
+        .find(|l| l.contains("acme/x: FAILED"))
+        .unwrap_or_else(|| panic!("{stdout}"));
+    assert!(
+        line.contains("error: fail... (moon-dst show-error "),
+        "{line}"
+    );
+    assert!(!stdout.contains("caused by"));
+
+    let id = line.rsplit(' ').next().unwrap().trim_end_matches(')');
+    let output = fleet.run_bare(&["show-error", &id[..8]]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("caused by: timeout"));
+}
+
+#[test]
+fn test_apply_table_lists_failures_once() {
+    let mut fleet = Fleet::new("table");
+    fleet
+        .repo("a", "me/a", &[("acme/x", "0.2.0")])
+        .repo("b", "me/b", &[("acme/y", "1.0.0")]);
+    fleet.on("add acme/x", Reply::fail(1, "error: no such version"));
+    fleet.on("add acme/y", Reply::bump("acme/y", "1.0.0", "1.1.0"));
+
+    let output = fleet.run(&[
+        "apply",
+        "--format",
+        "table",
+        "--no-justfile",
+        "--deterministic",
+    ]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let header = lines
+        .iter()
+        .position(|l| l.starts_with("Repository"))
+        .unwrap_or_else(|| panic!("{stdout}"));
+    assert_eq!(
+        lines[header],
+        "Repository  Status  Updated  Failed  Duration  Notes"
+    );
+    assert!(
+        lines[header + 1].starts_with("a           FAILED  0        1"),
+        "{stdout}"
+    );
+    assert!(
+        lines[header + 2].starts_with("b           OK      1        0"),
+        "{stdout}"
+    );
+    assert_eq!(stdout.matches("no such version").count(), 1, "{stdout}");
+    assert!(stdout.contains("Failures:\n  a\n    moon add acme/x: "));
+}
+
+#[test]
+fn test_apply_groups_errors_many_repos_share() {
+    let mut fleet = Fleet::new("repeated");
+    fleet
+        .repo("a", "me/a", &[("acme/x", "0.2.0")])
+        .repo("b", "me/b", &[("acme/x", "0.2.0")])
+        .repo("c", "me/c", &[("acme/x", "0.2.0")])
+        .repo("d", "me/d", &[("acme/y", "1.0.0")]);
+    fleet.on("add acme/x", Reply::fail(1, "error: registry timeout"));
+    fleet.on("add acme/y", Reply::fail(1, "error: no such version"));
+
+    let args = [
+        "apply",
+        "--format",
+        "text",
+        "--no-justfile",
+        "--deterministic",
+    ];
+    let output = fleet.run(&args);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("registry timeout").count(), 1, "{stdout}");
+    assert!(stdout.contains("Repeated failures:\n  "), "{stdout}");
+    assert!(stdout.contains("registry timeout (3 repos)\n"), "{stdout}");
+    assert!(stdout.contains("no such version"), "{stdout}");
+
+    let output = fleet.run(&[&args[..], &["--no-group-errors"]].concat());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("registry timeout").count(), 3, "{stdout}");
+    assert!(!stdout.contains("Repeated failures:"), "{stdout}");
+}
+
+#[test]
+fn test_apply_package_match_warns_on_typos() {
+    let mut fleet = Fleet::new("package_match");
+    fleet.repo(
+        "app",
+        "me/app",
+        &[("acme/json", "0.2.0"), ("acme/yaml", "1.0.0")],
+    );
+    fleet.on("add acme/json", Reply::bump("acme/json", "0.2.0", "0.3.0"));
+    fleet.on("add acme/yaml", Reply::ok());
+
+    let apply = ["apply", "--no-justfile", "--deterministic"];
+    let output = fleet.run(&[&apply[..], &["-p", "acme/jsno"]].concat());
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--package acme/jsno matched no dependency; did you mean acme/json?"),
+        "{stderr}"
+    );
+    assert_eq!(fleet.invocations(), ["app: update"]);
+
+    let output = fleet.run(&[&apply[..], &["-p", "acme/*", "--package-match", "glob"]].concat());
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("A016"));
+    assert!(fleet
+        .read("app/moon.mod.json")
+        .contains("\"acme/json\": \"0.3.0\""));
+
+    let output = fleet.run(&[&apply[..], &["-p", "(", "--package-match", "regex"]].concat());
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_renames_flagged_by_scan_and_applied() {
+    let mut fleet = Fleet::new("renames");
+    fleet
+        .repo(
+            "app",
+            "me/app",
+            &[("old/json", "1.0.0"), ("acme/x", "0.2.0")],
+        )
+        .repo("lib", "me/lib", &[("acme/x", "0.2.0")])
+        .file("app/src/moon.pkg.json", "{\"import\": [\"old/json\"]}\n")
+        .file("moon-dst.toml", "[renames]\n\"old/json\" = \"new/json\"\n");
+    fleet.on("add *", Reply::ok());
+
+    let (ok, json) = fleet.json(&["scan", "--format", "json", "--deterministic"]);
+    assert!(ok);
+    let diagnostics = json["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{json}");
+    assert_eq!(diagnostics[0]["code"], "A017");
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("old/json -> new/json"));
+
+    let output = fleet.run(&[
+        "apply",
+        "--apply-renames",
+        "--no-justfile",
+        "--deterministic",
+    ]);
+    assert!(output.status.success());
+    assert!(fleet
+        .read("app/moon.mod.json")
+        .contains("\"new/json\": \"1.0.0\""));
+    assert!(!fleet.read("app/moon.mod.json").contains("old/json"));
+    assert_eq!(
+        fleet.read("app/src/moon.pkg.json"),
+        "{\"import\": [\"new/json\"]}\n"
+    );
+    assert!(fleet
+        .invocations()
+        .contains(&"app: add new/json".to_string()));
+}
+
+#[test]
+fn test_outdated_baseline_fails_only_on_new_deps() {
+    let fleet = Fleet::new("baseline");
+    fleet.repo("app", "me/app", &[("acme/x", "0.2.0")]).repo(
+        "lib",
+        "me/lib",
+        &[("acme/x", "0.2.0")],
+    );
+    let index = fleet.dir.join("moon-home/registry/index/user/acme");
+    std::fs::create_dir_all(&index).unwrap();
+    std::fs::write(
+        index.join("x.index"),
+        "{\"name\":\"acme/x\",\"version\":\"0.2.0\"}\n{\"name\":\"acme/x\",\"version\":\"0.3.0\"}\n",
+    )
+    .unwrap();
+    let file = fleet.dir.join("baseline.toml");
+    let baseline = file.to_str().unwrap();
+
+    let output = fleet.run(&["outdated", "--baseline", baseline, "--write-baseline"]);
+    assert!(output.status.success());
+    let written = std::fs::read_to_string(&file).unwrap();
+    assert_eq!(written.matches("[[accepted]]").count(), 2, "{written}");
+    assert!(written.contains("repo = \"app\""), "{written}");
+
+    let output = fleet.run(&["outdated", "--baseline", baseline]);
+    assert!(output.status.success());
+
+    fleet.repo("svc", "me/svc", &[("acme/x", "0.2.0")]);
+    fleet.repo("app", "me/app", &[("acme/x", "0.3.0")]);
+    let output = fleet.run(&["outdated", "--baseline", baseline]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("svc"), "{stdout}");
+    assert!(!stdout.contains("/lib"), "{stdout}");
+    assert!(stdout.contains("1 outdated dependencies in 1 repos (1 accepted in the baseline)"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("A018"), "{stderr}");
+    assert!(stderr.contains("acme/x in app (me/app) is no longer outdated"));
+}
+
+#[test]
+fn test_gate_judges_checks_by_one_policy() {
+    let fleet = Fleet::new("gate");
+    fleet.repo("app", "me/app", &[("acme/x", "0.1.0")]).repo(
+        "lib",
+        "me/lib",
+        &[("acme/x", "0.3.0")],
+    );
+    let index = fleet.dir.join("moon-home/registry/index/user/acme");
+    std::fs::create_dir_all(&index).unwrap();
+    std::fs::write(
+        index.join("x.index"),
+        "{\"name\":\"acme/x\",\"version\":\"0.1.0\"}\n{\"name\":\"acme/x\",\"version\":\"0.4.0\"}\n",
+    )
+    .unwrap();
+
+    let output = fleet.run(&["gate"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("error[G001]: acme/x 0.1.0 is 3 major versions behind (latest: 0.4.0)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("warning[L002]: acme/x 0.3.0 is outdated"));
+    assert!(stdout.contains("Gate: FAILED (1 error, 1 warning; fails on error)\n  outdated: 1 error, 1 warning\n  lint: ok\n"));
+
+    assert!(fleet
+        .run(&["gate", "--max-major-lag", "3"])
+        .status
+        .success());
+    assert!(!fleet
+        .run(&["gate", "--max-major-lag", "3", "--fail-on", "warning"])
+        .status
+        .success());
+    let (ok, json) = fleet.json(&["gate", "--format", "json", "--check", "lint"]);
+    assert!(ok, "{json}");
+    assert_eq!(json["passed"], true);
+    assert_eq!(json["checks"][0]["check"], "lint");
+}
+
+#[test]
+fn test_gate_audit_levels_and_sarif() {
+    let fleet = Fleet::new("gate_audit");
+    fleet
+        .repo("app", "me/app", &[("acme/y", "1.0.0")])
+        .file("moon-dst.toml", "[gate]\nyanked = \"warning\"\n");
+    let index = fleet.dir.join("moon-home/registry/index/user/acme");
+    std::fs::create_dir_all(&index).unwrap();
+    std::fs::write(
+        index.join("y.index"),
+        "{\"name\":\"acme/y\",\"version\":\"1.0.0\",\"yanked\":true}\n",
+    )
+    .unwrap();
+
+    let (ok, sarif) = fleet.json(&["gate", "--format", "sarif"]);
+    assert!(ok, "{sarif}");
+    let result = &sarif["runs"][0]["results"][0];
+    assert_eq!(result["ruleId"], "L004");
+    assert_eq!(result["level"], "warning");
+    assert!(!fleet
+        .run(&["gate", "--fail-on", "warning"])
+        .status
+        .success());
+}
+
+#[test]
+fn test_apply_over_threshold_needs_yes() {
+    let mut fleet = Fleet::new("confirm");
+    fleet
+        .repo("a", "me/a", &[("acme/x", "0.2.0")])
+        .repo("b", "me/b", &[("acme/x", "0.2.0")])
+        .file("moon-dst.toml", "[apply]\nconfirm-threshold = 1\n");
+    fleet.on("add acme/x", Reply::ok());
+
+    let output = fleet.run(&["apply", "--no-justfile", "--deterministic"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E022"), "{stderr}");
+    assert!(
+        stderr.contains("run moon add in 2 repos (2 packages)"),
+        "{stderr}"
+    );
+    assert!(fleet.invocations().is_empty());
+
+    let output = fleet.run(&["apply", "--no-justfile", "--deterministic", "--yes"]);
+    assert!(output.status.success());
+    assert!(fleet.invocations().contains(&"b: add acme/x".to_string()));
+}
+
+#[test]
+fn test_apply_from_outdated_document() {
+    let fleet = Fleet::new("from_outdated");
+    fleet
+        .repo("app", "me/app", &[("acme/x", "0.2.0"), ("acme/y", "1.0.0")])
+        .repo("lib", "me/lib", &[("acme/x", "0.2.0")]);
+    let report = fleet.dir.join("outdated.json");
+    std::fs::write(
+        &report,
+        r#"{"outdated": [
+            {"repo": "app", "module": "me/app", "dep": "acme/x",
+             "declared": "0.2.0", "latest": "0.3.0", "outdated": true},
+            {"repo": "app", "module": "me/app", "dep": "acme/y",
+             "declared": "1.0.0", "latest": "1.0.0", "outdated": false}
+        ]}"#,
+    )
+    .unwrap();
+
+    let output = fleet.run(&[
+        "apply",
+        "--no-justfile",
+        "--skip-update",
+        "--from-outdated",
+        report.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(fleet.invocations(), ["app: add acme/x@0.3.0"]);
+}
+
+#[test]
+fn test_apply_plain_output() {
+    let mut fleet = Fleet::new("plain");
+    fleet.repo("app", "me/app", &[("acme/x", "0.2.0")]).repo(
+        "lib",
+        "me/lib",
+        &[("acme/y", "1.0.0")],
+    );
+    fleet.on("add acme/x", Reply::bump("acme/x", "0.2.0", "0.3.0"));
+    fleet.on("add acme/y", Reply::fail(1, "resolution failed"));
+
+    let output = fleet.run(&["apply", "--no-justfile", "--skip-update", "--plain"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[PASS] "), "{stdout}");
+    assert!(stdout.contains("[FAIL] "));
+    assert!(stdout.contains("Updated: acme/x: from 0.2.0 to 0.3.0"));
+    assert!(stdout.contains("FAIL: acme/y: "));
+    assert!(stdout.contains("Summary: 1/2 repos succeeded\nSummary: 1 packages updated\n"));
+    assert!(!stdout.contains("->"));
+}
+
+#[test]
+fn test_apply_estimates_time_left_from_past_runs() {
+    let mut fleet = Fleet::new("eta");
+    fleet.repo("app", "me/app", &[("acme/x", "0.2.0")]).repo(
+        "lib",
+        "me/lib",
+        &[("acme/x", "0.2.0")],
+    );
+    fleet.on("add acme/x", Reply::ok());
+    let events = fleet.dir.join("results.ndjson");
+    let apply = || {
+        let output = fleet.run(&[
+            "apply",
+            "--no-justfile",
+            "--jobs",
+            "1",
+            "--results-out",
+            events.to_str().unwrap(),
+        ]);
+        assert!(output.status.success());
+        std::fs::read_to_string(&events).unwrap()
+    };
+
+    // The first run has only its own durations to go by
+    apply();
+    let durations = fleet.dir.join("data/durations.json");
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(durations).unwrap()).unwrap();
+    assert_eq!(saved["repos"].as_object().unwrap().len(), 2);
+
+    let lines: Vec<serde_json::Value> = apply()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0]["eta_secs"].is_u64());
+    assert_eq!(lines[1]["eta_secs"], 0);
+}
+
+#[test]
+fn test_run_id_correlates_output_and_history() {
+    let mut fleet = Fleet::new("run_id");
+    fleet.repo("app", "me/app", &[("acme/x", "0.2.0")]);
+    fleet.on("add acme/x", Reply::bump("acme/x", "0.2.0", "0.3.0"));
+    let events = fleet.dir.join("results.ndjson");
+
+    let (ok, json) = fleet.json(&[
+        "apply",
+        "--format",
+        "json",
+        "--no-justfile",
+        "--results-out",
+        events.to_str().unwrap(),
+    ]);
+    assert!(ok, "{json}");
+    let run_id = json["run_id"].as_str().unwrap();
+    assert_eq!(run_id.len(), 26);
+    let event: serde_json::Value =
+        serde_json::from_str(std::fs::read_to_string(&events).unwrap().trim()).unwrap();
+    assert_eq!(event["run_id"], run_id);
+
+    let list = fleet.run(&["history", "list"]);
+    assert!(String::from_utf8_lossy(&list.stdout).starts_with(run_id));
+    let (ok, run) = fleet.json(&["history", "show", run_id, "--format", "json"]);
+    assert!(ok, "{run}");
+    assert_eq!(run["repos"][0]["changes"][0]["new_version"], "0.3.0");
+    assert!(!fleet
+        .run(&["history", "show", "01J9Z3K8Q0AB12CD34EF56GH78"])
+        .status
+        .success());
+}
+
+#[test]
+fn test_just_creates_then_keeps_justfiles() {
+    let fleet = Fleet::new("just");
+    fleet.repo("app", "me/app", &[]);
+
+    let output = fleet.run(&["just", "--set", "target=wasm-gc"]);
+    assert!(output.status.success());
+    let justfile = fleet.read("app/justfile");
+    assert!(justfile.contains("target := \"wasm-gc\""));
+    assert!(justfile.contains("moon check"));
+
+    fleet.file("app/justfile", "# mine\n");
+    assert!(fleet.run(&["just"]).status.success());
+    assert_eq!(fleet.read("app/justfile"), "# mine\n");
+}
+
+#[test]
+fn test_just_writes_root_justfile_for_modules() {
+    let fleet = Fleet::new("just-mono");
+    fleet
+        .file("mono/.git/HEAD", "ref: refs/heads/main\n")
+        .file("mono/core/moon.mod.json", "{\"name\": \"me/core\"}\n")
+        .file(
+            "mono/packages/http/moon.mod.json",
+            "{\"name\": \"me/http\"}\n",
+        );
+
+    assert!(fleet.run(&["just"]).status.success());
+    assert!(fleet.read("mono/core/justfile").contains("moon check"));
+    assert!(fleet
+        .read("mono/packages/http/justfile")
+        .contains("moon check"));
+    let root = fleet.read("mono/justfile");
+    assert!(root.contains(
+        "check-all:\n    cd 'core' && just check\n    cd 'packages/http' && just check\n"
+    ));
+    assert!(root.contains("fmt-all:") && root.contains("test-all:"));
+}
+
+#[test]
+fn test_scan_merges_several_roots() {
+    let fleet = Fleet::new("roots");
+    fleet
+        .repo("app", "me/app", &[])
+        .repo("lib", "me/lib", &[])
+        .repo("../work/tool", "me/tool", &[]);
+
+    // The fleet root is appended last, so app is found from two roots
+    let roots = format!(
+        "{},{}",
+        fleet.root().join("app").display(),
+        fleet.dir.join("work").display()
+    );
+    let (ok, json) = fleet.json(&["scan", "--format", "json", "--root", &roots]);
+    assert!(ok);
+    let names: Vec<&str> = json["repos"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|repo| repo["repo_root"].as_str().unwrap())
+        .collect();
+    assert_eq!(names.len(), 3, "{names:?}");
+    assert!(names[0].ends_with("repos/app"));
+    assert!(names[1].ends_with("repos/lib"));
+    assert!(names[2].ends_with("work/tool"));
+}
+
+#[test]
+fn test_scan_collects_diagnostics() {
+    let fleet = Fleet::new("diagnostics");
+    fleet
+        .repo("app", "me/app", &[])
+        .file("broken/moon.mod.json", "{\"name\": ");
+
+    let (ok, json) = fleet.json(&["scan", "--format", "json", "--deterministic"]);
+    assert!(ok);
+    assert_eq!(json["repos"].as_array().unwrap().len(), 1);
+    let diagnostics = json["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "A001");
+    assert_eq!(diagnostics[0]["level"], "warning");
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("broken/moon.mod.json"));
+
+    let (ok, json) = fleet.json(&["scan", "--format", "json", "--deny", "invalid-manifest"]);
+    assert!(!ok);
+    assert_eq!(json["diagnostics"][0]["level"], "error");
+    let (ok, json) = fleet.json(&["scan", "--format", "json", "--allow", "A001"]);
+    assert!(ok);
+    assert!(json.get("diagnostics").is_none());
+}
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: MIT
+//! End-to-end test harness: temp repo trees and a scriptable fake `moon`
+//!
+//! A [`Fleet`] is a fresh directory of repos plus an isolated environment
+//! (`HOME`, `MOON_HOME`, `MOON_DST_HOME`, `XDG_CONFIG_HOME`) and a `bin/`
+//! directory holding a `moon` shell script put first on `PATH`. The script
+//! appends each invocation as `<cwd>\t<args>` to a log and answers from the
+//! rules given with [`Fleet::on`]: the first rule whose glob matches the
+//! arguments runs its shell snippet (in the command's working directory)
+//! and exits with its code. Unmatched commands succeed silently.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// What the fake `moon` does for matching arguments
+pub struct Reply {
+    script: String,
+    code: i32,
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply {
+            script: String::new(),
+            code: 0,
+        }
+    }
+
+    /// Print `stderr` and exit with `code`
+    pub fn fail(code: i32, stderr: &str) -> Self {
+        Reply {
+            script: format!("printf '%s\\n' '{}' >&2", stderr.replace('\'', "'\\''")),
+            code,
+        }
+    }
+
+    /// Set the declared version of `dep` in ./moon.mod.json, as `moon add`
+    /// does
+    pub fn bump(dep: &str, from: &str, to: &str) -> Self {
+        Reply {
+            script: format!(
+                "sed -e 's|\"{dep}\": *\"{from}\"|\"{dep}\": \"{to}\"|' moon.mod.json > moon.mod.json.tmp && mv moon.mod.json.tmp moon.mod.json"
+            ),
+            code: 0,
+        }
+    }
+}
+
+pub struct Fleet {
+    pub dir: PathBuf,
+    rules: Vec<(String, Reply)>,
+}
+
+impl Fleet {
+    /// An empty fleet in a fresh directory named after the test
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("moon_dst_e2e_{name}_test"));
+        let _ = std::fs::remove_dir_all(&dir);
+        for sub in ["repos", "bin", "home", "moon-home", "data", "xdg"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let fleet = Fleet {
+            dir,
+            rules: Vec::new(),
+        };
+        fleet.write_moon();
+        fleet
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self.dir.join("repos")
+    }
+
+    /// Write a file below the fleet root
+    pub fn file(&self, rel: &str, content: &str) -> &Self {
+        let path = self.root().join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+        self
+    }
+
+    /// A repo (with a `.git` directory) whose root module has `deps`
+    pub fn repo(&self, rel: &str, name: &str, deps: &[(&str, &str)]) -> &Self {
+        std::fs::create_dir_all(self.root().join(rel).join(".git")).unwrap();
+        let deps: Vec<String> = deps
+            .iter()
+            .map(|(dep, version)| format!("\"{dep}\": \"{version}\""))
+            .collect();
+        self.file(
+            &format!("{rel}/moon.mod.json"),
+            &format!(
+                "{{\"name\": \"{name}\", \"deps\": {{{}}}}}\n",
+                deps.join(", ")
+            ),
+        )
+    }
+
+    pub fn read(&self, rel: &str) -> String {
+        std::fs::read_to_string(self.root().join(rel)).unwrap()
+    }
+
+    /// Answer `moon <args>` matching the shell glob `pattern` with `reply`
+    pub fn on(&mut self, pattern: &str, reply: Reply) -> &mut Self {
+        self.rules.push((pattern.to_string(), reply));
+        self.write_moon();
+        self
+    }
+
+    fn write_moon(&self) {
+        let mut script = String::from("#!/bin/sh\n");
+        script.push_str(&format!(
+            "printf '%s\\t%s\\n' \"$PWD\" \"$*\" >> '{}'\n",
+            self.log_path().display()
+        ));
+        script.push_str("case \"$*\" in\n");
+        script.push_str("  version) echo 'moon 0.1.20250108 (fake)'; exit 0 ;;\n");
+        for (pattern, reply) in &self.rules {
+            script.push_str(&format!(
+                "  {}) {}\n    exit {} ;;\n",
+                pattern.replace(' ', "\\ "),
+                if reply.script.is_empty() {
+                    ":"
+                } else {
+                    &reply.script
+                },
+                reply.code
+            ));
+        }
+        script.push_str("esac\nexit 0\n");
+        let path = self.dir.join("bin/moon");
+        std::fs::write(&path, script).unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join("moon.log")
+    }
+
+    /// Recorded invocations as `<repo>: <args>`, `version` queries left out
+    pub fn invocations(&self) -> Vec<String> {
+        let log = std::fs::read_to_string(self.log_path()).unwrap_or_default();
+        let root = self.root().canonicalize().unwrap();
+        log.lines()
+            .filter_map(|line| {
+                let (cwd, args) = line.split_once('\t')?;
+                let rel = Path::new(cwd).strip_prefix(&root).unwrap_or(Path::new(cwd));
+                (args != "version").then(|| format!("{}: {args}", rel.display()))
+            })
+            .collect()
+    }
+
+    /// Run moon-dst with `args` (and `--root` pointing at the fleet)
+    pub fn run(&self, args: &[&str]) -> Output {
+        let root = self.root();
+        let mut with_root: Vec<&str> = args.to_vec();
+        with_root.extend(["--root", root.to_str().unwrap()]);
+        self.run_bare(&with_root)
+    }
+
+    /// Run moon-dst with just `args`, for commands without `--root`
+    pub fn run_bare(&self, args: &[&str]) -> Output {
+        let path = std::env::join_paths(std::iter::once(self.dir.join("bin")).chain(
+            std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+        ))
+        .unwrap();
+        Command::new(env!("CARGO_BIN_EXE_moon-dst"))
+            .args(args)
+            .env("PATH", path)
+            .env("HOME", self.dir.join("home"))
+            .env("MOON_HOME", self.dir.join("moon-home"))
+            .env("MOON_DST_HOME", self.dir.join("data"))
+            .env("XDG_CONFIG_HOME", self.dir.join("xdg"))
+            .env_remove("SOURCE_DATE_EPOCH")
+            .output()
+            .unwrap()
+    }
+
+    /// Run moon-dst and parse its stdout as JSON
+    pub fn json(&self, args: &[&str]) -> (bool, serde_json::Value) {
+        let output = self.run(args);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let value = serde_json::from_str(&stdout).unwrap_or_else(|e| {
+            panic!(
+                "not JSON ({e}): {stdout}\nstderr: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )
+        });
+        (output.status.success(), value)
+    }
+}
+
+impl Drop for Fleet {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}